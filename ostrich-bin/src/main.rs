@@ -2,6 +2,10 @@ use std::process::exit;
 
 use argh::FromArgs;
 
+mod service;
+#[cfg(feature = "wizard")]
+mod wizard;
+
 const VERSION: Option<&'static str> = option_env!("CARGO_PKG_VERSION");
 const COMMIT_HASH: Option<&'static str> = option_env!("CFG_COMMIT_HASH");
 const COMMIT_DATE: Option<&'static str> = option_env!("CFG_COMMIT_DATE");
@@ -26,6 +30,17 @@ fn default_thread_stack_size() -> usize {
     256 * 1024
 }
 
+// Loads the config for the test commands, exits if it can't.
+fn load_config(path: &str) -> ostrich::config::Config {
+    match ostrich::config::from_file(path) {
+        Ok(config) => config,
+        Err(e) => {
+            println!("load config {} failed: {}", path, e);
+            exit(1);
+        }
+    }
+}
+
 #[derive(FromArgs)]
 /// A lightweight and fast proxy utility
 struct Args {
@@ -46,15 +61,52 @@ struct Args {
     #[argh(option, default = "default_thread_stack_size()")]
     thread_stack_size: usize,
 
+    /// asks for a server and a local SOCKS port, writes a configuration
+    /// file to the path of --config and exit
+    #[cfg(feature = "wizard")]
+    #[argh(switch)]
+    wizard: bool,
+
     /// tests the configuration and exit
     #[argh(switch, short = 'T')]
     test: bool,
 
+    /// encrypts the configuration file with the key in CONFIG_KEY or
+    /// CONFIG_KEY_FILE, writes it to the specified path and exit
+    #[argh(option)]
+    encrypt_config: Option<String>,
+
+    /// checks the environment prerequisites and exit
+    #[argh(switch)]
+    doctor: bool,
+
     /// tests the connectivity of the specified outbound
     #[argh(option, short = 't')]
     test_outbound: Option<String>,
 
-    /// timeout for outbound connectivity tests, in seconds
+    /// tests the connectivity of all outbounds, prints them sorted by latency
+    #[argh(switch)]
+    test_all: bool,
+
+    /// the maximum number of outbounds tested at a time by --test-all
+    #[argh(option, default = "8")]
+    test_concurrency: usize,
+
+    /// also measures the download throughput of the outbound tested, bounded
+    /// by THROUGHPUT_TEST_BYTES and THROUGHPUT_TEST_TIMEOUT
+    #[argh(switch)]
+    test_throughput: bool,
+
+    /// prints the routes a running instance installed, through its API at
+    /// the first TCP address in API_LISTEN
+    #[argh(switch)]
+    show_routes: bool,
+
+    /// tests a round-trip through the specified inbound
+    #[argh(option)]
+    test_inbound: Option<String>,
+
+    /// timeout for connectivity tests, in seconds
     #[argh(option, short = 'd', default = "4")]
     test_outbound_timeout: u64,
 
@@ -66,6 +118,9 @@ struct Args {
     /// prints version
     #[argh(switch, short = 'V')]
     version: bool,
+
+    #[argh(subcommand)]
+    command: Option<service::ServiceCommand>,
 }
 
 fn main() {
@@ -76,15 +131,73 @@ fn main() {
         exit(0);
     }
 
-    if args.test {
-        if let Err(e) = ostrich::test_config(&args.config) {
+    if let Some(out) = args.encrypt_config {
+        use ostrich::config::encrypted;
+        let data = match std::fs::read(&args.config) {
+            Ok(data) if encrypted::is_encrypted(&data) => {
+                println!("{} is already encrypted", &args.config);
+                exit(1);
+            }
+            Ok(data) => data,
+            Err(e) => {
+                println!("read {} failed: {}", &args.config, e);
+                exit(1);
+            }
+        };
+        let result = encrypted::key_from_env()
+            .and_then(|key| encrypted::encrypt(&data, &key))
+            .and_then(|data| Ok(std::fs::write(&out, data)?));
+        if let Err(e) = result {
+            println!("encrypt config failed: {}", e);
+            exit(1);
+        }
+        println!("encrypted config written to {}", out);
+        exit(0);
+    }
+
+    #[cfg(feature = "wizard")]
+    if args.wizard {
+        if let Err(e) = wizard::run(&args.config) {
             println!("{}", e);
             exit(1);
-        } else {
-            println!("ok");
-            exit(0);
+        }
+        println!(
+            "config written to {}, start with: ostrich -c {}",
+            &args.config, &args.config
+        );
+        exit(0);
+    }
+
+    if args.test {
+        match ostrich::test_config(&args.config) {
+            Ok(warnings) => {
+                for w in warnings {
+                    println!("warning: {}", w);
+                }
+                println!("ok");
+                exit(0);
+            }
+            Err(e) => {
+                println!("{}", e);
+                exit(1);
+            }
+        }
+    }
+    if args.show_routes {
+        match ostrich::util::show_routes(&ostrich::option::API_LISTEN) {
+            Ok(changes) => {
+                for change in changes {
+                    println!("{}", change);
+                }
+                exit(0);
+            }
+            Err(e) => {
+                println!("show routes failed: {}", e);
+                exit(1);
+            }
         }
     }
+
     let path = std::env::current_dir().unwrap();
     #[cfg(target_os = "windows")]
     let wintun_path = "C:\\Users\\nancy\\.ostrich\\assets\\wintun.dll";
@@ -102,11 +215,114 @@ fn main() {
     );
     println!("tun2socks path: {}", tun2socks_path);
 
+    if args.doctor {
+        let checks = ostrich::util::doctor(&args.config, Some(tun2socks_path.as_ref()));
+        let mut failed = false;
+        for check in checks {
+            match check.result {
+                Ok(_) => println!("[ok] {}", check.name),
+                Err(e) => {
+                    failed = true;
+                    println!("[fail] {}: {}", check.name, e);
+                }
+            }
+        }
+        exit(if failed { 1 } else { 0 });
+    }
+
+    if let Some(command) = args.command {
+        let res = match command {
+            service::ServiceCommand::Install(cmd) => service::install(&cmd.config),
+            service::ServiceCommand::Uninstall(_) => service::uninstall(),
+            #[cfg(target_os = "windows")]
+            service::ServiceCommand::Run(cmd) => service::run(service::RunArgs {
+                config: cmd.config,
+                wintun_path: wintun_path.to_string(),
+                tun2socks_path: tun2socks_path.to_owned(),
+            }),
+            #[cfg(not(target_os = "windows"))]
+            service::ServiceCommand::Run(_) => {
+                Err("run-service is only supported on Windows".to_string())
+            }
+        };
+        if let Err(e) = res {
+            println!("{}", e);
+            exit(1);
+        }
+        exit(0);
+    }
+
     #[cfg(any(target_os = "macos", target_os = "linux"))]
     if let Some(iface) = args.boundif {
         std::env::set_var("OUTBOUND_INTERFACE", &iface);
     }
 
+    if args.test_all {
+        let config = load_config(&args.config);
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let mut results = match rt.block_on(ostrich::util::test_all_outbounds(
+            &config,
+            Some(std::time::Duration::from_secs(args.test_outbound_timeout)),
+            args.test_concurrency,
+        )) {
+            Ok(results) => results,
+            Err(e) => {
+                println!("test outbounds failed: {}", e);
+                exit(1);
+            }
+        };
+        // Fastest first, the failed ones last.
+        results.sort_by_key(|(_, tcp_res, udp_res)| {
+            (
+                tcp_res.is_err(),
+                tcp_res.as_ref().ok().copied(),
+                udp_res.is_err(),
+                udp_res.as_ref().ok().copied(),
+            )
+        });
+        let cell = |res: &Result<std::time::Duration, ostrich::Error>| match res {
+            Ok(duration) => format!("{}ms", duration.as_millis()),
+            Err(_) => "failed".to_string(),
+        };
+        let width = results
+            .iter()
+            .map(|(tag, _, _)| tag.len())
+            .max()
+            .unwrap_or(0)
+            .max(3);
+        println!(
+            "{:<width$}  {:>8}  {:>8}",
+            "TAG",
+            "TCP",
+            "UDP",
+            width = width
+        );
+        for (tag, tcp_res, udp_res) in results.iter() {
+            println!(
+                "{:<width$}  {:>8}  {:>8}",
+                tag,
+                cell(tcp_res),
+                cell(udp_res),
+                width = width
+            );
+        }
+        for (tag, tcp_res, udp_res) in results.iter() {
+            if let Err(e) = tcp_res {
+                println!("{}: TCP failed: {}", tag, e);
+            }
+            if let Err(e) = udp_res {
+                println!("{}: UDP failed: {}", tag, e);
+            }
+        }
+        let all_failed = results
+            .iter()
+            .all(|(_, tcp_res, udp_res)| tcp_res.is_err() && udp_res.is_err());
+        exit(if all_failed { 1 } else { 0 });
+    }
+
     if let Some(tag) = args.test_outbound {
         let config = ostrich::config::from_file(&args.config).unwrap();
         let rt = tokio::runtime::Builder::new_current_thread()
@@ -131,13 +347,46 @@ fn main() {
                     Ok(duration) => println!("UDP ok in {}ms", duration.as_millis()),
                     Err(e) => println!("UDP failed: {}", e),
                 }
+                if args.test_throughput {
+                    match rt.block_on(ostrich::util::test_outbound_throughput(&tag, &config)) {
+                        Ok(t) => println!(
+                            "throughput {:.2} Mbps, {} bytes in {}ms",
+                            t.mbps(),
+                            t.bytes,
+                            t.elapsed.as_millis()
+                        ),
+                        Err(e) => println!("throughput test failed: {}", e),
+                    }
+                }
+                exit(0);
+            }
+        }
+    }
+
+    if let Some(tag) = args.test_inbound {
+        let config = load_config(&args.config);
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        match rt.block_on(ostrich::util::test_inbound(
+            &tag,
+            &config,
+            Some(std::time::Duration::from_secs(args.test_outbound_timeout)),
+        )) {
+            Ok(duration) => {
+                println!("round-trip ok in {}ms", duration.as_millis());
                 exit(0);
             }
+            Err(e) => {
+                println!("test inbound failed: {}", e);
+                exit(1);
+            }
         }
     }
 
     if let Err(e) = ostrich::util::run_with_options(
-        // 0,
+        0,
         args.config,
         #[cfg(target_os = "windows")]
         wintun_path.to_string(),
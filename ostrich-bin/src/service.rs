@@ -0,0 +1,323 @@
+#[cfg(any(target_os = "windows", target_os = "macos"))]
+use std::path::{Path, PathBuf};
+
+use argh::FromArgs;
+
+#[cfg(target_os = "windows")]
+pub const SERVICE_NAME: &str = "ostrich";
+
+#[cfg(target_os = "macos")]
+const LAUNCHD_LABEL: &str = "com.ostrich.daemon";
+
+#[cfg(target_os = "macos")]
+const LAUNCHD_PLIST_PATH: &str = "/Library/LaunchDaemons/com.ostrich.daemon.plist";
+
+#[derive(FromArgs)]
+#[argh(subcommand)]
+pub enum ServiceCommand {
+    Install(InstallService),
+    Uninstall(UninstallService),
+    Run(RunService),
+}
+
+#[derive(FromArgs)]
+/// installs ostrich as an auto-start system service
+#[argh(subcommand, name = "install-service")]
+pub struct InstallService {
+    /// the configuration file used by the service
+    #[argh(option, short = 'c', default = "String::from(\"config.conf\")")]
+    pub config: String,
+}
+
+#[derive(FromArgs)]
+/// uninstalls the ostrich system service
+#[argh(subcommand, name = "uninstall-service")]
+pub struct UninstallService {}
+
+#[derive(FromArgs)]
+/// runs under the Windows service control manager, not meant to be called directly
+#[argh(subcommand, name = "run-service")]
+pub struct RunService {
+    /// the configuration file
+    #[argh(option, short = 'c')]
+    pub config: String,
+}
+
+// The service runs from a different working directory, so always pass an
+// absolute config path.
+#[cfg(any(target_os = "windows", target_os = "macos"))]
+fn absolute_config_path(config: &str) -> Result<PathBuf, String> {
+    let path = Path::new(config);
+    if !path.exists() {
+        return Err(format!("config file {} not found", config));
+    }
+    path.canonicalize()
+        .map_err(|e| format!("resolve config path {} failed: {}", config, e))
+}
+
+#[cfg(any(target_os = "windows", target_os = "macos"))]
+fn current_exe() -> Result<PathBuf, String> {
+    std::env::current_exe().map_err(|e| format!("get executable path failed: {}", e))
+}
+
+#[cfg(target_os = "windows")]
+pub fn install(config: &str) -> Result<(), String> {
+    use std::ffi::OsString;
+    use windows_service::service::{
+        ServiceAccess, ServiceErrorControl, ServiceInfo, ServiceStartType, ServiceType,
+    };
+    use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+
+    let config = absolute_config_path(config)?;
+    let manager = ServiceManager::local_computer(
+        None::<&str>,
+        ServiceManagerAccess::CONNECT | ServiceManagerAccess::CREATE_SERVICE,
+    )
+    .map_err(|e| format!("open service manager failed: {}", e))?;
+    let info = ServiceInfo {
+        name: OsString::from(SERVICE_NAME),
+        display_name: OsString::from("ostrich proxy"),
+        service_type: ServiceType::OWN_PROCESS,
+        start_type: ServiceStartType::AutoStart,
+        error_control: ServiceErrorControl::Normal,
+        executable_path: current_exe()?,
+        launch_arguments: vec![
+            OsString::from("run-service"),
+            OsString::from("-c"),
+            config.into_os_string(),
+        ],
+        dependencies: vec![],
+        // LocalSystem, which has the privileges required by TUN.
+        account_name: None,
+        account_password: None,
+    };
+    let service = manager
+        .create_service(&info, ServiceAccess::CHANGE_CONFIG | ServiceAccess::START)
+        .map_err(|e| format!("create service failed: {}", e))?;
+    service
+        .start::<&str>(&[])
+        .map_err(|e| format!("start service failed: {}", e))?;
+    Ok(())
+}
+
+#[cfg(target_os = "windows")]
+pub fn uninstall() -> Result<(), String> {
+    use windows_service::service::{ServiceAccess, ServiceState};
+    use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)
+        .map_err(|e| format!("open service manager failed: {}", e))?;
+    let service = manager
+        .open_service(
+            SERVICE_NAME,
+            ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE,
+        )
+        .map_err(|e| format!("open service failed: {}", e))?;
+    let status = service
+        .query_status()
+        .map_err(|e| format!("query service status failed: {}", e))?;
+    if status.current_state != ServiceState::Stopped {
+        service
+            .stop()
+            .map_err(|e| format!("stop service failed: {}", e))?;
+    }
+    service
+        .delete()
+        .map_err(|e| format!("delete service failed: {}", e))?;
+    Ok(())
+}
+
+#[cfg(target_os = "windows")]
+mod windows_main {
+    use std::ffi::OsString;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    use windows_service::service::{
+        ServiceControl, ServiceControlAccept, ServiceExitCode, ServiceState, ServiceStatus,
+        ServiceType,
+    };
+    use windows_service::service_control_handler::{self, ServiceControlHandlerResult};
+    use windows_service::{define_windows_service, service_dispatcher};
+
+    use super::SERVICE_NAME;
+
+    pub struct RunArgs {
+        pub config: String,
+        pub wintun_path: String,
+        pub tun2socks_path: String,
+    }
+
+    // The dispatcher calls into service_main without any context, hand the
+    // arguments over through a static.
+    static RUN_ARGS: Mutex<Option<RunArgs>> = Mutex::new(None);
+
+    // The service runs a single runtime.
+    const RT_ID: ostrich::RuntimeId = 0;
+
+    define_windows_service!(ffi_service_main, service_main);
+
+    fn set_state(
+        handle: &service_control_handler::ServiceStatusHandle,
+        state: ServiceState,
+        exit_code: u32,
+    ) {
+        let controls_accepted = if state == ServiceState::Running {
+            ServiceControlAccept::STOP
+        } else {
+            ServiceControlAccept::empty()
+        };
+        let _ = handle.set_service_status(ServiceStatus {
+            service_type: ServiceType::OWN_PROCESS,
+            current_state: state,
+            controls_accepted,
+            exit_code: ServiceExitCode::Win32(exit_code),
+            checkpoint: 0,
+            wait_hint: Duration::default(),
+            process_id: None,
+        });
+    }
+
+    fn service_main(_arguments: Vec<OsString>) {
+        let Some(args) = RUN_ARGS.lock().unwrap().take() else {
+            return;
+        };
+        let handler = |control| match control {
+            ServiceControl::Stop => {
+                ostrich::shutdown(RT_ID);
+                ServiceControlHandlerResult::NoError
+            }
+            ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+            _ => ServiceControlHandlerResult::NotImplemented,
+        };
+        let Ok(handle) = service_control_handler::register(SERVICE_NAME, handler) else {
+            return;
+        };
+        set_state(&handle, ServiceState::Running, 0);
+        let exit_code = match ostrich::util::run_with_options(
+            RT_ID,
+            args.config,
+            args.wintun_path,
+            args.tun2socks_path,
+        ) {
+            Ok(_) => 0,
+            Err(_) => 1,
+        };
+        set_state(&handle, ServiceState::Stopped, exit_code);
+    }
+
+    pub fn run(args: RunArgs) -> Result<(), String> {
+        *RUN_ARGS.lock().unwrap() = Some(args);
+        service_dispatcher::start(SERVICE_NAME, ffi_service_main)
+            .map_err(|e| format!("start service dispatcher failed: {}", e))
+    }
+}
+
+#[cfg(target_os = "windows")]
+pub use windows_main::{run, RunArgs};
+
+#[cfg(target_os = "macos")]
+fn launchctl(args: &[&str]) -> Result<(), String> {
+    let status = std::process::Command::new("launchctl")
+        .args(args)
+        .status()
+        .map_err(|e| format!("run launchctl failed: {}", e))?;
+    if !status.success() {
+        return Err(format!("launchctl {} failed: {}", args.join(" "), status));
+    }
+    Ok(())
+}
+
+// Escapes a value interpolated into the plist, the paths may contain any of
+// the XML special characters.
+#[cfg(any(target_os = "macos", test))]
+fn xml_escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(target_os = "macos")]
+pub fn install(config: &str) -> Result<(), String> {
+    let config = absolute_config_path(config)?;
+    let exe = current_exe()?;
+    let plist = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>Label</key>
+    <string>{label}</string>
+    <key>ProgramArguments</key>
+    <array>
+        <string>{exe}</string>
+        <string>-c</string>
+        <string>{config}</string>
+    </array>
+    <key>WorkingDirectory</key>
+    <string>{dir}</string>
+    <key>RunAtLoad</key>
+    <true/>
+    <key>KeepAlive</key>
+    <true/>
+</dict>
+</plist>
+"#,
+        label = LAUNCHD_LABEL,
+        exe = xml_escape(&exe.to_string_lossy()),
+        config = xml_escape(&config.to_string_lossy()),
+        dir = xml_escape(
+            &config
+                .parent()
+                .unwrap_or_else(|| Path::new("/"))
+                .to_string_lossy()
+        ),
+    );
+    // A LaunchDaemon runs as root, which is required for creating the TUN device.
+    std::fs::write(LAUNCHD_PLIST_PATH, plist)
+        .map_err(|e| format!("write {} failed: {}", LAUNCHD_PLIST_PATH, e))?;
+    launchctl(&["load", "-w", LAUNCHD_PLIST_PATH])
+}
+
+#[cfg(target_os = "macos")]
+pub fn uninstall() -> Result<(), String> {
+    if !Path::new(LAUNCHD_PLIST_PATH).exists() {
+        return Err(format!("{} not found", LAUNCHD_PLIST_PATH));
+    }
+    launchctl(&["unload", "-w", LAUNCHD_PLIST_PATH])?;
+    std::fs::remove_file(LAUNCHD_PLIST_PATH)
+        .map_err(|e| format!("remove {} failed: {}", LAUNCHD_PLIST_PATH, e))
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+pub fn install(_config: &str) -> Result<(), String> {
+    Err("service installation is not supported on this platform".to_string())
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+pub fn uninstall() -> Result<(), String> {
+    Err("service installation is not supported on this platform".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_xml_escape() {
+        assert_eq!(xml_escape("/Users/me/config.conf"), "/Users/me/config.conf");
+        assert_eq!(
+            xml_escape("/Users/me/a&b/<c>/\"d's\".conf"),
+            "/Users/me/a&amp;b/&lt;c&gt;/&quot;d&apos;s&quot;.conf"
+        );
+    }
+}
@@ -0,0 +1,394 @@
+use std::io::{self, BufRead, Write};
+use std::path::Path;
+
+use base64::Engine;
+
+const PROXY_TAG: &str = "Proxy";
+const DEFAULT_SOCKS_PORT: u16 = 1080;
+
+// Decodes the %XX escapes of a URI component.
+fn percent_decode(s: &str) -> Result<String, String> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = s
+                .get(i + 1..i + 3)
+                .and_then(|h| u8::from_str_radix(h, 16).ok())
+                .ok_or_else(|| format!("invalid escape in {}", s))?;
+            out.push(hex);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).map_err(|_| format!("invalid UTF-8 in {}", s))
+}
+
+// The conf format splits on commas and equal signs, and trims the values.
+fn check_value<'a>(name: &str, value: &'a str) -> Result<&'a str, String> {
+    if value.is_empty() {
+        return Err(format!("missing {}", name));
+    }
+    if value.trim() != value || value.contains(&[',', '=', '\n', '#'][..]) {
+        return Err(format!(
+            "{} contains characters the config can't hold: {}",
+            name, value
+        ));
+    }
+    Ok(value)
+}
+
+// Splits "host:port" or "[ipv6]:port".
+fn split_host_port(s: &str) -> Result<(String, u16), String> {
+    let (host, port) = s
+        .rsplit_once(':')
+        .ok_or_else(|| format!("missing port in {}", s))?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let port = port
+        .parse::<u16>()
+        .ok()
+        .filter(|p| *p != 0)
+        .ok_or_else(|| format!("invalid port in {}", s))?;
+    Ok((check_value("host", host)?.to_string(), port))
+}
+
+fn split_query(s: &str) -> (&str, Vec<(&str, &str)>) {
+    match s.split_once('?') {
+        Some((s, query)) => (
+            s,
+            query.split('&').filter_map(|p| p.split_once('=')).collect(),
+        ),
+        None => (s, Vec::new()),
+    }
+}
+
+/// Builds the conf line of a trojan server, without the tag.
+pub fn trojan_proxy(
+    host: &str,
+    port: u16,
+    password: &str,
+    sni: Option<&str>,
+) -> Result<String, String> {
+    let mut line = format!(
+        "trojan, {}, {}, password={}",
+        check_value("host", host)?,
+        port,
+        check_value("password", password)?
+    );
+    if let Some(sni) = sni {
+        line.push_str(&format!(", sni={}", check_value("sni", sni)?));
+    }
+    Ok(line)
+}
+
+// trojan://password@host:port?sni=example.com&type=ws&path=/ws#name
+fn trojan_uri(uri: &str) -> Result<String, String> {
+    let (rest, query) = split_query(uri);
+    let (userinfo, server) = rest
+        .rsplit_once('@')
+        .ok_or_else(|| "missing password".to_string())?;
+    let (host, port) = split_host_port(server.trim_end_matches('/'))?;
+    let password = percent_decode(userinfo)?;
+    let mut sni = None;
+    let mut params = Vec::new();
+    for (k, v) in query {
+        let v = percent_decode(v)?;
+        match k {
+            "sni" | "peer" if !v.is_empty() => sni = Some(v),
+            "allowInsecure" if v == "1" || v == "true" => {
+                params.push("tls-insecure=true".to_string())
+            }
+            "type" if v == "ws" => params.push("ws=true".to_string()),
+            "type" if v != "tcp" => return Err(format!("unsupported transport {}", v)),
+            "path" => params.push(format!("ws-path={}", check_value("path", &v)?)),
+            "host" => params.push(format!("ws-host={}", check_value("host", &v)?)),
+            "alpn" => {
+                let alpn: Vec<&str> = v.split(',').map(str::trim).collect();
+                params.push(format!("alpn={}", check_value("alpn", &alpn.join("|"))?));
+            }
+            _ => (),
+        }
+    }
+    let mut line = trojan_proxy(&host, port, &password, sni.as_deref())?;
+    for param in params {
+        line.push_str(", ");
+        line.push_str(&param);
+    }
+    Ok(line)
+}
+
+// The user info is "method:password", base64 encoded, or percent encoded for
+// the 2022 ciphers.
+fn decode_ss_userinfo(userinfo: &str) -> Result<String, String> {
+    let userinfo = percent_decode(userinfo)?;
+    if userinfo.contains(':') {
+        return Ok(userinfo);
+    }
+    let trimmed = userinfo.trim_end_matches('=');
+    let engines = [
+        base64::engine::general_purpose::URL_SAFE_NO_PAD,
+        base64::engine::general_purpose::STANDARD_NO_PAD,
+    ];
+    engines
+        .iter()
+        .find_map(|e| e.decode(trimmed).ok())
+        .and_then(|d| String::from_utf8(d).ok())
+        .ok_or_else(|| "invalid user info".to_string())
+}
+
+// SIP002 ss://userinfo@host:port/?plugin=...#name, or the legacy
+// ss://base64(method:password@host:port)#name.
+fn ss_uri(uri: &str) -> Result<String, String> {
+    let (rest, query) = split_query(uri);
+    if query.iter().any(|(k, _)| *k == "plugin") {
+        return Err("shadowsocks plugins are not supported".to_string());
+    }
+    let rest = rest.trim_end_matches('/');
+    let (method, password, server) = match rest.rsplit_once('@') {
+        Some((userinfo, server)) => {
+            let userinfo = decode_ss_userinfo(userinfo)?;
+            let (method, password) = userinfo
+                .split_once(':')
+                .ok_or_else(|| "missing password".to_string())?;
+            (method.to_string(), password.to_string(), server.to_string())
+        }
+        None => {
+            let decoded = decode_ss_userinfo(rest)?;
+            let (userinfo, server) = decoded
+                .rsplit_once('@')
+                .ok_or_else(|| "missing server".to_string())?;
+            let (method, password) = userinfo
+                .split_once(':')
+                .ok_or_else(|| "missing password".to_string())?;
+            (method.to_string(), password.to_string(), server.to_string())
+        }
+    };
+    let (host, port) = split_host_port(&server)?;
+    ostrich::proxy::shadowsocks::ShadowCipher::new(&method, &password)
+        .map_err(|e| e.to_string())?;
+    Ok(format!(
+        "ss, {}, {}, encrypt-method={}, password={}",
+        host,
+        port,
+        check_value("method", &method)?,
+        check_value("password", &password)?
+    ))
+}
+
+/// Builds the conf line of the server a share URI points to, without the
+/// tag, trojan:// and ss:// URIs are supported.
+pub fn proxy_from_uri(uri: &str) -> Result<String, String> {
+    // The name in the fragment is not used, the tag is always the same.
+    let uri = uri.trim();
+    let uri = uri.split_once('#').map(|(u, _)| u).unwrap_or(uri);
+    if let Some(rest) = uri.strip_prefix("trojan://") {
+        trojan_uri(rest)
+    } else if let Some(rest) = uri.strip_prefix("ss://") {
+        ss_uri(rest)
+    } else {
+        Err("expect a trojan:// or ss:// URI".to_string())
+    }
+}
+
+/// Makes a config sending everything through the proxy, with a SOCKS inbound
+/// on the local port. It's parsed and rendered again so that what's written
+/// is known to load.
+pub fn make_config(proxy: &str, socks_port: u16) -> Result<String, String> {
+    let conf = format!(
+        "[General]\n\
+         loglevel = info\n\
+         dns-server = 1.1.1.1, 8.8.8.8\n\
+         socks-interface = 127.0.0.1\n\
+         socks-port = {}\n\
+         \n\
+         [Proxy]\n\
+         Direct = direct\n\
+         {} = {}\n\
+         \n\
+         [Rule]\n\
+         FINAL, {}\n",
+        socks_port, PROXY_TAG, proxy, PROXY_TAG
+    );
+    let config = ostrich::config::from_string(&conf).map_err(|e| e.to_string())?;
+    if !config.outbounds.iter().any(|o| o.tag == PROXY_TAG) {
+        return Err(format!("invalid server {}", proxy));
+    }
+    Ok(ostrich::config::to_string(&config))
+}
+
+struct Prompt<R, W> {
+    input: R,
+    output: W,
+}
+
+impl<R: BufRead, W: Write> Prompt<R, W> {
+    // Asks until there's a valid answer, an empty one takes the default.
+    fn ask<T, F>(&mut self, question: &str, default: Option<&str>, parse: F) -> io::Result<T>
+    where
+        F: Fn(&str) -> Result<T, String>,
+    {
+        loop {
+            match default {
+                Some(d) if !d.is_empty() => write!(self.output, "{} [{}]: ", question, d)?,
+                _ => write!(self.output, "{}: ", question)?,
+            }
+            self.output.flush()?;
+            let mut line = String::new();
+            if self.input.read_line(&mut line)? == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "aborted"));
+            }
+            let line = line.trim();
+            let answer = if line.is_empty() {
+                default.unwrap_or_default()
+            } else {
+                line
+            };
+            match parse(answer) {
+                Ok(v) => return Ok(v),
+                Err(e) => writeln!(self.output, "{}", e)?,
+            }
+        }
+    }
+
+    fn ask_proxy(&mut self) -> io::Result<String> {
+        let uri = self.ask(
+            "Server URI (trojan:// or ss://), empty to enter the trojan server",
+            Some(""),
+            |s| {
+                if s.is_empty() {
+                    Ok(None)
+                } else {
+                    proxy_from_uri(s).map(Some)
+                }
+            },
+        )?;
+        if let Some(proxy) = uri {
+            return Ok(proxy);
+        }
+        let host = self.ask("Trojan server address", None, |s| {
+            check_value("address", s).map(str::to_string)
+        })?;
+        let port = self.ask("Trojan server port", Some("443"), |s| {
+            s.parse::<u16>()
+                .ok()
+                .filter(|p| *p != 0)
+                .ok_or_else(|| format!("invalid port {}", s))
+        })?;
+        let password = self.ask("Password", None, |s| {
+            check_value("password", s).map(str::to_string)
+        })?;
+        let sni = self.ask("TLS server name, empty to use the address", Some(""), |s| {
+            if s.is_empty() {
+                Ok(None)
+            } else {
+                check_value("server name", s).map(|s| Some(s.to_string()))
+            }
+        })?;
+        Ok(trojan_proxy(&host, port, &password, sni.as_deref()).unwrap())
+    }
+
+    fn ask_config(&mut self) -> io::Result<String> {
+        loop {
+            let proxy = self.ask_proxy()?;
+            let default_port = DEFAULT_SOCKS_PORT.to_string();
+            let socks_port = self.ask("Local SOCKS port", Some(&default_port), |s| {
+                s.parse::<u16>()
+                    .ok()
+                    .filter(|p| *p != 0)
+                    .ok_or_else(|| format!("invalid port {}", s))
+            })?;
+            match make_config(&proxy, socks_port) {
+                Ok(conf) => return Ok(conf),
+                Err(e) => writeln!(self.output, "invalid config: {}, try again", e)?,
+            }
+        }
+    }
+}
+
+/// Asks for a server and a local SOCKS port on the terminal, writes the
+/// config to `path`.
+pub fn run(path: &str) -> Result<(), String> {
+    let stdin = io::stdin();
+    let mut prompt = Prompt {
+        input: stdin.lock(),
+        output: io::stdout(),
+    };
+    let err = |e: io::Error| e.to_string();
+    if Path::new(path).exists() {
+        let overwrite = prompt
+            .ask(
+                &format!("{} exists, overwrite? (y/n)", path),
+                Some("n"),
+                |s| Ok(s.eq_ignore_ascii_case("y")),
+            )
+            .map_err(err)?;
+        if !overwrite {
+            return Err("aborted".to_string());
+        }
+    }
+    let conf = prompt.ask_config().map_err(err)?;
+    std::fs::write(path, conf).map_err(|e| format!("write {} failed: {}", path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_proxy_from_uri() {
+        assert_eq!(
+            proxy_from_uri(
+                "trojan://p%40ss@example.com:443?sni=cdn.example.com&type=ws&path=%2Fws#Home"
+            )
+            .unwrap(),
+            "trojan, example.com, 443, password=p@ss, sni=cdn.example.com, ws=true, ws-path=/ws"
+        );
+        assert_eq!(
+            proxy_from_uri("trojan://pass@[2001:db8::1]:8443").unwrap(),
+            "trojan, 2001:db8::1, 8443, password=pass"
+        );
+        // base64url of "aes-128-gcm:test"
+        assert_eq!(
+            proxy_from_uri("ss://YWVzLTEyOC1nY206dGVzdA@192.168.100.1:8888#Example").unwrap(),
+            "ss, 192.168.100.1, 8888, encrypt-method=aes-128-gcm, password=test"
+        );
+        // Legacy, base64 of "aes-128-gcm:test@192.168.100.1:8888"
+        assert_eq!(
+            proxy_from_uri("ss://YWVzLTEyOC1nY206dGVzdEAxOTIuMTY4LjEwMC4xOjg4ODg=").unwrap(),
+            "ss, 192.168.100.1, 8888, encrypt-method=aes-128-gcm, password=test"
+        );
+
+        assert!(proxy_from_uri("vmess://abc").is_err());
+        assert!(proxy_from_uri("trojan://example.com:443").is_err());
+        assert!(proxy_from_uri("trojan://pa,ss@example.com:443").is_err());
+        assert!(proxy_from_uri("trojan://pass@example.com").is_err());
+        assert!(proxy_from_uri("ss://YWVzLTEyOC1nY206dGVzdA@1.2.3.4:8888/?plugin=obfs").is_err());
+        assert!(proxy_from_uri("ss://cmM0LW1kNTp0ZXN0@1.2.3.4:8888").is_err());
+    }
+
+    #[test]
+    fn test_ask_config() {
+        let input = b"ftp://example.com\n\nexample.com\n\npass\n\n1081\n";
+        let mut output = Vec::new();
+        let mut prompt = Prompt {
+            input: &input[..],
+            output: &mut output,
+        };
+        let conf = prompt.ask_config().unwrap();
+        let config = ostrich::config::from_string(&conf).unwrap();
+        assert!(config.inbounds.iter().any(|i| i.port == 1081));
+        assert!(config.outbounds.iter().any(|o| o.tag == PROXY_TAG));
+        assert!(String::from_utf8(output)
+            .unwrap()
+            .contains("expect a trojan:// or ss:// URI"));
+
+        let mut prompt = Prompt {
+            input: &b"trojan://pass@example.com:443\n"[..],
+            output: Vec::new(),
+        };
+        assert!(prompt.ask_config().is_err());
+    }
+}
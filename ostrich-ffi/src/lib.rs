@@ -18,10 +18,19 @@ pub const ERR_SYNC_CHANNEL_RECV: i32 = 6;
 pub const ERR_RUNTIME_MANAGER: i32 = 7;
 /// No associated config file.
 pub const ERR_NO_CONFIG_FILE: i32 = 8;
+/// An inbound failed to bind its address.
+pub const ERR_BIND_FAILED: i32 = 9;
+/// TUN setup error.
+pub const ERR_TUN_SETUP: i32 = 10;
+/// Outbound unreachable.
+pub const ERR_OUTBOUND_UNREACHABLE: i32 = 11;
 
 fn to_errno(e: ostrich::Error) -> i32 {
     match e {
-        ostrich::Error::Config(..) => ERR_CONFIG,
+        ostrich::Error::Config(..) | ostrich::Error::ConfigParse(..) => ERR_CONFIG,
+        ostrich::Error::BindFailed { .. } => ERR_BIND_FAILED,
+        ostrich::Error::TunSetup(..) => ERR_TUN_SETUP,
+        ostrich::Error::OutboundUnreachable { .. } => ERR_OUTBOUND_UNREACHABLE,
         ostrich::Error::NoConfigFile => ERR_NO_CONFIG_FILE,
         ostrich::Error::Io(..) => ERR_IO,
         #[cfg(feature = "auto-reload")]
@@ -93,9 +102,9 @@ pub extern "C" fn ostrich_run(rt_id: u16, config_path: *const c_char) -> i32 {
     if let Ok(config_path) = unsafe { CStr::from_ptr(config_path).to_str() } {
         let opts = ostrich::StartOptions {
             config: ostrich::Config::File(config_path.to_string()),
-            #[cfg(feature = "auto-reload")]
-            auto_reload: false,
-            runtime_opt: ostrich::RuntimeOption::SingleThread,
+            api_listen: None,
+            #[cfg(target_os = "android")]
+            socket_protect_path: None,
         };
         if let Err(e) = ostrich::start(rt_id, opts) {
             return to_errno(e);
@@ -111,9 +120,9 @@ pub extern "C" fn ostrich_run_with_config_string(rt_id: u16, config: *const c_ch
     if let Ok(config) = unsafe { CStr::from_ptr(config).to_str() } {
         let opts = ostrich::StartOptions {
             config: ostrich::Config::Str(config.to_string()),
-            #[cfg(feature = "auto-reload")]
-            auto_reload: false,
-            runtime_opt: ostrich::RuntimeOption::SingleThread,
+            api_listen: None,
+            #[cfg(target_os = "android")]
+            socket_protect_path: None,
         };
         if let Err(e) = ostrich::start(rt_id, opts) {
             return to_errno(e);
@@ -0,0 +1,74 @@
+//! Throughput of relaying a TCP session between an inbound stream and a
+//! direct outbound connected to a loopback echo server.
+//!
+//!     cargo bench -p ostrich --bench relay
+//!
+//! The relay reads the buffer size and timeouts from the options as a running
+//! instance does, e.g. set `LINK_BUFFER_SIZE` to compare buffer sizes.
+
+use std::net::SocketAddr;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+const SIZES: &[usize] = &[64 * 1024, 1024 * 1024, 16 * 1024 * 1024];
+
+async fn run_echo_server(listener: TcpListener) {
+    loop {
+        if let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let (mut r, mut w) = stream.split();
+                let _ = tokio::io::copy(&mut r, &mut w).await;
+            });
+        }
+    }
+}
+
+// Sends the payload through the relay and reads it back from the echo server.
+async fn relay_once(server: SocketAddr, payload: &[u8]) {
+    let (client, mut lhs) = tokio::io::duplex(64 * 1024);
+    let mut rhs = ostrich::proxy::dial_tcp_stream(&server).await.unwrap();
+    let relay = tokio::spawn(async move { ostrich::common::io::relay(&mut lhs, &mut rhs).await });
+
+    let (mut r, mut w) = tokio::io::split(client);
+    let write = async {
+        w.write_all(payload).await.unwrap();
+        w.shutdown().await.unwrap();
+    };
+    let read = async {
+        let mut buf = vec![0u8; 64 * 1024];
+        let mut n = 0;
+        while n < payload.len() {
+            let k = r.read(&mut buf).await.unwrap();
+            assert!(k > 0, "relay closed after {} bytes", n);
+            n += k;
+        }
+    };
+    futures::future::join(write, read).await;
+    drop((r, w));
+    let _ = relay.await;
+}
+
+fn bench_relay(c: &mut Criterion) {
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let listener = rt.block_on(TcpListener::bind("127.0.0.1:0")).unwrap();
+    let server = listener.local_addr().unwrap();
+    rt.spawn(run_echo_server(listener));
+
+    let mut group = c.benchmark_group("relay");
+    for size in SIZES {
+        let payload = vec![0x5au8; *size];
+        group.throughput(Throughput::Bytes(*size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &payload, |b, p| {
+            b.to_async(&rt).iter(|| relay_once(server, p))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_relay);
+criterion_main!(benches);
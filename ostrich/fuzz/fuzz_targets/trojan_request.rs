@@ -0,0 +1,14 @@
+#![no_main]
+
+use bytes::BytesMut;
+use libfuzzer_sys::fuzz_target;
+use ostrich::proxy::trojan::codec;
+
+fuzz_target!(|data: &[u8]| {
+    // Must never panic, and whatever decodes must encode back to the same bytes.
+    if let Ok((req, n)) = codec::decode_request(data) {
+        let mut buf = BytesMut::new();
+        codec::encode_request(&req, &mut buf);
+        assert_eq!(&buf[..], &data[..n]);
+    }
+});
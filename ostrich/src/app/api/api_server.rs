@@ -0,0 +1,594 @@
+use std::fmt;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    routing::{get, post, put},
+    Json, Router,
+};
+use log::*;
+use serde_derive::{Deserialize, Serialize};
+use trust_dns_proto::rr::record_type::RecordType;
+
+use crate::{RuntimeManager, Runner};
+
+#[derive(Serialize)]
+struct FlushResponse {
+    #[serde(rename = "dnsCacheEntries")]
+    dns_cache_entries: usize,
+    #[serde(rename = "statCounters")]
+    stat_counters: usize,
+}
+
+async fn maintenance_flush(State(rm): State<Arc<RuntimeManager>>) -> Json<FlushResponse> {
+    let summary = rm.flush_caches().await;
+    Json(FlushResponse {
+        dns_cache_entries: summary.dns_cache_entries,
+        stat_counters: summary.stat_counters,
+    })
+}
+
+#[derive(Deserialize)]
+struct LogsQuery {
+    level: Option<String>,
+    limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct LogLine {
+    level: String,
+    message: String,
+}
+
+async fn logs(Query(query): Query<LogsQuery>) -> Result<Json<Vec<LogLine>>, (StatusCode, String)> {
+    let level = match query.level.as_deref() {
+        Some(l) => l
+            .parse::<LevelFilter>()
+            .map_err(|_| (StatusCode::BAD_REQUEST, format!("invalid level: {}", l)))?,
+        None => LevelFilter::Trace,
+    };
+    let lines = crate::app::logger::recent_logs(level, query.limit.unwrap_or(200))
+        .into_iter()
+        .map(|l| LogLine {
+            level: l.level.to_string(),
+            message: l.message,
+        })
+        .collect();
+    Ok(Json(lines))
+}
+
+#[derive(Serialize)]
+struct OutboundStats {
+    tag: String,
+    attempts: u64,
+    successes: u64,
+    #[serde(rename = "connectFailures")]
+    connect_failures: u64,
+    #[serde(rename = "handshakeFailures")]
+    handshake_failures: u64,
+    timeouts: u64,
+    #[serde(rename = "consecutiveFailures")]
+    consecutive_failures: u64,
+    #[serde(rename = "successRate")]
+    success_rate: Option<f64>,
+    active: u64,
+    connected: bool,
+    // Seconds since the epoch and since then the attempts have been
+    // succeeding.
+    #[serde(rename = "upSince")]
+    up_since: Option<u64>,
+    #[serde(rename = "upFor")]
+    up_for: Option<u64>,
+}
+
+async fn outbound_stats(State(rm): State<Arc<RuntimeManager>>) -> Json<Vec<OutboundStats>> {
+    let stats = rm
+        .outbound_stats()
+        .into_iter()
+        .map(|s| OutboundStats {
+            success_rate: s.success_rate(),
+            connected: s.connected(),
+            up_since: s
+                .up_since
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_secs()),
+            up_for: s.up_for().map(|d| d.as_secs()),
+            active: s.active,
+            tag: s.tag,
+            attempts: s.attempts,
+            successes: s.successes,
+            connect_failures: s.connect_failures,
+            handshake_failures: s.handshake_failures,
+            timeouts: s.timeouts,
+            consecutive_failures: s.consecutive_failures,
+        })
+        .collect();
+    Json(stats)
+}
+
+#[cfg(feature = "stat")]
+#[derive(Serialize)]
+struct OutboundUsage {
+    tag: String,
+    month: String,
+    #[serde(rename = "monthSent")]
+    month_sent: u64,
+    #[serde(rename = "monthRecvd")]
+    month_recvd: u64,
+    #[serde(rename = "totalSent")]
+    total_sent: u64,
+    #[serde(rename = "totalRecvd")]
+    total_recvd: u64,
+}
+
+#[cfg(feature = "stat")]
+async fn outbound_usage(State(rm): State<Arc<RuntimeManager>>) -> Json<Vec<OutboundUsage>> {
+    let usage = rm
+        .outbound_usage()
+        .await
+        .into_iter()
+        .map(|(tag, u)| OutboundUsage {
+            tag,
+            month: u.month,
+            month_sent: u.month_sent,
+            month_recvd: u.month_recvd,
+            total_sent: u.total_sent,
+            total_recvd: u.total_recvd,
+        })
+        .collect();
+    Json(usage)
+}
+
+#[cfg(feature = "stat")]
+#[derive(Serialize)]
+struct OutboundTraffic {
+    upload: u64,
+    download: u64,
+    active: u64,
+}
+
+#[cfg(feature = "stat")]
+async fn outbound_traffic(
+    State(rm): State<Arc<RuntimeManager>>,
+) -> Json<std::collections::BTreeMap<String, OutboundTraffic>> {
+    let traffic = rm
+        .outbound_traffic()
+        .await
+        .into_iter()
+        .map(|(tag, t)| {
+            (
+                tag,
+                OutboundTraffic {
+                    upload: t.sent,
+                    download: t.recvd,
+                    active: t.active,
+                },
+            )
+        })
+        .collect();
+    Json(traffic)
+}
+
+#[cfg(feature = "stat")]
+#[derive(Serialize)]
+struct ClientUsage {
+    inbound: String,
+    client: String,
+    sent: u64,
+    recvd: u64,
+}
+
+#[cfg(feature = "stat")]
+async fn client_usage(State(rm): State<Arc<RuntimeManager>>) -> Json<Vec<ClientUsage>> {
+    let usage = rm
+        .client_usage()
+        .await
+        .into_iter()
+        .map(|c| ClientUsage {
+            inbound: c.inbound_tag,
+            client: c.client,
+            sent: c.usage.total_sent,
+            recvd: c.usage.total_recvd,
+        })
+        .collect();
+    Json(usage)
+}
+
+#[derive(Serialize)]
+struct InboundReady {
+    tag: String,
+    ready: bool,
+}
+
+#[derive(Serialize)]
+struct Health {
+    ready: bool,
+    inbounds: Vec<InboundReady>,
+    tun: Option<bool>,
+}
+
+// 200 if all the inbounds are serving, 503 otherwise, for liveness and
+// readiness probes.
+async fn health(State(rm): State<Arc<RuntimeManager>>) -> (StatusCode, Json<Health>) {
+    let health = rm.inbound_health();
+    let status = if health.ready() {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    let inbounds = health
+        .listeners
+        .iter()
+        .map(|(tag, ready)| InboundReady {
+            tag: tag.clone(),
+            ready: *ready,
+        })
+        .collect();
+    (
+        status,
+        Json(Health {
+            ready: health.ready(),
+            inbounds,
+            tun: health.tun,
+        }),
+    )
+}
+
+async fn routes() -> Json<Vec<String>> {
+    Json(crate::route_changes())
+}
+
+#[derive(Serialize)]
+struct TunStats {
+    #[serde(rename = "rxPackets")]
+    rx_packets: u64,
+    #[serde(rename = "rxBytes")]
+    rx_bytes: u64,
+    #[serde(rename = "txPackets")]
+    tx_packets: u64,
+    #[serde(rename = "txBytes")]
+    tx_bytes: u64,
+    #[serde(rename = "droppedPackets")]
+    dropped_packets: u64,
+}
+
+async fn tun_stats(State(rm): State<Arc<RuntimeManager>>) -> Json<TunStats> {
+    let c = rm.tun_counters();
+    Json(TunStats {
+        rx_packets: c.rx_packets,
+        rx_bytes: c.rx_bytes,
+        tx_packets: c.tx_packets,
+        tx_bytes: c.tx_bytes,
+        dropped_packets: c.dropped_packets,
+    })
+}
+
+async fn dns_update(
+    State(rm): State<Arc<RuntimeManager>>,
+    body: String,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let dns = crate::config::json::dns_from_string(&body)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    rm.reload_dns(&protobuf::MessageField::some(dns))
+        .await
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    Ok(StatusCode::OK)
+}
+
+#[derive(Serialize)]
+struct TlsReloadResponse {
+    reloaded: usize,
+}
+
+async fn tls_reload(
+    State(rm): State<Arc<RuntimeManager>>,
+) -> Result<Json<TlsReloadResponse>, (StatusCode, String)> {
+    let reloaded = rm
+        .reload_tls()
+        .await
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(TlsReloadResponse { reloaded }))
+}
+
+#[derive(Deserialize)]
+struct DnsResolveQuery {
+    name: String,
+    #[serde(rename = "type")]
+    type_: Option<String>,
+}
+
+#[derive(Serialize)]
+struct DnsResolveResponse {
+    name: String,
+    #[serde(rename = "type")]
+    type_: String,
+    answers: Vec<String>,
+    upstream: String,
+    cached: bool,
+    ttl: u64,
+}
+
+async fn dns_resolve(
+    State(rm): State<Arc<RuntimeManager>>,
+    Query(query): Query<DnsResolveQuery>,
+) -> Result<Json<DnsResolveResponse>, (StatusCode, String)> {
+    let type_ = query.type_.as_deref().unwrap_or("A").to_uppercase();
+    let ty = type_
+        .parse::<RecordType>()
+        .map_err(|_| (StatusCode::BAD_REQUEST, format!("invalid type: {}", type_)))?;
+    let res = rm
+        .resolve(&query.name, ty)
+        .await
+        .map_err(|e| (StatusCode::BAD_GATEWAY, e.to_string()))?;
+    Ok(Json(DnsResolveResponse {
+        name: query.name,
+        type_,
+        answers: res.ips.iter().map(ToString::to_string).collect(),
+        upstream: res.source.to_string(),
+        cached: res.cached,
+        ttl: res.ttl,
+    }))
+}
+
+async fn outbounds_reload(
+    State(rm): State<Arc<RuntimeManager>>,
+) -> Result<StatusCode, (StatusCode, String)> {
+    rm.reload_outbounds()
+        .await
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    Ok(StatusCode::OK)
+}
+
+#[derive(Deserialize)]
+struct AddOutboundQuery {
+    replace: Option<bool>,
+}
+
+async fn add_outbound(
+    State(rm): State<Arc<RuntimeManager>>,
+    Query(query): Query<AddOutboundQuery>,
+    body: String,
+) -> Result<StatusCode, (StatusCode, String)> {
+    let outbound = crate::config::json::outbound_from_string(&body)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    rm.add_outbound(&outbound, query.replace.unwrap_or(false))
+        .await
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()))?;
+    Ok(StatusCode::CREATED)
+}
+
+#[derive(Deserialize)]
+struct ShutdownQuery {
+    wait: Option<bool>,
+    timeout: Option<u64>,
+}
+
+// Seconds to wait for the runtime to stop if not given.
+const DEFAULT_SHUTDOWN_TIMEOUT: u64 = 10;
+
+async fn shutdown(
+    State(rm): State<Arc<RuntimeManager>>,
+    Query(query): Query<ShutdownQuery>,
+) -> StatusCode {
+    if !rm.shutdown().await {
+        return StatusCode::INTERNAL_SERVER_ERROR;
+    }
+    if !query.wait.unwrap_or(false) {
+        return StatusCode::ACCEPTED;
+    }
+    let timeout = Duration::from_secs(query.timeout.unwrap_or(DEFAULT_SHUTDOWN_TIMEOUT));
+    if rm.wait_stopped(timeout).await {
+        StatusCode::OK
+    } else {
+        StatusCode::GATEWAY_TIMEOUT
+    }
+}
+
+/// An address to serve the API on, a socket address or `unix:` followed by
+/// the path of a Unix domain socket.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenAddr {
+    Tcp(SocketAddr),
+    #[cfg(unix)]
+    Unix(std::path::PathBuf),
+}
+
+impl FromStr for ListenAddr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            #[cfg(unix)]
+            return if path.is_empty() {
+                Err(anyhow::anyhow!("invalid api address {}: missing path", s))
+            } else {
+                Ok(ListenAddr::Unix(path.into()))
+            };
+            #[cfg(not(unix))]
+            return Err(anyhow::anyhow!(
+                "invalid api address {}: unix sockets are not supported",
+                s
+            ));
+        }
+        s.parse::<SocketAddr>()
+            .map(ListenAddr::Tcp)
+            .map_err(|e| anyhow::anyhow!("invalid api address {}: {}", s, e))
+    }
+}
+
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ListenAddr::Tcp(addr) => write!(f, "tcp {}", addr),
+            #[cfg(unix)]
+            ListenAddr::Unix(path) => write!(f, "unix {}", path.display()),
+        }
+    }
+}
+
+/// Parses a comma separated list of addresses, such as API_LISTEN.
+pub fn parse_listen_addrs(s: &str) -> anyhow::Result<Vec<ListenAddr>> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|a| !a.is_empty())
+        .map(str::parse)
+        .collect()
+}
+
+// Accepts the connections to a Unix domain socket for the HTTP server.
+#[cfg(unix)]
+struct UnixAccept(tokio::net::UnixListener);
+
+#[cfg(unix)]
+impl hyper::server::accept::Accept for UnixAccept {
+    type Conn = tokio::net::UnixStream;
+    type Error = std::io::Error;
+
+    fn poll_accept(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Result<Self::Conn, Self::Error>>> {
+        self.0
+            .poll_accept(cx)
+            .map(|res| Some(res.map(|(stream, _)| stream)))
+    }
+}
+
+// Removes the socket file once the server stops, or is dropped on shutdown.
+#[cfg(unix)]
+struct SocketFile<'a>(&'a std::path::Path);
+
+#[cfg(unix)]
+impl Drop for SocketFile<'_> {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(self.0);
+    }
+}
+
+#[cfg(unix)]
+async fn serve_unix(
+    path: &std::path::Path,
+    app: Router,
+    signal: impl std::future::Future<Output = ()>,
+) -> anyhow::Result<()> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+    // A socket left by a previous run fails the bind.
+    if std::fs::symlink_metadata(path).map_or(false, |m| m.file_type().is_socket()) {
+        std::fs::remove_file(path)?;
+    }
+    let listener = tokio::net::UnixListener::bind(path)?;
+    let _file = SocketFile(path);
+    // The API controls the runtime, only the owner may connect.
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    axum::Server::builder(UnixAccept(listener))
+        .serve(app.into_make_service())
+        .with_graceful_shutdown(signal)
+        .await?;
+    Ok(())
+}
+
+pub struct ApiServer {
+    runtime_manager: Arc<RuntimeManager>,
+}
+
+impl ApiServer {
+    pub fn new(runtime_manager: Arc<RuntimeManager>) -> Self {
+        Self { runtime_manager }
+    }
+
+    pub fn serve(&self, listen_addr: ListenAddr) -> Runner {
+        let app = Router::new()
+            .route("/dns", put(dns_update))
+            .route("/health", get(health))
+            .route("/dns/resolve", get(dns_resolve))
+            .route("/maintenance/flush", post(maintenance_flush))
+            .route("/logs", get(logs))
+            .route("/outbounds", post(add_outbound))
+            .route("/outbounds/reload", post(outbounds_reload))
+            .route("/outbounds/stats", get(outbound_stats))
+            .route("/routes", get(routes))
+            .route("/tun/stats", get(tun_stats))
+            .route("/tls/reload", post(tls_reload))
+            .route("/shutdown", post(shutdown));
+        #[cfg(feature = "stat")]
+        let app = app
+            .route("/outbounds/usage", get(outbound_usage))
+            .route("/clients/usage", get(client_usage))
+            .route("/stats/outbounds", get(outbound_traffic));
+        let app = app.with_state(self.runtime_manager.clone());
+        // Stops accepting and finishes the pending requests once the runtime
+        // has stopped, e.g. answers the POST /shutdown waiting for it.
+        let signal = self.runtime_manager.stopped_signal();
+        Box::pin(async move {
+            info!("api server listening {}", &listen_addr);
+            let res = match &listen_addr {
+                ListenAddr::Tcp(addr) => axum::Server::bind(addr)
+                    .serve(app.into_make_service())
+                    .with_graceful_shutdown(signal)
+                    .await
+                    .map_err(anyhow::Error::from),
+                #[cfg(unix)]
+                ListenAddr::Unix(path) => serve_unix(path, app, signal).await,
+            };
+            if let Err(e) = res {
+                error!("api server on {} failed: {}", &listen_addr, e);
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_parse_listen_addrs() {
+        let addrs = parse_listen_addrs("127.0.0.1:9991, unix:/tmp/ostrich-api.sock,").unwrap();
+        assert_eq!(
+            addrs,
+            vec![
+                ListenAddr::Tcp("127.0.0.1:9991".parse().unwrap()),
+                ListenAddr::Unix("/tmp/ostrich-api.sock".into()),
+            ]
+        );
+        assert!(parse_listen_addrs("").unwrap().is_empty());
+        assert!(parse_listen_addrs("127.0.0.1").is_err());
+        assert!(parse_listen_addrs("unix:").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_serve_unix() {
+        use std::os::unix::fs::PermissionsExt;
+        let path = std::env::temp_dir().join(format!("ostrich-api-{}.sock", std::process::id()));
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+            let serve = serve_unix(&path, Router::new(), async {
+                let _ = rx.await;
+            });
+            tokio::pin!(serve);
+            // Still serving.
+            assert!(
+                tokio::time::timeout(std::time::Duration::from_millis(100), &mut serve)
+                    .await
+                    .is_err()
+            );
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+            // Until signaled.
+            tx.send(()).unwrap();
+            serve.await.unwrap();
+        });
+        // The socket file is gone with the server.
+        assert!(!path.exists());
+    }
+}
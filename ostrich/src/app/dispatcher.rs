@@ -1,7 +1,8 @@
 use std::convert::TryFrom;
 use std::io::{self, ErrorKind};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use async_recursion::async_recursion;
 use log::*;
@@ -11,6 +12,7 @@ use tokio::sync::RwLock;
 use crate::{
     app::SyncDnsClient,
     common::{self, sniff},
+    config::router::Unmatched,
     option,
     proxy::*,
     session::*,
@@ -20,7 +22,25 @@ use crate::{
 use crate::app::SyncStatManager;
 
 use super::outbound::manager::OutboundManager;
-use super::router::Router;
+use super::outbound::metrics::{Failure, OutboundMetrics};
+use super::router::{Resolve, Router};
+
+// Whether the session looks like QUIC, i.e. HTTP/3.
+fn is_quic(sess: &Session) -> bool {
+    sess.network == Network::Udp && sess.destination.port() == 443
+}
+
+// Logs at info level for sessions traced by their routing rule, at debug
+// level otherwise.
+macro_rules! sess_debug {
+    ($sess:expr, $($arg:tt)+) => {
+        if $sess.trace {
+            info!($($arg)+)
+        } else {
+            debug!($($arg)+)
+        }
+    };
+}
 
 #[inline]
 fn log_request(
@@ -54,10 +74,61 @@ fn log_request(
     );
 }
 
+/// Tracks the streams in flight and the time of the latest activity.
+pub struct Activity {
+    start: Instant,
+    streams: AtomicUsize,
+    // Milliseconds since start.
+    last: AtomicU64,
+}
+
+impl Activity {
+    fn new() -> Self {
+        Activity {
+            start: Instant::now(),
+            streams: AtomicUsize::new(0),
+            last: AtomicU64::new(0),
+        }
+    }
+
+    pub fn touch(&self) {
+        self.last
+            .store(self.start.elapsed().as_millis() as u64, Ordering::Relaxed);
+    }
+
+    /// How long nothing has happened, `None` if any stream is in flight.
+    pub fn idle_for(&self) -> Option<Duration> {
+        if self.streams.load(Ordering::Relaxed) > 0 {
+            return None;
+        }
+        let last = Duration::from_millis(self.last.load(Ordering::Relaxed));
+        Some(self.start.elapsed().saturating_sub(last))
+    }
+
+    fn enter(&self) -> ActivityGuard<'_> {
+        self.streams.fetch_add(1, Ordering::Relaxed);
+        self.touch();
+        ActivityGuard(self)
+    }
+}
+
+struct ActivityGuard<'a>(&'a Activity);
+
+impl Drop for ActivityGuard<'_> {
+    fn drop(&mut self) {
+        self.0.touch();
+        self.0.streams.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
 pub struct Dispatcher {
+    activity: Activity,
+    metrics: Arc<OutboundMetrics>,
     outbound_manager: Arc<RwLock<OutboundManager>>,
     router: Arc<RwLock<Router>>,
     dns_client: SyncDnsClient,
+    // BLOCK_QUIC, a field so that it can be tested.
+    block_quic: bool,
     #[cfg(feature = "stat")]
     stat_manager: SyncStatManager,
 }
@@ -70,23 +141,35 @@ impl Dispatcher {
         #[cfg(feature = "stat")] stat_manager: SyncStatManager,
     ) -> Self {
         Dispatcher {
+            activity: Activity::new(),
+            metrics: Arc::new(OutboundMetrics::new()),
             outbound_manager,
             router,
             dns_client,
+            block_quic: *option::BLOCK_QUIC,
             #[cfg(feature = "stat")]
             stat_manager,
         }
     }
 
+    pub fn activity(&self) -> &Activity {
+        &self.activity
+    }
+
+    pub fn metrics(&self) -> Arc<OutboundMetrics> {
+        self.metrics.clone()
+    }
+
     pub async fn dispatch_stream<T>(&self, mut sess: Session, lhs: T)
     where
         T: 'static + AsyncRead + AsyncWrite + Unpin + Send + Sync,
     {
+        let _activity = self.activity.enter();
         log::debug!("dispatching {}:{}", &sess.network, &sess.destination);
-        let mut lhs: Box<dyn ProxyStream> = if *option::DOMAIN_SNIFFING
-            && !sess.destination.is_domain()
-            && sess.destination.port() == 443
-        {
+        let sniff_domain = *option::DOMAIN_SNIFFING && !sess.destination.is_domain();
+        let sniff_sni = self.router.read().await.sni_rules();
+        let sniff = (sniff_domain || sniff_sni) && sess.destination.port() == 443;
+        let mut lhs: Box<dyn ProxyStream> = if sniff {
             let mut lhs = sniff::SniffingStream::new(lhs);
             match lhs.sniff().await {
                 Ok(res) => {
@@ -95,18 +178,22 @@ impl Dispatcher {
                             "sniffed domain {} for tcp link {} <-> {}",
                             &domain, &sess.source, &sess.destination,
                         );
-                        sess.destination =
-                            match SocksAddr::try_from((&domain, sess.destination.port())) {
-                                Ok(a) => a,
-                                Err(e) => {
-                                    warn!(
-                                        "convert sniffed domain {} to destination failed: {}",
-                                        &domain, e,
-                                    );
-                                    return;
-                                }
-                            };
+                        if sniff_domain {
+                            sess.destination =
+                                match SocksAddr::try_from((&domain, sess.destination.port())) {
+                                    Ok(a) => a,
+                                    Err(e) => {
+                                        warn!(
+                                            "convert sniffed domain {} to destination failed: {}",
+                                            &domain, e,
+                                        );
+                                        return;
+                                    }
+                                };
+                        }
+                        sess.sni = Some(domain);
                     }
+                    sess.alpn = lhs.alpn().to_vec();
                 }
                 Err(e) => {
                     debug!(
@@ -121,32 +208,19 @@ impl Dispatcher {
             Box::new(lhs)
         };
 
-        let outbound = {
-            let router = self.router.read().await;
-            match router.pick_route(&sess).await {
-                Ok(tag) => {
-                    debug!(
-                        "picked route [{}] for {} -> {}",
-                        tag, &sess.source, &sess.destination
-                    );
-                    tag.to_owned()
-                }
-                Err(err) => {
-                    trace!("pick route failed: {}", err);
-                    if let Some(tag) = self.outbound_manager.read().await.default_handler() {
-                        debug!(
-                            "picked default route [{}] for {} -> {}",
-                            tag, &sess.source, &sess.destination
-                        );
-                        tag
-                    } else {
-                        warn!("can not find any handlers");
-                        return;
-                    }
-                }
-            }
+        let outbound = match self.route(&mut sess).await {
+            Ok(tag) => tag,
+            Err(_) => return,
         };
+        self.dispatch_stream_to(sess, lhs, outbound).await
+    }
 
+    /// Dispatches the stream to the outbound, bypassing the router.
+    pub async fn dispatch_stream_to<T>(&self, mut sess: Session, mut lhs: T, outbound: String)
+    where
+        T: 'static + AsyncRead + AsyncWrite + Unpin + Send + Sync,
+    {
+        let _activity = self.activity.enter();
         sess.outbound_tag = outbound.clone();
 
         let h = if let Some(h) = self.outbound_manager.read().await.get(&outbound) {
@@ -156,7 +230,8 @@ impl Dispatcher {
             warn!("handler not found");
             return;
         };
-        log::debug!(
+        sess_debug!(
+            sess,
             "handling {}:{} with {}",
             &sess.network,
             &sess.destination,
@@ -168,13 +243,16 @@ impl Dispatcher {
             match crate::proxy::connect_stream_outbound(&sess, self.dns_client.clone(), &h).await {
                 Ok(s) => s,
                 Err(e) => {
-                    debug!(
+                    sess_debug!(
+                        sess,
                         "dispatch tcp {} -> {} to [{}] failed: {}",
                         &sess.source,
                         &sess.destination,
                         &h.tag(),
                         e
                     );
+                    self.metrics
+                        .record_failure(h.tag(), Failure::from_error(Failure::Connect, &e));
                     log_request(&sess, h.tag(), h.color(), None);
                     return;
                 }
@@ -196,6 +274,8 @@ impl Dispatcher {
             Ok(mut rhs) => {
                 let elapsed = tokio::time::Instant::now().duration_since(handshake_start);
 
+                self.metrics.record_success(h.tag());
+                let _active = self.metrics.track_active(h.tag());
                 log_request(&sess, h.tag(), h.color(), Some(elapsed.as_millis()));
 
                 #[cfg(feature = "stat")]
@@ -207,17 +287,10 @@ impl Dispatcher {
                         .stat_stream(rhs, sess.clone());
                 }
 
-                match common::io::copy_buf_bidirectional_with_timeout(
-                    &mut lhs,
-                    &mut rhs,
-                    *option::LINK_BUFFER_SIZE * 1024,
-                    Duration::from_secs(*option::TCP_UPLINK_TIMEOUT),
-                    Duration::from_secs(*option::TCP_DOWNLINK_TIMEOUT),
-                )
-                .await
-                {
+                match common::io::relay(&mut lhs, &mut rhs).await {
                     Ok((up_count, down_count)) => {
-                        debug!(
+                        sess_debug!(
+                            sess,
                             "tcp link {} <-> {} done, ({}, {}) bytes transfered [{}]",
                             &sess.source,
                             &sess.destination,
@@ -227,7 +300,8 @@ impl Dispatcher {
                         );
                     }
                     Err(e) => {
-                        debug!(
+                        sess_debug!(
+                            sess,
                             "tcp link {} <-> {} error: {} [{}]",
                             &sess.source,
                             &sess.destination,
@@ -238,50 +312,189 @@ impl Dispatcher {
                 }
             }
             Err(e) => {
-                debug!(
+                sess_debug!(
+                    sess,
                     "dispatch tcp {} -> {} to [{}] failed: {}",
                     &sess.source,
                     &sess.destination,
                     &h.tag(),
                     e
                 );
+                self.metrics
+                    .record_failure(h.tag(), Failure::from_error(Failure::Handshake, &e));
                 log_request(&sess, h.tag(), h.color(), None);
             }
         }
     }
 
-    #[async_recursion]
-    pub async fn dispatch_datagram(
-        &self,
-        mut sess: Session,
-    ) -> io::Result<Box<dyn OutboundDatagram>> {
-        log::debug!("dispatching {}:{}", &sess.network, &sess.destination);
-        let outbound = {
-            let router = self.router.read().await;
-            match router.pick_route(&sess).await {
-                Ok(tag) => {
+    /// Returns the tag of the outbound the session should go through, the
+    /// destination of the session is replaced if the matched rule redirects
+    /// or forces where the domain is resolved.
+    ///
+    /// Sessions failing to be routed are dropped if the router fails closed,
+    /// handled as unmatched otherwise. QUIC is dropped before routing if
+    /// BLOCK_QUIC is set.
+    pub async fn route(&self, sess: &mut Session) -> io::Result<String> {
+        if self.block_quic && is_quic(sess) {
+            debug!(
+                "{} -> {} dropped as quic is blocked",
+                &sess.source, &sess.destination
+            );
+            return Err(io::Error::new(ErrorKind::Other, "quic blocked"));
+        }
+        let router = self.router.read().await;
+        let route = match router.pick_route(sess).await {
+            Ok(route) => route,
+            Err(e) if router.fail_closed() => {
+                warn!(
+                    "routing {} -> {} failed, dropped: {}",
+                    &sess.source, &sess.destination, e
+                );
+                return Err(io::Error::new(ErrorKind::Other, e.to_string()));
+            }
+            Err(e) => {
+                debug!(
+                    "routing {} -> {} failed, handled as unmatched: {}",
+                    &sess.source, &sess.destination, e
+                );
+                None
+            }
+        };
+        match route {
+            Some(route) => {
+                let (tag, redirect) = (route.target.to_owned(), route.redirect.cloned());
+                let resolve = route.resolve;
+                sess.trace = route.log;
+                sess_debug!(
+                    sess,
+                    "picked route [{}] for {} -> {}",
+                    tag,
+                    &sess.source,
+                    &sess.destination
+                );
+                trace!(
+                    "{} -> {} matched {} -> [{}]",
+                    &sess.source,
+                    &sess.destination,
+                    route.reason,
+                    tag
+                );
+                if let Some(addr) = redirect {
+                    sess_debug!(
+                        sess,
+                        "redirected {} -> {} to {}",
+                        &sess.source,
+                        &sess.destination,
+                        &addr
+                    );
+                    sess.destination = addr;
+                }
+                if let Err(e) = self.apply_resolve(sess, resolve).await {
+                    warn!("{} -> {} dropped: {}", &sess.source, &sess.destination, e);
+                    return Err(e);
+                }
+                Ok(tag)
+            }
+            None => {
+                trace!(
+                    "{} -> {} matched no rules, handled as {:?}",
+                    &sess.source,
+                    &sess.destination,
+                    router.unmatched()
+                );
+                let tag = match router.unmatched() {
+                    Unmatched::DEFAULT => self.outbound_manager.read().await.default_handler(),
+                    Unmatched::DIRECT => self.outbound_manager.read().await.direct_handler(),
+                    Unmatched::REJECT => {
+                        debug!(
+                            "rejected unmatched {} -> {}",
+                            &sess.source, &sess.destination
+                        );
+                        return Err(io::Error::new(ErrorKind::Other, "no matching rules"));
+                    }
+                    Unmatched::ERROR => {
+                        warn!(
+                            "no rules match {} -> {}, dropped",
+                            &sess.source, &sess.destination
+                        );
+                        return Err(io::Error::new(ErrorKind::Other, "no matching rules"));
+                    }
+                };
+                if let Some(tag) = tag {
                     debug!(
-                        "picked route [{}] for {} -> {}",
+                        "picked default route [{}] for {} -> {}",
                         tag, &sess.source, &sess.destination
                     );
-                    tag.to_owned()
+                    Ok(tag)
+                } else {
+                    warn!("no handler found");
+                    Err(io::Error::new(ErrorKind::Other, "no available handler"))
                 }
-                Err(err) => {
-                    trace!("pick route failed: {}", err);
-                    if let Some(tag) = self.outbound_manager.read().await.default_handler() {
-                        debug!(
-                            "picked default route [{}] for {} -> {}",
-                            tag, &sess.source, &sess.destination
+            }
+        }
+    }
+
+    // Resolves the domain destination here or restores it from the sniffed
+    // SNI for the outbound to resolve, as the matched rule asks.
+    async fn apply_resolve(&self, sess: &mut Session, resolve: Resolve) -> io::Result<()> {
+        match resolve {
+            Resolve::Local => {
+                if let SocksAddr::Domain(domain, port) = &sess.destination {
+                    let ips = self
+                        .dns_client
+                        .read()
+                        .await
+                        .lookup(domain)
+                        .await
+                        .map_err(|e| {
+                            io::Error::new(
+                                ErrorKind::Other,
+                                format!("lookup {} failed: {}", domain, e),
+                            )
+                        })?;
+                    let ip = ips.first().ok_or_else(|| {
+                        io::Error::new(ErrorKind::Other, format!("no address for {}", domain))
+                    })?;
+                    sess_debug!(sess, "resolved {} to {} locally", domain, ip);
+                    sess.destination = SocksAddr::from((*ip, *port));
+                }
+            }
+            Resolve::Remote => {
+                if let (Some(sni), false) = (&sess.sni, sess.destination.is_domain()) {
+                    if let Ok(addr) = SocksAddr::try_from((sni, sess.destination.port())) {
+                        sess_debug!(
+                            sess,
+                            "restored {} to {} for remote resolving",
+                            &sess.destination,
+                            sni
                         );
-                        tag
-                    } else {
-                        warn!("no handler found");
-                        return Err(io::Error::new(ErrorKind::Other, "no available handler"));
+                        sess.destination = addr;
                     }
                 }
             }
-        };
+            Resolve::Default => (),
+        }
+        Ok(())
+    }
 
+    #[async_recursion]
+    pub async fn dispatch_datagram(
+        &self,
+        mut sess: Session,
+    ) -> io::Result<Box<dyn OutboundDatagram>> {
+        log::debug!("dispatching {}:{}", &sess.network, &sess.destination);
+        let outbound = self.route(&mut sess).await?;
+        self.dispatch_datagram_to(sess, outbound).await
+    }
+
+    /// Dispatches the session to the outbound, which has already been picked
+    /// by `route`.
+    #[async_recursion]
+    pub async fn dispatch_datagram_to(
+        &self,
+        mut sess: Session,
+        outbound: String,
+    ) -> io::Result<Box<dyn OutboundDatagram>> {
         sess.outbound_tag = outbound.clone();
 
         let h = if let Some(h) = self.outbound_manager.read().await.get(&outbound) {
@@ -293,8 +506,17 @@ impl Dispatcher {
 
         let handshake_start = tokio::time::Instant::now();
         let transport =
-            crate::proxy::connect_datagram_outbound(&sess, self.dns_client.clone(), &h).await?;
-        log::debug!(
+            match crate::proxy::connect_datagram_outbound(&sess, self.dns_client.clone(), &h).await
+            {
+                Ok(t) => t,
+                Err(e) => {
+                    self.metrics
+                        .record_failure(h.tag(), Failure::from_error(Failure::Connect, &e));
+                    return Err(e);
+                }
+            };
+        sess_debug!(
+            sess,
             "handling {}:{} with {}",
             &sess.network,
             &sess.destination,
@@ -305,6 +527,7 @@ impl Dispatcher {
             Ok(mut d) => {
                 let elapsed = tokio::time::Instant::now().duration_since(handshake_start);
 
+                self.metrics.record_success(h.tag());
                 log_request(&sess, h.tag(), h.color(), Some(elapsed.as_millis()));
 
                 #[cfg(feature = "stat")]
@@ -319,16 +542,120 @@ impl Dispatcher {
                 Ok(d)
             }
             Err(e) => {
-                debug!(
+                sess_debug!(
+                    sess,
                     "dispatch udp {} -> {} to [{}] failed: {}",
                     &sess.source,
                     &sess.destination,
                     &h.tag(),
                     e
                 );
+                self.metrics
+                    .record_failure(h.tag(), Failure::from_error(Failure::Handshake, &e));
                 log_request(&sess, h.tag(), h.color(), None);
                 Err(e)
             }
         }
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::config::{self, Outbound};
+
+    // Routes 10.0.0.0/8 to "direct", anything else to "proxy" unless failing
+    // closed. Both connect directly.
+    pub(crate) fn dispatcher(fail_closed: bool) -> Dispatcher {
+        let mut dns = config::Dns::new();
+        dns.servers.push("127.0.0.1".to_string());
+        let dns_client = Arc::new(RwLock::new(
+            crate::app::dns_client::DnsClient::new(&protobuf::MessageField::some(dns)).unwrap(),
+        ));
+
+        let outbounds: Vec<Outbound> = ["proxy", "direct"]
+            .iter()
+            .map(|tag| {
+                let mut outbound = Outbound::new();
+                outbound.tag = tag.to_string();
+                outbound.protocol = "direct".to_string();
+                outbound
+            })
+            .collect();
+        let outbound_manager = OutboundManager::new(&outbounds, dns_client.clone()).unwrap();
+
+        // Routing domains needs to resolve them for the IP rule.
+        let mut rule = config::router::Rule::new();
+        rule.target_tag = "direct".to_string();
+        rule.ip_cidrs.push("10.0.0.0/8".to_string());
+        let mut router = config::Router::new();
+        router.rules.push(rule);
+        router.domain_resolve = true;
+        router.fail_closed = fail_closed;
+        let router = Router::new(
+            &mut protobuf::MessageField::some(router),
+            dns_client.clone(),
+        )
+        .unwrap();
+
+        Dispatcher::new(
+            Arc::new(RwLock::new(outbound_manager)),
+            Arc::new(RwLock::new(router)),
+            dns_client,
+            #[cfg(feature = "stat")]
+            Arc::new(RwLock::new(crate::app::stat_manager::StatManager::new())),
+        )
+    }
+
+    #[test]
+    fn test_route_failure() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            // The lookup fails on the label too long.
+            let domain = format!("{}.com", "a".repeat(64));
+            let sess = Session {
+                destination: SocksAddr::Domain(domain, 443),
+                ..Default::default()
+            };
+
+            // Fails open to the first outbound.
+            let d = dispatcher(false);
+            assert_eq!(d.route(&mut sess.clone()).await.unwrap(), "proxy");
+            // Fails closed.
+            let d = dispatcher(true);
+            assert!(d.route(&mut sess.clone()).await.is_err());
+            // Routes as usual otherwise.
+            let mut sess = Session {
+                destination: SocksAddr::Ip("10.0.0.1:443".parse().unwrap()),
+                ..Default::default()
+            };
+            assert_eq!(d.route(&mut sess).await.unwrap(), "direct");
+        });
+    }
+
+    #[test]
+    fn test_block_quic() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let sess = |network, port| Session {
+                network,
+                destination: SocksAddr::Ip(format!("1.2.3.4:{}", port).parse().unwrap()),
+                ..Default::default()
+            };
+            let mut d = dispatcher(false);
+            d.block_quic = false;
+            assert!(d.route(&mut sess(Network::Udp, 443)).await.is_ok());
+            d.block_quic = true;
+            assert!(d.route(&mut sess(Network::Udp, 443)).await.is_err());
+            // Only UDP to 443.
+            assert!(d.route(&mut sess(Network::Tcp, 443)).await.is_ok());
+            assert!(d.route(&mut sess(Network::Udp, 53)).await.is_ok());
+        });
+    }
+}
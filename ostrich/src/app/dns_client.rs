@@ -9,31 +9,146 @@ use anyhow::{anyhow, Result};
 use futures::future::select_ok;
 use log::*;
 use lru::LruCache;
+use parking_lot::Mutex;
 use rand::{rngs::StdRng, Rng, SeedableRng};
-use tokio::sync::Mutex as TokioMutex;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::{watch, Mutex as TokioMutex};
 use tokio::time::timeout;
 use trust_dns_proto::{
     op::{
-        header::MessageType, op_code::OpCode, query::Query, response_code::ResponseCode, Message,
+        header::MessageType, op_code::OpCode, query::Query, response_code::ResponseCode, Edns,
+        Message,
     },
     rr::{record_data::RData, record_type::RecordType, Name},
 };
 
+#[cfg(feature = "dns-over-https")]
+use super::doh::DohClient;
 use crate::{app::dispatcher::Dispatcher, option, proxy::*, session::*};
 
+// How long to cache the results of the system resolver.
+const SYSTEM_RESOLVER_TTL: u64 = 60;
+
+/// Where the addresses of a lookup came from.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Source {
+    Hosts,
+    System,
+    Server(SocketAddr),
+    Doh(String),
+}
+
+impl std::fmt::Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Source::Hosts => write!(f, "hosts"),
+            Source::System => write!(f, "system"),
+            Source::Server(addr) => write!(f, "{}", addr),
+            Source::Doh(url) => write!(f, "{}", url),
+        }
+    }
+}
+
+/// The answer of a one-off lookup, see `DnsClient::resolve`.
+#[derive(Debug)]
+pub struct Resolution {
+    pub ips: Vec<IpAddr>,
+    pub source: Source,
+    // Whether the answer was served from the cache.
+    pub cached: bool,
+    // Seconds the answer stays in the cache, 0 if it isn't cached.
+    pub ttl: u64,
+}
+
+/// Counts the queries sent out and not answered yet, so that shutdown can
+/// give them a moment to finish.
+#[derive(Clone)]
+pub struct PendingQueries(Arc<watch::Sender<usize>>);
+
+impl PendingQueries {
+    fn new() -> Self {
+        Self(Arc::new(watch::channel(0).0))
+    }
+
+    fn track(&self) -> PendingGuard {
+        self.0.send_modify(|n| *n += 1);
+        PendingGuard(self.0.clone())
+    }
+
+    pub fn count(&self) -> usize {
+        *self.0.borrow()
+    }
+
+    /// Waits until no query is pending, returns false on timeout.
+    pub async fn wait(&self, timeout: Duration) -> bool {
+        let mut rx = self.0.subscribe();
+        tokio::time::timeout(timeout, rx.wait_for(|n| *n == 0))
+            .await
+            .map_or(false, |r| r.is_ok())
+    }
+}
+
+struct PendingGuard(Arc<watch::Sender<usize>>);
+
+impl Drop for PendingGuard {
+    fn drop(&mut self) {
+        self.0.send_modify(|n| *n -= 1);
+    }
+}
+
+// Bounds of the TTL for a domain and its subdomains, no upper bound if `max`
+// is 0.
+struct TtlRule {
+    domain: String,
+    min: u32,
+    max: u32,
+}
+
+impl TtlRule {
+    fn matches(&self, host: &str) -> bool {
+        host.strip_suffix(self.domain.as_str())
+            .map_or(false, |p| p.is_empty() || p.ends_with('.'))
+    }
+
+    fn clamp(&self, ttl: u32) -> u32 {
+        let ttl = std::cmp::max(ttl, self.min);
+        if self.max > 0 {
+            std::cmp::min(ttl, self.max)
+        } else {
+            ttl
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 struct CacheEntry {
     pub ips: Vec<IpAddr>,
     // The deadline this entry should be considered expired.
     pub deadline: Instant,
+    pub source: Source,
 }
 
 pub struct DnsClient {
     dispatcher: Option<Weak<Dispatcher>>,
     servers: Vec<SocketAddr>,
+    // Weights of the servers in the same order, empty to race all servers.
+    weights: Vec<u32>,
+    // Current weights of the smooth weighted round-robin.
+    current_weights: Mutex<Vec<i64>>,
     hosts: IndexMap<String, Vec<IpAddr>>,
+    ttl_rules: Vec<TtlRule>,
     ipv4_cache: Arc<TokioMutex<LruCache<String, CacheEntry>>>,
     ipv6_cache: Arc<TokioMutex<LruCache<String, CacheEntry>>>,
+    // Whether to resolve through the system for bootstrap and as a fallback.
+    system_resolver: bool,
+    // The outbound to send non-bootstrap queries through instead of routing
+    // them.
+    outbound: Option<String>,
+    // Queries go to the DoH server first if any, the plain servers are the
+    // fallback.
+    #[cfg(feature = "dns-over-https")]
+    doh: Option<DohClient>,
+    pending: PendingQueries,
 }
 
 impl DnsClient {
@@ -48,6 +163,16 @@ impl DnsClient {
         Ok(servers)
     }
 
+    fn load_weights(dns: &crate::config::Dns) -> Vec<u32> {
+        if dns.weights.is_empty() {
+            return Vec::new();
+        }
+        dns.servers
+            .iter()
+            .map(|s| dns.weights.get(s).copied().unwrap_or(1))
+            .collect()
+    }
+
     fn load_hosts(dns: &crate::config::Dns) -> IndexMap<String, Vec<IpAddr>> {
         let mut hosts = IndexMap::new();
         for (name, ips) in dns.hosts.iter() {
@@ -66,6 +191,46 @@ impl DnsClient {
         parsed_hosts
     }
 
+    fn load_ttl_rules(dns: &crate::config::Dns) -> Result<Vec<TtlRule>> {
+        let mut rules = Vec::new();
+        for r in dns.ttl_rules.iter() {
+            if r.max > 0 && r.min > r.max {
+                return Err(anyhow!(
+                    "invalid ttl bounds {}-{} for {}",
+                    r.min,
+                    r.max,
+                    &r.domain
+                ));
+            }
+            rules.push(TtlRule {
+                domain: r.domain.trim_start_matches('.').to_lowercase(),
+                min: r.min,
+                max: r.max,
+            });
+        }
+        Ok(rules)
+    }
+
+    fn load_outbound(dns: &crate::config::Dns) -> Option<String> {
+        Some(dns.outbound.clone()).filter(|tag| !tag.is_empty())
+    }
+
+    #[cfg(feature = "dns-over-https")]
+    fn load_doh(dns: &crate::config::Dns) -> Result<Option<DohClient>> {
+        if dns.doh_server.is_empty() {
+            return Ok(None);
+        }
+        DohClient::new(&dns.doh_server).map(Some)
+    }
+
+    #[cfg(not(feature = "dns-over-https"))]
+    fn check_doh(dns: &crate::config::Dns) -> Result<()> {
+        if !dns.doh_server.is_empty() {
+            return Err(anyhow!("dns-over-https is not supported in this build"));
+        }
+        Ok(())
+    }
+
     pub fn new(dns: &protobuf::MessageField<crate::config::Dns>) -> Result<Self> {
         let dns = if let Some(dns) = dns.as_ref() {
             dns
@@ -73,7 +238,13 @@ impl DnsClient {
             return Err(anyhow!("empty dns config"));
         };
         let servers = Self::load_servers(dns)?;
+        let weights = Self::load_weights(dns);
         let hosts = Self::load_hosts(dns);
+        let ttl_rules = Self::load_ttl_rules(dns)?;
+        #[cfg(feature = "dns-over-https")]
+        let doh = Self::load_doh(dns)?;
+        #[cfg(not(feature = "dns-over-https"))]
+        Self::check_doh(dns)?;
         let ipv4_cache = Arc::new(TokioMutex::new(LruCache::<String, CacheEntry>::new(
             NonZeroUsize::new(*option::DNS_CACHE_SIZE).unwrap(),
         )));
@@ -84,12 +255,26 @@ impl DnsClient {
         Ok(Self {
             dispatcher: None,
             servers,
+            current_weights: Mutex::new(vec![0; weights.len()]),
+            weights,
             hosts,
+            ttl_rules,
             ipv4_cache,
             ipv6_cache,
+            system_resolver: dns.system_resolver,
+            outbound: Self::load_outbound(dns),
+            #[cfg(feature = "dns-over-https")]
+            doh,
+            pending: PendingQueries::new(),
         })
     }
 
+    /// Returns the counter of the queries in flight, shared by all clones
+    /// and kept across reloads.
+    pub fn pending_queries(&self) -> PendingQueries {
+        self.pending.clone()
+    }
+
     pub fn replace_dispatcher(&mut self, dispatcher: Weak<Dispatcher>) {
         self.dispatcher.replace(dispatcher);
     }
@@ -101,9 +286,24 @@ impl DnsClient {
             return Err(anyhow!("empty dns config"));
         };
         let servers = Self::load_servers(dns)?;
+        let weights = Self::load_weights(dns);
         let hosts = Self::load_hosts(dns);
+        let ttl_rules = Self::load_ttl_rules(dns)?;
+        #[cfg(feature = "dns-over-https")]
+        let doh = Self::load_doh(dns)?;
+        #[cfg(not(feature = "dns-over-https"))]
+        Self::check_doh(dns)?;
         self.servers = servers;
+        self.current_weights = Mutex::new(vec![0; weights.len()]);
+        self.weights = weights;
         self.hosts = hosts;
+        self.ttl_rules = ttl_rules;
+        self.system_resolver = dns.system_resolver;
+        self.outbound = Self::load_outbound(dns);
+        #[cfg(feature = "dns-over-https")]
+        {
+            self.doh = doh;
+        }
         Ok(())
     }
 
@@ -161,6 +361,24 @@ impl DnsClient {
         }
     }
 
+    /// Drops all cached entries, returns the number of entries removed.
+    pub async fn flush_cache(&self) -> usize {
+        let mut ipv4_cache = self.ipv4_cache.lock().await;
+        let mut ipv6_cache = self.ipv6_cache.lock().await;
+        let n = ipv4_cache.len() + ipv6_cache.len();
+        ipv4_cache.clear();
+        ipv6_cache.clear();
+        n
+    }
+
+    /// Drops the connection to the DoH server, if any.
+    pub async fn reset_conns(&self) {
+        #[cfg(feature = "dns-over-https")]
+        if let Some(doh) = self.doh.as_ref() {
+            doh.reset().await;
+        }
+    }
+
     /// Updates the cache according to the IP address successfully connected.
     pub async fn optimize_cache(&self, address: String, connected_ip: IpAddr) {
         match connected_ip {
@@ -169,6 +387,158 @@ impl DnsClient {
         }
     }
 
+    // Opens a stream to the server, directly or through the dispatcher.
+    async fn connect_stream(&self, is_direct: bool, server: &SocketAddr) -> Result<AnyStream> {
+        if is_direct {
+            Ok(dial_tcp_stream(server).await?)
+        } else {
+            let dispatcher = self
+                .dispatcher
+                .as_ref()
+                .and_then(|d| d.upgrade())
+                .ok_or_else(|| anyhow!("dispatcher is unavailable"))?;
+            let sess = Session {
+                network: Network::Tcp,
+                destination: SocksAddr::from(server),
+                ..Default::default()
+            };
+            // The dispatcher relays an inbound stream, hand it one end of a
+            // pipe and talk through the other.
+            let (lhs, rhs) = tokio::io::duplex(u16::MAX as usize + 2);
+            let outbound = self.outbound.clone();
+            tokio::spawn(async move {
+                match outbound {
+                    Some(tag) => dispatcher.dispatch_stream_to(sess, rhs, tag).await,
+                    None => dispatcher.dispatch_stream(sess, rhs).await,
+                }
+            });
+            Ok(Box::new(lhs))
+        }
+    }
+
+    // Queries the server over TCP, used when the UDP answer is truncated.
+    async fn tcp_query(
+        &self,
+        is_direct: bool,
+        request: &[u8],
+        server: &SocketAddr,
+    ) -> Result<Message> {
+        let mut stream = self.connect_stream(is_direct, server).await?;
+        timeout(Duration::from_secs(*option::DNS_TIMEOUT), async move {
+            // Messages over TCP are prefixed with a 2-byte length.
+            stream.write_u16(request.len() as u16).await?;
+            stream.write_all(request).await?;
+            stream.flush().await?;
+            let n = stream.read_u16().await? as usize;
+            let mut buf = vec![0u8; n];
+            stream.read_exact(&mut buf).await?;
+            Message::from_vec(&buf).map_err(|e| anyhow!("parse message failed: {:?}", e))
+        })
+        .await?
+    }
+
+    // Applies the first TTL rule matching the host.
+    fn clamp_ttl(&self, host: &str, ttl: u32) -> u32 {
+        let host = host.trim_end_matches('.').to_lowercase();
+        self.ttl_rules
+            .iter()
+            .find(|r| r.matches(&host))
+            .map_or(ttl, |r| r.clamp(ttl))
+    }
+
+    // Takes the addresses and the TTL of an answer.
+    fn entry_from_response(
+        &self,
+        host: &str,
+        resp: &Message,
+        source: Source,
+        start: tokio::time::Instant,
+    ) -> Result<CacheEntry> {
+        if resp.response_code() != ResponseCode::NoError {
+            // TODO Needs more careful investigations, I'm not quite sure about
+            // this.
+            return Err(anyhow!("response error {}", resp.response_code()));
+        }
+        let mut ips = Vec::new();
+        for ans in resp.answers() {
+            // TODO checks?
+            match ans.rdata() {
+                RData::A(ip) => {
+                    ips.push(IpAddr::V4(ip.to_owned()));
+                }
+                RData::AAAA(ip) => {
+                    ips.push(IpAddr::V6(ip.to_owned()));
+                }
+                _ => (),
+            }
+        }
+        if ips.is_empty() {
+            // response with 0 records
+            //
+            // TODO Not sure how to due with this.
+            return Err(anyhow!("no records"));
+        }
+        let elapsed = tokio::time::Instant::now().duration_since(start);
+        let ttl = self.clamp_ttl(host, resp.answers().iter().next().unwrap().ttl());
+        debug!(
+            "return {} ips (ttl {}) for {} from {} in {}ms",
+            ips.len(),
+            ttl,
+            host,
+            source,
+            elapsed.as_millis(),
+        );
+        let deadline = Instant::now()
+            .checked_add(Duration::from_secs(ttl.into()))
+            .ok_or_else(|| anyhow!("invalid ttl"))?;
+        let entry = CacheEntry {
+            ips,
+            deadline,
+            source,
+        };
+        trace!("ips for {}:\n{:#?}", host, &entry);
+        Ok(entry)
+    }
+
+    // Resolves the DoH server by the static hosts or the system, querying
+    // the DNS servers for it could need the DoH server itself.
+    #[cfg(feature = "dns-over-https")]
+    async fn doh_server_addr(&self, doh: &DohClient) -> Result<SocketAddr> {
+        if let Ok(ip) = doh.host().parse::<IpAddr>() {
+            return Ok(SocketAddr::new(ip, doh.port()));
+        }
+        if let Some(ip) = self.hosts.get(doh.host()).and_then(|ips| ips.first()) {
+            return Ok(SocketAddr::new(*ip, doh.port()));
+        }
+        tokio::net::lookup_host((doh.host(), doh.port()))
+            .await?
+            .next()
+            .ok_or_else(|| anyhow!("could not resolve doh server {}", doh.host()))
+    }
+
+    #[cfg(feature = "dns-over-https")]
+    async fn doh_query(
+        &self,
+        doh: &DohClient,
+        is_direct: bool,
+        request: &[u8],
+        host: &str,
+    ) -> Result<CacheEntry> {
+        debug!("looking up host {} on {}", host, doh.url());
+        let start = tokio::time::Instant::now();
+        let connect = async {
+            let server = self.doh_server_addr(doh).await?;
+            self.connect_stream(is_direct, &server).await
+        };
+        let resp = timeout(
+            Duration::from_secs(*option::DNS_TIMEOUT),
+            doh.query(is_direct, request, connect),
+        )
+        .await
+        .map_err(|e| anyhow!("doh query timeout: {}", e))??;
+        self.entry_from_response(host, &resp, Source::Doh(doh.url()), start)
+    }
+
     async fn query_task(
         &self,
         is_direct: bool,
@@ -187,7 +557,10 @@ impl DnsClient {
                     ..Default::default()
                 };
                 if let Some(dispatcher) = dispatcher_weak.upgrade() {
-                    dispatcher.dispatch_datagram(sess).await?
+                    match &self.outbound {
+                        Some(tag) => dispatcher.dispatch_datagram_to(sess, tag.clone()).await?,
+                        None => dispatcher.dispatch_datagram(sess).await?,
+                    }
                 } else {
                     return Err(anyhow!("dispatcher is deallocated"));
                 }
@@ -196,6 +569,7 @@ impl DnsClient {
             }
         };
         let (mut r, mut s) = socket.split();
+        let server_addr = server;
         let server = SocksAddr::from(server);
         let mut last_err = None;
         for _i in 0..*option::MAX_DNS_RETRIES {
@@ -203,7 +577,8 @@ impl DnsClient {
             let start = tokio::time::Instant::now();
             match s.send_to(&request, &server).await {
                 Ok(_) => {
-                    let mut buf = vec![0u8; 512];
+                    let mut buf =
+                        vec![0u8; std::cmp::max(512, *option::DNS_EDNS_BUFFER_SIZE as usize)];
                     match timeout(
                         Duration::from_secs(*option::DNS_TIMEOUT),
                         r.recv_from(&mut buf),
@@ -220,56 +595,35 @@ impl DnsClient {
                                         break;
                                     }
                                 };
-                                if resp.response_code() != ResponseCode::NoError {
-                                    last_err =
-                                        Some(anyhow!("response error {}", resp.response_code()));
-                                    // error response, no retry
-                                    //
-                                    // TODO Needs more careful investigations, I'm not quite sure about
-                                    // this.
-                                    break;
-                                }
-                                let mut ips = Vec::new();
-                                for ans in resp.answers() {
-                                    // TODO checks?
-                                    match ans.rdata() {
-                                        RData::A(ip) => {
-                                            ips.push(IpAddr::V4(ip.to_owned()));
-                                        }
-                                        RData::AAAA(ip) => {
-                                            ips.push(IpAddr::V6(ip.to_owned()));
-                                        }
-                                        _ => (),
-                                    }
-                                }
-                                if !ips.is_empty() {
-                                    let elapsed = tokio::time::Instant::now().duration_since(start);
-                                    let ttl = resp.answers().iter().next().unwrap().ttl();
+                                let resp = if resp.truncated() && *option::DNS_TCP_FALLBACK {
                                     debug!(
-                                        "return {} ips (ttl {}) for {} from {} in {}ms",
-                                        ips.len(),
-                                        ttl,
-                                        host,
-                                        server,
-                                        elapsed.as_millis(),
+                                        "truncated answer for {} from {}, retry over tcp",
+                                        host, server
                                     );
-                                    let deadline = if let Some(d) =
-                                        Instant::now().checked_add(Duration::from_secs(ttl.into()))
-                                    {
-                                        d
-                                    } else {
-                                        last_err = Some(anyhow!("invalid ttl"));
-                                        break;
-                                    };
-                                    let entry = CacheEntry { ips, deadline };
-                                    trace!("ips for {}:\n{:#?}", host, &entry);
-                                    return Ok(entry);
+                                    match self.tcp_query(is_direct, &request, server_addr).await {
+                                        Ok(resp) => resp,
+                                        Err(err) => {
+                                            last_err =
+                                                Some(anyhow!("tcp fallback failed: {}", err));
+                                            // retry over udp
+                                            continue;
+                                        }
+                                    }
                                 } else {
-                                    // response with 0 records
-                                    //
-                                    // TODO Not sure how to due with this.
-                                    last_err = Some(anyhow!("no records"));
-                                    break;
+                                    resp
+                                };
+                                match self.entry_from_response(
+                                    host,
+                                    &resp,
+                                    Source::Server(*server_addr),
+                                    start,
+                                ) {
+                                    Ok(entry) => return Ok(entry),
+                                    Err(err) => {
+                                        // error response or no records, no retry
+                                        last_err = Some(err);
+                                        break;
+                                    }
                                 }
                             }
                             Err(err) => {
@@ -292,6 +646,65 @@ impl DnsClient {
         Err(last_err.unwrap_or_else(|| anyhow!("all lookup attempts failed")))
     }
 
+    // Picks the server for the next query by the weights, if any.
+    fn pick_server(&self) -> Option<usize> {
+        if self.weights.is_empty() {
+            return None;
+        }
+        smooth_weighted_pick(&mut self.current_weights.lock(), &self.weights)
+    }
+
+    // Sends the query to the server picked by the weights, falls back to
+    // racing the others if it fails. All servers are raced without weights.
+    async fn query_servers(
+        &self,
+        is_direct: bool,
+        request: Vec<u8>,
+        host: &str,
+    ) -> Result<CacheEntry> {
+        let _pending = self.pending.track();
+        #[cfg(feature = "dns-over-https")]
+        if let Some(doh) = self.doh.as_ref() {
+            match self.doh_query(doh, is_direct, &request, host).await {
+                Ok(entry) => return Ok(entry),
+                Err(e) => debug!(
+                    "lookup {} on {} failed: {}, trying the plain servers",
+                    host,
+                    doh.url(),
+                    e
+                ),
+            }
+        }
+        let picked = self.pick_server();
+        if let Some(i) = picked {
+            match self
+                .query_task(is_direct, request.clone(), host, &self.servers[i])
+                .await
+            {
+                Ok(entry) => return Ok(entry),
+                Err(e) => debug!(
+                    "lookup {} on {} failed: {}, trying the other servers",
+                    host, &self.servers[i], e
+                ),
+            }
+        }
+        let mut tasks = Vec::new();
+        for (i, server) in self.servers.iter().enumerate() {
+            if Some(i) == picked {
+                continue;
+            }
+            let t = self.query_task(is_direct, request.clone(), host, server);
+            tasks.push(Box::pin(t));
+        }
+        if tasks.is_empty() {
+            return Err(anyhow!("all dns servers failed"));
+        }
+        select_ok(tasks.into_iter())
+            .await
+            .map(|(entry, _)| entry)
+            .map_err(|e| anyhow!("all dns servers failed, last error: {}", e))
+    }
+
     fn new_query(name: Name, ty: RecordType) -> Message {
         let mut msg = Message::new();
         msg.add_query(Query::query(name, ty));
@@ -301,6 +714,11 @@ impl DnsClient {
         msg.set_op_code(OpCode::Query);
         msg.set_message_type(MessageType::Query);
         msg.set_recursion_desired(true);
+        if *option::DNS_EDNS_BUFFER_SIZE > 0 {
+            let mut edns = Edns::new();
+            edns.set_max_payload(*option::DNS_EDNS_BUFFER_SIZE);
+            msg.set_edns(edns);
+        }
         msg
     }
 
@@ -314,79 +732,94 @@ impl DnsClient {
         };
     }
 
-    async fn get_cached(&self, host: &String) -> Result<Vec<IpAddr>> {
-        let mut cached_ips = Vec::new();
+    // The cache of the records of the type, A or AAAA.
+    fn cache_of(&self, ty: RecordType) -> &TokioMutex<LruCache<String, CacheEntry>> {
+        if ty == RecordType::AAAA {
+            &self.ipv6_cache
+        } else {
+            &self.ipv4_cache
+        }
+    }
 
-        // TODO reduce boilerplates
-        match (*crate::option::ENABLE_IPV6, *crate::option::PREFER_IPV6) {
-            (true, true) => {
-                if let Some(entry) = self.ipv6_cache.lock().await.get(host) {
-                    if entry
-                        .deadline
-                        .checked_duration_since(Instant::now())
-                        .is_none()
-                    {
-                        return Err(anyhow!("entry expired"));
-                    }
-                    let mut ips = entry.ips.to_vec();
-                    cached_ips.append(&mut ips);
-                }
-                if let Some(entry) = self.ipv4_cache.lock().await.get(host) {
-                    if entry
-                        .deadline
-                        .checked_duration_since(Instant::now())
-                        .is_none()
-                    {
-                        return Err(anyhow!("entry expired"));
-                    }
-                    let mut ips = entry.ips.to_vec();
-                    cached_ips.append(&mut ips);
-                }
-            }
-            (true, false) => {
-                if let Some(entry) = self.ipv4_cache.lock().await.get(host) {
-                    if entry
-                        .deadline
-                        .checked_duration_since(Instant::now())
-                        .is_none()
-                    {
-                        return Err(anyhow!("entry expired"));
-                    }
-                    let mut ips = entry.ips.to_vec();
-                    cached_ips.append(&mut ips);
-                }
-                if let Some(entry) = self.ipv6_cache.lock().await.get(host) {
-                    if entry
-                        .deadline
-                        .checked_duration_since(Instant::now())
-                        .is_none()
-                    {
-                        return Err(anyhow!("entry expired"));
-                    }
-                    let mut ips = entry.ips.to_vec();
-                    cached_ips.append(&mut ips);
-                }
-            }
-            _ => {
-                if let Some(entry) = self.ipv4_cache.lock().await.get(host) {
-                    if entry
-                        .deadline
-                        .checked_duration_since(Instant::now())
-                        .is_none()
-                    {
-                        return Err(anyhow!("entry expired"));
-                    }
-                    let mut ips = entry.ips.to_vec();
-                    cached_ips.append(&mut ips);
-                }
+    // Answers from the cache entries of the types, none if one has expired.
+    async fn get_cached(&self, host: &str, types: &[RecordType]) -> Option<Resolution> {
+        let mut res: Option<Resolution> = None;
+        for ty in types {
+            if let Some(entry) = self.cache_of(*ty).lock().await.get(host) {
+                let left = entry.deadline.checked_duration_since(Instant::now())?;
+                let res = res.get_or_insert_with(|| Resolution {
+                    ips: Vec::new(),
+                    source: entry.source.clone(),
+                    cached: true,
+                    ttl: left.as_secs(),
+                });
+                res.ips.extend_from_slice(&entry.ips);
+                res.ttl = res.ttl.min(left.as_secs());
             }
         }
+        res
+    }
 
-        if !cached_ips.is_empty() {
-            Ok(cached_ips)
-        } else {
-            Err(anyhow!("empty result"))
+    // Resolves with getaddrinfo on the blocking pool, which sees the names
+    // only the OS knows how to resolve, e.g. split DNS of a VPN.
+    async fn system_lookup(&self, host: &str) -> Result<Vec<IpAddr>> {
+        let _pending = self.pending.track();
+        let ips: Vec<IpAddr> = tokio::net::lookup_host((host, 0))
+            .await?
+            .map(|a| a.ip())
+            .filter(|ip| ip.is_ipv4() || *option::ENABLE_IPV6)
+            .collect();
+        if ips.is_empty() {
+            return Err(anyhow!("system resolver returned no address"));
+        }
+        debug!("return {} ips for {} from system resolver", ips.len(), host);
+        // getaddrinfo doesn't tell the TTL.
+        let deadline = Instant::now() + Duration::from_secs(SYSTEM_RESOLVER_TTL);
+        let (v4, v6): (Vec<IpAddr>, Vec<IpAddr>) = ips.iter().partition(|ip| ip.is_ipv4());
+        for family in [v4, v6] {
+            self.cache_insert(
+                host,
+                CacheEntry {
+                    ips: family,
+                    deadline,
+                    source: Source::System,
+                },
+            )
+            .await;
         }
+        Ok(ips)
+    }
+
+    // The system lookup keeping the addresses of the record types.
+    async fn system_lookup_types(&self, host: &str, types: &[RecordType]) -> Result<Resolution> {
+        let ips: Vec<IpAddr> = self
+            .system_lookup(host)
+            .await?
+            .into_iter()
+            .filter(|ip| types.contains(&record_type(ip)))
+            .collect();
+        if ips.is_empty() {
+            return Err(anyhow!("system resolver returned no {:?} record", types));
+        }
+        Ok(Resolution {
+            ips,
+            source: Source::System,
+            cached: false,
+            ttl: SYSTEM_RESOLVER_TTL,
+        })
+    }
+
+    /// Looks up the A or AAAA records of the host the way sessions do,
+    /// through the cache, the static hosts and the routed servers, and tells
+    /// where the answer came from. Meant for diagnostics.
+    pub async fn resolve(&self, host: &str, ty: RecordType) -> Result<Resolution> {
+        if ty != RecordType::A && ty != RecordType::AAAA {
+            return Err(anyhow!("unsupported record type {}", ty));
+        }
+        if host.parse::<IpAddr>().is_ok() {
+            return Err(anyhow!("{} is not a domain name", host));
+        }
+        self.lookup_types(host, false, &[ty]).await
     }
 
     pub async fn lookup(&self, host: &String) -> Result<Vec<IpAddr>> {
@@ -397,135 +830,123 @@ impl DnsClient {
         self._lookup(host, true).await
     }
 
+    /// Resolves the destination a direct outbound connects to, the query goes
+    /// through the DNS outbound if there is one so it doesn't leak, otherwise
+    /// it's sent directly.
+    pub async fn destination_lookup(&self, host: &String) -> Result<Vec<IpAddr>> {
+        self._lookup(host, self.outbound.is_none()).await
+    }
+
     pub async fn _lookup(&self, host: &String, is_direct: bool) -> Result<Vec<IpAddr>> {
         if let Ok(ip) = host.parse::<IpAddr>() {
             return Ok(vec![ip]);
         }
+        let types: &[RecordType] = match (*option::ENABLE_IPV6, *option::PREFER_IPV6) {
+            (true, true) => &[RecordType::AAAA, RecordType::A],
+            (true, false) => &[RecordType::A, RecordType::AAAA],
+            _ => &[RecordType::A],
+        };
+        self.lookup_types(host, is_direct, types)
+            .await
+            .map(|res| res.ips)
+    }
 
-        if let Ok(ips) = self.get_cached(host).await {
-            return Ok(ips);
+    // Looks up the records of the types, in order, through the cache, the
+    // static hosts, the servers and the system resolver.
+    async fn lookup_types(
+        &self,
+        host: &str,
+        is_direct: bool,
+        types: &[RecordType],
+    ) -> Result<Resolution> {
+        if let Some(res) = self.get_cached(host, types).await {
+            return Ok(res);
         }
 
         // Making cache lookup a priority rather than static hosts lookup
         // and insert the static IPs to the cache because there's a chance
         // for the IPs in the cache to be re-ordered.
-        if !self.hosts.is_empty() {
-            if let Some(ips) = self.hosts.get(host) {
-                if !ips.is_empty() {
-                    if ips.len() > 1 {
-                        let deadline = Instant::now()
-                            .checked_add(Duration::from_secs(6000))
-                            .unwrap();
-                        self.cache_insert(
-                            host,
-                            CacheEntry {
-                                ips: ips.clone(),
-                                deadline,
-                            },
-                        )
-                        .await;
-                    }
-                    return Ok(ips.to_vec());
+        if let Some(ips) = self.hosts.get(host) {
+            let ips: Vec<IpAddr> = ips
+                .iter()
+                .copied()
+                .filter(|ip| types.contains(&record_type(ip)))
+                .collect();
+            if !ips.is_empty() {
+                if ips.len() > 1 {
+                    let deadline = Instant::now()
+                        .checked_add(Duration::from_secs(6000))
+                        .unwrap();
+                    self.cache_insert(
+                        host,
+                        CacheEntry {
+                            ips: ips.clone(),
+                            deadline,
+                            source: Source::Hosts,
+                        },
+                    )
+                    .await;
                 }
+                return Ok(Resolution {
+                    ips,
+                    source: Source::Hosts,
+                    cached: false,
+                    ttl: 0,
+                });
             }
         }
 
-        let mut fqdn = host.to_owned();
-        fqdn.push('.');
-        let name = match Name::from_str(&fqdn) {
-            Ok(n) => n,
-            Err(e) => return Err(anyhow!("invalid domain name [{}]: {}", host, e)),
-        };
-
-        let mut query_tasks = Vec::new();
-
-        // TODO reduce boilerplates
-        match (*crate::option::ENABLE_IPV6, *crate::option::PREFER_IPV6) {
-            (true, true) => {
-                let msg = Self::new_query(name.clone(), RecordType::AAAA);
-                let msg_buf = match msg.to_vec() {
-                    Ok(b) => b,
-                    Err(e) => return Err(anyhow!("encode message to buffer failed: {}", e)),
-                };
-                let mut tasks = Vec::new();
-                for server in &self.servers {
-                    let t = self.query_task(is_direct, msg_buf.clone(), host, server);
-                    tasks.push(Box::pin(t));
-                }
-                let query_task = select_ok(tasks.into_iter());
-                query_tasks.push(query_task);
-
-                let msg = Self::new_query(name.clone(), RecordType::A);
-                let msg_buf = match msg.to_vec() {
-                    Ok(b) => b,
-                    Err(e) => return Err(anyhow!("encode message to buffer failed: {}", e)),
-                };
-                let mut tasks = Vec::new();
-                for server in &self.servers {
-                    let t = self.query_task(is_direct, msg_buf.clone(), host, server);
-                    tasks.push(Box::pin(t));
-                }
-                let query_task = select_ok(tasks.into_iter());
-                query_tasks.push(query_task);
+        // Bootstrap lookups resolve the proxy servers themselves.
+        if is_direct && self.system_resolver {
+            match self.system_lookup_types(host, types).await {
+                Ok(res) => return Ok(res),
+                Err(e) => debug!("system lookup {} failed: {}", host, e),
             }
-            (true, false) => {
-                let msg = Self::new_query(name.clone(), RecordType::A);
-                let msg_buf = match msg.to_vec() {
-                    Ok(b) => b,
-                    Err(e) => return Err(anyhow!("encode message to buffer failed: {}", e)),
-                };
-                let mut tasks = Vec::new();
-                for server in &self.servers {
-                    let t = self.query_task(is_direct, msg_buf.clone(), host, server);
-                    tasks.push(Box::pin(t));
-                }
-                let query_task = select_ok(tasks.into_iter());
-                query_tasks.push(query_task);
+        }
 
-                let msg = Self::new_query(name.clone(), RecordType::AAAA);
-                let msg_buf = match msg.to_vec() {
-                    Ok(b) => b,
-                    Err(e) => return Err(anyhow!("encode message to buffer failed: {}", e)),
-                };
-                let mut tasks = Vec::new();
-                for server in &self.servers {
-                    let t = self.query_task(is_direct, msg_buf.clone(), host, server);
-                    tasks.push(Box::pin(t));
-                }
-                let query_task = select_ok(tasks.into_iter());
-                query_tasks.push(query_task);
-            }
-            _ => {
-                let msg = Self::new_query(name.clone(), RecordType::A);
-                let msg_buf = match msg.to_vec() {
-                    Ok(b) => b,
-                    Err(e) => return Err(anyhow!("encode message to buffer failed: {}", e)),
-                };
-                let mut tasks = Vec::new();
-                for server in &self.servers {
-                    let t = self.query_task(is_direct, msg_buf.clone(), host, server);
-                    tasks.push(Box::pin(t));
-                }
-                let query_task = select_ok(tasks.into_iter());
-                query_tasks.push(query_task);
-            }
+        let name = Name::from_str(&format!("{}.", host))
+            .map_err(|e| anyhow!("invalid domain name [{}]: {}", host, e))?;
+        let mut query_tasks = Vec::new();
+        for ty in types {
+            let request = Self::new_query(name.clone(), *ty)
+                .to_vec()
+                .map_err(|e| anyhow!("encode message to buffer failed: {}", e))?;
+            query_tasks.push(self.query_servers(is_direct, request, host));
         }
 
-        let mut ips = Vec::new();
+        let mut res: Option<Resolution> = None;
         let mut last_err = None;
-
         for v in futures::future::join_all(query_tasks).await {
             match v {
-                Ok(mut v) => {
-                    self.cache_insert(host, v.0.clone()).await;
-                    ips.append(&mut v.0.ips);
+                Ok(entry) => {
+                    self.cache_insert(host, entry.clone()).await;
+                    let ttl = entry
+                        .deadline
+                        .saturating_duration_since(Instant::now())
+                        .as_secs();
+                    let res = res.get_or_insert_with(|| Resolution {
+                        ips: Vec::new(),
+                        source: entry.source.clone(),
+                        cached: false,
+                        ttl,
+                    });
+                    res.ips.extend(entry.ips);
+                    res.ttl = res.ttl.min(ttl);
                 }
-                Err(e) => last_err = Some(anyhow!("all dns servers failed, last error: {}", e)),
+                Err(e) => last_err = Some(e),
             }
         }
 
-        if !ips.is_empty() {
-            return Ok(ips);
+        if let Some(res) = res.filter(|res| !res.ips.is_empty()) {
+            return Ok(res);
+        }
+
+        if !is_direct && self.system_resolver {
+            debug!("lookup {} failed, trying the system resolver", host);
+            match self.system_lookup_types(host, types).await {
+                Ok(res) => return Ok(res),
+                Err(e) => debug!("system lookup {} failed: {}", host, e),
+            }
         }
 
         Err(last_err.unwrap_or_else(|| anyhow!("could not resolve to any address")))
@@ -533,3 +954,272 @@ impl DnsClient {
 }
 
 impl UdpConnector for DnsClient {}
+
+// The type of the records of the address.
+fn record_type(ip: &IpAddr) -> RecordType {
+    match ip {
+        IpAddr::V4(..) => RecordType::A,
+        IpAddr::V6(..) => RecordType::AAAA,
+    }
+}
+
+// Smooth weighted round-robin, as in nginx: every pick adds the weights to
+// the current weights and takes the largest, which is then lowered by the
+// total, spreading the picks of a server evenly over a round.
+fn smooth_weighted_pick(current: &mut [i64], weights: &[u32]) -> Option<usize> {
+    let total: i64 = weights.iter().map(|w| *w as i64).sum();
+    if total == 0 {
+        return None;
+    }
+    let mut best = 0;
+    for (i, w) in weights.iter().enumerate() {
+        current[i] += *w as i64;
+        if current[i] > current[best] {
+            best = i;
+        }
+    }
+    current[best] -= total;
+    Some(best)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    // A client with a server nothing answers on, for tests not querying it.
+    pub(crate) fn dns_client() -> crate::app::SyncDnsClient {
+        let mut dns = crate::config::Dns::new();
+        dns.servers.push("127.0.0.1".to_string());
+        Arc::new(tokio::sync::RwLock::new(
+            DnsClient::new(&protobuf::MessageField::some(dns)).unwrap(),
+        ))
+    }
+
+    // Answers a single query with 1.2.3.4, returns where it listens.
+    async fn answer_once() -> SocketAddr {
+        let server = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = server.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = vec![0u8; 512];
+            let (n, src) = server.recv_from(&mut buf).await.unwrap();
+            let mut msg = Message::from_vec(&buf[..n]).unwrap();
+            let name = msg.queries()[0].name().clone();
+            msg.set_message_type(MessageType::Response);
+            msg.add_answer(trust_dns_proto::rr::Record::from_rdata(
+                name,
+                60,
+                RData::A("1.2.3.4".parse().unwrap()),
+            ));
+            server.send_to(&msg.to_vec().unwrap(), src).await.unwrap();
+        });
+        server_addr
+    }
+
+    #[test]
+    fn test_smooth_weighted_pick() {
+        let weights = [5, 1, 1];
+        let mut current = [0; 3];
+        let picks: Vec<usize> = (0..7)
+            .map(|_| smooth_weighted_pick(&mut current, &weights).unwrap())
+            .collect();
+        assert_eq!(picks, vec![0, 0, 1, 0, 2, 0, 0]);
+
+        let weights = [0, 0];
+        let mut current = [0; 2];
+        assert_eq!(smooth_weighted_pick(&mut current, &weights), None);
+    }
+
+    #[test]
+    fn test_resolve_hosts() {
+        let mut dns = crate::config::Dns::new();
+        dns.servers.push("127.0.0.1".to_string());
+        let mut ips = crate::config::dns::Ips::new();
+        ips.values = vec!["1.2.3.4".to_string(), "::1".to_string()];
+        dns.hosts.insert("example.com".to_string(), ips);
+        let client = DnsClient::new(&protobuf::MessageField::some(dns)).unwrap();
+
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let res = client.resolve("example.com", RecordType::A).await.unwrap();
+            assert_eq!(res.ips, vec!["1.2.3.4".parse::<IpAddr>().unwrap()]);
+            assert_eq!(res.source, Source::Hosts);
+            assert!(!res.cached);
+            let res = client
+                .resolve("example.com", RecordType::AAAA)
+                .await
+                .unwrap();
+            assert_eq!(res.ips, vec!["::1".parse::<IpAddr>().unwrap()]);
+            assert!(client.resolve("example.com", RecordType::MX).await.is_err());
+            assert!(client.resolve("1.2.3.4", RecordType::A).await.is_err());
+        });
+    }
+
+    #[test]
+    fn test_resolve_shares_lookup() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let server_addr = answer_once().await;
+
+            let mut dns = crate::config::Dns::new();
+            dns.servers.push("127.0.0.1".to_string());
+            let mut client = DnsClient::new(&protobuf::MessageField::some(dns)).unwrap();
+            client.servers = vec![server_addr];
+
+            let ip = "1.2.3.4".parse::<IpAddr>().unwrap();
+            let ips = client
+                .direct_lookup(&"example.com".to_string())
+                .await
+                .unwrap();
+            assert_eq!(ips, vec![ip]);
+            // Served by the cache the session lookup filled.
+            let res = client.resolve("example.com", RecordType::A).await.unwrap();
+            assert_eq!(res.ips, vec![ip]);
+            assert_eq!(res.source, Source::Server(server_addr));
+            assert!(res.cached);
+            assert!(res.ttl > 0 && res.ttl <= 60);
+        });
+    }
+
+    #[test]
+    fn test_dns_outbound() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let server_addr = answer_once().await;
+
+            // The server address routes to "proxy", the DNS outbound is
+            // "direct".
+            let dispatcher = Arc::new(crate::app::dispatcher::tests::dispatcher(false));
+            let mut dns = crate::config::Dns::new();
+            dns.servers.push("127.0.0.1".to_string());
+            dns.outbound = "direct".to_string();
+            let mut client = DnsClient::new(&protobuf::MessageField::some(dns)).unwrap();
+            client.servers = vec![server_addr];
+            client.replace_dispatcher(Arc::downgrade(&dispatcher));
+
+            let ips = client
+                .destination_lookup(&"example.com".to_string())
+                .await
+                .unwrap();
+            assert_eq!(ips, vec!["1.2.3.4".parse::<IpAddr>().unwrap()]);
+            let stats = dispatcher.metrics().snapshot();
+            let successes = |tag: &str| {
+                stats
+                    .iter()
+                    .find(|s| s.tag == tag)
+                    .map_or(0, |s| s.successes)
+            };
+            assert_eq!(successes("direct"), 1);
+            assert_eq!(successes("proxy"), 0);
+        });
+    }
+
+    #[test]
+    fn test_clamp_ttl() {
+        let mut dns = crate::config::Dns::new();
+        dns.servers.push("127.0.0.1".to_string());
+        let mut rule = crate::config::dns::TtlRule::new();
+        rule.domain = "cdn.example.com".to_string();
+        rule.min = 60;
+        rule.max = 3600;
+        dns.ttl_rules.push(rule);
+        let mut rule = crate::config::dns::TtlRule::new();
+        rule.domain = "example.com".to_string();
+        rule.min = 300;
+        dns.ttl_rules.push(rule);
+        let client = DnsClient::new(&protobuf::MessageField::some(dns.clone())).unwrap();
+
+        assert_eq!(client.clamp_ttl("cdn.example.com", 10), 60);
+        assert_eq!(client.clamp_ttl("a.cdn.example.com.", 86400), 3600);
+        assert_eq!(client.clamp_ttl("www.example.com", 10), 300);
+        assert_eq!(client.clamp_ttl("Example.COM", 10), 300);
+        assert_eq!(client.clamp_ttl("badexample.com", 10), 10);
+
+        dns.ttl_rules[1].max = 100;
+        assert!(DnsClient::new(&protobuf::MessageField::some(dns)).is_err());
+    }
+
+    #[cfg(feature = "dns-over-https")]
+    #[test]
+    fn test_doh_server_addr() {
+        let mut dns = crate::config::Dns::new();
+        dns.servers.push("127.0.0.1".to_string());
+        let mut ips = crate::config::dns::Ips::new();
+        ips.values = vec!["1.2.3.4".to_string()];
+        dns.hosts.insert("doh.example.com".to_string(), ips);
+        dns.doh_server = "https://doh.example.com:8443/dns-query".to_string();
+        let client = DnsClient::new(&protobuf::MessageField::some(dns.clone())).unwrap();
+
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let doh = client.doh.as_ref().unwrap();
+            let addr = client.doh_server_addr(doh).await.unwrap();
+            assert_eq!(addr, "1.2.3.4:8443".parse::<SocketAddr>().unwrap());
+        });
+
+        dns.doh_server = "http://doh.example.com/dns-query".to_string();
+        assert!(DnsClient::new(&protobuf::MessageField::some(dns)).is_err());
+    }
+
+    #[cfg(feature = "dns-over-https")]
+    #[test]
+    fn test_doh_fallback() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let server_addr = answer_once().await;
+
+            let mut dns = crate::config::Dns::new();
+            dns.servers.push("127.0.0.1".to_string());
+            // Nothing listens there.
+            dns.doh_server = "https://127.0.0.1:1/dns-query".to_string();
+            let mut client = DnsClient::new(&protobuf::MessageField::some(dns)).unwrap();
+            client.servers = vec![server_addr];
+
+            let request =
+                DnsClient::new_query(Name::from_str("example.com.").unwrap(), RecordType::A)
+                    .to_vec()
+                    .unwrap();
+            let entry = client
+                .query_servers(true, request, "example.com")
+                .await
+                .unwrap();
+            assert_eq!(entry.ips, vec!["1.2.3.4".parse::<IpAddr>().unwrap()]);
+            assert_eq!(entry.source, Source::Server(server_addr));
+        });
+    }
+
+    #[test]
+    fn test_pending_queries() {
+        let pending = PendingQueries::new();
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            assert!(pending.wait(Duration::from_millis(10)).await);
+            let guard = pending.track();
+            assert_eq!(pending.count(), 1);
+            assert!(!pending.wait(Duration::from_millis(10)).await);
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                drop(guard);
+            });
+            assert!(pending.wait(Duration::from_secs(1)).await);
+            assert_eq!(pending.count(), 0);
+        });
+    }
+}
@@ -0,0 +1,326 @@
+use std::convert::TryFrom;
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Weak};
+
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use h2::client::SendRequest;
+use log::*;
+use tokio::sync::Mutex;
+use tokio_rustls::{
+    rustls::{ClientConfig, ServerName},
+    TlsConnector,
+};
+use trust_dns_proto::op::Message;
+
+use crate::proxy::AnyStream;
+
+const CONTENT_TYPE: &str = "application/dns-message";
+// The largest DNS message.
+const MAX_RESPONSE_SIZE: usize = u16::MAX as usize;
+
+// A connection to the server, shared by the queries until it's closed.
+struct Conn {
+    send_request: SendRequest<Bytes>,
+    closed: Arc<AtomicBool>,
+}
+
+/// Sends queries to a DNS-over-HTTPS server as RFC 8484 POST requests, over
+/// a HTTP/2 connection kept open between queries.
+pub struct DohClient {
+    url: http::Uri,
+    host: String,
+    port: u16,
+    tls_config: Arc<ClientConfig>,
+    // Connections made directly and through the dispatcher.
+    direct_conn: Arc<Mutex<Option<Conn>>>,
+    routed_conn: Arc<Mutex<Option<Conn>>>,
+}
+
+// Stops handing out an expired connection and drops its pooled handle, the
+// connection closes when the queries on it are done.
+async fn retire(pool: Weak<Mutex<Option<Conn>>>, closed: &Arc<AtomicBool>) {
+    closed.store(true, Ordering::Relaxed);
+    if let Some(pool) = pool.upgrade() {
+        let mut conn = pool.lock().await;
+        if matches!(conn.as_ref(), Some(c) if Arc::ptr_eq(&c.closed, closed)) {
+            conn.take();
+        }
+    }
+}
+
+impl DohClient {
+    pub fn new(url: &str) -> Result<Self> {
+        let uri = url
+            .parse::<http::Uri>()
+            .map_err(|e| anyhow!("invalid doh server {}: {}", url, e))?;
+        if uri.scheme_str() != Some("https") {
+            return Err(anyhow!("invalid doh server {}: expect a https url", url));
+        }
+        let host = uri
+            .host()
+            .ok_or_else(|| anyhow!("invalid doh server {}: missing host", url))?
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .to_string();
+        ServerName::try_from(host.as_str())
+            .map_err(|_| anyhow!("invalid doh server {}: invalid host", url))?;
+        Ok(DohClient {
+            port: uri.port_u16().unwrap_or(443),
+            url: uri,
+            host,
+            tls_config: crate::common::tls::make_default_config(&["h2"])?,
+            direct_conn: Arc::new(Mutex::new(None)),
+            routed_conn: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn url(&self) -> String {
+        self.url.to_string()
+    }
+
+    // Returns a handle to the current connection, makes a new one over the
+    // stream `connect` returns if there's none or it's closed.
+    async fn send_request<F>(&self, is_direct: bool, connect: F) -> Result<SendRequest<Bytes>>
+    where
+        F: Future<Output = Result<AnyStream>>,
+    {
+        let pool = if is_direct {
+            &self.direct_conn
+        } else {
+            &self.routed_conn
+        };
+        let mut conn = pool.lock().await;
+        if let Some(c) = conn.as_ref() {
+            if !c.closed.load(Ordering::Relaxed) {
+                return Ok(c.send_request.clone());
+            }
+        }
+        let stream = connect.await?;
+        let name = ServerName::try_from(self.host.as_str())
+            .map_err(|_| anyhow!("invalid dnsname: {}", &self.host))?;
+        let stream = TlsConnector::from(self.tls_config.clone())
+            .connect(name, stream)
+            .await?;
+        let (send_request, connection) = h2::client::handshake(stream).await?;
+        let closed = Arc::new(AtomicBool::new(false));
+        let closed2 = closed.clone();
+        let url = self.url();
+        let pool = Arc::downgrade(pool);
+        tokio::spawn(async move {
+            let mut connection = Box::pin(connection);
+            let res = match crate::proxy::pooled_conn_max_lifetime() {
+                Some(lifetime) => match tokio::time::timeout(lifetime, &mut connection).await {
+                    Ok(res) => res,
+                    Err(_) => {
+                        debug!("doh connection to {} expired", url);
+                        retire(pool, &closed2).await;
+                        connection.await
+                    }
+                },
+                None => connection.await,
+            };
+            if let Err(e) = res {
+                debug!("doh connection to {} failed: {}", url, e);
+            }
+            closed2.store(true, Ordering::Relaxed);
+        });
+        *conn = Some(Conn {
+            send_request: send_request.clone(),
+            closed,
+        });
+        Ok(send_request)
+    }
+
+    /// Drops the connections kept between queries, the next query makes a
+    /// new one.
+    pub async fn reset(&self) {
+        self.direct_conn.lock().await.take();
+        self.routed_conn.lock().await.take();
+    }
+
+    /// Sends the query, connecting through `connect` if there's no
+    /// connection to reuse.
+    pub async fn query<F>(&self, is_direct: bool, request: &[u8], connect: F) -> Result<Message>
+    where
+        F: Future<Output = Result<AnyStream>>,
+    {
+        let req = http::Request::builder()
+            .method(http::Method::POST)
+            .uri(self.url.clone())
+            .header(http::header::CONTENT_TYPE, CONTENT_TYPE)
+            .header(http::header::ACCEPT, CONTENT_TYPE)
+            .body(())?;
+        let mut send_request = self.send_request(is_direct, connect).await?.ready().await?;
+        let (response, mut send) = send_request.send_request(req, false)?;
+        send.send_data(Bytes::copy_from_slice(request), true)?;
+        let response = response.await?;
+        if response.status() != http::StatusCode::OK {
+            return Err(anyhow!("doh server returned {}", response.status()));
+        }
+        let mut body = response.into_body();
+        let mut buf = Vec::new();
+        while let Some(data) = body.data().await {
+            let data = data?;
+            let _ = body.flow_control().release_capacity(data.len());
+            buf.extend_from_slice(&data);
+            if buf.len() > MAX_RESPONSE_SIZE {
+                return Err(anyhow!("doh response too large"));
+            }
+        }
+        Message::from_vec(&buf).map_err(|e| anyhow!("parse message failed: {:?}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
+    use tokio_rustls::TlsAcceptor;
+    use trust_dns_proto::op::MessageType;
+    use trust_dns_proto::rr::{RData, Record, RecordType};
+
+    use super::*;
+    use crate::config::TrojanOutboundSettings;
+
+    #[test]
+    fn test_query() {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+        let mut server_config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![Certificate(cert.serialize_der().unwrap())],
+                PrivateKey(cert.serialize_private_key_der()),
+            )
+            .unwrap();
+        server_config.alpn_protocols = vec![b"h2".to_vec()];
+
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let (client, server) = tokio::io::duplex(64 * 1024);
+            // Answers the A queries with 1.2.3.4.
+            tokio::spawn(async move {
+                let stream = TlsAcceptor::from(Arc::new(server_config))
+                    .accept(server)
+                    .await
+                    .unwrap();
+                let mut conn = h2::server::handshake(stream).await.unwrap();
+                while let Some(req) = conn.accept().await {
+                    let (req, mut respond) = req.unwrap();
+                    assert_eq!(req.method(), http::Method::POST);
+                    assert_eq!(req.headers()[http::header::CONTENT_TYPE], CONTENT_TYPE);
+                    let mut body = req.into_body();
+                    let mut buf = Vec::new();
+                    while let Some(data) = body.data().await {
+                        buf.extend_from_slice(&data.unwrap());
+                    }
+                    let mut msg = Message::from_vec(&buf).unwrap();
+                    let name = msg.queries()[0].name().clone();
+                    msg.set_message_type(MessageType::Response);
+                    msg.add_answer(Record::from_rdata(
+                        name,
+                        60,
+                        RData::A("1.2.3.4".parse().unwrap()),
+                    ));
+                    let response = http::Response::builder()
+                        .header(http::header::CONTENT_TYPE, CONTENT_TYPE)
+                        .body(())
+                        .unwrap();
+                    let mut send = respond.send_response(response, false).unwrap();
+                    send.send_data(Bytes::from(msg.to_vec().unwrap()), true)
+                        .unwrap();
+                }
+            });
+
+            let mut c = DohClient::new("https://localhost/dns-query").unwrap();
+            let mut settings = TrojanOutboundSettings::new();
+            settings.ca_cert = cert.serialize_pem().unwrap();
+            settings.alpn = vec!["h2".to_string()];
+            c.tls_config = crate::common::tls::make_config(&settings).unwrap();
+
+            let mut query = Message::new();
+            query.add_query(trust_dns_proto::op::Query::query(
+                "example.com.".parse().unwrap(),
+                RecordType::A,
+            ));
+            let connect = async move { Ok(Box::new(client) as AnyStream) };
+            let resp = c
+                .query(true, &query.to_vec().unwrap(), connect)
+                .await
+                .unwrap();
+            assert_eq!(
+                resp.answers()[0].rdata(),
+                &RData::A("1.2.3.4".parse().unwrap())
+            );
+
+            // The connection is kept for the next query.
+            let connect = async { Err(anyhow!("not connecting again")) };
+            assert!(c
+                .query(true, &query.to_vec().unwrap(), connect)
+                .await
+                .is_ok());
+        });
+    }
+
+    #[test]
+    fn test_new() {
+        let c = DohClient::new("https://1.1.1.1/dns-query").unwrap();
+        assert_eq!(c.host(), "1.1.1.1");
+        assert_eq!(c.port(), 443);
+        let c = DohClient::new("https://[2606:4700:4700::1111]:8443/dns-query").unwrap();
+        assert_eq!(c.host(), "2606:4700:4700::1111");
+        assert_eq!(c.port(), 8443);
+        let c = DohClient::new("https://dns.google/dns-query").unwrap();
+        assert_eq!(c.url(), "https://dns.google/dns-query");
+
+        assert!(DohClient::new("http://1.1.1.1/dns-query").is_err());
+        assert!(DohClient::new("1.1.1.1").is_err());
+    }
+
+    #[test]
+    fn test_retire() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let (client, server) = tokio::io::duplex(1024);
+            tokio::spawn(async move {
+                let mut conn = h2::server::handshake(server).await.unwrap();
+                while conn.accept().await.is_some() {}
+            });
+            let (send_request, connection) = h2::client::handshake(client).await.unwrap();
+            tokio::spawn(connection);
+            let c = DohClient::new("https://1.1.1.1/dns-query").unwrap();
+            let closed = Arc::new(AtomicBool::new(false));
+            *c.direct_conn.lock().await = Some(Conn {
+                send_request,
+                closed: closed.clone(),
+            });
+
+            // Another connection has taken its place.
+            retire(
+                Arc::downgrade(&c.direct_conn),
+                &Arc::new(AtomicBool::new(false)),
+            )
+            .await;
+            assert!(c.direct_conn.lock().await.is_some());
+
+            retire(Arc::downgrade(&c.direct_conn), &closed).await;
+            assert!(closed.load(Ordering::Relaxed));
+            assert!(c.direct_conn.lock().await.is_none());
+        });
+    }
+}
@@ -1,5 +1,5 @@
 use indexmap::IndexMap;
-use std::net::{IpAddr, Ipv4Addr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
 use anyhow::{anyhow, Result};
 use byteorder::{BigEndian, ByteOrder};
@@ -32,8 +32,8 @@ impl FakeDns {
         self.0.read().await.query_domain(ip)
     }
 
-    pub async fn query_fake_ip(&self, domain: &str) -> Option<IpAddr> {
-        self.0.read().await.query_fake_ip(domain)
+    pub async fn query_fake_ip(&self, domain: &str, ipv6: bool) -> Option<IpAddr> {
+        self.0.read().await.query_fake_ip(domain, ipv6)
     }
 
     pub async fn generate_fake_response(&self, request: &[u8]) -> Result<Vec<u8>> {
@@ -54,12 +54,33 @@ pub(self) struct FakeDnsImpl {
     ttl: u32,
     filters: Vec<String>,
     mode: FakeDnsMode,
+    // Answers AAAA queries with addresses in the prefix, which map to the
+    // IPv4 ones by the last 32 bits, rather than nothing.
+    ipv6_prefix: Option<[u8; 12]>,
 }
 
 impl FakeDnsImpl {
     pub(self) fn new(mode: FakeDnsMode) -> Self {
         let min_cursor = Self::ip_to_u32(&Ipv4Addr::new(198, 18, 0, 0));
         let max_cursor = Self::ip_to_u32(&Ipv4Addr::new(198, 18, 4, 255));
+        let ipv6_prefix = if *crate::option::ENABLE_IPV6 {
+            match crate::option::FAKE_DNS_IPV6_PREFIX.parse::<Ipv6Addr>() {
+                Ok(prefix) => {
+                    let mut octets = [0u8; 12];
+                    octets.copy_from_slice(&prefix.octets()[..12]);
+                    Some(octets)
+                }
+                Err(e) => {
+                    warn!(
+                        "invalid fake IPv6 prefix, AAAA queries are not answered: {}",
+                        e
+                    );
+                    None
+                }
+            }
+        } else {
+            None
+        };
         Self {
             ip_to_domain: IndexMap::new(),
             domain_to_ip: IndexMap::new(),
@@ -69,6 +90,7 @@ impl FakeDnsImpl {
             ttl: 1,
             filters: Vec::new(),
             mode,
+            ipv6_prefix,
         }
     }
 
@@ -78,16 +100,20 @@ impl FakeDnsImpl {
 
     pub(self) fn query_domain(&self, ip: &IpAddr) -> Option<String> {
         let ip = match ip {
-            IpAddr::V4(ip) => ip,
-            _ => return None,
+            IpAddr::V4(ip) => Self::ip_to_u32(ip),
+            IpAddr::V6(ip) => self.ipv6_to_u32(ip)?,
         };
-        self.ip_to_domain.get(&Self::ip_to_u32(ip)).cloned()
+        self.ip_to_domain.get(&ip).cloned()
     }
 
-    pub(self) fn query_fake_ip(&self, domain: &str) -> Option<IpAddr> {
-        self.domain_to_ip
-            .get(domain)
-            .map(|v| IpAddr::V4(Self::u32_to_ip(v.to_owned())))
+    // Returns the IPv6 one if `ipv6` is set and fake IPv6 addresses are
+    // enabled, the IPv4 one otherwise.
+    pub(self) fn query_fake_ip(&self, domain: &str, ipv6: bool) -> Option<IpAddr> {
+        let v = self.domain_to_ip.get(domain)?.to_owned();
+        match self.u32_to_ipv6(v) {
+            Some(ip) if ipv6 => Some(IpAddr::V6(ip)),
+            _ => Some(IpAddr::V4(Self::u32_to_ip(v))),
+        }
     }
 
     pub(self) fn generate_fake_response(&mut self, request: &[u8]) -> Result<Vec<u8>> {
@@ -124,7 +150,7 @@ impl FakeDnsImpl {
             return Err(anyhow!("domain {} not accepted", domain));
         }
 
-        let ip = if let Some(ip) = self.query_fake_ip(&domain) {
+        let ip = if let Some(ip) = self.query_fake_ip(&domain, false) {
             match ip {
                 IpAddr::V4(a) => a,
                 _ => return Err(anyhow!("unexpected Ipv6 fake IP")),
@@ -161,19 +187,47 @@ impl FakeDnsImpl {
                 .set_rdata(RData::A(ip));
             resp.add_answer(ans);
         }
+        if query.query_type() == RecordType::AAAA {
+            if let Some(ip) = self.u32_to_ipv6(Self::ip_to_u32(&ip)) {
+                let mut ans = Record::new();
+                ans.set_name(raw_name.clone())
+                    .set_rr_type(RecordType::AAAA)
+                    .set_ttl(self.ttl)
+                    .set_dns_class(DNSClass::IN)
+                    .set_rdata(RData::AAAA(ip));
+                resp.add_answer(ans);
+            }
+        }
 
         Ok(resp.to_vec()?)
     }
 
     pub(self) fn is_fake_ip(&self, ip: &IpAddr) -> bool {
         let ip = match ip {
-            IpAddr::V4(ip) => ip,
-            _ => return false,
+            IpAddr::V4(ip) => Self::ip_to_u32(ip),
+            IpAddr::V6(ip) => match self.ipv6_to_u32(ip) {
+                Some(ip) => ip,
+                None => return false,
+            },
         };
-        let ip = Self::ip_to_u32(ip);
         ip >= self.min_cursor && ip <= self.max_cursor
     }
 
+    fn u32_to_ipv6(&self, ip: u32) -> Option<Ipv6Addr> {
+        let mut octets = [0u8; 16];
+        octets[..12].copy_from_slice(self.ipv6_prefix.as_ref()?);
+        BigEndian::write_u32(&mut octets[12..], ip);
+        Some(Ipv6Addr::from(octets))
+    }
+
+    fn ipv6_to_u32(&self, ip: &Ipv6Addr) -> Option<u32> {
+        let octets = ip.octets();
+        if &octets[..12] != self.ipv6_prefix.as_ref()? {
+            return None;
+        }
+        Some(BigEndian::read_u32(&octets[12..]))
+    }
+
     fn allocate_ip(&mut self, domain: &str) -> Ipv4Addr {
         if let Some(prev_domain) = self.ip_to_domain.insert(self.cursor, domain.to_owned()) {
             // Remove the entry in the reverse map to make sure we won't have
@@ -249,4 +303,26 @@ mod tests {
         let ip2 = 2130706433u32;
         assert_eq!(ip1, ip2);
     }
+
+    #[test]
+    fn test_fake_ipv6() {
+        let mut fakedns = FakeDnsImpl::new(FakeDnsMode::Exclude);
+        fakedns.ipv6_prefix = Some([0xfd, 0x07, 0xfa, 0x6e, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let ip = fakedns.allocate_ip("example.com");
+        let ip6 = match fakedns.query_fake_ip("example.com", true) {
+            Some(IpAddr::V6(ip6)) => ip6,
+            other => panic!("unexpected {:?}", other),
+        };
+        assert_eq!(ip6.octets()[12..], ip.octets());
+        assert!(fakedns.is_fake_ip(&IpAddr::V6(ip6)));
+        assert_eq!(
+            fakedns.query_domain(&IpAddr::V6(ip6)).as_deref(),
+            Some("example.com")
+        );
+        assert!(!fakedns.is_fake_ip(&"fd07::2".parse().unwrap()));
+        assert_eq!(
+            fakedns.query_fake_ip("example.com", false),
+            Some(IpAddr::V4(ip))
+        );
+    }
 }
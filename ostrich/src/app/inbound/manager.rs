@@ -1,6 +1,7 @@
 use indexmap::IndexMap;
 use std::os::windows::process::CommandExt;
 use std::process::{Child, Stdio};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 
 use anyhow::{anyhow, Result};
@@ -11,6 +12,7 @@ use crate::config;
 use crate::proxy;
 use crate::proxy::AnyInboundHandler;
 use crate::Runner;
+use crate::TunCounters;
 use std::sync::Mutex;
 
 use super::network_listener::NetworkInboundListener;
@@ -28,6 +30,7 @@ use crate::proxy::socks;
     )
 ))]
 use super::tun_listener::TunInboundListener;
+use protobuf::Message;
 
 #[cfg(all(feature = "inbound-tun", any(target_os = "windows",)))]
 impl Drop for InboundManager {
@@ -35,7 +38,399 @@ impl Drop for InboundManager {
         if let Some(process) = self.tun2socks_process.lock().unwrap().as_mut() {
             // self.tun2socks_process.as_mut()
             //     .unwrap()
-            process.kill().expect("cant kill tun2socks process")
+            process.kill().expect("cant kill tun2socks process");
+            let _ = std::fs::remove_file(tun2socks_pid_file(&self.tun_device));
+        }
+    }
+}
+
+/// The settings of the TUN inbound can't be used to set up the device.
+#[derive(Debug, thiserror::Error)]
+#[error("{0}")]
+pub struct TunSetupError(pub anyhow::Error);
+
+/// The tun device set up for tun2socks, as configured by the settings of the
+/// TUN inbound, the DEFAULT_TUN_* options for those not set.
+#[derive(Clone, Debug)]
+pub struct TunDevice {
+    pub name: String,
+    pub address: String,
+    pub gateway: String,
+    pub netmask: String,
+    /// The IPv6 address, gateway and prefix length used with ENABLE_IPV6,
+    /// there are no settings for them.
+    pub ipv6_address: String,
+    pub ipv6_gateway: String,
+    pub ipv6_prefixlen: i32,
+    /// The DNS server the system is set to use through the device, none
+    /// leaves the DNS of the system untouched.
+    pub dns: Option<String>,
+}
+
+impl TunDevice {
+    /// `dns_servers` are those of the DNS config, the first IPv4 one is set
+    /// as the DNS of the device if the TUN inbound doesn't set one.
+    pub fn from_inbounds(inbounds: &[config::Inbound], dns_servers: &[String]) -> Result<Self> {
+        let settings = match inbounds.iter().find(|inbound| inbound.protocol == "tun") {
+            Some(inbound) => config::TunInboundSettings::parse_from_bytes(&inbound.settings)?,
+            None => config::TunInboundSettings::new(),
+        };
+        let or_default = |value: &str, default: &str| {
+            if value.is_empty() {
+                default.to_string()
+            } else {
+                value.to_string()
+            }
+        };
+        let dns = match settings.dns.as_str() {
+            "system" => None,
+            "" => dns_servers
+                .iter()
+                .find(|s| s.parse::<std::net::Ipv4Addr>().is_ok())
+                .cloned(),
+            dns => Some(dns.to_string()),
+        };
+        let device = TunDevice {
+            name: or_default(&settings.name, &crate::option::DEFAULT_TUN_NAME),
+            address: or_default(&settings.address, &crate::option::DEFAULT_TUN_IPV4_ADDR),
+            gateway: or_default(&settings.gateway, &crate::option::DEFAULT_TUN_IPV4_GW),
+            netmask: or_default(&settings.netmask, &crate::option::DEFAULT_TUN_IPV4_MASK),
+            ipv6_address: crate::option::DEFAULT_TUN_IPV6_ADDR.clone(),
+            ipv6_gateway: crate::option::DEFAULT_TUN_IPV6_GW.clone(),
+            ipv6_prefixlen: *crate::option::DEFAULT_TUN_IPV6_PREFIXLEN,
+            dns,
+        };
+        // They end up in commands, better fail here than there.
+        for (what, value) in [
+            ("address", &device.address),
+            ("gateway", &device.gateway),
+            ("netmask", &device.netmask),
+        ]
+        .into_iter()
+        .chain(device.dns.as_ref().map(|dns| ("dns", dns)))
+        {
+            value
+                .parse::<std::net::Ipv4Addr>()
+                .map_err(|e| anyhow!("invalid tun {} {}: {}", what, value, e))?;
+        }
+        let mask = u32::from(device.netmask.parse::<std::net::Ipv4Addr>()?);
+        if mask.leading_ones() != mask.count_ones() {
+            return Err(anyhow!("invalid tun netmask {}", &device.netmask));
+        }
+        for (what, value) in [
+            ("ipv6 address", &device.ipv6_address),
+            ("ipv6 gateway", &device.ipv6_gateway),
+        ] {
+            value
+                .parse::<std::net::Ipv6Addr>()
+                .map_err(|e| anyhow!("invalid tun {} {}: {}", what, value, e))?;
+        }
+        if !(0..=128).contains(&device.ipv6_prefixlen) {
+            return Err(anyhow!(
+                "invalid tun ipv6 prefix length {}",
+                device.ipv6_prefixlen
+            ));
+        }
+        Ok(device)
+    }
+
+    /// The prefix length of the netmask.
+    pub fn prefix_len(&self) -> u32 {
+        self.netmask
+            .parse::<std::net::Ipv4Addr>()
+            .map(|mask| u32::from(mask).count_ones())
+            .unwrap_or(32)
+    }
+}
+
+// Where the PID of the tun2socks started on the device is kept, so that the
+// next run can tell which process this one left behind.
+#[cfg(all(feature = "inbound-tun", target_os = "windows"))]
+fn tun2socks_pid_file(device: &TunDevice) -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("ostrich-tun2socks-{}.pid", &device.name))
+}
+
+// Removes what a previous instance that didn't exit cleanly may have left
+// behind: the tun2socks process still holding the device, the device itself
+// and the default routes through it. Failures are ignored as usually there's
+// nothing to remove.
+#[cfg(feature = "inbound-tun")]
+fn cleanup_stale_tun(tun2socks_path: &str, device: &TunDevice) {
+    use std::process::Command;
+
+    fn quiet(cmd: &mut Command) {
+        #[cfg(target_os = "windows")]
+        cmd.creation_flags(0x08000000);
+        let _ = cmd
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+    }
+
+    if let Ok(ifs) = local_ip_address::list_afinet_netifas() {
+        if ifs.iter().any(|(name, _)| name == &device.name) {
+            log::warn!(
+                "removing stale tun device {} from a previous run",
+                &device.name
+            );
+        }
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    {
+        use crate::sys::{apply, Change};
+
+        let _ = tun2socks_path;
+        quiet(
+            Command::new("pkill")
+                .arg("-f")
+                .arg(format!("tun://{}", &device.name)),
+        );
+        if let Ok(gw) = device.gateway.parse() {
+            let _ = apply(Change::DeleteDefaultIpv4RouteVia(gw));
+        }
+        if let Ok(gw) = device.ipv6_gateway.parse() {
+            let _ = apply(Change::DeleteDefaultIpv6RouteVia(gw));
+        }
+        #[cfg(target_os = "linux")]
+        let _ = apply(Change::DeleteTun(device.name.clone()));
+    }
+    #[cfg(target_os = "windows")]
+    {
+        // Only the process the previous run started, the image name filter
+        // keeps a reused PID from being killed.
+        let pid_file = tun2socks_pid_file(device);
+        let pid = std::fs::read_to_string(&pid_file)
+            .ok()
+            .and_then(|pid| pid.trim().parse::<u32>().ok());
+        let name = std::path::Path::new(tun2socks_path).file_name();
+        if let (Some(pid), Some(name)) = (pid, name) {
+            quiet(
+                Command::new("taskkill")
+                    .args(["/F", "/PID"])
+                    .arg(pid.to_string())
+                    .arg("/FI")
+                    .arg(format!("IMAGENAME eq {}", name.to_string_lossy())),
+            );
+        }
+        let _ = std::fs::remove_file(&pid_file);
+        quiet(Command::new("route").args([
+            "delete",
+            "0.0.0.0",
+            "mask",
+            "0.0.0.0",
+            &device.gateway,
+        ]));
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "macos", target_os = "windows")))]
+    let _ = tun2socks_path;
+}
+
+// Creates the tun device and starts tun2socks on it, returns an error instead of
+// panicking so that the caller can keep running the network inbounds. The
+// device is created and addressed through `sys`, by the route helper if
+// there's one.
+#[cfg(all(
+    feature = "inbound-tun",
+    any(
+        target_os = "ios",
+        target_os = "android",
+        target_os = "macos",
+        target_os = "linux"
+    )
+))]
+fn setup_tun_device(tun2socks_path: &str, device: &TunDevice) -> Result<()> {
+    use local_ip_address::list_afinet_netifas;
+    use std::process::Command;
+
+    cleanup_stale_tun(tun2socks_path, device);
+
+    #[cfg(target_os = "linux")]
+    {
+        use crate::sys::{apply, Change};
+
+        apply(Change::AddTun {
+            interface: device.name.clone(),
+            user: unsafe { libc::getuid() },
+        })?;
+        apply(Change::AddIpv4Address {
+            interface: device.name.clone(),
+            addr: device.address.parse()?,
+            gateway: device.gateway.parse()?,
+            mask: device.netmask.parse()?,
+        })?;
+        apply(Change::SetLinkUp(device.name.clone()))?;
+        std::thread::sleep(std::time::Duration::from_secs(3));
+        log::warn!("tun device is up");
+    }
+
+    Command::new(tun2socks_path)
+        .arg("-device")
+        .arg(format!("tun://{}", &device.name))
+        .arg("-proxy")
+        .arg("socks5://127.0.0.1:1086")
+        .arg("-loglevel")
+        .arg("debug")
+        .spawn()
+        .map_err(|e| anyhow!("start tun2socks {} failed: {}", tun2socks_path, e))?;
+    println!("init tun device process finished");
+
+    #[cfg(target_os = "macos")]
+    {
+        use crate::sys::{apply, Change};
+
+        // The point-to-point peer is the device itself.
+        apply(Change::AddIpv4Address {
+            interface: device.name.clone(),
+            addr: device.address.parse()?,
+            gateway: device.address.parse()?,
+            mask: device.netmask.parse()?,
+        })?;
+        apply(Change::SetLinkUp(device.name.clone()))?;
+    }
+
+    for _ in 0..20 {
+        std::thread::sleep(std::time::Duration::from_millis(500));
+        let network_interfaces = list_afinet_netifas()
+            .map_err(|e| anyhow!("list network interfaces failed: {}", e))?;
+        if network_interfaces
+            .iter()
+            .any(|(name, _)| name == &device.name)
+        {
+            println!("tun device up");
+            return Ok(());
+        }
+    }
+    Err(anyhow!("tun device {} is not up after 10s", &device.name))
+}
+
+// Whether the TUN inbound takes an opened fd or an existing device, which
+// leaves nothing to create.
+#[cfg(all(
+    feature = "inbound-tun",
+    any(
+        target_os = "ios",
+        target_os = "android",
+        target_os = "macos",
+        target_os = "linux"
+    )
+))]
+fn tun_supplied(inbounds: &[config::Inbound]) -> bool {
+    inbounds.iter().any(|inbound| {
+        inbound.protocol == "tun"
+            && crate::config::TunInboundSettings::parse_from_bytes(&inbound.settings)
+                .map(|s| s.fd >= 0 || s.existing)
+                .unwrap_or(false)
+    })
+}
+
+/// Whether the inbounds are serving, for health checks. Listeners are ready
+/// from being bound until their runners end, the TUN inbound from its runner
+/// starting until it ends, as long as the device is up.
+pub struct InboundReadiness {
+    listeners: Mutex<IndexMap<String, bool>>,
+    // None if there's no TUN inbound.
+    tun: Mutex<Option<bool>>,
+    // The device checked for being up, none if it's supplied and there's no
+    // name to check.
+    tun_device: Option<String>,
+}
+
+/// The readiness of the inbounds at some point.
+#[derive(Clone, Debug)]
+pub struct InboundHealth {
+    /// The tags of the network listeners and whether they're serving.
+    pub listeners: Vec<(String, bool)>,
+    /// Whether the TUN inbound is serving, none if there's no TUN inbound.
+    pub tun: Option<bool>,
+}
+
+impl InboundHealth {
+    /// All the listeners are serving, and the TUN inbound if there's one.
+    pub fn ready(&self) -> bool {
+        self.listeners.iter().all(|(_, ready)| *ready) && self.tun.unwrap_or(true)
+    }
+}
+
+impl InboundReadiness {
+    fn new(tun_device: Option<String>) -> Self {
+        InboundReadiness {
+            listeners: Mutex::new(IndexMap::new()),
+            tun: Mutex::new(None),
+            tun_device,
+        }
+    }
+
+    fn set_listener(&self, tag: &str, ready: bool) {
+        self.listeners
+            .lock()
+            .unwrap()
+            .insert(tag.to_string(), ready);
+    }
+
+    fn set_tun(&self, ready: bool) {
+        *self.tun.lock().unwrap() = Some(ready);
+    }
+
+    pub fn health(&self) -> InboundHealth {
+        let listeners = self
+            .listeners
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(tag, ready)| (tag.clone(), *ready))
+            .collect();
+        let tun = self.tun.lock().unwrap().map(|ready| {
+            ready
+                && self
+                    .tun_device
+                    .as_deref()
+                    .map_or(true, crate::proxy::interface_is_up)
+        });
+        InboundHealth { listeners, tun }
+    }
+}
+
+/// Packets and bytes through the TUN device of a runtime, whatever happens to
+/// them afterwards.
+#[derive(Default)]
+pub struct TunTraffic {
+    rx_packets: AtomicU64,
+    rx_bytes: AtomicU64,
+    tx_packets: AtomicU64,
+    tx_bytes: AtomicU64,
+    dropped_packets: AtomicU64,
+}
+
+impl TunTraffic {
+    /// Counts a packet read from the device.
+    pub fn record_rx(&self, bytes: u64) {
+        self.rx_packets.fetch_add(1, Ordering::Relaxed);
+        self.rx_bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Counts a packet written to the device.
+    pub fn record_tx(&self, bytes: u64) {
+        self.tx_packets.fetch_add(1, Ordering::Relaxed);
+        self.tx_bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Counts a packet read from the device but dropped as the queue to the
+    /// stack was full.
+    pub fn record_dropped(&self) {
+        self.dropped_packets.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the packets and bytes read from and written to the device
+    /// since start.
+    pub fn counters(&self) -> TunCounters {
+        TunCounters {
+            rx_packets: self.rx_packets.load(Ordering::Relaxed),
+            rx_bytes: self.rx_bytes.load(Ordering::Relaxed),
+            tx_packets: self.tx_packets.load(Ordering::Relaxed),
+            tx_bytes: self.tx_bytes.load(Ordering::Relaxed),
+            dropped_packets: self.dropped_packets.load(Ordering::Relaxed),
         }
     }
 }
@@ -55,6 +450,61 @@ pub struct InboundManager {
     #[cfg(all(feature = "inbound-tun", any(target_os = "windows",)))]
     tun2socks_process: Arc<Mutex<Option<Child>>>,
     tun_auto: bool,
+    tun_device: TunDevice,
+    readiness: Arc<InboundReadiness>,
+    tun_traffic: Arc<TunTraffic>,
+}
+
+// Returns where the UDP relay of the SOCKS inbound binds if it's configured
+// apart from the TCP control port.
+fn socks_udp_relay(inbound: &config::Inbound) -> Result<Option<std::net::SocketAddr>> {
+    if inbound.settings.is_empty() {
+        return Ok(None);
+    }
+    let settings = config::SocksInboundSettings::parse_from_bytes(&inbound.settings)?;
+    if settings.udp_address.is_empty() && settings.udp_port == 0 {
+        return Ok(None);
+    }
+    let address = if settings.udp_address.is_empty() {
+        &inbound.address
+    } else {
+        &settings.udp_address
+    };
+    let port = if settings.udp_port == 0 {
+        inbound.port
+    } else {
+        settings.udp_port
+    };
+    let port = u16::try_from(port)
+        .map_err(|_| anyhow!("invalid [{}] inbound: udp port {}", &inbound.tag, port))?;
+    let ip = address.parse().map_err(|e| {
+        anyhow!(
+            "invalid [{}] inbound: udp address {}: {}",
+            &inbound.tag,
+            address,
+            e
+        )
+    })?;
+    Ok(Some(std::net::SocketAddr::new(ip, port)))
+}
+
+// Returns the username and password clients of the SOCKS inbound must log in
+// with if they're configured.
+fn socks_credentials(inbound: &config::Inbound) -> Result<Option<(String, String)>> {
+    if inbound.settings.is_empty() {
+        return Ok(None);
+    }
+    let settings = config::SocksInboundSettings::parse_from_bytes(&inbound.settings)?;
+    if settings.username.is_empty() {
+        if !settings.password.is_empty() {
+            return Err(anyhow!(
+                "invalid [{}] inbound: password without username",
+                &inbound.tag
+            ));
+        }
+        return Ok(None);
+    }
+    Ok(Some((settings.username, settings.password)))
 }
 
 impl InboundManager {
@@ -62,17 +512,43 @@ impl InboundManager {
         inbounds: &Vec<config::Inbound>,
         dispatcher: Arc<Dispatcher>,
         nat_manager: Arc<NatManager>,
+        dns_servers: &[String],
         #[cfg(target_os = "windows")] mut ipset: Vec<String>,
         #[cfg(target_os = "windows")] wintun_path: String,
         #[cfg(target_os = "windows")] tun2socks_path: String,
     ) -> Result<Self> {
         let mut handlers: IndexMap<String, AnyInboundHandler> = IndexMap::new();
+        let tun_device = TunDevice::from_inbounds(inbounds, dns_servers).map_err(TunSetupError)?;
+        // A supplied device may not have the name of the settings.
+        #[cfg(all(
+            feature = "inbound-tun",
+            any(
+                target_os = "ios",
+                target_os = "android",
+                target_os = "macos",
+                target_os = "linux"
+            )
+        ))]
+        let readiness = Arc::new(InboundReadiness::new(
+            Some(tun_device.name.clone()).filter(|_| !tun_supplied(inbounds)),
+        ));
+        #[cfg(not(all(
+            feature = "inbound-tun",
+            any(
+                target_os = "ios",
+                target_os = "android",
+                target_os = "macos",
+                target_os = "linux"
+            )
+        )))]
+        let readiness = Arc::new(InboundReadiness::new(Some(tun_device.name.clone())));
+        let tun_traffic = Arc::new(TunTraffic::default());
         let tun2socks_process = Arc::new(Mutex::new(None));
         let tun2socks_process_clone = tun2socks_process.clone();
         let tag = String::from("socks_in");
 
-        let stream = Arc::new(socks::inbound::StreamHandler);
-        let datagram = Arc::new(socks::inbound::DatagramHandler);
+        let stream = Arc::new(socks::inbound::StreamHandler::default());
+        let datagram = Arc::new(socks::inbound::DatagramHandler::default());
         let handler = Arc::new(proxy::inbound::Handler::new(
             tag.clone(),
             Some(stream),
@@ -87,6 +563,13 @@ impl InboundManager {
             let (tun_tx, mut tun_rx) = mpsc::channel(1);
             let tun2socks_path = tun2socks_path.clone();
             let ipset = ipset.clone();
+            let device = tun_device.clone();
+            let device2 = tun_device.clone();
+
+            cleanup_stale_tun(&tun2socks_path, &device);
+            readiness.set_tun(false);
+            let readiness2 = readiness.clone();
+            let tun2socks_process_weak = Arc::downgrade(&tun2socks_process);
 
             tokio::spawn(async move {
                 // println!("tun2socks path: {}", tun2socks_path.as_str());
@@ -96,7 +579,7 @@ impl InboundManager {
                     // .stdout(Stdio::null())
                     // .stdin(Stdio::null())
                     .arg("-device")
-                    .arg("tun://utun233")
+                    .arg(format!("tun://{}", &device.name))
                     .arg("-proxy")
                     .arg("socks5://127.0.0.1:1086")
                     // flag.StringVar(&key.LogLevel, "loglevel", "info", "Log level [debug|info|warning|error|silent]")
@@ -105,6 +588,11 @@ impl InboundManager {
                     .spawn()
                     .expect("failed to execute process");
                 // println!("init tun device process finished");
+                if let Err(e) =
+                    std::fs::write(tun2socks_pid_file(&device), process.id().to_string())
+                {
+                    log::warn!("writing tun2socks pid failed: {}", e);
+                }
                 *tun2socks_process_clone.lock().unwrap() = Some(process);
                 if let Err(e) = tun_tx.send(()).await {
                     log::warn!("tun device completed signal failed: {}", e);
@@ -119,12 +607,13 @@ impl InboundManager {
                     let network_interfaces = list_afinet_netifas().unwrap();
 
                     for (name, _g) in network_interfaces.iter() {
-                        if name == "utun233" {
+                        if name == &device2.name {
                             // println!("tun device up");
                             break 'netif;
                         }
                     }
                 }
+                readiness2.set_tun(true);
                 // std::thread::sleep(std::time::Duration::from_secs(2));
 
                 let gateway = cmd::get_default_ipv4_gateway().unwrap();
@@ -138,29 +627,31 @@ impl InboundManager {
                     .arg("ip")
                     .arg("set")
                     .arg("address")
-                    .arg("utun233")
+                    .arg(&device2.name)
                     .arg("static")
-                    .arg("172.7.0.2")
-                    .arg("255.255.255.0")
-                    .arg("172.7.0.1")
+                    .arg(&device2.address)
+                    .arg(&device2.netmask)
+                    .arg(&device2.gateway)
                     .arg("3")
                     .output()
                     .expect("failed to execute command");
 
                 // netsh interface ip set dns name=%tun_device% static 8.8.8.8
-                let _ = Command::new("netsh").creation_flags(0x08000000)
-                    .stderr(Stdio::null())
-                    .stdout(Stdio::null())
-                    .stdin(Stdio::null())
-                    .arg("interface")
-                    .arg("ip")
-                    .arg("set")
-                    .arg("dns")
-                    .arg("name=utun233")
-                    .arg("static")
-                    .arg("127.0.0.1")
-                    .output()
-                    .expect("failed to execute command");
+                if let Some(dns) = &device2.dns {
+                    let _ = Command::new("netsh").creation_flags(0x08000000)
+                        .stderr(Stdio::null())
+                        .stdout(Stdio::null())
+                        .stdin(Stdio::null())
+                        .arg("interface")
+                        .arg("ip")
+                        .arg("set")
+                        .arg("dns")
+                        .arg(format!("name={}", &device2.name))
+                        .arg("static")
+                        .arg(dns)
+                        .output()
+                        .expect("failed to execute command");
+                }
                 // println!("process finished with: {}", out);
                 for ip in &ipset {
                     let _ = Command::new("route").creation_flags(0x08000000)
@@ -176,6 +667,25 @@ impl InboundManager {
                         .expect("failed to execute command");
                     // println!("process finished with: {}", out);
                 }
+
+                // TUN is down with tun2socks, until the manager goes.
+                loop {
+                    tokio::time::sleep(std::time::Duration::from_secs(1)).await;
+                    let process = match tun2socks_process_weak.upgrade() {
+                        Some(p) => p,
+                        None => break,
+                    };
+                    let exited = match process.lock().unwrap().as_mut().map(Child::try_wait) {
+                        Some(Ok(Some(status))) => Some(status.to_string()),
+                        Some(Err(e)) => Some(e.to_string()),
+                        _ => None,
+                    };
+                    if let Some(status) = exited {
+                        log::error!("tun2socks exited: {}", status);
+                        readiness2.set_tun(false);
+                        break;
+                    }
+                }
             });
         }
         #[cfg(all(
@@ -187,102 +697,21 @@ impl InboundManager {
                 target_os = "linux"
             )
         ))]
-        {
-            use crate::common::cmd;
-            use std::process::Command;
-            use tokio::sync::mpsc;
-            let (tun_tx, mut tun_rx) = std::sync::mpsc::channel();
-            let tun2socks_path = tun2socks_path.clone();
-            #[cfg(all(feature = "inbound-tun", any(target_os = "linux",)))]
-            {
-                // ip tuntap del mode tun dev utun233
-                let _ = Command::new("ip")
-                    .arg("tuntap")
-                    .arg("del")
-                    .arg("mode")
-                    .arg("tun")
-                    // flag.StringVar(&key.LogLevel, "loglevel", "info", "Log level [debug|info|warning|error|silent]")
-                    .arg("dev")
-                    .arg("utun233")
-                    .status()
-                    .expect("failed to execute process");
-                // ip tuntap add mode tun dev utun233
-                let _ = Command::new("ip")
-                    .arg("tuntap")
-                    .arg("add")
-                    .arg("mode")
-                    .arg("tun")
-                    // flag.StringVar(&key.LogLevel, "loglevel", "info", "Log level [debug|info|warning|error|silent]")
-                    .arg("dev")
-                    .arg("utun233")
-                    .status()
-                    .expect("failed to execute process");
-                // ip addr add 172.7.0.2 dev utun233
-                let _ = Command::new("ip")
-                    .arg("addr")
-                    .arg("add")
-                    .arg(&*option::DEFAULT_TUN_IPV4_ADDR)
-                    // flag.StringVar(&key.LogLevel, "loglevel", "info", "Log level [debug|info|warning|error|silent]")
-                    .arg("dev")
-                    .arg("utun233")
-                    .status()
-                    .expect("failed to execute process");
-                // ip link set dev utun233 up
-                let _ = Command::new("ip")
-                    .arg("link")
-                    .arg("set")
-                    // flag.StringVar(&key.LogLevel, "loglevel", "info", "Log level [debug|info|warning|error|silent]")
-                    .arg("dev")
-                    .arg("utun233")
-                    .arg("up")
-                    .status()
-                    .expect("failed to execute process");
-                std::thread::sleep(std::time::Duration::from_secs(3));
-                log::warn!("tun device is up");
-            }
-
-            std::thread::spawn(move || {
-                let _ = Command::new(tun2socks_path.as_str())
-                    .arg("-device")
-                    .arg("tun://utun233")
-                    .arg("-proxy")
-                    .arg("socks5://127.0.0.1:1086")
-                    // flag.StringVar(&key.LogLevel, "loglevel", "info", "Log level [debug|info|warning|error|silent]")
-                    .arg("-loglevel")
-                    .arg("debug")
-                    .spawn()
-                    .expect("failed to execute process");
-                println!("init tun device process finished");
-                if let Err(e) = tun_tx.send(()) {
-                    log::warn!("tun device completed signal failed: {}", e);
-                }
-            });
-            let _ = tun_rx.recv().unwrap();
-            #[cfg(all(feature = "inbound-tun", any(target_os = "macos",)))]
-            {
-                // ifconfig utun233 172.7.0.2 172.7.0.2 up
-                let _ = Command::new("ifconfig")
-                    .arg("utun233")
-                    .arg("172.7.0.2")
-                    .arg("172.7.0.2")
-                    .arg("up")
-                    // flag.StringVar(&key.LogLevel, "loglevel", "info", "Log level [debug|info|warning|error|silent]")
-                    .status()
-                    .expect("failed to execute process");
-            }
-            'netif: loop {
-                use local_ip_address::list_afinet_netifas;
-                std::thread::sleep(std::time::Duration::from_millis(500));
-                let network_interfaces = list_afinet_netifas().unwrap();
-
-                for (name, _g) in network_interfaces.iter() {
-                    if name == "utun233" {
-                        println!("tun device up");
-                        break 'netif;
-                    }
+        let tun_ready = if tun_supplied(inbounds) {
+            log::info!("using the supplied tun device");
+            true
+        } else {
+            match setup_tun_device(&tun2socks_path, &tun_device) {
+                Ok(_) => true,
+                Err(e) => {
+                    log::error!(
+                        "setting up tun device failed, continue without tun inbound: {}",
+                        e
+                    );
+                    false
                 }
             }
-        }
+        };
         let mut network_listeners: IndexMap<String, NetworkInboundListener> = IndexMap::new();
 
         #[cfg(all(
@@ -310,10 +739,16 @@ impl InboundManager {
                     )
                 ))]
                 "tun" => {
+                    readiness.set_tun(false);
+                    if !tun_ready {
+                        log::warn!("tun inbound [{}] disabled", &tag);
+                        continue;
+                    }
                     let listener = TunInboundListener {
                         inbound: inbound.clone(),
                         dispatcher: dispatcher.clone(),
                         nat_manager: nat_manager.clone(),
+                        traffic: tun_traffic.clone(),
                     };
                     tun_listener.replace(listener);
                     let settings =
@@ -323,12 +758,43 @@ impl InboundManager {
                 _ => {
                     if inbound.port != 0 {
                         if let Some(h) = handlers.get(&tag) {
+                            let (udp_address, credentials) = match inbound.protocol.as_str() {
+                                "socks" => (socks_udp_relay(inbound)?, socks_credentials(inbound)?),
+                                _ => (None, None),
+                            };
+                            // The control connection tells clients where the
+                            // relay is, and checks the credentials. The relay
+                            // only serves clients which logged in if there are.
+                            let handler: AnyInboundHandler =
+                                if udp_address.is_some() || credentials.is_some() {
+                                    let associations =
+                                        Arc::new(socks::inbound::UdpAssociations::default());
+                                    let relay_associations =
+                                        credentials.as_ref().map(|_| associations.clone());
+                                    Arc::new(proxy::inbound::Handler::new(
+                                        tag.clone(),
+                                        Some(Arc::new(socks::inbound::StreamHandler::new(
+                                            udp_address,
+                                            credentials,
+                                            associations,
+                                        ))),
+                                        Some(Arc::new(socks::inbound::DatagramHandler::new(
+                                            relay_associations,
+                                        ))),
+                                    ))
+                                } else {
+                                    h.clone()
+                                };
                             let listener = NetworkInboundListener {
                                 address: inbound.address.clone(),
                                 port: inbound.port as u16,
-                                handler: h.clone(),
+                                interface: inbound.interface.clone(),
+                                handler,
                                 dispatcher: dispatcher.clone(),
                                 nat_manager: nat_manager.clone(),
+                                accept_proxy_protocol: inbound.accept_proxy_protocol,
+                                udp_address,
+                                reuse_port: inbound.reuse_port,
                             };
                             network_listeners.insert(tag.clone(), listener);
                         }
@@ -352,13 +818,24 @@ impl InboundManager {
             #[cfg(all(feature = "inbound-tun", any(target_os = "windows",)))]
             tun2socks_process,
             tun_auto,
+            tun_device,
+            readiness,
+            tun_traffic,
         })
     }
 
     pub fn get_network_runners(&self) -> Result<Vec<Runner>> {
         let mut runners: Vec<Runner> = Vec::new();
-        for (_, listener) in self.network_listeners.iter() {
-            runners.append(&mut listener.listen()?);
+        for (tag, listener) in self.network_listeners.iter() {
+            for runner in listener.listen()? {
+                let readiness = self.readiness.clone();
+                let tag = tag.clone();
+                runners.push(Box::pin(async move {
+                    runner.await;
+                    readiness.set_listener(&tag, false);
+                }));
+            }
+            self.readiness.set_listener(tag, true);
         }
         Ok(runners)
     }
@@ -374,7 +851,13 @@ impl InboundManager {
     ))]
     pub fn get_tun_runner(&self) -> Result<Runner> {
         if let Some(listener) = &self.tun_listener {
-            return listener.listen();
+            let runner = listener.listen()?;
+            let readiness = self.readiness.clone();
+            readiness.set_tun(true);
+            return Ok(Box::pin(async move {
+                runner.await;
+                readiness.set_tun(false);
+            }));
         }
         Err(anyhow!("no tun inbound"))
     }
@@ -395,4 +878,126 @@ impl InboundManager {
     pub fn tun_auto(&self) -> bool {
         self.tun_auto
     }
+
+    /// The tun device tun2socks runs on.
+    pub fn tun_device(&self) -> &TunDevice {
+        &self.tun_device
+    }
+
+    /// The readiness of the inbounds, kept up to date by their runners.
+    pub fn readiness(&self) -> Arc<InboundReadiness> {
+        self.readiness.clone()
+    }
+
+    /// The counters of the TUN device, kept up to date by its runner.
+    pub fn tun_traffic(&self) -> Arc<TunTraffic> {
+        self.tun_traffic.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tun_device_dns() {
+        let servers = vec!["2606:4700:4700::1111".to_string(), "1.1.1.1".to_string()];
+        let tun = |dns: &str| {
+            let mut settings = config::TunInboundSettings::new();
+            settings.dns = dns.to_string();
+            let mut inbound = config::Inbound::new();
+            inbound.protocol = "tun".to_string();
+            inbound.settings = settings.write_to_bytes().unwrap();
+            vec![inbound]
+        };
+        let device = TunDevice::from_inbounds(&tun(""), &servers).unwrap();
+        assert_eq!(device.dns.as_deref(), Some("1.1.1.1"));
+        let device = TunDevice::from_inbounds(&tun(""), &servers[..1]).unwrap();
+        assert!(device.dns.is_none());
+        let device = TunDevice::from_inbounds(&tun("system"), &servers).unwrap();
+        assert!(device.dns.is_none());
+        let device = TunDevice::from_inbounds(&tun("9.9.9.9"), &servers).unwrap();
+        assert_eq!(device.dns.as_deref(), Some("9.9.9.9"));
+        assert!(TunDevice::from_inbounds(&tun("dns.example.com"), &servers).is_err());
+    }
+
+    #[test]
+    fn test_tun_device_from_inbounds() {
+        let tun = |name: &str, address: &str, netmask: &str| {
+            let mut settings = config::TunInboundSettings::new();
+            settings.name = name.to_string();
+            settings.address = address.to_string();
+            settings.netmask = netmask.to_string();
+            let mut inbound = config::Inbound::new();
+            inbound.protocol = "tun".to_string();
+            inbound.settings = settings.write_to_bytes().unwrap();
+            vec![inbound]
+        };
+        let device = TunDevice::from_inbounds(&[], &[]).unwrap();
+        assert_eq!(device.name, *crate::option::DEFAULT_TUN_NAME);
+        assert_eq!(device.address, *crate::option::DEFAULT_TUN_IPV4_ADDR);
+        assert_eq!(device.gateway, *crate::option::DEFAULT_TUN_IPV4_GW);
+        assert_eq!(device.netmask, *crate::option::DEFAULT_TUN_IPV4_MASK);
+        assert_eq!(device.ipv6_address, *crate::option::DEFAULT_TUN_IPV6_ADDR);
+
+        let device =
+            TunDevice::from_inbounds(&tun("utun9", "10.9.0.2", "255.255.0.0"), &[]).unwrap();
+        assert_eq!(device.name, "utun9");
+        assert_eq!(device.address, "10.9.0.2");
+        assert_eq!(device.gateway, *crate::option::DEFAULT_TUN_IPV4_GW);
+        assert_eq!(device.netmask, "255.255.0.0");
+        assert_eq!(device.prefix_len(), 16);
+
+        assert!(TunDevice::from_inbounds(&tun("", "10.9.0.300", ""), &[]).is_err());
+        assert!(TunDevice::from_inbounds(&tun("", "", "255.0.255.0"), &[]).is_err());
+    }
+
+    #[test]
+    fn test_readiness() {
+        let readiness = InboundReadiness::new(None);
+        assert!(readiness.health().ready());
+        readiness.set_listener("socks", false);
+        readiness.set_listener("http", true);
+        assert!(!readiness.health().ready());
+        readiness.set_listener("socks", true);
+        let health = readiness.health();
+        assert!(health.ready());
+        assert_eq!(
+            health.listeners,
+            vec![("socks".to_string(), true), ("http".to_string(), true)]
+        );
+        assert!(health.tun.is_none());
+        readiness.set_tun(false);
+        assert!(!readiness.health().ready());
+        readiness.set_tun(true);
+        assert!(readiness.health().ready());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_readiness_tun_down() {
+        let readiness = InboundReadiness::new(Some("nope0".to_string()));
+        readiness.set_tun(true);
+        assert_eq!(readiness.health().tun, Some(false));
+        let readiness = InboundReadiness::new(Some("lo".to_string()));
+        readiness.set_tun(true);
+        assert_eq!(readiness.health().tun, Some(true));
+    }
+
+    #[test]
+    fn test_tun_traffic() {
+        // Each runtime counts its own device.
+        let traffic = TunTraffic::default();
+        let other = TunTraffic::default();
+        traffic.record_rx(100);
+        traffic.record_rx(60);
+        traffic.record_tx(40);
+        traffic.record_dropped();
+        let c = traffic.counters();
+        assert_eq!((c.rx_packets, c.rx_bytes), (2, 160));
+        assert_eq!((c.tx_packets, c.tx_bytes), (1, 40));
+        assert_eq!(c.dropped_packets, 1);
+        let c = other.counters();
+        assert_eq!((c.rx_packets, c.tx_packets, c.dropped_packets), (0, 0, 0));
+    }
 }
@@ -1,4 +1,4 @@
-mod network_listener;
+pub(crate) mod network_listener;
 
 #[cfg(all(
     feature = "inbound-tun",
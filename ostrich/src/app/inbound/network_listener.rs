@@ -120,15 +120,28 @@ async fn handle_inbound_transport(
 }
 
 // Handle an accepted inbound TCP stream.
-async fn handle_inbound_tcp_stream(
-    stream: TcpStream,
+pub(crate) async fn handle_inbound_tcp_stream(
+    mut stream: TcpStream,
     handler: AnyInboundHandler,
     dispatcher: Arc<Dispatcher>,
     nat_manager: Arc<NatManager>,
+    accept_proxy_protocol: bool,
 ) -> io::Result<()> {
-    let source = stream
+    let mut source = stream
         .peer_addr()
         .unwrap_or_else(|_| *crate::option::UNSPECIFIED_BIND_ADDR);
+    // The stream is relayed by a load balancer, which tells the address of
+    // the original client in a PROXY protocol header.
+    if accept_proxy_protocol {
+        if let Some(addr) = timeout(
+            Duration::from_secs(*crate::option::INBOUND_ACCEPT_TIMEOUT),
+            crate::common::proxy_protocol::read_header(&mut stream),
+        )
+        .await??
+        {
+            source = addr;
+        }
+    }
     let local_addr = stream
         .local_addr()
         .unwrap_or_else(|_| *crate::option::UNSPECIFIED_BIND_ADDR);
@@ -151,13 +164,12 @@ async fn handle_inbound_tcp_stream(
 
 // Handle inbounds which listen on TCP.
 async fn handle_tcp_listen(
-    listen_addr: SocketAddr,
+    listener: crate::proxy::TcpListener,
     handler: AnyInboundHandler,
     dispatcher: Arc<Dispatcher>,
     nat_manager: Arc<NatManager>,
+    accept_proxy_protocol: bool,
 ) -> io::Result<()> {
-    let listener = crate::proxy::TcpListener::bind(&listen_addr).await?;
-    info!("listening tcp {}", &listen_addr);
     loop {
         let (stream, _) = listener.accept().await?;
         let handler_cloned = handler.clone();
@@ -170,6 +182,7 @@ async fn handle_tcp_listen(
                 handler_cloned,
                 dispatcher_cloned,
                 nat_manager_cloned,
+                accept_proxy_protocol,
             )
             .await
             {
@@ -181,13 +194,11 @@ async fn handle_tcp_listen(
 
 // Handle inbounds which bind on UDP.
 async fn handle_udp_listen(
-    listen_addr: SocketAddr,
+    socket: UdpSocket,
     handler: AnyInboundHandler,
     dispatcher: Arc<Dispatcher>,
     nat_manager: Arc<NatManager>,
 ) -> io::Result<()> {
-    let socket = UdpSocket::bind(&listen_addr).await?;
-    info!("listening udp {}", &listen_addr);
     // Transforms the UDP socket into an inbound transport.
     let transport = handler
         .datagram()?
@@ -197,30 +208,66 @@ async fn handle_udp_listen(
     Ok(())
 }
 
+/// An inbound failed to bind its address.
+#[derive(Debug, thiserror::Error)]
+#[error("bind {addr} failed: {source}")]
+pub struct BindError {
+    pub addr: SocketAddr,
+    pub source: io::Error,
+}
+
 pub struct NetworkInboundListener {
     pub address: String,
     pub port: u16,
+    // Accepts traffic arriving on this interface only if not empty.
+    pub interface: String,
     pub handler: AnyInboundHandler,
     pub dispatcher: Arc<Dispatcher>,
     pub nat_manager: Arc<NatManager>,
+    // Expects a PROXY protocol header in front of each TCP stream.
+    pub accept_proxy_protocol: bool,
+    // Binds UDP here instead of the address and port of TCP if present.
+    pub udp_address: Option<SocketAddr>,
+    // Lets other listeners, e.g. of other processes, bind the same port.
+    pub reuse_port: bool,
 }
 
 impl NetworkInboundListener {
+    fn log_listening(&self, network: &str, listen_addr: &SocketAddr) {
+        if self.interface.is_empty() {
+            info!("listening {} {}", network, listen_addr);
+        } else {
+            info!(
+                "listening {} {} on {}",
+                network, listen_addr, &self.interface
+            );
+        }
+    }
+
+    /// Binds the address right away, so that a failure is reported here
+    /// rather than by the runners, which serve the bound sockets. Must be
+    /// called within the context of a runtime.
     pub fn listen(&self) -> Result<Vec<Runner>> {
         let mut runners: Vec<Runner> = Vec::new();
         let listen_addr = SocketAddr::new(self.address.parse()?, self.port);
+        let bind_error = |addr| move |source| BindError { addr, source };
         // Check whether this inbound listens on TCP.
         if self.handler.stream().is_ok() {
-            let listen_addr_cloned = listen_addr.clone();
+            let listener =
+                crate::proxy::TcpListener::bind_now(&listen_addr, &self.interface, self.reuse_port)
+                    .map_err(bind_error(listen_addr))?;
+            self.log_listening("tcp", &listen_addr);
             let handler_cloned = self.handler.clone();
             let dispatcher_cloned = self.dispatcher.clone();
             let nat_manager_cloned = self.nat_manager.clone();
+            let accept_proxy_protocol = self.accept_proxy_protocol;
             runners.push(Box::pin(async move {
                 if let Err(e) = handle_tcp_listen(
-                    listen_addr_cloned,
+                    listener,
                     handler_cloned,
                     dispatcher_cloned,
                     nat_manager_cloned,
+                    accept_proxy_protocol,
                 )
                 .await
                 {
@@ -230,13 +277,17 @@ impl NetworkInboundListener {
         }
         // Check whether this inbound binds on UDP.
         if self.handler.datagram().is_ok() {
-            let listen_addr_cloned = listen_addr.clone();
+            let udp_addr = self.udp_address.unwrap_or(listen_addr);
+            let socket =
+                crate::proxy::bind_udp_socket_now(&udp_addr, &self.interface, self.reuse_port)
+                    .map_err(bind_error(udp_addr))?;
+            self.log_listening("udp", &udp_addr);
             let handler_cloned = self.handler.clone();
             let dispatcher_cloned = self.dispatcher.clone();
             let nat_manager_cloned = self.nat_manager.clone();
             runners.push(Box::pin(async move {
                 if let Err(e) = handle_udp_listen(
-                    listen_addr_cloned,
+                    socket,
                     handler_cloned,
                     dispatcher_cloned,
                     nat_manager_cloned,
@@ -3,6 +3,7 @@ use std::sync::Arc;
 use anyhow::Result;
 
 use crate::app::dispatcher::Dispatcher;
+use crate::app::inbound::manager::TunTraffic;
 use crate::app::nat_manager::NatManager;
 use crate::config::Inbound;
 use crate::proxy::tun;
@@ -12,6 +13,7 @@ pub struct TunInboundListener {
     pub inbound: Inbound,
     pub dispatcher: Arc<Dispatcher>,
     pub nat_manager: Arc<NatManager>,
+    pub traffic: Arc<TunTraffic>,
 }
 
 impl TunInboundListener {
@@ -20,6 +22,7 @@ impl TunInboundListener {
             self.inbound.clone(),
             self.dispatcher.clone(),
             self.nat_manager.clone(),
+            self.traffic.clone(),
         )
     }
 }
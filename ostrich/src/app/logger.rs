@@ -5,12 +5,153 @@ use log4rs::append::file::FileAppender;
 use log4rs::append::{console::ConsoleAppender, Append};
 use log4rs::config::{Appender, Config, Root};
 use log4rs::encode::pattern::PatternEncoder;
+use log4rs::filter::threshold::ThresholdFilter;
 use log4rs::Handle;
 
 use crate::config;
 
+pub use ring::{recent_logs, LogLine};
+
 static HANDLE: Mutex<Option<Handle>> = Mutex::new(None);
 
+mod ring {
+    use super::*;
+    use log4rs::encode::writer::simple::SimpleWriter;
+    use log4rs::encode::Encode;
+    use std::collections::VecDeque;
+
+    #[derive(Debug, Clone)]
+    pub struct LogLine {
+        pub level: log::Level,
+        pub message: String,
+    }
+
+    struct Ring {
+        lines: VecDeque<LogLine>,
+        size: usize,
+    }
+
+    impl Ring {
+        fn set_size(&mut self, size: usize) {
+            self.size = size;
+            while self.lines.len() > size {
+                self.lines.pop_front();
+            }
+        }
+
+        fn push(&mut self, line: LogLine) {
+            if self.lines.len() >= self.size {
+                self.lines.pop_front();
+            }
+            self.lines.push_back(line);
+        }
+
+        fn recent(&self, level: log::LevelFilter, limit: usize) -> Vec<LogLine> {
+            let mut lines: Vec<LogLine> = self
+                .lines
+                .iter()
+                .rev()
+                .filter(|l| l.level <= level)
+                .take(limit)
+                .cloned()
+                .collect();
+            lines.reverse();
+            lines
+        }
+    }
+
+    static RING: Mutex<Option<Ring>> = Mutex::new(None);
+
+    /// Keeps at most `size` lines, 0 drops the ring.
+    pub(super) fn resize(size: usize) {
+        let mut ring = RING.lock().unwrap();
+        if size == 0 {
+            *ring = None;
+            return;
+        }
+        ring.get_or_insert_with(|| Ring {
+            lines: VecDeque::new(),
+            size,
+        })
+        .set_size(size);
+    }
+
+    /// Returns the latest `limit` lines at `level` or more severe, oldest first.
+    pub fn recent_logs(level: log::LevelFilter, limit: usize) -> Vec<LogLine> {
+        match RING.lock().unwrap().as_ref() {
+            Some(ring) => ring.recent(level, limit),
+            None => Vec::new(),
+        }
+    }
+
+    #[derive(Debug)]
+    pub(super) struct RingAppender {
+        encoder: Box<dyn Encode>,
+    }
+
+    impl RingAppender {
+        pub fn new() -> Self {
+            RingAppender {
+                encoder: Box::new(PatternEncoder::new("[{d(%Y-%m-%d %H:%M:%S)}][{l}] {m}")),
+            }
+        }
+    }
+
+    impl log4rs::append::Append for RingAppender {
+        fn append(&self, record: &log::Record<'_>) -> Result<()> {
+            let mut w = SimpleWriter(Vec::new());
+            self.encoder.encode(&mut w, record)?;
+            let mut ring = RING.lock().unwrap();
+            if let Some(ring) = ring.as_mut() {
+                ring.push(LogLine {
+                    level: record.level(),
+                    message: String::from_utf8_lossy(&w.0).into_owned(),
+                });
+            }
+            Ok(())
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use log::{Level, LevelFilter};
+
+        #[test]
+        fn test_ring() {
+            let mut ring = Ring {
+                lines: VecDeque::new(),
+                size: 3,
+            };
+            for (level, message) in [
+                (Level::Info, "a"),
+                (Level::Debug, "b"),
+                (Level::Warn, "c"),
+                (Level::Error, "d"),
+            ] {
+                ring.push(LogLine {
+                    level,
+                    message: message.to_string(),
+                });
+            }
+            let messages = |lines: Vec<LogLine>| -> Vec<String> {
+                lines.into_iter().map(|l| l.message).collect()
+            };
+            // The oldest line is dropped.
+            assert_eq!(
+                messages(ring.recent(LevelFilter::Trace, 10)),
+                ["b", "c", "d"]
+            );
+            assert_eq!(messages(ring.recent(LevelFilter::Info, 10)), ["c", "d"]);
+            assert_eq!(messages(ring.recent(LevelFilter::Trace, 2)), ["c", "d"]);
+            ring.set_size(1);
+            assert_eq!(messages(ring.recent(LevelFilter::Trace, 10)), ["d"]);
+        }
+    }
+}
+
 #[cfg(any(target_os = "ios", target_os = "android", target_os = "macos"))]
 mod mobile {
     use super::*;
@@ -55,6 +196,224 @@ mod mobile {
     }
 }
 
+#[cfg(unix)]
+mod syslog_out {
+    use super::*;
+    use log4rs::encode::writer::simple::SimpleWriter;
+    use log4rs::encode::Encode;
+    use std::str::FromStr;
+    use syslog::{Facility, Formatter3164, Logger, LoggerBackend};
+
+    pub(crate) struct SyslogAppender {
+        logger: Mutex<Logger<LoggerBackend, Formatter3164>>,
+        encoder: Box<dyn Encode>,
+    }
+
+    impl std::fmt::Debug for SyslogAppender {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("SyslogAppender").finish()
+        }
+    }
+
+    impl SyslogAppender {
+        pub fn new(tag: &str, facility: &str) -> Result<Self> {
+            let facility = if facility.is_empty() {
+                Facility::LOG_DAEMON
+            } else {
+                Facility::from_str(facility)
+                    .map_err(|_| anyhow!("invalid syslog facility: {}", facility))?
+            };
+            let process = if tag.is_empty() { "ostrich" } else { tag };
+            let formatter = Formatter3164 {
+                facility,
+                hostname: None,
+                process: process.to_string(),
+                pid: std::process::id(),
+            };
+            let logger =
+                syslog::unix(formatter).map_err(|e| anyhow!("connect syslog failed: {}", e))?;
+            Ok(SyslogAppender {
+                logger: Mutex::new(logger),
+                // Syslog records its own timestamp and severity.
+                encoder: Box::new(PatternEncoder::new("{m}")),
+            })
+        }
+    }
+
+    impl log4rs::append::Append for SyslogAppender {
+        fn append(&self, record: &log::Record<'_>) -> Result<()> {
+            let mut w = SimpleWriter(Vec::new());
+            self.encoder.encode(&mut w, record)?;
+            let msg = String::from_utf8_lossy(&w.0);
+            let mut logger = self.logger.lock().unwrap();
+            match record.level() {
+                log::Level::Error => logger.err(msg),
+                log::Level::Warn => logger.warning(msg),
+                log::Level::Info => logger.info(msg),
+                log::Level::Debug | log::Level::Trace => logger.debug(msg),
+            }
+            .map_err(|e| anyhow!("write syslog failed: {}", e))
+        }
+
+        fn flush(&self) {}
+    }
+}
+
+mod daily {
+    use super::*;
+    use chrono::{DateTime, Local, NaiveDate};
+    use log4rs::encode::writer::simple::SimpleWriter;
+    use log4rs::encode::Encode;
+    use std::fs::{self, File, OpenOptions};
+    use std::io::{BufWriter, Write};
+    use std::path::{Path, PathBuf};
+
+    #[derive(Debug)]
+    struct Current {
+        file: BufWriter<File>,
+        // The day the records in the file are of.
+        date: NaiveDate,
+    }
+
+    /// Appends to the file until the first record of a new day, which moves
+    /// the file to `<path>.<date>` and starts a new one. Only the latest
+    /// LOG_ROTATION_KEEP rotated files are kept.
+    #[derive(Debug)]
+    pub(super) struct DailyFileAppender {
+        path: PathBuf,
+        encoder: Box<dyn Encode>,
+        current: Mutex<Current>,
+    }
+
+    fn open(path: &Path) -> std::io::Result<BufWriter<File>> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(BufWriter::new(file))
+    }
+
+    // Returns the rotated files of the path with their dates, oldest first.
+    fn rotated_files(path: &Path) -> Vec<(NaiveDate, PathBuf)> {
+        let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+            return Vec::new();
+        };
+        let dir = if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir
+        };
+        let prefix = format!("{}.", name.to_string_lossy());
+        let mut files: Vec<(NaiveDate, PathBuf)> = fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .flatten()
+            .filter_map(|entry| {
+                let file_name = entry.file_name();
+                let date = file_name.to_str()?.strip_prefix(&prefix)?;
+                let date = NaiveDate::parse_from_str(date, "%Y-%m-%d").ok()?;
+                Some((date, entry.path()))
+            })
+            .collect();
+        files.sort();
+        files
+    }
+
+    impl DailyFileAppender {
+        pub fn new(path: &str, encoder: Box<dyn Encode>) -> Result<Self> {
+            let path = PathBuf::from(path);
+            if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+                fs::create_dir_all(dir)
+                    .map_err(|e| anyhow!("create log directory {} failed: {}", dir.display(), e))?;
+            }
+            let file = open(&path)
+                .map_err(|e| anyhow!("open log file {} failed: {}", path.display(), e))?;
+            // A file left by a previous run may be of an earlier day.
+            let date = fs::metadata(&path)
+                .and_then(|m| m.modified())
+                .map(|t| DateTime::<Local>::from(t).date_naive())
+                .unwrap_or_else(|_| Local::now().date_naive());
+            Ok(DailyFileAppender {
+                path,
+                encoder,
+                current: Mutex::new(Current { file, date }),
+            })
+        }
+
+        fn rotate(&self, current: &mut Current, today: NaiveDate) -> std::io::Result<()> {
+            current.file.flush()?;
+            let rotated = format!(
+                "{}.{}",
+                self.path.display(),
+                current.date.format("%Y-%m-%d")
+            );
+            fs::rename(&self.path, rotated)?;
+            current.file = open(&self.path)?;
+            current.date = today;
+            let files = rotated_files(&self.path);
+            let excess = files
+                .len()
+                .saturating_sub(*crate::option::LOG_ROTATION_KEEP);
+            for (_, path) in files.into_iter().take(excess) {
+                let _ = fs::remove_file(path);
+            }
+            Ok(())
+        }
+    }
+
+    impl log4rs::append::Append for DailyFileAppender {
+        fn append(&self, record: &log::Record<'_>) -> Result<()> {
+            let today = Local::now().date_naive();
+            let mut current = self.current.lock().unwrap();
+            if current.date != today {
+                self.rotate(&mut current, today)?;
+            }
+            self.encoder
+                .encode(&mut SimpleWriter(&mut current.file), record)?;
+            current.file.flush()?;
+            Ok(())
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use log4rs::append::Append;
+
+        #[test]
+        fn test_daily_rotation() {
+            let dir = std::env::temp_dir().join("ostrich_test_log_rotation");
+            let _ = fs::remove_dir_all(&dir);
+            let path = dir.join("ostrich.log");
+            let appender = DailyFileAppender::new(
+                &path.to_string_lossy(),
+                Box::new(PatternEncoder::new("{m}{n}")),
+            )
+            .unwrap();
+            let append = |msg: &str| {
+                appender
+                    .append(
+                        &log::Record::builder()
+                            .args(format_args!("{}", msg))
+                            .level(log::Level::Info)
+                            .build(),
+                    )
+                    .unwrap()
+            };
+            append("today");
+            let yesterday = Local::now().date_naive().pred_opt().unwrap();
+            appender.current.lock().unwrap().date = yesterday;
+            append("next day");
+
+            let rotated = rotated_files(&path);
+            assert_eq!(rotated.len(), 1);
+            assert_eq!(rotated[0].0, yesterday);
+            assert_eq!(fs::read_to_string(&rotated[0].1).unwrap(), "today\n");
+            assert_eq!(fs::read_to_string(&path).unwrap(), "next day\n");
+            fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+}
+
 #[derive(Debug)]
 struct ModuleFilter;
 
@@ -78,20 +437,30 @@ impl log4rs::filter::Filter for ModuleFilter {
     }
 }
 
-pub fn setup_logger(config: &protobuf::MessageField<crate::config::Log>) -> Result<()> {
-    let Some(config) = config.as_ref() else {
-        return Err(anyhow!("empty log config"));
-    };
-    let loglevel = match config.level.unwrap() {
+fn level_filter(level: config::log::Level) -> log::LevelFilter {
+    match level {
         config::log::Level::TRACE => log::LevelFilter::Trace,
         config::log::Level::DEBUG => log::LevelFilter::Debug,
         config::log::Level::INFO => log::LevelFilter::Info,
         config::log::Level::WARN => log::LevelFilter::Warn,
         config::log::Level::ERROR => log::LevelFilter::Error,
+    }
+}
+
+/// Sets up the global logger, or reconfigures it if it's been set up, by a
+/// previous start in the process for example.
+pub fn setup_logger(config: &protobuf::MessageField<crate::config::Log>) -> Result<()> {
+    let Some(config) = config.as_ref() else {
+        return Err(anyhow!("empty log config"));
     };
+    let loglevel = level_filter(config.level.unwrap());
     let mut builder = Config::builder();
     let mut root = Root::builder();
-    let appender = Appender::builder().filter(Box::new(ModuleFilter));
+    // The root level may be lowered for the ring, outputs keep their own.
+    let mut root_level = loglevel;
+    let appender = Appender::builder()
+        .filter(Box::new(ModuleFilter))
+        .filter(Box::new(ThresholdFilter::new(loglevel)));
     let encoder = if *crate::option::LOG_NO_COLOR {
         PatternEncoder::new("[{d(%Y-%m-%d %H:%M:%S)}][{l}] {m}{n}")
     } else {
@@ -133,20 +502,68 @@ pub fn setup_logger(config: &protobuf::MessageField<crate::config::Log>) -> Resu
             root = root.appender("console");
         }
         config::log::Output::FILE => {
-            let file_out = FileAppender::builder()
-                .encoder(Box::new(encoder))
-                .build(&config.output_file)
-                .unwrap();
-            builder = builder.appender(appender.build("file", Box::new(file_out)));
+            let file_out: Box<dyn Append> = match config.rotation.enum_value_or_default() {
+                config::log::Rotation::NONE => Box::new(
+                    FileAppender::builder()
+                        .encoder(Box::new(encoder))
+                        .build(&config.output_file)
+                        .map_err(|e| {
+                            anyhow!("open log file {} failed: {}", &config.output_file, e)
+                        })?,
+                ),
+                config::log::Rotation::DAILY => Box::new(daily::DailyFileAppender::new(
+                    &config.output_file,
+                    Box::new(encoder),
+                )?),
+            };
+            builder = builder.appender(appender.build("file", file_out));
             root = root.appender("file");
         }
+        config::log::Output::SYSLOG => {
+            #[cfg(unix)]
+            {
+                let syslog_out =
+                    syslog_out::SyslogAppender::new(&config.syslog_tag, &config.syslog_facility)?;
+                builder = builder.appender(appender.build("syslog", Box::new(syslog_out)));
+                root = root.appender("syslog");
+            }
+            #[cfg(not(unix))]
+            return Err(anyhow!("syslog output is only supported on unix"));
+        }
+    }
+    #[cfg(unix)]
+    if config.syslog && config.output.unwrap() != config::log::Output::SYSLOG {
+        let syslog_out =
+            syslog_out::SyslogAppender::new(&config.syslog_tag, &config.syslog_facility)?;
+        builder = builder.appender(
+            Appender::builder()
+                .filter(Box::new(ModuleFilter))
+                .filter(Box::new(ThresholdFilter::new(loglevel)))
+                .build("syslog", Box::new(syslog_out)),
+        );
+        root = root.appender("syslog");
+    }
+    ring::resize(config.ring_size as usize);
+    if config.ring_size > 0 {
+        let ring_level = level_filter(config.ring_level.unwrap());
+        builder = builder.appender(
+            Appender::builder()
+                .filter(Box::new(ModuleFilter))
+                .filter(Box::new(ThresholdFilter::new(ring_level)))
+                .build("ring", Box::new(ring::RingAppender::new())),
+        );
+        root = root.appender("ring");
+        root_level = root_level.max(ring_level);
     }
-    let config = builder.build(root.build(loglevel)).unwrap();
+    let config = builder
+        .build(root.build(root_level))
+        .map_err(|e| anyhow!("invalid log config: {}", e))?;
     let mut handle = HANDLE.lock().unwrap();
     if let Some(handle) = handle.as_ref() {
         handle.set_config(config);
     } else {
-        *handle = Some(log4rs::init_config(config).unwrap());
+        *handle =
+            Some(log4rs::init_config(config).map_err(|e| anyhow!("set up logger failed: {}", e))?);
     }
     Ok(())
 }
@@ -2,14 +2,21 @@ use std::sync::Arc;
 
 use tokio::sync::RwLock;
 
+#[cfg(feature = "api")]
+pub mod api;
 pub mod dispatcher;
 pub mod dns_client;
+#[cfg(feature = "dns-over-https")]
+pub mod doh;
 pub mod inbound;
 pub mod logger;
 pub mod nat_manager;
 pub mod outbound;
 pub mod router;
 
+#[cfg(feature = "stat")]
+pub mod stat_manager;
+
 #[cfg(any(
     target_os = "ios",
     target_os = "android",
@@ -19,3 +26,6 @@ pub mod router;
 pub mod fake_dns;
 
 pub type SyncDnsClient = Arc<RwLock<dns_client::DnsClient>>;
+
+#[cfg(feature = "stat")]
+pub type SyncStatManager = Arc<RwLock<stat_manager::StatManager>>;
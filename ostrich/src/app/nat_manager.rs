@@ -1,4 +1,5 @@
 use indexmap::IndexMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant};
 
@@ -42,12 +43,79 @@ impl std::fmt::Display for UdpPacket {
     }
 }
 
-type SessionMap = IndexMap<DatagramSource, (Sender<UdpPacket>, oneshot::Sender<bool>, Instant)>;
+// Datagrams from the same source are routed one by one, and relayed through
+// a session per outbound, so that each of them goes out through the outbound
+// picked by the rules for its own destination. Redirected datagrams get a
+// session of their own, replies on it appear to come from the original
+// destination.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct SessionKey {
+    source: DatagramSource,
+    outbound: String,
+    redirected_from: Option<SocksAddr>,
+}
+
+impl std::fmt::Display for SessionKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} [{}]", self.source, self.outbound)
+    }
+}
+
+// Bytes of uplink datagrams queued but not sent yet.
+#[derive(Clone, Default)]
+struct Buffered {
+    session: Arc<AtomicUsize>,
+    total: Arc<AtomicUsize>,
+}
+
+impl Buffered {
+    // Accounts for a datagram about to be queued, fails if it would exceed
+    // any of the limits. The bytes are given back when the returned
+    // reservation is dropped, whether the datagram was sent or discarded.
+    fn acquire(&self, n: usize) -> Result<Reservation, &'static str> {
+        if self.session.load(Ordering::Relaxed) + n > *option::UDP_SESSION_BUFFER_LIMIT * 1024 {
+            return Err("session buffer limit exceeded");
+        }
+        if self.total.fetch_add(n, Ordering::Relaxed) + n > *option::UDP_BUFFER_LIMIT * 1024 {
+            self.total.fetch_sub(n, Ordering::Relaxed);
+            return Err("global buffer limit exceeded");
+        }
+        self.session.fetch_add(n, Ordering::Relaxed);
+        Ok(Reservation {
+            buffered: self.clone(),
+            n,
+        })
+    }
+
+    fn release(&self, n: usize) {
+        self.session.fetch_sub(n, Ordering::Relaxed);
+        self.total.fetch_sub(n, Ordering::Relaxed);
+    }
+}
+
+// Buffer space held by a queued datagram.
+struct Reservation {
+    buffered: Buffered,
+    n: usize,
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        self.buffered.release(self.n);
+    }
+}
+
+type Queued = (UdpPacket, Reservation);
+
+type SessionMap = IndexMap<SessionKey, (Sender<Queued>, oneshot::Sender<bool>, Instant, Buffered)>;
 
 pub struct NatManager {
     sessions: Arc<Mutex<SessionMap>>,
     dispatcher: Arc<Dispatcher>,
     timeout_check_task: Mutex<Option<BoxFuture<'static, ()>>>,
+    // Bytes queued for all sessions, each runtime has its own manager so the
+    // limit doesn't span runtimes.
+    buffered: Arc<AtomicUsize>,
 }
 
 impl NatManager {
@@ -100,17 +168,37 @@ impl NatManager {
             sessions,
             dispatcher,
             timeout_check_task: Mutex::new(Some(timeout_check_task)),
+            buffered: Arc::new(AtomicUsize::new(0)),
         }
     }
 
+    /// Ends all sessions, returns the number of sessions ended.
+    pub async fn clear(&self) -> usize {
+        let mut sessions = self.sessions.lock().await;
+        let n = sessions.len();
+        for (key, sess) in sessions.drain(..) {
+            if let Err(e) = sess.1.send(true) {
+                debug!("failed to send abort signal on session {}: {}", key, e);
+            }
+        }
+        n
+    }
+
     fn _send<'a>(
         &self,
         guard: &mut MutexGuard<'a, SessionMap>,
-        key: &DatagramSource,
+        key: &SessionKey,
         pkt: UdpPacket,
     ) {
         if let Some(sess) = guard.get_mut(key) {
-            if let Err(err) = sess.0.try_send(pkt) {
+            let reservation = match sess.3.acquire(pkt.data.len()) {
+                Ok(r) => r,
+                Err(e) => {
+                    warn!("dropped uplink packet {} on session {}: {}", &pkt, key, e);
+                    return;
+                }
+            };
+            if let Err(err) = sess.0.try_send((pkt, reservation)) {
                 trace!("send uplink packet failed {}", err);
             }
             sess.2 = Instant::now(); // activity update
@@ -125,16 +213,9 @@ impl NatManager {
         dgram_src: &DatagramSource,
         inbound_tag: &str,
         client_ch_tx: &Sender<UdpPacket>,
-        pkt: UdpPacket,
+        mut pkt: UdpPacket,
     ) {
-        let mut guard = self.sessions.lock().await;
-
-        if guard.contains_key(dgram_src) {
-            self._send(&mut guard, dgram_src, pkt);
-            return;
-        }
-
-        let sess = sess.cloned().unwrap_or(Session {
+        let mut sess = sess.cloned().unwrap_or(Session {
             network: Network::Udp,
             source: dgram_src.address,
             destination: pkt.dst_addr.clone(),
@@ -142,25 +223,55 @@ impl NatManager {
             ..Default::default()
         });
 
-        self.add_session(sess, dgram_src.clone(), client_ch_tx.clone(), &mut guard)
+        self.dispatcher.activity().touch();
+        let destination = sess.destination.clone();
+        let outbound = match self.dispatcher.route(&mut sess).await {
+            Ok(tag) => tag,
+            Err(e) => {
+                debug!("route udp packet {} failed: {}", &pkt, e);
+                return;
+            }
+        };
+        let redirected_from = if sess.destination != destination {
+            Some(std::mem::replace(
+                &mut pkt.dst_addr,
+                sess.destination.clone(),
+            ))
+        } else {
+            None
+        };
+        let key = SessionKey {
+            source: *dgram_src,
+            outbound,
+            redirected_from,
+        };
+
+        let mut guard = self.sessions.lock().await;
+
+        if guard.contains_key(&key) {
+            self._send(&mut guard, &key, pkt);
+            return;
+        }
+
+        self.add_session(sess, key.clone(), client_ch_tx.clone(), &mut guard)
             .await;
 
         debug!(
             "added udp session {} -> {} ({})",
-            &dgram_src,
+            &key,
             &pkt.dst_addr,
             guard.len(),
         );
 
-        self._send(&mut guard, dgram_src, pkt);
+        self._send(&mut guard, &key, pkt);
 
         drop(guard);
     }
 
-    pub async fn add_session<'a>(
+    async fn add_session<'a>(
         &self,
         sess: Session,
-        raddr: DatagramSource,
+        key: SessionKey,
         client_ch_tx: Sender<UdpPacket>,
         guard: &mut MutexGuard<'a, SessionMap>,
     ) {
@@ -173,7 +284,17 @@ impl NatManager {
             mpsc::channel(*crate::option::UDP_UPLINK_CHANNEL_SIZE);
         let (downlink_abort_tx, downlink_abort_rx) = oneshot::channel();
 
-        guard.insert(raddr, (target_ch_tx, downlink_abort_tx, Instant::now()));
+        let raddr = key.source;
+        let outbound = key.outbound.clone();
+        let redirected_from = key.redirected_from.clone();
+        let buffered = Buffered {
+            session: Arc::new(AtomicUsize::new(0)),
+            total: self.buffered.clone(),
+        };
+        guard.insert(
+            key.clone(),
+            (target_ch_tx, downlink_abort_tx, Instant::now(), buffered),
+        );
 
         let dispatcher = self.dispatcher.clone();
         let sessions = self.sessions.clone();
@@ -183,11 +304,11 @@ impl NatManager {
         // TCP stream would block the task.
         tokio::spawn(async move {
             // new socket to communicate with the target.
-            let socket = match dispatcher.dispatch_datagram(sess).await {
+            let socket = match dispatcher.dispatch_datagram_to(sess, outbound).await {
                 Ok(s) => s,
                 Err(e) => {
-                    debug!("dispatch {} failed: {}", &raddr, e);
-                    sessions.lock().await.remove(&raddr);
+                    debug!("dispatch {} failed: {}", &key, e);
+                    sessions.lock().await.remove(&key);
                     return;
                 }
             };
@@ -209,7 +330,7 @@ impl NatManager {
                         Ok((n, addr)) => {
                             let pkt = UdpPacket::new(
                                 (&buf[..n]).to_vec(),
-                                addr.clone(),
+                                redirected_from.clone().unwrap_or_else(|| addr.clone()),
                                 SocksAddr::from(raddr.address),
                             );
                             if let Err(err) = client_ch_tx.send(pkt).await {
@@ -219,11 +340,12 @@ impl NatManager {
                                 );
                                 break;
                             }
+                            dispatcher.activity().touch();
 
                             // activity update
                             {
                                 let mut sessions = sessions.lock().await;
-                                if let Some(sess) = sessions.get_mut(&raddr) {
+                                if let Some(sess) = sessions.get_mut(&key) {
                                     if addr.port() == 53 {
                                         // If the destination port is 53, we assume it's a
                                         // DNS query and set a negative timeout so it will
@@ -239,7 +361,7 @@ impl NatManager {
                         }
                     }
                 }
-                sessions.lock().await.remove(&raddr);
+                sessions.lock().await.remove(&key);
             };
 
             let (downlink_task, downlink_task_handle) = abortable(downlink_task);
@@ -253,7 +375,8 @@ impl NatManager {
 
             // uplink
             tokio::spawn(async move {
-                while let Some(pkt) = target_ch_rx.recv().await {
+                while let Some((pkt, reservation)) = target_ch_rx.recv().await {
+                    drop(reservation);
                     if let Err(e) = target_sock_send.send_to(&pkt.data, &pkt.dst_addr).await {
                         debug!(
                             "Failed to send uplink packets on session {} to {}: {:?}",
@@ -269,3 +392,68 @@ impl NatManager {
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buffered_released_on_broken_uplink() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let buffered = Buffered::default();
+            let (tx, mut rx) = mpsc::channel::<Queued>(8);
+            let addr = SocksAddr::from(("127.0.0.1".parse::<std::net::IpAddr>().unwrap(), 53));
+            for _ in 0..3 {
+                let pkt = UdpPacket::new(vec![0u8; 100], addr.clone(), addr.clone());
+                let r = buffered.acquire(pkt.data.len()).unwrap();
+                tx.try_send((pkt, r)).unwrap();
+            }
+            assert_eq!(buffered.total.load(Ordering::Relaxed), 300);
+
+            // The uplink sends one datagram then breaks, leaving the rest queued.
+            let (_pkt, r) = rx.recv().await.unwrap();
+            drop(r);
+            assert_eq!(buffered.total.load(Ordering::Relaxed), 200);
+            drop(rx);
+            assert_eq!(buffered.total.load(Ordering::Relaxed), 0);
+            assert_eq!(buffered.session.load(Ordering::Relaxed), 0);
+
+            // Datagrams queued to a closed channel are given back too.
+            let pkt = UdpPacket::new(vec![0u8; 100], addr.clone(), addr);
+            let r = buffered.acquire(pkt.data.len()).unwrap();
+            assert!(tx.try_send((pkt, r)).is_err());
+            assert_eq!(buffered.total.load(Ordering::Relaxed), 0);
+        });
+    }
+
+    #[test]
+    fn test_session_per_outbound() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let dispatcher = crate::app::dispatcher::tests::dispatcher(false);
+            let nat_manager = NatManager::new(Arc::new(dispatcher));
+            let (client_tx, _client_rx) = mpsc::channel(8);
+            let dgram_src = DatagramSource::new("127.0.0.1:5000".parse().unwrap(), None);
+            let src = SocksAddr::from(dgram_src.address);
+            for dst in ["127.0.0.1:9", "127.0.0.2:9", "10.0.0.1:9"] {
+                let dst = SocksAddr::from(dst.parse::<std::net::SocketAddr>().unwrap());
+                let pkt = UdpPacket::new(vec![0u8; 10], src.clone(), dst);
+                nat_manager
+                    .send(None, &dgram_src, "test", &client_tx, pkt)
+                    .await;
+            }
+            // Routed one by one, the first two share the session of the
+            // default outbound.
+            let sessions = nat_manager.sessions.lock().await;
+            let outbounds: Vec<&str> = sessions.keys().map(|k| k.outbound.as_str()).collect();
+            assert_eq!(outbounds, ["proxy", "direct"]);
+        });
+    }
+}
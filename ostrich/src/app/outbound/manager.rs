@@ -1,9 +1,15 @@
 use anyhow::{anyhow, Result};
-use futures::future::AbortHandle;
-use indexmap::{map::Values, IndexMap};
+use futures::future::{abortable, AbortHandle};
+use indexmap::{
+    map::{Keys, Values},
+    IndexMap,
+};
 use log::*;
 use protobuf::Message;
+use std::collections::{HashMap, HashSet};
 use std::convert::From;
+use std::sync::Arc;
+use std::time::Duration;
 
 #[cfg(feature = "outbound-direct")]
 use crate::proxy::direct;
@@ -11,9 +17,22 @@ use crate::proxy::direct;
 #[cfg(feature = "outbound-trojan")]
 use crate::proxy::trojan;
 
-use crate::proxy::trojan::outbound::tls::make_config;
+#[cfg(feature = "outbound-shadowsocks")]
+use crate::proxy::shadowsocks;
+
+#[cfg(feature = "outbound-static")]
+use crate::proxy::static_;
+
+#[cfg(feature = "outbound-http2")]
+use crate::proxy::http2;
+
+#[cfg(feature = "outbound-ssh")]
+use crate::proxy::ssh;
+
+use crate::common::tls::TlsConfig;
 use crate::{
     app::SyncDnsClient,
+    common::proxy_protocol,
     config::{self, Outbound},
     proxy::{outbound::HandlerBuilder, *},
 };
@@ -23,7 +42,22 @@ pub struct OutboundManager {
     #[cfg(feature = "plugin")]
     external_handlers: super::plugin::ExternalHandlers,
     default_handler: Option<String>,
-    abort_handles: Vec<AbortHandle>,
+    // The first direct outbound, for sessions matching no rules.
+    direct_handler: Option<String>,
+    // The background tasks of the outbounds, by the tag of the outbound.
+    abort_handles: Vec<(String, AbortHandle)>,
+    // The TLS configs of the trojan outbounds, rebuilt by `reload_tls`.
+    tls_configs: Vec<(String, Arc<TlsConfig>)>,
+    // The configs the handlers are built from, to tell what a reload changes.
+    outbounds: Vec<Outbound>,
+}
+
+impl Drop for OutboundManager {
+    fn drop(&mut self) {
+        for (_, abort_handle) in self.abort_handles.iter() {
+            abort_handle.abort();
+        }
+    }
 }
 
 struct HandlerCacheEntry<'a> {
@@ -31,6 +65,11 @@ struct HandlerCacheEntry<'a> {
     handler: AnyOutboundHandler,
     protocol: &'a str,
     settings: &'a Vec<u8>,
+    proxy_protocol: Option<proxy_protocol::Version>,
+    dscp: Option<u8>,
+    fwmark: Option<u32>,
+    connect_timeout: Option<Duration>,
+    connect_retries: u32,
 }
 
 impl OutboundManager {
@@ -41,7 +80,8 @@ impl OutboundManager {
         handlers: &mut IndexMap<String, AnyOutboundHandler>,
         #[cfg(feature = "plugin")] external_handlers: &mut super::plugin::ExternalHandlers,
         default_handler: &mut Option<String>,
-        abort_handles: &mut Vec<AbortHandle>,
+        abort_handles: &mut Vec<(String, AbortHandle)>,
+        tls_configs: &mut Vec<(String, Arc<TlsConfig>)>,
     ) -> Result<()> {
         // If there are multiple outbounds with the same setting, we would want
         // a shared one to reduce memory usage. This vector is used as a cache for
@@ -58,9 +98,75 @@ impl OutboundManager {
                 debug!("default handler [{}]", &outbound.tag);
             }
 
+            let proxy_protocol = if !outbound.proxy_protocol.is_empty() {
+                Some(
+                    outbound
+                        .proxy_protocol
+                        .parse::<proxy_protocol::Version>()
+                        .map_err(|e| anyhow!("invalid [{}] outbound: {}", &tag, e))?,
+                )
+            } else {
+                None
+            };
+            if proxy_protocol.is_some()
+                && !matches!(
+                    outbound.protocol.as_str(),
+                    "direct" | "trojan" | "shadowsocks"
+                )
+            {
+                return Err(anyhow!(
+                    "invalid [{}] outbound: proxy protocol not supported by {}",
+                    &tag,
+                    &outbound.protocol
+                ));
+            }
+            if outbound.dscp > 63 {
+                return Err(anyhow!(
+                    "invalid [{}] outbound: dscp {} out of range",
+                    &tag,
+                    outbound.dscp
+                ));
+            }
+            let dscp = if outbound.dscp != 0 {
+                Some(outbound.dscp as u8)
+            } else {
+                None
+            };
+            let fwmark = if outbound.fwmark != 0 {
+                Some(outbound.fwmark)
+            } else {
+                None
+            };
+            if fwmark.is_some() && !cfg!(target_os = "linux") {
+                return Err(anyhow!(
+                    "invalid [{}] outbound: fwmark requires Linux",
+                    &tag
+                ));
+            }
+            if outbound.connect_retries > 10 {
+                return Err(anyhow!(
+                    "invalid [{}] outbound: connect retries {} out of range",
+                    &tag,
+                    outbound.connect_retries
+                ));
+            }
+            let connect_timeout = Some(Duration::from_secs(if outbound.connect_timeout != 0 {
+                outbound.connect_timeout as u64
+            } else {
+                *crate::option::OUTBOUND_CONNECT_TIMEOUT
+            }));
+            let connect_retries = outbound.connect_retries;
+
             // Check whether an identical one already exist.
             for e in cached_handlers.iter() {
-                if e.protocol == &outbound.protocol && e.settings == &outbound.settings {
+                if e.protocol == &outbound.protocol
+                    && e.settings == &outbound.settings
+                    && e.proxy_protocol == proxy_protocol
+                    && e.dscp == dscp
+                    && e.fwmark == fwmark
+                    && e.connect_timeout == connect_timeout
+                    && e.connect_retries == connect_retries
+                {
                     trace!("add handler [{}] cloned from [{}]", &tag, &e.tag);
                     handlers.insert(tag.clone(), e.handler.clone());
                     continue 'loop1;
@@ -72,6 +178,11 @@ impl OutboundManager {
                 "direct" => HandlerBuilder::default()
                     .tag(tag.clone())
                     .color(colored::Color::Green)
+                    .proxy_protocol(proxy_protocol)
+                    .dscp(dscp)
+                    .fwmark(fwmark)
+                    .connect_timeout(connect_timeout)
+                    .connect_retries(connect_retries)
                     .stream_handler(Box::new(direct::StreamHandler))
                     .datagram_handler(Box::new(direct::DatagramHandler))
                     .build(),
@@ -80,26 +191,203 @@ impl OutboundManager {
                     let settings =
                         config::TrojanOutboundSettings::parse_from_bytes(&outbound.settings)
                             .map_err(|e| anyhow!("invalid [{}] outbound settings: {}", &tag, e))?;
-                    let server_name = settings.server_name.clone();
+                    // The name for the TLS handshake, the destination's if
+                    // neither is set.
+                    let server_name = if !settings.sni.is_empty() {
+                        settings.sni.clone()
+                    } else {
+                        settings.server_name.clone()
+                    };
+                    if !server_name.is_empty() {
+                        tokio_rustls::rustls::ServerName::try_from(server_name.as_str()).map_err(
+                            |_| anyhow!("invalid [{}] outbound sni: {}", &tag, &server_name),
+                        )?;
+                    }
+                    if settings.allow_insecure {
+                        warn!(
+                            "[{}] accepts any server certificate, connections can be intercepted",
+                            &tag
+                        );
+                    }
+
+                    let tls_config = Arc::new(
+                        TlsConfig::new(&settings)
+                            .map_err(|e| anyhow!("invalid [{}] outbound settings: {}", &tag, e))?,
+                    );
+                    tls_configs.push((tag.clone(), tls_config.clone()));
 
-                    let tls_config = make_config(&settings);
+                    let endpoints = trojan::outbound::Endpoints::new(
+                        &settings.address,
+                        settings.port as u16,
+                        &settings.servers,
+                    )
+                    .map(Arc::new)
+                    .map_err(|e| anyhow!("invalid [{}] outbound settings: {}", &tag, e))?;
 
                     let tcp = Box::new(trojan::outbound::StreamHandler {
-                        address: settings.address.clone(),
-                        port: settings.port as u16,
+                        endpoints: endpoints.clone(),
+                        dns_client: dns_client.clone(),
+                        dscp,
+                        fwmark,
                         password: settings.password.clone(),
 
                         server_name: server_name.clone(),
                         tls_config: tls_config.clone(),
+                        compress: settings.compress,
                     });
                     let udp = Box::new(trojan::outbound::DatagramHandler {
-                        address: settings.address,
-                        port: settings.port as u16,
+                        endpoints,
+                        dns_client: dns_client.clone(),
+                        dscp,
+                        fwmark,
                         password: settings.password,
 
                         server_name: server_name.clone(),
                         tls_config: tls_config.clone(),
                     });
+                    HandlerBuilder::default()
+                        .tag(tag.clone())
+                        .proxy_protocol(proxy_protocol)
+                        .dscp(dscp)
+                        .fwmark(fwmark)
+                        .connect_timeout(connect_timeout)
+                        .connect_retries(connect_retries)
+                        .stream_handler(tcp)
+                        .datagram_handler(udp)
+                        .build()
+                }
+                #[cfg(feature = "outbound-shadowsocks")]
+                "shadowsocks" => {
+                    let settings =
+                        config::ShadowsocksOutboundSettings::parse_from_bytes(&outbound.settings)
+                            .map_err(|e| anyhow!("invalid [{}] outbound settings: {}", &tag, e))?;
+                    if settings.address.is_empty()
+                        || settings.port == 0
+                        || settings.port > u16::MAX as u32
+                    {
+                        return Err(anyhow!("invalid [{}] outbound server address", &tag));
+                    }
+                    let cipher = Arc::new(
+                        shadowsocks::ShadowCipher::new(&settings.method, &settings.password)
+                            .map_err(|e| anyhow!("invalid [{}] outbound settings: {}", &tag, e))?,
+                    );
+                    let tcp = Box::new(shadowsocks::outbound::StreamHandler {
+                        address: settings.address.clone(),
+                        port: settings.port as u16,
+                        cipher: cipher.clone(),
+                    });
+                    let udp = Box::new(shadowsocks::outbound::DatagramHandler {
+                        address: settings.address,
+                        port: settings.port as u16,
+                        cipher,
+                    });
+                    HandlerBuilder::default()
+                        .tag(tag.clone())
+                        .proxy_protocol(proxy_protocol)
+                        .dscp(dscp)
+                        .fwmark(fwmark)
+                        .connect_timeout(connect_timeout)
+                        .connect_retries(connect_retries)
+                        .stream_handler(tcp)
+                        .datagram_handler(udp)
+                        .build()
+                }
+                #[cfg(feature = "outbound-http2")]
+                "http2" => {
+                    let settings =
+                        config::Http2OutboundSettings::parse_from_bytes(&outbound.settings)
+                            .map_err(|e| anyhow!("invalid [{}] outbound settings: {}", &tag, e))?;
+                    let tcp = Box::new(
+                        http2::outbound::StreamHandler::new(
+                            &settings,
+                            dns_client.clone(),
+                            dscp,
+                            fwmark,
+                            connect_timeout,
+                            connect_retries,
+                        )
+                        .map_err(|e| anyhow!("invalid [{}] outbound settings: {}", &tag, e))?,
+                    );
+                    HandlerBuilder::default()
+                        .tag(tag.clone())
+                        .dscp(dscp)
+                        .fwmark(fwmark)
+                        .connect_timeout(connect_timeout)
+                        .connect_retries(connect_retries)
+                        .stream_handler(tcp)
+                        .build()
+                }
+                #[cfg(feature = "outbound-ssh")]
+                "ssh" => {
+                    let settings =
+                        config::SshOutboundSettings::parse_from_bytes(&outbound.settings)
+                            .map_err(|e| anyhow!("invalid [{}] outbound settings: {}", &tag, e))?;
+                    let tcp = Box::new(
+                        ssh::outbound::StreamHandler::new(
+                            &settings,
+                            dns_client.clone(),
+                            dscp,
+                            fwmark,
+                            connect_timeout,
+                            connect_retries,
+                        )
+                        .map_err(|e| anyhow!("invalid [{}] outbound settings: {}", &tag, e))?,
+                    );
+                    HandlerBuilder::default()
+                        .tag(tag.clone())
+                        .dscp(dscp)
+                        .fwmark(fwmark)
+                        .connect_timeout(connect_timeout)
+                        .connect_retries(connect_retries)
+                        .stream_handler(tcp)
+                        .build()
+                }
+                #[cfg(feature = "outbound-static")]
+                "static" => {
+                    let settings =
+                        config::StaticOutboundSettings::parse_from_bytes(&outbound.settings)
+                            .map_err(|e| anyhow!("invalid [{}] outbound settings: {}", &tag, e))?;
+                    let mut actors = Vec::new();
+                    for actor in settings.actors.iter() {
+                        if let Some(a) = handlers.get(actor) {
+                            actors.push(a.clone());
+                        } else {
+                            // Not loaded yet, try again in the next pass.
+                            continue 'loop1;
+                        }
+                    }
+                    if actors.is_empty() {
+                        continue;
+                    }
+                    let selector = Arc::new(
+                        static_::Selector::new(&tag, actors, &settings)
+                            .map_err(|e| anyhow!("invalid [{}] outbound settings: {}", &tag, e))?,
+                    );
+                    if let Some(store) = selector.smart_store() {
+                        let (flush_task, abort_handle) =
+                            abortable(static_::smart::flush_task(store));
+                        tokio::spawn(flush_task);
+                        abort_handles.push((tag.clone(), abort_handle));
+                    }
+                    if let Some((health, interval)) = selector.failover_health() {
+                        let (check_task, abort_handle) =
+                            abortable(static_::failover::health_check_task(
+                                Arc::downgrade(&selector),
+                                health,
+                                interval,
+                                dns_client.clone(),
+                            ));
+                        tokio::spawn(check_task);
+                        abort_handles.push((tag.clone(), abort_handle));
+                    }
+                    let tcp = Box::new(static_::StreamHandler {
+                        selector: selector.clone(),
+                        dns_client: dns_client.clone(),
+                    });
+                    let udp = Box::new(static_::DatagramHandler {
+                        selector,
+                        dns_client: dns_client.clone(),
+                    });
                     HandlerBuilder::default()
                         .tag(tag.clone())
                         .stream_handler(tcp)
@@ -113,6 +401,11 @@ impl OutboundManager {
                 handler: h.clone(),
                 protocol: &outbound.protocol,
                 settings: &outbound.settings,
+                proxy_protocol,
+                dscp,
+                fwmark,
+                connect_timeout,
+                connect_retries,
             });
             trace!("add handler [{}]", &tag);
             handlers.insert(tag, h);
@@ -123,36 +416,291 @@ impl OutboundManager {
         Ok(())
     }
 
+    // Returns the tags of the outbounds a composite outbound refers to.
+    fn outbound_actors(outbound: &Outbound) -> Vec<String> {
+        let actors = match outbound.protocol.as_str() {
+            "tryall" => config::TryAllOutboundSettings::parse_from_bytes(&outbound.settings)
+                .map(|s| s.actors),
+            "static" => config::StaticOutboundSettings::parse_from_bytes(&outbound.settings)
+                .map(|s| s.actors),
+            "amux" => {
+                config::AMuxOutboundSettings::parse_from_bytes(&outbound.settings).map(|s| s.actors)
+            }
+            "chain" => config::ChainOutboundSettings::parse_from_bytes(&outbound.settings)
+                .map(|s| s.actors),
+            "failover" => config::FailOverOutboundSettings::parse_from_bytes(&outbound.settings)
+                .map(|s| s.actors),
+            "select" => config::SelectOutboundSettings::parse_from_bytes(&outbound.settings)
+                .map(|s| s.actors),
+            _ => return Vec::new(),
+        };
+        actors.unwrap_or_default()
+    }
+
+    fn check_cycles(outbounds: &[Outbound]) -> Result<()> {
+        fn visit<'a>(
+            tag: &'a str,
+            graph: &'a IndexMap<&'a str, Vec<String>>,
+            visiting: &mut Vec<&'a str>,
+            visited: &mut HashSet<&'a str>,
+        ) -> Result<()> {
+            if visited.contains(tag) {
+                return Ok(());
+            }
+            if let Some(pos) = visiting.iter().position(|t| *t == tag) {
+                let mut cycle = visiting[pos..].to_vec();
+                cycle.push(tag);
+                return Err(anyhow!("outbound reference cycle: {}", cycle.join(" -> ")));
+            }
+            visiting.push(tag);
+            if let Some(actors) = graph.get(tag) {
+                for actor in actors {
+                    visit(actor, graph, visiting, visited)?;
+                }
+            }
+            visiting.pop();
+            visited.insert(tag);
+            Ok(())
+        }
+
+        let graph: IndexMap<&str, Vec<String>> = outbounds
+            .iter()
+            .map(|o| (o.tag.as_str(), Self::outbound_actors(o)))
+            .collect();
+        let mut visited = HashSet::new();
+        for tag in graph.keys() {
+            visit(tag, &graph, &mut Vec::new(), &mut visited)?;
+        }
+        Ok(())
+    }
+
     pub fn new(outbounds: &Vec<Outbound>, dns_client: SyncDnsClient) -> Result<Self> {
-        let mut handlers: IndexMap<String, AnyOutboundHandler> = IndexMap::new();
-        #[cfg(feature = "plugin")]
-        let mut external_handlers = super::plugin::ExternalHandlers::new();
-        let mut default_handler: Option<String> = None;
-        let mut abort_handles: Vec<AbortHandle> = Vec::new();
-        for _i in 0..4 {
-            Self::load_handlers(
+        let mut m = OutboundManager {
+            handlers: IndexMap::new(),
+            #[cfg(feature = "plugin")]
+            external_handlers: super::plugin::ExternalHandlers::new(),
+            default_handler: None,
+            direct_handler: None,
+            abort_handles: Vec::new(),
+            tls_configs: Vec::new(),
+            outbounds: Vec::new(),
+        };
+        m.reload(outbounds, dns_client)?;
+        Ok(m)
+    }
+
+    // Returns the tags of the outbounds with the same config as the current
+    // ones, and referring only to such outbounds, their handlers can be kept.
+    fn unchanged_tags(&self, outbounds: &[Outbound]) -> HashSet<String> {
+        let old: HashMap<&str, &Outbound> =
+            self.outbounds.iter().map(|o| (o.tag.as_str(), o)).collect();
+        let mut unchanged: HashSet<String> = outbounds
+            .iter()
+            .filter(|o| old.get(o.tag.as_str()) == Some(o) && self.handlers.contains_key(&o.tag))
+            .map(|o| o.tag.clone())
+            .collect();
+        loop {
+            let changed: Vec<&Outbound> = outbounds
+                .iter()
+                .filter(|o| unchanged.contains(&o.tag))
+                .filter(|o| {
+                    Self::outbound_actors(o)
+                        .iter()
+                        .any(|a| !unchanged.contains(a))
+                })
+                .collect();
+            if changed.is_empty() {
+                return unchanged;
+            }
+            for o in changed {
+                unchanged.remove(&o.tag);
+            }
+        }
+    }
+
+    /// Replaces the outbounds with the given ones. The handlers of the
+    /// outbounds not changed, and referring to no changed ones, are kept so
+    /// that sessions on them aren't disrupted. The current outbounds are kept
+    /// if any of the given ones fails to load.
+    pub fn reload(&mut self, outbounds: &Vec<Outbound>, dns_client: SyncDnsClient) -> Result<()> {
+        match &*crate::option::OUTBOUND_FWMARK {
+            Err(e) => return Err(anyhow!("invalid OUTBOUND_FWMARK: {}", e)),
+            Ok(mark) if *mark != 0 && !cfg!(target_os = "linux") => {
+                return Err(anyhow!("OUTBOUND_FWMARK requires Linux"));
+            }
+            _ => {}
+        }
+        Self::check_cycles(outbounds)?;
+        let unchanged = self.unchanged_tags(outbounds);
+        let mut handlers: IndexMap<String, AnyOutboundHandler> = outbounds
+            .iter()
+            .filter(|o| unchanged.contains(&o.tag))
+            .filter_map(|o| Some((o.tag.clone(), self.handlers.get(&o.tag)?.clone())))
+            .collect();
+        let mut default_handler: Option<String> = outbounds.first().map(|o| o.tag.clone());
+        let mut abort_handles: Vec<(String, AbortHandle)> = Vec::new();
+        let mut tls_configs: Vec<(String, Arc<TlsConfig>)> = Vec::new();
+        // Outbounds referring to others can only be loaded after their actors,
+        // keep loading until no more handlers can be added, the number of passes
+        // depends on the depth of the references.
+        let loaded = loop {
+            let n_handlers = handlers.len();
+            if let Err(e) = Self::load_handlers(
                 outbounds,
                 dns_client.clone(),
                 &mut handlers,
                 #[cfg(feature = "plugin")]
-                &mut external_handlers,
+                &mut self.external_handlers,
                 &mut default_handler,
                 &mut abort_handles,
-            )?;
+                &mut tls_configs,
+            ) {
+                break Err(e);
+            }
+            if handlers.len() == n_handlers {
+                break Ok(());
+            }
+        };
+        if let Err(e) = loaded {
+            for (_, abort_handle) in abort_handles.iter() {
+                abort_handle.abort();
+            }
+            return Err(e);
         }
-        Ok(OutboundManager {
-            handlers,
-            #[cfg(feature = "plugin")]
-            external_handlers,
-            default_handler,
-            abort_handles,
-        })
+
+        // The tasks and TLS configs stay with the handlers they belong to, a
+        // handler may be shared by outbounds with identical configs.
+        let kept = |tag: &String| {
+            self.handlers
+                .get(tag)
+                .map_or(false, |h| handlers.values().any(|n| Arc::ptr_eq(h, n)))
+        };
+        for (tag, abort_handle) in self.abort_handles.drain(..) {
+            if kept(&tag) {
+                abort_handles.push((tag, abort_handle));
+            } else {
+                abort_handle.abort();
+            }
+        }
+        tls_configs.extend(self.tls_configs.drain(..).filter(|(tag, _)| kept(tag)));
+
+        self.direct_handler = outbounds
+            .iter()
+            .find(|o| o.protocol == "direct" && handlers.contains_key(&o.tag))
+            .map(|o| o.tag.clone());
+        debug!(
+            "loaded {} outbounds, {} kept",
+            handlers.len(),
+            unchanged.len()
+        );
+        self.handlers = handlers;
+        self.default_handler = default_handler;
+        self.abort_handles = abort_handles;
+        self.tls_configs = tls_configs;
+        self.outbounds = outbounds.clone();
+        Ok(())
     }
 
     pub fn add(&mut self, tag: String, handler: AnyOutboundHandler) {
         self.handlers.insert(tag, handler);
     }
 
+    /// Rebuilds the TLS configs of the trojan outbounds so that updated root
+    /// certificates apply to new connections, returns the number rebuilt.
+    /// Stops at the first failure, the configs not rebuilt are kept.
+    pub fn reload_tls(&self) -> Result<usize> {
+        for (_, c) in self.tls_configs.iter() {
+            c.reload()?;
+        }
+        Ok(self.tls_configs.len())
+    }
+
+    /// Builds the handler of the outbound and adds it, an existing outbound
+    /// with the same tag is an error unless `replace` is set. Outbounds
+    /// referring to others can only refer to the ones already added, and
+    /// those referring to a replaced one keep the old handler until reloaded.
+    pub fn add_outbound(
+        &mut self,
+        outbound: &Outbound,
+        dns_client: SyncDnsClient,
+        replace: bool,
+    ) -> Result<()> {
+        if outbound.tag.is_empty() {
+            return Err(anyhow!("missing outbound tag"));
+        }
+        if !replace && self.handlers.contains_key(&outbound.tag) {
+            return Err(anyhow!("outbound [{}] already exists", &outbound.tag));
+        }
+        let mut handlers = self.handlers.clone();
+        handlers.shift_remove(&outbound.tag);
+        let mut default_handler = self.default_handler.clone();
+        let mut abort_handles: Vec<(String, AbortHandle)> = Vec::new();
+        let mut tls_configs: Vec<(String, Arc<TlsConfig>)> = Vec::new();
+        let handler = Self::load_handlers(
+            &vec![outbound.clone()],
+            dns_client,
+            &mut handlers,
+            #[cfg(feature = "plugin")]
+            &mut self.external_handlers,
+            &mut default_handler,
+            &mut abort_handles,
+            &mut tls_configs,
+        )
+        .and_then(|_| {
+            handlers.shift_remove(&outbound.tag).ok_or_else(|| {
+                anyhow!("unsupported [{}] outbound or missing actors", &outbound.tag)
+            })
+        });
+        let handler = match handler {
+            Ok(handler) => handler,
+            Err(e) => {
+                for (_, abort_handle) in abort_handles.iter() {
+                    abort_handle.abort();
+                }
+                return Err(e);
+            }
+        };
+
+        // The tasks and TLS configs of a replaced handler go with it, unless
+        // another outbound shares the handler.
+        if let Some(old) = self.handlers.get(&outbound.tag) {
+            let sharing = self
+                .handlers
+                .iter()
+                .find(|(tag, h)| *tag != &outbound.tag && Arc::ptr_eq(h, old))
+                .map(|(tag, _)| tag.clone());
+            for (tag, abort_handle) in self.abort_handles.iter_mut() {
+                if tag == &outbound.tag {
+                    match &sharing {
+                        Some(sharing) => *tag = sharing.clone(),
+                        None => abort_handle.abort(),
+                    }
+                }
+            }
+            self.abort_handles.retain(|(tag, _)| tag != &outbound.tag);
+            for (tag, _) in self.tls_configs.iter_mut() {
+                if tag == &outbound.tag {
+                    if let Some(sharing) = &sharing {
+                        *tag = sharing.clone();
+                    }
+                }
+            }
+            self.tls_configs.retain(|(tag, _)| tag != &outbound.tag);
+        }
+        self.abort_handles.append(&mut abort_handles);
+        self.tls_configs.append(&mut tls_configs);
+        self.add(outbound.tag.clone(), handler);
+        if self.default_handler.is_none() {
+            self.default_handler = Some(outbound.tag.clone());
+        }
+        if self.direct_handler.is_none() && outbound.protocol == "direct" {
+            self.direct_handler = Some(outbound.tag.clone());
+        }
+        self.outbounds.retain(|o| o.tag != outbound.tag);
+        self.outbounds.push(outbound.clone());
+        Ok(())
+    }
+
     pub fn get(&self, tag: &str) -> Option<AnyOutboundHandler> {
         self.handlers.get(tag).map(Clone::clone)
     }
@@ -161,11 +709,30 @@ impl OutboundManager {
         self.default_handler.as_ref().map(Clone::clone)
     }
 
+    pub fn direct_handler(&self) -> Option<String> {
+        self.direct_handler.clone()
+    }
+
     pub fn handlers(&self) -> Handlers {
         Handlers {
             inner: self.handlers.values(),
         }
     }
+
+    /// The tags of all outbounds, including the ones sharing the handler of
+    /// an identical outbound, which `handlers` reports by the first tag.
+    pub fn tags(&self) -> Keys<String, AnyOutboundHandler> {
+        self.handlers.keys()
+    }
+
+    /// Drops the connections the handlers keep for later sessions.
+    pub async fn reset_conns(&self) {
+        for h in self.handlers.values() {
+            if let Ok(h) = h.stream() {
+                h.reset().await;
+            }
+        }
+    }
 }
 
 pub struct Handlers<'a> {
@@ -179,3 +746,281 @@ impl<'a> Iterator for Handlers<'a> {
         self.inner.next()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chain(tag: &str, actors: &[&str]) -> Outbound {
+        let mut settings = config::ChainOutboundSettings::new();
+        settings.actors = actors.iter().map(|a| a.to_string()).collect();
+        let mut outbound = Outbound::new();
+        outbound.tag = tag.to_string();
+        outbound.protocol = "chain".to_string();
+        outbound.settings = settings.write_to_bytes().unwrap();
+        outbound
+    }
+
+    #[test]
+    fn test_check_cycles() {
+        let mut direct = Outbound::new();
+        direct.tag = "direct".to_string();
+        direct.protocol = "direct".to_string();
+
+        let outbounds = vec![
+            chain("a", &["b"]),
+            chain("b", &["c"]),
+            chain("c", &["d"]),
+            chain("d", &["direct"]),
+            direct.clone(),
+        ];
+        assert!(OutboundManager::check_cycles(&outbounds).is_ok());
+
+        let outbounds = vec![chain("a", &["b"]), chain("b", &["c"]), chain("c", &["a"]), direct];
+        assert!(OutboundManager::check_cycles(&outbounds).is_err());
+    }
+
+    fn trojan(tag: &str, password: &str) -> Outbound {
+        let mut settings = config::TrojanOutboundSettings::new();
+        settings.address = "127.0.0.1".to_string();
+        settings.port = 443;
+        settings.password = password.to_string();
+        let mut outbound = Outbound::new();
+        outbound.tag = tag.to_string();
+        outbound.protocol = "trojan".to_string();
+        outbound.settings = settings.write_to_bytes().unwrap();
+        outbound
+    }
+
+    fn group(tag: &str, actors: &[&str]) -> Outbound {
+        let mut settings = config::StaticOutboundSettings::new();
+        settings.actors = actors.iter().map(|a| a.to_string()).collect();
+        let mut outbound = Outbound::new();
+        outbound.tag = tag.to_string();
+        outbound.protocol = "static".to_string();
+        outbound.settings = settings.write_to_bytes().unwrap();
+        outbound
+    }
+
+    #[test]
+    fn test_reload() {
+        let dns_client = crate::app::dns_client::tests::dns_client();
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let _g = rt.enter();
+
+        let mut direct = Outbound::new();
+        direct.tag = "Direct".to_string();
+        direct.protocol = "direct".to_string();
+        let outbounds = vec![
+            trojan("A", "pass"),
+            trojan("B", "pass"),
+            group("Group", &["A", "Direct"]),
+            direct.clone(),
+        ];
+        let mut m = OutboundManager::new(&outbounds, dns_client.clone()).unwrap();
+        let old = |tag: &str| m.get(tag).unwrap();
+        let (a, b, group, d) = (old("A"), old("B"), old("Group"), old("Direct"));
+
+        // Changing A rebuilds it and the group referring to it.
+        let outbounds = vec![
+            trojan("A", "new"),
+            trojan("B", "pass"),
+            group("Group", &["A", "Direct"]),
+            direct,
+        ];
+        m.reload(&outbounds, dns_client.clone()).unwrap();
+        assert!(!Arc::ptr_eq(&a, &m.get("A").unwrap()));
+        assert!(!Arc::ptr_eq(&group, &m.get("Group").unwrap()));
+        assert!(Arc::ptr_eq(&d, &m.get("Direct").unwrap()));
+        assert_eq!(m.default_handler(), Some("A".to_string()));
+        assert_eq!(m.direct_handler(), Some("Direct".to_string()));
+        // B shared the handler of A, the TLS config goes with it.
+        assert!(Arc::ptr_eq(&b, &m.get("B").unwrap()));
+        assert_eq!(m.reload_tls().unwrap(), 2);
+        assert!(m.abort_handles.is_empty());
+
+        // A failed reload keeps everything.
+        let a = m.get("A").unwrap();
+        let outbounds = vec![chain("X", &["Y"]), chain("Y", &["X"])];
+        assert!(m.reload(&outbounds, dns_client).is_err());
+        assert!(Arc::ptr_eq(&a, &m.get("A").unwrap()));
+    }
+
+    #[test]
+    fn test_add_outbound() {
+        let dns_client = crate::app::dns_client::tests::dns_client();
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let _g = rt.enter();
+
+        let mut direct = Outbound::new();
+        direct.tag = "Direct".to_string();
+        direct.protocol = "direct".to_string();
+        let mut m = OutboundManager::new(&vec![direct], dns_client.clone()).unwrap();
+
+        // Adding an existing tag takes `replace`.
+        m.add_outbound(&trojan("A", "pass"), dns_client.clone(), false)
+            .unwrap();
+        assert!(m
+            .add_outbound(&trojan("A", "new"), dns_client.clone(), false)
+            .is_err());
+        let a = m.get("A").unwrap();
+        m.add_outbound(&trojan("A", "new"), dns_client.clone(), true)
+            .unwrap();
+        assert!(!Arc::ptr_eq(&a, &m.get("A").unwrap()));
+        assert_eq!(m.tags().collect::<Vec<_>>(), vec!["Direct", "A"]);
+        // The TLS config of the replaced handler is dropped.
+        assert_eq!(m.reload_tls().unwrap(), 1);
+
+        // Replacing a failover group stops the health checks of the old one.
+        let mut failover = group("Group", &["A", "Direct"]);
+        let mut settings =
+            config::StaticOutboundSettings::parse_from_bytes(&failover.settings).unwrap();
+        settings.method = "failover".to_string();
+        failover.settings = settings.write_to_bytes().unwrap();
+        m.add_outbound(&failover, dns_client.clone(), false)
+            .unwrap();
+        assert_eq!(m.abort_handles.len(), 1);
+        let old = m.abort_handles[0].1.clone();
+        m.add_outbound(&failover, dns_client.clone(), true).unwrap();
+        assert!(old.is_aborted());
+        assert_eq!(m.abort_handles.len(), 1);
+        assert!(!m.abort_handles[0].1.is_aborted());
+
+        // Nothing is left of a failed one, actors must be added first.
+        let handles = m.abort_handles.len();
+        let mut missing = failover.clone();
+        missing.tag = "Missing".to_string();
+        let mut settings =
+            config::StaticOutboundSettings::parse_from_bytes(&missing.settings).unwrap();
+        settings.actors = vec!["X".to_string()];
+        missing.settings = settings.write_to_bytes().unwrap();
+        assert!(m.add_outbound(&missing, dns_client, false).is_err());
+        assert!(m.get("Missing").is_none());
+        assert_eq!(m.abort_handles.len(), handles);
+    }
+
+    #[test]
+    fn test_tags() {
+        let dns_client = crate::app::dns_client::tests::dns_client();
+
+        let mut a = Outbound::new();
+        a.tag = "A".to_string();
+        a.protocol = "direct".to_string();
+        let mut b = a.clone();
+        b.tag = "B".to_string();
+        let m = OutboundManager::new(&vec![a, b], dns_client).unwrap();
+        // B shares the handler of A.
+        assert!(m.handlers().all(|h| h.tag() == "A"));
+        assert_eq!(m.tags().collect::<Vec<_>>(), vec!["A", "B"]);
+    }
+
+    #[test]
+    fn test_connect_options() {
+        let dns_client = crate::app::dns_client::tests::dns_client();
+
+        let mut a = Outbound::new();
+        a.tag = "A".to_string();
+        a.protocol = "direct".to_string();
+        let mut b = a.clone();
+        b.tag = "B".to_string();
+        b.connect_timeout = 5;
+        b.connect_retries = 2;
+        let m = OutboundManager::new(&vec![a, b.clone()], dns_client.clone()).unwrap();
+        let (a, h) = (m.get("A").unwrap(), m.get("B").unwrap());
+        assert!(!Arc::ptr_eq(&a, &h));
+        assert_eq!(
+            a.connect_timeout(),
+            Some(Duration::from_secs(
+                *crate::option::OUTBOUND_CONNECT_TIMEOUT
+            ))
+        );
+        assert_eq!(a.connect_retries(), 0);
+        assert_eq!(h.connect_timeout(), Some(Duration::from_secs(5)));
+        assert_eq!(h.connect_retries(), 2);
+
+        b.connect_retries = 11;
+        assert!(OutboundManager::new(&vec![b], dns_client).is_err());
+    }
+
+    #[test]
+    fn test_dscp() {
+        let dns_client = crate::app::dns_client::tests::dns_client();
+
+        let mut a = Outbound::new();
+        a.tag = "A".to_string();
+        a.protocol = "direct".to_string();
+        let mut b = a.clone();
+        b.tag = "B".to_string();
+        b.dscp = 46;
+        let m = OutboundManager::new(&vec![a, b.clone()], dns_client.clone()).unwrap();
+        assert_eq!(m.get("A").unwrap().dscp(), None);
+        assert_eq!(m.get("B").unwrap().dscp(), Some(46));
+
+        b.dscp = 64;
+        assert!(OutboundManager::new(&vec![b], dns_client).is_err());
+    }
+
+    #[cfg(feature = "outbound-static")]
+    #[test]
+    fn test_proxy_protocol() {
+        let dns_client = crate::app::dns_client::tests::dns_client();
+
+        let mut a = Outbound::new();
+        a.tag = "A".to_string();
+        a.protocol = "direct".to_string();
+        a.proxy_protocol = "v2".to_string();
+        let m = OutboundManager::new(&vec![a.clone()], dns_client.clone()).unwrap();
+        assert_eq!(
+            m.get("A").unwrap().proxy_protocol(),
+            Some(proxy_protocol::Version::V2)
+        );
+
+        // Not supported by the static outbound.
+        let mut settings = config::StaticOutboundSettings::new();
+        settings.actors.push("A".to_string());
+        let mut b = Outbound::new();
+        b.tag = "B".to_string();
+        b.protocol = "static".to_string();
+        b.settings = settings.write_to_bytes().unwrap();
+        b.proxy_protocol = "v1".to_string();
+        assert!(OutboundManager::new(&vec![a, b], dns_client).is_err());
+    }
+
+    #[cfg(feature = "outbound-shadowsocks")]
+    #[test]
+    fn test_shadowsocks() {
+        fn shadowsocks(tag: &str, method: &str) -> Outbound {
+            let mut settings = config::ShadowsocksOutboundSettings::new();
+            settings.address = "127.0.0.1".to_string();
+            settings.port = 8388;
+            settings.method = method.to_string();
+            settings.password = "pass".to_string();
+            let mut outbound = Outbound::new();
+            outbound.tag = tag.to_string();
+            outbound.protocol = "shadowsocks".to_string();
+            outbound.settings = settings.write_to_bytes().unwrap();
+            outbound
+        }
+
+        let dns_client = crate::app::dns_client::tests::dns_client();
+
+        // Identical settings share a handler.
+        let outbounds = vec![
+            shadowsocks("A", "aes-256-gcm"),
+            shadowsocks("B", "aes-256-gcm"),
+            shadowsocks("C", "chacha20-ietf-poly1305"),
+        ];
+        let m = OutboundManager::new(&outbounds, dns_client.clone()).unwrap();
+        assert!(Arc::ptr_eq(&m.get("A").unwrap(), &m.get("B").unwrap()));
+        assert!(!Arc::ptr_eq(&m.get("A").unwrap(), &m.get("C").unwrap()));
+
+        let outbounds = vec![shadowsocks("A", "rc4-md5")];
+        assert!(OutboundManager::new(&outbounds, dns_client).is_err());
+    }
+}
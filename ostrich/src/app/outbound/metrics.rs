@@ -0,0 +1,237 @@
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use indexmap::IndexMap;
+
+/// Where an attempt through an outbound failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    /// Connecting to the outbound's server failed.
+    Connect,
+    /// The outbound's own handshake failed.
+    Handshake,
+    /// Either of the above timed out.
+    Timeout,
+}
+
+impl Failure {
+    /// Categorizes an error from the given phase, timeouts are told apart
+    /// whatever the phase.
+    pub fn from_error(phase: Failure, e: &io::Error) -> Self {
+        if e.kind() == io::ErrorKind::TimedOut {
+            Failure::Timeout
+        } else {
+            phase
+        }
+    }
+}
+
+#[derive(Default)]
+struct Counters {
+    attempts: AtomicU64,
+    successes: AtomicU64,
+    connect_failures: AtomicU64,
+    handshake_failures: AtomicU64,
+    timeouts: AtomicU64,
+    consecutive_failures: AtomicU64,
+    // Streams through the outbound not closed yet.
+    active: AtomicU64,
+    // Milliseconds since the epoch of the first success after the latest
+    // failure, 0 if the latest attempt failed.
+    up_since: AtomicU64,
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as u64)
+}
+
+/// Keeps a stream counted as active until dropped.
+pub struct ActiveGuard(Arc<Counters>);
+
+impl Drop for ActiveGuard {
+    fn drop(&mut self) {
+        self.0.active.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// A snapshot of the counters of an outbound.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct OutboundStat {
+    pub tag: String,
+    pub attempts: u64,
+    pub successes: u64,
+    pub connect_failures: u64,
+    pub handshake_failures: u64,
+    pub timeouts: u64,
+    pub consecutive_failures: u64,
+    pub active: u64,
+    /// Since when the attempts have been succeeding, `None` if the latest
+    /// one failed or none was made.
+    pub up_since: Option<SystemTime>,
+}
+
+impl OutboundStat {
+    /// Whether any stream through the outbound is open.
+    pub fn connected(&self) -> bool {
+        self.active > 0
+    }
+
+    /// How long the attempts have been succeeding.
+    pub fn up_for(&self) -> Option<Duration> {
+        self.up_since
+            .map(|t| SystemTime::now().duration_since(t).unwrap_or_default())
+    }
+
+    pub fn failures(&self) -> u64 {
+        self.connect_failures + self.handshake_failures + self.timeouts
+    }
+
+    /// The ratio of successful attempts, `None` if never attempted.
+    pub fn success_rate(&self) -> Option<f64> {
+        if self.attempts == 0 {
+            None
+        } else {
+            Some(self.successes as f64 / self.attempts as f64)
+        }
+    }
+}
+
+/// Counts the attempts made through each outbound by the dispatcher.
+#[derive(Default)]
+pub struct OutboundMetrics {
+    counters: RwLock<IndexMap<String, Arc<Counters>>>,
+}
+
+impl OutboundMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn counters(&self, tag: &str) -> Arc<Counters> {
+        if let Some(c) = self.counters.read().unwrap().get(tag) {
+            return c.clone();
+        }
+        self.counters
+            .write()
+            .unwrap()
+            .entry(tag.to_string())
+            .or_default()
+            .clone()
+    }
+
+    pub fn record_success(&self, tag: &str) {
+        let c = self.counters(tag);
+        c.attempts.fetch_add(1, Ordering::Relaxed);
+        c.successes.fetch_add(1, Ordering::Relaxed);
+        c.consecutive_failures.store(0, Ordering::Relaxed);
+        let _ = c
+            .up_since
+            .compare_exchange(0, now_millis(), Ordering::Relaxed, Ordering::Relaxed);
+    }
+
+    /// Counts a stream through the outbound as active for the lifetime of
+    /// the returned guard.
+    pub fn track_active(&self, tag: &str) -> ActiveGuard {
+        let c = self.counters(tag);
+        c.active.fetch_add(1, Ordering::Relaxed);
+        ActiveGuard(c)
+    }
+
+    pub fn record_failure(&self, tag: &str, failure: Failure) {
+        let c = self.counters(tag);
+        c.attempts.fetch_add(1, Ordering::Relaxed);
+        match failure {
+            Failure::Connect => &c.connect_failures,
+            Failure::Handshake => &c.handshake_failures,
+            Failure::Timeout => &c.timeouts,
+        }
+        .fetch_add(1, Ordering::Relaxed);
+        c.consecutive_failures.fetch_add(1, Ordering::Relaxed);
+        c.up_since.store(0, Ordering::Relaxed);
+    }
+
+    /// Returns the consecutive failures of the outbound since its latest
+    /// success.
+    pub fn consecutive_failures(&self, tag: &str) -> u64 {
+        self.counters
+            .read()
+            .unwrap()
+            .get(tag)
+            .map_or(0, |c| c.consecutive_failures.load(Ordering::Relaxed))
+    }
+
+    pub fn snapshot(&self) -> Vec<OutboundStat> {
+        self.counters
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(tag, c)| OutboundStat {
+                tag: tag.clone(),
+                attempts: c.attempts.load(Ordering::Relaxed),
+                successes: c.successes.load(Ordering::Relaxed),
+                connect_failures: c.connect_failures.load(Ordering::Relaxed),
+                handshake_failures: c.handshake_failures.load(Ordering::Relaxed),
+                timeouts: c.timeouts.load(Ordering::Relaxed),
+                consecutive_failures: c.consecutive_failures.load(Ordering::Relaxed),
+                active: c.active.load(Ordering::Relaxed),
+                up_since: match c.up_since.load(Ordering::Relaxed) {
+                    0 => None,
+                    ms => Some(UNIX_EPOCH + Duration::from_millis(ms)),
+                },
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_outbound_metrics() {
+        let m = OutboundMetrics::new();
+        m.record_failure("proxy", Failure::Connect);
+        let e = io::Error::new(io::ErrorKind::TimedOut, "timed out");
+        m.record_failure("proxy", Failure::from_error(Failure::Handshake, &e));
+        assert_eq!(m.consecutive_failures("proxy"), 2);
+        m.record_success("proxy");
+        m.record_success("direct");
+        assert_eq!(m.consecutive_failures("proxy"), 0);
+        let guard = m.track_active("proxy");
+
+        let stats = m.snapshot();
+        let up_since = stats[0].up_since;
+        assert!(up_since.is_some());
+        assert!(stats[0].up_for().unwrap() < Duration::from_secs(60));
+        assert_eq!(stats.len(), 2);
+        assert_eq!(
+            stats[0],
+            OutboundStat {
+                tag: "proxy".to_string(),
+                attempts: 3,
+                successes: 1,
+                connect_failures: 1,
+                handshake_failures: 0,
+                timeouts: 1,
+                consecutive_failures: 0,
+                active: 1,
+                up_since,
+            }
+        );
+        assert_eq!(stats[0].failures(), 2);
+        assert!(stats[0].connected());
+        assert_eq!(stats[1].success_rate(), Some(1.0));
+
+        drop(guard);
+        m.record_success("proxy");
+        assert_eq!(m.snapshot()[0].up_since, up_since);
+        m.record_failure("proxy", Failure::Connect);
+        let stats = m.snapshot();
+        assert!(!stats[0].connected());
+        assert_eq!(stats[0].up_since, None);
+    }
+}
@@ -4,3 +4,4 @@ use std::sync::Arc;
 use tokio::sync::RwLock;
 
 pub mod manager;
+pub mod metrics;
@@ -1,36 +1,192 @@
 use indexmap::IndexMap;
+use std::convert::TryFrom;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
 
 use anyhow::anyhow;
 use anyhow::Result;
+use chrono::{Datelike, Timelike};
 use cidr::IpCidr;
 use futures::TryFutureExt;
 use log::*;
-use maxminddb::geoip2::Country;
+use maxminddb::geoip2::{Asn, Country};
 use memmap2::Mmap;
 
 use crate::app::SyncDnsClient;
 use crate::config;
 use crate::session::{Network, Session, SocksAddr};
 
+/// What the rules are matched on for a routing decision.
+pub struct MatchContext<'a> {
+    pub sess: &'a Session,
+    /// The local weekday from Monday and the minutes from midnight of the
+    /// decision, the same for all the rules.
+    pub time: (u32, u32),
+}
+
 pub trait Condition: Send + Sync + Unpin {
-    fn apply(&self, sess: &Session) -> bool;
+    fn apply(&self, ctx: &MatchContext) -> bool;
+}
+
+/// Where the domain of a session is resolved.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Resolve {
+    /// Leaves the destination as is.
+    Default,
+    /// Resolves the domain before handing the session to the outbound.
+    Local,
+    /// Leaves the domain to the outbound, restores it from the sniffed SNI
+    /// if the destination is an IP.
+    Remote,
+}
+
+impl std::str::FromStr for Resolve {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "" => Ok(Resolve::Default),
+            "local" => Ok(Resolve::Local),
+            "remote" => Ok(Resolve::Remote),
+            _ => Err(anyhow!("unknown resolve mode {}", s)),
+        }
+    }
+}
+
+/// Why a route was picked, for debugging the rules.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Reason<'a> {
+    /// The destination is a LAN address.
+    Lan,
+    /// The rule at the index of the loaded rules matched, only after the
+    /// domain was resolved if `resolved`.
+    Rule {
+        index: usize,
+        desc: &'a str,
+        resolved: bool,
+    },
+}
+
+impl<'a> std::fmt::Display for Reason<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Reason::Lan => write!(f, "lan address"),
+            Reason::Rule {
+                index,
+                desc,
+                resolved,
+            } => {
+                write!(f, "rule #{} {}", index, desc)?;
+                if *resolved {
+                    write!(f, " after resolving the domain for ip rules")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The route of a session picked by the router.
+pub struct Route<'a> {
+    pub target: &'a String,
+    /// Replaces the destination of the session if present.
+    pub redirect: Option<&'a SocksAddr>,
+    /// Logs the session verbosely.
+    pub log: bool,
+    pub resolve: Resolve,
+    pub reason: Reason<'a>,
+}
+
+impl<'a> Route<'a> {
+    fn new(target: &'a String, reason: Reason<'a>) -> Self {
+        Route {
+            target,
+            redirect: None,
+            log: false,
+            resolve: Resolve::Default,
+            reason,
+        }
+    }
+}
+
+// Describes a condition of a rule, only the first few values are listed.
+fn describe<S: AsRef<str>>(kind: &str, values: &[S]) -> String {
+    const MAX_VALUES: usize = 3;
+    let mut listed: Vec<&str> = values.iter().take(MAX_VALUES).map(|v| v.as_ref()).collect();
+    let more = format!("+{}", values.len().saturating_sub(MAX_VALUES));
+    if values.len() > MAX_VALUES {
+        listed.push(&more);
+    }
+    format!("{}:{}", kind, listed.join("|"))
 }
 
 struct Rule {
     target: String,
     condition: Box<dyn Condition>,
+    // Whether the rule has conditions which can only match an IP destination,
+    // domain destinations must be resolved before such rules can apply.
+    requires_ip: bool,
+    // Replaces the destination of matched sessions.
+    redirect: Option<SocksAddr>,
+    // Logs matched sessions verbosely.
+    log: bool,
+    resolve: Resolve,
+    // The conditions, e.g. `domain_suffix:example.com, port:443`.
+    desc: String,
 }
 
 impl Rule {
-    fn new(target: String, condition: Box<dyn Condition>) -> Self {
-        Rule { target, condition }
+    fn new(
+        target: String,
+        condition: Box<dyn Condition>,
+        requires_ip: bool,
+        redirect: Option<SocksAddr>,
+        log: bool,
+        resolve: Resolve,
+        desc: String,
+    ) -> Self {
+        Rule {
+            target,
+            condition,
+            requires_ip,
+            redirect,
+            log,
+            resolve,
+            desc,
+        }
+    }
+
+    fn route(&self, index: usize, resolved: bool) -> Route {
+        Route {
+            target: &self.target,
+            redirect: self.redirect.as_ref(),
+            log: self.log,
+            resolve: self.resolve,
+            reason: Reason::Rule {
+                index,
+                desc: &self.desc,
+                resolved,
+            },
+        }
     }
 }
 
+// Parses a redirect address in the form of `host:port`, IPv6 addresses must
+// be enclosed in brackets.
+fn parse_redirect(s: &str) -> Result<SocksAddr> {
+    if let Ok(addr) = s.parse::<SocketAddr>() {
+        return Ok(SocksAddr::from(addr));
+    }
+    let (host, port) = s.rsplit_once(':').ok_or_else(|| anyhow!("missing port"))?;
+    let port = port
+        .parse::<u16>()
+        .map_err(|e| anyhow!("invalid port: {}", e))?;
+    SocksAddr::try_from((host, port)).map_err(|e| anyhow!("invalid host: {}", e))
+}
+
 impl Condition for Rule {
-    fn apply(&self, sess: &Session) -> bool {
-        self.condition.apply(sess)
+    fn apply(&self, ctx: &MatchContext) -> bool {
+        self.condition.apply(ctx)
     }
 }
 
@@ -49,9 +205,9 @@ impl MmdbMatcher {
 }
 
 impl Condition for MmdbMatcher {
-    fn apply(&self, sess: &Session) -> bool {
-        if !sess.destination.is_domain() {
-            if let Some(ip) = sess.destination.ip() {
+    fn apply(&self, ctx: &MatchContext) -> bool {
+        if !ctx.sess.destination.is_domain() {
+            if let Some(ip) = ctx.sess.destination.ip() {
                 if let Ok(country) = self.reader.lookup::<Country>(ip) {
                     if let Some(country) = country.country {
                         if let Some(iso_code) = country.iso_code {
@@ -68,6 +224,29 @@ impl Condition for MmdbMatcher {
     }
 }
 
+struct AsnMatcher {
+    reader: Arc<maxminddb::Reader<Mmap>>,
+    numbers: Vec<u32>,
+}
+
+impl Condition for AsnMatcher {
+    fn apply(&self, ctx: &MatchContext) -> bool {
+        if !ctx.sess.destination.is_domain() {
+            if let Some(ip) = ctx.sess.destination.ip() {
+                if let Ok(asn) = self.reader.lookup::<Asn>(ip) {
+                    if let Some(number) = asn.autonomous_system_number {
+                        if self.numbers.contains(&number) {
+                            debug!("[{}] matches asn [{}]", ip, number);
+                            return true;
+                        }
+                    }
+                }
+            }
+        }
+        false
+    }
+}
+
 struct IpCidrMatcher {
     values: Vec<IpCidr>,
 }
@@ -90,10 +269,10 @@ impl IpCidrMatcher {
 }
 
 impl Condition for IpCidrMatcher {
-    fn apply(&self, sess: &Session) -> bool {
-        if !sess.destination.is_domain() {
+    fn apply(&self, ctx: &MatchContext) -> bool {
+        if !ctx.sess.destination.is_domain() {
             for cidr in &self.values {
-                if let Some(ip) = sess.destination.ip() {
+                if let Some(ip) = ctx.sess.destination.ip() {
                     if cidr.contains(&ip) {
                         debug!("[{}] matches ip-cidr [{}]", ip, &cidr);
                         return true;
@@ -120,10 +299,10 @@ impl InboundTagMatcher {
 }
 
 impl Condition for InboundTagMatcher {
-    fn apply(&self, sess: &Session) -> bool {
+    fn apply(&self, ctx: &MatchContext) -> bool {
         for v in &self.values {
-            if v == &sess.inbound_tag {
-                debug!("[{}] matches inbound tag [{}]", &sess.inbound_tag, v);
+            if v == &ctx.sess.inbound_tag {
+                debug!("[{}] matches inbound tag [{}]", &ctx.sess.inbound_tag, v);
                 return true;
             }
         }
@@ -150,10 +329,10 @@ impl NetworkMatcher {
 }
 
 impl Condition for NetworkMatcher {
-    fn apply(&self, sess: &Session) -> bool {
+    fn apply(&self, ctx: &MatchContext) -> bool {
         for v in &self.values {
-            if v == &sess.network {
-                debug!("[{}] matches network [{}]", &sess.network, v);
+            if v == &ctx.sess.network {
+                debug!("[{}] matches network [{}]", &ctx.sess.network, v);
                 return true;
             }
         }
@@ -181,8 +360,8 @@ impl PortMatcher {
 }
 
 impl Condition for PortMatcher {
-    fn apply(&self, sess: &Session) -> bool {
-        self.condition.apply(sess)
+    fn apply(&self, ctx: &MatchContext) -> bool {
+        self.condition.apply(ctx)
     }
 }
 
@@ -215,8 +394,8 @@ impl PortRangeMatcher {
 }
 
 impl Condition for PortRangeMatcher {
-    fn apply(&self, sess: &Session) -> bool {
-        let port = sess.destination.port();
+    fn apply(&self, ctx: &MatchContext) -> bool {
+        let port = ctx.sess.destination.port();
         if port >= self.start && port <= self.end {
             debug!(
                 "[{}] matches port range [{}-{}]",
@@ -240,9 +419,9 @@ impl DomainKeywordMatcher {
 }
 
 impl Condition for DomainKeywordMatcher {
-    fn apply(&self, sess: &Session) -> bool {
-        if sess.destination.is_domain() {
-            if let Some(domain) = sess.destination.domain() {
+    fn apply(&self, ctx: &MatchContext) -> bool {
+        if ctx.sess.destination.is_domain() {
+            if let Some(domain) = ctx.sess.destination.domain() {
                 if domain.contains(&self.value) {
                     debug!("[{}] matches domain keyword [{}]", domain, &self.value);
                     return true;
@@ -284,9 +463,9 @@ fn is_sub_domain(d1: &str, d2: &str) -> bool {
 }
 
 impl Condition for DomainSuffixMatcher {
-    fn apply(&self, sess: &Session) -> bool {
-        if sess.destination.is_domain() {
-            if let Some(domain) = sess.destination.domain() {
+    fn apply(&self, ctx: &MatchContext) -> bool {
+        if ctx.sess.destination.is_domain() {
+            if let Some(domain) = ctx.sess.destination.domain() {
                 if is_sub_domain(domain, &self.value) {
                     debug!("[{}] matches domain suffix [{}]", domain, &self.value);
                     return true;
@@ -308,9 +487,9 @@ impl DomainFullMatcher {
 }
 
 impl Condition for DomainFullMatcher {
-    fn apply(&self, sess: &Session) -> bool {
-        if sess.destination.is_domain() {
-            if let Some(domain) = sess.destination.domain() {
+    fn apply(&self, ctx: &MatchContext) -> bool {
+        if ctx.sess.destination.is_domain() {
+            if let Some(domain) = ctx.sess.destination.domain() {
                 if domain == &self.value {
                     debug!("{} matches domain [{}]", domain, &self.value);
                     return true;
@@ -321,6 +500,168 @@ impl Condition for DomainFullMatcher {
     }
 }
 
+struct SniMatcher {
+    values: Vec<String>,
+}
+
+impl SniMatcher {
+    fn new(snis: &mut Vec<String>) -> Self {
+        let mut values = Vec::new();
+        for sni in snis.iter_mut() {
+            values.push(std::mem::take(sni).to_lowercase());
+        }
+        Self { values }
+    }
+}
+
+impl Condition for SniMatcher {
+    fn apply(&self, ctx: &MatchContext) -> bool {
+        if let Some(sni) = &ctx.sess.sni {
+            let sni = sni.to_lowercase();
+            for v in &self.values {
+                let matched = if let Some(suffix) = v.strip_prefix("*.") {
+                    sni != suffix && is_sub_domain(&sni, suffix)
+                } else {
+                    &sni == v
+                };
+                if matched {
+                    debug!("[{}] matches sni [{}]", &sni, v);
+                    return true;
+                }
+            }
+        }
+        false
+    }
+}
+
+struct AlpnMatcher {
+    values: Vec<String>,
+}
+
+impl AlpnMatcher {
+    fn new(alpns: &mut Vec<String>) -> Self {
+        let mut values = Vec::new();
+        for alpn in alpns.iter_mut() {
+            values.push(std::mem::take(alpn));
+        }
+        Self { values }
+    }
+}
+
+impl Condition for AlpnMatcher {
+    fn apply(&self, ctx: &MatchContext) -> bool {
+        for alpn in &ctx.sess.alpn {
+            if self.values.contains(alpn) {
+                debug!("[{}] matches alpn", alpn);
+                return true;
+            }
+        }
+        false
+    }
+}
+
+// A weekly window in local time, e.g. `mon-fri 09:00-18:00`, `sat|sun` or
+// `22:00-06:00`. Without days it's every day, without hours the whole day, a
+// window ending before it starts runs past midnight into the next day.
+struct ScheduleMatcher {
+    // Bit n is set for the n-th day from Monday.
+    days: u8,
+    // Minutes from midnight, the end is exclusive.
+    start: u32,
+    end: u32,
+}
+
+const MINUTES_PER_DAY: u32 = 24 * 60;
+
+fn parse_weekday(s: &str) -> Result<u32> {
+    s.parse::<chrono::Weekday>()
+        .map(|d| d.num_days_from_monday())
+        .map_err(|_| anyhow!("invalid day {}", s))
+}
+
+// Parses `HH:MM` into minutes from midnight, `24:00` included.
+fn parse_minutes(s: &str) -> Result<u32> {
+    let (h, m) = s
+        .split_once(':')
+        .ok_or_else(|| anyhow!("invalid time {}", s))?;
+    match (h.parse::<u32>(), m.parse::<u32>()) {
+        (Ok(h), Ok(m)) if h <= 24 && m < 60 && h * 60 + m <= MINUTES_PER_DAY => Ok(h * 60 + m),
+        _ => Err(anyhow!("invalid time {}", s)),
+    }
+}
+
+impl ScheduleMatcher {
+    fn new(schedule: &str) -> Result<Self> {
+        let mut days = None;
+        let mut hours = None;
+        for part in schedule.split_whitespace() {
+            if part.contains(':') && hours.is_none() {
+                let (start, end) = part
+                    .split_once('-')
+                    .ok_or_else(|| anyhow!("invalid hours {}", part))?;
+                let (start, end) = (parse_minutes(start)?, parse_minutes(end)?);
+                if start == end {
+                    return Err(anyhow!("empty hours {}", part));
+                }
+                hours = Some((start, end));
+            } else if !part.contains(':') && days.is_none() {
+                let mut bits = 0u8;
+                // Days or ranges of days, which may wrap around the week.
+                for item in part.split('|') {
+                    let (first, last) = match item.split_once('-') {
+                        Some((first, last)) => (parse_weekday(first)?, parse_weekday(last)?),
+                        None => (parse_weekday(item)?, parse_weekday(item)?),
+                    };
+                    let mut day = first;
+                    loop {
+                        bits |= 1 << day;
+                        if day == last {
+                            break;
+                        }
+                        day = (day + 1) % 7;
+                    }
+                }
+                days = Some(bits);
+            } else {
+                return Err(anyhow!("unexpected {}", part));
+            }
+        }
+        if days.is_none() && hours.is_none() {
+            return Err(anyhow!("empty schedule"));
+        }
+        let (start, end) = hours.unwrap_or((0, MINUTES_PER_DAY));
+        Ok(Self {
+            days: days.unwrap_or(0x7f),
+            start,
+            end,
+        })
+    }
+
+    fn matches(&self, weekday: u32, minutes: u32) -> bool {
+        let on = |day: u32| self.days & (1 << day) != 0;
+        if self.start < self.end {
+            on(weekday) && self.start <= minutes && minutes < self.end
+        } else {
+            (on(weekday) && minutes >= self.start) || (on((weekday + 6) % 7) && minutes < self.end)
+        }
+    }
+}
+
+fn local_time() -> (u32, u32) {
+    let now = chrono::Local::now();
+    (
+        now.weekday().num_days_from_monday(),
+        now.hour() * 60 + now.minute(),
+    )
+}
+
+impl Condition for ScheduleMatcher {
+    fn apply(&self, ctx: &MatchContext) -> bool {
+        let (weekday, minutes) = ctx.time;
+        self.matches(weekday, minutes)
+    }
+}
+
 struct DomainMatcher {
     condition: Box<dyn Condition>,
 }
@@ -349,8 +690,8 @@ impl DomainMatcher {
 }
 
 impl Condition for DomainMatcher {
-    fn apply(&self, sess: &Session) -> bool {
-        self.condition.apply(sess)
+    fn apply(&self, ctx: &MatchContext) -> bool {
+        self.condition.apply(ctx)
     }
 }
 
@@ -375,9 +716,9 @@ impl ConditionAnd {
 }
 
 impl Condition for ConditionAnd {
-    fn apply(&self, sess: &Session) -> bool {
+    fn apply(&self, ctx: &MatchContext) -> bool {
         for cond in &self.conditions {
-            if !cond.apply(sess) {
+            if !cond.apply(ctx) {
                 return false;
             }
         }
@@ -402,9 +743,9 @@ impl ConditionOr {
 }
 
 impl Condition for ConditionOr {
-    fn apply(&self, sess: &Session) -> bool {
+    fn apply(&self, ctx: &MatchContext) -> bool {
         for cond in &self.conditions {
-            if cond.apply(sess) {
+            if cond.apply(ctx) {
                 return true;
             }
         }
@@ -412,41 +753,91 @@ impl Condition for ConditionOr {
     }
 }
 
+// Whether the address is private, loopback or link-local.
+fn is_lan(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => ip.is_private() || ip.is_loopback() || ip.is_link_local(),
+        IpAddr::V6(ip) => {
+            let seg = ip.segments()[0];
+            ip.is_loopback() || (seg & 0xfe00) == 0xfc00 || (seg & 0xffc0) == 0xfe80
+        }
+    }
+}
+
 pub struct Router {
     rules: Vec<Rule>,
+    // Outbound for LAN destinations, they bypass the rules.
+    lan_target: Option<String>,
     domain_resolve: bool,
+    // Whether any rules match on SNI or ALPN, streams are sniffed only if so.
+    sni_rules: bool,
+    // Whether any rules match on schedules, the local time is read only if
+    // so.
+    schedules: bool,
+    unmatched: config::router::Unmatched,
+    fail_closed: bool,
     dns_client: SyncDnsClient,
 }
 
+// Opens the mmdb file, or returns the reader already opened for it.
+fn open_mmdb(
+    readers: &mut IndexMap<String, Arc<maxminddb::Reader<Mmap>>>,
+    file: &str,
+) -> Result<Arc<maxminddb::Reader<Mmap>>> {
+    if let Some(r) = readers.get(file) {
+        return Ok(r.clone());
+    }
+    let r = maxminddb::Reader::open_mmap(file)
+        .map_err(|e| anyhow!("open mmdb file {} failed: {:?}", file, e))?;
+    let r = Arc::new(r);
+    readers.insert(file.to_owned(), r.clone());
+    Ok(r)
+}
+
 impl Router {
-    fn load_rules(rules: &mut Vec<Rule>, routing_rules: &mut Vec<config::router::Rule>) {
+    fn load_rules(
+        rules: &mut Vec<Rule>,
+        routing_rules: &mut Vec<config::router::Rule>,
+    ) -> Result<()> {
         let mut mmdb_readers: IndexMap<String, Arc<maxminddb::Reader<Mmap>>> = IndexMap::new();
         for rr in routing_rules.iter_mut() {
             let mut cond_and = ConditionAnd::new();
+            let mut desc = Vec::new();
 
             if rr.domains.len() > 0 {
+                for (type_, kind) in [
+                    (config::router::rule::domain::Type::PLAIN, "domain_keyword"),
+                    (config::router::rule::domain::Type::DOMAIN, "domain_suffix"),
+                    (config::router::rule::domain::Type::FULL, "domain"),
+                ] {
+                    let values: Vec<&str> = rr
+                        .domains
+                        .iter()
+                        .filter(|d| d.type_.enum_value() == Ok(type_))
+                        .map(|d| d.value.as_str())
+                        .collect();
+                    if !values.is_empty() {
+                        desc.push(describe(kind, &values));
+                    }
+                }
                 cond_and.add(Box::new(DomainMatcher::new(&mut rr.domains)));
             }
 
             if rr.ip_cidrs.len() > 0 {
+                desc.push(describe("ip_cidr", &rr.ip_cidrs));
                 cond_and.add(Box::new(IpCidrMatcher::new(&mut rr.ip_cidrs)));
             }
 
             if rr.mmdbs.len() > 0 {
+                let codes: Vec<&str> = rr.mmdbs.iter().map(|m| m.country_code.as_str()).collect();
+                desc.push(describe("geoip", &codes));
                 for mmdb in rr.mmdbs.iter() {
-                    let reader = match mmdb_readers.get(&mmdb.file) {
-                        Some(r) => r.clone(),
-                        None => match maxminddb::Reader::open_mmap(&mmdb.file) {
-                            Ok(r) => {
-                                let r = Arc::new(r);
-                                mmdb_readers.insert((&mmdb.file).to_owned(), r.clone());
-                                r
-                            }
-                            Err(e) => {
-                                warn!("open mmdb file {} failed: {:?}", mmdb.file, e);
-                                continue;
-                            }
-                        },
+                    let reader = match open_mmdb(&mut mmdb_readers, &mmdb.file) {
+                        Ok(r) => r,
+                        Err(e) => {
+                            warn!("{}", e);
+                            continue;
+                        }
                     };
                     cond_and.add(Box::new(MmdbMatcher::new(
                         reader,
@@ -455,62 +846,210 @@ impl Router {
                 }
             }
 
+            if rr.asns.len() > 0 {
+                let numbers: Vec<String> = rr.asns.iter().map(|a| a.number.to_string()).collect();
+                desc.push(describe("asn", &numbers));
+                // Any of the numbers match, whichever file it's looked up in.
+                let mut by_file: IndexMap<&str, Vec<u32>> = IndexMap::new();
+                for asn in rr.asns.iter() {
+                    by_file
+                        .entry(asn.file.as_str())
+                        .or_default()
+                        .push(asn.number);
+                }
+                let mut cond_or = ConditionOr::new();
+                for (file, numbers) in by_file {
+                    let reader = open_mmdb(&mut mmdb_readers, file)?;
+                    cond_or.add(Box::new(AsnMatcher { reader, numbers }));
+                }
+                cond_and.add(Box::new(cond_or));
+            }
+
+            if rr.geosites.len() > 0 {
+                let categories: Vec<&str> =
+                    rr.geosites.iter().map(|g| g.category.as_str()).collect();
+                desc.push(describe("geosite", &categories));
+                // Domains in any of the categories match.
+                let mut domains = Vec::new();
+                for geosite in rr.geosites.iter() {
+                    let mut d =
+                        config::external_rule::load_site_domains(&geosite.file, &geosite.category)
+                            .map_err(|e| {
+                                anyhow!(
+                                    "load geosite {} from {} failed: {}",
+                                    geosite.category,
+                                    geosite.file,
+                                    e
+                                )
+                            })?;
+                    domains.append(&mut d);
+                }
+                if domains.len() > 0 {
+                    cond_and.add(Box::new(DomainMatcher::new(&mut domains)));
+                }
+            }
+
             if rr.port_ranges.len() > 0 {
+                desc.push(describe("port", &rr.port_ranges));
                 cond_and.add(Box::new(PortMatcher::new(&rr.port_ranges)));
             }
 
             if rr.networks.len() > 0 {
+                desc.push(describe("network", &rr.networks));
                 cond_and.add(Box::new(NetworkMatcher::new(&mut rr.networks)));
             }
 
             if rr.inbound_tags.len() > 0 {
+                desc.push(describe("inbound_tag", &rr.inbound_tags));
                 cond_and.add(Box::new(InboundTagMatcher::new(&mut rr.inbound_tags)));
             }
 
+            if rr.snis.len() > 0 {
+                desc.push(describe("sni", &rr.snis));
+                cond_and.add(Box::new(SniMatcher::new(&mut rr.snis)));
+            }
+
+            if rr.alpns.len() > 0 {
+                desc.push(describe("alpn", &rr.alpns));
+                cond_and.add(Box::new(AlpnMatcher::new(&mut rr.alpns)));
+            }
+
+            if !rr.schedule.is_empty() {
+                let m = ScheduleMatcher::new(&rr.schedule).map_err(|e| {
+                    anyhow!(
+                        "invalid schedule {} at target {}: {}",
+                        rr.schedule,
+                        rr.target_tag,
+                        e
+                    )
+                })?;
+                desc.push(describe("schedule", &[&rr.schedule]));
+                cond_and.add(Box::new(m));
+            }
+
             if cond_and.is_empty() {
                 warn!("empty rule at target {}", rr.target_tag);
                 continue;
             }
 
+            let redirect = if !rr.redirect.is_empty() {
+                Some(parse_redirect(&rr.redirect).map_err(|e| {
+                    anyhow!(
+                        "invalid redirect {} at target {}: {}",
+                        rr.redirect,
+                        rr.target_tag,
+                        e
+                    )
+                })?)
+            } else {
+                None
+            };
+
+            let resolve = rr
+                .resolve
+                .parse::<Resolve>()
+                .map_err(|e| anyhow!("invalid resolve at target {}: {}", rr.target_tag, e))?;
+
+            let requires_ip = rr.ip_cidrs.len() > 0 || rr.mmdbs.len() > 0 || rr.asns.len() > 0;
             let tag = std::mem::take(&mut rr.target_tag);
-            rules.push(Rule::new(tag, Box::new(cond_and)));
+            rules.push(Rule::new(
+                tag,
+                Box::new(cond_and),
+                requires_ip,
+                redirect,
+                rr.log,
+                resolve,
+                desc.join(", "),
+            ));
         }
+        Ok(())
     }
 
     pub fn new(
         router: &mut protobuf::MessageField<config::Router>,
         dns_client: SyncDnsClient,
-    ) -> Self {
+    ) -> Result<Self> {
         let mut rules: Vec<Rule> = Vec::new();
+        let mut lan_target = None;
         let mut domain_resolve = false;
+        let mut sni_rules = false;
+        let mut schedules = false;
+        let mut unmatched = config::router::Unmatched::DEFAULT;
+        let mut fail_closed = false;
         if let Some(router) = router.as_mut() {
-            Self::load_rules(&mut rules, &mut router.rules);
+            sni_rules = Self::has_sni_rules(&router.rules);
+            schedules = router.rules.iter().any(|r| !r.schedule.is_empty());
+            Self::load_rules(&mut rules, &mut router.rules)?;
+            lan_target = Some(router.lan_target.clone()).filter(|t| !t.is_empty());
             domain_resolve = router.domain_resolve;
+            unmatched = router.unmatched.enum_value_or_default();
+            fail_closed = router.fail_closed;
         }
-        Router {
+        Ok(Router {
             rules,
+            lan_target,
             domain_resolve,
+            sni_rules,
+            schedules,
+            unmatched,
+            fail_closed,
             dns_client,
-        }
+        })
     }
 
-    pub fn reload(&mut self, router: &mut protobuf::MessageField<config::Router>) -> Result<()> {
-        self.rules.clear();
-        if let Some(router) = router.as_mut() {
-            Self::load_rules(&mut self.rules, &mut router.rules);
-            self.domain_resolve = router.domain_resolve;
-        }
-        Ok(())
+    fn has_sni_rules(routing_rules: &[config::router::Rule]) -> bool {
+        routing_rules
+            .iter()
+            .any(|r| !r.snis.is_empty() || !r.alpns.is_empty())
+    }
+
+    /// Whether the ClientHello of TLS streams should be sniffed for routing.
+    pub fn sni_rules(&self) -> bool {
+        self.sni_rules
+    }
+
+    /// What happens to sessions matching no rules.
+    pub fn unmatched(&self) -> config::router::Unmatched {
+        self.unmatched
+    }
+
+    /// Whether sessions failing to be routed are dropped rather than handled
+    /// as unmatched.
+    pub fn fail_closed(&self) -> bool {
+        self.fail_closed
     }
 
-    pub async fn pick_route<'a>(&'a self, sess: &'a Session) -> Result<&'a String> {
+    // Returns the index of the first rule matching the session, only rules
+    // matching on IP if `ip_rules`. Schedules are matched at `time`.
+    fn first_match(&self, sess: &Session, time: (u32, u32), ip_rules: bool) -> Option<usize> {
+        let ctx = MatchContext { sess, time };
+        self.rules
+            .iter()
+            .position(|r| (!ip_rules || r.requires_ip) && r.apply(&ctx))
+    }
+
+    /// Returns the route of the first rule matching the session, none if no
+    /// rules match, an error if the session can't be matched against all the
+    /// rules.
+    pub async fn pick_route<'a>(&'a self, sess: &'a Session) -> Result<Option<Route<'a>>> {
         log::debug!("picking route for {}:{}", &sess.network, &sess.destination);
-        for rule in &self.rules {
-            if rule.apply(sess) {
-                return Ok(&rule.target);
+        if let (Some(target), Some(ip)) = (&self.lan_target, sess.destination.ip()) {
+            if is_lan(&ip) {
+                debug!("[{}] is a lan address", ip);
+                return Ok(Some(Route::new(target, Reason::Lan)));
             }
         }
-        if sess.destination.is_domain() && self.domain_resolve {
+        // The time schedules are matched at, kept for matching again.
+        let time = if self.schedules { local_time() } else { (0, 0) };
+        if let Some(index) = self.first_match(sess, time, false) {
+            return Ok(Some(self.rules[index].route(index, false)));
+        }
+        // Resolving the domain only helps if there are rules matching on IP,
+        // otherwise it's a wasted query and leaks the domain to local DNS.
+        if sess.destination.is_domain()
+            && self.domain_resolve
+            && self.rules.iter().any(|r| r.requires_ip)
+        {
             let ips = {
                 self.dns_client
                     .read()
@@ -531,14 +1070,12 @@ impl Router {
                     ips[0],
                     sess.destination.host()
                 );
-                for rule in &self.rules {
-                    if rule.apply(&new_sess) {
-                        return Ok(&rule.target);
-                    }
+                if let Some(index) = self.first_match(&new_sess, time, true) {
+                    return Ok(Some(self.rules[index].route(index, true)));
                 }
             }
         }
-        Err(anyhow!("no matching rules"))
+        Ok(None)
     }
 }
 
@@ -548,6 +1085,11 @@ mod tests {
 
     use super::*;
 
+    // Matches at midnight on Monday.
+    fn ctx(sess: &Session) -> MatchContext {
+        MatchContext { sess, time: (0, 0) }
+    }
+
     #[test]
     fn test_is_sub_domain() {
         let d1 = "video.google.com".to_string();
@@ -569,16 +1111,16 @@ mod tests {
         // test port range
         let m = PortMatcher::new(&vec!["1024-5000".to_string(), "6000-7000".to_string()]);
         sess.destination = SocksAddr::Domain("www.google.com".to_string(), 2000);
-        assert!(m.apply(&sess));
+        assert!(m.apply(&ctx(&sess)));
         sess.destination = SocksAddr::Domain("www.google.com".to_string(), 5001);
-        assert!(!m.apply(&sess));
+        assert!(!m.apply(&ctx(&sess)));
         sess.destination = SocksAddr::Domain("www.google.com".to_string(), 6001);
-        assert!(m.apply(&sess));
+        assert!(m.apply(&ctx(&sess)));
 
         // test single port range
         let m = PortMatcher::new(&vec!["22-22".to_string()]);
         sess.destination = SocksAddr::Domain("www.google.com".to_string(), 22);
-        assert!(m.apply(&sess));
+        assert!(m.apply(&ctx(&sess)));
 
         // test invalid port ranges
         let m = PortRangeMatcher::new("22-21");
@@ -594,4 +1136,305 @@ mod tests {
         let m = PortRangeMatcher::new("22-23-24");
         assert!(m.is_err());
     }
+
+    #[test]
+    fn test_rule_requires_ip() {
+        let mut domain_rule = config::router::Rule::new();
+        domain_rule.target_tag = "proxy".to_string();
+        let mut domain = config::router::rule::Domain::new();
+        domain.type_ = protobuf::EnumOrUnknown::new(config::router::rule::domain::Type::FULL);
+        domain.value = "www.google.com".to_string();
+        domain_rule.domains.push(domain);
+
+        let mut ip_rule = config::router::Rule::new();
+        ip_rule.target_tag = "direct".to_string();
+        ip_rule.ip_cidrs.push("8.8.8.0/24".to_string());
+
+        let mut rules = Vec::new();
+        Router::load_rules(&mut rules, &mut vec![domain_rule, ip_rule]).unwrap();
+        assert_eq!(rules.len(), 2);
+        assert!(!rules[0].requires_ip);
+        assert!(rules[1].requires_ip);
+    }
+
+    #[test]
+    fn test_rule_redirect() {
+        let mut rule = config::router::Rule::new();
+        rule.target_tag = "direct".to_string();
+        rule.port_ranges.push("123-123".to_string());
+        rule.redirect = "pool.ntp.org:123".to_string();
+
+        let mut bad_rule = rule.clone();
+        bad_rule.redirect = "pool.ntp.org".to_string();
+
+        let mut rules = Vec::new();
+        Router::load_rules(&mut rules, &mut vec![rule.clone()]).unwrap();
+        assert_eq!(rules.len(), 1);
+        assert_eq!(
+            rules[0].redirect,
+            Some(SocksAddr::Domain("pool.ntp.org".to_string(), 123))
+        );
+        assert_eq!(rules[0].resolve, Resolve::Default);
+        let err = Router::load_rules(&mut Vec::new(), &mut vec![rule, bad_rule])
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(err.contains("invalid redirect pool.ntp.org"), "{}", err);
+
+        assert_eq!(
+            parse_redirect("[::1]:53").unwrap(),
+            SocksAddr::Ip("[::1]:53".parse().unwrap())
+        );
+        assert!(parse_redirect("127.0.0.1:abc").is_err());
+    }
+
+    #[test]
+    fn test_rule_resolve() {
+        let mut rule = config::router::Rule::new();
+        rule.target_tag = "proxy".to_string();
+        rule.port_ranges.push("443-443".to_string());
+        rule.resolve = "remote".to_string();
+
+        let mut bad_rule = rule.clone();
+        bad_rule.resolve = "nowhere".to_string();
+
+        let mut rules = Vec::new();
+        Router::load_rules(&mut rules, &mut vec![rule.clone()]).unwrap();
+        assert_eq!(rules.len(), 1);
+        assert_eq!(rules[0].resolve, Resolve::Remote);
+        assert!(Router::load_rules(&mut Vec::new(), &mut vec![rule, bad_rule]).is_err());
+        assert_eq!("local".parse::<Resolve>().unwrap(), Resolve::Local);
+    }
+
+    #[test]
+    fn test_rule_desc() {
+        let mut rule = config::router::Rule::new();
+        rule.target_tag = "proxy".to_string();
+        for (type_, value) in [
+            (config::router::rule::domain::Type::DOMAIN, "example.com"),
+            (config::router::rule::domain::Type::PLAIN, "ads"),
+            (config::router::rule::domain::Type::DOMAIN, "example.org"),
+        ] {
+            let mut domain = config::router::rule::Domain::new();
+            domain.type_ = protobuf::EnumOrUnknown::new(type_);
+            domain.value = value.to_string();
+            rule.domains.push(domain);
+        }
+        rule.port_ranges = (1..=5).map(|p| format!("{}-{}", p, p)).collect();
+
+        let mut rules = Vec::new();
+        Router::load_rules(&mut rules, &mut vec![rule]).unwrap();
+        assert_eq!(
+            rules[0].desc,
+            "domain_keyword:ads, domain_suffix:example.com|example.org, port:1-1|2-2|3-3|+2"
+        );
+        let route = rules[0].route(0, true);
+        assert_eq!(
+            route.reason.to_string(),
+            format!(
+                "rule #0 {} after resolving the domain for ip rules",
+                &rules[0].desc
+            )
+        );
+    }
+
+    #[test]
+    fn test_rule_geosite() {
+        let dir = std::env::temp_dir().join("ostrich_test_geosite");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("category-ads"),
+            "# ads\nads.example.com @ads\nfull:track.example.net\ninclude:more-ads\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("more-ads"), "keyword:doubleclick\nregexp:^ad\\d\n").unwrap();
+
+        let mut rule = config::router::Rule::new();
+        rule.target_tag = "reject".to_string();
+        let mut geosite = config::router::rule::Geosite::new();
+        geosite.file = dir.to_string_lossy().to_string();
+        geosite.category = "category-ads".to_string();
+        rule.geosites.push(geosite);
+
+        let mut rules = Vec::new();
+        Router::load_rules(&mut rules, &mut vec![rule.clone()]).unwrap();
+        // A category that fails to load fails the rules.
+        rule.geosites[0].category = "category-missing".to_string();
+        assert!(Router::load_rules(&mut Vec::new(), &mut vec![rule]).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(rules.len(), 1);
+        assert!(!rules[0].requires_ip);
+        let sess = |host: &str| Session {
+            destination: SocksAddr::Domain(host.to_string(), 443),
+            ..Default::default()
+        };
+        assert!(rules[0].apply(&ctx(&sess("x.ads.example.com"))));
+        assert!(rules[0].apply(&ctx(&sess("track.example.net"))));
+        assert!(!rules[0].apply(&ctx(&sess("www.track.example.net"))));
+        assert!(rules[0].apply(&ctx(&sess("ad.doubleclick.net"))));
+        assert!(!rules[0].apply(&ctx(&sess("example.com"))));
+    }
+
+    #[test]
+    fn test_rule_asn() {
+        use crate::config::external_rule::{add_external_rule, parse_asn};
+
+        assert_eq!(parse_asn("13335").unwrap(), 13335);
+        assert_eq!(parse_asn("as13335").unwrap(), 13335);
+        assert!(parse_asn("AS").is_err());
+        assert!(parse_asn("cloudflare").is_err());
+
+        let mut rule = config::router::Rule::new();
+        rule.target_tag = "direct".to_string();
+        add_external_rule(&mut rule, "asn:/nonexistent/asn.mmdb:AS13335").unwrap();
+        assert_eq!(rule.asns[0].number, 13335);
+        assert!(add_external_rule(&mut rule, "asn:AS-x").is_err());
+
+        // The database is required.
+        let err = Router::load_rules(&mut Vec::new(), &mut vec![rule])
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(err.contains("/nonexistent/asn.mmdb"), "{}", err);
+    }
+
+    #[test]
+    fn test_sni_matcher() {
+        let m = SniMatcher::new(&mut vec![
+            "*.googlevideo.com".to_string(),
+            "example.com".to_string(),
+        ]);
+        let mut sess = Session {
+            destination: SocksAddr::Ip("1.2.3.4:443".parse().unwrap()),
+            ..Default::default()
+        };
+        assert!(!m.apply(&ctx(&sess)));
+        sess.sni = Some("r1.googlevideo.com".to_string());
+        assert!(m.apply(&ctx(&sess)));
+        sess.sni = Some("googlevideo.com".to_string());
+        assert!(!m.apply(&ctx(&sess)));
+        sess.sni = Some("Example.com".to_string());
+        assert!(m.apply(&ctx(&sess)));
+        sess.sni = Some("www.example.com".to_string());
+        assert!(!m.apply(&ctx(&sess)));
+    }
+
+    #[test]
+    fn test_alpn_matcher() {
+        let m = AlpnMatcher::new(&mut vec!["h3".to_string()]);
+        let mut sess = Session::default();
+        assert!(!m.apply(&ctx(&sess)));
+        sess.alpn = vec!["h2".to_string(), "http/1.1".to_string()];
+        assert!(!m.apply(&ctx(&sess)));
+        sess.alpn = vec!["h3".to_string(), "h2".to_string()];
+        assert!(m.apply(&ctx(&sess)));
+    }
+
+    #[test]
+    fn test_schedule_matcher() {
+        // Monday is 0.
+        let m = ScheduleMatcher::new("mon-fri 09:00-18:00").unwrap();
+        assert!(m.matches(0, 9 * 60));
+        assert!(m.matches(4, 18 * 60 - 1));
+        assert!(!m.matches(4, 18 * 60));
+        assert!(!m.matches(2, 8 * 60 + 59));
+        assert!(!m.matches(5, 12 * 60));
+
+        let m = ScheduleMatcher::new("sat|Sun").unwrap();
+        assert!(m.matches(5, 0));
+        assert!(m.matches(6, MINUTES_PER_DAY - 1));
+        assert!(!m.matches(0, 0));
+
+        // Past midnight into Saturday, but not Monday morning.
+        let m = ScheduleMatcher::new("22:00-06:00 fri-sun").unwrap();
+        assert!(m.matches(4, 23 * 60));
+        assert!(m.matches(5, 5 * 60));
+        assert!(!m.matches(4, 5 * 60));
+        assert!(m.matches(0, 5 * 60));
+        assert!(!m.matches(0, 23 * 60));
+
+        let m = ScheduleMatcher::new("00:00-24:00").unwrap();
+        assert!(m.matches(3, 0) && m.matches(3, MINUTES_PER_DAY - 1));
+
+        for bad in [
+            "",
+            "mon-xyz",
+            "09:00",
+            "09:00-09:00",
+            "10:60-11:00",
+            "99999999:00-01:00",
+            "4294967295:59-01:00",
+            "mon tue",
+            "mon,tue",
+        ] {
+            assert!(ScheduleMatcher::new(bad).is_err(), "{}", bad);
+        }
+
+        let mut rule = config::router::Rule::new();
+        rule.target_tag = "direct".to_string();
+        rule.schedule = "mon-fri 09:00-18:00".to_string();
+        let mut bad_rule = rule.clone();
+        bad_rule.schedule = "workdays".to_string();
+        let mut rules = Vec::new();
+        Router::load_rules(&mut rules, &mut vec![rule.clone()]).unwrap();
+        assert_eq!(rules.len(), 1);
+        assert_eq!(rules[0].desc, "schedule:mon-fri 09:00-18:00");
+        assert!(Router::load_rules(&mut Vec::new(), &mut vec![rule, bad_rule]).is_err());
+    }
+
+    #[test]
+    fn test_alpn_rule() {
+        let mut rule = config::router::Rule::new();
+        rule.target_tag = "h3".to_string();
+        rule.alpns.push("h3".to_string());
+        let mut config = config::Router::new();
+        config.rules.push(rule);
+        let dns_client = crate::app::dns_client::tests::dns_client();
+        let router = Router::new(&mut protobuf::MessageField::some(config), dns_client).unwrap();
+        // The streams are sniffed for it.
+        assert!(router.sni_rules());
+
+        let mut sess = Session::default();
+        assert_eq!(router.first_match(&sess, (0, 0), false), None);
+        sess.alpn = vec!["h2".to_string(), "h3".to_string()];
+        assert_eq!(router.first_match(&sess, (0, 0), false), Some(0));
+    }
+
+    #[test]
+    fn test_decision_time() {
+        let mut config = config::Router::new();
+        for (target, schedule) in [("work", "mon-fri 09:00-18:00"), ("weekend", "sat|sun")] {
+            let mut rule = config::router::Rule::new();
+            rule.target_tag = target.to_string();
+            rule.schedule = schedule.to_string();
+            config.rules.push(rule);
+        }
+        let dns_client = crate::app::dns_client::tests::dns_client();
+        let router = Router::new(&mut protobuf::MessageField::some(config), dns_client).unwrap();
+        let sess = Session::default();
+
+        // All schedules are matched at the time of the decision.
+        assert_eq!(router.first_match(&sess, (0, 10 * 60), false), Some(0));
+        assert_eq!(router.first_match(&sess, (6, 10 * 60), false), Some(1));
+        assert_eq!(router.first_match(&sess, (0, 20 * 60), false), None);
+    }
+
+    #[test]
+    fn test_is_lan() {
+        for ip in &[
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.1.1",
+            "127.0.0.1",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+        ] {
+            assert!(is_lan(&ip.parse().unwrap()), "{}", ip);
+        }
+        for ip in &["8.8.8.8", "172.32.0.1", "2001:db8::1"] {
+            assert!(!is_lan(&ip.parse().unwrap()), "{}", ip);
+        }
+    }
 }
@@ -1,3 +1,5 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
 use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
 use std::sync::Arc;
 use std::{io, pin::Pin};
@@ -143,6 +145,9 @@ pub struct Counter {
     pub bytes_sent: Arc<AtomicU64>,
     pub recv_completed: Arc<AtomicBool>,
     pub send_completed: Arc<AtomicBool>,
+    // Bytes already added to the outbound usage.
+    folded_recvd: u64,
+    folded_sent: u64,
 }
 
 impl Counter {
@@ -179,15 +184,228 @@ fn log_session_end(c: &Counter) {
     );
 }
 
+/// Cumulative bytes through an outbound, kept across restarts.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Usage {
+    /// The month counted in `month_sent` and `month_recvd`, as `YYYY-MM`.
+    pub month: String,
+    pub month_sent: u64,
+    pub month_recvd: u64,
+    pub total_sent: u64,
+    pub total_recvd: u64,
+}
+
+impl Usage {
+    fn add(&mut self, month: &str, sent: u64, recvd: u64) {
+        if self.month != month {
+            self.month = month.to_string();
+            self.month_sent = 0;
+            self.month_recvd = 0;
+        }
+        self.month_sent += sent;
+        self.month_recvd += recvd;
+        self.total_sent += sent;
+        self.total_recvd += recvd;
+    }
+}
+
+fn current_month() -> String {
+    chrono::Local::now().format("%Y-%m").to_string()
+}
+
+// One outbound per line, tab separated: tag, month, month sent, month
+// received, total sent, total received.
+fn parse_usage(s: &str) -> HashMap<String, Usage> {
+    let mut usage = HashMap::new();
+    for line in s.lines() {
+        let parts: Vec<&str> = line.split('\t').collect();
+        if parts.len() != 6 {
+            continue;
+        }
+        let nums: Vec<u64> = parts[2..].iter().filter_map(|x| x.parse().ok()).collect();
+        if nums.len() != 4 {
+            continue;
+        }
+        usage.insert(
+            parts[0].to_string(),
+            Usage {
+                month: parts[1].to_string(),
+                month_sent: nums[0],
+                month_recvd: nums[1],
+                total_sent: nums[2],
+                total_recvd: nums[3],
+            },
+        );
+    }
+    usage
+}
+
+fn format_usage(usage: &HashMap<String, Usage>) -> String {
+    let mut tags: Vec<&String> = usage.keys().collect();
+    tags.sort();
+    let mut s = String::new();
+    for tag in tags {
+        let u = &usage[tag];
+        s.push_str(&format!(
+            "{}\t{}\t{}\t{}\t{}\t{}\n",
+            tag, u.month, u.month_sent, u.month_recvd, u.total_sent, u.total_recvd
+        ));
+    }
+    s
+}
+
+/// Cumulative bytes of a client through an inbound since start, the client
+/// as identified by `Session::client`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientUsage {
+    pub inbound_tag: String,
+    pub client: String,
+    pub usage: Usage,
+}
+
+/// Bytes through an outbound and the sessions on it.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct OutboundTraffic {
+    pub sent: u64,
+    pub recvd: u64,
+    pub active: u64,
+}
+
 pub struct StatManager {
     pub counters: Vec<Counter>,
+    usage: HashMap<String, Usage>,
+    // By inbound tag and client, not saved across restarts.
+    client_usage: HashMap<(String, String), Usage>,
 }
 
 impl StatManager {
     pub fn new() -> Self {
         Self {
             counters: Vec::new(),
+            usage: HashMap::new(),
+            client_usage: HashMap::new(),
+        }
+    }
+
+    /// Loads the outbound usage saved by a previous run, a missing file
+    /// starts from zero.
+    pub fn load_usage<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        match std::fs::read_to_string(path) {
+            Ok(s) => {
+                self.usage = parse_usage(&s);
+                Ok(())
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Writes the outbound usage, including the bytes of the sessions
+    /// still running. The file is replaced at once so a crash midway
+    /// leaves the previous one.
+    pub fn save_usage<P: AsRef<Path>>(&mut self, path: P) -> io::Result<()> {
+        self.fold_usage();
+        let path = path.as_ref();
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        std::fs::write(&tmp, format_usage(&self.usage))?;
+        std::fs::rename(&tmp, path)
+    }
+
+    /// Returns the cumulative usage per outbound tag.
+    pub fn usage(&mut self) -> Vec<(String, Usage)> {
+        self.fold_usage();
+        let mut usage: Vec<(String, Usage)> = self
+            .usage
+            .iter()
+            .map(|(tag, u)| (tag.clone(), u.clone()))
+            .collect();
+        usage.sort_by(|a, b| a.0.cmp(&b.0));
+        usage
+    }
+
+    /// Returns the total bytes and the active sessions per outbound tag,
+    /// `tags` get zeroed entries if nothing has gone through them.
+    pub fn outbound_traffic<I>(&mut self, tags: I) -> BTreeMap<String, OutboundTraffic>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        self.fold_usage();
+        let mut traffic: BTreeMap<String, OutboundTraffic> = tags
+            .into_iter()
+            .map(|tag| (tag, OutboundTraffic::default()))
+            .collect();
+        for (tag, u) in self.usage.iter() {
+            let t = traffic.entry(tag.clone()).or_default();
+            t.sent = u.total_sent;
+            t.recvd = u.total_recvd;
+        }
+        for c in self.counters.iter() {
+            if !c.recv_completed() || !c.send_completed() {
+                traffic
+                    .entry(c.sess.outbound_tag.clone())
+                    .or_default()
+                    .active += 1;
+            }
         }
+        traffic
+    }
+
+    /// Returns the usage per inbound and client.
+    pub fn client_usage(&mut self) -> Vec<ClientUsage> {
+        self.fold_usage();
+        let mut usage: Vec<ClientUsage> = self
+            .client_usage
+            .iter()
+            .map(|((inbound_tag, client), u)| ClientUsage {
+                inbound_tag: inbound_tag.clone(),
+                client: client.clone(),
+                usage: u.clone(),
+            })
+            .collect();
+        usage.sort_by(|a, b| (&a.inbound_tag, &a.client).cmp(&(&b.inbound_tag, &b.client)));
+        usage
+    }
+
+    // Adds the bytes counted since the last call to the outbound and the
+    // client usage.
+    fn fold_usage(&mut self) {
+        let month = current_month();
+        for c in self.counters.iter_mut() {
+            let recvd = c.bytes_recvd();
+            let sent = c.bytes_sent();
+            let delta_recvd = recvd.saturating_sub(c.folded_recvd);
+            let delta_sent = sent.saturating_sub(c.folded_sent);
+            c.folded_recvd = recvd;
+            c.folded_sent = sent;
+            if delta_recvd == 0 && delta_sent == 0 {
+                continue;
+            }
+            self.usage
+                .entry(c.sess.outbound_tag.clone())
+                .or_default()
+                .add(&month, delta_sent, delta_recvd);
+            self.client_usage
+                .entry((c.sess.inbound_tag.clone(), c.sess.client()))
+                .or_default()
+                .add(&month, delta_sent, delta_recvd);
+        }
+    }
+
+    /// Saves the outbound usage to the file every interval.
+    pub fn save_task(
+        sm: super::SyncStatManager,
+        path: String,
+        interval: std::time::Duration,
+    ) -> crate::Runner {
+        Box::pin(async move {
+            loop {
+                tokio::time::sleep(interval).await;
+                if let Err(e) = sm.write().await.save_usage(&path) {
+                    log::warn!("saving outbound usage to {} failed: {}", &path, e);
+                }
+            }
+        })
     }
 
     pub fn cleanup_task(sm: super::SyncStatManager) -> crate::Runner {
@@ -195,6 +413,7 @@ impl StatManager {
             loop {
                 tokio::time::sleep(std::time::Duration::from_secs(20)).await;
                 let mut sm = sm.write().await;
+                sm.fold_usage();
                 let mut i = 0;
                 while i < sm.counters.len() {
                     if sm.counters[i].recv_completed() && sm.counters[i].send_completed() {
@@ -208,6 +427,20 @@ impl StatManager {
         })
     }
 
+    /// Zeroes the byte counters of all sessions, returns the number of
+    /// counters reset. Finished sessions are still left to the cleanup task,
+    /// the outbound usage is kept.
+    pub fn reset(&mut self) -> usize {
+        self.fold_usage();
+        for c in self.counters.iter_mut() {
+            c.bytes_recvd.store(0, Ordering::Relaxed);
+            c.bytes_sent.store(0, Ordering::Relaxed);
+            c.folded_recvd = 0;
+            c.folded_sent = 0;
+        }
+        self.counters.len()
+    }
+
     pub fn stat_stream(&mut self, stream: AnyStream, sess: Session) -> AnyStream {
         let bytes_recvd = Arc::new(AtomicU64::new(0));
         let bytes_sent = Arc::new(AtomicU64::new(0));
@@ -219,6 +452,8 @@ impl StatManager {
             bytes_sent: bytes_sent.clone(),
             recv_completed: recv_completed.clone(),
             send_completed: send_completed.clone(),
+            folded_recvd: 0,
+            folded_sent: 0,
         });
         Box::new(Stream {
             inner: stream,
@@ -244,6 +479,8 @@ impl StatManager {
             bytes_sent: bytes_sent.clone(),
             recv_completed: recv_completed.clone(),
             send_completed: send_completed.clone(),
+            folded_recvd: 0,
+            folded_sent: 0,
         });
         Box::new(Datagram {
             inner: dgram,
@@ -254,3 +491,104 @@ impl StatManager {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_usage_roundtrip() {
+        let mut usage = HashMap::new();
+        let mut u = Usage::default();
+        u.add("2024-01", 10, 20);
+        u.add("2024-02", 1, 2);
+        assert_eq!(u.month_sent, 1);
+        assert_eq!(u.total_recvd, 22);
+        usage.insert("Proxy".to_string(), u);
+        usage.insert("Direct".to_string(), Usage::default());
+        assert_eq!(parse_usage(&format_usage(&usage)), usage);
+    }
+
+    #[test]
+    fn test_usage_survives_reload() {
+        let path = std::env::temp_dir().join(format!("stat-usage-{}", std::process::id()));
+        let mut sm = StatManager::new();
+        let mut sess = Session::default();
+        sess.outbound_tag = "Proxy".to_string();
+        let (a, _b) = tokio::io::duplex(16);
+        let _s = sm.stat_stream(Box::new(a), sess);
+        sm.counters[0].bytes_sent.store(100, Ordering::Relaxed);
+        sm.save_usage(&path).unwrap();
+        sm.counters[0].bytes_sent.store(150, Ordering::Relaxed);
+        sm.save_usage(&path).unwrap();
+
+        let mut sm = StatManager::new();
+        sm.load_usage(&path).unwrap();
+        let usage = sm.usage();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(usage.len(), 1);
+        assert_eq!(usage[0].0, "Proxy");
+        assert_eq!(usage[0].1.total_sent, 150);
+        assert_eq!(usage[0].1.month, current_month());
+    }
+
+    #[test]
+    fn test_outbound_traffic() {
+        let mut sm = StatManager::new();
+        let mut sess = Session::default();
+        sess.outbound_tag = "Proxy".to_string();
+        let (a, _b) = tokio::io::duplex(16);
+        let _s1 = sm.stat_stream(Box::new(a), sess.clone());
+        let (a, _b) = tokio::io::duplex(16);
+        let _s2 = sm.stat_stream(Box::new(a), sess);
+        sm.counters[0].bytes_sent.store(10, Ordering::Relaxed);
+        sm.counters[1].bytes_recvd.store(20, Ordering::Relaxed);
+        sm.counters[1].recv_completed.store(true, Ordering::Relaxed);
+        sm.counters[1].send_completed.store(true, Ordering::Relaxed);
+
+        let traffic = sm.outbound_traffic(vec!["Direct".to_string(), "Proxy".to_string()]);
+        assert_eq!(traffic.len(), 2);
+        assert_eq!(traffic["Direct"], OutboundTraffic::default());
+        assert_eq!(
+            traffic["Proxy"],
+            OutboundTraffic {
+                sent: 10,
+                recvd: 20,
+                active: 1,
+            }
+        );
+
+        // Still counted after the counters are reset.
+        sm.reset();
+        let traffic = sm.outbound_traffic(Vec::new());
+        assert_eq!(traffic["Proxy"].sent, 10);
+    }
+
+    #[test]
+    fn test_client_usage() {
+        let mut sm = StatManager::new();
+        let mut sess = Session::default();
+        sess.inbound_tag = "socks".to_string();
+        sess.source = "192.168.1.2:50000".parse().unwrap();
+        let mut streams = Vec::new();
+        for user in [None, None, Some("alice")] {
+            let mut sess = sess.clone();
+            sess.user = user.map(ToString::to_string);
+            let (a, _b) = tokio::io::duplex(16);
+            streams.push(sm.stat_stream(Box::new(a), sess));
+        }
+        for (i, c) in sm.counters.iter().enumerate() {
+            c.bytes_sent.store(10 * (i as u64 + 1), Ordering::Relaxed);
+            c.bytes_recvd.store(100, Ordering::Relaxed);
+        }
+
+        let usage = sm.client_usage();
+        assert_eq!(usage.len(), 2);
+        assert_eq!(usage[0].client, "192.168.1.2");
+        assert_eq!(usage[0].inbound_tag, "socks");
+        assert_eq!(usage[0].usage.total_sent, 30);
+        assert_eq!(usage[0].usage.total_recvd, 200);
+        assert_eq!(usage[1].client, "alice");
+        assert_eq!(usage[1].usage.total_sent, 30);
+    }
+}
@@ -1,7 +1,7 @@
 use std::net::{Ipv4Addr, Ipv6Addr};
 use std::process::Command;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 
 pub fn get_default_ipv4_gateway() -> Result<String> {
     let out = Command::new("ip")
@@ -10,17 +10,21 @@ pub fn get_default_ipv4_gateway() -> Result<String> {
         .arg("1")
         .output()
         .expect("failed to execute command");
-    assert!(out.status.success());
+    // The network may not be up yet.
+    if !out.status.success() {
+        return Err(anyhow!("no default route"));
+    }
     let out = String::from_utf8_lossy(&out.stdout).to_string();
     let cols: Vec<&str> = out
         .lines()
-        .filter(|l| l.contains("via"))
-        .next()
-        .unwrap()
+        .find(|l| l.contains("via"))
+        .ok_or_else(|| anyhow!("no default gateway"))?
         .split_whitespace()
         .map(str::trim)
         .collect();
-    assert!(cols.len() >= 3);
+    if cols.len() < 3 {
+        return Err(anyhow!("unexpected route {}", out.trim()));
+    }
     let res = cols[2].to_string();
     Ok(res)
 }
@@ -33,17 +37,21 @@ pub fn get_default_ipv6_gateway() -> Result<String> {
         .arg("::2")
         .output()
         .expect("failed to execute command");
-    assert!(out.status.success());
+    // Not an error worth panicking, the host may simply have no IPv6 connectivity.
+    if !out.status.success() {
+        return Err(anyhow!("no default ipv6 route"));
+    }
     let out = String::from_utf8_lossy(&out.stdout).to_string();
     let cols: Vec<&str> = out
         .lines()
-        .filter(|l| l.contains("via"))
-        .next()
-        .unwrap()
+        .find(|l| l.contains("via"))
+        .ok_or_else(|| anyhow!("no default ipv6 gateway"))?
         .split_whitespace()
         .map(str::trim)
         .collect();
-    assert!(cols.len() >= 5);
+    if cols.len() < 5 {
+        return Err(anyhow!("unexpected ipv6 route: {}", out));
+    }
     let res = cols[4].to_string();
     Ok(res)
 }
@@ -100,17 +108,21 @@ pub fn get_default_interface() -> Result<String> {
         .arg("1")
         .output()
         .expect("failed to execute command");
-    assert!(out.status.success());
+    // The network may not be up yet.
+    if !out.status.success() {
+        return Err(anyhow!("no default route"));
+    }
     let out = String::from_utf8_lossy(&out.stdout).to_string();
     let cols: Vec<&str> = out
         .lines()
-        .filter(|l| l.contains("via"))
-        .next()
-        .unwrap()
+        .find(|l| l.contains("via"))
+        .ok_or_else(|| anyhow!("no default gateway"))?
         .split_whitespace()
         .map(str::trim)
         .collect();
-    assert!(cols.len() >= 5);
+    if cols.len() < 5 {
+        return Err(anyhow!("unexpected route {}", out.trim()));
+    }
     let res = cols[4].to_string();
     Ok(res)
 }
@@ -124,7 +136,7 @@ pub fn add_interface_ipv4_address(
     Command::new("ip")
         .arg("addr")
         .arg("add")
-        .arg(format!("{}/{}", addr.to_string(), mask.to_string()))
+        .arg(format!("{}/{}", addr, u32::from(mask).count_ones()))
         .arg("dev")
         .arg(name.to_string())
         .status()
@@ -398,3 +410,86 @@ pub fn delete_iptable_forward(interface: &str) -> Result<()> {
         .expect("failed to execute command");
     Ok(())
 }
+
+pub fn add_tun_device(name: &str, user: u32) -> Result<()> {
+    let status = Command::new("ip")
+        .arg("tuntap")
+        .arg("add")
+        .arg("mode")
+        .arg("tun")
+        .arg("dev")
+        .arg(name)
+        .arg("user")
+        .arg(user.to_string())
+        .status()?;
+    if !status.success() {
+        return Err(anyhow!("add tun device {} failed: {}", name, status));
+    }
+    Ok(())
+}
+
+pub fn delete_tun_device(name: &str) -> Result<()> {
+    let status = Command::new("ip")
+        .arg("tuntap")
+        .arg("del")
+        .arg("mode")
+        .arg("tun")
+        .arg("dev")
+        .arg(name)
+        .status()?;
+    if !status.success() {
+        return Err(anyhow!("delete tun device {} failed: {}", name, status));
+    }
+    Ok(())
+}
+
+pub fn set_link_up(name: &str) -> Result<()> {
+    let status = Command::new("ip")
+        .arg("link")
+        .arg("set")
+        .arg("dev")
+        .arg(name)
+        .arg("up")
+        .status()?;
+    if !status.success() {
+        return Err(anyhow!("set {} up failed: {}", name, status));
+    }
+    Ok(())
+}
+
+pub fn delete_default_ipv4_route_via(gateway: Ipv4Addr) -> Result<()> {
+    let status = Command::new("ip")
+        .arg("route")
+        .arg("del")
+        .arg("default")
+        .arg("via")
+        .arg(gateway.to_string())
+        .status()?;
+    if !status.success() {
+        return Err(anyhow!(
+            "delete default route via {} failed: {}",
+            gateway,
+            status
+        ));
+    }
+    Ok(())
+}
+
+pub fn delete_default_ipv6_route_via(gateway: Ipv6Addr) -> Result<()> {
+    let status = Command::new("ip")
+        .arg("-6")
+        .arg("route")
+        .arg("del")
+        .arg("default")
+        .arg("via")
+        .arg(gateway.to_string())
+        .status()?;
+    if !status.success() {
+        return Err(anyhow!(
+            "delete default route via {} failed: {}",
+            gateway,
+            status
+        ));
+    }
+    Ok(())
+}
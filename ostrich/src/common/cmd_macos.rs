@@ -1,7 +1,7 @@
 use std::net::{Ipv4Addr, Ipv6Addr};
 use std::process::Command;
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 
 pub fn get_default_ipv4_gateway() -> Result<String> {
     let out = Command::new("route")
@@ -10,16 +10,21 @@ pub fn get_default_ipv4_gateway() -> Result<String> {
         .arg("1")
         .output()
         .expect("failed to execute command");
-    assert!(out.status.success());
+    // The network may not be up yet.
+    if !out.status.success() {
+        return Err(anyhow!("no default route"));
+    }
     let out = String::from_utf8_lossy(&out.stdout).to_string();
     let cols: Vec<&str> = out
         .lines()
         .find(|l| l.contains("gateway"))
-        .unwrap()
+        .ok_or_else(|| anyhow!("no default gateway"))?
         .split_whitespace()
         .map(str::trim)
         .collect();
-    assert!(cols.len() == 2);
+    if cols.len() != 2 {
+        return Err(anyhow!("unexpected route {}", out.trim()));
+    }
     let res = cols[1].to_string();
     Ok(res)
 }
@@ -32,16 +37,21 @@ pub fn get_default_ipv6_gateway() -> Result<String> {
         .arg("::2")
         .output()
         .expect("failed to execute command");
-    assert!(out.status.success());
+    // Not an error worth panicking, the host may simply have no IPv6 connectivity.
+    if !out.status.success() {
+        return Err(anyhow!("no default ipv6 route"));
+    }
     let out = String::from_utf8_lossy(&out.stdout).to_string();
     let cols: Vec<&str> = out
         .lines()
         .find(|l| l.contains("gateway"))
-        .unwrap()
+        .ok_or_else(|| anyhow!("no default ipv6 gateway"))?
         .split_whitespace()
         .map(str::trim)
         .collect();
-    assert!(cols.len() == 2);
+    if cols.len() != 2 {
+        return Err(anyhow!("unexpected ipv6 route: {}", out));
+    }
     let parts: Vec<&str> = cols[1].split('%').map(str::trim).collect();
     assert!(!parts.is_empty());
     let res = parts[0].to_string();
@@ -55,16 +65,21 @@ pub fn get_default_interface() -> Result<String> {
         .arg("1")
         .output()
         .expect("failed to execute command");
-    assert!(out.status.success());
+    // The network may not be up yet.
+    if !out.status.success() {
+        return Err(anyhow!("no default route"));
+    }
     let out = String::from_utf8_lossy(&out.stdout).to_string();
     let cols: Vec<&str> = out
         .lines()
         .find(|l| l.contains("interface"))
-        .unwrap()
+        .ok_or_else(|| anyhow!("no default interface"))?
         .split_whitespace()
         .map(str::trim)
         .collect();
-    assert!(cols.len() == 2);
+    if cols.len() != 2 {
+        return Err(anyhow!("unexpected route {}", out.trim()));
+    }
     let res = cols[1].to_string();
     Ok(res)
 }
@@ -244,3 +259,46 @@ pub fn set_ipv6_forwarding(val: bool) -> Result<()> {
         .expect("failed to execute command");
     Ok(())
 }
+
+pub fn set_link_up(name: &str) -> Result<()> {
+    let status = Command::new("ifconfig").arg(name).arg("up").status()?;
+    if !status.success() {
+        return Err(anyhow!("set {} up failed: {}", name, status));
+    }
+    Ok(())
+}
+
+pub fn delete_default_ipv4_route_via(gateway: Ipv4Addr) -> Result<()> {
+    let status = Command::new("route")
+        .arg("-n")
+        .arg("delete")
+        .arg("default")
+        .arg(gateway.to_string())
+        .status()?;
+    if !status.success() {
+        return Err(anyhow!(
+            "delete default route via {} failed: {}",
+            gateway,
+            status
+        ));
+    }
+    Ok(())
+}
+
+pub fn delete_default_ipv6_route_via(gateway: Ipv6Addr) -> Result<()> {
+    let status = Command::new("route")
+        .arg("-n")
+        .arg("delete")
+        .arg("-inet6")
+        .arg("default")
+        .arg(gateway.to_string())
+        .status()?;
+    if !status.success() {
+        return Err(anyhow!(
+            "delete default route via {} failed: {}",
+            gateway,
+            status
+        ));
+    }
+    Ok(())
+}
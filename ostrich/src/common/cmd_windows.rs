@@ -33,12 +33,50 @@ pub fn get_default_ipv6_gateway() -> Result<String> {
     Ok(ipv6_gateway)
 }
 
+// Returns the adapter of the default IPv4 gateway, the one with the lowest
+// metric if several.
+fn get_default_adapter() -> Result<ipconfig::Adapter> {
+    let gateway: IpAddr = get_default_ipv4_gateway()?.parse()?;
+    let mut adapters = ipconfig::get_adapters()?;
+    adapters.sort_by(|ip1, ip2| ip1.ipv4_metric().cmp(&ip2.ipv4_metric()));
+    adapters
+        .into_iter()
+        .find(|adapter| adapter.gateways().contains(&gateway))
+        .ok_or_else(|| anyhow::anyhow!("no adapter found for default gateway {}", gateway))
+}
+
 pub fn get_default_ipv4_address() -> Result<String> {
-    todo!()
+    let adapter = get_default_adapter()?;
+    adapter
+        .ip_addresses()
+        .iter()
+        .find(|ip| ip.is_ipv4())
+        .map(|ip| ip.to_string())
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "no ipv4 address found on adapter {}",
+                adapter.friendly_name()
+            )
+        })
 }
 
 pub fn get_default_ipv6_address() -> Result<String> {
-    todo!()
+    let adapter = get_default_adapter()?;
+    adapter
+        .ip_addresses()
+        .iter()
+        .find(|ip| match ip {
+            // Skips link-local addresses, fe80::/10.
+            IpAddr::V6(ip) => ip.segments()[0] & 0xffc0 != 0xfe80,
+            IpAddr::V4(..) => false,
+        })
+        .map(|ip| ip.to_string())
+        .ok_or_else(|| {
+            anyhow::anyhow!(
+                "no ipv6 address found on adapter {}",
+                adapter.friendly_name()
+            )
+        })
 }
 
 pub fn get_default_interface() -> Result<String> {
@@ -0,0 +1,199 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use bytes::{Buf, BufMut, BytesMut};
+use futures::ready;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+// Most bytes of a write put in one frame.
+const MAX_CHUNK: usize = 16 * 1024;
+// Frame header: a flag and the big-endian length of the payload.
+const HEADER_LEN: usize = 3;
+const FLAG_RAW: u8 = 0;
+const FLAG_LZ4: u8 = 1;
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+/// Appends a frame carrying `data` to `buf`, LZ4 compressed if that makes it
+/// smaller.
+fn encode_frame(data: &[u8], buf: &mut BytesMut) {
+    let compressed = lz4_flex::block::compress_prepend_size(data);
+    let (flag, payload) = if compressed.len() < data.len() {
+        (FLAG_LZ4, compressed.as_slice())
+    } else {
+        (FLAG_RAW, data)
+    };
+    buf.put_u8(flag);
+    buf.put_u16(payload.len() as u16);
+    buf.put_slice(payload);
+}
+
+/// Decodes the frame at the beginning of `buf` into `out` and consumes it,
+/// returns false if the frame is not complete yet.
+fn decode_frame(buf: &mut BytesMut, out: &mut BytesMut) -> io::Result<bool> {
+    if buf.len() < HEADER_LEN {
+        return Ok(false);
+    }
+    let len = u16::from_be_bytes([buf[1], buf[2]]) as usize;
+    if buf.len() < HEADER_LEN + len {
+        return Ok(false);
+    }
+    let flag = buf[0];
+    buf.advance(HEADER_LEN);
+    let payload = buf.split_to(len);
+    match flag {
+        FLAG_RAW => out.put_slice(&payload),
+        FLAG_LZ4 => {
+            // Sizes above a frame are made up, don't allocate for them.
+            if payload.len() < 4
+                || u32::from_le_bytes([payload[0], payload[1], payload[2], payload[3]]) as usize
+                    > MAX_CHUNK
+            {
+                return Err(invalid_data("invalid compressed frame"));
+            }
+            let data = lz4_flex::block::decompress_size_prepended(&payload)
+                .map_err(|_| invalid_data("invalid compressed frame"))?;
+            out.put_slice(&data);
+        }
+        _ => return Err(invalid_data("invalid frame flag")),
+    }
+    Ok(true)
+}
+
+/// Compresses what's written to the inner stream and decompresses what's
+/// read from it, in frames of up to 16KB. Both ends must use it.
+///
+/// Compressing data an attacker can partly choose next to secrets leaks the
+/// secrets through the sizes, so it's meant for plaintext protocols only,
+/// never for TLS payloads.
+pub struct CompressStream<S> {
+    inner: S,
+    // Bytes read from the inner stream not decoded yet.
+    rbuf: BytesMut,
+    // Decoded bytes not read yet.
+    decoded: BytesMut,
+    // Encoded frames not written to the inner stream yet.
+    wbuf: BytesMut,
+}
+
+impl<S> CompressStream<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            rbuf: BytesMut::new(),
+            decoded: BytesMut::new(),
+            wbuf: BytesMut::new(),
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> CompressStream<S> {
+    fn poll_write_pending(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+        while !self.wbuf.is_empty() {
+            let n = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.wbuf))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.wbuf.advance(n);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for CompressStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut ReadBuf,
+    ) -> Poll<io::Result<()>> {
+        let me = self.get_mut();
+        loop {
+            if !me.decoded.is_empty() {
+                let n = std::cmp::min(buf.remaining(), me.decoded.len());
+                buf.put_slice(&me.decoded.split_to(n));
+                return Poll::Ready(Ok(()));
+            }
+            if decode_frame(&mut me.rbuf, &mut me.decoded)? {
+                continue;
+            }
+            let mut tmp = [0u8; 4 * 1024];
+            let mut tmp_buf = ReadBuf::new(&mut tmp);
+            ready!(Pin::new(&mut me.inner).poll_read(cx, &mut tmp_buf))?;
+            if tmp_buf.filled().is_empty() {
+                if me.rbuf.is_empty() {
+                    return Poll::Ready(Ok(()));
+                }
+                return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+            }
+            me.rbuf.put_slice(tmp_buf.filled());
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for CompressStream<S> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let me = self.get_mut();
+        ready!(me.poll_write_pending(cx))?;
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        let n = std::cmp::min(buf.len(), MAX_CHUNK);
+        encode_frame(&buf[..n], &mut me.wbuf);
+        // The frame is accepted as a whole, what's left goes out on the next
+        // write or flush.
+        let _ = me.poll_write_pending(cx)?;
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let me = self.get_mut();
+        ready!(me.poll_write_pending(cx))?;
+        Pin::new(&mut me.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let me = self.get_mut();
+        ready!(me.poll_write_pending(cx))?;
+        Pin::new(&mut me.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn test_compress_stream() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let (a, mut b) = tokio::io::duplex(64 * 1024);
+            let mut a = CompressStream::new(a);
+
+            let text = "GET / HTTP/1.1\r\nHost: example.com\r\n\r\n".repeat(2000);
+            a.write_all(text.as_bytes()).await.unwrap();
+            a.shutdown().await.unwrap();
+            let mut wire = Vec::new();
+            b.read_to_end(&mut wire).await.unwrap();
+            assert!(wire.len() < text.len() / 4);
+
+            let mut r = CompressStream::new(wire.as_slice());
+            let mut out = Vec::new();
+            r.read_to_end(&mut out).await.unwrap();
+            assert_eq!(out, text.as_bytes());
+
+            // Incompressible data goes raw, a truncated frame is an error.
+            let mut wire = BytesMut::new();
+            encode_frame(&[1, 2, 3], &mut wire);
+            assert_eq!(&wire[..], &[FLAG_RAW, 0, 3, 1, 2, 3]);
+            let mut r = CompressStream::new(&wire[..4]);
+            assert!(r.read_to_end(&mut Vec::new()).await.is_err());
+        });
+    }
+}
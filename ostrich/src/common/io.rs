@@ -4,8 +4,14 @@ use std::pin::Pin;
 use std::task::{Context, Poll};
 use std::time::Duration;
 
-use futures::ready;
 use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::time::Sleep;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stall {
+    Read,
+    Write,
+}
 
 #[derive(Debug)]
 pub struct CopyBuffer {
@@ -15,6 +21,10 @@ pub struct CopyBuffer {
     cap: usize,
     amt: u64,
     buf: Box<[u8]>,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    // The pending operation and when it times out.
+    stall: Option<(Stall, Pin<Box<Sleep>>)>,
 }
 
 impl CopyBuffer {
@@ -26,6 +36,9 @@ impl CopyBuffer {
             cap: 0,
             amt: 0,
             buf: vec![0; 2 * 1024].into_boxed_slice(),
+            read_timeout: None,
+            write_timeout: None,
+            stall: None,
         }
     }
 
@@ -45,13 +58,56 @@ impl CopyBuffer {
             cap: 0,
             amt: 0,
             buf: buf.into_boxed_slice(),
+            read_timeout: None,
+            write_timeout: None,
+            stall: None,
         })
     }
 
+    /// Limits how long a single read or write may be pending, the copy fails
+    /// with `TimedOut` once exceeded.
+    pub fn with_timeouts(
+        mut self,
+        read_timeout: Option<Duration>,
+        write_timeout: Option<Duration>,
+    ) -> Self {
+        self.read_timeout = read_timeout;
+        self.write_timeout = write_timeout;
+        self
+    }
+
     pub fn amount_transfered(&self) -> u64 {
         self.amt
     }
 
+    // Called when the reader or writer is pending, fails if it has been
+    // pending for longer than the timeout of the operation.
+    fn poll_stall(&mut self, cx: &mut Context<'_>, op: Stall) -> io::Result<()> {
+        let timeout = match op {
+            Stall::Read => self.read_timeout,
+            Stall::Write => self.write_timeout,
+        };
+        let timeout = if let Some(t) = timeout {
+            t
+        } else {
+            return Ok(());
+        };
+        match &self.stall {
+            Some((o, _)) if *o == op => (),
+            _ => self.stall = Some((op, Box::pin(tokio::time::sleep(timeout)))),
+        }
+        if let Some((_, delay)) = self.stall.as_mut() {
+            if delay.as_mut().poll(cx).is_ready() {
+                let msg = match op {
+                    Stall::Read => "read timed out",
+                    Stall::Write => "write timed out",
+                };
+                return Err(io::Error::new(io::ErrorKind::TimedOut, msg));
+            }
+        }
+        Ok(())
+    }
+
     pub fn poll_copy<R, W>(
         &mut self,
         cx: &mut Context<'_>,
@@ -70,16 +126,20 @@ impl CopyBuffer {
                 let mut buf = ReadBuf::new(&mut me.buf);
 
                 match reader.as_mut().poll_read(cx, &mut buf) {
-                    Poll::Ready(Ok(_)) => (),
+                    Poll::Ready(Ok(_)) => me.stall = None,
                     Poll::Ready(Err(err)) => return Poll::Ready(Err(err)),
                     Poll::Pending => {
                         // Try flushing when the reader has no progress to avoid deadlock
                         // when the reader depends on buffered writer.
                         if self.need_flush {
-                            ready!(writer.as_mut().poll_flush(cx))?;
+                            if writer.as_mut().poll_flush(cx)?.is_pending() {
+                                self.poll_stall(cx, Stall::Write)?;
+                                return Poll::Pending;
+                            }
                             self.need_flush = false;
                         }
 
+                        self.poll_stall(cx, Stall::Read)?;
                         return Poll::Pending;
                     }
                 }
@@ -96,7 +156,16 @@ impl CopyBuffer {
             // If our buffer has some data, let's write it out!
             while self.pos < self.cap {
                 let me = &mut *self;
-                let i = ready!(writer.as_mut().poll_write(cx, &me.buf[me.pos..me.cap]))?;
+                let i = match writer.as_mut().poll_write(cx, &me.buf[me.pos..me.cap]) {
+                    Poll::Ready(res) => {
+                        me.stall = None;
+                        res?
+                    }
+                    Poll::Pending => {
+                        self.poll_stall(cx, Stall::Write)?;
+                        return Poll::Pending;
+                    }
+                };
                 if i == 0 {
                     return Poll::Ready(Err(io::Error::new(
                         io::ErrorKind::WriteZero,
@@ -120,7 +189,10 @@ impl CopyBuffer {
             // If we've written all the data and we've seen EOF, flush out the
             // data and finish the transfer.
             if self.pos == self.cap && self.read_done {
-                ready!(writer.as_mut().poll_flush(cx))?;
+                if writer.as_mut().poll_flush(cx)?.is_pending() {
+                    self.poll_stall(cx, Stall::Write)?;
+                    return Poll::Pending;
+                }
                 return Poll::Ready(Ok(self.amt));
             }
         }
@@ -268,16 +340,20 @@ pub async fn copy_buf_bidirectional_with_timeout<A, B>(
     size: usize,
     a_to_b_timeout_duration: Duration,
     b_to_a_timeout_duration: Duration,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
 ) -> Result<(u64, u64), std::io::Error>
 where
     A: AsyncRead + AsyncWrite + Unpin + ?Sized,
     B: AsyncRead + AsyncWrite + Unpin + ?Sized,
 {
+    let a_to_b = CopyBuffer::new_with_capacity(size)?.with_timeouts(read_timeout, write_timeout);
+    let b_to_a = CopyBuffer::new_with_capacity(size)?.with_timeouts(read_timeout, write_timeout);
     CopyBidirectional {
         a,
         b,
-        a_to_b: TransferState::Running(CopyBuffer::new_with_capacity(size)?),
-        b_to_a: TransferState::Running(CopyBuffer::new_with_capacity(size)?),
+        a_to_b: TransferState::Running(a_to_b),
+        b_to_a: TransferState::Running(b_to_a),
         a_to_b_count: 0,
         b_to_a_count: 0,
         a_to_b_delay: None,
@@ -287,3 +363,60 @@ where
     }
     .await
 }
+
+/// Relays between the inbound and outbound streams of a TCP session until
+/// both sides are done, with the buffer size and timeouts from the options.
+/// Returns the bytes sent and received.
+pub async fn relay<A, B>(lhs: &mut A, rhs: &mut B) -> io::Result<(u64, u64)>
+where
+    A: AsyncRead + AsyncWrite + Unpin + ?Sized,
+    B: AsyncRead + AsyncWrite + Unpin + ?Sized,
+{
+    copy_buf_bidirectional_with_timeout(
+        lhs,
+        rhs,
+        *crate::option::LINK_BUFFER_SIZE * 1024,
+        Duration::from_secs(*crate::option::TCP_UPLINK_TIMEOUT),
+        Duration::from_secs(*crate::option::TCP_DOWNLINK_TIMEOUT),
+        Some(*crate::option::TCP_READ_TIMEOUT)
+            .filter(|t| *t > 0)
+            .map(Duration::from_secs),
+        Some(*crate::option::TCP_WRITE_TIMEOUT)
+            .filter(|t| *t > 0)
+            .map(Duration::from_secs),
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_copy_read_timeout() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            // Peers on the other ends are kept alive but never send anything.
+            let (mut a, _a_peer) = tokio::io::duplex(64);
+            let (mut b, _b_peer) = tokio::io::duplex(64);
+            let res = tokio::time::timeout(
+                Duration::from_secs(5),
+                copy_buf_bidirectional_with_timeout(
+                    &mut a,
+                    &mut b,
+                    1024,
+                    Duration::from_secs(10),
+                    Duration::from_secs(10),
+                    Some(Duration::from_millis(100)),
+                    None,
+                ),
+            )
+            .await
+            .expect("wedged read not aborted");
+            assert_eq!(res.unwrap_err().kind(), io::ErrorKind::TimedOut);
+        });
+    }
+}
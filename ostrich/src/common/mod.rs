@@ -1,8 +1,12 @@
+pub mod compress;
 pub mod crypto;
 pub mod io;
 pub mod net;
+pub mod proxy_protocol;
 pub mod resolver;
 pub mod sniff;
+#[cfg(feature = "rustls-tls")]
+pub mod tls;
 
 #[cfg(target_os = "macos")]
 pub mod cmd_macos;
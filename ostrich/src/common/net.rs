@@ -2,6 +2,16 @@ use std::net::{SocketAddr, SocketAddrV6};
 
 use anyhow::{anyhow, Result};
 
+/// Parses a firewall mark, in decimal or hex with the `0x` prefix.
+pub fn parse_fwmark(s: &str) -> Result<u32> {
+    let s = s.trim();
+    let mark = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse::<u32>(),
+    };
+    mark.map_err(|_| anyhow!("invalid fwmark {}", s))
+}
+
 pub fn parse_bind_addr(bind: &str) -> Result<SocketAddr> {
     let mut split = bind.split('%');
     let ip_addr = split.next().ok_or_else(|| anyhow!("Empty bind address"))?;
@@ -21,3 +31,18 @@ pub fn parse_bind_addr(bind: &str) -> Result<SocketAddr> {
         None => Ok(SocketAddr::new(ip_addr.parse()?, 0)),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_fwmark() {
+        assert_eq!(parse_fwmark("255").unwrap(), 255);
+        assert_eq!(parse_fwmark("0x1ff").unwrap(), 0x1ff);
+        assert_eq!(parse_fwmark("0XFFFFFFFF").unwrap(), u32::MAX);
+        assert!(parse_fwmark("0x").is_err());
+        assert!(parse_fwmark("-1").is_err());
+        assert!(parse_fwmark("mark").is_err());
+    }
+}
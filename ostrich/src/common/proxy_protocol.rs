@@ -0,0 +1,218 @@
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+
+use anyhow::{anyhow, Error};
+use bytes::{BufMut, BytesMut};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+// https://www.haproxy.org/download/2.8/doc/proxy-protocol.txt
+
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+// Version 2, PROXY command.
+const V2_PROXY: u8 = 0x21;
+const V2_TCP4: u8 = 0x11;
+const V2_TCP6: u8 = 0x21;
+// Version 2, LOCAL command, e.g. health checks from the balancer itself.
+const V2_LOCAL: u8 = 0x20;
+// The longest possible v1 header, including the CRLF.
+const V1_MAX_LEN: usize = 107;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    V1,
+    V2,
+}
+
+impl FromStr for Version {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "v1" | "1" => Ok(Version::V1),
+            "v2" | "2" => Ok(Version::V2),
+            _ => Err(anyhow!("unknown proxy protocol version: {}", s)),
+        }
+    }
+}
+
+// Addresses of different families are both sent as IPv6.
+fn same_family(src: &SocketAddr, dst: &SocketAddr) -> (IpAddr, IpAddr) {
+    match (src.ip(), dst.ip()) {
+        (IpAddr::V4(s), IpAddr::V6(d)) => (IpAddr::V6(s.to_ipv6_mapped()), IpAddr::V6(d)),
+        (IpAddr::V6(s), IpAddr::V4(d)) => (IpAddr::V6(s), IpAddr::V6(d.to_ipv6_mapped())),
+        (s, d) => (s, d),
+    }
+}
+
+/// Encodes the header telling the upstream about a TCP connection from `src`
+/// to `dst`.
+pub fn encode_header(version: Version, src: &SocketAddr, dst: &SocketAddr) -> BytesMut {
+    let (src_ip, dst_ip) = same_family(src, dst);
+    let mut buf = BytesMut::new();
+    match version {
+        Version::V1 => {
+            let proto = if src_ip.is_ipv4() { "TCP4" } else { "TCP6" };
+            buf.put_slice(
+                format!(
+                    "PROXY {} {} {} {} {}\r\n",
+                    proto,
+                    src_ip,
+                    dst_ip,
+                    src.port(),
+                    dst.port()
+                )
+                .as_bytes(),
+            );
+        }
+        Version::V2 => {
+            buf.put_slice(V2_SIGNATURE);
+            buf.put_u8(V2_PROXY);
+            match (src_ip, dst_ip) {
+                (IpAddr::V4(s), IpAddr::V4(d)) => {
+                    buf.put_u8(V2_TCP4);
+                    buf.put_u16(4 + 4 + 2 + 2);
+                    buf.put_slice(&s.octets());
+                    buf.put_slice(&d.octets());
+                }
+                (IpAddr::V6(s), IpAddr::V6(d)) => {
+                    buf.put_u8(V2_TCP6);
+                    buf.put_u16(16 + 16 + 2 + 2);
+                    buf.put_slice(&s.octets());
+                    buf.put_slice(&d.octets());
+                }
+                _ => unreachable!(),
+            }
+            buf.put_u16(src.port());
+            buf.put_u16(dst.port());
+        }
+    }
+    buf
+}
+
+fn invalid<E: Into<Box<dyn std::error::Error + Send + Sync>>>(e: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e)
+}
+
+fn parse_v1(line: &str) -> io::Result<Option<SocketAddr>> {
+    let parts: Vec<&str> = line.split(' ').collect();
+    match parts.get(1) {
+        Some(&"UNKNOWN") => return Ok(None),
+        Some(&"TCP4") | Some(&"TCP6") if parts.len() == 6 => (),
+        _ => return Err(invalid(format!("invalid proxy protocol header: {}", line))),
+    }
+    let ip: IpAddr = parts[2].parse().map_err(invalid)?;
+    let port: u16 = parts[4].parse().map_err(invalid)?;
+    Ok(Some(SocketAddr::new(ip, port)))
+}
+
+/// Reads the header in front of an accepted connection, returning the source
+/// address of the original client, or `None` if the sender didn't tell, e.g.
+/// `UNKNOWN` or a `LOCAL` command. Nothing past the header is consumed.
+pub async fn read_header<S>(stream: &mut S) -> io::Result<Option<SocketAddr>>
+where
+    S: AsyncRead + Unpin,
+{
+    // The shortest v1 header, `PROXY UNKNOWN\r\n`, is longer than the v2
+    // signature.
+    let mut buf = vec![0u8; V2_SIGNATURE.len()];
+    stream.read_exact(&mut buf).await?;
+
+    if buf == V2_SIGNATURE {
+        let mut head = [0u8; 4];
+        stream.read_exact(&mut head).await?;
+        let mut addrs = vec![0u8; u16::from_be_bytes([head[2], head[3]]) as usize];
+        stream.read_exact(&mut addrs).await?;
+        return match (head[0], head[1]) {
+            (V2_LOCAL, _) => Ok(None),
+            (V2_PROXY, V2_TCP4) if addrs.len() >= 12 => {
+                let ip = Ipv4Addr::new(addrs[0], addrs[1], addrs[2], addrs[3]);
+                let port = u16::from_be_bytes([addrs[8], addrs[9]]);
+                Ok(Some(SocketAddr::new(IpAddr::V4(ip), port)))
+            }
+            (V2_PROXY, V2_TCP6) if addrs.len() >= 36 => {
+                let mut octets = [0u8; 16];
+                octets.copy_from_slice(&addrs[..16]);
+                let port = u16::from_be_bytes([addrs[32], addrs[33]]);
+                Ok(Some(SocketAddr::new(
+                    IpAddr::V6(Ipv6Addr::from(octets)),
+                    port,
+                )))
+            }
+            (V2_PROXY, _) => Ok(None),
+            (cmd, fam) => Err(invalid(format!(
+                "invalid proxy protocol v2 header: {:#x} {:#x}",
+                cmd, fam
+            ))),
+        };
+    }
+
+    if !buf.starts_with(b"PROXY ") {
+        return Err(invalid("missing proxy protocol header"));
+    }
+    // Read byte by byte to not consume the payload following the header.
+    while !buf.ends_with(b"\r\n") {
+        if buf.len() >= V1_MAX_LEN {
+            return Err(invalid("proxy protocol v1 header too long"));
+        }
+        buf.push(stream.read_u8().await?);
+    }
+    let line = std::str::from_utf8(&buf[..buf.len() - 2]).map_err(invalid)?;
+    parse_v1(line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_header() {
+        let src: SocketAddr = "192.168.1.2:51000".parse().unwrap();
+        let dst: SocketAddr = "1.2.3.4:443".parse().unwrap();
+        assert_eq!(
+            &encode_header(Version::V1, &src, &dst)[..],
+            b"PROXY TCP4 192.168.1.2 1.2.3.4 51000 443\r\n"
+        );
+
+        let v2 = encode_header(Version::V2, &src, &dst);
+        assert_eq!(&v2[..12], V2_SIGNATURE);
+        assert_eq!(&v2[12..16], &[0x21, 0x11, 0, 12]);
+        assert_eq!(
+            &v2[16..],
+            &[192, 168, 1, 2, 1, 2, 3, 4, 0xc7, 0x38, 0x01, 0xbb]
+        );
+
+        let dst: SocketAddr = "[2001:db8::1]:443".parse().unwrap();
+        assert_eq!(
+            &encode_header(Version::V1, &src, &dst)[..],
+            b"PROXY TCP6 ::ffff:192.168.1.2 2001:db8::1 51000 443\r\n"
+        );
+        assert_eq!(encode_header(Version::V2, &src, &dst).len(), 16 + 36);
+    }
+
+    #[test]
+    fn test_read_header() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let src: SocketAddr = "192.168.1.2:51000".parse().unwrap();
+        for dst in ["1.2.3.4:443", "[2001:db8::1]:443"] {
+            let dst: SocketAddr = dst.parse().unwrap();
+            for version in [Version::V1, Version::V2] {
+                let mut data = encode_header(version, &src, &dst).to_vec();
+                data.extend_from_slice(b"payload");
+                let mut r = &data[..];
+                let parsed = rt.block_on(read_header(&mut r)).unwrap().unwrap();
+                assert_eq!(parsed.port(), src.port());
+                assert_eq!(r, b"payload");
+            }
+        }
+
+        let mut r = &b"PROXY UNKNOWN\r\npayload"[..];
+        assert_eq!(rt.block_on(read_header(&mut r)).unwrap(), None);
+        assert_eq!(r, b"payload");
+
+        let mut r = &b"GET / HTTP/1.1\r\n\r\n"[..];
+        assert!(rt.block_on(read_header(&mut r)).is_err());
+    }
+}
@@ -1,7 +1,6 @@
 use std::net::SocketAddr;
 
 use anyhow::{anyhow, Result};
-use futures::TryFutureExt;
 use rand::prelude::SliceRandom;
 use rand::rngs::StdRng;
 use rand::SeedableRng;
@@ -14,19 +13,23 @@ pub struct Resolver {
 }
 
 impl Resolver {
+    /// Resolves the address, `destination` tells whether it's where a direct
+    /// outbound connects to rather than the server of a proxy.
     pub async fn new<'a>(
         dns_client: SyncDnsClient,
         address: &'a String,
         port: &'a u16,
+        destination: bool,
     ) -> Result<Self> {
-        let mut ips = {
-            dns_client
-                .read()
-                .await
-                .direct_lookup(address)
-                .map_err(|e| anyhow!("lookup {} failed: {}", address, e))
-                .await?
+        let ips = {
+            let dns_client = dns_client.read().await;
+            if destination {
+                dns_client.destination_lookup(address).await
+            } else {
+                dns_client.direct_lookup(address).await
+            }
         };
+        let mut ips = ips.map_err(|e| anyhow!("lookup {} failed: {}", address, e))?;
         match *crate::option::OUTBOUND_DIAL_ORDER {
             DialOrder::Ordered => ips.reverse(),
             DialOrder::Random => ips.shuffle(&mut StdRng::from_entropy()),
@@ -7,11 +7,15 @@ use std::time::Duration;
 use byteorder::{BigEndian, ByteOrder};
 use bytes::BytesMut;
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, ReadBuf};
-use tokio::time::timeout;
+use tokio::time::{timeout_at, Instant};
 
 pub struct SniffingStream<T> {
     inner: T,
     buf: BytesMut,
+    alpn: Vec<String>,
+    // Gives up sniffing after this long or this many bytes.
+    timeout: Duration,
+    max_bytes: usize,
 }
 
 impl<T> SniffingStream<T>
@@ -19,17 +23,44 @@ where
     T: AsyncRead + AsyncWrite + Unpin,
 {
     pub fn new(inner: T) -> Self {
+        Self::with_bounds(
+            inner,
+            Duration::from_millis(*crate::option::SNIFF_TIMEOUT_MS),
+            *crate::option::SNIFF_MAX_BYTES,
+        )
+    }
+
+    pub fn with_bounds(inner: T, timeout: Duration, max_bytes: usize) -> Self {
         SniffingStream {
             inner,
             buf: BytesMut::new(),
+            alpn: Vec::new(),
+            timeout,
+            max_bytes,
         }
     }
 
+    /// The protocols offered in the ALPN extension of the sniffed ClientHello.
+    pub fn alpn(&self) -> &[String] {
+        &self.alpn
+    }
+
+    /// Reads the ClientHello and returns the server name in it, `None` if the
+    /// stream is not TLS, or nothing conclusive arrives within the bounds,
+    /// e.g. the server speaks first. What's read is replayed to the reader.
     pub async fn sniff(&mut self) -> io::Result<Option<String>> {
+        let deadline = Instant::now() + self.timeout;
         let mut buf = vec![0u8; 2 * 1024];
-        'outer: for _ in 0..2 {
-            match timeout(Duration::from_millis(100), self.inner.read(&mut buf)).await {
+        loop {
+            if self.buf.len() >= self.max_bytes {
+                return Ok(None);
+            }
+            let to_read = min(buf.len(), self.max_bytes - self.buf.len());
+            match timeout_at(deadline, self.inner.read(&mut buf[..to_read])).await {
                 Ok(res) => match res {
+                    Ok(0) => {
+                        return Ok(None);
+                    }
                     Ok(n) => {
                         self.buf.extend_from_slice(&buf[..n]);
 
@@ -51,65 +82,69 @@ where
                         if sbuf.len() < 5 + header_len {
                             continue;
                         }
+                        // The record is complete, anything inconsistent in it
+                        // won't be fixed by reading more.
                         let sbuf = &sbuf[5..5 + header_len];
                         // ?
                         if sbuf.len() < 42 {
-                            continue;
+                            return Ok(None);
                         }
                         let session_id_len = sbuf[38] as usize;
                         if session_id_len > 32 || sbuf.len() < 39 + session_id_len {
-                            continue;
+                            return Ok(None);
                         }
                         let sbuf = &sbuf[39 + session_id_len..];
                         if sbuf.len() < 2 {
-                            continue;
+                            return Ok(None);
                         }
                         let cipher_suite_bytes = BigEndian::read_u16(&sbuf[..2]) as usize;
                         if sbuf.len() < 2 + cipher_suite_bytes {
-                            continue;
+                            return Ok(None);
                         }
                         let sbuf = &sbuf[2 + cipher_suite_bytes..];
                         if sbuf.is_empty() {
-                            continue;
+                            return Ok(None);
                         }
                         let compression_method_bytes = sbuf[0] as usize;
                         if sbuf.len() < 1 + compression_method_bytes {
-                            continue;
+                            return Ok(None);
                         }
                         let sbuf = &sbuf[1 + compression_method_bytes..];
                         if sbuf.len() < 2 {
-                            continue;
+                            return Ok(None);
                         }
                         let extensions_bytes = BigEndian::read_u16(&sbuf[..2]) as usize;
                         if sbuf.len() < 2 + extensions_bytes {
-                            continue;
+                            return Ok(None);
                         }
                         let mut sbuf = &sbuf[2..2 + extensions_bytes];
+                        let mut server_name = None;
+                        let mut alpn = Vec::new();
                         while !sbuf.is_empty() {
                             // extension + extension-specific-len
                             if sbuf.len() < 4 {
-                                continue 'outer;
+                                return Ok(None);
                             }
                             let extension = BigEndian::read_u16(&sbuf[..2]);
                             let extension_len = BigEndian::read_u16(&sbuf[2..4]) as usize;
                             sbuf = &sbuf[4..];
                             if sbuf.len() < extension_len {
-                                continue 'outer;
+                                return Ok(None);
                             }
                             // extension "server name"
                             if extension == 0x0 {
                                 let mut ebuf = &sbuf[..extension_len];
                                 if ebuf.len() < 2 {
-                                    continue 'outer;
+                                    return Ok(None);
                                 }
                                 let entry_len = BigEndian::read_u16(&ebuf[..2]) as usize;
                                 ebuf = &ebuf[2..];
                                 if ebuf.len() < entry_len {
-                                    continue 'outer;
+                                    return Ok(None);
                                 }
                                 // just make sure no oob
                                 if ebuf.is_empty() {
-                                    continue 'outer;
+                                    return Ok(None);
                                 }
                                 let entry_type = ebuf[0];
                                 // type "DNS hostname"
@@ -117,30 +152,50 @@ where
                                     ebuf = &ebuf[1..];
                                     // just make sure no oob
                                     if ebuf.len() < 2 {
-                                        continue 'outer;
+                                        return Ok(None);
                                     }
                                     let hostname_len = BigEndian::read_u16(&ebuf[..2]) as usize;
                                     ebuf = &ebuf[2..];
                                     if ebuf.len() < hostname_len {
-                                        continue 'outer;
+                                        return Ok(None);
                                     }
-                                    return Ok(Some(
-                                        String::from_utf8_lossy(&ebuf[..hostname_len]).into(),
-                                    ));
-                                } else {
-                                    // TODO
-                                    // I assume there's only "DNS hostname" type
-                                    // in the the "server name" extension, should
-                                    // check if this is true later.
-                                    //
-                                    // I also assume there's only one entry in the
-                                    // "server name" extension list.
+                                    server_name =
+                                        Some(String::from_utf8_lossy(&ebuf[..hostname_len]).into());
+                                }
+                                // TODO
+                                // I assume there's only "DNS hostname" type
+                                // in the the "server name" extension, should
+                                // check if this is true later.
+                                //
+                                // I also assume there's only one entry in the
+                                // "server name" extension list.
+                            } else if extension == 0x10 {
+                                // extension "application layer protocol negotiation"
+                                let mut ebuf = &sbuf[..extension_len];
+                                if ebuf.len() < 2 {
                                     return Ok(None);
                                 }
-                            } else {
-                                sbuf = &sbuf[extension_len..];
+                                let list_len = BigEndian::read_u16(&ebuf[..2]) as usize;
+                                ebuf = &ebuf[2..];
+                                if ebuf.len() < list_len {
+                                    return Ok(None);
+                                }
+                                ebuf = &ebuf[..list_len];
+                                while !ebuf.is_empty() {
+                                    let proto_len = ebuf[0] as usize;
+                                    if ebuf.len() < 1 + proto_len {
+                                        return Ok(None);
+                                    }
+                                    alpn.push(
+                                        String::from_utf8_lossy(&ebuf[1..1 + proto_len]).into(),
+                                    );
+                                    ebuf = &ebuf[1 + proto_len..];
+                                }
                             }
+                            sbuf = &sbuf[extension_len..];
                         }
+                        self.alpn = alpn;
+                        return Ok(server_name);
                     }
                     Err(e) => {
                         return Err(e);
@@ -151,7 +206,6 @@ where
                 }
             }
         }
-        Ok(None)
     }
 }
 
@@ -189,3 +243,77 @@ impl<T: AsyncWrite + Unpin> AsyncWrite for SniffingStream<T> {
         AsyncWrite::poll_shutdown(Pin::new(&mut self.inner), cx)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::AsyncWriteExt;
+
+    use super::*;
+
+    #[test]
+    fn test_sniff_bounds() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            // The client sends nothing, waiting for the server to speak first.
+            let (_client, server) = tokio::io::duplex(1024);
+            let mut stream = SniffingStream::with_bounds(server, Duration::from_millis(50), 1024);
+            let start = Instant::now();
+            assert_eq!(stream.sniff().await.unwrap(), None);
+            assert!(start.elapsed() < Duration::from_secs(1));
+
+            // A handshake record longer than the bytes allowed.
+            let (mut client, server) = tokio::io::duplex(1024);
+            client
+                .write_all(&[0x16, 0x03, 0x01, 0x02, 0x00])
+                .await
+                .unwrap();
+            client.write_all(&[0u8; 100]).await.unwrap();
+            let mut stream = SniffingStream::with_bounds(server, Duration::from_secs(60), 64);
+            assert_eq!(stream.sniff().await.unwrap(), None);
+            let mut replayed = vec![0u8; 64];
+            stream.read_exact(&mut replayed).await.unwrap();
+            assert_eq!(&replayed[..5], &[0x16, 0x03, 0x01, 0x02, 0x00]);
+
+            // A complete handshake record that's not a ClientHello, with the
+            // client waiting for an answer.
+            let (mut client, server) = tokio::io::duplex(1024);
+            client
+                .write_all(&[0x16, 0x03, 0x01, 0x00, 0x30])
+                .await
+                .unwrap();
+            client.write_all(&[0xffu8; 0x30]).await.unwrap();
+            let mut stream = SniffingStream::with_bounds(server, Duration::from_secs(60), 1024);
+            let start = Instant::now();
+            assert_eq!(stream.sniff().await.unwrap(), None);
+            assert!(start.elapsed() < Duration::from_secs(1));
+        });
+    }
+
+    #[cfg(feature = "rustls-tls")]
+    #[test]
+    fn test_sniff_client_hello() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let (client, server) = tokio::io::duplex(4096);
+            let config = crate::common::tls::make_default_config(&["h2", "http/1.1"]).unwrap();
+            let connector = tokio_rustls::TlsConnector::from(config);
+            let name = tokio_rustls::rustls::ServerName::try_from("example.com").unwrap();
+            // Sends the ClientHello, nothing answers it.
+            tokio::spawn(async move {
+                let _ = connector.connect(name, client).await;
+            });
+            let mut stream = SniffingStream::with_bounds(server, Duration::from_secs(5), 4096);
+            assert_eq!(
+                stream.sniff().await.unwrap(),
+                Some("example.com".to_string())
+            );
+            assert_eq!(stream.alpn(), ["h2", "http/1.1"]);
+        });
+    }
+}
@@ -0,0 +1,326 @@
+use crate::config::TrojanOutboundSettings;
+use anyhow::{anyhow, Result};
+use sha2::{Digest, Sha256};
+use std::io::BufReader;
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+use tokio_rustls::rustls::{
+    client::{ServerCertVerified, ServerCertVerifier},
+    version::{TLS12, TLS13},
+    Certificate, ClientConfig, OwnedTrustAnchor, ServerName, SupportedCipherSuite,
+    SupportedProtocolVersion, ALL_CIPHER_SUITES,
+};
+use webpki_roots;
+
+// Accepts any server certificate, see `allow_insecure`.
+struct InsecureCertVerifier;
+
+impl ServerCertVerifier for InsecureCertVerifier {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, tokio_rustls::rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+// Accepts the server certificate only if its SHA-256 digest matches the pin,
+// the server name is not checked since it may be a dummy one.
+struct PinnedCertVerifier {
+    pin: Vec<u8>,
+}
+
+impl ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, tokio_rustls::rustls::Error> {
+        if Sha256::digest(&end_entity.0).as_slice() == self.pin.as_slice() {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(tokio_rustls::rustls::Error::General(
+                "certificate pin mismatch".to_string(),
+            ))
+        }
+    }
+}
+
+// Parses the suite names separated by ':' or '|', e.g.
+// "TLS13_CHACHA20_POLY1305_SHA256", names are case insensitive.
+fn parse_suites(suites: &str) -> Result<Vec<SupportedCipherSuite>> {
+    let mut parsed = Vec::new();
+    for name in suites.split(&[':', '|'][..]).map(str::trim) {
+        if name.is_empty() {
+            continue;
+        }
+        let suite = ALL_CIPHER_SUITES
+            .iter()
+            .find(|s| format!("{:?}", s.suite()).eq_ignore_ascii_case(name))
+            .ok_or_else(|| anyhow!("unsupported cipher suite {}", name))?;
+        if !parsed.contains(suite) {
+            parsed.push(*suite);
+        }
+    }
+    if parsed.is_empty() {
+        return Err(anyhow!("invalid cipher suites {}", suites));
+    }
+    Ok(parsed)
+}
+
+pub fn make_config(
+    config: &TrojanOutboundSettings,
+) -> Result<Arc<tokio_rustls::rustls::ClientConfig>> {
+    let mut root_cert_store = tokio_rustls::rustls::RootCertStore::empty();
+
+    root_cert_store.add_server_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.0.iter().map(|ta| {
+        OwnedTrustAnchor::from_subject_spki_name_constraints(
+            ta.subject,
+            ta.spki,
+            ta.name_constraints,
+        )
+    }));
+
+    // Extra roots to trust, e.g. a private CA.
+    if !config.ca_cert.is_empty() {
+        let (pem, source) = if config.ca_cert.contains("-----BEGIN") {
+            (config.ca_cert.as_bytes().to_vec(), "inline PEM")
+        } else {
+            let pem = std::fs::read(&config.ca_cert)
+                .map_err(|e| anyhow!("read CA cert {} failed: {}", &config.ca_cert, e))?;
+            (pem, config.ca_cert.as_str())
+        };
+        let certs = rustls_pemfile::certs(&mut BufReader::new(pem.as_slice()))
+            .map_err(|e| anyhow!("invalid CA cert {}: {}", source, e))?;
+        let (added, _) = root_cert_store.add_parsable_certificates(&certs);
+        if added == 0 {
+            return Err(anyhow!("no certificate found in {}", source));
+        }
+    }
+
+    let builder = if config.suites.is_empty() {
+        tokio_rustls::rustls::ClientConfig::builder().with_safe_defaults()
+    } else {
+        let suites = parse_suites(&config.suites)?;
+        // Only offers the versions the suites are for, or the server may pick
+        // one there's no suite to negotiate with.
+        let versions: Vec<&'static SupportedProtocolVersion> = [&TLS13, &TLS12]
+            .into_iter()
+            .filter(|v| suites.iter().any(|s| s.version().version == v.version))
+            .collect();
+        tokio_rustls::rustls::ClientConfig::builder()
+            .with_cipher_suites(&suites)
+            .with_safe_default_kx_groups()
+            .with_protocol_versions(&versions)
+            .map_err(|e| anyhow!("invalid cipher suites {}: {}", &config.suites, e))?
+    };
+    let mut tls_config = builder
+        .with_root_certificates(root_cert_store)
+        .with_no_client_auth(); // i guess this was previously the default?
+
+    if !config.cert_pin.is_empty() {
+        let pin = hex::decode(config.cert_pin.replace(':', ""))
+            .map_err(|e| anyhow!("invalid certificate pin {}: {}", &config.cert_pin, e))?;
+        if pin.len() != 32 {
+            return Err(anyhow!(
+                "invalid certificate pin {}: expect a SHA-256 digest",
+                &config.cert_pin
+            ));
+        }
+        tls_config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(PinnedCertVerifier { pin }));
+    } else if config.allow_insecure {
+        tls_config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(InsecureCertVerifier));
+    }
+
+    // The server name is still required by rustls for verification, but won't
+    // be sent in the ClientHello.
+    tls_config.enable_sni = !config.no_sni;
+    tls_config.alpn_protocols = config.alpn.iter().map(|a| a.as_bytes().to_vec()).collect();
+
+    Ok(Arc::new(tls_config))
+}
+
+/// The client config with the default roots offering the ALPN protocols,
+/// for connections without TLS settings of their own.
+pub fn make_default_config(alpn: &[&str]) -> Result<Arc<ClientConfig>> {
+    let mut settings = TrojanOutboundSettings::new();
+    settings.alpn = alpn.iter().map(|a| a.to_string()).collect();
+    make_config(&settings)
+}
+
+/// The client config of an outbound, rebuilt from its settings on `reload` so
+/// that changed roots apply to new connections without a restart.
+pub struct TlsConfig {
+    settings: TrojanOutboundSettings,
+    config: RwLock<Arc<ClientConfig>>,
+}
+
+impl TlsConfig {
+    pub fn new(settings: &TrojanOutboundSettings) -> Result<Self> {
+        Ok(Self {
+            config: RwLock::new(make_config(settings)?),
+            settings: settings.clone(),
+        })
+    }
+
+    pub fn get(&self) -> Arc<ClientConfig> {
+        self.config.read().unwrap().clone()
+    }
+
+    /// Rebuilds the config, the current one is kept if that fails.
+    pub fn reload(&self) -> Result<()> {
+        let config = make_config(&self.settings)?;
+        *self.config.write().unwrap() = config;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio_rustls::rustls::{PrivateKey, ServerConfig};
+    use tokio_rustls::{TlsAcceptor, TlsConnector};
+
+    use super::*;
+
+    // Handshakes with a server for localhost presenting the self-signed
+    // certificate, returns the negotiated ALPN protocol.
+    fn handshake(
+        config: Arc<ClientConfig>,
+        cert: &rcgen::Certificate,
+        alpn: &[&str],
+    ) -> Result<Option<Vec<u8>>> {
+        let mut server_config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![Certificate(cert.serialize_der().unwrap())],
+                PrivateKey(cert.serialize_private_key_der()),
+            )
+            .unwrap();
+        server_config.alpn_protocols = alpn.iter().map(|a| a.as_bytes().to_vec()).collect();
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let (client, server) = tokio::io::duplex(16 * 1024);
+            let name = ServerName::try_from("localhost").unwrap();
+            let (client, _) = tokio::join!(
+                TlsConnector::from(config).connect(name, client),
+                TlsAcceptor::from(Arc::new(server_config)).accept(server),
+            );
+            Ok(client?.get_ref().1.alpn_protocol().map(|p| p.to_vec()))
+        })
+    }
+
+    #[test]
+    fn test_ca_cert() {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+        let pem = cert.serialize_pem().unwrap();
+        let mut settings = TrojanOutboundSettings::new();
+        assert!(handshake(make_config(&settings).unwrap(), &cert, &[]).is_err());
+
+        // Inline.
+        settings.ca_cert = pem.clone();
+        assert!(handshake(make_config(&settings).unwrap(), &cert, &[]).is_ok());
+
+        // From a file.
+        let path = std::env::temp_dir().join("ostrich_test_tls_ca_cert.pem");
+        std::fs::write(&path, &pem).unwrap();
+        settings.ca_cert = path.to_string_lossy().to_string();
+        assert!(handshake(make_config(&settings).unwrap(), &cert, &[]).is_ok());
+        std::fs::write(&path, "not a certificate").unwrap();
+        assert!(make_config(&settings).is_err());
+        std::fs::remove_file(&path).unwrap();
+        assert!(make_config(&settings).is_err());
+
+        settings.ca_cert = "-----BEGIN CERTIFICATE-----\n-----END CERTIFICATE-----\n".to_string();
+        assert!(make_config(&settings).is_err());
+    }
+
+    #[test]
+    fn test_allow_insecure() {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+        let mut settings = TrojanOutboundSettings::new();
+        settings.allow_insecure = true;
+        assert!(handshake(make_config(&settings).unwrap(), &cert, &[]).is_ok());
+    }
+
+    #[test]
+    fn test_reload() {
+        let mut settings = TrojanOutboundSettings::new();
+        let tls_config = TlsConfig::new(&settings).unwrap();
+        let old = tls_config.get();
+        tls_config.reload().unwrap();
+        assert!(!Arc::ptr_eq(&old, &tls_config.get()));
+
+        // The CA bundle went missing since, the current config is kept.
+        settings.ca_cert = "/nonexistent/ca.pem".to_string();
+        let tls_config = TlsConfig {
+            settings,
+            config: RwLock::new(old.clone()),
+        };
+        assert!(tls_config.reload().is_err());
+        assert!(Arc::ptr_eq(&old, &tls_config.get()));
+    }
+
+    #[test]
+    fn test_alpn() {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+        let mut settings = TrojanOutboundSettings::new();
+        settings.allow_insecure = true;
+        let config = make_config(&settings).unwrap();
+        assert!(config.alpn_protocols.is_empty());
+        assert_eq!(handshake(config, &cert, &["h2"]).unwrap(), None);
+
+        settings.alpn = vec!["h2".to_string(), "http/1.1".to_string()];
+        let config = make_config(&settings).unwrap();
+        assert_eq!(
+            config.alpn_protocols,
+            vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+        );
+        assert_eq!(
+            handshake(config, &cert, &["http/1.1"]).unwrap(),
+            Some(b"http/1.1".to_vec())
+        );
+    }
+
+    #[test]
+    fn test_suites() {
+        let mut settings = TrojanOutboundSettings::new();
+        let config = make_config(&settings).unwrap();
+        assert!(config.cipher_suites.len() > 1);
+
+        settings.suites = "TLS13_CHACHA20_POLY1305_SHA256".to_string();
+        let config = make_config(&settings).unwrap();
+        let suites: Vec<String> = config
+            .cipher_suites
+            .iter()
+            .map(|s| format!("{:?}", s.suite()))
+            .collect();
+        assert_eq!(suites, vec!["TLS13_CHACHA20_POLY1305_SHA256"]);
+
+        settings.suites =
+            "tls13_chacha20_poly1305_sha256|TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256"
+                .to_string();
+        assert_eq!(make_config(&settings).unwrap().cipher_suites.len(), 2);
+
+        settings.suites = "TLS13_CHACHA20_POLY1305_SHA256:TLS_RSA_WITH_RC4_128_MD5".to_string();
+        assert!(make_config(&settings).is_err());
+        settings.suites = "|".to_string();
+        assert!(make_config(&settings).is_err());
+    }
+}
@@ -1,9 +1,8 @@
-use std::borrow::Cow;
 use std::collections::HashMap;
-use std::fs::File;
-use std::io::{self, BufRead};
+use std::io;
 use std::path::Path;
 
+use anyhow::anyhow;
 use anyhow::Result;
 use protobuf::Message;
 use regex::Regex;
@@ -24,19 +23,45 @@ pub struct General {
     pub tun: Option<Tun>,
     pub tun_fd: Option<i32>,
     pub tun_auto: Option<bool>,
+    pub tun_existing: Option<String>,
+    pub tun_dns: Option<String>,
     pub loglevel: Option<String>,
     pub logoutput: Option<String>,
+    pub log_rotation: Option<String>,
+    pub log_syslog: Option<bool>,
+    pub log_syslog_tag: Option<String>,
+    pub log_syslog_facility: Option<String>,
+    pub log_ring_size: Option<u32>,
+    pub log_ring_level: Option<String>,
     pub dns_server: Option<Vec<String>>,
     pub dns_interface: Option<String>,
+    pub dns_system_resolver: Option<bool>,
+    pub dns_outbound: Option<String>,
+    pub dns_weight: Option<Vec<String>>,
+    pub dns_ttl: Option<Vec<String>>,
+    pub doh_server: Option<String>,
     pub always_real_ip: Option<Vec<String>>,
     pub always_fake_ip: Option<Vec<String>>,
     pub http_interface: Option<String>,
     pub http_port: Option<u16>,
+    pub http_bind_interface: Option<String>,
+    pub http_accept_proxy_protocol: Option<bool>,
+    pub http_reuse_port: Option<bool>,
     pub socks_interface: Option<String>,
     pub socks_port: Option<u16>,
+    pub socks_bind_interface: Option<String>,
+    pub socks_accept_proxy_protocol: Option<bool>,
+    pub socks_reuse_port: Option<bool>,
+    pub socks_udp_interface: Option<String>,
+    pub socks_udp_port: Option<u16>,
+    pub socks_username: Option<String>,
+    pub socks_password: Option<String>,
     pub api_interface: Option<String>,
     pub api_port: Option<u16>,
     pub routing_domain_resolve: Option<bool>,
+    pub routing_bypass_lan: Option<bool>,
+    pub routing_unmatched: Option<String>,
+    pub routing_fail_closed: Option<bool>,
 }
 
 #[derive(Debug)]
@@ -69,15 +94,37 @@ pub struct Proxy {
 
     // trojan
     pub sni: Option<String>,
+    pub no_sni: Option<bool>,
+    pub tls_pin: Option<String>,
+    pub tls_suites: Option<String>,
+    pub servers: Option<Vec<String>>,
+    pub compress: Option<bool>,
+    pub alpn: Option<Vec<String>>,
 
     // vmess
     pub username: Option<String>,
 
+    // http2
+    pub connect_protocol: Option<String>,
+    pub connect_path: Option<String>,
+
+    // ssh
+    pub private_key: Option<String>,
+    pub private_key_passphrase: Option<String>,
+    pub host_key: Option<String>,
+    pub host_key_insecure: Option<bool>,
+
     pub amux: Option<bool>,
     pub amux_max: Option<i32>,
     pub amux_con: Option<i32>,
 
     pub quic: Option<bool>,
+
+    pub proxy_protocol: Option<String>,
+    pub dscp: Option<u8>,
+    pub fwmark: Option<u32>,
+    pub connect_timeout: Option<u32>,
+    pub connect_retries: Option<u32>,
 }
 
 impl Default for Proxy {
@@ -100,11 +147,28 @@ impl Default for Proxy {
             ws_path: None,
             ws_host: None,
             sni: None,
+            no_sni: Some(false),
+            tls_pin: None,
+            tls_suites: None,
+            servers: None,
+            compress: Some(false),
+            alpn: None,
             username: None,
+            connect_protocol: None,
+            connect_path: None,
+            private_key: None,
+            private_key_passphrase: None,
+            host_key: None,
+            host_key_insecure: None,
             amux: Some(false),
             amux_max: Some(8),
             amux_con: Some(2),
             quic: Some(false),
+            proxy_protocol: None,
+            dscp: None,
+            fwmark: None,
+            connect_timeout: None,
+            connect_retries: None,
         }
     }
 }
@@ -132,6 +196,9 @@ pub struct ProxyGroup {
 
     // static
     pub method: Option<String>,
+    pub smart_cache: Option<String>,
+    pub fallback_timeout: Option<u32>,
+    pub weights: Option<Vec<u32>>,
 }
 
 impl Default for ProxyGroup {
@@ -153,6 +220,9 @@ impl Default for ProxyGroup {
             health_check_active: None,
             delay_base: None,
             method: None,
+            smart_cache: None,
+            fallback_timeout: None,
+            weights: None,
         }
     }
 }
@@ -162,6 +232,10 @@ pub struct Rule {
     pub type_field: String,
     pub filter: Option<String>,
     pub target: String,
+    pub redirect: Option<String>,
+    pub log: Option<bool>,
+    pub resolve: Option<String>,
+    pub schedule: Option<String>,
 }
 
 #[derive(Debug, Default)]
@@ -171,19 +245,35 @@ pub struct Config {
     pub proxy_group: Option<Vec<ProxyGroup>>,
     pub rule: Option<Vec<Rule>>,
     pub host: Option<HashMap<String, Vec<String>>>,
+    // Warnings from parsing, e.g. unknown keys skipped.
+    pub warnings: Vec<String>,
 }
 
-fn read_lines<P>(filename: P) -> io::Result<io::Lines<io::BufReader<File>>>
-where
-    P: AsRef<Path>,
-{
-    let file = File::open(filename)?;
-    Ok(io::BufReader::new(file).lines())
+// A comment starts with `#` or `;` at the beginning of the line or after a
+// whitespace, outside double quotes, so that values like `pa#ss` or URIs with
+// fragments are kept intact.
+fn remove_comments(text: &str) -> &str {
+    let mut quoted = false;
+    let mut after_space = true;
+    for (i, c) in text.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '#' | ';' if !quoted && after_space => return text[..i].trim_end(),
+            _ => (),
+        }
+        after_space = c.is_whitespace();
+    }
+    text
 }
 
-fn remove_comments(text: &str) -> Cow<str> {
-    let re = Regex::new(r"(#[^*]*)").unwrap();
-    re.replace(text, "")
+// Strips the double quotes around a value, which keep the comment characters
+// in it.
+fn unquote(text: &str) -> &str {
+    if text.len() >= 2 && text.starts_with('"') && text.ends_with('"') {
+        &text[1..text.len() - 1]
+    } else {
+        text
+    }
 }
 
 fn get_section(text: &str) -> Option<&str> {
@@ -201,7 +291,7 @@ where
     let mut curr_sect: String = "".to_string();
     for line in lines.flatten().map(|x| x.trim()) {
         let line = remove_comments(line);
-        if let Some(s) = get_section(line.as_ref()) {
+        if let Some(s) = get_section(line) {
             curr_sect = s.to_string();
             continue;
         }
@@ -249,9 +339,17 @@ where
 }
 
 pub fn from_lines(lines: Vec<io::Result<String>>) -> Result<Config> {
+    parse_lines(lines, *crate::option::CONFIG_STRICT)
+}
+
+// Parses the lines, keys not recognized are skipped with a warning, or fail
+// the config if `strict`.
+pub(super) fn parse_lines(lines: Vec<io::Result<String>>, strict: bool) -> Result<Config> {
+    let mut unknown = Vec::new();
+
     let env_lines = get_lines_by_section("Env", lines.iter());
     for line in env_lines {
-        let parts: Vec<&str> = line.split('=').map(str::trim).collect();
+        let parts: Vec<&str> = line.split('=').map(str::trim).map(unquote).collect();
         if parts.len() != 2 {
             continue;
         }
@@ -261,7 +359,7 @@ pub fn from_lines(lines: Vec<io::Result<String>>) -> Result<Config> {
     let mut general = General::default();
     let general_lines = get_lines_by_section("General", lines.iter());
     for line in general_lines {
-        let parts: Vec<&str> = line.split('=').map(str::trim).collect();
+        let parts: Vec<&str> = line.split('=').map(str::trim).map(unquote).collect();
         if parts.len() != 2 {
             continue;
         }
@@ -269,10 +367,17 @@ pub fn from_lines(lines: Vec<io::Result<String>>) -> Result<Config> {
             "tun-fd" => {
                 general.tun_fd = get_value::<i32>(parts[1]);
             }
+            "tun-dns" => {
+                general.tun_dns = get_string(parts[1]);
+            }
             "tun" => {
                 if let Some(items) = get_char_sep_slice(parts[1], ',') {
                     if items.len() == 1 {
-                        general.tun_auto = Some(items[0] == "auto");
+                        if items[0] == "auto" {
+                            general.tun_auto = Some(true);
+                        } else {
+                            general.tun_existing = Some(items[0].clone());
+                        }
                         continue;
                     }
                     if items.len() != 5 {
@@ -291,12 +396,53 @@ pub fn from_lines(lines: Vec<io::Result<String>>) -> Result<Config> {
             "loglevel" => {
                 general.loglevel = Some(parts[1].to_string());
             }
-            "logoutput" => {
+            "logoutput" | "log-output" => {
                 general.logoutput = Some(parts[1].to_string());
             }
+            "log-rotation" => {
+                general.log_rotation = Some(parts[1].to_string());
+            }
+            "log-syslog" => {
+                general.log_syslog = if parts[1] == "true" {
+                    Some(true)
+                } else {
+                    Some(false)
+                };
+            }
+            "log-syslog-tag" => {
+                general.log_syslog_tag = get_string(parts[1]);
+            }
+            "log-syslog-facility" => {
+                general.log_syslog_facility = get_string(parts[1]);
+            }
+            "log-ring-size" => {
+                general.log_ring_size = get_value::<u32>(parts[1]);
+            }
+            "log-ring-level" => {
+                general.log_ring_level = Some(parts[1].to_string());
+            }
             "dns-server" => {
                 general.dns_server = get_char_sep_slice(parts[1], ',');
             }
+            "dns-system-resolver" => {
+                general.dns_system_resolver = if parts[1] == "true" {
+                    Some(true)
+                } else {
+                    Some(false)
+                };
+            }
+            "dns-weight" => {
+                general.dns_weight = get_char_sep_slice(parts[1], ',');
+            }
+            "dns-ttl" => {
+                general.dns_ttl = get_char_sep_slice(parts[1], ',');
+            }
+            "dns-outbound" => {
+                general.dns_outbound = get_string(parts[1]);
+            }
+            "doh-server" => {
+                general.doh_server = get_string(parts[1]);
+            }
             "dns-interface" => {
                 general.dns_interface = get_string(parts[1]);
             }
@@ -313,25 +459,88 @@ pub fn from_lines(lines: Vec<io::Result<String>>) -> Result<Config> {
                     Some(false)
                 };
             }
+            "routing-bypass-lan" => {
+                general.routing_bypass_lan = if parts[1] == "true" {
+                    Some(true)
+                } else {
+                    Some(false)
+                };
+            }
+            "routing-unmatched" => {
+                general.routing_unmatched = get_string(parts[1]);
+            }
+            "routing-fail-closed" => {
+                general.routing_fail_closed = if parts[1] == "true" {
+                    Some(true)
+                } else {
+                    Some(false)
+                };
+            }
             "http-interface" | "interface" => {
                 general.http_interface = get_string(parts[1]);
             }
             "http-port" | "port" => {
                 general.http_port = get_value::<u16>(parts[1]);
             }
+            "http-bind-interface" => {
+                general.http_bind_interface = get_string(parts[1]);
+            }
+            "http-accept-proxy-protocol" => {
+                general.http_accept_proxy_protocol = if parts[1] == "true" {
+                    Some(true)
+                } else {
+                    Some(false)
+                };
+            }
+            "http-reuse-port" => {
+                general.http_reuse_port = if parts[1] == "true" {
+                    Some(true)
+                } else {
+                    Some(false)
+                };
+            }
             "socks-interface" => {
                 general.socks_interface = get_string(parts[1]);
             }
             "socks-port" => {
                 general.socks_port = get_value::<u16>(parts[1]);
             }
+            "socks-bind-interface" => {
+                general.socks_bind_interface = get_string(parts[1]);
+            }
+            "socks-accept-proxy-protocol" => {
+                general.socks_accept_proxy_protocol = if parts[1] == "true" {
+                    Some(true)
+                } else {
+                    Some(false)
+                };
+            }
+            "socks-reuse-port" => {
+                general.socks_reuse_port = if parts[1] == "true" {
+                    Some(true)
+                } else {
+                    Some(false)
+                };
+            }
+            "socks-udp-interface" => {
+                general.socks_udp_interface = get_string(parts[1]);
+            }
+            "socks-udp-port" => {
+                general.socks_udp_port = get_value::<u16>(parts[1]);
+            }
+            "socks-username" => {
+                general.socks_username = get_string(parts[1]);
+            }
+            "socks-password" => {
+                general.socks_password = get_string(parts[1]);
+            }
             "api-interface" => {
                 general.api_interface = get_string(parts[1]);
             }
             "api-port" => {
                 general.api_port = get_value::<u16>(parts[1]);
             }
-            _ => {}
+            _ => unknown.push(format!("{} in [General]", parts[0])),
         }
     }
 
@@ -363,7 +572,7 @@ pub fn from_lines(lines: Vec<io::Result<String>>) -> Result<Config> {
         // extract key-value params
         // let params = &params[2..];
         for param in &params {
-            let parts: Vec<&str> = param.split('=').map(str::trim).collect();
+            let parts: Vec<&str> = param.split('=').map(str::trim).map(unquote).collect();
             if parts.len() != 2 {
                 continue;
             }
@@ -405,9 +614,39 @@ pub fn from_lines(lines: Vec<io::Result<String>>) -> Result<Config> {
                 "sni" => {
                     proxy.sni = Some(v.to_string());
                 }
+                "no-sni" => proxy.no_sni = if v == "true" { Some(true) } else { Some(false) },
+                "compress" => proxy.compress = if v == "true" { Some(true) } else { Some(false) },
+                "tls-pin" => {
+                    proxy.tls_pin = Some(v.to_string());
+                }
+                "tls-suites" => {
+                    proxy.tls_suites = Some(v.to_string());
+                }
+                "servers" => {
+                    proxy.servers = Some(v.split('|').map(|s| s.trim().to_string()).collect());
+                }
+                "alpn" => {
+                    proxy.alpn = Some(v.split('|').map(|s| s.trim().to_string()).collect());
+                }
+                "connect-protocol" => {
+                    proxy.connect_protocol = Some(v.to_string());
+                }
+                "connect-path" => {
+                    proxy.connect_path = Some(v.to_string());
+                }
                 "username" => {
                     proxy.username = Some(v.to_string());
                 }
+                "private-key" => {
+                    proxy.private_key = Some(v.to_string());
+                }
+                "private-key-passphrase" => {
+                    proxy.private_key_passphrase = Some(v.to_string());
+                }
+                "host-key" => {
+                    proxy.host_key = Some(v.to_string());
+                }
+                "host-key-insecure" => proxy.host_key_insecure = Some(v == "true"),
                 "amux" => proxy.amux = if v == "true" { Some(true) } else { Some(false) },
                 "amux-max" => {
                     let i = if let Ok(i) = v.parse::<i32>() {
@@ -429,7 +668,29 @@ pub fn from_lines(lines: Vec<io::Result<String>>) -> Result<Config> {
                 "interface" => {
                     proxy.interface = v.to_string();
                 }
-                _ => {}
+                "proxy-protocol" => {
+                    proxy.proxy_protocol = Some(v.to_string());
+                }
+                "dscp" => {
+                    proxy.dscp = v.parse::<u8>().ok();
+                }
+                "fwmark" => {
+                    proxy.fwmark = Some(
+                        crate::common::net::parse_fwmark(v)
+                            .map_err(|e| anyhow!("invalid [Proxy] {}: {}", tag, e))?,
+                    );
+                }
+                "connect-timeout" => {
+                    proxy.connect_timeout = Some(v.parse::<u32>().map_err(|e| {
+                        anyhow!("invalid connect-timeout {} in [Proxy] {}: {}", v, tag, e)
+                    })?);
+                }
+                "connect-retries" => {
+                    proxy.connect_retries = Some(v.parse::<u32>().map_err(|e| {
+                        anyhow!("invalid connect-retries {} in [Proxy] {}: {}", v, tag, e)
+                    })?);
+                }
+                _ => unknown.push(format!("{} in [Proxy] {}", k, tag)),
             }
         }
 
@@ -519,7 +780,7 @@ pub fn from_lines(lines: Vec<io::Result<String>>) -> Result<Config> {
 
         for param in params {
             if param.contains('=') {
-                let parts: Vec<&str> = param.split('=').map(str::trim).collect();
+                let parts: Vec<&str> = param.split('=').map(str::trim).map(unquote).collect();
                 if parts.len() != 2 {
                     continue;
                 }
@@ -618,7 +879,30 @@ pub fn from_lines(lines: Vec<io::Result<String>>) -> Result<Config> {
                         };
                         group.method = i;
                     }
-                    _ => {}
+                    "smart-cache" => {
+                        group.smart_cache = Some(v.to_string());
+                    }
+                    "fallback-timeout" => {
+                        group.fallback_timeout = Some(v.parse::<u32>().map_err(|e| {
+                            anyhow!(
+                                "invalid fallback-timeout {} in [Proxy Group] {}: {}",
+                                v,
+                                tag,
+                                e
+                            )
+                        })?);
+                    }
+                    "weights" => {
+                        group.weights = Some(
+                            v.split('|')
+                                .map(|w| w.trim().parse::<u32>())
+                                .collect::<Result<Vec<_>, _>>()
+                                .map_err(|e| {
+                                    anyhow!("invalid weights {} in [Proxy Group] {}: {}", v, tag, e)
+                                })?,
+                        );
+                    }
+                    _ => unknown.push(format!("{} in [Proxy Group] {}", k, tag)),
                 }
             }
         }
@@ -672,12 +956,26 @@ pub fn from_lines(lines: Vec<io::Result<String>>) -> Result<Config> {
 
         match rule.type_field.as_str() {
             "IP-CIDR" | "DOMAIN" | "DOMAIN-SUFFIX" | "DOMAIN-KEYWORD" | "GEOIP" | "EXTERNAL"
-            | "PORT-RANGE" | "NETWORK" | "INBOUND-TAG" => {
+            | "PORT-RANGE" | "NETWORK" | "INBOUND-TAG" | "SNI" | "ALPN" | "SCHEDULE" | "ASN" => {
                 rule.filter = Some(params[1].to_string());
             }
             _ => {}
         }
 
+        // optional parameters after the target, e.g. redirect=pool.ntp.org:123
+        for param in &params[3..] {
+            if let Some((k, v)) = param.split_once('=') {
+                let v = unquote(v.trim());
+                match k.trim() {
+                    "redirect" => rule.redirect = Some(v.to_string()),
+                    "log" => rule.log = Some(v == "true"),
+                    "resolve" => rule.resolve = Some(v.to_string()),
+                    "schedule" => rule.schedule = Some(v.to_string()),
+                    k => unknown.push(format!("{} in [Rule] {}", k, &line)),
+                }
+            }
+        }
+
         rules.push(rule);
     }
 
@@ -697,12 +995,15 @@ pub fn from_lines(lines: Vec<io::Result<String>>) -> Result<Config> {
         hosts.insert(name.to_owned(), ips);
     }
 
+    let warnings = crate::config::check_unknown_keys(unknown, strict)?;
+
     Ok(Config {
         general: Some(general),
         proxy: Some(proxies),
         proxy_group: Some(proxy_groups),
         rule: Some(rules),
         host: Some(hosts),
+        warnings,
     })
 }
 
@@ -710,26 +1011,42 @@ pub fn to_internal(conf: &mut Config) -> Result<internal::Config> {
     let mut log = internal::Log::new();
     if let Some(ext_general) = &conf.general {
         if let Some(ext_loglevel) = &ext_general.loglevel {
-            match ext_loglevel.as_str() {
-                "trace" => log.level = protobuf::EnumOrUnknown::new(internal::log::Level::TRACE),
-                "debug" => log.level = protobuf::EnumOrUnknown::new(internal::log::Level::DEBUG),
-                "info" => log.level = protobuf::EnumOrUnknown::new(internal::log::Level::INFO),
-                "warn" => log.level = protobuf::EnumOrUnknown::new(internal::log::Level::WARN),
-                "error" => log.level = protobuf::EnumOrUnknown::new(internal::log::Level::ERROR),
-                _ => log.level = protobuf::EnumOrUnknown::new(internal::log::Level::WARN),
-            }
+            log.level = protobuf::EnumOrUnknown::new(crate::config::parse_log_level(ext_loglevel));
         }
         if let Some(ext_logoutput) = &ext_general.logoutput {
             match ext_logoutput.as_str() {
                 "console" => {
                     log.output = protobuf::EnumOrUnknown::new(internal::log::Output::CONSOLE)
                 }
+                "syslog" => {
+                    log.output = protobuf::EnumOrUnknown::new(internal::log::Output::SYSLOG)
+                }
                 _ => {
                     log.output = protobuf::EnumOrUnknown::new(internal::log::Output::FILE);
                     log.output_file = ext_logoutput.clone();
                 }
             }
         }
+        if let Some(ext_log_rotation) = &ext_general.log_rotation {
+            log.rotation =
+                protobuf::EnumOrUnknown::new(crate::config::parse_log_rotation(ext_log_rotation)?);
+        }
+        if let Some(ext_log_syslog) = ext_general.log_syslog {
+            log.syslog = ext_log_syslog;
+        }
+        if let Some(ext_log_syslog_tag) = &ext_general.log_syslog_tag {
+            log.syslog_tag = ext_log_syslog_tag.clone();
+        }
+        if let Some(ext_log_syslog_facility) = &ext_general.log_syslog_facility {
+            log.syslog_facility = ext_log_syslog_facility.clone();
+        }
+        if let Some(ext_log_ring_size) = ext_general.log_ring_size {
+            log.ring_size = ext_log_ring_size;
+        }
+        if let Some(ext_log_ring_level) = &ext_general.log_ring_level {
+            log.ring_level =
+                protobuf::EnumOrUnknown::new(crate::config::parse_log_level(ext_log_ring_level));
+        }
     }
 
     let mut inbounds = Vec::new();
@@ -740,6 +1057,15 @@ pub fn to_internal(conf: &mut Config) -> Result<internal::Config> {
             inbound.tag = "http".to_string();
             inbound.address = ext_general.http_interface.as_ref().unwrap().to_string();
             inbound.port = ext_general.http_port.unwrap() as u32;
+            if let Some(iface) = &ext_general.http_bind_interface {
+                inbound.interface = iface.clone();
+            }
+            if let Some(accept) = ext_general.http_accept_proxy_protocol {
+                inbound.accept_proxy_protocol = accept;
+            }
+            if let Some(reuse_port) = ext_general.http_reuse_port {
+                inbound.reuse_port = reuse_port;
+            }
             inbounds.push(inbound);
         }
         if ext_general.socks_interface.is_some() && ext_general.socks_port.is_some() {
@@ -748,11 +1074,43 @@ pub fn to_internal(conf: &mut Config) -> Result<internal::Config> {
             inbound.tag = "socks".to_string();
             inbound.address = ext_general.socks_interface.as_ref().unwrap().to_string();
             inbound.port = ext_general.socks_port.unwrap() as u32;
+            if let Some(iface) = &ext_general.socks_bind_interface {
+                inbound.interface = iface.clone();
+            }
+            if let Some(accept) = ext_general.socks_accept_proxy_protocol {
+                inbound.accept_proxy_protocol = accept;
+            }
+            if let Some(reuse_port) = ext_general.socks_reuse_port {
+                inbound.reuse_port = reuse_port;
+            }
+            if ext_general.socks_password.is_some() && ext_general.socks_username.is_none() {
+                return Err(anyhow!("socks-password without socks-username"));
+            }
+            if ext_general.socks_udp_interface.is_some()
+                || ext_general.socks_udp_port.is_some()
+                || ext_general.socks_username.is_some()
+            {
+                let mut settings = internal::SocksInboundSettings::new();
+                if let Some(ext_udp_interface) = &ext_general.socks_udp_interface {
+                    settings.udp_address = ext_udp_interface.clone();
+                }
+                if let Some(ext_udp_port) = ext_general.socks_udp_port {
+                    settings.udp_port = ext_udp_port as u32;
+                }
+                if let Some(ext_username) = &ext_general.socks_username {
+                    settings.username = ext_username.clone();
+                }
+                if let Some(ext_password) = &ext_general.socks_password {
+                    settings.password = ext_password.clone();
+                }
+                inbound.settings = settings.write_to_bytes().unwrap();
+            }
             inbounds.push(inbound);
         }
 
         if ext_general.tun_fd.is_some()
             || ext_general.tun_auto.is_some()
+            || ext_general.tun_existing.is_some()
             || ext_general.tun.is_some()
         {
             let mut inbound = internal::Inbound::new();
@@ -780,17 +1138,25 @@ pub fn to_internal(conf: &mut Config) -> Result<internal::Config> {
                 }
             }
 
+            if let Some(ext_dns) = &ext_general.tun_dns {
+                settings.dns = ext_dns.clone();
+            }
+
             if ext_general.tun_fd.is_some() {
                 settings.fd = ext_general.tun_fd.unwrap();
             } else if ext_general.tun_auto.is_some() && ext_general.tun_auto.unwrap() {
                 settings.auto = true;
                 settings.fd = -1; // disable fd option
+            } else if let Some(ext_name) = &ext_general.tun_existing {
+                settings.fd = -1; // disable fd option
+                settings.name = crate::config::parse_tun_name(ext_name)?;
+                settings.existing = true;
             } else {
                 let ext_tun = ext_general.tun.as_ref().unwrap();
 
                 settings.fd = -1; // disable fd option
                 if let Some(ext_name) = &ext_tun.name {
-                    settings.name = ext_name.clone();
+                    settings.name = crate::config::parse_tun_name(ext_name)?;
                 }
                 if let Some(ext_address) = &ext_tun.address {
                     settings.address = ext_address.clone();
@@ -825,6 +1191,23 @@ pub fn to_internal(conf: &mut Config) -> Result<internal::Config> {
             };
             outbound.protocol = ext_protocol.to_string();
             outbound.tag = ext_proxy.tag.clone();
+            // The outbound dialing the server sends the header, i.e. the
+            // innermost one of a chain.
+            if let Some(ext_proxy_protocol) = &ext_proxy.proxy_protocol {
+                outbound.proxy_protocol = ext_proxy_protocol.clone();
+            }
+            if let Some(ext_dscp) = ext_proxy.dscp {
+                outbound.dscp = ext_dscp as u32;
+            }
+            if let Some(ext_fwmark) = ext_proxy.fwmark {
+                outbound.fwmark = ext_fwmark;
+            }
+            if let Some(ext_connect_timeout) = ext_proxy.connect_timeout {
+                outbound.connect_timeout = ext_connect_timeout;
+            }
+            if let Some(ext_connect_retries) = ext_proxy.connect_retries {
+                outbound.connect_retries = ext_connect_retries;
+            }
             match outbound.protocol.as_str() {
                 "direct" | "drop" => {
                     outbounds.push(outbound);
@@ -841,6 +1224,61 @@ pub fn to_internal(conf: &mut Config) -> Result<internal::Config> {
                     outbound.settings = settings;
                     outbounds.push(outbound);
                 }
+                "http2" => {
+                    let mut settings = internal::Http2OutboundSettings::new();
+                    if let Some(ext_address) = &ext_proxy.address {
+                        settings.address = ext_address.clone();
+                    }
+                    if let Some(ext_port) = &ext_proxy.port {
+                        settings.port = *ext_port as u32;
+                    }
+                    if let Some(ext_sni) = &ext_proxy.sni {
+                        settings.server_name = ext_sni.clone();
+                    }
+                    if let Some(ext_username) = &ext_proxy.username {
+                        settings.username = ext_username.clone();
+                    }
+                    if let Some(ext_password) = &ext_proxy.password {
+                        settings.password = ext_password.clone();
+                    }
+                    if let Some(ext_protocol) = &ext_proxy.connect_protocol {
+                        settings.protocol = ext_protocol.clone();
+                    }
+                    if let Some(ext_path) = &ext_proxy.connect_path {
+                        settings.path = ext_path.clone();
+                    }
+                    let settings = settings.write_to_bytes().unwrap();
+                    outbound.settings = settings;
+                    outbounds.push(outbound);
+                }
+                "ssh" => {
+                    let mut settings = internal::SshOutboundSettings::new();
+                    if let Some(ext_address) = &ext_proxy.address {
+                        settings.address = ext_address.clone();
+                    }
+                    if let Some(ext_port) = &ext_proxy.port {
+                        settings.port = *ext_port as u32;
+                    }
+                    if let Some(ext_username) = &ext_proxy.username {
+                        settings.username = ext_username.clone();
+                    }
+                    if let Some(ext_password) = &ext_proxy.password {
+                        settings.password = ext_password.clone();
+                    }
+                    if let Some(ext_private_key) = &ext_proxy.private_key {
+                        settings.private_key = ext_private_key.clone();
+                    }
+                    if let Some(ext_passphrase) = &ext_proxy.private_key_passphrase {
+                        settings.private_key_passphrase = ext_passphrase.clone();
+                    }
+                    if let Some(ext_host_key) = &ext_proxy.host_key {
+                        settings.host_key = ext_host_key.clone();
+                    }
+                    settings.allow_insecure = ext_proxy.host_key_insecure.unwrap_or_default();
+                    let settings = settings.write_to_bytes().unwrap();
+                    outbound.settings = settings;
+                    outbounds.push(outbound);
+                }
                 "socks" => {
                     let mut settings = internal::SocksOutboundSettings::new();
                     if let Some(ext_address) = &ext_proxy.address {
@@ -1018,6 +1456,34 @@ pub fn to_internal(conf: &mut Config) -> Result<internal::Config> {
                     if let Some(ext_password) = &ext_proxy.password {
                         settings.password = ext_password.clone();
                     }
+                    settings.no_sni = ext_proxy.no_sni.unwrap_or_default();
+                    settings.compress = ext_proxy.compress.unwrap_or_default();
+                    settings.allow_insecure = ext_proxy.tls_insecure.unwrap_or_default();
+                    if let Some(ext_tls_pin) = &ext_proxy.tls_pin {
+                        settings.cert_pin = ext_tls_pin.clone();
+                    }
+                    if let Some(ext_tls_suites) = &ext_proxy.tls_suites {
+                        settings.suites = ext_tls_suites.clone();
+                    }
+                    if let Some(ext_tls_cert) = &ext_proxy.tls_cert {
+                        let cert = Path::new(ext_tls_cert);
+                        if cert.is_absolute() {
+                            settings.ca_cert = cert.to_string_lossy().to_string();
+                        } else {
+                            let asset_loc = Path::new(&*crate::option::ASSET_LOCATION);
+                            let path = asset_loc.join(cert).to_string_lossy().to_string();
+                            settings.ca_cert = path;
+                        }
+                    }
+                    if let Some(ext_servers) = &ext_proxy.servers {
+                        settings.servers = ext_servers.clone();
+                    }
+                    if let Some(ext_sni) = &ext_proxy.sni {
+                        settings.sni = ext_sni.clone();
+                    }
+                    if let Some(ext_alpn) = &ext_proxy.alpn {
+                        settings.alpn = ext_alpn.clone();
+                    }
                     let settings = settings.write_to_bytes().unwrap();
                     outbound.settings = settings;
                     outbound.tag = format!("{}_trojan_xxx", ext_proxy.tag.clone());
@@ -1266,6 +1732,18 @@ pub fn to_internal(conf: &mut Config) -> Result<internal::Config> {
                     } else {
                         settings.method = "random".to_string();
                     }
+                    if let Some(ext_smart_cache) = &ext_proxy_group.smart_cache {
+                        settings.smart_cache = ext_smart_cache.clone();
+                    }
+                    if let Some(ext_fallback_timeout) = ext_proxy_group.fallback_timeout {
+                        settings.fallback_timeout = ext_fallback_timeout;
+                    }
+                    if let Some(ext_check_interval) = ext_proxy_group.check_interval {
+                        settings.check_interval = ext_check_interval as u32;
+                    }
+                    if let Some(ext_weights) = &ext_proxy_group.weights {
+                        settings.weights = ext_weights.clone();
+                    }
                     let settings = settings.write_to_bytes().unwrap();
                     outbound.settings = settings;
                     outbounds.push(outbound);
@@ -1354,6 +1832,7 @@ pub fn to_internal(conf: &mut Config) -> Result<internal::Config> {
 
     let mut int_router = internal::Router::new();
     let mut rules = Vec::new();
+    let mut has_final = false;
     if let Some(ext_rules) = conf.rule.as_mut() {
         for ext_rule in ext_rules.iter_mut() {
             let mut rule = internal::router::Rule::new();
@@ -1363,6 +1842,7 @@ pub fn to_internal(conf: &mut Config) -> Result<internal::Config> {
 
             // handle FINAL rule first
             if ext_rule.type_field == "FINAL" {
+                has_final = true;
                 // reorder outbounds to make the FINAL one first
                 let mut idx = None;
                 for (i, v) in outbounds.iter().enumerate() {
@@ -1416,6 +1896,21 @@ pub fn to_internal(conf: &mut Config) -> Result<internal::Config> {
                     mmdb.country_code = ext_filter;
                     rule.mmdbs.push(mmdb)
                 }
+                "GEOSITE" => {
+                    if let Err(e) = external_rule::add_external_rule(
+                        &mut rule,
+                        &format!("geosite:{}", ext_filter),
+                    ) {
+                        return Err(anyhow!("invalid geosite rule {}: {}", ext_filter, e));
+                    }
+                }
+                "ASN" => {
+                    if let Err(e) =
+                        external_rule::add_external_rule(&mut rule, &format!("asn:{}", ext_filter))
+                    {
+                        return Err(anyhow!("invalid asn rule {}: {}", ext_filter, e));
+                    }
+                }
                 "EXTERNAL" => match external_rule::add_external_rule(&mut rule, &ext_filter) {
                     Ok(_) => (),
                     Err(e) => {
@@ -1431,8 +1926,29 @@ pub fn to_internal(conf: &mut Config) -> Result<internal::Config> {
                 "INBOUND-TAG" => {
                     rule.inbound_tags.push(ext_filter);
                 }
+                "SNI" => {
+                    rule.snis.push(ext_filter);
+                }
+                "ALPN" => {
+                    rule.alpns.push(ext_filter);
+                }
+                "SCHEDULE" => {
+                    rule.schedule = ext_filter;
+                }
                 _ => {}
             }
+            if let Some(ext_redirect) = ext_rule.redirect.take() {
+                rule.redirect = ext_redirect;
+            }
+            if let Some(ext_log) = ext_rule.log {
+                rule.log = ext_log;
+            }
+            if let Some(ext_resolve) = ext_rule.resolve.take() {
+                rule.resolve = ext_resolve;
+            }
+            if let Some(ext_schedule) = ext_rule.schedule.take() {
+                rule.schedule = ext_schedule;
+            }
             rules.push(rule);
         }
     }
@@ -1441,6 +1957,24 @@ pub fn to_internal(conf: &mut Config) -> Result<internal::Config> {
         if let Some(ext_domain_resolve) = ext_general.routing_domain_resolve {
             int_router.domain_resolve = ext_domain_resolve;
         }
+        // The FINAL rule already picks the outbound for unmatched sessions.
+        if let (Some(ext_unmatched), false) = (&ext_general.routing_unmatched, has_final) {
+            int_router.unmatched =
+                protobuf::EnumOrUnknown::new(crate::config::parse_unmatched(ext_unmatched)?);
+        }
+        if let Some(ext_fail_closed) = ext_general.routing_fail_closed {
+            int_router.fail_closed = ext_fail_closed;
+        }
+    }
+    let bypass_lan = conf
+        .general
+        .as_ref()
+        .and_then(|g| g.routing_bypass_lan)
+        .unwrap_or(true);
+    if bypass_lan {
+        if let Some(direct) = outbounds.iter().find(|o| o.protocol == "direct") {
+            int_router.lan_target = direct.tag.clone();
+        }
     }
     let router = protobuf::MessageField::some(int_router);
 
@@ -1456,6 +1990,45 @@ pub fn to_internal(conf: &mut Config) -> Result<internal::Config> {
                 dns.servers = servers;
             }
         }
+        if let Some(ext_dns_system_resolver) = ext_general.dns_system_resolver {
+            dns.system_resolver = ext_dns_system_resolver;
+        }
+        if let Some(ext_dns_outbound) = &ext_general.dns_outbound {
+            dns.outbound = ext_dns_outbound.clone();
+        }
+        if let Some(ext_doh_server) = &ext_general.doh_server {
+            dns.doh_server = ext_doh_server.clone();
+        }
+        if let Some(ext_dns_weights) = &ext_general.dns_weight {
+            // <server>:<weight>, the server may be an IPv6 address.
+            for ext_dns_weight in ext_dns_weights {
+                let invalid = || anyhow!("invalid dns-weight {}", ext_dns_weight);
+                let (server, weight) = ext_dns_weight.rsplit_once(':').ok_or_else(invalid)?;
+                let server = server.trim();
+                if server.is_empty() {
+                    return Err(invalid());
+                }
+                let weight = weight.trim().parse::<u32>().map_err(|_| invalid())?;
+                dns.weights.insert(server.to_string(), weight);
+            }
+        }
+        if let Some(ext_dns_ttls) = &ext_general.dns_ttl {
+            // <domain>:<min>[:<max>]
+            for ext_dns_ttl in ext_dns_ttls {
+                let invalid = || anyhow!("invalid dns-ttl {}", ext_dns_ttl);
+                let parts: Vec<&str> = ext_dns_ttl.split(':').map(str::trim).collect();
+                if parts.len() < 2 || parts.len() > 3 || parts[0].is_empty() {
+                    return Err(invalid());
+                }
+                let mut rule = internal::dns::TtlRule::new();
+                rule.domain = parts[0].to_string();
+                rule.min = parts[1].parse::<u32>().map_err(|_| invalid())?;
+                if let Some(max) = parts.get(2) {
+                    rule.max = max.parse::<u32>().map_err(|_| invalid())?;
+                }
+                dns.ttl_rules.push(rule);
+            }
+        }
     }
     if dns.servers.is_empty() {
         dns.servers.push("1.1.1.1".to_string());
@@ -1481,12 +2054,16 @@ pub fn to_internal(conf: &mut Config) -> Result<internal::Config> {
     config.outbounds = outbounds;
     config.router = router;
     config.dns = protobuf::MessageField::some(dns);
+    config.warnings = std::mem::take(&mut conf.warnings);
 
     Ok(config)
 }
 
 pub fn from_string(s: &str) -> Result<internal::Config> {
-    let lines = s.lines().map(|s| Ok(s.to_string())).collect();
+    let lines = crate::config::normalize_text(s)
+        .lines()
+        .map(|s| Ok(s.to_string()))
+        .collect();
     let mut config = from_lines(lines)?;
     to_internal(&mut config)
 }
@@ -1495,7 +2072,271 @@ pub fn from_file<P>(path: P) -> Result<internal::Config>
 where
     P: AsRef<Path>,
 {
-    let lines = read_lines(path)?.collect();
-    let mut config = from_lines(lines)?;
-    to_internal(&mut config)
+    from_string(&std::fs::read_to_string(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remove_comments() {
+        assert_eq!(remove_comments("# comment"), "");
+        assert_eq!(remove_comments("; comment"), "");
+        assert_eq!(
+            remove_comments("socks-port = 1080 # local"),
+            "socks-port = 1080"
+        );
+        assert_eq!(
+            remove_comments("socks-port = 1080 ; local"),
+            "socks-port = 1080"
+        );
+        assert_eq!(
+            remove_comments("password=pa#ss;word"),
+            "password=pa#ss;word"
+        );
+        assert_eq!(
+            remove_comments("url = http://example.com/a#b"),
+            "url = http://example.com/a#b"
+        );
+        assert_eq!(
+            remove_comments(r#"password="pa # ss" # quoted"#),
+            r#"password="pa # ss""#
+        );
+
+        let lines = vec![
+            Ok("[General] # general".to_string()),
+            Ok("socks-port = 1080 # local".to_string()),
+            Ok("[Proxy]".to_string()),
+            Ok("SS = ss, 1.2.3.4, 8388, password=pa#ss ; backup".to_string()),
+        ];
+        let config = from_lines(lines).unwrap();
+        assert_eq!(config.general.unwrap().socks_port, Some(1080));
+        let proxies = config.proxy.unwrap();
+        assert_eq!(proxies[0].password.as_deref(), Some("pa#ss"));
+    }
+
+    #[test]
+    fn test_unquote() {
+        assert_eq!(unquote(r#""pa # ss""#), "pa # ss");
+        assert_eq!(unquote(r#""""#), "");
+        assert_eq!(unquote(r#"""#), r#"""#);
+        assert_eq!(unquote(r#""pass"#), r#""pass"#);
+        assert_eq!(unquote("pass"), "pass");
+
+        let conf = r#"
+[General]
+log-syslog-tag = "ostrich # tun"
+
+[Proxy]
+SS = ss, 1.2.3.4, 8388, password="pa ; ss" ; backup
+
+[Proxy Group]
+Group = static, SS, method="rr"
+
+[Rule]
+DOMAIN, example.com, SS, redirect="1.2.3.4:53"
+"#;
+        let config = from_lines(conf.lines().map(|l| Ok(l.to_string())).collect()).unwrap();
+        assert_eq!(
+            config.general.unwrap().log_syslog_tag.as_deref(),
+            Some("ostrich # tun")
+        );
+        assert_eq!(
+            config.proxy.unwrap()[0].password.as_deref(),
+            Some("pa ; ss")
+        );
+        assert_eq!(config.proxy_group.unwrap()[0].method.as_deref(), Some("rr"));
+        assert_eq!(
+            config.rule.unwrap()[0].redirect.as_deref(),
+            Some("1.2.3.4:53")
+        );
+    }
+
+    #[test]
+    fn test_unknown_keys() {
+        let conf = r#"
+[General]
+socks-port = 1080
+socks-prot = 1081
+
+[Proxy]
+SS = ss, 1.2.3.4, 8388, encrypt-method=aes-128-gcm, pasword=pass
+
+[Rule]
+DOMAIN, example.com, SS, lgo=true
+FINAL, SS
+"#;
+        let lines = || conf.lines().map(|l| Ok(l.to_string())).collect();
+        let mut config = parse_lines(lines(), false).unwrap();
+        assert_eq!(config.general.as_ref().unwrap().socks_port, Some(1080));
+        assert_eq!(config.rule.as_ref().unwrap().len(), 2);
+        // The warnings come with the parsed config.
+        let warnings = to_internal(&mut config).unwrap().warnings;
+        assert_eq!(warnings.len(), 3, "{:?}", warnings);
+        assert!(warnings[0].contains("socks-prot in [General]"));
+
+        let err = parse_lines(lines(), true).unwrap_err().to_string();
+        assert!(err.contains("socks-prot in [General]"), "{}", err);
+        assert!(err.contains("pasword in [Proxy] SS"), "{}", err);
+        assert!(err.contains("lgo in [Rule]"), "{}", err);
+    }
+
+    #[test]
+    fn test_socks_credentials() {
+        let conf = "[General]\nsocks-interface = 127.0.0.1\nsocks-port = 1080\n";
+        assert!(from_string(&format!("{}socks-password = pass\n", conf)).is_err());
+        let config = from_string(&format!(
+            "{}socks-username = user\nsocks-password = pass\n",
+            conf
+        ))
+        .unwrap();
+        let inbound = config
+            .inbounds
+            .iter()
+            .find(|i| i.protocol == "socks")
+            .unwrap();
+        let settings = internal::SocksInboundSettings::parse_from_bytes(&inbound.settings).unwrap();
+        assert_eq!(settings.username, "user");
+        assert_eq!(settings.password, "pass");
+    }
+
+    #[test]
+    fn test_tun_existing() {
+        let config = from_string("[General]\ntun = tun0\n").unwrap();
+        let inbound = config
+            .inbounds
+            .iter()
+            .find(|i| i.protocol == "tun")
+            .unwrap();
+        let settings = internal::TunInboundSettings::parse_from_bytes(&inbound.settings).unwrap();
+        assert!(settings.existing);
+        assert!(!settings.auto);
+        assert_eq!(settings.fd, -1);
+        assert_eq!(settings.name, "tun0");
+    }
+
+    #[test]
+    fn test_fwmark() {
+        let conf = |fwmark: &str| {
+            format!(
+                "[Proxy]\nDirect = direct, fwmark={}\n[Rule]\nFINAL, Direct\n",
+                fwmark
+            )
+        };
+        let config = from_string(&conf("0x10")).unwrap();
+        assert_eq!(config.outbounds[0].fwmark, 0x10);
+        let err = from_string(&conf("0xzz")).unwrap_err().to_string();
+        assert!(err.contains("invalid [Proxy] Direct"), "{}", err);
+        assert!(from_string(&conf("-1")).is_err());
+    }
+
+    #[test]
+    fn test_connect_options() {
+        let conf = |options: &str| {
+            format!(
+                "[Proxy]\nDirect = direct, {}\n[Rule]\nFINAL, Direct\n",
+                options
+            )
+        };
+        let config = from_string(&conf("connect-timeout=5, connect-retries=2")).unwrap();
+        assert_eq!(config.outbounds[0].connect_timeout, 5);
+        assert_eq!(config.outbounds[0].connect_retries, 2);
+        let err = from_string(&conf("connect-timeout=5s"))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("invalid connect-timeout 5s"), "{}", err);
+        assert!(from_string(&conf("connect-retries=-1")).is_err());
+    }
+
+    #[test]
+    fn test_weights() {
+        let conf = |weights: &str| {
+            format!(
+                "[Proxy]\nDirect = direct\nReject = reject\n\
+                 [Proxy Group]\nWeighted = static, Direct, Reject, method=weighted, weights={}\n\
+                 [Rule]\nFINAL, Weighted\n",
+                weights
+            )
+        };
+        let config = from_string(&conf("3|1")).unwrap();
+        let outbound = config
+            .outbounds
+            .iter()
+            .find(|o| o.tag == "Weighted")
+            .unwrap();
+        let settings =
+            internal::StaticOutboundSettings::parse_from_bytes(&outbound.settings).unwrap();
+        assert_eq!(settings.weights, vec![3, 1]);
+        let err = from_string(&conf("3|x")).unwrap_err().to_string();
+        assert!(err.contains("invalid weights 3|x"), "{}", err);
+        assert!(from_string(&conf("3|-1")).is_err());
+    }
+
+    #[test]
+    fn test_fallback_timeout() {
+        let conf = |timeout: &str| {
+            format!(
+                "[Proxy]\nDirect = direct\nReject = reject\n\
+                 [Proxy Group]\nFallback = static, Direct, Reject, method=fallback, fallback-timeout={}\n\
+                 [Rule]\nFINAL, Fallback\n",
+                timeout
+            )
+        };
+        let config = from_string(&conf("2")).unwrap();
+        let outbound = config
+            .outbounds
+            .iter()
+            .find(|o| o.tag == "Fallback")
+            .unwrap();
+        let settings =
+            internal::StaticOutboundSettings::parse_from_bytes(&outbound.settings).unwrap();
+        assert_eq!(settings.fallback_timeout, 2);
+        let err = from_string(&conf("-1")).unwrap_err().to_string();
+        assert!(err.contains("invalid fallback-timeout -1"), "{}", err);
+    }
+
+    #[test]
+    fn test_dns_ttl() {
+        let conf = |ttl: &str| {
+            format!(
+                "[General]\ndns-ttl = {}\n[Proxy]\nDirect = direct\n[Rule]\nFINAL, Direct\n",
+                ttl
+            )
+        };
+        let config = from_string(&conf("example.com:60:300, example.org:30")).unwrap();
+        let rules = &config.dns.ttl_rules;
+        assert_eq!(rules.len(), 2);
+        assert_eq!(
+            (rules[0].domain.as_str(), rules[0].min, rules[0].max),
+            ("example.com", 60, 300)
+        );
+        assert_eq!(
+            (rules[1].domain.as_str(), rules[1].min, rules[1].max),
+            ("example.org", 30, 0)
+        );
+        for ttl in ["example.com", "example.com:x", "example.com:60:-1", ":60"] {
+            let err = from_string(&conf(ttl)).unwrap_err().to_string();
+            assert!(err.contains("invalid dns-ttl"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_dns_weight() {
+        let conf = |weight: &str| {
+            format!(
+                "[General]\ndns-weight = {}\n[Proxy]\nDirect = direct\n[Rule]\nFINAL, Direct\n",
+                weight
+            )
+        };
+        let config = from_string(&conf("1.1.1.1:5, ::1:1")).unwrap();
+        let weights = &config.dns.weights;
+        assert_eq!(weights.len(), 2);
+        assert_eq!(weights.get("1.1.1.1"), Some(&5));
+        assert_eq!(weights.get("::1"), Some(&1));
+        for weight in ["1.1.1.1", "1.1.1.1:x", "1.1.1.1:-1", ":5"] {
+            let err = from_string(&conf(weight)).unwrap_err().to_string();
+            assert!(err.contains("invalid dns-weight"), "{}", err);
+        }
+    }
 }
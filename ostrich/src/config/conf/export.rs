@@ -0,0 +1,760 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use protobuf::Message;
+
+use crate::config::internal;
+use crate::config::internal::router::Unmatched;
+
+// Kinds of the outbounds the parser derives from a single proxy line, tagged
+// `<tag>_<kind>_xxx`.
+const DERIVED_KINDS: &[&str] = &["tls", "ws", "amux", "quic", "trojan", "vmess", "ss", "obfs"];
+
+fn derived_tag(tag: &str, kind: &str) -> String {
+    format!("{}_{}_xxx", tag, kind)
+}
+
+fn settings<M: Message>(outbound: &internal::Outbound) -> M {
+    M::parse_from_bytes(&outbound.settings).unwrap_or_default()
+}
+
+fn level_str(level: internal::log::Level) -> &'static str {
+    match level {
+        internal::log::Level::TRACE => "trace",
+        internal::log::Level::DEBUG => "debug",
+        internal::log::Level::INFO => "info",
+        internal::log::Level::WARN => "warn",
+        internal::log::Level::ERROR => "error",
+    }
+}
+
+fn push_param(params: &mut Vec<String>, key: &str, value: &str) {
+    if !value.is_empty() {
+        params.push(format!("{}={}", key, value));
+    }
+}
+
+fn general(config: &internal::Config) -> Vec<String> {
+    let mut lines = Vec::new();
+
+    let log = &config.log;
+    lines.push(format!(
+        "loglevel = {}",
+        level_str(log.level.enum_value_or_default())
+    ));
+    match log.output.enum_value_or_default() {
+        internal::log::Output::CONSOLE => lines.push("logoutput = console".to_string()),
+        internal::log::Output::SYSLOG => lines.push("logoutput = syslog".to_string()),
+        internal::log::Output::FILE => lines.push(format!("logoutput = {}", log.output_file)),
+    }
+    if log.rotation.enum_value_or_default() == internal::log::Rotation::DAILY {
+        lines.push("log-rotation = daily".to_string());
+    }
+    if log.syslog {
+        lines.push("log-syslog = true".to_string());
+    }
+    if !log.syslog_tag.is_empty() {
+        lines.push(format!("log-syslog-tag = {}", log.syslog_tag));
+    }
+    if !log.syslog_facility.is_empty() {
+        lines.push(format!("log-syslog-facility = {}", log.syslog_facility));
+    }
+    if log.ring_size > 0 {
+        lines.push(format!("log-ring-size = {}", log.ring_size));
+        lines.push(format!(
+            "log-ring-level = {}",
+            level_str(log.ring_level.enum_value_or_default())
+        ));
+    }
+
+    if !config.dns.servers.is_empty() {
+        lines.push(format!("dns-server = {}", config.dns.servers.join(", ")));
+    }
+    if config.dns.system_resolver {
+        lines.push("dns-system-resolver = true".to_string());
+    }
+    if !config.dns.weights.is_empty() {
+        let mut weights: Vec<String> = config
+            .dns
+            .weights
+            .iter()
+            .map(|(server, weight)| format!("{}:{}", server, weight))
+            .collect();
+        weights.sort();
+        lines.push(format!("dns-weight = {}", weights.join(", ")));
+    }
+    if !config.dns.ttl_rules.is_empty() {
+        let rules: Vec<String> = config
+            .dns
+            .ttl_rules
+            .iter()
+            .map(|r| {
+                if r.max > 0 {
+                    format!("{}:{}:{}", r.domain, r.min, r.max)
+                } else {
+                    format!("{}:{}", r.domain, r.min)
+                }
+            })
+            .collect();
+        lines.push(format!("dns-ttl = {}", rules.join(", ")));
+    }
+    if !config.dns.outbound.is_empty() {
+        lines.push(format!("dns-outbound = {}", config.dns.outbound));
+    }
+    if !config.dns.doh_server.is_empty() {
+        lines.push(format!("doh-server = {}", config.dns.doh_server));
+    }
+
+    for inbound in &config.inbounds {
+        match inbound.protocol.as_str() {
+            "http" | "socks" => {
+                let p = &inbound.protocol;
+                lines.push(format!("{}-interface = {}", p, inbound.address));
+                lines.push(format!("{}-port = {}", p, inbound.port));
+                if !inbound.interface.is_empty() {
+                    lines.push(format!("{}-bind-interface = {}", p, inbound.interface));
+                }
+                if inbound.accept_proxy_protocol {
+                    lines.push(format!("{}-accept-proxy-protocol = true", p));
+                }
+                if inbound.reuse_port {
+                    lines.push(format!("{}-reuse-port = true", p));
+                }
+                if let Ok(s) = internal::SocksInboundSettings::parse_from_bytes(&inbound.settings) {
+                    if !s.udp_address.is_empty() {
+                        lines.push(format!("socks-udp-interface = {}", s.udp_address));
+                    }
+                    if s.udp_port != 0 {
+                        lines.push(format!("socks-udp-port = {}", s.udp_port));
+                    }
+                    if !s.username.is_empty() {
+                        lines.push(format!("socks-username = {}", s.username));
+                        lines.push(format!("socks-password = {}", s.password));
+                    }
+                }
+            }
+            "tun" => {
+                let s = internal::TunInboundSettings::parse_from_bytes(&inbound.settings)
+                    .unwrap_or_default();
+                if s.auto {
+                    lines.push("tun = auto".to_string());
+                } else if s.fd >= 0 {
+                    lines.push(format!("tun-fd = {}", s.fd));
+                } else if s.existing {
+                    lines.push(format!("tun = {}", s.name));
+                } else {
+                    lines.push(format!(
+                        "tun = {}, {}, {}, {}, {}",
+                        s.name, s.address, s.netmask, s.gateway, s.mtu
+                    ));
+                }
+                if !s.dns.is_empty() {
+                    lines.push(format!("tun-dns = {}", s.dns));
+                }
+                if !s.fake_dns_exclude.is_empty() {
+                    lines.push(format!(
+                        "always-real-ip = {}",
+                        s.fake_dns_exclude.join(", ")
+                    ));
+                }
+                if !s.fake_dns_include.is_empty() {
+                    lines.push(format!(
+                        "always-fake-ip = {}",
+                        s.fake_dns_include.join(", ")
+                    ));
+                }
+            }
+            p => lines.push(format!(
+                "# inbound {} ({}) is not supported by the conf format",
+                inbound.tag, p
+            )),
+        }
+    }
+
+    if config.router.domain_resolve {
+        lines.push("routing-domain-resolve = true".to_string());
+    }
+    if config.router.lan_target.is_empty() {
+        lines.push("routing-bypass-lan = false".to_string());
+    }
+    match config.router.unmatched.enum_value_or_default() {
+        Unmatched::DEFAULT => (),
+        Unmatched::REJECT => lines.push("routing-unmatched = reject".to_string()),
+        Unmatched::DIRECT => lines.push("routing-unmatched = direct".to_string()),
+        Unmatched::ERROR => lines.push("routing-unmatched = error".to_string()),
+    }
+    if config.router.fail_closed {
+        lines.push("routing-fail-closed = true".to_string());
+    }
+
+    lines
+}
+
+// Folds a chain built by the parser from a single proxy line back into the
+// parameters of that line.
+fn folded_proxy(
+    tag: &str,
+    actors: &[String],
+    outbounds: &HashMap<&str, &internal::Outbound>,
+) -> Option<Vec<String>> {
+    let part = |kind: &str| outbounds.get(derived_tag(tag, kind).as_str()).copied();
+    let last = actors.last()?;
+    if !actors
+        .iter()
+        .all(|a| DERIVED_KINDS.iter().any(|k| *a == derived_tag(tag, k)))
+    {
+        return None;
+    }
+
+    let mut params = Vec::new();
+    if *last == derived_tag(tag, "ss") {
+        let ss: internal::ShadowsocksOutboundSettings = settings(part("ss")?);
+        params.push("ss".to_string());
+        params.push(ss.address.clone());
+        params.push(ss.port.to_string());
+        push_param(&mut params, "encrypt-method", &ss.method);
+        push_param(&mut params, "password", &ss.password);
+        if let Some(obfs) = part("obfs") {
+            let obfs: internal::ObfsOutboundSettings = settings(obfs);
+            push_param(&mut params, "obfs", &obfs.method);
+            push_param(&mut params, "obfs-host", &obfs.host);
+            push_param(&mut params, "obfs-path", &obfs.path);
+        }
+        push_param(&mut params, "proxy-protocol", &part("ss")?.proxy_protocol);
+        push_marks(&mut params, part("ss")?);
+        push_connect(&mut params, part("ss")?);
+        return Some(params);
+    }
+
+    let (protocol, address, port) = if *last == derived_tag(tag, "trojan") {
+        let t: internal::TrojanOutboundSettings = settings(part("trojan")?);
+        params.push("trojan".to_string());
+        ("trojan", t.address, t.port)
+    } else if *last == derived_tag(tag, "vmess") {
+        let v: internal::VMessOutboundSettings = settings(part("vmess")?);
+        params.push("vmess".to_string());
+        ("vmess", v.address, v.port)
+    } else {
+        return None;
+    };
+
+    // The amux outbound dials the server itself.
+    let amux = actors.contains(&derived_tag(tag, "amux"));
+    let mut server = (address, port);
+    let mut extra = Vec::new();
+    if amux {
+        let a: internal::AMuxOutboundSettings = settings(part("amux")?);
+        server = (a.address, a.port);
+        extra.push("amux=true".to_string());
+        extra.push(format!("amux-max={}", a.max_accepts));
+        extra.push(format!("amux-con={}", a.concurrency));
+    }
+    params.push(server.0);
+    params.push(server.1.to_string());
+
+    if let Some(tls) = part("tls") {
+        let tls: internal::TlsOutboundSettings = settings(tls);
+        if protocol == "vmess" {
+            params.push("tls=true".to_string());
+        }
+        push_param(&mut params, "sni", &tls.server_name);
+        push_param(&mut params, "tls-cert", &tls.certificate);
+        if tls.insecure {
+            params.push("tls-insecure=true".to_string());
+        }
+    } else if let Some(quic) = part("quic") {
+        let quic: internal::QuicOutboundSettings = settings(quic);
+        push_param(&mut params, "sni", &quic.server_name);
+        push_param(&mut params, "tls-cert", &quic.certificate);
+    }
+    if actors.contains(&derived_tag(tag, "quic")) {
+        params.push("quic=true".to_string());
+    }
+    if let Some(ws) = part("ws") {
+        let ws: internal::WebSocketOutboundSettings = settings(ws);
+        params.push("ws=true".to_string());
+        push_param(&mut params, "ws-path", &ws.path);
+        if let Some(host) = ws.headers.get("Host") {
+            push_param(&mut params, "ws-host", host);
+        }
+    }
+
+    if protocol == "trojan" {
+        let t: internal::TrojanOutboundSettings = settings(part("trojan")?);
+        push_param(&mut params, "password", &t.password);
+        if t.no_sni {
+            params.push("no-sni=true".to_string());
+        }
+        if t.compress {
+            params.push("compress=true".to_string());
+        }
+        push_param(&mut params, "tls-pin", &t.cert_pin);
+        push_param(&mut params, "tls-suites", &t.suites);
+        // Also set on the tls part, if any.
+        if !params.iter().any(|p| p.starts_with("tls-cert=")) {
+            push_param(&mut params, "tls-cert", &t.ca_cert);
+        }
+        if !params.iter().any(|p| p.starts_with("sni=")) {
+            push_param(&mut params, "sni", &t.sni);
+        }
+        if !t.alpn.is_empty() {
+            params.push(format!("alpn={}", t.alpn.join("|")));
+        }
+        if t.allow_insecure && !params.iter().any(|p| p == "tls-insecure=true") {
+            params.push("tls-insecure=true".to_string());
+        }
+        if !t.servers.is_empty() {
+            params.push(format!("servers={}", t.servers.join("|")));
+        }
+    } else {
+        let v: internal::VMessOutboundSettings = settings(part("vmess")?);
+        push_param(&mut params, "username", &v.uuid);
+        push_param(&mut params, "encrypt-method", &v.security);
+    }
+    params.extend(extra);
+    if let Some(base) = part(protocol) {
+        push_param(&mut params, "proxy-protocol", &base.proxy_protocol);
+        push_marks(&mut params, base);
+        push_connect(&mut params, base);
+    }
+    Some(params)
+}
+
+fn proxy(outbound: &internal::Outbound) -> Option<Vec<String>> {
+    let mut params = Vec::new();
+    match outbound.protocol.as_str() {
+        "direct" | "drop" => params.push(outbound.protocol.clone()),
+        "redirect" => {
+            let s: internal::RedirectOutboundSettings = settings(outbound);
+            params.push("redirect".to_string());
+            params.push(s.address);
+            params.push(s.port.to_string());
+        }
+        "socks" => {
+            let s: internal::SocksOutboundSettings = settings(outbound);
+            params.push("socks".to_string());
+            params.push(s.address);
+            params.push(s.port.to_string());
+            push_param(&mut params, "username", &s.username);
+            push_param(&mut params, "password", &s.password);
+        }
+        "shadowsocks" => {
+            let s: internal::ShadowsocksOutboundSettings = settings(outbound);
+            params.push("ss".to_string());
+            params.push(s.address);
+            params.push(s.port.to_string());
+            push_param(&mut params, "encrypt-method", &s.method);
+            push_param(&mut params, "password", &s.password);
+        }
+        "http2" => {
+            let s: internal::Http2OutboundSettings = settings(outbound);
+            params.push("http2".to_string());
+            params.push(s.address);
+            params.push(s.port.to_string());
+            push_param(&mut params, "sni", &s.server_name);
+            push_param(&mut params, "username", &s.username);
+            push_param(&mut params, "password", &s.password);
+            push_param(&mut params, "connect-protocol", &s.protocol);
+            push_param(&mut params, "connect-path", &s.path);
+        }
+        "ssh" => {
+            let s: internal::SshOutboundSettings = settings(outbound);
+            params.push("ssh".to_string());
+            params.push(s.address);
+            params.push(s.port.to_string());
+            push_param(&mut params, "username", &s.username);
+            push_param(&mut params, "password", &s.password);
+            push_param(&mut params, "private-key", &s.private_key);
+            push_param(
+                &mut params,
+                "private-key-passphrase",
+                &s.private_key_passphrase,
+            );
+            push_param(&mut params, "host-key", &s.host_key);
+            if s.allow_insecure {
+                params.push("host-key-insecure=true".to_string());
+            }
+        }
+        "vmess" => {
+            let s: internal::VMessOutboundSettings = settings(outbound);
+            params.push("vmess".to_string());
+            params.push(s.address);
+            params.push(s.port.to_string());
+            push_param(&mut params, "username", &s.uuid);
+            push_param(&mut params, "encrypt-method", &s.security);
+        }
+        _ => return None,
+    }
+    push_param(&mut params, "proxy-protocol", &outbound.proxy_protocol);
+    push_marks(&mut params, outbound);
+    push_connect(&mut params, outbound);
+    Some(params)
+}
+
+fn push_marks(params: &mut Vec<String>, outbound: &internal::Outbound) {
+    if outbound.dscp != 0 {
+        params.push(format!("dscp={}", outbound.dscp));
+    }
+    if outbound.fwmark != 0 {
+        params.push(format!("fwmark={:#x}", outbound.fwmark));
+    }
+}
+
+fn push_connect(params: &mut Vec<String>, outbound: &internal::Outbound) {
+    if outbound.connect_timeout != 0 {
+        params.push(format!("connect-timeout={}", outbound.connect_timeout));
+    }
+    if outbound.connect_retries != 0 {
+        params.push(format!("connect-retries={}", outbound.connect_retries));
+    }
+}
+
+fn proxy_group(outbound: &internal::Outbound) -> Option<Vec<String>> {
+    let mut params = vec![outbound.protocol.clone()];
+    match outbound.protocol.as_str() {
+        "chain" => {
+            let s: internal::ChainOutboundSettings = settings(outbound);
+            params.extend(s.actors);
+        }
+        "select" => {
+            let s: internal::SelectOutboundSettings = settings(outbound);
+            params.extend(s.actors);
+        }
+        "tryall" => {
+            let s: internal::TryAllOutboundSettings = settings(outbound);
+            params.extend(s.actors);
+            if s.delay_base > 0 {
+                params.push(format!("delay-base={}", s.delay_base));
+            }
+        }
+        "static" => {
+            let s: internal::StaticOutboundSettings = settings(outbound);
+            params.extend(s.actors);
+            push_param(&mut params, "method", &s.method);
+            push_param(&mut params, "smart-cache", &s.smart_cache);
+            if s.fallback_timeout > 0 {
+                params.push(format!("fallback-timeout={}", s.fallback_timeout));
+            }
+            if s.check_interval > 0 {
+                params.push(format!("check-interval={}", s.check_interval));
+            }
+            if !s.weights.is_empty() {
+                let weights: Vec<String> = s.weights.iter().map(ToString::to_string).collect();
+                params.push(format!("weights={}", weights.join("|")));
+            }
+        }
+        "failover" => {
+            let s: internal::FailOverOutboundSettings = settings(outbound);
+            params.extend(s.actors);
+            params.push(format!("health-check={}", s.health_check));
+            params.push(format!("check-interval={}", s.check_interval));
+            params.push(format!("fail-timeout={}", s.fail_timeout));
+            params.push(format!("failover={}", s.failover));
+            params.push(format!("fallback-cache={}", s.fallback_cache));
+            params.push(format!("cache-size={}", s.cache_size));
+            params.push(format!("cache-timeout={}", s.cache_timeout));
+            push_param(&mut params, "last-resort", &s.last_resort);
+            params.push(format!("health-check-timeout={}", s.health_check_timeout));
+            params.push(format!("health-check-delay={}", s.health_check_delay));
+            params.push(format!("health-check-active={}", s.health_check_active));
+        }
+        _ => return None,
+    }
+    if params.len() < 2 {
+        // The parser requires at least one actor.
+        return None;
+    }
+    Some(params)
+}
+
+fn rule(rule: &internal::router::Rule) -> Vec<String> {
+    use internal::router::rule::domain::Type;
+
+    let default_mmdb = Path::new(&*crate::option::ASSET_LOCATION)
+        .join("geo.mmdb")
+        .to_string_lossy()
+        .to_string();
+    let default_site = Path::new(&*crate::option::ASSET_LOCATION)
+        .join("site.dat")
+        .to_string_lossy()
+        .to_string();
+    let default_asn = Path::new(&*crate::option::ASSET_LOCATION)
+        .join("asn.mmdb")
+        .to_string_lossy()
+        .to_string();
+
+    // Conditions of different types are ANDed, which a conf rule can't
+    // express, those of the same type are ORed, i.e. one rule each.
+    let groups: Vec<Vec<(&str, String)>> = vec![
+        rule.domains
+            .iter()
+            .map(|d| {
+                let t = match d.type_.enum_value_or_default() {
+                    Type::PLAIN => "DOMAIN-KEYWORD",
+                    Type::DOMAIN => "DOMAIN-SUFFIX",
+                    Type::FULL => "DOMAIN",
+                };
+                (t, d.value.clone())
+            })
+            .collect(),
+        rule.ip_cidrs
+            .iter()
+            .map(|c| ("IP-CIDR", c.clone()))
+            .collect(),
+        rule.mmdbs
+            .iter()
+            .map(|m| {
+                if m.file == default_mmdb {
+                    ("GEOIP", m.country_code.clone())
+                } else {
+                    ("EXTERNAL", format!("mmdb:{}:{}", m.file, m.country_code))
+                }
+            })
+            .collect(),
+        rule.asns
+            .iter()
+            .map(|a| {
+                if a.file == default_asn {
+                    ("ASN", a.number.to_string())
+                } else {
+                    ("EXTERNAL", format!("asn:{}:{}", a.file, a.number))
+                }
+            })
+            .collect(),
+        rule.geosites
+            .iter()
+            .map(|g| {
+                if g.file == default_site {
+                    ("GEOSITE", g.category.clone())
+                } else {
+                    ("EXTERNAL", format!("geosite:{}:{}", g.file, g.category))
+                }
+            })
+            .collect(),
+        rule.port_ranges
+            .iter()
+            .map(|p| ("PORT-RANGE", p.clone()))
+            .collect(),
+        rule.networks
+            .iter()
+            .map(|n| ("NETWORK", n.clone()))
+            .collect(),
+        rule.inbound_tags
+            .iter()
+            .map(|t| ("INBOUND-TAG", t.clone()))
+            .collect(),
+        rule.snis.iter().map(|s| ("SNI", s.clone())).collect(),
+        rule.alpns.iter().map(|a| ("ALPN", a.clone())).collect(),
+    ];
+    let mut groups = groups.into_iter().filter(|g| !g.is_empty());
+    let conds = match (groups.next(), groups.next()) {
+        (Some(conds), None) => conds,
+        (None, _) if !rule.schedule.is_empty() => vec![("SCHEDULE", rule.schedule.clone())],
+        (None, _) => return Vec::new(),
+        _ => {
+            return vec![format!(
+                "# rule to {} combines several condition types, not supported by the conf format",
+                rule.target_tag
+            )]
+        }
+    };
+    conds
+        .into_iter()
+        .map(|(t, filter)| {
+            let mut line = format!("{}, {}, {}", t, filter, rule.target_tag);
+            if !rule.redirect.is_empty() {
+                line.push_str(&format!(", redirect={}", rule.redirect));
+            }
+            if rule.log {
+                line.push_str(", log=true");
+            }
+            if !rule.resolve.is_empty() {
+                line.push_str(&format!(", resolve={}", rule.resolve));
+            }
+            if !rule.schedule.is_empty() && t != "SCHEDULE" {
+                line.push_str(&format!(", schedule={}", rule.schedule));
+            }
+            line
+        })
+        .collect()
+}
+
+/// Renders the config in the conf format, parsing the result back gives an
+/// equivalent config. Outbounds derived from a single proxy line by the parser
+/// are folded back into that line, what the format can't express is written
+/// as comments.
+pub fn to_string(config: &internal::Config) -> String {
+    let by_tag: HashMap<&str, &internal::Outbound> = config
+        .outbounds
+        .iter()
+        .map(|o| (o.tag.as_str(), o))
+        .collect();
+
+    let mut proxies = Vec::new();
+    let mut groups = Vec::new();
+    let mut folded = HashSet::new();
+    for outbound in &config.outbounds {
+        if outbound.protocol != "chain" {
+            continue;
+        }
+        let chain: internal::ChainOutboundSettings = settings(outbound);
+        if let Some(params) = folded_proxy(&outbound.tag, &chain.actors, &by_tag) {
+            for kind in DERIVED_KINDS {
+                folded.insert(derived_tag(&outbound.tag, kind));
+            }
+            proxies.push((outbound.tag.as_str(), params));
+        }
+    }
+    let mut lines_proxy = Vec::new();
+    for outbound in &config.outbounds {
+        if folded.contains(&outbound.tag) {
+            continue;
+        }
+        if let Some(i) = proxies.iter().position(|(t, _)| *t == outbound.tag) {
+            let (tag, params) = proxies.remove(i);
+            lines_proxy.push(format!("{} = {}", tag, params.join(", ")));
+        } else if let Some(params) = proxy(outbound) {
+            lines_proxy.push(format!("{} = {}", outbound.tag, params.join(", ")));
+        } else if let Some(params) = proxy_group(outbound) {
+            groups.push(format!("{} = {}", outbound.tag, params.join(", ")));
+        } else {
+            lines_proxy.push(format!(
+                "# outbound {} ({}) is not supported by the conf format",
+                outbound.tag, outbound.protocol
+            ));
+        }
+    }
+
+    let mut rules: Vec<String> = config.router.rules.iter().flat_map(rule).collect();
+    // The parser makes the target of the FINAL rule the first outbound,
+    // which is where unmatched sessions go only by default.
+    if let (Some(first), Unmatched::DEFAULT) = (
+        config.outbounds.first(),
+        config.router.unmatched.enum_value_or_default(),
+    ) {
+        rules.push(format!("FINAL, {}", first.tag));
+    }
+
+    let mut hosts: Vec<(&String, &internal::dns::Ips)> = config.dns.hosts.iter().collect();
+    hosts.sort_by(|a, b| a.0.cmp(b.0));
+    let hosts: Vec<String> = hosts
+        .into_iter()
+        .map(|(name, ips)| format!("{} = {}", name, ips.values.join(", ")))
+        .collect();
+
+    let mut out = String::new();
+    for (section, lines) in [
+        ("General", general(config)),
+        ("Proxy", lines_proxy),
+        ("Proxy Group", groups),
+        ("Rule", rules),
+        ("Host", hosts),
+    ] {
+        if lines.is_empty() {
+            continue;
+        }
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        out.push_str(&format!("[{}]\n", section));
+        for line in lines {
+            out.push_str(&line);
+            out.push('\n');
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_string_roundtrip() {
+        let conf = r#"
+[General]
+loglevel = debug
+logoutput = /var/log/ostrich.log
+log-rotation = daily
+dns-server = 1.1.1.1, 8.8.8.8
+dns-ttl = cdn.example.com:60:3600, example.org:300
+doh-server = https://1.1.1.1/dns-query
+socks-interface = 127.0.0.1
+socks-port = 1080
+socks-udp-port = 1081
+socks-username = user
+socks-password = pass
+socks-reuse-port = true
+tun = utun8, 10.10.0.2, 255.255.255.0, 10.10.0.1, 1500
+tun-dns = system
+always-real-ip = *.apple.com
+routing-domain-resolve = true
+routing-fail-closed = true
+
+[Proxy]
+Direct = direct, connect-timeout=5, connect-retries=1
+Reject = reject
+SS = ss, 1.2.3.4, 8388, encrypt-method=aes-128-gcm, password=pass, obfs=http, obfs-host=example.com
+H2 = http2, proxy.example.com, 443, username=user, password=pass, connect-protocol=connect-tcp
+SSH = ssh, ssh.example.com, 22, username=user, password=pass, host-key=SHA256:nThbg6kXUpJWGl7E1IGOCspRomTxdCARLviKw6E5SY8, fwmark=0x10
+Trojan = trojan, 1.2.3.4, 443, password=pass, sni=example.com, ws=true, ws-path=/ws, compress=true, alpn=h2|http/1.1, tls-suites=TLS13_CHACHA20_POLY1305_SHA256, servers=1.2.3.5:443|1.2.3.6:8443
+VMess = vmess, 1.2.3.4, 10086, username=uuid, tls=true, amux=true
+
+[Proxy Group]
+Group = failover, SS, Trojan, health-check=false, fail-timeout=8
+Static = static, VMess, Direct, method=fallback, fallback-timeout=2
+Primary = static, Trojan, SS, method=failover, check-interval=5
+Weighted = static, Trojan, SS, Direct, method=weighted, weights=3|1|0
+
+[Rule]
+DOMAIN-SUFFIX, google.com, Group, log=true, resolve=remote
+IP-CIDR, 8.8.8.8/32, Static
+GEOIP, cn, Direct
+ASN, AS13335, Direct
+GEOSITE, category-ads, Reject
+NETWORK, udp, Direct, redirect=1.1.1.1:53
+SCHEDULE, mon-fri 09:00-18:00, Direct
+DOMAIN, example.org, Reject, schedule=sat|sun
+FINAL, Group
+
+[Host]
+example.com = 1.2.3.4, 5.6.7.8
+"#;
+        let config = super::super::from_string(conf).unwrap();
+        let exported = to_string(&config);
+        assert!(!exported.contains('#'), "{}", exported);
+        assert_eq!(super::super::from_string(&exported).unwrap(), config);
+        // Only keys the parser knows are written.
+        let lines = exported.lines().map(|l| Ok(l.to_string())).collect();
+        assert!(super::super::config::parse_lines(lines, true).is_ok());
+    }
+
+    #[test]
+    fn test_unmatched_roundtrip() {
+        let conf = r#"
+[General]
+routing-unmatched = reject
+
+[Proxy]
+Direct = direct
+
+[Rule]
+DOMAIN-SUFFIX, example.com, Direct
+"#;
+        let config = super::super::from_string(conf).unwrap();
+        assert_eq!(
+            config.router.unmatched.enum_value_or_default(),
+            Unmatched::REJECT
+        );
+        let exported = to_string(&config);
+        assert!(!exported.contains("FINAL"), "{}", exported);
+        assert_eq!(super::super::from_string(&exported).unwrap(), config);
+
+        let with_final = format!("{}FINAL, Direct\n", conf);
+        let config = super::super::from_string(&with_final).unwrap();
+        assert_eq!(
+            config.router.unmatched.enum_value_or_default(),
+            Unmatched::DEFAULT
+        );
+    }
+}
@@ -1,3 +1,5 @@
 mod config;
+mod export;
 
 pub use config::*;
+pub use export::to_string;
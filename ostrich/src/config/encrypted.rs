@@ -0,0 +1,123 @@
+//! Config files encrypted at rest, decrypted in memory when loaded.
+//!
+//! The container is the magic line, a random salt and nonce, then the config
+//! sealed with ChaCha20-Poly1305 under a key derived from a passphrase with
+//! PBKDF2-HMAC-SHA256. The passphrase is taken from the `CONFIG_KEY`
+//! environment variable, or the file `CONFIG_KEY_FILE` points to.
+
+use std::num::NonZeroU32;
+
+use anyhow::{anyhow, Result};
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305, NONCE_LEN};
+use ring::pbkdf2;
+use ring::rand::{SecureRandom, SystemRandom};
+
+const MAGIC: &[u8] = b"ostrich-encrypted-config-v1\n";
+const SALT_LEN: usize = 16;
+const KEY_LEN: usize = 32;
+const PBKDF2_ITERATIONS: u32 = 100_000;
+
+/// Returns whether `data` is an encrypted config.
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// Reads the passphrase from `CONFIG_KEY` or the file in `CONFIG_KEY_FILE`.
+pub fn key_from_env() -> Result<String> {
+    if let Ok(key) = std::env::var("CONFIG_KEY") {
+        if !key.is_empty() {
+            return Ok(key);
+        }
+    }
+    if let Ok(path) = std::env::var("CONFIG_KEY_FILE") {
+        let key = std::fs::read_to_string(&path)
+            .map_err(|e| anyhow!("read config key from {} failed: {}", &path, e))?;
+        let key = key.trim_end_matches(&['\r', '\n'][..]);
+        if !key.is_empty() {
+            return Ok(key.to_string());
+        }
+    }
+    Err(anyhow!(
+        "the config is encrypted, set the key in CONFIG_KEY or CONFIG_KEY_FILE"
+    ))
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<LessSafeKey> {
+    let mut key = [0u8; KEY_LEN];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        NonZeroU32::new(PBKDF2_ITERATIONS).unwrap(),
+        salt,
+        passphrase.as_bytes(),
+        &mut key,
+    );
+    let key =
+        UnboundKey::new(&CHACHA20_POLY1305, &key).map_err(|_| anyhow!("invalid config key"))?;
+    Ok(LessSafeKey::new(key))
+}
+
+/// Encrypts a config with the passphrase.
+pub fn encrypt(plaintext: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+    let rng = SystemRandom::new();
+    let mut salt = [0u8; SALT_LEN];
+    let mut nonce = [0u8; NONCE_LEN];
+    rng.fill(&mut salt)
+        .and_then(|_| rng.fill(&mut nonce))
+        .map_err(|_| anyhow!("generate random bytes failed"))?;
+    let mut sealed = plaintext.to_vec();
+    derive_key(passphrase, &salt)?
+        .seal_in_place_append_tag(
+            Nonce::assume_unique_for_key(nonce),
+            Aad::from(MAGIC),
+            &mut sealed,
+        )
+        .map_err(|_| anyhow!("encrypt config failed"))?;
+    let mut out = Vec::with_capacity(MAGIC.len() + SALT_LEN + NONCE_LEN + sealed.len());
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&salt);
+    out.extend_from_slice(&nonce);
+    out.extend_from_slice(&sealed);
+    Ok(out)
+}
+
+/// Decrypts a config encrypted by `encrypt`.
+pub fn decrypt(data: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+    let data = data
+        .strip_prefix(MAGIC)
+        .ok_or_else(|| anyhow!("not an encrypted config"))?;
+    if data.len() < SALT_LEN + NONCE_LEN + CHACHA20_POLY1305.tag_len() {
+        return Err(anyhow!("truncated encrypted config"));
+    }
+    let (salt, data) = data.split_at(SALT_LEN);
+    let (nonce, sealed) = data.split_at(NONCE_LEN);
+    let nonce =
+        Nonce::try_assume_unique_for_key(nonce).map_err(|_| anyhow!("invalid encrypted config"))?;
+    let mut buf = sealed.to_vec();
+    let len = derive_key(passphrase, salt)?
+        .open_in_place(nonce, Aad::from(MAGIC), &mut buf)
+        .map_err(|_| anyhow!("decrypt config failed, wrong key or corrupted file"))?
+        .len();
+    buf.truncate(len);
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encrypt_decrypt() {
+        let conf = b"[General]\nloglevel = info\n";
+        let data = encrypt(conf, "secret").unwrap();
+        assert!(is_encrypted(&data));
+        assert!(!is_encrypted(conf));
+        assert!(!data.windows(conf.len()).any(|w| w == conf));
+        assert_eq!(decrypt(&data, "secret").unwrap(), conf);
+
+        assert!(decrypt(&data, "wrong").is_err());
+        let mut tampered = data.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(decrypt(&tampered, "secret").is_err());
+        assert!(decrypt(&data[..MAGIC.len() + SALT_LEN], "secret").is_err());
+    }
+}
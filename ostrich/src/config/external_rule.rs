@@ -35,6 +35,121 @@ pub fn load_site_rule(filter: &str) -> Result<(String, String)> {
     load_file_or_default(filter, "site.dat")
 }
 
+pub fn load_asn_rule(filter: &str) -> Result<(String, String)> {
+    load_file_or_default(filter, "asn.mmdb")
+}
+
+/// Parses an autonomous system number, e.g. `13335` or `AS13335`.
+pub fn parse_asn(s: &str) -> Result<u32> {
+    let s = s.trim();
+    let digits = match s.get(..2) {
+        Some(prefix) if prefix.eq_ignore_ascii_case("as") => &s[2..],
+        _ => s,
+    };
+    digits
+        .parse::<u32>()
+        .map_err(|_| anyhow!("invalid asn {}", s))
+}
+
+fn new_domain(
+    type_: internal::router::rule::domain::Type,
+    value: String,
+) -> internal::router::rule::Domain {
+    let mut d = internal::router::rule::Domain::new();
+    d.type_ = protobuf::EnumOrUnknown::new(type_);
+    d.value = value;
+    d
+}
+
+/// Loads the domains of the category from a v2ray-style dat file, a plain
+/// domain list file, or the list named after the category in a directory.
+pub fn load_site_domains(
+    file: &str,
+    category: &str,
+) -> Result<Vec<internal::router::rule::Domain>> {
+    let path = Path::new(file);
+    if path.is_dir() {
+        return load_site_list(path, &category.to_lowercase(), 0);
+    }
+    if path.extension().map_or(false, |ext| ext == "dat") {
+        return load_site_dat(file, category);
+    }
+    parse_site_list(&std::fs::read_to_string(path)?, |_| {
+        Err(anyhow!("include in {} needs a directory of lists", file))
+    })
+}
+
+fn load_site_dat(file: &str, category: &str) -> Result<Vec<internal::router::rule::Domain>> {
+    use internal::router::rule::domain::Type;
+
+    let mut domains = Vec::new();
+    // Loads SiteGroup objects one by one instead of loading the whole list.
+    let mut reader = BufReader::with_capacity(2048, File::open(file)?);
+    let mut input = protobuf::CodedInputStream::new(&mut reader);
+    while !input.eof()? {
+        let _ = input.read_raw_byte()?; // skip
+        let mut site_group = input.read_message::<geosite::SiteGroup>()?;
+        if site_group.tag.eq_ignore_ascii_case(category) {
+            for domain in site_group.domain.iter_mut() {
+                let type_ = match domain.type_.unwrap() {
+                    geosite::domain::Type::Plain => Type::PLAIN,
+                    geosite::domain::Type::Domain => Type::DOMAIN,
+                    geosite::domain::Type::Full => Type::FULL,
+                    _ => continue,
+                };
+                domains.push(new_domain(type_, std::mem::take(&mut domain.value)));
+            }
+            return Ok(domains); // assume at most 1 matched tag
+        }
+    }
+    Err(anyhow!("category {} not found in {}", category, file))
+}
+
+// Loads a list of the v2fly domain-list-community layout, in which lists
+// include others in the same directory by name.
+fn load_site_list(
+    dir: &Path,
+    name: &str,
+    depth: usize,
+) -> Result<Vec<internal::router::rule::Domain>> {
+    if depth > 8 {
+        return Err(anyhow!("too deeply nested include of {}", name));
+    }
+    let path = dir.join(name);
+    let text = std::fs::read_to_string(&path)
+        .map_err(|e| anyhow!("read {} failed: {}", path.display(), e))?;
+    parse_site_list(&text, |name| load_site_list(dir, name, depth + 1))
+}
+
+// Parses lines like `example.com`, `domain:example.com`, `full:example.com`
+// or `keyword:example`, with `#` comments and trailing `@attribute`s.
+// Regular expressions aren't supported and skipped.
+fn parse_site_list<F>(text: &str, mut include: F) -> Result<Vec<internal::router::rule::Domain>>
+where
+    F: FnMut(&str) -> Result<Vec<internal::router::rule::Domain>>,
+{
+    use internal::router::rule::domain::Type;
+
+    let mut domains = Vec::new();
+    for line in text.lines() {
+        let line = line.split('#').next().unwrap_or_default();
+        let entry = match line.split_whitespace().next() {
+            Some(e) => e,
+            None => continue,
+        };
+        let (kind, value) = entry.split_once(':').unwrap_or(("domain", entry));
+        match kind {
+            "domain" => domains.push(new_domain(Type::DOMAIN, value.to_string())),
+            "full" => domains.push(new_domain(Type::FULL, value.to_string())),
+            "keyword" => domains.push(new_domain(Type::PLAIN, value.to_string())),
+            "include" => domains.append(&mut include(value)?),
+            "regexp" => log::debug!("skipped unsupported site rule {}", entry),
+            _ => return Err(anyhow!("invalid site rule {}", entry)),
+        }
+    }
+    Ok(domains)
+}
+
 pub fn add_external_rule(rule: &mut internal::router::Rule, ext_external: &str) -> Result<()> {
     if ext_external.starts_with("mmdb") {
         let (file, code) = match load_mmdb_rule(ext_external) {
@@ -57,53 +172,33 @@ pub fn add_external_rule(rule: &mut internal::router::Rule, ext_external: &str)
             }
         };
 
-        // Loads SiteGroup objects one by one instead of loading the whole list.
-        let mut reader = BufReader::with_capacity(2048, File::open(&file)?);
-        let mut input = protobuf::CodedInputStream::new(&mut reader);
-        while !input.eof()? {
-            let _ = input.read_raw_byte()?; // skip
-            let mut site_group = input.read_message::<geosite::SiteGroup>()?;
-            if site_group.tag == code.to_uppercase() {
-                for domain in site_group.domain.iter_mut() {
-                    let mut domain_rule = match domain.type_.unwrap() {
-                        geosite::domain::Type::Plain => {
-                            let mut d = internal::router::rule::Domain::new();
-                            d.type_ = protobuf::EnumOrUnknown::new(
-                                internal::router::rule::domain::Type::PLAIN,
-                            );
-                            d
-                        }
-                        geosite::domain::Type::Domain => {
-                            let mut d = internal::router::rule::Domain::new();
-                            d.type_ = protobuf::EnumOrUnknown::new(
-                                internal::router::rule::domain::Type::DOMAIN,
-                            );
-                            d
-                        }
-                        geosite::domain::Type::Full => {
-                            let mut d = internal::router::rule::Domain::new();
-                            d.type_ = protobuf::EnumOrUnknown::new(
-                                internal::router::rule::domain::Type::FULL,
-                            );
-                            d
-                        }
-                        _ => {
-                            continue;
-                        }
-                    };
-                    let value = std::mem::take(&mut domain.value);
-                    domain_rule.value = value;
-                    rule.domains.push(domain_rule);
-                }
-                // println!(
-                //     "loaded {} domain rules from [{}] for tag [{}]",
-                //     rule.domains.len(),
-                //     file,
-                //     code
-                // );
-                break; // assume at most 1 matched tag
+        rule.domains.append(&mut load_site_domains(&file, &code)?);
+    }
+
+    if ext_external.starts_with("asn") {
+        let (file, number) = match load_asn_rule(ext_external) {
+            Ok((f, n)) => (f, n),
+            Err(e) => {
+                return Err(anyhow!("load asn rule failed: {}", e));
+            }
+        };
+        let mut asn = internal::router::rule::Asn::new();
+        asn.file = file;
+        asn.number = parse_asn(&number)?;
+        rule.asns.push(asn);
+    }
+
+    if ext_external.starts_with("geosite") {
+        let (file, category) = match load_site_rule(ext_external) {
+            Ok((f, c)) => (f, c),
+            Err(e) => {
+                return Err(anyhow!("load geosite rule failed: {}", e));
             }
-        }
+        };
+        let mut geosite = internal::router::rule::Geosite::new();
+        geosite.file = file;
+        geosite.category = category;
+        rule.geosites.push(geosite);
     }
     Ok(())
 }
@@ -34,6 +34,16 @@ pub struct Dns {
     pub servers: ::std::vec::Vec<::std::string::String>,
     // @@protoc_insertion_point(field:Dns.hosts)
     pub hosts: ::std::collections::HashMap<::std::string::String, dns::Ips>,
+    // @@protoc_insertion_point(field:Dns.system_resolver)
+    pub system_resolver: bool,
+    // @@protoc_insertion_point(field:Dns.outbound)
+    pub outbound: ::std::string::String,
+    // @@protoc_insertion_point(field:Dns.weights)
+    pub weights: ::std::collections::HashMap<::std::string::String, u32>,
+    // @@protoc_insertion_point(field:Dns.ttl_rules)
+    pub ttl_rules: ::std::vec::Vec<dns::TtlRule>,
+    // @@protoc_insertion_point(field:Dns.doh_server)
+    pub doh_server: ::std::string::String,
     // special fields
     // @@protoc_insertion_point(special_field:Dns.special_fields)
     pub special_fields: ::protobuf::SpecialFields,
@@ -79,6 +89,33 @@ impl ::protobuf::Message for Dns {
                     is.pop_limit(old_limit);
                     self.hosts.insert(key, value);
                 },
+                32 => {
+                    self.system_resolver = is.read_bool()?;
+                },
+                42 => {
+                    self.outbound = is.read_string()?;
+                },
+                50 => {
+                    let len = is.read_raw_varint32()?;
+                    let old_limit = is.push_limit(len as u64)?;
+                    let mut key = ::std::default::Default::default();
+                    let mut value = ::std::default::Default::default();
+                    while let Some(tag) = is.read_raw_tag_or_eof()? {
+                        match tag {
+                            10 => key = is.read_string()?,
+                            16 => value = is.read_uint32()?,
+                            _ => ::protobuf::rt::skip_field_for_tag(tag, is)?,
+                        };
+                    }
+                    is.pop_limit(old_limit);
+                    self.weights.insert(key, value);
+                },
+                58 => {
+                    self.ttl_rules.push(is.read_message()?);
+                },
+                66 => {
+                    self.doh_server = is.read_string()?;
+                },
                 tag => {
                     ::protobuf::rt::read_unknown_or_skip_group(tag, is, self.special_fields.mut_unknown_fields())?;
                 },
@@ -101,6 +138,25 @@ impl ::protobuf::Message for Dns {
             entry_size += 1 + ::protobuf::rt::compute_raw_varint64_size(len) + len;
             my_size += 1 + ::protobuf::rt::compute_raw_varint64_size(entry_size) + entry_size
         };
+        if self.system_resolver != false {
+            my_size += 1 + 1;
+        }
+        if !self.outbound.is_empty() {
+            my_size += ::protobuf::rt::string_size(5, &self.outbound);
+        }
+        for (k, v) in &self.weights {
+            let mut entry_size = 0;
+            entry_size += ::protobuf::rt::string_size(1, &k);
+            entry_size += ::protobuf::rt::uint32_size(2, *v);
+            my_size += 1 + ::protobuf::rt::compute_raw_varint64_size(entry_size) + entry_size
+        };
+        for value in &self.ttl_rules {
+            let len = value.compute_size();
+            my_size += 1 + ::protobuf::rt::compute_raw_varint64_size(len) + len;
+        };
+        if !self.doh_server.is_empty() {
+            my_size += ::protobuf::rt::string_size(8, &self.doh_server);
+        }
         my_size += ::protobuf::rt::unknown_fields_size(self.special_fields.unknown_fields());
         self.special_fields.cached_size().set(my_size as u32);
         my_size
@@ -120,6 +176,27 @@ impl ::protobuf::Message for Dns {
             os.write_string(1, &k)?;
             ::protobuf::rt::write_message_field_with_cached_size(2, v, os)?;
         };
+        if self.system_resolver != false {
+            os.write_bool(4, self.system_resolver)?;
+        }
+        if !self.outbound.is_empty() {
+            os.write_string(5, &self.outbound)?;
+        }
+        for (k, v) in &self.weights {
+            let mut entry_size = 0;
+            entry_size += ::protobuf::rt::string_size(1, &k);
+            entry_size += ::protobuf::rt::uint32_size(2, *v);
+            os.write_raw_varint32(50)?; // Tag.
+            os.write_raw_varint32(entry_size as u32)?;
+            os.write_string(1, &k)?;
+            os.write_uint32(2, *v)?;
+        };
+        for v in &self.ttl_rules {
+            ::protobuf::rt::write_message_field_with_cached_size(7, v, os)?;
+        };
+        if !self.doh_server.is_empty() {
+            os.write_string(8, &self.doh_server)?;
+        }
         os.write_unknown_fields(self.special_fields.unknown_fields())?;
         ::std::result::Result::Ok(())
     }
@@ -139,6 +216,11 @@ impl ::protobuf::Message for Dns {
     fn clear(&mut self) {
         self.servers.clear();
         self.hosts.clear();
+        self.system_resolver = false;
+        self.outbound.clear();
+        self.weights.clear();
+        self.ttl_rules.clear();
+        self.doh_server.clear();
         self.special_fields.clear();
     }
 
@@ -239,6 +321,122 @@ pub mod dns {
             &instance
         }
     }
+
+    #[derive(PartialEq,Clone,Default,Debug)]
+    // @@protoc_insertion_point(message:Dns.TtlRule)
+    pub struct TtlRule {
+        // message fields
+        // @@protoc_insertion_point(field:Dns.TtlRule.domain)
+        pub domain: ::std::string::String,
+        // @@protoc_insertion_point(field:Dns.TtlRule.min)
+        pub min: u32,
+        // @@protoc_insertion_point(field:Dns.TtlRule.max)
+        pub max: u32,
+        // special fields
+        // @@protoc_insertion_point(special_field:Dns.TtlRule.special_fields)
+        pub special_fields: ::protobuf::SpecialFields,
+    }
+
+    impl<'a> ::std::default::Default for &'a TtlRule {
+        fn default() -> &'a TtlRule {
+            <TtlRule as ::protobuf::Message>::default_instance()
+        }
+    }
+
+    impl TtlRule {
+        pub fn new() -> TtlRule {
+            ::std::default::Default::default()
+        }
+    }
+
+    impl ::protobuf::Message for TtlRule {
+        const NAME: &'static str = "TtlRule";
+
+        fn is_initialized(&self) -> bool {
+            true
+        }
+
+        fn merge_from(&mut self, is: &mut ::protobuf::CodedInputStream<'_>) -> ::protobuf::Result<()> {
+            while let Some(tag) = is.read_raw_tag_or_eof()? {
+                match tag {
+                    10 => {
+                        self.domain = is.read_string()?;
+                    },
+                    16 => {
+                        self.min = is.read_uint32()?;
+                    },
+                    24 => {
+                        self.max = is.read_uint32()?;
+                    },
+                    tag => {
+                        ::protobuf::rt::read_unknown_or_skip_group(tag, is, self.special_fields.mut_unknown_fields())?;
+                    },
+                };
+            }
+            ::std::result::Result::Ok(())
+        }
+
+        // Compute sizes of nested messages
+        #[allow(unused_variables)]
+        fn compute_size(&self) -> u64 {
+            let mut my_size = 0;
+            if !self.domain.is_empty() {
+                my_size += ::protobuf::rt::string_size(1, &self.domain);
+            }
+            if self.min != 0 {
+                my_size += ::protobuf::rt::uint32_size(2, self.min);
+            }
+            if self.max != 0 {
+                my_size += ::protobuf::rt::uint32_size(3, self.max);
+            }
+            my_size += ::protobuf::rt::unknown_fields_size(self.special_fields.unknown_fields());
+            self.special_fields.cached_size().set(my_size as u32);
+            my_size
+        }
+
+        fn write_to_with_cached_sizes(&self, os: &mut ::protobuf::CodedOutputStream<'_>) -> ::protobuf::Result<()> {
+            if !self.domain.is_empty() {
+                os.write_string(1, &self.domain)?;
+            }
+            if self.min != 0 {
+                os.write_uint32(2, self.min)?;
+            }
+            if self.max != 0 {
+                os.write_uint32(3, self.max)?;
+            }
+            os.write_unknown_fields(self.special_fields.unknown_fields())?;
+            ::std::result::Result::Ok(())
+        }
+
+        fn special_fields(&self) -> &::protobuf::SpecialFields {
+            &self.special_fields
+        }
+
+        fn mut_special_fields(&mut self) -> &mut ::protobuf::SpecialFields {
+            &mut self.special_fields
+        }
+
+        fn new() -> TtlRule {
+            TtlRule::new()
+        }
+
+        fn clear(&mut self) {
+            self.domain.clear();
+            self.min = 0;
+            self.max = 0;
+            self.special_fields.clear();
+        }
+
+        fn default_instance() -> &'static TtlRule {
+            static instance: TtlRule = TtlRule {
+                domain: ::std::string::String::new(),
+                min: 0,
+                max: 0,
+                special_fields: ::protobuf::SpecialFields::new(),
+            };
+            &instance
+        }
+    }
 }
 
 #[derive(PartialEq,Clone,Default,Debug)]
@@ -251,6 +449,18 @@ pub struct Log {
     pub output: ::protobuf::EnumOrUnknown<log::Output>,
     // @@protoc_insertion_point(field:Log.output_file)
     pub output_file: ::std::string::String,
+    // @@protoc_insertion_point(field:Log.syslog)
+    pub syslog: bool,
+    // @@protoc_insertion_point(field:Log.syslog_tag)
+    pub syslog_tag: ::std::string::String,
+    // @@protoc_insertion_point(field:Log.syslog_facility)
+    pub syslog_facility: ::std::string::String,
+    // @@protoc_insertion_point(field:Log.ring_size)
+    pub ring_size: u32,
+    // @@protoc_insertion_point(field:Log.ring_level)
+    pub ring_level: ::protobuf::EnumOrUnknown<log::Level>,
+    // @@protoc_insertion_point(field:Log.rotation)
+    pub rotation: ::protobuf::EnumOrUnknown<log::Rotation>,
     // special fields
     // @@protoc_insertion_point(special_field:Log.special_fields)
     pub special_fields: ::protobuf::SpecialFields,
@@ -287,6 +497,24 @@ impl ::protobuf::Message for Log {
                 26 => {
                     self.output_file = is.read_string()?;
                 },
+                32 => {
+                    self.syslog = is.read_bool()?;
+                },
+                42 => {
+                    self.syslog_tag = is.read_string()?;
+                },
+                50 => {
+                    self.syslog_facility = is.read_string()?;
+                },
+                56 => {
+                    self.ring_size = is.read_uint32()?;
+                },
+                64 => {
+                    self.ring_level = is.read_enum_or_unknown()?;
+                },
+                72 => {
+                    self.rotation = is.read_enum_or_unknown()?;
+                },
                 tag => {
                     ::protobuf::rt::read_unknown_or_skip_group(tag, is, self.special_fields.mut_unknown_fields())?;
                 },
@@ -308,6 +536,24 @@ impl ::protobuf::Message for Log {
         if !self.output_file.is_empty() {
             my_size += ::protobuf::rt::string_size(3, &self.output_file);
         }
+        if self.syslog != false {
+            my_size += 1 + 1;
+        }
+        if !self.syslog_tag.is_empty() {
+            my_size += ::protobuf::rt::string_size(5, &self.syslog_tag);
+        }
+        if !self.syslog_facility.is_empty() {
+            my_size += ::protobuf::rt::string_size(6, &self.syslog_facility);
+        }
+        if self.ring_size != 0 {
+            my_size += ::protobuf::rt::uint32_size(7, self.ring_size);
+        }
+        if self.ring_level != ::protobuf::EnumOrUnknown::new(log::Level::INFO) {
+            my_size += ::protobuf::rt::int32_size(8, self.ring_level.value());
+        }
+        if self.rotation != ::protobuf::EnumOrUnknown::new(log::Rotation::NONE) {
+            my_size += ::protobuf::rt::int32_size(9, self.rotation.value());
+        }
         my_size += ::protobuf::rt::unknown_fields_size(self.special_fields.unknown_fields());
         self.special_fields.cached_size().set(my_size as u32);
         my_size
@@ -323,6 +569,24 @@ impl ::protobuf::Message for Log {
         if !self.output_file.is_empty() {
             os.write_string(3, &self.output_file)?;
         }
+        if self.syslog != false {
+            os.write_bool(4, self.syslog)?;
+        }
+        if !self.syslog_tag.is_empty() {
+            os.write_string(5, &self.syslog_tag)?;
+        }
+        if !self.syslog_facility.is_empty() {
+            os.write_string(6, &self.syslog_facility)?;
+        }
+        if self.ring_size != 0 {
+            os.write_uint32(7, self.ring_size)?;
+        }
+        if self.ring_level != ::protobuf::EnumOrUnknown::new(log::Level::INFO) {
+            os.write_enum(8, ::protobuf::EnumOrUnknown::value(&self.ring_level))?;
+        }
+        if self.rotation != ::protobuf::EnumOrUnknown::new(log::Rotation::NONE) {
+            os.write_enum(9, ::protobuf::EnumOrUnknown::value(&self.rotation))?;
+        }
         os.write_unknown_fields(self.special_fields.unknown_fields())?;
         ::std::result::Result::Ok(())
     }
@@ -343,6 +607,12 @@ impl ::protobuf::Message for Log {
         self.level = ::protobuf::EnumOrUnknown::new(log::Level::INFO);
         self.output = ::protobuf::EnumOrUnknown::new(log::Output::CONSOLE);
         self.output_file.clear();
+        self.syslog = false;
+        self.syslog_tag.clear();
+        self.syslog_facility.clear();
+        self.ring_size = 0;
+        self.ring_level = ::protobuf::EnumOrUnknown::new(log::Level::INFO);
+        self.rotation = ::protobuf::EnumOrUnknown::new(log::Rotation::NONE);
         self.special_fields.clear();
     }
 
@@ -351,6 +621,12 @@ impl ::protobuf::Message for Log {
             level: ::protobuf::EnumOrUnknown::from_i32(0),
             output: ::protobuf::EnumOrUnknown::from_i32(0),
             output_file: ::std::string::String::new(),
+            syslog: false,
+            syslog_tag: ::std::string::String::new(),
+            syslog_facility: ::std::string::String::new(),
+            ring_size: 0,
+            ring_level: ::protobuf::EnumOrUnknown::from_i32(0),
+            rotation: ::protobuf::EnumOrUnknown::from_i32(0),
             special_fields: ::protobuf::SpecialFields::new(),
         };
         &instance
@@ -415,6 +691,8 @@ pub mod log {
         CONSOLE = 0,
         // @@protoc_insertion_point(enum_value:Log.Output.FILE)
         FILE = 1,
+        // @@protoc_insertion_point(enum_value:Log.Output.SYSLOG)
+        SYSLOG = 2,
     }
 
     impl ::protobuf::Enum for Output {
@@ -428,6 +706,7 @@ pub mod log {
             match value {
                 0 => ::std::option::Option::Some(Output::CONSOLE),
                 1 => ::std::option::Option::Some(Output::FILE),
+                2 => ::std::option::Option::Some(Output::SYSLOG),
                 _ => ::std::option::Option::None
             }
         }
@@ -435,6 +714,7 @@ pub mod log {
         const VALUES: &'static [Output] = &[
             Output::CONSOLE,
             Output::FILE,
+            Output::SYSLOG,
         ];
     }
 
@@ -444,6 +724,43 @@ pub mod log {
         }
     }
 
+
+    #[derive(Clone,Copy,PartialEq,Eq,Debug,Hash)]
+    // @@protoc_insertion_point(enum:Log.Rotation)
+    pub enum Rotation {
+        // @@protoc_insertion_point(enum_value:Log.Rotation.NONE)
+        NONE = 0,
+        // @@protoc_insertion_point(enum_value:Log.Rotation.DAILY)
+        DAILY = 1,
+    }
+
+    impl ::protobuf::Enum for Rotation {
+        const NAME: &'static str = "Rotation";
+
+        fn value(&self) -> i32 {
+            *self as i32
+        }
+
+        fn from_i32(value: i32) -> ::std::option::Option<Rotation> {
+            match value {
+                0 => ::std::option::Option::Some(Rotation::NONE),
+                1 => ::std::option::Option::Some(Rotation::DAILY),
+                _ => ::std::option::Option::None
+            }
+        }
+
+        const VALUES: &'static [Rotation] = &[
+            Rotation::NONE,
+            Rotation::DAILY,
+        ];
+    }
+
+    impl ::std::default::Default for Rotation {
+        fn default() -> Self {
+            Rotation::NONE
+        }
+    }
+
 }
 
 #[derive(PartialEq,Clone,Default,Debug)]
@@ -468,6 +785,10 @@ pub struct TunInboundSettings {
     pub fake_dns_exclude: ::std::vec::Vec<::std::string::String>,
     // @@protoc_insertion_point(field:TunInboundSettings.fake_dns_include)
     pub fake_dns_include: ::std::vec::Vec<::std::string::String>,
+    // @@protoc_insertion_point(field:TunInboundSettings.existing)
+    pub existing: bool,
+    // @@protoc_insertion_point(field:TunInboundSettings.dns)
+    pub dns: ::std::string::String,
     // special fields
     // @@protoc_insertion_point(special_field:TunInboundSettings.special_fields)
     pub special_fields: ::protobuf::SpecialFields,
@@ -522,6 +843,12 @@ impl ::protobuf::Message for TunInboundSettings {
                 66 => {
                     self.fake_dns_include.push(is.read_string()?);
                 },
+                80 => {
+                    self.existing = is.read_bool()?;
+                },
+                90 => {
+                    self.dns = is.read_string()?;
+                },
                 tag => {
                     ::protobuf::rt::read_unknown_or_skip_group(tag, is, self.special_fields.mut_unknown_fields())?;
                 },
@@ -561,6 +888,12 @@ impl ::protobuf::Message for TunInboundSettings {
         for value in &self.fake_dns_include {
             my_size += ::protobuf::rt::string_size(8, &value);
         };
+        if self.existing != false {
+            my_size += 1 + 1;
+        }
+        if !self.dns.is_empty() {
+            my_size += ::protobuf::rt::string_size(11, &self.dns);
+        }
         my_size += ::protobuf::rt::unknown_fields_size(self.special_fields.unknown_fields());
         self.special_fields.cached_size().set(my_size as u32);
         my_size
@@ -594,6 +927,12 @@ impl ::protobuf::Message for TunInboundSettings {
         for v in &self.fake_dns_include {
             os.write_string(8, &v)?;
         };
+        if self.existing != false {
+            os.write_bool(10, self.existing)?;
+        }
+        if !self.dns.is_empty() {
+            os.write_string(11, &self.dns)?;
+        }
         os.write_unknown_fields(self.special_fields.unknown_fields())?;
         ::std::result::Result::Ok(())
     }
@@ -620,6 +959,8 @@ impl ::protobuf::Message for TunInboundSettings {
         self.mtu = 0;
         self.fake_dns_exclude.clear();
         self.fake_dns_include.clear();
+        self.existing = false;
+        self.dns.clear();
         self.special_fields.clear();
     }
 
@@ -634,6 +975,8 @@ impl ::protobuf::Message for TunInboundSettings {
             mtu: 0,
             fake_dns_exclude: ::std::vec::Vec::new(),
             fake_dns_include: ::std::vec::Vec::new(),
+            existing: false,
+            dns: ::std::string::String::new(),
             special_fields: ::protobuf::SpecialFields::new(),
         };
         &instance
@@ -757,32 +1100,36 @@ impl ::protobuf::Message for CatInboundSettings {
 }
 
 #[derive(PartialEq,Clone,Default,Debug)]
-// @@protoc_insertion_point(message:ShadowsocksInboundSettings)
-pub struct ShadowsocksInboundSettings {
+// @@protoc_insertion_point(message:SocksInboundSettings)
+pub struct SocksInboundSettings {
     // message fields
-    // @@protoc_insertion_point(field:ShadowsocksInboundSettings.method)
-    pub method: ::std::string::String,
-    // @@protoc_insertion_point(field:ShadowsocksInboundSettings.password)
+    // @@protoc_insertion_point(field:SocksInboundSettings.udp_address)
+    pub udp_address: ::std::string::String,
+    // @@protoc_insertion_point(field:SocksInboundSettings.udp_port)
+    pub udp_port: u32,
+    // @@protoc_insertion_point(field:SocksInboundSettings.username)
+    pub username: ::std::string::String,
+    // @@protoc_insertion_point(field:SocksInboundSettings.password)
     pub password: ::std::string::String,
     // special fields
-    // @@protoc_insertion_point(special_field:ShadowsocksInboundSettings.special_fields)
+    // @@protoc_insertion_point(special_field:SocksInboundSettings.special_fields)
     pub special_fields: ::protobuf::SpecialFields,
 }
 
-impl<'a> ::std::default::Default for &'a ShadowsocksInboundSettings {
-    fn default() -> &'a ShadowsocksInboundSettings {
-        <ShadowsocksInboundSettings as ::protobuf::Message>::default_instance()
+impl<'a> ::std::default::Default for &'a SocksInboundSettings {
+    fn default() -> &'a SocksInboundSettings {
+        <SocksInboundSettings as ::protobuf::Message>::default_instance()
     }
 }
 
-impl ShadowsocksInboundSettings {
-    pub fn new() -> ShadowsocksInboundSettings {
+impl SocksInboundSettings {
+    pub fn new() -> SocksInboundSettings {
         ::std::default::Default::default()
     }
 }
 
-impl ::protobuf::Message for ShadowsocksInboundSettings {
-    const NAME: &'static str = "ShadowsocksInboundSettings";
+impl ::protobuf::Message for SocksInboundSettings {
+    const NAME: &'static str = "SocksInboundSettings";
 
     fn is_initialized(&self) -> bool {
         true
@@ -792,9 +1139,15 @@ impl ::protobuf::Message for ShadowsocksInboundSettings {
         while let Some(tag) = is.read_raw_tag_or_eof()? {
             match tag {
                 10 => {
-                    self.method = is.read_string()?;
+                    self.udp_address = is.read_string()?;
                 },
-                18 => {
+                16 => {
+                    self.udp_port = is.read_uint32()?;
+                },
+                26 => {
+                    self.username = is.read_string()?;
+                },
+                34 => {
                     self.password = is.read_string()?;
                 },
                 tag => {
@@ -809,11 +1162,17 @@ impl ::protobuf::Message for ShadowsocksInboundSettings {
     #[allow(unused_variables)]
     fn compute_size(&self) -> u64 {
         let mut my_size = 0;
-        if !self.method.is_empty() {
-            my_size += ::protobuf::rt::string_size(1, &self.method);
+        if !self.udp_address.is_empty() {
+            my_size += ::protobuf::rt::string_size(1, &self.udp_address);
+        }
+        if self.udp_port != 0 {
+            my_size += ::protobuf::rt::uint32_size(2, self.udp_port);
+        }
+        if !self.username.is_empty() {
+            my_size += ::protobuf::rt::string_size(3, &self.username);
         }
         if !self.password.is_empty() {
-            my_size += ::protobuf::rt::string_size(2, &self.password);
+            my_size += ::protobuf::rt::string_size(4, &self.password);
         }
         my_size += ::protobuf::rt::unknown_fields_size(self.special_fields.unknown_fields());
         self.special_fields.cached_size().set(my_size as u32);
@@ -821,11 +1180,17 @@ impl ::protobuf::Message for ShadowsocksInboundSettings {
     }
 
     fn write_to_with_cached_sizes(&self, os: &mut ::protobuf::CodedOutputStream<'_>) -> ::protobuf::Result<()> {
-        if !self.method.is_empty() {
-            os.write_string(1, &self.method)?;
+        if !self.udp_address.is_empty() {
+            os.write_string(1, &self.udp_address)?;
+        }
+        if self.udp_port != 0 {
+            os.write_uint32(2, self.udp_port)?;
+        }
+        if !self.username.is_empty() {
+            os.write_string(3, &self.username)?;
         }
         if !self.password.is_empty() {
-            os.write_string(2, &self.password)?;
+            os.write_string(4, &self.password)?;
         }
         os.write_unknown_fields(self.special_fields.unknown_fields())?;
         ::std::result::Result::Ok(())
@@ -839,19 +1204,23 @@ impl ::protobuf::Message for ShadowsocksInboundSettings {
         &mut self.special_fields
     }
 
-    fn new() -> ShadowsocksInboundSettings {
-        ShadowsocksInboundSettings::new()
+    fn new() -> SocksInboundSettings {
+        SocksInboundSettings::new()
     }
 
     fn clear(&mut self) {
-        self.method.clear();
+        self.udp_address.clear();
+        self.udp_port = 0;
+        self.username.clear();
         self.password.clear();
         self.special_fields.clear();
     }
 
-    fn default_instance() -> &'static ShadowsocksInboundSettings {
-        static instance: ShadowsocksInboundSettings = ShadowsocksInboundSettings {
-            method: ::std::string::String::new(),
+    fn default_instance() -> &'static SocksInboundSettings {
+        static instance: SocksInboundSettings = SocksInboundSettings {
+            udp_address: ::std::string::String::new(),
+            udp_port: 0,
+            username: ::std::string::String::new(),
             password: ::std::string::String::new(),
             special_fields: ::protobuf::SpecialFields::new(),
         };
@@ -860,30 +1229,32 @@ impl ::protobuf::Message for ShadowsocksInboundSettings {
 }
 
 #[derive(PartialEq,Clone,Default,Debug)]
-// @@protoc_insertion_point(message:TrojanInboundSettings)
-pub struct TrojanInboundSettings {
+// @@protoc_insertion_point(message:ShadowsocksInboundSettings)
+pub struct ShadowsocksInboundSettings {
     // message fields
-    // @@protoc_insertion_point(field:TrojanInboundSettings.passwords)
-    pub passwords: ::std::vec::Vec<::std::string::String>,
+    // @@protoc_insertion_point(field:ShadowsocksInboundSettings.method)
+    pub method: ::std::string::String,
+    // @@protoc_insertion_point(field:ShadowsocksInboundSettings.password)
+    pub password: ::std::string::String,
     // special fields
-    // @@protoc_insertion_point(special_field:TrojanInboundSettings.special_fields)
+    // @@protoc_insertion_point(special_field:ShadowsocksInboundSettings.special_fields)
     pub special_fields: ::protobuf::SpecialFields,
 }
 
-impl<'a> ::std::default::Default for &'a TrojanInboundSettings {
-    fn default() -> &'a TrojanInboundSettings {
-        <TrojanInboundSettings as ::protobuf::Message>::default_instance()
+impl<'a> ::std::default::Default for &'a ShadowsocksInboundSettings {
+    fn default() -> &'a ShadowsocksInboundSettings {
+        <ShadowsocksInboundSettings as ::protobuf::Message>::default_instance()
     }
 }
 
-impl TrojanInboundSettings {
-    pub fn new() -> TrojanInboundSettings {
+impl ShadowsocksInboundSettings {
+    pub fn new() -> ShadowsocksInboundSettings {
         ::std::default::Default::default()
     }
 }
 
-impl ::protobuf::Message for TrojanInboundSettings {
-    const NAME: &'static str = "TrojanInboundSettings";
+impl ::protobuf::Message for ShadowsocksInboundSettings {
+    const NAME: &'static str = "ShadowsocksInboundSettings";
 
     fn is_initialized(&self) -> bool {
         true
@@ -893,7 +1264,10 @@ impl ::protobuf::Message for TrojanInboundSettings {
         while let Some(tag) = is.read_raw_tag_or_eof()? {
             match tag {
                 10 => {
-                    self.passwords.push(is.read_string()?);
+                    self.method = is.read_string()?;
+                },
+                18 => {
+                    self.password = is.read_string()?;
                 },
                 tag => {
                     ::protobuf::rt::read_unknown_or_skip_group(tag, is, self.special_fields.mut_unknown_fields())?;
@@ -907,16 +1281,114 @@ impl ::protobuf::Message for TrojanInboundSettings {
     #[allow(unused_variables)]
     fn compute_size(&self) -> u64 {
         let mut my_size = 0;
-        for value in &self.passwords {
-            my_size += ::protobuf::rt::string_size(1, &value);
-        };
+        if !self.method.is_empty() {
+            my_size += ::protobuf::rt::string_size(1, &self.method);
+        }
+        if !self.password.is_empty() {
+            my_size += ::protobuf::rt::string_size(2, &self.password);
+        }
         my_size += ::protobuf::rt::unknown_fields_size(self.special_fields.unknown_fields());
         self.special_fields.cached_size().set(my_size as u32);
         my_size
     }
 
     fn write_to_with_cached_sizes(&self, os: &mut ::protobuf::CodedOutputStream<'_>) -> ::protobuf::Result<()> {
-        for v in &self.passwords {
+        if !self.method.is_empty() {
+            os.write_string(1, &self.method)?;
+        }
+        if !self.password.is_empty() {
+            os.write_string(2, &self.password)?;
+        }
+        os.write_unknown_fields(self.special_fields.unknown_fields())?;
+        ::std::result::Result::Ok(())
+    }
+
+    fn special_fields(&self) -> &::protobuf::SpecialFields {
+        &self.special_fields
+    }
+
+    fn mut_special_fields(&mut self) -> &mut ::protobuf::SpecialFields {
+        &mut self.special_fields
+    }
+
+    fn new() -> ShadowsocksInboundSettings {
+        ShadowsocksInboundSettings::new()
+    }
+
+    fn clear(&mut self) {
+        self.method.clear();
+        self.password.clear();
+        self.special_fields.clear();
+    }
+
+    fn default_instance() -> &'static ShadowsocksInboundSettings {
+        static instance: ShadowsocksInboundSettings = ShadowsocksInboundSettings {
+            method: ::std::string::String::new(),
+            password: ::std::string::String::new(),
+            special_fields: ::protobuf::SpecialFields::new(),
+        };
+        &instance
+    }
+}
+
+#[derive(PartialEq,Clone,Default,Debug)]
+// @@protoc_insertion_point(message:TrojanInboundSettings)
+pub struct TrojanInboundSettings {
+    // message fields
+    // @@protoc_insertion_point(field:TrojanInboundSettings.passwords)
+    pub passwords: ::std::vec::Vec<::std::string::String>,
+    // special fields
+    // @@protoc_insertion_point(special_field:TrojanInboundSettings.special_fields)
+    pub special_fields: ::protobuf::SpecialFields,
+}
+
+impl<'a> ::std::default::Default for &'a TrojanInboundSettings {
+    fn default() -> &'a TrojanInboundSettings {
+        <TrojanInboundSettings as ::protobuf::Message>::default_instance()
+    }
+}
+
+impl TrojanInboundSettings {
+    pub fn new() -> TrojanInboundSettings {
+        ::std::default::Default::default()
+    }
+}
+
+impl ::protobuf::Message for TrojanInboundSettings {
+    const NAME: &'static str = "TrojanInboundSettings";
+
+    fn is_initialized(&self) -> bool {
+        true
+    }
+
+    fn merge_from(&mut self, is: &mut ::protobuf::CodedInputStream<'_>) -> ::protobuf::Result<()> {
+        while let Some(tag) = is.read_raw_tag_or_eof()? {
+            match tag {
+                10 => {
+                    self.passwords.push(is.read_string()?);
+                },
+                tag => {
+                    ::protobuf::rt::read_unknown_or_skip_group(tag, is, self.special_fields.mut_unknown_fields())?;
+                },
+            };
+        }
+        ::std::result::Result::Ok(())
+    }
+
+    // Compute sizes of nested messages
+    #[allow(unused_variables)]
+    fn compute_size(&self) -> u64 {
+        let mut my_size = 0;
+        for value in &self.passwords {
+            my_size += ::protobuf::rt::string_size(1, &value);
+        };
+        my_size += ::protobuf::rt::unknown_fields_size(self.special_fields.unknown_fields());
+        self.special_fields.cached_size().set(my_size as u32);
+        my_size
+    }
+
+    fn write_to_with_cached_sizes(&self, os: &mut ::protobuf::CodedOutputStream<'_>) -> ::protobuf::Result<()> {
+        for v in &self.passwords {
             os.write_string(1, &v)?;
         };
         os.write_unknown_fields(self.special_fields.unknown_fields())?;
@@ -1452,6 +1924,12 @@ pub struct Inbound {
     pub port: u32,
     // @@protoc_insertion_point(field:Inbound.settings)
     pub settings: ::std::vec::Vec<u8>,
+    // @@protoc_insertion_point(field:Inbound.interface)
+    pub interface: ::std::string::String,
+    // @@protoc_insertion_point(field:Inbound.accept_proxy_protocol)
+    pub accept_proxy_protocol: bool,
+    // @@protoc_insertion_point(field:Inbound.reuse_port)
+    pub reuse_port: bool,
     // special fields
     // @@protoc_insertion_point(special_field:Inbound.special_fields)
     pub special_fields: ::protobuf::SpecialFields,
@@ -1494,6 +1972,15 @@ impl ::protobuf::Message for Inbound {
                 42 => {
                     self.settings = is.read_bytes()?;
                 },
+                50 => {
+                    self.interface = is.read_string()?;
+                },
+                56 => {
+                    self.accept_proxy_protocol = is.read_bool()?;
+                },
+                64 => {
+                    self.reuse_port = is.read_bool()?;
+                },
                 tag => {
                     ::protobuf::rt::read_unknown_or_skip_group(tag, is, self.special_fields.mut_unknown_fields())?;
                 },
@@ -1521,6 +2008,15 @@ impl ::protobuf::Message for Inbound {
         if !self.settings.is_empty() {
             my_size += ::protobuf::rt::bytes_size(5, &self.settings);
         }
+        if !self.interface.is_empty() {
+            my_size += ::protobuf::rt::string_size(6, &self.interface);
+        }
+        if self.accept_proxy_protocol != false {
+            my_size += 1 + 1;
+        }
+        if self.reuse_port != false {
+            my_size += 1 + 1;
+        }
         my_size += ::protobuf::rt::unknown_fields_size(self.special_fields.unknown_fields());
         self.special_fields.cached_size().set(my_size as u32);
         my_size
@@ -1542,6 +2038,15 @@ impl ::protobuf::Message for Inbound {
         if !self.settings.is_empty() {
             os.write_bytes(5, &self.settings)?;
         }
+        if !self.interface.is_empty() {
+            os.write_string(6, &self.interface)?;
+        }
+        if self.accept_proxy_protocol != false {
+            os.write_bool(7, self.accept_proxy_protocol)?;
+        }
+        if self.reuse_port != false {
+            os.write_bool(8, self.reuse_port)?;
+        }
         os.write_unknown_fields(self.special_fields.unknown_fields())?;
         ::std::result::Result::Ok(())
     }
@@ -1564,6 +2069,9 @@ impl ::protobuf::Message for Inbound {
         self.address.clear();
         self.port = 0;
         self.settings.clear();
+        self.interface.clear();
+        self.accept_proxy_protocol = false;
+        self.reuse_port = false;
         self.special_fields.clear();
     }
 
@@ -1574,6 +2082,9 @@ impl ::protobuf::Message for Inbound {
             address: ::std::string::String::new(),
             port: 0,
             settings: ::std::vec::Vec::new(),
+            interface: ::std::string::String::new(),
+            accept_proxy_protocol: false,
+            reuse_port: false,
             special_fields: ::protobuf::SpecialFields::new(),
         };
         &instance
@@ -2071,10 +2582,22 @@ pub struct TrojanOutboundSettings {
     pub server_name: ::std::string::String,
     // @@protoc_insertion_point(field:TrojanOutboundSettings.alpn)
     pub alpn: ::std::vec::Vec<::std::string::String>,
-    // @@protoc_insertion_point(field:TrojanOutboundSettings.certificate)
-    pub certificate: ::std::string::String,
+    // @@protoc_insertion_point(field:TrojanOutboundSettings.ca_cert)
+    pub ca_cert: ::std::string::String,
     // @@protoc_insertion_point(field:TrojanOutboundSettings.suites)
     pub suites: ::std::string::String,
+    // @@protoc_insertion_point(field:TrojanOutboundSettings.no_sni)
+    pub no_sni: bool,
+    // @@protoc_insertion_point(field:TrojanOutboundSettings.cert_pin)
+    pub cert_pin: ::std::string::String,
+    // @@protoc_insertion_point(field:TrojanOutboundSettings.servers)
+    pub servers: ::std::vec::Vec<::std::string::String>,
+    // @@protoc_insertion_point(field:TrojanOutboundSettings.compress)
+    pub compress: bool,
+    // @@protoc_insertion_point(field:TrojanOutboundSettings.allow_insecure)
+    pub allow_insecure: bool,
+    // @@protoc_insertion_point(field:TrojanOutboundSettings.sni)
+    pub sni: ::std::string::String,
     // special fields
     // @@protoc_insertion_point(special_field:TrojanOutboundSettings.special_fields)
     pub special_fields: ::protobuf::SpecialFields,
@@ -2118,11 +2641,29 @@ impl ::protobuf::Message for TrojanOutboundSettings {
                     self.alpn.push(is.read_string()?);
                 },
                 50 => {
-                    self.certificate = is.read_string()?;
+                    self.ca_cert = is.read_string()?;
                 },
                 58 => {
                     self.suites = is.read_string()?;
                 },
+                64 => {
+                    self.no_sni = is.read_bool()?;
+                },
+                74 => {
+                    self.cert_pin = is.read_string()?;
+                },
+                82 => {
+                    self.servers.push(is.read_string()?);
+                },
+                88 => {
+                    self.compress = is.read_bool()?;
+                },
+                96 => {
+                    self.allow_insecure = is.read_bool()?;
+                },
+                106 => {
+                    self.sni = is.read_string()?;
+                },
                 tag => {
                     ::protobuf::rt::read_unknown_or_skip_group(tag, is, self.special_fields.mut_unknown_fields())?;
                 },
@@ -2150,12 +2691,30 @@ impl ::protobuf::Message for TrojanOutboundSettings {
         for value in &self.alpn {
             my_size += ::protobuf::rt::string_size(5, &value);
         };
-        if !self.certificate.is_empty() {
-            my_size += ::protobuf::rt::string_size(6, &self.certificate);
+        if !self.ca_cert.is_empty() {
+            my_size += ::protobuf::rt::string_size(6, &self.ca_cert);
         }
         if !self.suites.is_empty() {
             my_size += ::protobuf::rt::string_size(7, &self.suites);
         }
+        if self.no_sni != false {
+            my_size += 1 + 1;
+        }
+        if !self.cert_pin.is_empty() {
+            my_size += ::protobuf::rt::string_size(9, &self.cert_pin);
+        }
+        for value in &self.servers {
+            my_size += ::protobuf::rt::string_size(10, &value);
+        };
+        if self.compress != false {
+            my_size += 1 + 1;
+        }
+        if self.allow_insecure != false {
+            my_size += 1 + 1;
+        }
+        if !self.sni.is_empty() {
+            my_size += ::protobuf::rt::string_size(13, &self.sni);
+        }
         my_size += ::protobuf::rt::unknown_fields_size(self.special_fields.unknown_fields());
         self.special_fields.cached_size().set(my_size as u32);
         my_size
@@ -2177,12 +2736,30 @@ impl ::protobuf::Message for TrojanOutboundSettings {
         for v in &self.alpn {
             os.write_string(5, &v)?;
         };
-        if !self.certificate.is_empty() {
-            os.write_string(6, &self.certificate)?;
+        if !self.ca_cert.is_empty() {
+            os.write_string(6, &self.ca_cert)?;
         }
         if !self.suites.is_empty() {
             os.write_string(7, &self.suites)?;
         }
+        if self.no_sni != false {
+            os.write_bool(8, self.no_sni)?;
+        }
+        if !self.cert_pin.is_empty() {
+            os.write_string(9, &self.cert_pin)?;
+        }
+        for v in &self.servers {
+            os.write_string(10, &v)?;
+        };
+        if self.compress != false {
+            os.write_bool(11, self.compress)?;
+        }
+        if self.allow_insecure != false {
+            os.write_bool(12, self.allow_insecure)?;
+        }
+        if !self.sni.is_empty() {
+            os.write_string(13, &self.sni)?;
+        }
         os.write_unknown_fields(self.special_fields.unknown_fields())?;
         ::std::result::Result::Ok(())
     }
@@ -2205,8 +2782,14 @@ impl ::protobuf::Message for TrojanOutboundSettings {
         self.password.clear();
         self.server_name.clear();
         self.alpn.clear();
-        self.certificate.clear();
+        self.ca_cert.clear();
         self.suites.clear();
+        self.no_sni = false;
+        self.cert_pin.clear();
+        self.servers.clear();
+        self.compress = false;
+        self.allow_insecure = false;
+        self.sni.clear();
         self.special_fields.clear();
     }
 
@@ -2217,8 +2800,363 @@ impl ::protobuf::Message for TrojanOutboundSettings {
             password: ::std::string::String::new(),
             server_name: ::std::string::String::new(),
             alpn: ::std::vec::Vec::new(),
-            certificate: ::std::string::String::new(),
+            ca_cert: ::std::string::String::new(),
             suites: ::std::string::String::new(),
+            no_sni: false,
+            cert_pin: ::std::string::String::new(),
+            servers: ::std::vec::Vec::new(),
+            compress: false,
+            allow_insecure: false,
+            sni: ::std::string::String::new(),
+            special_fields: ::protobuf::SpecialFields::new(),
+        };
+        &instance
+    }
+}
+
+#[derive(PartialEq,Clone,Default,Debug)]
+// @@protoc_insertion_point(message:Http2OutboundSettings)
+pub struct Http2OutboundSettings {
+    // message fields
+    // @@protoc_insertion_point(field:Http2OutboundSettings.address)
+    pub address: ::std::string::String,
+    // @@protoc_insertion_point(field:Http2OutboundSettings.port)
+    pub port: u32,
+    // @@protoc_insertion_point(field:Http2OutboundSettings.server_name)
+    pub server_name: ::std::string::String,
+    // @@protoc_insertion_point(field:Http2OutboundSettings.username)
+    pub username: ::std::string::String,
+    // @@protoc_insertion_point(field:Http2OutboundSettings.password)
+    pub password: ::std::string::String,
+    // @@protoc_insertion_point(field:Http2OutboundSettings.protocol)
+    pub protocol: ::std::string::String,
+    // @@protoc_insertion_point(field:Http2OutboundSettings.path)
+    pub path: ::std::string::String,
+    // special fields
+    // @@protoc_insertion_point(special_field:Http2OutboundSettings.special_fields)
+    pub special_fields: ::protobuf::SpecialFields,
+}
+
+impl<'a> ::std::default::Default for &'a Http2OutboundSettings {
+    fn default() -> &'a Http2OutboundSettings {
+        <Http2OutboundSettings as ::protobuf::Message>::default_instance()
+    }
+}
+
+impl Http2OutboundSettings {
+    pub fn new() -> Http2OutboundSettings {
+        ::std::default::Default::default()
+    }
+}
+
+impl ::protobuf::Message for Http2OutboundSettings {
+    const NAME: &'static str = "Http2OutboundSettings";
+
+    fn is_initialized(&self) -> bool {
+        true
+    }
+
+    fn merge_from(&mut self, is: &mut ::protobuf::CodedInputStream<'_>) -> ::protobuf::Result<()> {
+        while let Some(tag) = is.read_raw_tag_or_eof()? {
+            match tag {
+                10 => {
+                    self.address = is.read_string()?;
+                },
+                16 => {
+                    self.port = is.read_uint32()?;
+                },
+                26 => {
+                    self.server_name = is.read_string()?;
+                },
+                34 => {
+                    self.username = is.read_string()?;
+                },
+                42 => {
+                    self.password = is.read_string()?;
+                },
+                50 => {
+                    self.protocol = is.read_string()?;
+                },
+                58 => {
+                    self.path = is.read_string()?;
+                },
+                tag => {
+                    ::protobuf::rt::read_unknown_or_skip_group(tag, is, self.special_fields.mut_unknown_fields())?;
+                },
+            };
+        }
+        ::std::result::Result::Ok(())
+    }
+
+    // Compute sizes of nested messages
+    #[allow(unused_variables)]
+    fn compute_size(&self) -> u64 {
+        let mut my_size = 0;
+        if !self.address.is_empty() {
+            my_size += ::protobuf::rt::string_size(1, &self.address);
+        }
+        if self.port != 0 {
+            my_size += ::protobuf::rt::uint32_size(2, self.port);
+        }
+        if !self.server_name.is_empty() {
+            my_size += ::protobuf::rt::string_size(3, &self.server_name);
+        }
+        if !self.username.is_empty() {
+            my_size += ::protobuf::rt::string_size(4, &self.username);
+        }
+        if !self.password.is_empty() {
+            my_size += ::protobuf::rt::string_size(5, &self.password);
+        }
+        if !self.protocol.is_empty() {
+            my_size += ::protobuf::rt::string_size(6, &self.protocol);
+        }
+        if !self.path.is_empty() {
+            my_size += ::protobuf::rt::string_size(7, &self.path);
+        }
+        my_size += ::protobuf::rt::unknown_fields_size(self.special_fields.unknown_fields());
+        self.special_fields.cached_size().set(my_size as u32);
+        my_size
+    }
+
+    fn write_to_with_cached_sizes(&self, os: &mut ::protobuf::CodedOutputStream<'_>) -> ::protobuf::Result<()> {
+        if !self.address.is_empty() {
+            os.write_string(1, &self.address)?;
+        }
+        if self.port != 0 {
+            os.write_uint32(2, self.port)?;
+        }
+        if !self.server_name.is_empty() {
+            os.write_string(3, &self.server_name)?;
+        }
+        if !self.username.is_empty() {
+            os.write_string(4, &self.username)?;
+        }
+        if !self.password.is_empty() {
+            os.write_string(5, &self.password)?;
+        }
+        if !self.protocol.is_empty() {
+            os.write_string(6, &self.protocol)?;
+        }
+        if !self.path.is_empty() {
+            os.write_string(7, &self.path)?;
+        }
+        os.write_unknown_fields(self.special_fields.unknown_fields())?;
+        ::std::result::Result::Ok(())
+    }
+
+    fn special_fields(&self) -> &::protobuf::SpecialFields {
+        &self.special_fields
+    }
+
+    fn mut_special_fields(&mut self) -> &mut ::protobuf::SpecialFields {
+        &mut self.special_fields
+    }
+
+    fn new() -> Http2OutboundSettings {
+        Http2OutboundSettings::new()
+    }
+
+    fn clear(&mut self) {
+        self.address.clear();
+        self.port = 0;
+        self.server_name.clear();
+        self.username.clear();
+        self.password.clear();
+        self.protocol.clear();
+        self.path.clear();
+        self.special_fields.clear();
+    }
+
+    fn default_instance() -> &'static Http2OutboundSettings {
+        static instance: Http2OutboundSettings = Http2OutboundSettings {
+            address: ::std::string::String::new(),
+            port: 0,
+            server_name: ::std::string::String::new(),
+            username: ::std::string::String::new(),
+            password: ::std::string::String::new(),
+            protocol: ::std::string::String::new(),
+            path: ::std::string::String::new(),
+            special_fields: ::protobuf::SpecialFields::new(),
+        };
+        &instance
+    }
+}
+
+#[derive(PartialEq,Clone,Default,Debug)]
+// @@protoc_insertion_point(message:SshOutboundSettings)
+pub struct SshOutboundSettings {
+    // message fields
+    // @@protoc_insertion_point(field:SshOutboundSettings.address)
+    pub address: ::std::string::String,
+    // @@protoc_insertion_point(field:SshOutboundSettings.port)
+    pub port: u32,
+    // @@protoc_insertion_point(field:SshOutboundSettings.username)
+    pub username: ::std::string::String,
+    // @@protoc_insertion_point(field:SshOutboundSettings.password)
+    pub password: ::std::string::String,
+    // @@protoc_insertion_point(field:SshOutboundSettings.private_key)
+    pub private_key: ::std::string::String,
+    // @@protoc_insertion_point(field:SshOutboundSettings.private_key_passphrase)
+    pub private_key_passphrase: ::std::string::String,
+    // @@protoc_insertion_point(field:SshOutboundSettings.host_key)
+    pub host_key: ::std::string::String,
+    // @@protoc_insertion_point(field:SshOutboundSettings.allow_insecure)
+    pub allow_insecure: bool,
+    // special fields
+    // @@protoc_insertion_point(special_field:SshOutboundSettings.special_fields)
+    pub special_fields: ::protobuf::SpecialFields,
+}
+
+impl<'a> ::std::default::Default for &'a SshOutboundSettings {
+    fn default() -> &'a SshOutboundSettings {
+        <SshOutboundSettings as ::protobuf::Message>::default_instance()
+    }
+}
+
+impl SshOutboundSettings {
+    pub fn new() -> SshOutboundSettings {
+        ::std::default::Default::default()
+    }
+}
+
+impl ::protobuf::Message for SshOutboundSettings {
+    const NAME: &'static str = "SshOutboundSettings";
+
+    fn is_initialized(&self) -> bool {
+        true
+    }
+
+    fn merge_from(&mut self, is: &mut ::protobuf::CodedInputStream<'_>) -> ::protobuf::Result<()> {
+        while let Some(tag) = is.read_raw_tag_or_eof()? {
+            match tag {
+                10 => {
+                    self.address = is.read_string()?;
+                },
+                16 => {
+                    self.port = is.read_uint32()?;
+                },
+                26 => {
+                    self.username = is.read_string()?;
+                },
+                34 => {
+                    self.password = is.read_string()?;
+                },
+                42 => {
+                    self.private_key = is.read_string()?;
+                },
+                50 => {
+                    self.private_key_passphrase = is.read_string()?;
+                },
+                58 => {
+                    self.host_key = is.read_string()?;
+                },
+                64 => {
+                    self.allow_insecure = is.read_bool()?;
+                },
+                tag => {
+                    ::protobuf::rt::read_unknown_or_skip_group(tag, is, self.special_fields.mut_unknown_fields())?;
+                },
+            };
+        }
+        ::std::result::Result::Ok(())
+    }
+
+    // Compute sizes of nested messages
+    #[allow(unused_variables)]
+    fn compute_size(&self) -> u64 {
+        let mut my_size = 0;
+        if !self.address.is_empty() {
+            my_size += ::protobuf::rt::string_size(1, &self.address);
+        }
+        if self.port != 0 {
+            my_size += ::protobuf::rt::uint32_size(2, self.port);
+        }
+        if !self.username.is_empty() {
+            my_size += ::protobuf::rt::string_size(3, &self.username);
+        }
+        if !self.password.is_empty() {
+            my_size += ::protobuf::rt::string_size(4, &self.password);
+        }
+        if !self.private_key.is_empty() {
+            my_size += ::protobuf::rt::string_size(5, &self.private_key);
+        }
+        if !self.private_key_passphrase.is_empty() {
+            my_size += ::protobuf::rt::string_size(6, &self.private_key_passphrase);
+        }
+        if !self.host_key.is_empty() {
+            my_size += ::protobuf::rt::string_size(7, &self.host_key);
+        }
+        if self.allow_insecure != false {
+            my_size += 1 + 1;
+        }
+        my_size += ::protobuf::rt::unknown_fields_size(self.special_fields.unknown_fields());
+        self.special_fields.cached_size().set(my_size as u32);
+        my_size
+    }
+
+    fn write_to_with_cached_sizes(&self, os: &mut ::protobuf::CodedOutputStream<'_>) -> ::protobuf::Result<()> {
+        if !self.address.is_empty() {
+            os.write_string(1, &self.address)?;
+        }
+        if self.port != 0 {
+            os.write_uint32(2, self.port)?;
+        }
+        if !self.username.is_empty() {
+            os.write_string(3, &self.username)?;
+        }
+        if !self.password.is_empty() {
+            os.write_string(4, &self.password)?;
+        }
+        if !self.private_key.is_empty() {
+            os.write_string(5, &self.private_key)?;
+        }
+        if !self.private_key_passphrase.is_empty() {
+            os.write_string(6, &self.private_key_passphrase)?;
+        }
+        if !self.host_key.is_empty() {
+            os.write_string(7, &self.host_key)?;
+        }
+        if self.allow_insecure != false {
+            os.write_bool(8, self.allow_insecure)?;
+        }
+        os.write_unknown_fields(self.special_fields.unknown_fields())?;
+        ::std::result::Result::Ok(())
+    }
+
+    fn special_fields(&self) -> &::protobuf::SpecialFields {
+        &self.special_fields
+    }
+
+    fn mut_special_fields(&mut self) -> &mut ::protobuf::SpecialFields {
+        &mut self.special_fields
+    }
+
+    fn new() -> SshOutboundSettings {
+        SshOutboundSettings::new()
+    }
+
+    fn clear(&mut self) {
+        self.address.clear();
+        self.port = 0;
+        self.username.clear();
+        self.password.clear();
+        self.private_key.clear();
+        self.private_key_passphrase.clear();
+        self.host_key.clear();
+        self.allow_insecure = false;
+        self.special_fields.clear();
+    }
+
+    fn default_instance() -> &'static SshOutboundSettings {
+        static instance: SshOutboundSettings = SshOutboundSettings {
+            address: ::std::string::String::new(),
+            port: 0,
+            username: ::std::string::String::new(),
+            password: ::std::string::String::new(),
+            private_key: ::std::string::String::new(),
+            private_key_passphrase: ::std::string::String::new(),
+            host_key: ::std::string::String::new(),
+            allow_insecure: false,
             special_fields: ::protobuf::SpecialFields::new(),
         };
         &instance
@@ -2585,6 +3523,14 @@ pub struct StaticOutboundSettings {
     pub actors: ::std::vec::Vec<::std::string::String>,
     // @@protoc_insertion_point(field:StaticOutboundSettings.method)
     pub method: ::std::string::String,
+    // @@protoc_insertion_point(field:StaticOutboundSettings.smart_cache)
+    pub smart_cache: ::std::string::String,
+    // @@protoc_insertion_point(field:StaticOutboundSettings.fallback_timeout)
+    pub fallback_timeout: u32,
+    // @@protoc_insertion_point(field:StaticOutboundSettings.check_interval)
+    pub check_interval: u32,
+    // @@protoc_insertion_point(field:StaticOutboundSettings.weights)
+    pub weights: ::std::vec::Vec<u32>,
     // special fields
     // @@protoc_insertion_point(special_field:StaticOutboundSettings.special_fields)
     pub special_fields: ::protobuf::SpecialFields,
@@ -2615,8 +3561,23 @@ impl ::protobuf::Message for StaticOutboundSettings {
                 10 => {
                     self.actors.push(is.read_string()?);
                 },
-                18 => {
-                    self.method = is.read_string()?;
+                18 => {
+                    self.method = is.read_string()?;
+                },
+                26 => {
+                    self.smart_cache = is.read_string()?;
+                },
+                32 => {
+                    self.fallback_timeout = is.read_uint32()?;
+                },
+                40 => {
+                    self.check_interval = is.read_uint32()?;
+                },
+                50 => {
+                    is.read_repeated_packed_uint32_into(&mut self.weights)?;
+                },
+                48 => {
+                    self.weights.push(is.read_uint32()?);
                 },
                 tag => {
                     ::protobuf::rt::read_unknown_or_skip_group(tag, is, self.special_fields.mut_unknown_fields())?;
@@ -2636,6 +3597,16 @@ impl ::protobuf::Message for StaticOutboundSettings {
         if !self.method.is_empty() {
             my_size += ::protobuf::rt::string_size(2, &self.method);
         }
+        if !self.smart_cache.is_empty() {
+            my_size += ::protobuf::rt::string_size(3, &self.smart_cache);
+        }
+        if self.fallback_timeout != 0 {
+            my_size += ::protobuf::rt::uint32_size(4, self.fallback_timeout);
+        }
+        if self.check_interval != 0 {
+            my_size += ::protobuf::rt::uint32_size(5, self.check_interval);
+        }
+        my_size += ::protobuf::rt::vec_packed_uint32_size(6, &self.weights);
         my_size += ::protobuf::rt::unknown_fields_size(self.special_fields.unknown_fields());
         self.special_fields.cached_size().set(my_size as u32);
         my_size
@@ -2648,6 +3619,16 @@ impl ::protobuf::Message for StaticOutboundSettings {
         if !self.method.is_empty() {
             os.write_string(2, &self.method)?;
         }
+        if !self.smart_cache.is_empty() {
+            os.write_string(3, &self.smart_cache)?;
+        }
+        if self.fallback_timeout != 0 {
+            os.write_uint32(4, self.fallback_timeout)?;
+        }
+        if self.check_interval != 0 {
+            os.write_uint32(5, self.check_interval)?;
+        }
+        os.write_repeated_packed_uint32(6, &self.weights)?;
         os.write_unknown_fields(self.special_fields.unknown_fields())?;
         ::std::result::Result::Ok(())
     }
@@ -2667,6 +3648,10 @@ impl ::protobuf::Message for StaticOutboundSettings {
     fn clear(&mut self) {
         self.actors.clear();
         self.method.clear();
+        self.smart_cache.clear();
+        self.fallback_timeout = 0;
+        self.check_interval = 0;
+        self.weights.clear();
         self.special_fields.clear();
     }
 
@@ -2674,6 +3659,10 @@ impl ::protobuf::Message for StaticOutboundSettings {
         static instance: StaticOutboundSettings = StaticOutboundSettings {
             actors: ::std::vec::Vec::new(),
             method: ::std::string::String::new(),
+            smart_cache: ::std::string::String::new(),
+            fallback_timeout: 0,
+            check_interval: 0,
+            weights: ::std::vec::Vec::new(),
             special_fields: ::protobuf::SpecialFields::new(),
         };
         &instance
@@ -3619,6 +4608,16 @@ pub struct Outbound {
     pub protocol: ::std::string::String,
     // @@protoc_insertion_point(field:Outbound.settings)
     pub settings: ::std::vec::Vec<u8>,
+    // @@protoc_insertion_point(field:Outbound.proxy_protocol)
+    pub proxy_protocol: ::std::string::String,
+    // @@protoc_insertion_point(field:Outbound.dscp)
+    pub dscp: u32,
+    // @@protoc_insertion_point(field:Outbound.connect_timeout)
+    pub connect_timeout: u32,
+    // @@protoc_insertion_point(field:Outbound.connect_retries)
+    pub connect_retries: u32,
+    // @@protoc_insertion_point(field:Outbound.fwmark)
+    pub fwmark: u32,
     // special fields
     // @@protoc_insertion_point(special_field:Outbound.special_fields)
     pub special_fields: ::protobuf::SpecialFields,
@@ -3655,6 +4654,21 @@ impl ::protobuf::Message for Outbound {
                 34 => {
                     self.settings = is.read_bytes()?;
                 },
+                42 => {
+                    self.proxy_protocol = is.read_string()?;
+                },
+                48 => {
+                    self.dscp = is.read_uint32()?;
+                },
+                56 => {
+                    self.connect_timeout = is.read_uint32()?;
+                },
+                64 => {
+                    self.connect_retries = is.read_uint32()?;
+                },
+                72 => {
+                    self.fwmark = is.read_uint32()?;
+                },
                 tag => {
                     ::protobuf::rt::read_unknown_or_skip_group(tag, is, self.special_fields.mut_unknown_fields())?;
                 },
@@ -3676,6 +4690,21 @@ impl ::protobuf::Message for Outbound {
         if !self.settings.is_empty() {
             my_size += ::protobuf::rt::bytes_size(4, &self.settings);
         }
+        if !self.proxy_protocol.is_empty() {
+            my_size += ::protobuf::rt::string_size(5, &self.proxy_protocol);
+        }
+        if self.dscp != 0 {
+            my_size += ::protobuf::rt::uint32_size(6, self.dscp);
+        }
+        if self.connect_timeout != 0 {
+            my_size += ::protobuf::rt::uint32_size(7, self.connect_timeout);
+        }
+        if self.connect_retries != 0 {
+            my_size += ::protobuf::rt::uint32_size(8, self.connect_retries);
+        }
+        if self.fwmark != 0 {
+            my_size += ::protobuf::rt::uint32_size(9, self.fwmark);
+        }
         my_size += ::protobuf::rt::unknown_fields_size(self.special_fields.unknown_fields());
         self.special_fields.cached_size().set(my_size as u32);
         my_size
@@ -3691,6 +4720,21 @@ impl ::protobuf::Message for Outbound {
         if !self.settings.is_empty() {
             os.write_bytes(4, &self.settings)?;
         }
+        if !self.proxy_protocol.is_empty() {
+            os.write_string(5, &self.proxy_protocol)?;
+        }
+        if self.dscp != 0 {
+            os.write_uint32(6, self.dscp)?;
+        }
+        if self.connect_timeout != 0 {
+            os.write_uint32(7, self.connect_timeout)?;
+        }
+        if self.connect_retries != 0 {
+            os.write_uint32(8, self.connect_retries)?;
+        }
+        if self.fwmark != 0 {
+            os.write_uint32(9, self.fwmark)?;
+        }
         os.write_unknown_fields(self.special_fields.unknown_fields())?;
         ::std::result::Result::Ok(())
     }
@@ -3711,6 +4755,11 @@ impl ::protobuf::Message for Outbound {
         self.tag.clear();
         self.protocol.clear();
         self.settings.clear();
+        self.proxy_protocol.clear();
+        self.dscp = 0;
+        self.connect_timeout = 0;
+        self.connect_retries = 0;
+        self.fwmark = 0;
         self.special_fields.clear();
     }
 
@@ -3719,6 +4768,11 @@ impl ::protobuf::Message for Outbound {
             tag: ::std::string::String::new(),
             protocol: ::std::string::String::new(),
             settings: ::std::vec::Vec::new(),
+            proxy_protocol: ::std::string::String::new(),
+            dscp: 0,
+            connect_timeout: 0,
+            connect_retries: 0,
+            fwmark: 0,
             special_fields: ::protobuf::SpecialFields::new(),
         };
         &instance
@@ -3733,6 +4787,12 @@ pub struct Router {
     pub rules: ::std::vec::Vec<router::Rule>,
     // @@protoc_insertion_point(field:Router.domain_resolve)
     pub domain_resolve: bool,
+    // @@protoc_insertion_point(field:Router.lan_target)
+    pub lan_target: ::std::string::String,
+    // @@protoc_insertion_point(field:Router.unmatched)
+    pub unmatched: ::protobuf::EnumOrUnknown<router::Unmatched>,
+    // @@protoc_insertion_point(field:Router.fail_closed)
+    pub fail_closed: bool,
     // special fields
     // @@protoc_insertion_point(special_field:Router.special_fields)
     pub special_fields: ::protobuf::SpecialFields,
@@ -3766,6 +4826,15 @@ impl ::protobuf::Message for Router {
                 16 => {
                     self.domain_resolve = is.read_bool()?;
                 },
+                26 => {
+                    self.lan_target = is.read_string()?;
+                },
+                32 => {
+                    self.unmatched = is.read_enum_or_unknown()?;
+                },
+                40 => {
+                    self.fail_closed = is.read_bool()?;
+                },
                 tag => {
                     ::protobuf::rt::read_unknown_or_skip_group(tag, is, self.special_fields.mut_unknown_fields())?;
                 },
@@ -3785,6 +4854,15 @@ impl ::protobuf::Message for Router {
         if self.domain_resolve != false {
             my_size += 1 + 1;
         }
+        if !self.lan_target.is_empty() {
+            my_size += ::protobuf::rt::string_size(3, &self.lan_target);
+        }
+        if self.unmatched != ::protobuf::EnumOrUnknown::new(router::Unmatched::DEFAULT) {
+            my_size += ::protobuf::rt::int32_size(4, self.unmatched.value());
+        }
+        if self.fail_closed != false {
+            my_size += 1 + 1;
+        }
         my_size += ::protobuf::rt::unknown_fields_size(self.special_fields.unknown_fields());
         self.special_fields.cached_size().set(my_size as u32);
         my_size
@@ -3797,6 +4875,15 @@ impl ::protobuf::Message for Router {
         if self.domain_resolve != false {
             os.write_bool(2, self.domain_resolve)?;
         }
+        if !self.lan_target.is_empty() {
+            os.write_string(3, &self.lan_target)?;
+        }
+        if self.unmatched != ::protobuf::EnumOrUnknown::new(router::Unmatched::DEFAULT) {
+            os.write_enum(4, ::protobuf::EnumOrUnknown::value(&self.unmatched))?;
+        }
+        if self.fail_closed != false {
+            os.write_bool(5, self.fail_closed)?;
+        }
         os.write_unknown_fields(self.special_fields.unknown_fields())?;
         ::std::result::Result::Ok(())
     }
@@ -3816,6 +4903,9 @@ impl ::protobuf::Message for Router {
     fn clear(&mut self) {
         self.rules.clear();
         self.domain_resolve = false;
+        self.lan_target.clear();
+        self.unmatched = ::protobuf::EnumOrUnknown::new(router::Unmatched::DEFAULT);
+        self.fail_closed = false;
         self.special_fields.clear();
     }
 
@@ -3823,6 +4913,9 @@ impl ::protobuf::Message for Router {
         static instance: Router = Router {
             rules: ::std::vec::Vec::new(),
             domain_resolve: false,
+            lan_target: ::std::string::String::new(),
+            unmatched: ::protobuf::EnumOrUnknown::from_i32(0),
+            fail_closed: false,
             special_fields: ::protobuf::SpecialFields::new(),
         };
         &instance
@@ -3849,6 +4942,22 @@ pub mod router {
         pub networks: ::std::vec::Vec<::std::string::String>,
         // @@protoc_insertion_point(field:Router.Rule.inbound_tags)
         pub inbound_tags: ::std::vec::Vec<::std::string::String>,
+        // @@protoc_insertion_point(field:Router.Rule.redirect)
+        pub redirect: ::std::string::String,
+        // @@protoc_insertion_point(field:Router.Rule.snis)
+        pub snis: ::std::vec::Vec<::std::string::String>,
+        // @@protoc_insertion_point(field:Router.Rule.alpns)
+        pub alpns: ::std::vec::Vec<::std::string::String>,
+        // @@protoc_insertion_point(field:Router.Rule.log)
+        pub log: bool,
+        // @@protoc_insertion_point(field:Router.Rule.resolve)
+        pub resolve: ::std::string::String,
+        // @@protoc_insertion_point(field:Router.Rule.geosites)
+        pub geosites: ::std::vec::Vec<rule::Geosite>,
+        // @@protoc_insertion_point(field:Router.Rule.schedule)
+        pub schedule: ::std::string::String,
+        // @@protoc_insertion_point(field:Router.Rule.asns)
+        pub asns: ::std::vec::Vec<rule::Asn>,
         // special fields
         // @@protoc_insertion_point(special_field:Router.Rule.special_fields)
         pub special_fields: ::protobuf::SpecialFields,
@@ -3897,6 +5006,30 @@ pub mod router {
                     58 => {
                         self.inbound_tags.push(is.read_string()?);
                     },
+                    66 => {
+                        self.redirect = is.read_string()?;
+                    },
+                    74 => {
+                        self.snis.push(is.read_string()?);
+                    },
+                    82 => {
+                        self.alpns.push(is.read_string()?);
+                    },
+                    88 => {
+                        self.log = is.read_bool()?;
+                    },
+                    98 => {
+                        self.resolve = is.read_string()?;
+                    },
+                    106 => {
+                        self.geosites.push(is.read_message()?);
+                    },
+                    114 => {
+                        self.schedule = is.read_string()?;
+                    },
+                    122 => {
+                        self.asns.push(is.read_message()?);
+                    },
                     tag => {
                         ::protobuf::rt::read_unknown_or_skip_group(tag, is, self.special_fields.mut_unknown_fields())?;
                     },
@@ -3932,6 +5065,32 @@ pub mod router {
             for value in &self.inbound_tags {
                 my_size += ::protobuf::rt::string_size(7, &value);
             };
+            if !self.redirect.is_empty() {
+                my_size += ::protobuf::rt::string_size(8, &self.redirect);
+            }
+            for value in &self.snis {
+                my_size += ::protobuf::rt::string_size(9, &value);
+            };
+            for value in &self.alpns {
+                my_size += ::protobuf::rt::string_size(10, &value);
+            };
+            if self.log != false {
+                my_size += 1 + 1;
+            }
+            if !self.resolve.is_empty() {
+                my_size += ::protobuf::rt::string_size(12, &self.resolve);
+            }
+            for value in &self.geosites {
+                let len = value.compute_size();
+                my_size += 1 + ::protobuf::rt::compute_raw_varint64_size(len) + len;
+            };
+            if !self.schedule.is_empty() {
+                my_size += ::protobuf::rt::string_size(14, &self.schedule);
+            }
+            for value in &self.asns {
+                let len = value.compute_size();
+                my_size += 1 + ::protobuf::rt::compute_raw_varint64_size(len) + len;
+            };
             my_size += ::protobuf::rt::unknown_fields_size(self.special_fields.unknown_fields());
             self.special_fields.cached_size().set(my_size as u32);
             my_size
@@ -3959,6 +5118,30 @@ pub mod router {
             for v in &self.inbound_tags {
                 os.write_string(7, &v)?;
             };
+            if !self.redirect.is_empty() {
+                os.write_string(8, &self.redirect)?;
+            }
+            for v in &self.snis {
+                os.write_string(9, &v)?;
+            };
+            for v in &self.alpns {
+                os.write_string(10, &v)?;
+            };
+            if self.log != false {
+                os.write_bool(11, self.log)?;
+            }
+            if !self.resolve.is_empty() {
+                os.write_string(12, &self.resolve)?;
+            }
+            for v in &self.geosites {
+                ::protobuf::rt::write_message_field_with_cached_size(13, v, os)?;
+            };
+            if !self.schedule.is_empty() {
+                os.write_string(14, &self.schedule)?;
+            }
+            for v in &self.asns {
+                ::protobuf::rt::write_message_field_with_cached_size(15, v, os)?;
+            };
             os.write_unknown_fields(self.special_fields.unknown_fields())?;
             ::std::result::Result::Ok(())
         }
@@ -3983,6 +5166,14 @@ pub mod router {
             self.port_ranges.clear();
             self.networks.clear();
             self.inbound_tags.clear();
+            self.redirect.clear();
+            self.snis.clear();
+            self.alpns.clear();
+            self.log = false;
+            self.resolve.clear();
+            self.geosites.clear();
+            self.schedule.clear();
+            self.asns.clear();
             self.special_fields.clear();
         }
 
@@ -3995,6 +5186,14 @@ pub mod router {
                 port_ranges: ::std::vec::Vec::new(),
                 networks: ::std::vec::Vec::new(),
                 inbound_tags: ::std::vec::Vec::new(),
+                redirect: ::std::string::String::new(),
+                snis: ::std::vec::Vec::new(),
+                alpns: ::std::vec::Vec::new(),
+                log: false,
+                resolve: ::std::string::String::new(),
+                geosites: ::std::vec::Vec::new(),
+                schedule: ::std::string::String::new(),
+                asns: ::std::vec::Vec::new(),
                 special_fields: ::protobuf::SpecialFields::new(),
             };
             &instance
@@ -4252,7 +5451,258 @@ pub mod router {
                 &instance
             }
         }
+
+        #[derive(PartialEq,Clone,Default,Debug)]
+        // @@protoc_insertion_point(message:Router.Rule.Geosite)
+        pub struct Geosite {
+            // message fields
+            // @@protoc_insertion_point(field:Router.Rule.Geosite.file)
+            pub file: ::std::string::String,
+            // @@protoc_insertion_point(field:Router.Rule.Geosite.category)
+            pub category: ::std::string::String,
+            // special fields
+            // @@protoc_insertion_point(special_field:Router.Rule.Geosite.special_fields)
+            pub special_fields: ::protobuf::SpecialFields,
+        }
+
+        impl<'a> ::std::default::Default for &'a Geosite {
+            fn default() -> &'a Geosite {
+                <Geosite as ::protobuf::Message>::default_instance()
+            }
+        }
+
+        impl Geosite {
+            pub fn new() -> Geosite {
+                ::std::default::Default::default()
+            }
+        }
+
+        impl ::protobuf::Message for Geosite {
+            const NAME: &'static str = "Geosite";
+
+            fn is_initialized(&self) -> bool {
+                true
+            }
+
+            fn merge_from(&mut self, is: &mut ::protobuf::CodedInputStream<'_>) -> ::protobuf::Result<()> {
+                while let Some(tag) = is.read_raw_tag_or_eof()? {
+                    match tag {
+                        10 => {
+                            self.file = is.read_string()?;
+                        },
+                        18 => {
+                            self.category = is.read_string()?;
+                        },
+                        tag => {
+                            ::protobuf::rt::read_unknown_or_skip_group(tag, is, self.special_fields.mut_unknown_fields())?;
+                        },
+                    };
+                }
+                ::std::result::Result::Ok(())
+            }
+
+            // Compute sizes of nested messages
+            #[allow(unused_variables)]
+            fn compute_size(&self) -> u64 {
+                let mut my_size = 0;
+                if !self.file.is_empty() {
+                    my_size += ::protobuf::rt::string_size(1, &self.file);
+                }
+                if !self.category.is_empty() {
+                    my_size += ::protobuf::rt::string_size(2, &self.category);
+                }
+                my_size += ::protobuf::rt::unknown_fields_size(self.special_fields.unknown_fields());
+                self.special_fields.cached_size().set(my_size as u32);
+                my_size
+            }
+
+            fn write_to_with_cached_sizes(&self, os: &mut ::protobuf::CodedOutputStream<'_>) -> ::protobuf::Result<()> {
+                if !self.file.is_empty() {
+                    os.write_string(1, &self.file)?;
+                }
+                if !self.category.is_empty() {
+                    os.write_string(2, &self.category)?;
+                }
+                os.write_unknown_fields(self.special_fields.unknown_fields())?;
+                ::std::result::Result::Ok(())
+            }
+
+            fn special_fields(&self) -> &::protobuf::SpecialFields {
+                &self.special_fields
+            }
+
+            fn mut_special_fields(&mut self) -> &mut ::protobuf::SpecialFields {
+                &mut self.special_fields
+            }
+
+            fn new() -> Geosite {
+                Geosite::new()
+            }
+
+            fn clear(&mut self) {
+                self.file.clear();
+                self.category.clear();
+                self.special_fields.clear();
+            }
+
+            fn default_instance() -> &'static Geosite {
+                static instance: Geosite = Geosite {
+                    file: ::std::string::String::new(),
+                    category: ::std::string::String::new(),
+                    special_fields: ::protobuf::SpecialFields::new(),
+                };
+                &instance
+            }
+        }
+
+        #[derive(PartialEq,Clone,Default,Debug)]
+        // @@protoc_insertion_point(message:Router.Rule.Asn)
+        pub struct Asn {
+            // message fields
+            // @@protoc_insertion_point(field:Router.Rule.Asn.file)
+            pub file: ::std::string::String,
+            // @@protoc_insertion_point(field:Router.Rule.Asn.number)
+            pub number: u32,
+            // special fields
+            // @@protoc_insertion_point(special_field:Router.Rule.Asn.special_fields)
+            pub special_fields: ::protobuf::SpecialFields,
+        }
+
+        impl<'a> ::std::default::Default for &'a Asn {
+            fn default() -> &'a Asn {
+                <Asn as ::protobuf::Message>::default_instance()
+            }
+        }
+
+        impl Asn {
+            pub fn new() -> Asn {
+                ::std::default::Default::default()
+            }
+        }
+
+        impl ::protobuf::Message for Asn {
+            const NAME: &'static str = "Asn";
+
+            fn is_initialized(&self) -> bool {
+                true
+            }
+
+            fn merge_from(&mut self, is: &mut ::protobuf::CodedInputStream<'_>) -> ::protobuf::Result<()> {
+                while let Some(tag) = is.read_raw_tag_or_eof()? {
+                    match tag {
+                        10 => {
+                            self.file = is.read_string()?;
+                        },
+                        16 => {
+                            self.number = is.read_uint32()?;
+                        },
+                        tag => {
+                            ::protobuf::rt::read_unknown_or_skip_group(tag, is, self.special_fields.mut_unknown_fields())?;
+                        },
+                    };
+                }
+                ::std::result::Result::Ok(())
+            }
+
+            // Compute sizes of nested messages
+            #[allow(unused_variables)]
+            fn compute_size(&self) -> u64 {
+                let mut my_size = 0;
+                if !self.file.is_empty() {
+                    my_size += ::protobuf::rt::string_size(1, &self.file);
+                }
+                if self.number != 0 {
+                    my_size += ::protobuf::rt::uint32_size(2, self.number);
+                }
+                my_size += ::protobuf::rt::unknown_fields_size(self.special_fields.unknown_fields());
+                self.special_fields.cached_size().set(my_size as u32);
+                my_size
+            }
+
+            fn write_to_with_cached_sizes(&self, os: &mut ::protobuf::CodedOutputStream<'_>) -> ::protobuf::Result<()> {
+                if !self.file.is_empty() {
+                    os.write_string(1, &self.file)?;
+                }
+                if self.number != 0 {
+                    os.write_uint32(2, self.number)?;
+                }
+                os.write_unknown_fields(self.special_fields.unknown_fields())?;
+                ::std::result::Result::Ok(())
+            }
+
+            fn special_fields(&self) -> &::protobuf::SpecialFields {
+                &self.special_fields
+            }
+
+            fn mut_special_fields(&mut self) -> &mut ::protobuf::SpecialFields {
+                &mut self.special_fields
+            }
+
+            fn new() -> Asn {
+                Asn::new()
+            }
+
+            fn clear(&mut self) {
+                self.file.clear();
+                self.number = 0;
+                self.special_fields.clear();
+            }
+
+            fn default_instance() -> &'static Asn {
+                static instance: Asn = Asn {
+                    file: ::std::string::String::new(),
+                    number: 0,
+                    special_fields: ::protobuf::SpecialFields::new(),
+                };
+                &instance
+            }
+        }
+    }
+
+    #[derive(Clone,Copy,PartialEq,Eq,Debug,Hash)]
+    // @@protoc_insertion_point(enum:Router.Unmatched)
+    pub enum Unmatched {
+        // @@protoc_insertion_point(enum_value:Router.Unmatched.DEFAULT)
+        DEFAULT = 0,
+        // @@protoc_insertion_point(enum_value:Router.Unmatched.REJECT)
+        REJECT = 1,
+        // @@protoc_insertion_point(enum_value:Router.Unmatched.DIRECT)
+        DIRECT = 2,
+        // @@protoc_insertion_point(enum_value:Router.Unmatched.ERROR)
+        ERROR = 3,
+    }
+
+    impl ::protobuf::Enum for Unmatched {
+        const NAME: &'static str = "Unmatched";
+
+        fn value(&self) -> i32 {
+            *self as i32
+        }
+
+        fn from_i32(value: i32) -> ::std::option::Option<Unmatched> {
+            match value {
+                0 => ::std::option::Option::Some(Unmatched::DEFAULT),
+                1 => ::std::option::Option::Some(Unmatched::REJECT),
+                2 => ::std::option::Option::Some(Unmatched::DIRECT),
+                3 => ::std::option::Option::Some(Unmatched::ERROR),
+                _ => ::std::option::Option::None
+            }
+        }
+
+        const VALUES: &'static [Unmatched] = &[
+            Unmatched::DEFAULT,
+            Unmatched::REJECT,
+            Unmatched::DIRECT,
+            Unmatched::ERROR,
+        ];
+    }
+
+    impl ::std::default::Default for Unmatched {
+        fn default() -> Self {
+            Unmatched::DEFAULT
+        }
     }
+
 }
 
 #[derive(PartialEq,Clone,Default,Debug)]
@@ -4269,6 +5719,8 @@ pub struct Config {
     pub router: ::protobuf::MessageField<Router>,
     // @@protoc_insertion_point(field:Config.dns)
     pub dns: ::protobuf::MessageField<Dns>,
+    // @@protoc_insertion_point(field:Config.warnings)
+    pub warnings: ::std::vec::Vec<::std::string::String>,
     // special fields
     // @@protoc_insertion_point(special_field:Config.special_fields)
     pub special_fields: ::protobuf::SpecialFields,
@@ -4311,6 +5763,9 @@ impl ::protobuf::Message for Config {
                 42 => {
                     ::protobuf::rt::read_singular_message_into_field(is, &mut self.dns)?;
                 },
+                50 => {
+                    self.warnings.push(is.read_string()?);
+                },
                 tag => {
                     ::protobuf::rt::read_unknown_or_skip_group(tag, is, self.special_fields.mut_unknown_fields())?;
                 },
@@ -4343,6 +5798,9 @@ impl ::protobuf::Message for Config {
             let len = v.compute_size();
             my_size += 1 + ::protobuf::rt::compute_raw_varint64_size(len) + len;
         }
+        for value in &self.warnings {
+            my_size += ::protobuf::rt::string_size(6, &value);
+        };
         my_size += ::protobuf::rt::unknown_fields_size(self.special_fields.unknown_fields());
         self.special_fields.cached_size().set(my_size as u32);
         my_size
@@ -4364,6 +5822,9 @@ impl ::protobuf::Message for Config {
         if let Some(v) = self.dns.as_ref() {
             ::protobuf::rt::write_message_field_with_cached_size(5, v, os)?;
         }
+        for v in &self.warnings {
+            os.write_string(6, &v)?;
+        };
         os.write_unknown_fields(self.special_fields.unknown_fields())?;
         ::std::result::Result::Ok(())
     }
@@ -4386,6 +5847,7 @@ impl ::protobuf::Message for Config {
         self.outbounds.clear();
         self.router.clear();
         self.dns.clear();
+        self.warnings.clear();
         self.special_fields.clear();
     }
 
@@ -4396,6 +5858,7 @@ impl ::protobuf::Message for Config {
             outbounds: ::std::vec::Vec::new(),
             router: ::protobuf::MessageField::none(),
             dns: ::protobuf::MessageField::none(),
+            warnings: ::std::vec::Vec::new(),
             special_fields: ::protobuf::SpecialFields::new(),
         };
         &instance
@@ -13,12 +13,37 @@ use crate::config::{external_rule, internal};
 pub struct Dns {
     pub servers: Option<Vec<String>>,
     pub hosts: Option<HashMap<String, Vec<String>>>,
+    #[serde(rename = "systemResolver")]
+    pub system_resolver: Option<bool>,
+    pub outbound: Option<String>,
+    pub weights: Option<HashMap<String, u32>>,
+    #[serde(rename = "ttlRules")]
+    pub ttl_rules: Option<Vec<DnsTtlRule>>,
+    #[serde(rename = "dohServer")]
+    pub doh_server: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DnsTtlRule {
+    pub domain: String,
+    pub min: Option<u32>,
+    pub max: Option<u32>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Log {
     pub level: Option<String>,
     pub output: Option<String>,
+    pub rotation: Option<String>,
+    pub syslog: Option<bool>,
+    #[serde(rename = "syslogTag")]
+    pub syslog_tag: Option<String>,
+    #[serde(rename = "syslogFacility")]
+    pub syslog_facility: Option<String>,
+    #[serde(rename = "ringSize")]
+    pub ring_size: Option<u32>,
+    #[serde(rename = "ringLevel")]
+    pub ring_level: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -28,6 +53,16 @@ pub struct CatInboundSettings {
     pub port: u16,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SocksInboundSettings {
+    #[serde(rename = "udpAddress")]
+    pub udp_address: Option<String>,
+    #[serde(rename = "udpPort")]
+    pub udp_port: Option<u16>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ShadowsocksInboundSettings {
     pub method: Option<String>,
@@ -74,6 +109,7 @@ pub struct TunInboundSettings {
     pub auto: Option<bool>,
     pub fd: Option<i32>,
     pub name: Option<String>,
+    pub existing: Option<bool>,
     pub address: Option<String>,
     pub gateway: Option<String>,
     pub netmask: Option<String>,
@@ -82,6 +118,7 @@ pub struct TunInboundSettings {
     pub fake_dns_exclude: Option<Vec<String>>,
     #[serde(rename = "fakeDnsInclude")]
     pub fake_dns_include: Option<Vec<String>>,
+    pub dns: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -90,6 +127,11 @@ pub struct Inbound {
     pub tag: Option<String>,
     pub address: Option<String>,
     pub port: Option<u16>,
+    pub interface: Option<String>,
+    #[serde(rename = "acceptProxyProtocol")]
+    pub accept_proxy_protocol: Option<bool>,
+    #[serde(rename = "reusePort")]
+    pub reuse_port: Option<bool>,
     pub settings: Option<Box<RawValue>>,
 }
 
@@ -128,6 +170,47 @@ pub struct TrojanOutboundSettings {
     pub port: Option<u16>,
     pub password: Option<String>,
     pub server_name: Option<String>,
+    #[serde(rename = "noSni")]
+    pub no_sni: Option<bool>,
+    #[serde(rename = "certPin")]
+    pub cert_pin: Option<String>,
+    pub servers: Option<Vec<String>>,
+    #[serde(rename = "caCert")]
+    pub ca_cert: Option<String>,
+    #[serde(rename = "allowInsecure")]
+    pub allow_insecure: Option<bool>,
+    pub compress: Option<bool>,
+    pub sni: Option<String>,
+    pub alpn: Option<Vec<String>>,
+    pub suites: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Http2OutboundSettings {
+    pub address: Option<String>,
+    pub port: Option<u16>,
+    #[serde(rename = "serverName")]
+    pub server_name: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub protocol: Option<String>,
+    pub path: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SshOutboundSettings {
+    pub address: Option<String>,
+    pub port: Option<u16>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    #[serde(rename = "privateKey")]
+    pub private_key: Option<String>,
+    #[serde(rename = "privateKeyPassphrase")]
+    pub private_key_passphrase: Option<String>,
+    #[serde(rename = "hostKey")]
+    pub host_key: Option<String>,
+    #[serde(rename = "allowInsecure")]
+    pub allow_insecure: Option<bool>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -141,6 +224,13 @@ pub struct TryAllOutboundSettings {
 pub struct StaticOutboundSettings {
     pub actors: Option<Vec<String>>,
     pub method: Option<String>,
+    #[serde(rename = "smartCache")]
+    pub smart_cache: Option<String>,
+    #[serde(rename = "fallbackTimeout")]
+    pub fallback_timeout: Option<u32>,
+    #[serde(rename = "checkInterval")]
+    pub check_interval: Option<u32>,
+    pub weights: Option<Vec<u32>>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -223,6 +313,14 @@ pub struct Outbound {
     pub protocol: String,
     pub tag: Option<String>,
     pub settings: Option<Box<RawValue>>,
+    #[serde(rename = "proxyProtocol")]
+    pub proxy_protocol: Option<String>,
+    pub dscp: Option<u8>,
+    pub fwmark: Option<u32>,
+    #[serde(rename = "connectTimeout")]
+    pub connect_timeout: Option<u32>,
+    #[serde(rename = "connectRetries")]
+    pub connect_retries: Option<u32>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -234,13 +332,21 @@ pub struct Rule {
     #[serde(rename = "domainSuffix")]
     pub domain_suffix: Option<Vec<String>>,
     pub geoip: Option<Vec<String>>,
+    pub asn: Option<Vec<String>>,
+    pub geosite: Option<Vec<String>>,
     pub external: Option<Vec<String>>,
     #[serde(rename = "portRange")]
     pub port_range: Option<Vec<String>>,
     pub network: Option<Vec<String>>,
     #[serde(rename = "inboundTag")]
     pub inbound_tag: Option<Vec<String>>,
+    pub sni: Option<Vec<String>>,
+    pub alpn: Option<Vec<String>>,
     pub target: String,
+    pub redirect: Option<String>,
+    pub log: Option<bool>,
+    pub resolve: Option<String>,
+    pub schedule: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -248,6 +354,11 @@ pub struct Router {
     pub rules: Option<Vec<Rule>>,
     #[serde(rename = "domainResolve")]
     pub domain_resolve: Option<bool>,
+    #[serde(rename = "bypassLan")]
+    pub bypass_lan: Option<bool>,
+    pub unmatched: Option<String>,
+    #[serde(rename = "failClosed")]
+    pub fail_closed: Option<bool>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -259,18 +370,41 @@ pub struct Config {
     pub dns: Option<Dns>,
 }
 
+// Deserializes like `serde_json::from_str`, the paths of the keys `T` doesn't
+// have are added to `unknown` after `prefix`.
+fn from_str_checked<'a, T>(
+    s: &'a str,
+    prefix: &str,
+    unknown: &mut Vec<String>,
+) -> serde_json::Result<T>
+where
+    T: serde::Deserialize<'a>,
+{
+    let mut de = serde_json::Deserializer::from_str(s);
+    let value = serde_ignored::deserialize(&mut de, |path| {
+        // Leaves out the `?` serde_ignored puts for options.
+        unknown.push(format!("{}{}", prefix, path).replace("?.", ""));
+    })?;
+    de.end()?;
+    Ok(value)
+}
+
+// Returns the tag of the first direct outbound, empty if there's none.
+fn direct_tag(outbounds: &[internal::Outbound]) -> String {
+    outbounds
+        .iter()
+        .find(|o| o.protocol == "direct")
+        .map(|o| o.tag.clone())
+        .unwrap_or_default()
+}
+
 pub fn to_internal(json: &mut Config) -> Result<internal::Config> {
+    let mut unknown = Vec::new();
+
     let mut log = internal::Log::new();
     if let Some(ext_log) = &json.log {
         if let Some(ext_level) = &ext_log.level {
-            match ext_level.as_str() {
-                "trace" => log.level = protobuf::EnumOrUnknown::new(internal::log::Level::TRACE),
-                "debug" => log.level = protobuf::EnumOrUnknown::new(internal::log::Level::DEBUG),
-                "info" => log.level = protobuf::EnumOrUnknown::new(internal::log::Level::INFO),
-                "warn" => log.level = protobuf::EnumOrUnknown::new(internal::log::Level::WARN),
-                "error" => log.level = protobuf::EnumOrUnknown::new(internal::log::Level::ERROR),
-                _ => log.level = protobuf::EnumOrUnknown::new(internal::log::Level::WARN),
-            }
+            log.level = protobuf::EnumOrUnknown::new(crate::config::parse_log_level(ext_level));
         }
 
         if let Some(ext_output) = &ext_log.output {
@@ -278,17 +412,43 @@ pub fn to_internal(json: &mut Config) -> Result<internal::Config> {
                 "console" => {
                     log.output = protobuf::EnumOrUnknown::new(internal::log::Output::CONSOLE)
                 }
+                "syslog" => {
+                    log.output = protobuf::EnumOrUnknown::new(internal::log::Output::SYSLOG)
+                }
                 _ => {
                     log.output = protobuf::EnumOrUnknown::new(internal::log::Output::FILE);
                     log.output_file = ext_output.clone();
                 }
             }
         }
+
+        if let Some(ext_rotation) = &ext_log.rotation {
+            log.rotation =
+                protobuf::EnumOrUnknown::new(crate::config::parse_log_rotation(ext_rotation)?);
+        }
+
+        if let Some(ext_syslog) = ext_log.syslog {
+            log.syslog = ext_syslog;
+        }
+        if let Some(ext_syslog_tag) = &ext_log.syslog_tag {
+            log.syslog_tag = ext_syslog_tag.clone();
+        }
+        if let Some(ext_syslog_facility) = &ext_log.syslog_facility {
+            log.syslog_facility = ext_syslog_facility.clone();
+        }
+        if let Some(ext_ring_size) = ext_log.ring_size {
+            log.ring_size = ext_ring_size;
+        }
+        if let Some(ext_ring_level) = &ext_log.ring_level {
+            log.ring_level =
+                protobuf::EnumOrUnknown::new(crate::config::parse_log_level(ext_ring_level));
+        }
     }
 
     let mut inbounds = Vec::new();
     if let Some(ext_inbounds) = &json.inbounds {
-        for ext_inbound in ext_inbounds {
+        for (i, ext_inbound) in ext_inbounds.iter().enumerate() {
+            let settings_path = format!("inbounds.{}.settings.", i);
             let mut inbound = internal::Inbound::new();
             inbound.protocol = ext_inbound.protocol.clone();
             if let Some(ext_tag) = &ext_inbound.tag {
@@ -302,6 +462,15 @@ pub fn to_internal(json: &mut Config) -> Result<internal::Config> {
             if let Some(ext_port) = ext_inbound.port {
                 inbound.port = ext_port as u32;
             }
+            if let Some(ext_interface) = &ext_inbound.interface {
+                inbound.interface = ext_interface.clone();
+            }
+            if let Some(ext_accept) = ext_inbound.accept_proxy_protocol {
+                inbound.accept_proxy_protocol = ext_accept;
+            }
+            if let Some(ext_reuse_port) = ext_inbound.reuse_port {
+                inbound.reuse_port = ext_reuse_port;
+            }
             match inbound.protocol.as_str() {
                 #[cfg(any(
                     target_os = "ios",
@@ -314,8 +483,12 @@ pub fn to_internal(json: &mut Config) -> Result<internal::Config> {
                         return Err(anyhow!("invalid tun inbound settings"));
                     }
                     let mut settings = internal::TunInboundSettings::new();
-                    let ext_settings: TunInboundSettings =
-                        serde_json::from_str(ext_inbound.settings.as_ref().unwrap().get()).unwrap();
+                    let ext_settings: TunInboundSettings = from_str_checked(
+                        ext_inbound.settings.as_ref().unwrap().get(),
+                        &settings_path,
+                        &mut unknown,
+                    )
+                    .unwrap();
 
                     let mut fake_dns_exclude = Vec::new();
                     if let Some(ext_excludes) = ext_settings.fake_dns_exclude {
@@ -337,12 +510,19 @@ pub fn to_internal(json: &mut Config) -> Result<internal::Config> {
                         settings.fake_dns_include = fake_dns_include;
                     }
 
+                    if let Some(ext_dns) = ext_settings.dns {
+                        settings.dns = ext_dns;
+                    }
+
                     if let Some(ext_fd) = ext_settings.fd {
                         settings.fd = ext_fd;
                     } else {
                         settings.fd = -1; // disable fd option
                         if let Some(ext_name) = ext_settings.name {
-                            settings.name = ext_name;
+                            settings.name = crate::config::parse_tun_name(&ext_name)?;
+                        }
+                        if let Some(ext_existing) = ext_settings.existing {
+                            settings.existing = ext_existing;
                         }
                         if let Some(ext_address) = ext_settings.address {
                             settings.address = ext_address;
@@ -368,8 +548,12 @@ pub fn to_internal(json: &mut Config) -> Result<internal::Config> {
                 }
                 "cat" => {
                     let mut settings = internal::CatInboundSettings::new();
-                    let ext_settings: CatInboundSettings =
-                        serde_json::from_str(ext_inbound.settings.as_ref().unwrap().get()).unwrap();
+                    let ext_settings: CatInboundSettings = from_str_checked(
+                        ext_inbound.settings.as_ref().unwrap().get(),
+                        &settings_path,
+                        &mut unknown,
+                    )
+                    .unwrap();
                     settings.network = ext_settings.network.unwrap_or("tcp".to_string());
                     settings.address = ext_settings.address;
                     settings.port = ext_settings.port as u32;
@@ -381,12 +565,27 @@ pub fn to_internal(json: &mut Config) -> Result<internal::Config> {
                     inbounds.push(inbound);
                 }
                 "socks" => {
+                    if let Some(ext_settings) = ext_inbound.settings.as_ref() {
+                        let ext_settings: SocksInboundSettings =
+                            from_str_checked(ext_settings.get(), &settings_path, &mut unknown)
+                                .map_err(|e| anyhow!("invalid socks inbound settings: {}", e))?;
+                        let mut settings = internal::SocksInboundSettings::new();
+                        settings.udp_address = ext_settings.udp_address.unwrap_or_default();
+                        settings.udp_port = ext_settings.udp_port.unwrap_or_default() as u32;
+                        settings.username = ext_settings.username.unwrap_or_default();
+                        settings.password = ext_settings.password.unwrap_or_default();
+                        inbound.settings = settings.write_to_bytes().unwrap();
+                    }
                     inbounds.push(inbound);
                 }
                 "shadowsocks" => {
                     let mut settings = internal::ShadowsocksInboundSettings::new();
-                    let ext_settings: ShadowsocksInboundSettings =
-                        serde_json::from_str(ext_inbound.settings.as_ref().unwrap().get()).unwrap();
+                    let ext_settings: ShadowsocksInboundSettings = from_str_checked(
+                        ext_inbound.settings.as_ref().unwrap().get(),
+                        &settings_path,
+                        &mut unknown,
+                    )
+                    .unwrap();
                     if let Some(ext_method) = ext_settings.method {
                         settings.method = ext_method;
                     } else {
@@ -401,8 +600,12 @@ pub fn to_internal(json: &mut Config) -> Result<internal::Config> {
                 }
                 "trojan" => {
                     let mut settings = internal::TrojanInboundSettings::new();
-                    let ext_settings: TrojanInboundSettings =
-                        serde_json::from_str(ext_inbound.settings.as_ref().unwrap().get()).unwrap();
+                    let ext_settings: TrojanInboundSettings = from_str_checked(
+                        ext_inbound.settings.as_ref().unwrap().get(),
+                        &settings_path,
+                        &mut unknown,
+                    )
+                    .unwrap();
                     if let Some(ext_passwords) = ext_settings.passwords {
                         for ext_pass in ext_passwords {
                             settings.passwords.push(ext_pass);
@@ -414,8 +617,12 @@ pub fn to_internal(json: &mut Config) -> Result<internal::Config> {
                 }
                 "ws" => {
                     let mut settings = internal::WebSocketInboundSettings::new();
-                    let ext_settings: WebSocketInboundSettings =
-                        serde_json::from_str(ext_inbound.settings.as_ref().unwrap().get()).unwrap();
+                    let ext_settings: WebSocketInboundSettings = from_str_checked(
+                        ext_inbound.settings.as_ref().unwrap().get(),
+                        &settings_path,
+                        &mut unknown,
+                    )
+                    .unwrap();
                     match ext_settings.path {
                         Some(ext_path) if !ext_path.is_empty() => {
                             settings.path = ext_path;
@@ -431,9 +638,11 @@ pub fn to_internal(json: &mut Config) -> Result<internal::Config> {
                 "amux" => {
                     let mut settings = internal::AMuxInboundSettings::new();
                     if let Some(ext_settings) = &ext_inbound.settings {
-                        if let Ok(ext_settings) =
-                            serde_json::from_str::<AMuxInboundSettings>(ext_settings.get())
-                        {
+                        if let Ok(ext_settings) = from_str_checked::<AMuxInboundSettings>(
+                            ext_settings.get(),
+                            &settings_path,
+                            &mut unknown,
+                        ) {
                             if let Some(ext_actors) = ext_settings.actors {
                                 for ext_actor in ext_actors {
                                     settings.actors.push(ext_actor);
@@ -447,8 +656,12 @@ pub fn to_internal(json: &mut Config) -> Result<internal::Config> {
                 }
                 "quic" => {
                     let mut settings = internal::QuicInboundSettings::new();
-                    let ext_settings: QuicInboundSettings =
-                        serde_json::from_str(ext_inbound.settings.as_ref().unwrap().get()).unwrap();
+                    let ext_settings: QuicInboundSettings = from_str_checked(
+                        ext_inbound.settings.as_ref().unwrap().get(),
+                        &settings_path,
+                        &mut unknown,
+                    )
+                    .unwrap();
                     if let Some(ext_certificate) = ext_settings.certificate {
                         let cert = Path::new(&ext_certificate);
                         if cert.is_absolute() {
@@ -478,8 +691,12 @@ pub fn to_internal(json: &mut Config) -> Result<internal::Config> {
                 }
                 "tls" => {
                     let mut settings = internal::TlsInboundSettings::new();
-                    let ext_settings: TlsInboundSettings =
-                        serde_json::from_str(ext_inbound.settings.as_ref().unwrap().get()).unwrap();
+                    let ext_settings: TlsInboundSettings = from_str_checked(
+                        ext_inbound.settings.as_ref().unwrap().get(),
+                        &settings_path,
+                        &mut unknown,
+                    )
+                    .unwrap();
                     if let Some(ext_certificate) = ext_settings.certificate {
                         let cert = Path::new(&ext_certificate);
                         if cert.is_absolute() {
@@ -509,8 +726,12 @@ pub fn to_internal(json: &mut Config) -> Result<internal::Config> {
                         return Err(anyhow!("invalid chain inbound settings"));
                     }
                     let mut settings = internal::ChainInboundSettings::new();
-                    let ext_settings: ChainInboundSettings =
-                        serde_json::from_str(ext_inbound.settings.as_ref().unwrap().get()).unwrap();
+                    let ext_settings: ChainInboundSettings = from_str_checked(
+                        ext_inbound.settings.as_ref().unwrap().get(),
+                        &settings_path,
+                        &mut unknown,
+                    )
+                    .unwrap();
                     if let Some(ext_actors) = ext_settings.actors {
                         for ext_actor in ext_actors {
                             settings.actors.push(ext_actor);
@@ -529,12 +750,28 @@ pub fn to_internal(json: &mut Config) -> Result<internal::Config> {
 
     let mut outbounds = Vec::new();
     if let Some(ext_outbounds) = &json.outbounds {
-        for ext_outbound in ext_outbounds.iter() {
+        for (i, ext_outbound) in ext_outbounds.iter().enumerate() {
+            let settings_path = format!("outbounds.{}.settings.", i);
             let mut outbound = internal::Outbound::new();
             outbound.protocol = ext_outbound.protocol.clone();
             if let Some(ext_tag) = &ext_outbound.tag {
                 outbound.tag = ext_tag.to_owned();
             }
+            if let Some(ext_proxy_protocol) = &ext_outbound.proxy_protocol {
+                outbound.proxy_protocol = ext_proxy_protocol.to_owned();
+            }
+            if let Some(ext_dscp) = ext_outbound.dscp {
+                outbound.dscp = ext_dscp as u32;
+            }
+            if let Some(ext_fwmark) = ext_outbound.fwmark {
+                outbound.fwmark = ext_fwmark;
+            }
+            if let Some(ext_connect_timeout) = ext_outbound.connect_timeout {
+                outbound.connect_timeout = ext_connect_timeout;
+            }
+            if let Some(ext_connect_retries) = ext_outbound.connect_retries {
+                outbound.connect_retries = ext_connect_retries;
+            }
             match outbound.protocol.as_str() {
                 "direct" | "drop" => {
                     outbounds.push(outbound);
@@ -544,9 +781,14 @@ pub fn to_internal(json: &mut Config) -> Result<internal::Config> {
                         return Err(anyhow!("invalid redirect outbound settings"));
                     }
                     let mut settings = internal::RedirectOutboundSettings::new();
-                    let ext_settings: RedirectOutboundSettings =
-                        serde_json::from_str(ext_outbound.settings.as_ref().unwrap().get())
-                            .unwrap();
+                    let ext_settings: RedirectOutboundSettings = from_str_checked(
+                        ext_outbound.settings.as_ref().unwrap().get(),
+                        &settings_path,
+                        &mut unknown,
+                    )
+                    .map_err(|e| {
+                        anyhow!("invalid {} outbound settings: {}", &outbound.protocol, e)
+                    })?;
                     if let Some(ext_address) = ext_settings.address {
                         settings.address = ext_address;
                     }
@@ -562,9 +804,14 @@ pub fn to_internal(json: &mut Config) -> Result<internal::Config> {
                         return Err(anyhow!("invalid socks outbound settings"));
                     }
                     let mut settings = internal::SocksOutboundSettings::new();
-                    let ext_settings: SocksOutboundSettings =
-                        serde_json::from_str(ext_outbound.settings.as_ref().unwrap().get())
-                            .unwrap();
+                    let ext_settings: SocksOutboundSettings = from_str_checked(
+                        ext_outbound.settings.as_ref().unwrap().get(),
+                        &settings_path,
+                        &mut unknown,
+                    )
+                    .map_err(|e| {
+                        anyhow!("invalid {} outbound settings: {}", &outbound.protocol, e)
+                    })?;
                     if let Some(ext_address) = ext_settings.address {
                         settings.address = ext_address; // TODO checks
                     }
@@ -586,9 +833,14 @@ pub fn to_internal(json: &mut Config) -> Result<internal::Config> {
                         return Err(anyhow!("invalid shadowsocks outbound settings"));
                     }
                     let mut settings = internal::ShadowsocksOutboundSettings::new();
-                    let ext_settings: ShadowsocksOutboundSettings =
-                        serde_json::from_str(ext_outbound.settings.as_ref().unwrap().get())
-                            .unwrap();
+                    let ext_settings: ShadowsocksOutboundSettings = from_str_checked(
+                        ext_outbound.settings.as_ref().unwrap().get(),
+                        &settings_path,
+                        &mut unknown,
+                    )
+                    .map_err(|e| {
+                        anyhow!("invalid {} outbound settings: {}", &outbound.protocol, e)
+                    })?;
                     if let Some(ext_address) = ext_settings.address {
                         settings.address = ext_address; // TODO checks
                     }
@@ -608,10 +860,18 @@ pub fn to_internal(json: &mut Config) -> Result<internal::Config> {
                     outbounds.push(outbound);
                 }
                 "obfs" => {
+                    if ext_outbound.settings.is_none() {
+                        return Err(anyhow!("invalid obfs outbound settings"));
+                    }
                     let mut settings = internal::ObfsOutboundSettings::new();
-                    let ext_settings: ObfsOutboundSettings =
-                        serde_json::from_str(ext_outbound.settings.as_ref().unwrap().get())
-                            .unwrap();
+                    let ext_settings: ObfsOutboundSettings = from_str_checked(
+                        ext_outbound.settings.as_ref().unwrap().get(),
+                        &settings_path,
+                        &mut unknown,
+                    )
+                    .map_err(|e| {
+                        anyhow!("invalid {} outbound settings: {}", &outbound.protocol, e)
+                    })?;
                     if let Some(ext_method) = ext_settings.method {
                         // TODO checks
                         settings.method = ext_method;
@@ -633,15 +893,47 @@ pub fn to_internal(json: &mut Config) -> Result<internal::Config> {
                         return Err(anyhow!("invalid trojan outbound settings"));
                     }
                     let mut settings = internal::TrojanOutboundSettings::new();
-                    let ext_settings: TrojanOutboundSettings =
-                        serde_json::from_str(ext_outbound.settings.as_ref().unwrap().get())
-                            .unwrap();
+                    let ext_settings: TrojanOutboundSettings = from_str_checked(
+                        ext_outbound.settings.as_ref().unwrap().get(),
+                        &settings_path,
+                        &mut unknown,
+                    )
+                    .map_err(|e| {
+                        anyhow!("invalid {} outbound settings: {}", &outbound.protocol, e)
+                    })?;
                     if let Some(ext_address) = ext_settings.address {
                         settings.address = ext_address; // TODO checks
                     }
                     if let Some(server_name) = ext_settings.server_name {
                         settings.server_name = server_name; // TODO checks
                     }
+                    if let Some(ext_no_sni) = ext_settings.no_sni {
+                        settings.no_sni = ext_no_sni;
+                    }
+                    if let Some(ext_cert_pin) = ext_settings.cert_pin {
+                        settings.cert_pin = ext_cert_pin;
+                    }
+                    if let Some(ext_ca_cert) = ext_settings.ca_cert {
+                        settings.ca_cert = ext_ca_cert;
+                    }
+                    if let Some(ext_allow_insecure) = ext_settings.allow_insecure {
+                        settings.allow_insecure = ext_allow_insecure;
+                    }
+                    if let Some(ext_compress) = ext_settings.compress {
+                        settings.compress = ext_compress;
+                    }
+                    if let Some(ext_sni) = ext_settings.sni {
+                        settings.sni = ext_sni;
+                    }
+                    if let Some(ext_alpn) = ext_settings.alpn {
+                        settings.alpn = ext_alpn;
+                    }
+                    if let Some(ext_suites) = ext_settings.suites {
+                        settings.suites = ext_suites;
+                    }
+                    if let Some(ext_servers) = ext_settings.servers {
+                        settings.servers = ext_servers;
+                    }
                     if let Some(ext_port) = ext_settings.port {
                         settings.port = ext_port as u32; // TODO checks
                     }
@@ -652,12 +944,96 @@ pub fn to_internal(json: &mut Config) -> Result<internal::Config> {
                     outbound.settings = settings;
                     outbounds.push(outbound);
                 }
+                "http2" => {
+                    if ext_outbound.settings.is_none() {
+                        return Err(anyhow!("invalid http2 outbound settings"));
+                    }
+                    let mut settings = internal::Http2OutboundSettings::new();
+                    let ext_settings: Http2OutboundSettings = from_str_checked(
+                        ext_outbound.settings.as_ref().unwrap().get(),
+                        &settings_path,
+                        &mut unknown,
+                    )
+                    .map_err(|e| {
+                        anyhow!("invalid {} outbound settings: {}", &outbound.protocol, e)
+                    })?;
+                    if let Some(ext_address) = ext_settings.address {
+                        settings.address = ext_address;
+                    }
+                    if let Some(ext_port) = ext_settings.port {
+                        settings.port = ext_port as u32;
+                    }
+                    if let Some(ext_server_name) = ext_settings.server_name {
+                        settings.server_name = ext_server_name;
+                    }
+                    if let Some(ext_username) = ext_settings.username {
+                        settings.username = ext_username;
+                    }
+                    if let Some(ext_password) = ext_settings.password {
+                        settings.password = ext_password;
+                    }
+                    if let Some(ext_protocol) = ext_settings.protocol {
+                        settings.protocol = ext_protocol;
+                    }
+                    if let Some(ext_path) = ext_settings.path {
+                        settings.path = ext_path;
+                    }
+                    let settings = settings.write_to_bytes().unwrap();
+                    outbound.settings = settings;
+                    outbounds.push(outbound);
+                }
+                "ssh" => {
+                    if ext_outbound.settings.is_none() {
+                        return Err(anyhow!("invalid ssh outbound settings"));
+                    }
+                    let mut settings = internal::SshOutboundSettings::new();
+                    let ext_settings: SshOutboundSettings = from_str_checked(
+                        ext_outbound.settings.as_ref().unwrap().get(),
+                        &settings_path,
+                        &mut unknown,
+                    )
+                    .map_err(|e| {
+                        anyhow!("invalid {} outbound settings: {}", &outbound.protocol, e)
+                    })?;
+                    if let Some(ext_address) = ext_settings.address {
+                        settings.address = ext_address;
+                    }
+                    if let Some(ext_port) = ext_settings.port {
+                        settings.port = ext_port as u32;
+                    }
+                    if let Some(ext_username) = ext_settings.username {
+                        settings.username = ext_username;
+                    }
+                    if let Some(ext_password) = ext_settings.password {
+                        settings.password = ext_password;
+                    }
+                    if let Some(ext_private_key) = ext_settings.private_key {
+                        settings.private_key = ext_private_key;
+                    }
+                    if let Some(ext_passphrase) = ext_settings.private_key_passphrase {
+                        settings.private_key_passphrase = ext_passphrase;
+                    }
+                    if let Some(ext_host_key) = ext_settings.host_key {
+                        settings.host_key = ext_host_key;
+                    }
+                    if let Some(ext_allow_insecure) = ext_settings.allow_insecure {
+                        settings.allow_insecure = ext_allow_insecure;
+                    }
+                    let settings = settings.write_to_bytes().unwrap();
+                    outbound.settings = settings;
+                    outbounds.push(outbound);
+                }
                 "tls" => {
                     let mut settings = internal::TlsOutboundSettings::new();
                     if ext_outbound.settings.is_some() {
-                        let ext_settings: TlsOutboundSettings =
-                            serde_json::from_str(ext_outbound.settings.as_ref().unwrap().get())
-                                .unwrap();
+                        let ext_settings: TlsOutboundSettings = from_str_checked(
+                            ext_outbound.settings.as_ref().unwrap().get(),
+                            &settings_path,
+                            &mut unknown,
+                        )
+                        .map_err(|e| {
+                            anyhow!("invalid {} outbound settings: {}", &outbound.protocol, e)
+                        })?;
                         if let Some(ext_server_name) = ext_settings.server_name {
                             settings.server_name = ext_server_name; // TODO checks
                         }
@@ -692,9 +1068,14 @@ pub fn to_internal(json: &mut Config) -> Result<internal::Config> {
                         return Err(anyhow!("invalid ws outbound settings"));
                     }
                     let mut settings = internal::WebSocketOutboundSettings::new();
-                    let ext_settings: WebSocketOutboundSettings =
-                        serde_json::from_str(ext_outbound.settings.as_ref().unwrap().get())
-                            .unwrap();
+                    let ext_settings: WebSocketOutboundSettings = from_str_checked(
+                        ext_outbound.settings.as_ref().unwrap().get(),
+                        &settings_path,
+                        &mut unknown,
+                    )
+                    .map_err(|e| {
+                        anyhow!("invalid {} outbound settings: {}", &outbound.protocol, e)
+                    })?;
                     if let Some(ext_path) = ext_settings.path {
                         settings.path = ext_path; // TODO checks
                     }
@@ -710,9 +1091,14 @@ pub fn to_internal(json: &mut Config) -> Result<internal::Config> {
                         return Err(anyhow!("invalid tryall outbound settings"));
                     }
                     let mut settings = internal::TryAllOutboundSettings::new();
-                    let ext_settings: TryAllOutboundSettings =
-                        serde_json::from_str(ext_outbound.settings.as_ref().unwrap().get())
-                            .unwrap();
+                    let ext_settings: TryAllOutboundSettings = from_str_checked(
+                        ext_outbound.settings.as_ref().unwrap().get(),
+                        &settings_path,
+                        &mut unknown,
+                    )
+                    .map_err(|e| {
+                        anyhow!("invalid {} outbound settings: {}", &outbound.protocol, e)
+                    })?;
                     if let Some(ext_actors) = ext_settings.actors {
                         for ext_actor in ext_actors {
                             settings.actors.push(ext_actor);
@@ -732,9 +1118,14 @@ pub fn to_internal(json: &mut Config) -> Result<internal::Config> {
                         return Err(anyhow!("invalid static outbound settings"));
                     }
                     let mut settings = internal::StaticOutboundSettings::new();
-                    let ext_settings: StaticOutboundSettings =
-                        serde_json::from_str(ext_outbound.settings.as_ref().unwrap().get())
-                            .unwrap();
+                    let ext_settings: StaticOutboundSettings = from_str_checked(
+                        ext_outbound.settings.as_ref().unwrap().get(),
+                        &settings_path,
+                        &mut unknown,
+                    )
+                    .map_err(|e| {
+                        anyhow!("invalid {} outbound settings: {}", &outbound.protocol, e)
+                    })?;
                     if let Some(ext_actors) = ext_settings.actors {
                         for ext_actor in ext_actors {
                             settings.actors.push(ext_actor);
@@ -745,6 +1136,18 @@ pub fn to_internal(json: &mut Config) -> Result<internal::Config> {
                     } else {
                         settings.method = "random".to_string();
                     }
+                    if let Some(ext_smart_cache) = &ext_settings.smart_cache {
+                        settings.smart_cache = ext_smart_cache.clone();
+                    }
+                    if let Some(ext_fallback_timeout) = ext_settings.fallback_timeout {
+                        settings.fallback_timeout = ext_fallback_timeout;
+                    }
+                    if let Some(ext_check_interval) = ext_settings.check_interval {
+                        settings.check_interval = ext_check_interval;
+                    }
+                    if let Some(ext_weights) = ext_settings.weights {
+                        settings.weights = ext_weights;
+                    }
                     let settings = settings.write_to_bytes().unwrap();
                     outbound.settings = settings;
                     outbounds.push(outbound);
@@ -754,9 +1157,14 @@ pub fn to_internal(json: &mut Config) -> Result<internal::Config> {
                         return Err(anyhow!("invalid failover outbound settings"));
                     }
                     let mut settings = internal::FailOverOutboundSettings::new();
-                    let ext_settings: FailOverOutboundSettings =
-                        serde_json::from_str(ext_outbound.settings.as_ref().unwrap().get())
-                            .unwrap();
+                    let ext_settings: FailOverOutboundSettings = from_str_checked(
+                        ext_outbound.settings.as_ref().unwrap().get(),
+                        &settings_path,
+                        &mut unknown,
+                    )
+                    .map_err(|e| {
+                        anyhow!("invalid {} outbound settings: {}", &outbound.protocol, e)
+                    })?;
                     if let Some(ext_actors) = ext_settings.actors {
                         for ext_actor in ext_actors {
                             settings.actors.push(ext_actor);
@@ -806,9 +1214,14 @@ pub fn to_internal(json: &mut Config) -> Result<internal::Config> {
                         return Err(anyhow!("invalid amux outbound settings"));
                     }
                     let mut settings = internal::AMuxOutboundSettings::new();
-                    let ext_settings: AMuxOutboundSettings =
-                        serde_json::from_str(ext_outbound.settings.as_ref().unwrap().get())
-                            .unwrap();
+                    let ext_settings: AMuxOutboundSettings = from_str_checked(
+                        ext_outbound.settings.as_ref().unwrap().get(),
+                        &settings_path,
+                        &mut unknown,
+                    )
+                    .map_err(|e| {
+                        anyhow!("invalid {} outbound settings: {}", &outbound.protocol, e)
+                    })?;
                     if let Some(ext_address) = ext_settings.address {
                         settings.address = ext_address;
                     }
@@ -837,9 +1250,14 @@ pub fn to_internal(json: &mut Config) -> Result<internal::Config> {
                 "quic" => {
                     let mut settings = internal::QuicOutboundSettings::new();
                     if ext_outbound.settings.is_some() {
-                        let ext_settings: QuicOutboundSettings =
-                            serde_json::from_str(ext_outbound.settings.as_ref().unwrap().get())
-                                .unwrap();
+                        let ext_settings: QuicOutboundSettings = from_str_checked(
+                            ext_outbound.settings.as_ref().unwrap().get(),
+                            &settings_path,
+                            &mut unknown,
+                        )
+                        .map_err(|e| {
+                            anyhow!("invalid {} outbound settings: {}", &outbound.protocol, e)
+                        })?;
                         if let Some(ext_address) = ext_settings.address {
                             settings.address = ext_address;
                         }
@@ -872,9 +1290,14 @@ pub fn to_internal(json: &mut Config) -> Result<internal::Config> {
                         return Err(anyhow!("invalid chain outbound settings"));
                     }
                     let mut settings = internal::ChainOutboundSettings::new();
-                    let ext_settings: ChainOutboundSettings =
-                        serde_json::from_str(ext_outbound.settings.as_ref().unwrap().get())
-                            .unwrap();
+                    let ext_settings: ChainOutboundSettings = from_str_checked(
+                        ext_outbound.settings.as_ref().unwrap().get(),
+                        &settings_path,
+                        &mut unknown,
+                    )
+                    .map_err(|e| {
+                        anyhow!("invalid {} outbound settings: {}", &outbound.protocol, e)
+                    })?;
                     if let Some(ext_actors) = ext_settings.actors {
                         for ext_actor in ext_actors {
                             settings.actors.push(ext_actor);
@@ -889,9 +1312,14 @@ pub fn to_internal(json: &mut Config) -> Result<internal::Config> {
                         return Err(anyhow!("invalid select outbound settings"));
                     }
                     let mut settings = internal::SelectOutboundSettings::new();
-                    let ext_settings: SelectOutboundSettings =
-                        serde_json::from_str(ext_outbound.settings.as_ref().unwrap().get())
-                            .unwrap();
+                    let ext_settings: SelectOutboundSettings = from_str_checked(
+                        ext_outbound.settings.as_ref().unwrap().get(),
+                        &settings_path,
+                        &mut unknown,
+                    )
+                    .map_err(|e| {
+                        anyhow!("invalid {} outbound settings: {}", &outbound.protocol, e)
+                    })?;
                     if let Some(ext_actors) = ext_settings.actors {
                         for ext_actor in ext_actors {
                             settings.actors.push(ext_actor);
@@ -906,9 +1334,14 @@ pub fn to_internal(json: &mut Config) -> Result<internal::Config> {
                         return Err(anyhow!("invalid plugin outbound settings"));
                     }
                     let mut settings = internal::PluginOutboundSettings::new();
-                    let ext_settings: PluginOutboundSettings =
-                        serde_json::from_str(ext_outbound.settings.as_ref().unwrap().get())
-                            .unwrap();
+                    let ext_settings: PluginOutboundSettings = from_str_checked(
+                        ext_outbound.settings.as_ref().unwrap().get(),
+                        &settings_path,
+                        &mut unknown,
+                    )
+                    .map_err(|e| {
+                        anyhow!("invalid {} outbound settings: {}", &outbound.protocol, e)
+                    })?;
                     if let Some(ext_path) = ext_settings.path {
                         settings.path = ext_path; // TODO checks
                     }
@@ -980,6 +1413,21 @@ pub fn to_internal(json: &mut Config) -> Result<internal::Config> {
                         rule.mmdbs.push(mmdb)
                     }
                 }
+                if let Some(ext_asns) = ext_rule.asn.as_mut() {
+                    for ext_asn in ext_asns.drain(0..) {
+                        external_rule::add_external_rule(&mut rule, &format!("asn:{}", ext_asn))
+                            .map_err(|e| anyhow!("invalid asn {}: {}", ext_asn, e))?;
+                    }
+                }
+                if let Some(ext_geosites) = ext_rule.geosite.as_mut() {
+                    for ext_geosite in ext_geosites.drain(0..) {
+                        external_rule::add_external_rule(
+                            &mut rule,
+                            &format!("geosite:{}", ext_geosite),
+                        )
+                        .map_err(|e| anyhow!("invalid geosite {}: {}", ext_geosite, e))?;
+                    }
+                }
                 if let Some(ext_externals) = ext_rule.external.as_mut() {
                     for ext_external in ext_externals.drain(0..) {
                         match external_rule::add_external_rule(&mut rule, &ext_external) {
@@ -1007,6 +1455,28 @@ pub fn to_internal(json: &mut Config) -> Result<internal::Config> {
                         rule.inbound_tags.push(it);
                     }
                 }
+                if let Some(ext_snis) = ext_rule.sni.as_mut() {
+                    for sni in ext_snis.drain(0..) {
+                        rule.snis.push(sni);
+                    }
+                }
+                if let Some(ext_alpns) = ext_rule.alpn.as_mut() {
+                    for alpn in ext_alpns.drain(0..) {
+                        rule.alpns.push(alpn);
+                    }
+                }
+                if let Some(ext_redirect) = ext_rule.redirect.take() {
+                    rule.redirect = ext_redirect;
+                }
+                if let Some(ext_log) = ext_rule.log {
+                    rule.log = ext_log;
+                }
+                if let Some(ext_resolve) = ext_rule.resolve.take() {
+                    rule.resolve = ext_resolve;
+                }
+                if let Some(ext_schedule) = ext_rule.schedule.take() {
+                    rule.schedule = ext_schedule;
+                }
                 rules.push(rule);
             }
         }
@@ -1014,13 +1484,46 @@ pub fn to_internal(json: &mut Config) -> Result<internal::Config> {
         if let Some(ext_domain_resolve) = ext_router.domain_resolve {
             int_router.domain_resolve = ext_domain_resolve;
         }
+        if ext_router.bypass_lan.unwrap_or(true) {
+            int_router.lan_target = direct_tag(&outbounds);
+        }
+        if let Some(ext_unmatched) = &ext_router.unmatched {
+            int_router.unmatched =
+                protobuf::EnumOrUnknown::new(crate::config::parse_unmatched(ext_unmatched)?);
+        }
+        if let Some(ext_fail_closed) = ext_router.fail_closed {
+            int_router.fail_closed = ext_fail_closed;
+        }
         router = protobuf::MessageField::some(int_router);
+    } else {
+        // bypassLan defaults to on, it shouldn't need a router section.
+        let lan_target = direct_tag(&outbounds);
+        if !lan_target.is_empty() {
+            let mut int_router = internal::Router::new();
+            int_router.lan_target = lan_target;
+            router = protobuf::MessageField::some(int_router);
+        }
     }
 
+    let dns = dns_to_internal(json.dns.as_ref());
+
+    let warnings = crate::config::check_unknown_keys(unknown, *crate::option::CONFIG_STRICT)?;
+
+    let mut config = internal::Config::new();
+    config.log = protobuf::MessageField::some(log);
+    config.inbounds = inbounds;
+    config.outbounds = outbounds;
+    config.router = router;
+    config.dns = protobuf::MessageField::some(dns);
+    config.warnings = warnings;
+    Ok(config)
+}
+
+fn dns_to_internal(ext_dns: Option<&Dns>) -> internal::Dns {
     let mut dns = internal::Dns::new();
     let mut servers = Vec::new();
     let mut hosts = HashMap::new();
-    if let Some(ext_dns) = &json.dns {
+    if let Some(ext_dns) = ext_dns {
         if let Some(ext_servers) = ext_dns.servers.as_ref() {
             for ext_server in ext_servers {
                 servers.push(ext_server.to_owned());
@@ -1037,6 +1540,27 @@ pub fn to_internal(json: &mut Config) -> Result<internal::Config> {
                 hosts.insert(name.to_owned(), ips);
             }
         }
+        if let Some(ext_system_resolver) = ext_dns.system_resolver {
+            dns.system_resolver = ext_system_resolver;
+        }
+        if let Some(ext_outbound) = &ext_dns.outbound {
+            dns.outbound = ext_outbound.clone();
+        }
+        if let Some(ext_doh_server) = &ext_dns.doh_server {
+            dns.doh_server = ext_doh_server.clone();
+        }
+        if let Some(ext_weights) = &ext_dns.weights {
+            dns.weights = ext_weights.clone();
+        }
+        if let Some(ext_ttl_rules) = &ext_dns.ttl_rules {
+            for ext_ttl_rule in ext_ttl_rules {
+                let mut rule = internal::dns::TtlRule::new();
+                rule.domain = ext_ttl_rule.domain.clone();
+                rule.min = ext_ttl_rule.min.unwrap_or(0);
+                rule.max = ext_ttl_rule.max.unwrap_or(0);
+                dns.ttl_rules.push(rule);
+            }
+        }
     }
     if servers.len() == 0 {
         servers.push("1.1.1.1".to_string());
@@ -1045,30 +1569,73 @@ pub fn to_internal(json: &mut Config) -> Result<internal::Config> {
     if !hosts.is_empty() {
         dns.hosts = hosts;
     }
+    dns
+}
 
-    let mut config = internal::Config::new();
-    config.log = protobuf::MessageField::some(log);
-    config.inbounds = inbounds;
-    config.outbounds = outbounds;
-    config.router = router;
-    config.dns = protobuf::MessageField::some(dns);
-    Ok(config)
+/// Parses the `dns` object of a JSON config.
+pub fn dns_from_string(s: &str) -> Result<internal::Dns> {
+    let mut unknown = Vec::new();
+    let dns: Dns = from_str_checked(s, "", &mut unknown)
+        .map_err(|e| anyhow!("deserialize dns config failed: {}", e))?;
+    for w in crate::config::check_unknown_keys(unknown, *crate::option::CONFIG_STRICT)? {
+        log::warn!("{}", w);
+    }
+    Ok(dns_to_internal(Some(&dns)))
+}
+
+/// Parses an object of the `outbounds` array of a JSON config.
+pub fn outbound_from_string(s: &str) -> Result<internal::Outbound> {
+    let mut unknown = Vec::new();
+    let outbound: Outbound = from_str_checked(s, "", &mut unknown)
+        .map_err(|e| anyhow!("deserialize outbound config failed: {}", e))?;
+    for w in crate::config::check_unknown_keys(unknown, *crate::option::CONFIG_STRICT)? {
+        log::warn!("{}", w);
+    }
+    let mut config = Config {
+        log: None,
+        inbounds: None,
+        outbounds: Some(vec![outbound]),
+        router: None,
+        dns: None,
+    };
+    let mut config = to_internal(&mut config)?;
+    for w in &config.warnings {
+        log::warn!("{}", w);
+    }
+    config
+        .outbounds
+        .pop()
+        .ok_or_else(|| anyhow!("unsupported outbound protocol"))
+}
+
+// Parses the config, returns it with the warnings about unknown keys.
+fn parse(config: &str) -> Result<(Config, Vec<String>)> {
+    let mut unknown = Vec::new();
+    let config = from_str_checked(config, "", &mut unknown)
+        .map_err(|e| anyhow!("deserialize json config failed: {}", e))?;
+    let warnings = crate::config::check_unknown_keys(unknown, *crate::option::CONFIG_STRICT)?;
+    Ok((config, warnings))
 }
 
 pub fn json_from_string(config: &str) -> Result<Config> {
-    serde_json::from_str(config).map_err(|e| anyhow!("deserialize json config failed: {}", e))
+    let (config, warnings) = parse(config)?;
+    for w in warnings {
+        log::warn!("{}", w);
+    }
+    Ok(config)
 }
 
 pub fn from_string(s: &str) -> Result<internal::Config> {
-    let mut config = json_from_string(s)?;
-    to_internal(&mut config)
+    let (mut config, mut warnings) = parse(&crate::config::normalize_text(s))?;
+    let mut config = to_internal(&mut config)?;
+    warnings.append(&mut config.warnings);
+    config.warnings = warnings;
+    Ok(config)
 }
 
 pub fn from_file<P>(path: P) -> Result<internal::Config>
 where
     P: AsRef<Path>,
 {
-    let config = std::fs::read_to_string(path)?;
-    let mut config = json_from_string(&config)?;
-    to_internal(&mut config)
+    from_string(&std::fs::read_to_string(path)?)
 }
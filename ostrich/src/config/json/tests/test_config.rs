@@ -90,3 +90,75 @@ fn test_config() {
 
     assert!(crate::config::json::json_from_string(json_str).is_ok());
 }
+
+#[test]
+fn test_unknown_keys() {
+    let json_str = r#"
+    {
+        "log": {
+            "level": "info",
+            "levle": "debug"
+        },
+        "outbounds": [
+            {
+                "protocol": "direct",
+                "tag": "direct_out",
+                "conectTimeout": 3
+            },
+            {
+                "protocol": "trojan",
+                "tag": "trojan_out",
+                "settings": {
+                    "address": "1.2.3.4",
+                    "port": 443,
+                    "password": "pass",
+                    "passwrod": "pass"
+                }
+            }
+        ]
+    }
+    "#;
+
+    let config = crate::config::json::from_string(json_str).unwrap();
+    assert!(config.outbounds.iter().any(|o| o.tag == "trojan_out"));
+    let warnings = &config.warnings;
+    for key in [
+        "log.levle",
+        "outbounds.0.conectTimeout",
+        "outbounds.1.settings.passwrod",
+    ] {
+        assert!(warnings.iter().any(|w| w.contains(key)), "{:?}", warnings);
+    }
+}
+
+#[test]
+fn test_bypass_lan_without_router() {
+    let json_str = r#"
+    {
+        "outbounds": [
+            {
+                "protocol": "direct",
+                "tag": "direct_out"
+            }
+        ]
+    }
+    "#;
+
+    let config = crate::config::json::from_string(json_str).unwrap();
+    assert_eq!(config.router.lan_target, "direct_out");
+    assert!(config.router.rules.is_empty());
+}
+
+#[test]
+fn test_malformed_outbound_settings() {
+    // Errors rather than panics, outbounds come from the API too.
+    for outbound in [
+        r#"{"protocol": "socks", "tag": "s", "settings": {"port": "x"}}"#,
+        r#"{"protocol": "trojan", "tag": "t", "settings": "x"}"#,
+        r#"{"protocol": "chain", "tag": "c", "settings": {"actors": "a"}}"#,
+        r#"{"protocol": "obfs", "tag": "o"}"#,
+    ] {
+        let err = crate::config::json::outbound_from_string(outbound).unwrap_err();
+        assert!(err.to_string().contains("outbound settings"), "{}", err);
+    }
+}
@@ -1,3 +1,4 @@
+use std::borrow::Cow;
 use std::path::Path;
 
 use anyhow::anyhow;
@@ -6,6 +7,7 @@ use anyhow::Result;
 pub mod external_rule;
 pub mod geosite;
 pub mod internal;
+pub mod schema;
 
 #[cfg(feature = "config-json")]
 pub mod json;
@@ -13,13 +15,92 @@ pub mod json;
 #[cfg(feature = "config-conf")]
 pub mod conf;
 
+#[cfg(feature = "config-encrypted")]
+pub mod encrypted;
+
 pub use internal::*;
+pub use schema::{outbound_common_fields, outbound_schema};
+
+/// Skips the keys the parser doesn't recognize, from typos or newer versions,
+/// returning a warning for each, or fails on them if `strict`.
+#[cfg(any(feature = "config-json", feature = "config-conf"))]
+pub(crate) fn check_unknown_keys(keys: Vec<String>, strict: bool) -> Result<Vec<String>> {
+    if strict && !keys.is_empty() {
+        return Err(anyhow!("unknown config keys: {}", keys.join(", ")));
+    }
+    Ok(keys
+        .into_iter()
+        .map(|key| format!("unknown config key {}, skipped", key))
+        .collect())
+}
+
+/// Strips the UTF-8 BOM and turns CRLF and CR line endings into LF, as
+/// left by editors on Windows.
+pub fn normalize_text(s: &str) -> Cow<str> {
+    let s = s.strip_prefix('\u{feff}').unwrap_or(s);
+    if s.contains('\r') {
+        Cow::Owned(s.replace("\r\n", "\n").replace('\r', "\n"))
+    } else {
+        Cow::Borrowed(s)
+    }
+}
+
+/// Parses the policy for sessions matching no rules, `reject`, `direct` or
+/// `error`.
+pub fn parse_unmatched(s: &str) -> Result<internal::router::Unmatched> {
+    match s {
+        "reject" => Ok(internal::router::Unmatched::REJECT),
+        "direct" => Ok(internal::router::Unmatched::DIRECT),
+        "error" => Ok(internal::router::Unmatched::ERROR),
+        _ => Err(anyhow!("invalid unmatched policy {}", s)),
+    }
+}
+
+/// Parses a log level, `trace`, `debug`, `info`, `warn` or `error`, anything
+/// else is taken as `warn`.
+pub fn parse_log_level(s: &str) -> internal::log::Level {
+    match s {
+        "trace" => internal::log::Level::TRACE,
+        "debug" => internal::log::Level::DEBUG,
+        "info" => internal::log::Level::INFO,
+        "error" => internal::log::Level::ERROR,
+        _ => internal::log::Level::WARN,
+    }
+}
+
+/// Parses how the log file is rotated, `none` or `daily`.
+pub fn parse_log_rotation(s: &str) -> Result<internal::log::Rotation> {
+    match s {
+        "none" => Ok(internal::log::Rotation::NONE),
+        "daily" => Ok(internal::log::Rotation::DAILY),
+        _ => Err(anyhow!("invalid log rotation {}", s)),
+    }
+}
+
+/// Parses the name of a tun device, at most 15 ASCII letters, digits, `-`,
+/// `_` or `.`, the limit of interface names on Linux.
+pub fn parse_tun_name(s: &str) -> Result<String> {
+    if s.is_empty()
+        || s.len() > 15
+        || s == "."
+        || s == ".."
+        || !s
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_' || b == b'.')
+    {
+        return Err(anyhow!("invalid tun name {}", s));
+    }
+    Ok(s.to_string())
+}
 
 pub fn from_string(s: &str) -> Result<internal::Config> {
+    let s = &normalize_text(s);
     #[cfg(feature = "config-json")]
     {
-        if let Ok(c) = json::from_string(s) {
-            return Ok(c);
+        // A JSON config fails with its own errors, e.g. on unknown keys in
+        // the strict mode, rather than being parsed as a conf.
+        if s.trim_start().starts_with('{') {
+            return json::from_string(s);
         }
     }
     #[cfg(feature = "config-conf")]
@@ -30,7 +111,24 @@ pub fn from_string(s: &str) -> Result<internal::Config> {
     Err(anyhow!("could not load config from:\n{:?}", s))
 }
 
+/// Renders the config in the conf format.
+#[cfg(feature = "config-conf")]
+pub fn to_string(config: &internal::Config) -> String {
+    conf::to_string(config)
+}
+
 pub fn from_file(path: &str) -> Result<internal::Config> {
+    // Encrypted configs are told by their content, whatever the extension.
+    #[cfg(feature = "config-encrypted")]
+    {
+        let data = std::fs::read(path)?;
+        if encrypted::is_encrypted(&data) {
+            let data = encrypted::decrypt(&data, &encrypted::key_from_env()?)?;
+            let s = String::from_utf8(data)
+                .map_err(|_| anyhow!("decrypted config is not valid UTF-8"))?;
+            return from_string(&s);
+        }
+    }
     if let Some(ext) = Path::new(path).extension() {
         if let Some(ext) = ext.to_str() {
             match ext {
@@ -44,3 +142,80 @@ pub fn from_file(path: &str) -> Result<internal::Config> {
     }
     Err(anyhow!("config files use extension .json or .conf"))
 }
+
+#[cfg(all(test, feature = "config-conf"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bom_and_crlf() {
+        let conf = r#"
+[General]
+loglevel = info
+dns-server = 1.1.1.1
+
+[Proxy]
+Direct = direct
+
+[Rule]
+FINAL, Direct
+"#;
+        let expected = from_string(conf).unwrap();
+        let crlf = format!("\u{feff}{}", conf.replace('\n', "\r\n"));
+        assert_eq!(from_string(&crlf).unwrap(), expected);
+        assert_eq!(conf::from_string(&crlf).unwrap(), expected);
+        let cr = format!("\u{feff}{}", conf.replace('\n', "\r"));
+        assert_eq!(from_string(&cr).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_parse_log_rotation() {
+        assert_eq!(
+            parse_log_rotation("none").unwrap(),
+            internal::log::Rotation::NONE
+        );
+        assert_eq!(
+            parse_log_rotation("daily").unwrap(),
+            internal::log::Rotation::DAILY
+        );
+        assert!(parse_log_rotation("hourly").is_err());
+
+        let conf = "[General]\nlog-rotation = weekly\n";
+        assert!(from_string(conf).is_err());
+    }
+
+    #[test]
+    fn test_parse_tun_name() {
+        assert_eq!(parse_tun_name("utun233").unwrap(), "utun233");
+        assert_eq!(parse_tun_name("tun-0.a_b").unwrap(), "tun-0.a_b");
+        for name in [
+            "",
+            "..",
+            "tun/0",
+            "tun 0",
+            "tun0;reboot",
+            "a-very-long-tun-name",
+        ] {
+            assert!(parse_tun_name(name).is_err(), "{}", name);
+        }
+
+        let conf = "[General]\ntun = tun$(id)\n";
+        assert!(from_string(conf).is_err());
+    }
+
+    #[test]
+    fn test_parse_log_level() {
+        assert_eq!(parse_log_level("trace"), internal::log::Level::TRACE);
+        assert_eq!(parse_log_level("error"), internal::log::Level::ERROR);
+        assert_eq!(parse_log_level("warn"), internal::log::Level::WARN);
+        assert_eq!(parse_log_level("verbose"), internal::log::Level::WARN);
+
+        let conf = "[General]\nloglevel = debug\nlog-ring-level = info\n";
+        let log = from_string(conf).unwrap().log.unwrap();
+        assert_eq!(log.level.enum_value().unwrap(), internal::log::Level::DEBUG);
+        assert_eq!(
+            log.ring_level.enum_value().unwrap(),
+            internal::log::Level::INFO
+        );
+    }
+}
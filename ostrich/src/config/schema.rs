@@ -0,0 +1,204 @@
+//! Describes the settings each supported outbound protocol accepts, so that
+//! frontends can build config forms without hardcoding them.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldType {
+    String,
+    Bool,
+    Port,
+    Number,
+    StringList,
+    NumberList,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SettingField {
+    /// The field name as used in the JSON config.
+    pub name: &'static str,
+    pub field_type: FieldType,
+    pub required: bool,
+    /// The accepted values, any value if empty.
+    pub values: &'static [&'static str],
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutboundSchema {
+    /// The value of the `protocol` field of an outbound.
+    pub protocol: &'static str,
+    pub fields: Vec<SettingField>,
+}
+
+fn field(name: &'static str, field_type: FieldType, required: bool) -> SettingField {
+    SettingField {
+        name,
+        field_type,
+        required,
+        values: &[],
+    }
+}
+
+fn choice(name: &'static str, values: &'static [&'static str]) -> SettingField {
+    SettingField {
+        name,
+        field_type: FieldType::String,
+        required: false,
+        values,
+    }
+}
+
+/// Returns the fields every outbound accepts next to `settings`, whatever
+/// the protocol.
+pub fn outbound_common_fields() -> Vec<SettingField> {
+    vec![
+        choice("proxyProtocol", &["v1", "v2"]),
+        field("dscp", FieldType::Number, false),
+        field("fwmark", FieldType::Number, false),
+        field("connectTimeout", FieldType::Number, false),
+        field("connectRetries", FieldType::Number, false),
+    ]
+}
+
+/// Returns the settings schema of all outbound protocols compiled in.
+pub fn outbound_schema() -> Vec<OutboundSchema> {
+    #[allow(unused_mut)]
+    let mut schemas = Vec::new();
+
+    #[cfg(feature = "outbound-direct")]
+    schemas.push(OutboundSchema {
+        protocol: "direct",
+        fields: Vec::new(),
+    });
+
+    #[cfg(feature = "outbound-trojan")]
+    schemas.push(OutboundSchema {
+        protocol: "trojan",
+        fields: vec![
+            field("address", FieldType::String, true),
+            field("port", FieldType::Port, true),
+            field("password", FieldType::String, true),
+            field("servers", FieldType::StringList, false),
+            field("server_name", FieldType::String, false),
+            field("sni", FieldType::String, false),
+            field("noSni", FieldType::Bool, false),
+            field("alpn", FieldType::StringList, false),
+            field("suites", FieldType::String, false),
+            field("caCert", FieldType::String, false),
+            field("certPin", FieldType::String, false),
+            field("allowInsecure", FieldType::Bool, false),
+            field("compress", FieldType::Bool, false),
+        ],
+    });
+
+    #[cfg(feature = "outbound-shadowsocks")]
+    schemas.push(OutboundSchema {
+        protocol: "shadowsocks",
+        fields: vec![
+            field("address", FieldType::String, true),
+            field("port", FieldType::Port, true),
+            field("method", FieldType::String, false),
+            field("password", FieldType::String, true),
+        ],
+    });
+
+    #[cfg(feature = "outbound-ssh")]
+    schemas.push(OutboundSchema {
+        protocol: "ssh",
+        fields: vec![
+            field("address", FieldType::String, true),
+            field("port", FieldType::Port, true),
+            field("username", FieldType::String, true),
+            field("password", FieldType::String, false),
+            field("privateKey", FieldType::String, false),
+            field("privateKeyPassphrase", FieldType::String, false),
+            field("hostKey", FieldType::String, false),
+            field("allowInsecure", FieldType::Bool, false),
+        ],
+    });
+
+    #[cfg(feature = "outbound-static")]
+    schemas.push(OutboundSchema {
+        protocol: "static",
+        fields: vec![
+            field("actors", FieldType::StringList, true),
+            choice(
+                "method",
+                &["random", "rr", "weighted", "smart", "fallback", "failover"],
+            ),
+            field("weights", FieldType::NumberList, false),
+            field("smartCache", FieldType::String, false),
+            field("fallbackTimeout", FieldType::Number, false),
+            field("checkInterval", FieldType::Number, false),
+        ],
+    });
+
+    #[cfg(feature = "outbound-http2")]
+    schemas.push(OutboundSchema {
+        protocol: "http2",
+        fields: vec![
+            field("address", FieldType::String, true),
+            field("port", FieldType::Port, true),
+            field("serverName", FieldType::String, false),
+            field("username", FieldType::String, false),
+            field("password", FieldType::String, false),
+            field("protocol", FieldType::String, false),
+            field("path", FieldType::String, false),
+        ],
+    });
+
+    schemas
+}
+
+#[cfg(all(test, feature = "config-json"))]
+mod tests {
+    use serde::de::DeserializeOwned;
+    use serde::Serialize;
+
+    use super::*;
+    use crate::config::json;
+
+    // Returns the keys of the JSON settings type, all its fields are
+    // optional but the ones in `required`.
+    fn json_keys<T: DeserializeOwned + Serialize>(required: &str) -> Vec<String> {
+        let value: T = serde_json::from_str(&format!("{{{}}}", required)).unwrap();
+        let mut keys: Vec<String> = serde_json::to_value(value)
+            .unwrap()
+            .as_object()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect();
+        keys.sort();
+        keys
+    }
+
+    fn names(fields: &[SettingField]) -> Vec<String> {
+        let mut names: Vec<String> = fields.iter().map(|f| f.name.to_string()).collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_outbound_schema() {
+        let mut keys = json_keys::<json::Outbound>(r#""protocol": "direct""#);
+        keys.retain(|k| !["protocol", "tag", "settings"].contains(&k.as_str()));
+        assert_eq!(names(&outbound_common_fields()), keys);
+
+        for schema in outbound_schema() {
+            let keys = match schema.protocol {
+                "direct" => Vec::new(),
+                #[cfg(feature = "outbound-trojan")]
+                "trojan" => json_keys::<json::TrojanOutboundSettings>(""),
+                #[cfg(feature = "outbound-shadowsocks")]
+                "shadowsocks" => json_keys::<json::ShadowsocksOutboundSettings>(""),
+                #[cfg(feature = "outbound-ssh")]
+                "ssh" => json_keys::<json::SshOutboundSettings>(""),
+                #[cfg(feature = "outbound-static")]
+                "static" => json_keys::<json::StaticOutboundSettings>(""),
+                #[cfg(feature = "outbound-http2")]
+                "http2" => json_keys::<json::Http2OutboundSettings>(""),
+                p => panic!("no settings type for {}", p),
+            };
+            assert_eq!(names(&schema.fields), keys, "{}", schema.protocol);
+        }
+    }
+}
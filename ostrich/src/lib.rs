@@ -8,15 +8,30 @@ use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::sync_channel;
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::time::Duration;
 use thiserror::Error;
 use tokio::sync::mpsc;
+use tokio::sync::watch;
 use tokio::sync::RwLock;
 
 use app::{
-    dispatcher::Dispatcher, dns_client::DnsClient, inbound::manager::InboundManager,
-    nat_manager::NatManager, outbound::manager::OutboundManager, router::Router,
+    dispatcher::Dispatcher,
+    dns_client::DnsClient,
+    inbound::manager::{InboundHealth, InboundManager, InboundReadiness, TunTraffic},
+    nat_manager::NatManager,
+    outbound::{
+        manager::OutboundManager,
+        metrics::{OutboundMetrics, OutboundStat},
+    },
+    router::Router,
 };
 
+#[cfg(feature = "stat")]
+use app::{stat_manager::StatManager, SyncStatManager};
+
+#[cfg(feature = "api")]
+use app::api::api_server::ApiServer;
+
 pub mod app;
 pub mod common;
 pub mod config;
@@ -37,6 +52,23 @@ use tikv_jemallocator::Jemalloc;
 #[global_allocator]
 static GLOBAL: Jemalloc = Jemalloc;
 
+// Read by jemalloc at init: no background purge threads, and dirty pages are
+// returned to the system after a second rather than ten, which keeps the
+// memory accounted to mobile apps and network extensions close to what's in
+// use. The _RJEM_MALLOC_CONF environment variable still overrides it.
+#[cfg(all(
+    not(target_env = "msvc"),
+    any(
+        feature = "jemalloc-prompt-release",
+        target_os = "ios",
+        target_os = "android"
+    )
+))]
+#[allow(non_upper_case_globals)]
+#[export_name = "_rjem_malloc_conf"]
+static malloc_conf: &[u8; 61] =
+    b"background_thread:false,dirty_decay_ms:1000,muzzy_decay_ms:0\0";
+
 // #[cfg(any(target_os = "linux", target_os = "macos", target_os = "windows",target_os = "ios"))]
 #[cfg(target_env = "msvc")]
 #[global_allocator]
@@ -45,6 +77,21 @@ static ALLOC: rpmalloc::RpMalloc = rpmalloc::RpMalloc;
 pub enum Error {
     #[error(transparent)]
     Config(#[from] anyhow::Error),
+    /// The config can't be read or parsed.
+    #[error("parse config failed: {0}")]
+    ConfigParse(anyhow::Error),
+    /// An inbound can't listen on its address.
+    #[error("bind {addr} failed: {source}")]
+    BindFailed {
+        addr: std::net::SocketAddr,
+        source: io::Error,
+    },
+    /// The system network can't be set up for the TUN inbound.
+    #[error("setup tun failed: {0}")]
+    TunSetup(anyhow::Error),
+    /// The outbound can't reach the test destination.
+    #[error("outbound [{tag}] unreachable: {reason}")]
+    OutboundUnreachable { tag: String, reason: anyhow::Error },
     #[error("no associated config file")]
     NoConfigFile,
     #[error(transparent)]
@@ -70,6 +117,36 @@ pub struct RuntimeManager {
     router: Arc<RwLock<Router>>,
     dns_client: Arc<RwLock<DnsClient>>,
     outbound_manager: Arc<RwLock<OutboundManager>>,
+    outbound_metrics: Arc<OutboundMetrics>,
+    inbound_readiness: Arc<InboundReadiness>,
+    tun_traffic: Arc<TunTraffic>,
+    stopped: watch::Sender<bool>,
+    #[cfg(feature = "stat")]
+    stat_manager: SyncStatManager,
+}
+
+// Bounds the wait for the API server to close its connections once the
+// runtime has stopped, a slow request must not hold the shutdown.
+#[cfg(feature = "api")]
+const API_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// What has been cleared by `RuntimeManager::flush_caches`.
+#[derive(Debug)]
+pub struct FlushSummary {
+    pub dns_cache_entries: usize,
+    pub stat_counters: usize,
+}
+
+/// Packets and bytes through the TUN device, counted apart from the sessions
+/// since some packets are dropped or answered locally.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TunCounters {
+    pub rx_packets: u64,
+    pub rx_bytes: u64,
+    pub tx_packets: u64,
+    pub tx_bytes: u64,
+    // Read from the device but dropped as the queue to the stack was full.
+    pub dropped_packets: u64,
 }
 
 impl RuntimeManager {
@@ -80,6 +157,9 @@ impl RuntimeManager {
         router: Arc<RwLock<Router>>,
         dns_client: Arc<RwLock<DnsClient>>,
         outbound_manager: Arc<RwLock<OutboundManager>>,
+        outbound_metrics: Arc<OutboundMetrics>,
+        inbound_readiness: Arc<InboundReadiness>,
+        tun_traffic: Arc<TunTraffic>,
         #[cfg(feature = "stat")] stat_manager: SyncStatManager,
     ) -> Arc<Self> {
         Arc::new(Self {
@@ -89,9 +169,181 @@ impl RuntimeManager {
             router,
             dns_client,
             outbound_manager,
+            outbound_metrics,
+            inbound_readiness,
+            tun_traffic,
+            stopped: watch::channel(false).0,
+            #[cfg(feature = "stat")]
+            stat_manager,
         })
     }
 
+    /// Replaces the DNS servers and hosts. Queries in flight finish on the
+    /// old config, the cache is dropped since it holds the old static hosts.
+    pub async fn reload_dns(
+        &self,
+        dns: &protobuf::MessageField<config::Dns>,
+    ) -> anyhow::Result<()> {
+        let mut dns_client = self.dns_client.write().await;
+        dns_client.reload(dns)?;
+        dns_client.flush_cache().await;
+        log::info!("reloaded dns");
+        Ok(())
+    }
+
+    /// Looks up the host through the internal resolver, as sessions would.
+    pub async fn resolve(
+        &self,
+        host: &str,
+        ty: trust_dns_proto::rr::record_type::RecordType,
+    ) -> anyhow::Result<app::dns_client::Resolution> {
+        self.dns_client.read().await.resolve(host, ty).await
+    }
+
+    /// Adds a single outbound, usable by new sessions right away.
+    pub async fn add_outbound(
+        &self,
+        outbound: &config::Outbound,
+        replace: bool,
+    ) -> anyhow::Result<()> {
+        self.outbound_manager.write().await.add_outbound(
+            outbound,
+            self.dns_client.clone(),
+            replace,
+        )?;
+        log::info!("added outbound [{}]", &outbound.tag);
+        Ok(())
+    }
+
+    /// Replaces the outbounds with the ones in the config file, outbounds not
+    /// changed keep their sessions. TUN and the listeners stay up.
+    pub async fn reload_outbounds(&self) -> anyhow::Result<()> {
+        let path = self
+            .config_path
+            .as_ref()
+            .ok_or_else(|| anyhow!("no config file to reload from"))?;
+        let config = config::from_file(path)?;
+        log_config_warnings(&config);
+        self.outbound_manager
+            .write()
+            .await
+            .reload(&config.outbounds, self.dns_client.clone())?;
+        log::info!("reloaded outbounds from {}", path);
+        Ok(())
+    }
+
+    /// Re-reads the config file and replaces the outbounds and the routing
+    /// rules. TUN and the listeners stay up, nothing is changed if the config
+    /// fails to load.
+    pub async fn reload(&self) -> anyhow::Result<()> {
+        let path = self
+            .config_path
+            .as_ref()
+            .ok_or_else(|| anyhow!("no config file to reload from"))?;
+        let mut config = config::from_file(path)?;
+        log_config_warnings(&config);
+        // The rules are loaded first, they may fail on the files they refer to.
+        let router = Router::new(&mut config.router, self.dns_client.clone())?;
+        self.outbound_manager
+            .write()
+            .await
+            .reload(&config.outbounds, self.dns_client.clone())?;
+        proxy::set_interface_fail_closed(router.fail_closed());
+        *self.router.write().await = router;
+        log::info!("reloaded config from {}", path);
+        Ok(())
+    }
+
+    /// Rebuilds the TLS configs of the outbounds, picking up changed root
+    /// certificates. Established connections are not affected.
+    pub async fn reload_tls(&self) -> anyhow::Result<usize> {
+        let n = self.outbound_manager.read().await.reload_tls()?;
+        log::info!("reloaded {} tls configs", n);
+        Ok(n)
+    }
+
+    /// Returns the connect attempts counted per outbound.
+    pub fn outbound_stats(&self) -> Vec<OutboundStat> {
+        self.outbound_metrics.snapshot()
+    }
+
+    /// Returns whether the inbound listeners are bound and the TUN device, if
+    /// configured, is up.
+    pub fn inbound_health(&self) -> InboundHealth {
+        self.inbound_readiness.health()
+    }
+
+    /// Returns the TUN device counters of the runtime, all zeros if there's
+    /// no TUN inbound or tun2socks runs it.
+    pub fn tun_counters(&self) -> TunCounters {
+        self.tun_traffic.counters()
+    }
+
+    /// Clears the DNS cache and zeroes the stat counters. Routing decisions
+    /// are not cached, every session goes through the rules again anyway.
+    pub async fn flush_caches(&self) -> FlushSummary {
+        let dns_cache_entries = self.dns_client.read().await.flush_cache().await;
+        #[cfg(feature = "stat")]
+        let stat_counters = self.stat_manager.write().await.reset();
+        #[cfg(not(feature = "stat"))]
+        let stat_counters = 0;
+        let summary = FlushSummary {
+            dns_cache_entries,
+            stat_counters,
+        };
+        log::info!(
+            "flushed {} DNS cache entries, reset {} stat counters",
+            summary.dns_cache_entries,
+            summary.stat_counters
+        );
+        summary
+    }
+
+    /// Returns the bytes through each outbound, this month and in total,
+    /// including the previous runs when STAT_USAGE_FILE is set. Sessions are
+    /// only counted with ENABLE_STATS.
+    #[cfg(feature = "stat")]
+    pub async fn outbound_usage(&self) -> Vec<(String, app::stat_manager::Usage)> {
+        self.stat_manager.write().await.usage()
+    }
+
+    /// Returns the total bytes and the active sessions of each outbound,
+    /// including the ones nothing has gone through yet. Sessions are only
+    /// counted with ENABLE_STATS.
+    #[cfg(feature = "stat")]
+    pub async fn outbound_traffic(
+        &self,
+    ) -> std::collections::BTreeMap<String, app::stat_manager::OutboundTraffic> {
+        let tags: Vec<String> = self.outbound_manager.read().await.tags().cloned().collect();
+        self.stat_manager.write().await.outbound_traffic(tags)
+    }
+
+    /// Returns the bytes of each client through each inbound since start,
+    /// sessions are only counted with ENABLE_STATS.
+    #[cfg(feature = "stat")]
+    pub async fn client_usage(&self) -> Vec<app::stat_manager::ClientUsage> {
+        self.stat_manager.write().await.client_usage()
+    }
+
+    #[cfg(feature = "stat")]
+    async fn save_usage(&self) {
+        if option::STAT_USAGE_FILE.is_empty() {
+            return;
+        }
+        if let Err(e) = self
+            .stat_manager
+            .write()
+            .await
+            .save_usage(&*option::STAT_USAGE_FILE)
+        {
+            log::warn!(
+                "saving outbound usage to {} failed: {}",
+                &*option::STAT_USAGE_FILE,
+                e
+            );
+        }
+    }
+
     pub async fn shutdown(&self) -> bool {
         let tx = self.shutdown_tx.clone();
         if let Err(e) = tx.send(()).await {
@@ -109,30 +361,122 @@ impl RuntimeManager {
         }
         true
     }
+
+    /// Waits until the runtime has fully stopped, i.e. the routes restored
+    /// and the listeners closed, returns false on timeout.
+    pub async fn wait_stopped(&self, timeout: Duration) -> bool {
+        let mut rx = self.stopped.subscribe();
+        tokio::time::timeout(timeout, rx.wait_for(|stopped| *stopped))
+            .await
+            .map_or(false, |r| r.is_ok())
+    }
+
+    /// Sends the shutdown signal and waits until the runtime has stopped.
+    pub async fn shutdown_and_wait(&self, timeout: Duration) -> bool {
+        self.shutdown().await && self.wait_stopped(timeout).await
+    }
+
+    fn set_stopped(&self) {
+        self.stopped.send_replace(true);
+    }
+
+    // Resolves once the runtime has stopped.
+    #[cfg(feature = "api")]
+    pub(crate) fn stopped_signal(&self) -> impl std::future::Future<Output = ()> + Send + 'static {
+        let mut rx = self.stopped.subscribe();
+        async move {
+            let _ = rx.wait_for(|stopped| *stopped).await;
+        }
+    }
 }
 
+/// Identifies a runtime started by `start`, several runtimes can run in the
+/// same process as long as they don't share listening ports or TUN.
 pub type RuntimeId = u16;
-const INSTANCE_ID: RuntimeId = 1;
 lazy_static! {
     pub static ref RUNTIME_MANAGER: Mutex<IndexMap<RuntimeId, Arc<RuntimeManager>>> =
         Mutex::new(IndexMap::new());
+    // Ids of the runtimes being started or running.
+    static ref RUNTIME_IDS: Mutex<std::collections::HashSet<RuntimeId>> =
+        Mutex::new(std::collections::HashSet::new());
+}
+
+// Holds the id of a runtime from the start until it has stopped, so that no
+// other runtime starts with it meanwhile, and the manager registered under it
+// is only removed by the runtime owning it.
+struct RuntimeIdGuard(RuntimeId);
+
+impl RuntimeIdGuard {
+    fn reserve(rt_id: RuntimeId) -> Option<Self> {
+        if RUNTIME_IDS.lock().unwrap().insert(rt_id) {
+            Some(RuntimeIdGuard(rt_id))
+        } else {
+            None
+        }
+    }
+
+    fn register(&self, runtime_manager: Arc<RuntimeManager>) {
+        RUNTIME_MANAGER
+            .lock()
+            .unwrap()
+            .insert(self.0, runtime_manager);
+    }
+}
+
+impl Drop for RuntimeIdGuard {
+    fn drop(&mut self) {
+        RUNTIME_MANAGER.lock().unwrap().remove(&self.0);
+        RUNTIME_IDS.lock().unwrap().remove(&self.0);
+    }
 }
 
-pub fn shutdown() -> bool {
-    if let Some(m) = RUNTIME_MANAGER.lock().unwrap().get(&INSTANCE_ID) {
+pub fn shutdown(key: RuntimeId) -> bool {
+    if let Some(m) = RUNTIME_MANAGER.lock().unwrap().get(&key) {
         return m.blocking_shutdown();
     }
     false
 }
 
-pub fn is_running() -> bool {
-    RUNTIME_MANAGER.lock().unwrap().contains_key(&INSTANCE_ID)
+/// Shuts down the runtime and waits until it has fully stopped, returns false
+/// if it's not running or doesn't stop in time.
+pub async fn shutdown_and_wait(key: RuntimeId, timeout: Duration) -> bool {
+    let m = RUNTIME_MANAGER.lock().unwrap().get(&key).cloned();
+    match m {
+        Some(m) => m.shutdown_and_wait(timeout).await,
+        None => false,
+    }
+}
+
+/// Returns the changes made to the system routes, rules and addresses for
+/// TUN this session, in order, as the request lines of the route helper.
+pub fn route_changes() -> Vec<String> {
+    #[cfg(all(feature = "inbound-tun", any(target_os = "macos", target_os = "linux")))]
+    {
+        sys::changes()
+    }
+    #[cfg(not(all(feature = "inbound-tun", any(target_os = "macos", target_os = "linux"))))]
+    {
+        Vec::new()
+    }
+}
+
+pub fn is_running(key: RuntimeId) -> bool {
+    RUNTIME_MANAGER.lock().unwrap().contains_key(&key)
+}
+
+// Logs the warnings from parsing the config, which comes before the logger is
+// set up.
+fn log_config_warnings(config: &config::Config) {
+    for w in &config.warnings {
+        log::warn!("{}", w);
+    }
 }
 
-pub fn test_config(config_path: &str) -> Result<(), Error> {
+/// Checks the config file, returns the warnings from parsing it.
+pub fn test_config(config_path: &str) -> Result<Vec<String>, Error> {
     config::from_file(config_path)
-        .map(|_| ())
-        .map_err(Error::Config)
+        .map(|c| c.warnings)
+        .map_err(Error::ConfigParse)
 }
 
 fn new_runtime() -> Result<tokio::runtime::Runtime, Error> {
@@ -168,10 +512,71 @@ pub enum Config {
 pub struct StartOptions {
     // The path of the config.
     pub config: Config,
+    // Where the API of this runtime listens, API_LISTEN if not set. Runtimes
+    // in the same process need addresses of their own.
+    pub api_listen: Option<String>,
     #[cfg(target_os = "android")]
     pub socket_protect_path: Option<String>,
 }
+
+// Tears down the outbound UDP sessions and the pooled outbound connections,
+// which would otherwise stay open until their own timeouts, once nothing has
+// happened for a while. The next session sets them up again.
+fn idle_monitor(
+    dispatcher: Arc<Dispatcher>,
+    nat_manager: Arc<NatManager>,
+    outbound_manager: Arc<RwLock<OutboundManager>>,
+    dns_client: app::SyncDnsClient,
+) -> Runner {
+    Box::pin(async move {
+        let timeout = std::time::Duration::from_secs(*option::IDLE_TIMEOUT);
+        let interval = std::cmp::min(timeout, std::time::Duration::from_secs(10));
+        let mut released = false;
+        loop {
+            tokio::time::sleep(interval).await;
+            match dispatcher.activity().idle_for() {
+                Some(idle) if idle >= timeout => {
+                    if !released {
+                        let n = nat_manager.clear().await;
+                        outbound_manager.read().await.reset_conns().await;
+                        dns_client.read().await.reset_conns().await;
+                        log::info!(
+                            "idle for {}s, released {} udp sessions and the pooled connections",
+                            idle.as_secs(),
+                            n
+                        );
+                        released = true;
+                    }
+                }
+                _ => released = false,
+            }
+        }
+    })
+}
+
+// Drops the state built up over the previous network, the UDP sessions and
+// the pooled connections keep sending through sockets bound to the old
+// interface and the cached answers may not be reachable from the new one.
+#[cfg(all(feature = "inbound-tun", any(target_os = "macos", target_os = "linux")))]
+async fn drain_on_network_change(
+    nat_manager: &NatManager,
+    outbound_manager: &RwLock<OutboundManager>,
+    dns_client: &app::SyncDnsClient,
+) {
+    let sessions = nat_manager.clear().await;
+    outbound_manager.read().await.reset_conns().await;
+    let dns_client = dns_client.read().await;
+    dns_client.reset_conns().await;
+    let records = dns_client.flush_cache().await;
+    log::info!(
+        "network changed, released {} udp sessions, the pooled connections and {} dns records",
+        sessions,
+        records
+    );
+}
+
 pub fn start(
+    rt_id: RuntimeId,
     opts: StartOptions,
     // #[cfg(target_os = "windows")] mut ipset: Vec<String>,
     #[cfg(target_os = "windows")] wintun_path: String,
@@ -180,7 +585,17 @@ pub fn start(
     // #[cfg(debug_assertions)]
     // println!("start with options:\n{:#?}", opts);
 
-    // let (reload_tx, mut reload_rx) = mpsc::channel(1);
+    let rt_id_guard = match RuntimeIdGuard::reserve(rt_id) {
+        Some(g) => g,
+        None => {
+            log::warn!("runtime {} is already running", rt_id);
+            return Err(Error::RuntimeManager);
+        }
+    };
+
+    // Asks for the config file to be reloaded, on SIGHUP.
+    #[cfg(all(feature = "inbound-tun", any(target_os = "macos", target_os = "linux")))]
+    let (reload_tx, mut reload_rx) = mpsc::channel(1);
     let (shutdown_tx, mut shutdown_rx) = mpsc::channel(1);
 
     let config_path = match opts.config {
@@ -189,18 +604,24 @@ pub fn start(
     };
 
     let mut config = match opts.config {
-        Config::File(p) => config::from_file(&p).map_err(Error::Config)?,
-        Config::Str(s) => config::from_string(&s).map_err(Error::Config)?,
+        Config::File(p) => config::from_file(&p).map_err(Error::ConfigParse)?,
+        Config::Str(s) => config::from_string(&s).map_err(Error::ConfigParse)?,
         Config::Internal(c) => c,
     };
 
-    // app::logger::setup_logger(&config.log)?;
+    // Before the managers, so that what goes wrong setting them up is logged.
+    if config.log.is_some() {
+        app::logger::setup_logger(&config.log).map_err(Error::Config)?;
+    }
+    log_config_warnings(&config);
 
     let rt = new_runtime()?;
     let _g = rt.enter();
 
     let mut tasks: Vec<Runner> = Vec::new();
     let mut runners = Vec::new();
+    #[cfg(feature = "api")]
+    let mut api_runners = Vec::new();
 
     let dns_client = Arc::new(RwLock::new(
         DnsClient::new(&config.dns).map_err(Error::Config)?,
@@ -217,14 +638,31 @@ pub fn start(
     let outbound_manager = Arc::new(RwLock::new(
         OutboundManager::new(&config.outbounds, dns_client.clone()).map_err(Error::Config)?,
     ));
-    let router = Arc::new(RwLock::new(Router::new(
-        &mut config.router,
-        dns_client.clone(),
-    )));
+    let router = Router::new(&mut config.router, dns_client.clone()).map_err(Error::Config)?;
+    proxy::set_interface_fail_closed(router.fail_closed());
+    let router = Arc::new(RwLock::new(router));
     #[cfg(feature = "stat")]
     let stat_manager = Arc::new(RwLock::new(StatManager::new()));
     #[cfg(feature = "stat")]
     runners.push(StatManager::cleanup_task(stat_manager.clone()));
+    #[cfg(feature = "stat")]
+    if !option::STAT_USAGE_FILE.is_empty() {
+        let path = &*option::STAT_USAGE_FILE;
+        if !*option::ENABLE_STATS {
+            log::warn!(
+                "STAT_USAGE_FILE is set without ENABLE_STATS, the usage in {} won't grow",
+                path
+            );
+        }
+        if let Err(e) = rt.block_on(stat_manager.write()).load_usage(path) {
+            log::warn!("loading outbound usage from {} failed: {}", path, e);
+        }
+        runners.push(StatManager::save_task(
+            stat_manager.clone(),
+            path.clone(),
+            Duration::from_secs(*option::STAT_SAVE_INTERVAL),
+        ));
+    }
     let dispatcher = Arc::new(Dispatcher::new(
         outbound_manager.clone(),
         router.clone(),
@@ -242,11 +680,27 @@ pub fn start(
             .replace_dispatcher(dispatcher_weak);
     });
 
+    let outbound_metrics = dispatcher.metrics();
     let nat_manager = Arc::new(NatManager::new(dispatcher.clone()));
+    if *option::IDLE_TIMEOUT > 0 {
+        runners.push(idle_monitor(
+            dispatcher.clone(),
+            nat_manager.clone(),
+            outbound_manager.clone(),
+            dns_client.clone(),
+        ));
+    }
+    #[cfg(all(feature = "inbound-tun", any(target_os = "macos", target_os = "linux")))]
+    let drain = (
+        nat_manager.clone(),
+        outbound_manager.clone(),
+        dns_client.clone(),
+    );
     let inbound_manager = InboundManager::new(
         &config.inbounds,
         dispatcher,
         nat_manager,
+        &config.dns.servers,
         #[cfg(target_os = "windows")]
         ipset.clone(),
         #[cfg(target_os = "windows")]
@@ -254,16 +708,39 @@ pub fn start(
         #[cfg(target_os = "windows")]
         tun2socks_path,
     )
-    .map_err(Error::Config)?;
+    .map_err(
+        |e| match e.downcast::<app::inbound::manager::TunSetupError>() {
+            Ok(e) => Error::TunSetup(e.0),
+            Err(e) => Error::Config(e),
+        },
+    )?;
     // std::thread::sleep(std::time::Duration::from_secs(60));
-    let mut inbound_net_runners = inbound_manager
-        .get_network_runners()
-        .map_err(Error::Config)?;
+    let mut inbound_net_runners = inbound_manager.get_network_runners().map_err(|e| {
+        match e.downcast::<app::inbound::network_listener::BindError>() {
+            Ok(e) => Error::BindFailed {
+                addr: e.addr,
+                source: e.source,
+            },
+            Err(e) => Error::Config(e),
+        }
+    })?;
+    // Without them, there is nothing to fall back to if TUN fails.
+    #[cfg(all(
+        feature = "inbound-tun",
+        any(
+            target_os = "ios",
+            target_os = "android",
+            target_os = "macos",
+            target_os = "linux"
+        )
+    ))]
+    let has_network_inbounds = !inbound_net_runners.is_empty();
     runners.append(&mut inbound_net_runners);
 
     #[cfg(all(feature = "inbound-tun", any(target_os = "macos", target_os = "linux")))]
     let net_info = if inbound_manager.has_tun_listener() && inbound_manager.tun_auto() {
-        sys::get_net_info()?
+        sys::wait_for_net_info(Duration::from_secs(*option::WAIT_FOR_NETWORK))
+            .map_err(Error::TunSetup)?
     } else {
         sys::NetInfo::default()
     };
@@ -295,8 +772,14 @@ pub fn start(
             target_os = "linux"
         )
     ))]
-    if let Ok(r) = inbound_manager.get_tun_runner() {
-        runners.push(r);
+    if inbound_manager.has_tun_listener() {
+        match inbound_manager.get_tun_runner() {
+            Ok(r) => runners.push(r),
+            Err(e) if has_network_inbounds => {
+                log::error!("start tun inbound failed, continue without it: {}", e)
+            }
+            Err(e) => return Err(Error::TunSetup(e)),
+        }
     }
 
     #[cfg(feature = "inbound-cat")]
@@ -313,7 +796,7 @@ pub fn start(
         use futures::stream::StreamExt;
         use signal_hook::consts::signal::*;
         use signal_hook_tokio::Signals;
-        let mut signals = Signals::new(&[SIGTERM, SIGPIPE, SIGALRM])?;
+        let mut signals = Signals::new(&[SIGTERM, SIGHUP, SIGPIPE, SIGALRM])?;
         let signals_handle = signals.handle();
         let shutdown_tx = shutdown_tx.clone();
         let net_info = net_info.clone();
@@ -326,6 +809,8 @@ pub fn start(
             .unwrap_or_default();
         #[cfg(all(feature = "inbound-tun", any(target_os = "linux",)))]
         let network_changed = network_changed.clone();
+        let (nat_manager, outbound_manager, dns_client) = drain;
+        let tun_device = inbound_manager.tun_device().clone();
 
         tokio::spawn(async move {
             use if_watch::smol::IfWatcher;
@@ -338,8 +823,8 @@ pub fn start(
                     match event {
                         IfEvent::Up(up_ip) => {
                             if up_ip.addr().is_ipv4()
-                                && up_ip.addr().to_string() != "172.7.0.2".to_string()
-                                && up_ip.addr().to_string() != "172.7.0.1".to_string()
+                                && up_ip.addr().to_string() != tun_device.address
+                                && up_ip.addr().to_string() != tun_device.gateway
                                 && up_ip.addr().to_string() != "127.0.0.1".to_string()
                             {
                                 'net: loop {
@@ -353,7 +838,7 @@ pub fn start(
                                             {
                                                 #[cfg(target_os = "macos")]
                                                 {
-                                                    if ip != "172.7.0.2" {
+                                                    if ip != &tun_device.address {
                                                         println!("UP: after network interface changed,the new ipv4 is: {}", ip);
                                                         std::env::set_var(
                                                             "OUTBOUND_INTERFACE",
@@ -363,8 +848,25 @@ pub fn start(
                                                             "OUTBOUND_INTERFACE: {:?}",
                                                             std::env::var("OUTBOUND_INTERFACE")
                                                         );
-                                                        sys::post_tun_creation_setup(&sys_net);
+                                                        if let Err(e) = sys::post_tun_creation_setup(
+                                                            &sys_net,
+                                                            &tun_device,
+                                                        ) {
+                                                            log::error!(
+                                                                "routing into {} failed: {}",
+                                                                &tun_device.name,
+                                                                e
+                                                            );
+                                                        }
                                                         *net_info.lock().unwrap() = sys_net;
+                                                        if *option::DRAIN_ON_NETWORK_CHANGE {
+                                                            drain_on_network_change(
+                                                                &nat_manager,
+                                                                &outbound_manager,
+                                                                &dns_client,
+                                                            )
+                                                            .await;
+                                                        }
                                                         break 'net;
                                                     }
                                                     tokio::time::sleep(
@@ -378,7 +880,7 @@ pub fn start(
                                                     any(target_os = "linux",)
                                                 ))]
                                                 {
-                                                    if ip != "172.7.0.2" {
+                                                    if ip != &tun_device.address {
                                                         println!("UP: after network interface changed,the new ipv4 is: {}", ip);
                                                         std::env::set_var(
                                                             "OUTBOUND_INTERFACE",
@@ -388,8 +890,25 @@ pub fn start(
                                                             "OUTBOUND_INTERFACE: {:?}",
                                                             std::env::var("OUTBOUND_INTERFACE")
                                                         );
-                                                        sys::post_tun_creation_setup(&sys_net);
+                                                        if let Err(e) = sys::post_tun_creation_setup(
+                                                            &sys_net,
+                                                            &tun_device,
+                                                        ) {
+                                                            log::error!(
+                                                                "routing into {} failed: {}",
+                                                                &tun_device.name,
+                                                                e
+                                                            );
+                                                        }
                                                         *net_info.lock().unwrap() = sys_net;
+                                                        if *option::DRAIN_ON_NETWORK_CHANGE {
+                                                            drain_on_network_change(
+                                                                &nat_manager,
+                                                                &outbound_manager,
+                                                                &dns_client,
+                                                            )
+                                                            .await;
+                                                        }
                                                         break 'net;
                                                     }
                                                     if init_ipv4 != up_ip.addr().to_string() {
@@ -479,6 +998,11 @@ pub fn start(
                             }
                             break;
                         }
+                        SIGHUP => {
+                            log::trace!("signal received {}", &SIGHUP);
+                            // A reload already pending reads the file anyway.
+                            let _ = reload_tx.try_send(());
+                        }
                         _ => unreachable!(),
                     }
                 }
@@ -490,6 +1014,7 @@ pub fn start(
     }
     #[cfg(all(feature = "inbound-tun", any(target_os = "windows",)))]
     {
+        let tun_device = inbound_manager.tun_device().clone();
         tokio::spawn(async move {
             use crate::common::cmd;
             use if_watch::smol::IfWatcher;
@@ -539,8 +1064,8 @@ pub fn start(
                 match if_event {
                     IfEvent::Up(ip) => {
                         if ip.addr().is_ipv4()
-                            && ip.addr().to_string() != "172.7.0.2".to_string()
-                            && ip.addr().to_string() != "172.7.0.1".to_string()
+                            && ip.addr().to_string() != tun_device.address
+                            && ip.addr().to_string() != tun_device.gateway
                             && ip.addr().to_string() != "127.0.0.1".to_string()
                         // && ip.addr().to_string() != init_gateway
                         {
@@ -570,7 +1095,7 @@ pub fn start(
                                 .stdin(Stdio::null())
                                 .arg("delete")
                                 .arg("0.0.0.0")
-                                .arg("172.7.0.1")
+                                .arg(&tun_device.gateway)
                                 .output()
                                 .expect("failed to execute command");
                             // println!("route delete command finished with: {}", out);
@@ -585,27 +1110,29 @@ pub fn start(
                                         .arg("ip")
                                         .arg("set")
                                         .arg("address")
-                                        .arg("utun233")
+                                        .arg(&tun_device.name)
                                         .arg("static")
-                                        .arg("172.7.0.2")
-                                        .arg("255.255.255.0")
-                                        .arg("172.7.0.1")
+                                        .arg(&tun_device.address)
+                                        .arg(&tun_device.netmask)
+                                        .arg(&tun_device.gateway)
                                         .arg("3")
                                         .output()
                                         .expect("failed to execute command");
-                                    let out = Command::new("netsh").creation_flags(0x08000000)
-                                        .stderr(Stdio::null())
-                                        .stdout(Stdio::null())
-                                        .stdin(Stdio::null())
-                                        .arg("interface")
-                                        .arg("ip")
-                                        .arg("set")
-                                        .arg("dns")
-                                        .arg("name=utun233")
-                                        .arg("static")
-                                        .arg("127.0.0.1")
-                                        .output()
-                                        .expect("failed to execute command");
+                                    if let Some(dns) = &tun_device.dns {
+                                        let out = Command::new("netsh").creation_flags(0x08000000)
+                                            .stderr(Stdio::null())
+                                            .stdout(Stdio::null())
+                                            .stdin(Stdio::null())
+                                            .arg("interface")
+                                            .arg("ip")
+                                            .arg("set")
+                                            .arg("dns")
+                                            .arg(format!("name={}", &tun_device.name))
+                                            .arg("static")
+                                            .arg(dns)
+                                            .output()
+                                            .expect("failed to execute command");
+                                    }
                                     // println!("setup tun device command finished with: {}", out);
                                     for v in &ipset {
                                         let out = Command::new("route").creation_flags(0x08000000)
@@ -640,12 +1167,17 @@ pub fn start(
     /*    #[cfg(all(feature = "inbound-tun", any(target_os = "macos", target_os = "linux")))]
     sys::post_tun_creation_setup(&net_info);*/
 
+    let pending_dns = rt.block_on(dns_client.read()).pending_queries();
+
     let runtime_manager = RuntimeManager::new(
         config_path,
         shutdown_tx,
         router,
         dns_client,
         outbound_manager,
+        outbound_metrics,
+        inbound_manager.readiness(),
+        inbound_manager.tun_traffic(),
         #[cfg(feature = "stat")]
         stat_manager,
     );
@@ -658,21 +1190,26 @@ pub fn start(
         }
     }
 
+    #[cfg(all(feature = "inbound-tun", any(target_os = "macos", target_os = "linux")))]
+    {
+        let runtime_manager = runtime_manager.clone();
+        runners.push(Box::pin(async move {
+            while reload_rx.recv().await.is_some() {
+                if let Err(e) = runtime_manager.reload().await {
+                    log::error!("reloading config failed, keeping the current one: {}", e);
+                }
+            }
+        }));
+    }
+
     #[cfg(feature = "api")]
     {
-        use std::net::SocketAddr;
-        let listen_addr = if !(&*option::API_LISTEN).is_empty() {
-            Some(
-                (&*option::API_LISTEN)
-                    .parse::<SocketAddr>()
-                    .map_err(|e| Error::Config(anyhow!("parse SocketAddr failed: {}", e)))?,
-            )
-        } else {
-            None
-        };
-        if let Some(listen_addr) = listen_addr {
-            let api_server = ApiServer::new(runtime_manager.clone());
-            runners.push(api_server.serve(listen_addr));
+        let api_listen = opts.api_listen.as_ref().unwrap_or(&*option::API_LISTEN);
+        let listen_addrs =
+            app::api::api_server::parse_listen_addrs(api_listen).map_err(Error::Config)?;
+        let api_server = ApiServer::new(runtime_manager.clone());
+        for listen_addr in listen_addrs {
+            api_runners.push(api_server.serve(listen_addr));
         }
     }
 
@@ -693,19 +1230,35 @@ pub fn start(
         let _ = tokio::signal::ctrl_c().await;
     }));
 
-    RUNTIME_MANAGER
-        .lock()
-        .unwrap()
-        .insert(INSTANCE_ID, runtime_manager);
+    rt_id_guard.register(runtime_manager.clone());
+
+    // Outlives the other runners, the callers waiting for the runtime to stop
+    // get their answers through it.
+    #[cfg(feature = "api")]
+    let api_task = rt.spawn(futures::future::join_all(api_runners));
 
-    log::trace!("added runtime {}", &INSTANCE_ID);
+    log::trace!("added runtime {}", &rt_id);
 
     rt.block_on(futures::future::select_all(tasks));
 
+    // Sessions are still running on the runtime, let the lookups they wait
+    // on finish rather than cutting them halfway.
+    let grace = Duration::from_millis(*option::DNS_SHUTDOWN_GRACE_MS);
+    if pending_dns.count() > 0 && !rt.block_on(pending_dns.wait(grace)) {
+        log::debug!(
+            "{} dns queries still pending after {:?}",
+            pending_dns.count(),
+            grace
+        );
+    }
+
+    #[cfg(feature = "stat")]
+    rt.block_on(runtime_manager.save_usage());
+
     #[cfg(all(feature = "inbound-tun", any(target_os = "macos")))]
     {
         // if !network_changed.load(Ordering::Relaxed) {
-        //     log::trace!("runtime {} quit as untouched os route", &INSTANCE_ID);
+        //     log::trace!("runtime {} quit as untouched os route", &rt_id);
         //     sys::post_tun_completion_setup(&net_info);
         // }
         let net_info = net_info.lock().unwrap();
@@ -724,15 +1277,17 @@ pub fn start(
             ..
         } = &net
         {
-            if ip != "172.7.0.2" {
-                sys::post_tun_completion_setup(&net);
+            if ip != &inbound_manager.tun_device().address {
+                if let Err(e) = sys::post_tun_completion_setup(&net, inbound_manager.tun_device()) {
+                    log::error!("restoring the routes failed: {}", e);
+                }
             }
         }
     }
     #[cfg(all(feature = "inbound-tun", any(target_os = "linux",)))]
     {
         if !network_changed.load(Ordering::Relaxed) {
-            log::trace!("runtime {} quit as untouched os route", &INSTANCE_ID);
+            log::trace!("runtime {} quit as untouched os route", &rt_id);
             let net_info = net_info.lock().unwrap();
             let net = sys::NetInfo {
                 default_ipv4_gateway: net_info.default_ipv4_gateway.clone(),
@@ -749,8 +1304,12 @@ pub fn start(
                 ..
             } = &net
             {
-                if ip != "172.7.0.2" {
-                    sys::post_tun_completion_setup(&net);
+                if ip != &inbound_manager.tun_device().address {
+                    if let Err(e) =
+                        sys::post_tun_completion_setup(&net, inbound_manager.tun_device())
+                    {
+                        log::error!("restoring the routes failed: {}", e);
+                    }
                 }
             }
         }
@@ -760,11 +1319,22 @@ pub fn start(
 
     drop(inbound_manager);
 
-    RUNTIME_MANAGER.lock().unwrap().remove(&INSTANCE_ID);
+    drop(rt_id_guard);
+
+    runtime_manager.set_stopped();
+    // The API server shuts down gracefully on it, i.e. once the pending
+    // responses have been written out.
+    #[cfg(feature = "api")]
+    if rt
+        .block_on(tokio::time::timeout(API_SHUTDOWN_TIMEOUT, api_task))
+        .is_err()
+    {
+        log::debug!("api server still busy after {:?}", API_SHUTDOWN_TIMEOUT);
+    }
 
     rt.shutdown_background();
 
-    log::trace!("removed runtime {}", &INSTANCE_ID);
+    log::trace!("removed runtime {}", &rt_id);
 
     Ok(())
 }
@@ -774,38 +1344,145 @@ mod tests {
     use super::*;
     use std::thread;
 
-    #[test]
-    fn test_restart() {
-        let conf = r#"
+    fn socks_conf(port: u16) -> String {
+        format!(
+            r#"
 [General]
 loglevel = trace
 dns-server = 1.1.1.1
 socks-interface = 127.0.0.1
-socks-port = 1080
+socks-port = {}
 # tun = auto
 
 [Proxy]
 Direct = direct
-"#;
+"#,
+            port
+        )
+    }
+
+    fn start_options(conf: String) -> StartOptions {
+        StartOptions {
+            config: Config::Str(conf),
+            api_listen: None,
+            #[cfg(target_os = "android")]
+            socket_protect_path: None,
+        }
+    }
 
+    fn wait_stopped(key: RuntimeId) {
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(30);
+        while is_running(key) {
+            assert!(
+                std::time::Instant::now() < deadline,
+                "runtime {} didn't stop",
+                key
+            );
+            thread::sleep(std::time::Duration::from_millis(100));
+        }
+    }
+
+    #[test]
+    fn test_restart() {
         for _i in 1..3 {
             thread::spawn(move || {
-                let opts = StartOptions {
-                    config: Config::Str(conf.to_string()),
-                    #[cfg(feature = "auto-reload")]
-                    auto_reload: false,
-                    runtime_opt: RuntimeOption::SingleThread,
-                };
-                start(0, opts).unwrap();
+                start(0, start_options(socks_conf(1080))).unwrap();
             });
             thread::sleep(std::time::Duration::from_secs(2));
             assert!(shutdown(0));
-            loop {
-                thread::sleep(std::time::Duration::from_secs(1));
-                if !is_running(0) {
-                    break;
-                }
-            }
+            wait_stopped(0);
         }
     }
+
+    #[test]
+    fn test_multi_instance() {
+        let handles: Vec<_> = [(10, 1090), (11, 1091)]
+            .into_iter()
+            .map(|(key, port)| thread::spawn(move || start(key, start_options(socks_conf(port)))))
+            .collect();
+        thread::sleep(std::time::Duration::from_secs(2));
+        assert!(is_running(10) && is_running(11));
+        // The id is taken while running.
+        assert!(start(10, start_options(socks_conf(1092))).is_err());
+
+        assert!(shutdown(10));
+        wait_stopped(10);
+        assert!(is_running(11));
+        assert!(std::net::TcpStream::connect("127.0.0.1:1091").is_ok());
+        assert!(shutdown(11));
+        wait_stopped(11);
+        for h in handles {
+            assert!(h.join().unwrap().is_ok());
+        }
+    }
+
+    #[test]
+    fn test_reload_keeps_config() {
+        let path = std::env::temp_dir().join(format!("ostrich-reload-{}.conf", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        std::fs::write(&path, socks_conf(1094)).unwrap();
+        let opts = StartOptions {
+            config: Config::File(path.clone()),
+            ..start_options(String::new())
+        };
+        let handle = thread::spawn(move || start(22, opts));
+        thread::sleep(std::time::Duration::from_secs(2));
+        let m = RUNTIME_MANAGER.lock().unwrap().get(&22).cloned().unwrap();
+
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            // Reject would be added if the config was applied halfway.
+            std::fs::write(
+                &path,
+                "[Proxy]\nReject = reject\nDirect = direct, fwmark=0xzz\n",
+            )
+            .unwrap();
+            assert!(m.reload().await.is_err());
+            let outbound_manager = m.outbound_manager.read().await;
+            assert!(outbound_manager.get("Direct").is_some());
+            assert!(outbound_manager.get("Reject").is_none());
+        });
+        // Still running on the old config.
+        assert!(std::net::TcpStream::connect("127.0.0.1:1094").is_ok());
+
+        assert!(shutdown(22));
+        wait_stopped(22);
+        assert!(handle.join().unwrap().is_ok());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_tun_setup_error() {
+        let conf = "[General]\ntun = tun0\ntun-dns = 1.1.1\n[Proxy]\nDirect = direct\n";
+        match start(21, start_options(conf.to_string())) {
+            Err(Error::TunSetup(e)) => assert!(e.to_string().contains("invalid tun dns")),
+            res => panic!("unexpected {:?}", res.map(|_| ())),
+        }
+        assert!(!is_running(21));
+    }
+
+    #[cfg(feature = "api")]
+    #[test]
+    fn test_api_shutdown_wait() {
+        use std::io::{Read, Write};
+        let handle = thread::spawn(|| {
+            let mut opts = start_options(socks_conf(1093));
+            opts.api_listen = Some("127.0.0.1:9993".to_string());
+            start(20, opts)
+        });
+        thread::sleep(std::time::Duration::from_secs(2));
+        let mut stream = std::net::TcpStream::connect("127.0.0.1:9993").unwrap();
+        stream
+            .write_all(b"POST /shutdown?wait=true HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        // Answered once stopped, the connection is closed after it.
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(handle.join().unwrap().is_ok());
+        assert!(!is_running(20));
+    }
 }
@@ -69,6 +69,9 @@ lazy_static! {
 
 #[cfg(feature = "stat")]
 lazy_static! {
+    /// Counts the bytes of the sessions, per outbound and per client. Off by
+    /// default, the stat APIs and STAT_USAGE_FILE report nothing new without
+    /// it.
     pub static ref ENABLE_STATS: bool = get_env_var_or("ENABLE_STATS", false);
 }
 
@@ -89,6 +92,12 @@ lazy_static! {
         get_env_var_or("HTTP_FORWARDED_HEADER", "X-Forwarded-For".to_string())
     };
 
+    /// Fails loading configs with keys the parser doesn't recognize, rather
+    /// than skipping them with a warning.
+    pub static ref CONFIG_STRICT: bool = {
+        get_env_var_or("CONFIG_STRICT", false)
+    };
+
     pub static ref LOG_CONSOLE_OUT: bool = {
         get_env_var_or("LOG_CONSOLE_OUT", false)
     };
@@ -97,10 +106,26 @@ lazy_static! {
         get_env_var_or("LOG_NO_COLOR", false)
     };
 
+    /// Number of rotated log files kept, older ones are removed.
+    pub static ref LOG_ROTATION_KEEP: usize = {
+        get_env_var_or("LOG_ROTATION_KEEP", 7)
+    };
+
     pub static ref DOMAIN_SNIFFING: bool = {
         get_env_var_or("DOMAIN_SNIFFING", false)
     };
 
+    /// How long to wait for the ClientHello when sniffing a stream, in
+    /// milliseconds. The stream is routed by its address alone afterwards.
+    pub static ref SNIFF_TIMEOUT_MS: u64 = {
+        get_env_var_or("SNIFF_TIMEOUT_MS", 200)
+    };
+
+    /// The most bytes to read when sniffing a stream.
+    pub static ref SNIFF_MAX_BYTES: usize = {
+        get_env_var_or("SNIFF_MAX_BYTES", 4096)
+    };
+
     /// Uplink timeout after downlink EOF.
     pub static ref TCP_UPLINK_TIMEOUT: u64 = {
         get_env_var_or("TCP_UPLINK_TIMEOUT", 10)
@@ -111,6 +136,19 @@ lazy_static! {
         get_env_var_or("TCP_DOWNLINK_TIMEOUT", 10)
     };
 
+    /// Seconds a read on either side of a TCP link may wait for data before
+    /// the link is torn down, 0 disables it. Note that a link is also torn
+    /// down if one direction stays silent while the other one is busy.
+    pub static ref TCP_READ_TIMEOUT: u64 = {
+        get_env_var_or("TCP_READ_TIMEOUT", 0)
+    };
+
+    /// Seconds a write on either side of a TCP link may be blocked before the
+    /// link is torn down, 0 disables it.
+    pub static ref TCP_WRITE_TIMEOUT: u64 = {
+        get_env_var_or("TCP_WRITE_TIMEOUT", 0)
+    };
+
     /// Buffer size for uplink and downlink connections, in KB.
     pub static ref LINK_BUFFER_SIZE: usize = {
         get_env_var_or("LINK_BUFFER_SIZE", 2)
@@ -124,10 +162,40 @@ lazy_static! {
         get_env_var_or("NETSTACK_UDP_UPLINK_CHANNEL_SIZE", 256)
     };
 
+    /// Maximum packets read from TUN queued for the netstack, per worker.
+    pub static ref TUN_QUEUE_SIZE: usize = {
+        get_env_var_or("TUN_QUEUE_SIZE", 512)
+    };
+
+    /// Tasks feeding the packets read from TUN to the netstack, and tasks
+    /// handling the UDP packets from the netstack, the flows are spread among
+    /// them by source.
+    pub static ref TUN_WORKERS: usize = {
+        get_env_var_or("TUN_WORKERS", 1)
+    };
+
+    /// Drops packets read from TUN while the queue is full, instead of
+    /// pausing reading until the netstack catches up.
+    pub static ref TUN_QUEUE_DROP: bool = {
+        get_env_var_or("TUN_QUEUE_DROP", false)
+    };
+
     pub static ref UDP_UPLINK_CHANNEL_SIZE: usize = {
         get_env_var_or("UDP_UPLINK_CHANNEL_SIZE", 256)
     };
 
+    /// Maximum bytes of uplink UDP datagrams queued for a session, in KB,
+    /// datagrams beyond are dropped.
+    pub static ref UDP_SESSION_BUFFER_LIMIT: usize = {
+        get_env_var_or("UDP_SESSION_BUFFER_LIMIT", 1024)
+    };
+
+    /// Maximum bytes of uplink UDP datagrams queued for all sessions of a
+    /// runtime, in KB, datagrams beyond are dropped.
+    pub static ref UDP_BUFFER_LIMIT: usize = {
+        get_env_var_or("UDP_BUFFER_LIMIT", 16 * 1024)
+    };
+
     pub static ref UDP_DOWNLINK_CHANNEL_SIZE: usize = {
         get_env_var_or("UDP_DOWNLINK_CHANNEL_SIZE", 256)
     };
@@ -147,6 +215,12 @@ lazy_static! {
         get_env_var_or("OUTBOUND_DIAL_TIMEOUT", 4)
     };
 
+    /// Seconds connecting to the server of an outbound may take in all,
+    /// unless the outbound sets its own connect timeout.
+    pub static ref OUTBOUND_CONNECT_TIMEOUT: u64 = {
+        get_env_var_or("OUTBOUND_CONNECT_TIMEOUT", 4)
+    };
+
     pub static ref OUTBOUND_DIAL_ORDER: crate::proxy::DialOrder = {
         match get_env_var_or("OUTBOUND_DIAL_ORDER", "ordered".to_string()).as_str() {
             "random" => crate::proxy::DialOrder::Random,
@@ -160,6 +234,25 @@ lazy_static! {
         get_env_var_or("OUTBOUND_DIAL_CONCURRENCY", 1)
     };
 
+    /// Times resolving the address to dial is retried if it fails, e.g. right
+    /// after the network changes, before the connection is given up.
+    pub static ref OUTBOUND_RESOLVE_RETRIES: usize = {
+        get_env_var_or("OUTBOUND_RESOLVE_RETRIES", 0)
+    };
+
+    /// Milliseconds before the first retry of resolving the address to dial,
+    /// each further retry waits as much longer.
+    pub static ref OUTBOUND_RESOLVE_RETRY_DELAY: u64 = {
+        get_env_var_or("OUTBOUND_RESOLVE_RETRY_DELAY", 200)
+    };
+
+    /// Binds direct connections to the source address of the original client,
+    /// for transparent proxying on Linux, requires CAP_NET_ADMIN and policy
+    /// routing sending the replies back to this host.
+    pub static ref OUTBOUND_TRANSPARENT: bool = {
+        get_env_var_or("OUTBOUND_TRANSPARENT", false)
+    };
+
     pub static ref ASSET_LOCATION: String = {
         get_env_var_or_else("ASSET_LOCATION", || {
             let mut file = std::env::current_exe().unwrap();
@@ -172,6 +265,9 @@ lazy_static! {
         get_env_var_or("CACHE_LOCATION", "".to_string())
     };
 
+    /// Addresses to serve the API on, comma separated, each a socket address
+    /// or `unix:` followed by the path of a Unix domain socket. Runtimes
+    /// started with `StartOptions::api_listen` set use that instead.
     pub static ref API_LISTEN: String = {
         get_env_var_or("API_LISTEN", "".to_string())
     };
@@ -207,6 +303,18 @@ lazy_static! {
         outbound_binds
     };
 
+    /// Sets the firewall mark (SO_MARK) on outbound sockets, in decimal or hex
+    /// with the `0x` prefix, for policy routing the proxy's own traffic on
+    /// Linux, requires CAP_NET_ADMIN. Outbounds with their own fwmark use that
+    /// one instead, 0 leaves the sockets unmarked. A malformed mark fails
+    /// loading the outbounds.
+    pub static ref OUTBOUND_FWMARK: Result<u32, String> = {
+        match env::var("OUTBOUND_FWMARK") {
+            Ok(mark) => crate::common::net::parse_fwmark(&mark).map_err(|e| e.to_string()),
+            Err(_) => Ok(0),
+        }
+    };
+
     /// Sets the RPC service endpoint for protecting outbound sockets on Android to
     /// avoid infinite loop. The `path` is treated as a Unix domain socket endpoint.
     /// The RPC service simply listens for incoming connections, reads an int32 on
@@ -216,6 +324,25 @@ lazy_static! {
         get_env_var_or("SOCKET_PROTECT_PATH", "".to_string())
     };
 
+    /// Sets the Unix domain socket of a broker supplying outbound sockets, for
+    /// sandboxed processes not allowed to open sockets themselves. See
+    /// `proxy::broker` for the protocol.
+    pub static ref SOCKET_BROKER_PATH: String = {
+        get_env_var_or("SOCKET_BROKER_PATH", "".to_string())
+    };
+
+    /// Sets the Unix domain socket of a privileged helper making the route,
+    /// address and forwarding changes for the TUN inbound, so that the main
+    /// process doesn't need the privileges itself. See `sys` for the protocol.
+    pub static ref ROUTE_HELPER_PATH: String = {
+        get_env_var_or("ROUTE_HELPER_PATH", "".to_string())
+    };
+
+    /// Timeout for the route helper to reply to a request, in seconds.
+    pub static ref ROUTE_HELPER_TIMEOUT: u64 = {
+        get_env_var_or("ROUTE_HELPER_TIMEOUT", 10)
+    };
+
     pub static ref SOCKET_PROTECT_SERVER: Option<SocketAddr> = {
         get_env_var_or("SOCKET_PROTECT_SERVER", "".to_string()).parse().ok()
     };
@@ -247,6 +374,71 @@ lazy_static! {
         get_env_var_or("DNS_TIMEOUT", 4)
     };
 
+    /// Milliseconds shutdown waits for the DNS queries in flight to be
+    /// answered, 0 to not wait.
+    pub static ref DNS_SHUTDOWN_GRACE_MS: u64 = {
+        get_env_var_or("DNS_SHUTDOWN_GRACE_MS", 1000)
+    };
+
+    /// Seconds without any activity before tearing down the UDP sessions and
+    /// the pooled outbound connections, 0 disables it. New sessions set them
+    /// up again. The TUN device stays up, nothing would bring it back on the
+    /// next packet otherwise.
+    pub static ref IDLE_TIMEOUT: u64 = {
+        get_env_var_or("IDLE_TIMEOUT", 0)
+    };
+
+    /// Seconds a pooled connection, of the http2 and ssh outbounds and of the
+    /// DoH client, is handed out to new sessions, 0 keeps it until it's closed. An expired connection is replaced by a
+    /// new one and closed once the sessions on it are done, even if idle.
+    pub static ref POOLED_CONN_MAX_LIFETIME: u64 = {
+        get_env_var_or("POOLED_CONN_MAX_LIFETIME", 0)
+    };
+
+    /// Drops UDP to port 443 so that QUIC fails and the clients fall back to
+    /// HTTP/2 over TCP. Other UDP is not affected.
+    pub static ref BLOCK_QUIC: bool = {
+        get_env_var_or("BLOCK_QUIC", false)
+    };
+
+    /// Releases the UDP sessions and the DNS cache when the default network
+    /// changes, so nothing set up over the old network is reused. Streams in
+    /// flight are left to fail and be retried by the clients.
+    pub static ref DRAIN_ON_NETWORK_CHANGE: bool = {
+        get_env_var_or("DRAIN_ON_NETWORK_CHANGE", false)
+    };
+
+    /// The UDP payload size advertised through EDNS in DNS queries, 0 disables EDNS.
+    pub static ref DNS_EDNS_BUFFER_SIZE: u16 = {
+        get_env_var_or("DNS_EDNS_BUFFER_SIZE", 1232)
+    };
+
+    /// Whether to retry over TCP when a UDP DNS answer is truncated.
+    pub static ref DNS_TCP_FALLBACK: bool = {
+        get_env_var_or("DNS_TCP_FALLBACK", true)
+    };
+
+    /// The plain HTTP server an outbound throughput test downloads from.
+    pub static ref THROUGHPUT_TEST_HOST: String = {
+        get_env_var_or("THROUGHPUT_TEST_HOST", "speedtest.tele2.net".to_string())
+    };
+
+    /// The file downloaded by the throughput test, large enough to last
+    /// for the whole test.
+    pub static ref THROUGHPUT_TEST_PATH: String = {
+        get_env_var_or("THROUGHPUT_TEST_PATH", "/1GB.zip".to_string())
+    };
+
+    /// The throughput test stops after receiving this many bytes.
+    pub static ref THROUGHPUT_TEST_BYTES: u64 = {
+        get_env_var_or("THROUGHPUT_TEST_BYTES", 32 * 1024 * 1024)
+    };
+
+    /// The throughput test stops after this many seconds.
+    pub static ref THROUGHPUT_TEST_TIMEOUT: u64 = {
+        get_env_var_or("THROUGHPUT_TEST_TIMEOUT", 10)
+    };
+
     pub static ref DEFAULT_TUN_NAME: String = {
         get_env_var_or("DEFAULT_TUN_NAME", "utun233".to_string())
     };
@@ -263,15 +455,42 @@ lazy_static! {
         get_env_var_or("DEFAULT_TUN_IPV4_MASK", "255.255.255.0".to_string())
     };
 
+    /// File keeping the bytes through each outbound across restarts, only
+    /// with the stat feature and ENABLE_STATS, empty to not keep them.
+    pub static ref STAT_USAGE_FILE: String = {
+        get_env_var_or("STAT_USAGE_FILE", "".to_string())
+    };
+
+    /// Seconds between the saves of STAT_USAGE_FILE, it is also saved on
+    /// shutdown.
+    pub static ref STAT_SAVE_INTERVAL: u64 = {
+        get_env_var_or("STAT_SAVE_INTERVAL", 300)
+    };
+
+    /// Seconds to wait at startup for a default route and an address on the
+    /// default interface before setting up the TUN routes, 0 to not wait.
+    pub static ref WAIT_FOR_NETWORK: u64 = {
+        get_env_var_or("WAIT_FOR_NETWORK", 0)
+    };
+
+    /// IPv6 address of the TUN device, a unique local address by default.
     pub static ref DEFAULT_TUN_IPV6_ADDR: String = {
-        get_env_var_or("DEFAULT_TUN_IPV6_ADDR", "2001:2::2".to_string())
+        get_env_var_or("DEFAULT_TUN_IPV6_ADDR", "fd07::2".to_string())
     };
 
+    /// IPv6 gateway of the TUN device, the IPv6 default route points to it
+    /// when ENABLE_IPV6 is set.
     pub static ref DEFAULT_TUN_IPV6_GW: String = {
-        get_env_var_or("DEFAULT_TUN_IPV6_GW", "2001:2::1".to_string())
+        get_env_var_or("DEFAULT_TUN_IPV6_GW", "fd07::1".to_string())
     };
 
     pub static ref DEFAULT_TUN_IPV6_PREFIXLEN: i32 = {
         get_env_var_or("DEFAULT_TUN_IPV6_PREFIXLEN", 64)
     };
+
+    /// The /96 prefix of the fake IPv6 addresses answered to AAAA queries
+    /// under TUN when ENABLE_IPV6 is set, a unique local one by default.
+    pub static ref FAKE_DNS_IPV6_PREFIX: String = {
+        get_env_var_or("FAKE_DNS_IPV6_PREFIX", "fd07:fa6e::".to_string())
+    };
 }
@@ -0,0 +1,156 @@
+//! Obtains outbound sockets from a broker instead of opening them, for
+//! sandboxed processes not allowed to create sockets.
+//!
+//! The broker is either a callback registered with `set_socket_broker`, or
+//! the Unix domain socket at `SOCKET_BROKER_PATH`. For the latter, a request
+//! is a line of `<network> <address> [<fwmark>]`, e.g. `tcp 1.2.3.4:443` or
+//! `udp 1.2.3.4:53 16`, the broker replies with a status byte, 0 on success,
+//! along with the file descriptor passed as SCM_RIGHTS. For `tcp` the
+//! descriptor must be connected to the address, for `udp` it must be a socket
+//! of the same address family. Either must carry the firewall mark (SO_MARK)
+//! if one is requested, the process can't set it without CAP_NET_ADMIN.
+
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::os::unix::io::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+
+use parking_lot::RwLock;
+use tokio::net::{TcpStream, UdpSocket};
+
+use crate::{option, session::Network};
+
+type BrokerCallback = dyn Fn(Network, SocketAddr, Option<u32>) -> io::Result<RawFd> + Send + Sync;
+
+static CALLBACK: RwLock<Option<Arc<BrokerCallback>>> = RwLock::new(None);
+
+/// Registers a callback supplying the file descriptors of outbound sockets,
+/// marked with the firewall mark if one is given, it's called from a blocking
+/// thread.
+pub fn set_socket_broker<F>(f: F)
+where
+    F: Fn(Network, SocketAddr, Option<u32>) -> io::Result<RawFd> + Send + Sync + 'static,
+{
+    *CALLBACK.write() = Some(Arc::new(f));
+}
+
+pub fn unset_socket_broker() {
+    *CALLBACK.write() = None;
+}
+
+pub fn is_enabled() -> bool {
+    CALLBACK.read().is_some() || !option::SOCKET_BROKER_PATH.is_empty()
+}
+
+fn recv_fd(stream: &UnixStream) -> io::Result<(u8, Option<RawFd>)> {
+    let mut status = [0u8; 1];
+    let mut iov = libc::iovec {
+        iov_base: status.as_mut_ptr() as *mut libc::c_void,
+        iov_len: status.len(),
+    };
+    // Aligned for cmsghdr and large enough for a single descriptor.
+    let mut control = [0u64; 4];
+    let mut msg: libc::msghdr = unsafe { std::mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = std::mem::size_of_val(&control) as _;
+    let n = unsafe { libc::recvmsg(stream.as_raw_fd(), &mut msg, 0) };
+    if n < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    if n == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "socket broker closed the connection",
+        ));
+    }
+    let mut fd = None;
+    unsafe {
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        if !cmsg.is_null()
+            && (*cmsg).cmsg_level == libc::SOL_SOCKET
+            && (*cmsg).cmsg_type == libc::SCM_RIGHTS
+        {
+            fd = Some(std::ptr::read_unaligned(
+                libc::CMSG_DATA(cmsg) as *const RawFd
+            ));
+        }
+    }
+    Ok((status[0], fd))
+}
+
+fn request_line(network: Network, addr: SocketAddr, fwmark: Option<u32>) -> String {
+    match fwmark {
+        Some(fwmark) => format!("{} {} {}\n", network, addr, fwmark),
+        None => format!("{} {}\n", network, addr),
+    }
+}
+
+fn request_from_path(
+    path: &str,
+    network: Network,
+    addr: SocketAddr,
+    fwmark: Option<u32>,
+) -> io::Result<RawFd> {
+    let mut stream = UnixStream::connect(path)?;
+    stream.write_all(request_line(network, addr, fwmark).as_bytes())?;
+    match recv_fd(&stream)? {
+        (0, Some(fd)) => Ok(fd),
+        (0, None) => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "socket broker replied without a file descriptor",
+        )),
+        (status, fd) => {
+            if let Some(fd) = fd {
+                unsafe { libc::close(fd) };
+            }
+            Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("socket broker failed {} {}: {}", network, addr, status),
+            ))
+        }
+    }
+}
+
+async fn request(network: Network, addr: SocketAddr, fwmark: Option<u32>) -> io::Result<RawFd> {
+    let callback = CALLBACK.read().clone();
+    tokio::task::spawn_blocking(move || match callback {
+        Some(cb) => cb(network, addr, fwmark),
+        None => request_from_path(&option::SOCKET_BROKER_PATH, network, addr, fwmark),
+    })
+    .await
+    .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?
+}
+
+/// Gets a TCP stream connected to the address from the broker.
+pub async fn connect(addr: SocketAddr, fwmark: Option<u32>) -> io::Result<TcpStream> {
+    let fd = request(Network::Tcp, addr, fwmark).await?;
+    let stream = unsafe { std::net::TcpStream::from_raw_fd(fd) };
+    stream.set_nonblocking(true)?;
+    TcpStream::from_std(stream)
+}
+
+/// Gets a UDP socket of the address family of the indicator from the broker.
+pub async fn udp_socket(indicator: &SocketAddr, fwmark: Option<u32>) -> io::Result<UdpSocket> {
+    let fd = request(Network::Udp, *indicator, fwmark).await?;
+    let socket = unsafe { std::net::UdpSocket::from_raw_fd(fd) };
+    socket.set_nonblocking(true)?;
+    UdpSocket::from_std(socket)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_line() {
+        let addr: SocketAddr = "1.2.3.4:443".parse().unwrap();
+        assert_eq!(request_line(Network::Tcp, addr, None), "tcp 1.2.3.4:443\n");
+        assert_eq!(
+            request_line(Network::Udp, addr, Some(0x10)),
+            "udp 1.2.3.4:443 16\n"
+        );
+    }
+}
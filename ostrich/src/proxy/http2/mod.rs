@@ -0,0 +1,6 @@
+#[cfg(feature = "outbound-http2")]
+pub mod outbound;
+
+mod stream;
+
+pub use stream::{h2_err, H2Stream};
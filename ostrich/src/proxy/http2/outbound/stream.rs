@@ -0,0 +1,389 @@
+use std::convert::TryFrom;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use base64::Engine;
+use bytes::Bytes;
+use futures::TryFutureExt;
+use h2::client::SendRequest;
+use tokio::sync::Mutex;
+use tokio::time::timeout;
+use tokio_rustls::{
+    rustls::{ClientConfig, ServerName},
+    TlsConnector,
+};
+
+use crate::{
+    app::SyncDnsClient,
+    common::tls::make_default_config,
+    config::Http2OutboundSettings,
+    option,
+    proxy::{
+        http2::{h2_err, H2Stream},
+        *,
+    },
+    session::{Session, SocksAddr},
+};
+
+// Path of extended CONNECT requests if not set, as in the connect-tcp draft.
+const DEFAULT_PATH: &str = "/.well-known/masque/tcp/{target_host}/{target_port}/";
+
+// A connection to the server, shared by the sessions until it's closed.
+struct Conn {
+    send_request: SendRequest<Bytes>,
+    closed: Arc<AtomicBool>,
+}
+
+/// Tunnels each session through a CONNECT stream, all the streams are
+/// multiplexed over a single TLS connection to the server.
+pub struct Handler {
+    address: String,
+    port: u16,
+    server_name: String,
+    // Value of the Proxy-Authorization header, if any.
+    auth: Option<String>,
+    // Sends extended CONNECT requests with this protocol if not empty.
+    protocol: String,
+    path: String,
+    dns_client: SyncDnsClient,
+    dscp: Option<u8>,
+    fwmark: Option<u32>,
+    connect_timeout: Option<Duration>,
+    connect_retries: u32,
+    tls_config: Arc<ClientConfig>,
+    conn: Arc<Mutex<Option<Conn>>>,
+}
+
+// Stops handing out an expired connection and drops its pooled handle, the
+// connection closes when the sessions on it are done.
+async fn retire(pool: Weak<Mutex<Option<Conn>>>, closed: &Arc<AtomicBool>) {
+    closed.store(true, Ordering::Relaxed);
+    if let Some(pool) = pool.upgrade() {
+        let mut conn = pool.lock().await;
+        if matches!(conn.as_ref(), Some(c) if Arc::ptr_eq(&c.closed, closed)) {
+            conn.take();
+        }
+    }
+}
+
+impl Handler {
+    pub fn new(
+        settings: &Http2OutboundSettings,
+        dns_client: SyncDnsClient,
+        dscp: Option<u8>,
+        fwmark: Option<u32>,
+        connect_timeout: Option<Duration>,
+        connect_retries: u32,
+    ) -> Result<Self> {
+        if settings.address.is_empty() || settings.port == 0 || settings.port > u16::MAX as u32 {
+            return Err(anyhow!("invalid server address"));
+        }
+        let server_name = if !settings.server_name.is_empty() {
+            settings.server_name.clone()
+        } else {
+            settings.address.clone()
+        };
+        ServerName::try_from(server_name.as_str())
+            .map_err(|_| anyhow!("invalid server name {}", &server_name))?;
+        let auth = if !settings.username.is_empty() {
+            let credentials = format!("{}:{}", &settings.username, &settings.password);
+            Some(format!(
+                "Basic {}",
+                base64::engine::general_purpose::STANDARD.encode(credentials)
+            ))
+        } else {
+            None
+        };
+        let path = if !settings.path.is_empty() {
+            settings.path.clone()
+        } else {
+            DEFAULT_PATH.to_string()
+        };
+        Ok(Handler {
+            address: settings.address.clone(),
+            port: settings.port as u16,
+            server_name,
+            auth,
+            protocol: settings.protocol.clone(),
+            path,
+            dns_client,
+            dscp,
+            fwmark,
+            connect_timeout,
+            connect_retries,
+            tls_config: make_default_config(&["h2"])?,
+            conn: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    // Whether there's a connection to share which is not closed or expired.
+    fn is_connected(conn: &Option<Conn>) -> bool {
+        matches!(conn, Some(c) if !c.closed.load(Ordering::Relaxed))
+    }
+
+    // Returns a handle to the current connection, otherwise connects a new
+    // one over the stream dialed to the server, or dials one if not given.
+    // The pool is not locked while connecting, so a stalled server doesn't
+    // hold up sessions once another connection is made.
+    async fn send_request(&self, stream: Option<AnyStream>) -> io::Result<SendRequest<Bytes>> {
+        {
+            let conn = self.conn.lock().await;
+            if Self::is_connected(&conn) {
+                return Ok(conn.as_ref().unwrap().send_request.clone());
+            }
+        }
+        let name = ServerName::try_from(self.server_name.as_str()).map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid dnsname: {:?}", &self.server_name),
+            )
+        })?;
+        // Each dialing attempt is bounded by the connect timeout, the handshake
+        // by the dial timeout.
+        let stream = match stream {
+            Some(s) => s,
+            None => {
+                new_retried_tcp_stream(
+                    self.dns_client.clone(),
+                    &self.address,
+                    &self.port,
+                    self.dscp,
+                    self.fwmark,
+                    self.connect_timeout,
+                    self.connect_retries,
+                )
+                .await?
+            }
+        };
+        let handshake = async {
+            let stream = TlsConnector::from(self.tls_config.clone())
+                .connect(name, stream)
+                .await?;
+            h2::client::handshake(stream).map_err(h2_err).await
+        };
+        let (send_request, connection) = timeout(
+            Duration::from_secs(*option::OUTBOUND_DIAL_TIMEOUT),
+            handshake,
+        )
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "http2 handshake timed out"))??;
+        let closed = Arc::new(AtomicBool::new(false));
+        let closed2 = closed.clone();
+        let server = format!("{}:{}", &self.address, self.port);
+        let pool = Arc::downgrade(&self.conn);
+        let max_lifetime = pooled_conn_max_lifetime();
+        tokio::spawn(async move {
+            let mut connection = Box::pin(connection);
+            let res = match max_lifetime {
+                Some(lifetime) => match timeout(lifetime, &mut connection).await {
+                    Ok(res) => res,
+                    Err(_) => {
+                        log::debug!("http2 connection to {} expired", server);
+                        retire(pool, &closed2).await;
+                        connection.await
+                    }
+                },
+                None => connection.await,
+            };
+            if let Err(e) = res {
+                log::debug!("http2 connection to {} failed: {}", server, e);
+            }
+            closed2.store(true, Ordering::Relaxed);
+        });
+        // Takes the place of any connection made meanwhile, which closes once
+        // the sessions on it are done.
+        *self.conn.lock().await = Some(Conn {
+            send_request: send_request.clone(),
+            closed,
+        });
+        Ok(send_request)
+    }
+
+    fn request(&self, destination: &SocksAddr) -> io::Result<http::Request<()>> {
+        let mut builder = http::Request::builder().method(http::Method::CONNECT);
+        builder = if self.protocol.is_empty() {
+            builder.uri(destination.to_string())
+        } else {
+            // Colons of IPv6 addresses are not allowed in a path segment.
+            let path = self
+                .path
+                .replace("{target_host}", &destination.host().replace(':', "%3A"))
+                .replace("{target_port}", &destination.port().to_string());
+            builder
+                .uri(format!(
+                    "https://{}:{}{}",
+                    &self.server_name, self.port, path
+                ))
+                .extension(h2::ext::Protocol::from(self.protocol.as_str()))
+        };
+        if let Some(auth) = &self.auth {
+            builder = builder.header(http::header::PROXY_AUTHORIZATION, auth);
+        }
+        builder
+            .body(())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+    }
+}
+
+#[async_trait]
+impl OutboundStreamHandler for Handler {
+    fn connect_addr(&self) -> OutboundConnect {
+        // Sessions share the connection, the server is only dialed if there's
+        // none to share.
+        match self.conn.try_lock() {
+            Ok(conn) if Self::is_connected(&conn) => OutboundConnect::Unknown,
+            _ => OutboundConnect::Proxy(Network::Tcp, self.address.clone(), self.port),
+        }
+    }
+
+    async fn handle<'a>(
+        &'a self,
+        sess: &'a Session,
+        stream: Option<AnyStream>,
+    ) -> io::Result<AnyStream> {
+        let req = self.request(&sess.destination)?;
+        let mut send_request = self
+            .send_request(stream)
+            .await?
+            .ready()
+            .map_err(h2_err)
+            .await?;
+        if !self.protocol.is_empty() && !send_request.is_extended_connect_protocol_enabled() {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "server doesn't support extended CONNECT",
+            ));
+        }
+        let (response, send) = send_request.send_request(req, false).map_err(h2_err)?;
+        let response = response.map_err(h2_err).await?;
+        if !response.status().is_success() {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("http2 CONNECT failed: {}", response.status()),
+            ));
+        }
+        Ok(Box::new(H2Stream::new(send, response.into_body())))
+    }
+
+    async fn reset(&self) {
+        if let Some(conn) = self.conn.lock().await.take() {
+            conn.closed.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A connection to a server accepting streams without answering them, and
+    // its closed flag.
+    async fn conn() -> (Conn, Arc<AtomicBool>) {
+        let (client, server) = tokio::io::duplex(1024);
+        tokio::spawn(async move {
+            let mut conn = h2::server::handshake(server).await.unwrap();
+            while conn.accept().await.is_some() {}
+        });
+        let (send_request, connection) = h2::client::handshake(client).await.unwrap();
+        tokio::spawn(connection);
+        let closed = Arc::new(AtomicBool::new(false));
+        let conn = Conn {
+            send_request,
+            closed: closed.clone(),
+        };
+        (conn, closed)
+    }
+
+    #[test]
+    fn test_request() {
+        let mut settings = Http2OutboundSettings::new();
+        settings.address = "proxy.example.com".to_string();
+        settings.port = 443;
+        settings.username = "user".to_string();
+        settings.password = "pass".to_string();
+        let dns_client = crate::app::dns_client::tests::dns_client();
+        let h = Handler::new(&settings, dns_client.clone(), None, None, None, 0).unwrap();
+
+        let req = h
+            .request(&SocksAddr::Domain("example.com".to_string(), 80))
+            .unwrap();
+        assert_eq!(req.uri(), "example.com:80");
+        assert_eq!(
+            req.headers()[http::header::PROXY_AUTHORIZATION],
+            "Basic dXNlcjpwYXNz"
+        );
+
+        settings.protocol = "connect-tcp".to_string();
+        let h = Handler::new(&settings, dns_client, None, None, None, 0).unwrap();
+        let req = h
+            .request(&SocksAddr::from((
+                "::1".parse::<std::net::IpAddr>().unwrap(),
+                22,
+            )))
+            .unwrap();
+        assert_eq!(
+            req.uri(),
+            "https://proxy.example.com:443/.well-known/masque/tcp/%3A%3A1/22/"
+        );
+        assert!(req.extensions().get::<h2::ext::Protocol>().is_some());
+    }
+
+    #[test]
+    fn test_connect_addr() {
+        let mut settings = Http2OutboundSettings::new();
+        settings.address = "proxy.example.com".to_string();
+        settings.port = 443;
+        let dns_client = crate::app::dns_client::tests::dns_client();
+        let h = Handler::new(&settings, dns_client, None, None, None, 0).unwrap();
+        assert!(matches!(
+            h.connect_addr(),
+            OutboundConnect::Proxy(Network::Tcp, a, 443) if a == "proxy.example.com"
+        ));
+
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let (conn, closed) = conn().await;
+            *h.conn.lock().await = Some(conn);
+            // The open connection is shared rather than dialing the server.
+            assert!(matches!(h.connect_addr(), OutboundConnect::Unknown));
+            closed.store(true, Ordering::Relaxed);
+            assert!(matches!(h.connect_addr(), OutboundConnect::Proxy(..)));
+
+            let (conn, closed) = conn().await;
+            *h.conn.lock().await = Some(conn);
+            assert!(matches!(h.connect_addr(), OutboundConnect::Unknown));
+            // Reset connections aren't handed out again.
+            h.reset().await;
+            assert!(closed.load(Ordering::Relaxed));
+            assert!(h.conn.lock().await.is_none());
+            assert!(matches!(h.connect_addr(), OutboundConnect::Proxy(..)));
+        });
+    }
+
+    #[test]
+    fn test_retire() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let (conn, closed) = conn().await;
+            let pool = Arc::new(Mutex::new(Some(conn)));
+
+            // Another connection has taken its place.
+            retire(Arc::downgrade(&pool), &Arc::new(AtomicBool::new(false))).await;
+            assert!(pool.lock().await.is_some());
+
+            retire(Arc::downgrade(&pool), &closed).await;
+            assert!(closed.load(Ordering::Relaxed));
+            assert!(pool.lock().await.is_none());
+        });
+    }
+}
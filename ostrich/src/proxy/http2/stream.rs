@@ -0,0 +1,155 @@
+use std::cmp::min;
+use std::io;
+use std::pin::Pin;
+
+use bytes::Bytes;
+use futures::{
+    ready,
+    task::{Context, Poll},
+};
+use h2::{RecvStream, SendStream};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+pub fn h2_err(e: h2::Error) -> io::Error {
+    if e.is_io() {
+        e.into_io().unwrap()
+    } else {
+        io::Error::new(io::ErrorKind::Other, e)
+    }
+}
+
+/// A tunnel over the request and response bodies of a CONNECT stream.
+pub struct H2Stream {
+    send: SendStream<Bytes>,
+    recv: RecvStream,
+    // Received but not read yet.
+    buf: Bytes,
+}
+
+impl H2Stream {
+    pub fn new(send: SendStream<Bytes>, recv: RecvStream) -> Self {
+        H2Stream {
+            send,
+            recv,
+            buf: Bytes::new(),
+        }
+    }
+}
+
+impl AsyncRead for H2Stream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut ReadBuf,
+    ) -> Poll<io::Result<()>> {
+        while self.buf.is_empty() {
+            match ready!(self.recv.poll_data(cx)) {
+                Some(Ok(data)) => {
+                    // Lets the peer send as much again.
+                    let _ = self.recv.flow_control().release_capacity(data.len());
+                    self.buf = data;
+                }
+                // Closing the stream with NO_ERROR is a normal close.
+                Some(Err(e)) if e.reason() == Some(h2::Reason::NO_ERROR) => {
+                    return Poll::Ready(Ok(()))
+                }
+                Some(Err(e)) => return Poll::Ready(Err(h2_err(e))),
+                None => return Poll::Ready(Ok(())),
+            }
+        }
+        let n = min(buf.remaining(), self.buf.len());
+        buf.put_slice(&self.buf.split_to(n));
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for H2Stream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        // Sends no more than the flow control window allows.
+        self.send.reserve_capacity(buf.len());
+        let n = match ready!(self.send.poll_capacity(cx)) {
+            Some(Ok(n)) => n,
+            Some(Err(e)) => return Poll::Ready(Err(h2_err(e))),
+            None => return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into())),
+        };
+        self.send
+            .send_data(Bytes::copy_from_slice(&buf[..n]), false)
+            .map_err(h2_err)?;
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, _cx: &mut Context) -> Poll<io::Result<()>> {
+        // Fails only if the stream is already closed.
+        let _ = self.send.send_data(Bytes::new(), true);
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn test_h2_stream() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let (a, b) = tokio::io::duplex(64 * 1024);
+
+            // Echoes the body of the first CONNECT request back.
+            tokio::spawn(async move {
+                let mut conn = h2::server::handshake(b).await.unwrap();
+                let (req, mut respond) = conn.accept().await.unwrap().unwrap();
+                tokio::spawn(async move { while conn.accept().await.is_some() {} });
+                assert_eq!(req.method(), http::Method::CONNECT);
+                let resp = http::Response::builder().status(200).body(()).unwrap();
+                let send = respond.send_response(resp, false).unwrap();
+                let mut stream = H2Stream::new(send, req.into_body());
+                let mut data = Vec::new();
+                stream.read_to_end(&mut data).await.unwrap();
+                stream.write_all(&data).await.unwrap();
+                stream.shutdown().await.unwrap();
+            });
+
+            let (send_request, conn) = h2::client::handshake(a).await.unwrap();
+            tokio::spawn(conn);
+            let mut send_request = send_request.ready().await.unwrap();
+            let req = http::Request::builder()
+                .method(http::Method::CONNECT)
+                .uri("example.com:443")
+                .body(())
+                .unwrap();
+            let (resp, send) = send_request.send_request(req, false).unwrap();
+            let resp = resp.await.unwrap();
+            assert_eq!(resp.status(), http::StatusCode::OK);
+
+            let stream = H2Stream::new(send, resp.into_body());
+            // More than the initial window of 64KB.
+            let data = vec![7u8; 200 * 1024];
+            let (mut r, mut w) = tokio::io::split(stream);
+            let writer = tokio::spawn(async move {
+                w.write_all(&data).await.unwrap();
+                w.shutdown().await.unwrap();
+            });
+            let mut echoed = Vec::new();
+            r.read_to_end(&mut echoed).await.unwrap();
+            writer.await.unwrap();
+            assert_eq!(echoed.len(), 200 * 1024);
+            assert!(echoed.iter().all(|b| *b == 7));
+        });
+    }
+}
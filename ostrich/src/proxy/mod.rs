@@ -1,12 +1,12 @@
 use std::io;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
-use std::sync::Arc;
-use std::time::Duration;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use async_trait::async_trait;
 use futures::future::select_ok;
 use futures::stream::Stream;
-use futures::TryFutureExt;
 use log::*;
 use socket2::SockRef;
 use thiserror::Error;
@@ -35,10 +35,21 @@ pub mod datagram;
 pub mod inbound;
 pub mod outbound;
 
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+pub mod broker;
+
 #[cfg(feature = "outbound-direct")]
 pub mod direct;
+#[cfg(feature = "outbound-http2")]
+pub mod http2;
+#[cfg(feature = "outbound-shadowsocks")]
+pub mod shadowsocks;
 #[cfg(any(feature = "inbound-socks", feature = "outbound-socks"))]
 pub mod socks;
+#[cfg(feature = "outbound-ssh")]
+pub mod ssh;
+#[cfg(feature = "outbound-static")]
+pub mod static_;
 #[cfg(any(feature = "inbound-trojan", feature = "outbound-trojan"))]
 pub mod trojan;
 #[cfg(all(
@@ -164,6 +175,37 @@ impl TcpListener {
         })
     }
 
+    /// Binds to the address and accepts connections arriving on the
+    /// interface only.
+    pub async fn bind_interface(addr: &SocketAddr, iface: &str) -> io::Result<Self> {
+        Self::bind_now(addr, iface, false)
+    }
+
+    /// Binds to the address without waiting, accepting connections arriving
+    /// on the interface only if it's not empty. With `reuse_port`, other
+    /// sockets may bind the same address, the kernel balances connections
+    /// among them. Must be called within the context of a runtime.
+    pub fn bind_now(addr: &SocketAddr, iface: &str, reuse_port: bool) -> io::Result<Self> {
+        let socket = match addr {
+            SocketAddr::V4(..) => TcpSocket::new_v4()?,
+            SocketAddr::V6(..) => TcpSocket::new_v6()?,
+        };
+        // On Windows it lets the socket take over a port in use, the bind
+        // must fail instead, as std does.
+        #[cfg(not(target_os = "windows"))]
+        socket.set_reuseaddr(true)?;
+        if reuse_port {
+            set_reuse_port(&socket)?;
+        }
+        if !iface.is_empty() {
+            bind_to_interface(&socket, iface, addr)?;
+        }
+        socket.bind(addr.to_owned())?;
+        Ok(Self {
+            inner: socket.listen(1024)?,
+        })
+    }
+
     pub async fn accept(&self) -> io::Result<(TcpStream, SocketAddr)> {
         let (stream, addr) = self.inner.accept().await?;
         apply_socket_opts(&stream)?;
@@ -172,6 +214,201 @@ impl TcpListener {
     }
 }
 
+// Binds a UDP socket receiving datagrams arriving on the interface only.
+pub async fn bind_udp_socket_interface(addr: &SocketAddr, iface: &str) -> io::Result<UdpSocket> {
+    bind_udp_socket_now(addr, iface, false)
+}
+
+// Binds a UDP socket without waiting, receiving datagrams arriving on the
+// interface only if it's not empty. See `TcpListener::bind_now` for
+// `reuse_port`. Must be called within the context of a runtime.
+pub fn bind_udp_socket_now(
+    addr: &SocketAddr,
+    iface: &str,
+    reuse_port: bool,
+) -> io::Result<UdpSocket> {
+    use socket2::{Domain, Socket, Type};
+    let socket = match addr {
+        SocketAddr::V4(..) => Socket::new(Domain::IPV4, Type::DGRAM, None)?,
+        SocketAddr::V6(..) => Socket::new(Domain::IPV6, Type::DGRAM, None)?,
+    };
+    socket.set_nonblocking(true)?;
+    if reuse_port {
+        set_reuse_port(&socket)?;
+    }
+    if !iface.is_empty() {
+        bind_to_interface(&socket, iface, addr)?;
+    }
+    socket.bind(&addr.to_owned().into())?;
+    UdpSocket::from_std(socket.into())
+}
+
+// Lets other sockets bind the same address and port.
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+fn set_reuse_port<T: AsRawFd>(socket: &T) -> io::Result<()> {
+    let enable: libc::c_int = 1;
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_REUSEPORT,
+            &enable as *const _ as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if ret == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "linux")))]
+fn set_reuse_port<T>(_socket: &T) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "SO_REUSEPORT is not supported on this platform",
+    ))
+}
+
+// Restricts the socket to send and receive packets on the interface only.
+fn bind_to_interface<T: BindSocket>(
+    socket: &T,
+    iface: &str,
+    indicator: &SocketAddr,
+) -> io::Result<()> {
+    #[cfg(target_os = "macos")]
+    unsafe {
+        let ifa = std::ffi::CString::new(iface.as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let ifidx: libc::c_uint = libc::if_nametoindex(ifa.as_ptr());
+        if ifidx == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let ret = match indicator {
+            SocketAddr::V4(..) => libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::IPPROTO_IP,
+                libc::IP_BOUND_IF,
+                &ifidx as *const _ as *const libc::c_void,
+                std::mem::size_of::<libc::c_uint>() as libc::socklen_t,
+            ),
+            SocketAddr::V6(..) => libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::IPPROTO_IPV6,
+                libc::IPV6_BOUND_IF,
+                &ifidx as *const _ as *const libc::c_void,
+                std::mem::size_of::<libc::c_uint>() as libc::socklen_t,
+            ),
+        };
+        if ret == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+    #[cfg(target_os = "linux")]
+    unsafe {
+        let _ = indicator;
+        let ifa = std::ffi::CString::new(iface.as_bytes())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        let ret = libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_BINDTODEVICE,
+            ifa.as_ptr() as *const libc::c_void,
+            ifa.as_bytes().len() as libc::socklen_t,
+        );
+        if ret == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+    #[cfg(not(any(target_os = "macos", target_os = "linux")))]
+    {
+        let _ = (socket, iface, indicator);
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            "binding to interface is not supported on this platform",
+        ))
+    }
+}
+
+// Whether the interface exists, is up and running, i.e. has a carrier,
+// assumed so where it can't be told.
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+pub(crate) fn interface_is_up(iface: &str) -> bool {
+    let mut addrs: *mut libc::ifaddrs = std::ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut addrs) } != 0 {
+        return true;
+    }
+    let mut up = false;
+    let mut cur = addrs;
+    while !cur.is_null() {
+        let ifa = unsafe { &*cur };
+        let name = unsafe { std::ffi::CStr::from_ptr(ifa.ifa_name) };
+        if name.to_bytes() == iface.as_bytes() {
+            let flags = (libc::IFF_UP | libc::IFF_RUNNING) as libc::c_uint;
+            up = ifa.ifa_flags & flags == flags;
+            break;
+        }
+        cur = ifa.ifa_next;
+    }
+    unsafe { libc::freeifaddrs(addrs) };
+    up
+}
+
+// Windows lists the adapters with an address, which a wintun adapter loses
+// when its tun2socks exits.
+#[cfg(target_os = "windows")]
+pub(crate) fn interface_is_up(iface: &str) -> bool {
+    match local_ip_address::list_afinet_netifas() {
+        Ok(ifs) => ifs.iter().any(|(name, _)| name == iface),
+        Err(_) => true,
+    }
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "linux", target_os = "windows")))]
+pub(crate) fn interface_is_up(_iface: &str) -> bool {
+    true
+}
+
+// Whether the outbound interfaces were found down, to warn only once until
+// they're back.
+static OUTBOUND_INTERFACE_DOWN: AtomicBool = AtomicBool::new(false);
+
+// Whether connections fail rather than take the default route while the
+// outbound interfaces are down, as the routing policy fails closed.
+static OUTBOUND_INTERFACE_FAIL_CLOSED: AtomicBool = AtomicBool::new(false);
+
+// How long the state of an outbound interface is trusted before it's looked
+// up again, so that binds don't enumerate the interfaces every time.
+const INTERFACE_STATE_TTL: Duration = Duration::from_secs(1);
+
+// The outbound interfaces looked up, with when and whether they were up.
+static INTERFACE_STATES: Mutex<Vec<(String, Instant, bool)>> = Mutex::new(Vec::new());
+
+/// Follows the fail-closed routing policy for connections through outbound
+/// interfaces found down, which would otherwise take the default route.
+pub fn set_interface_fail_closed(fail_closed: bool) {
+    OUTBOUND_INTERFACE_FAIL_CLOSED.store(fail_closed, Ordering::Relaxed);
+}
+
+// Like `interface_is_up`, the state is cached for INTERFACE_STATE_TTL.
+fn outbound_interface_is_up(iface: &str) -> bool {
+    let now = Instant::now();
+    let mut states = INTERFACE_STATES.lock().unwrap();
+    if let Some(state) = states.iter_mut().find(|(name, ..)| name == iface) {
+        if now.duration_since(state.1) < INTERFACE_STATE_TTL {
+            return state.2;
+        }
+        state.1 = now;
+        state.2 = interface_is_up(iface);
+        return state.2;
+    }
+    let up = interface_is_up(iface);
+    states.push((iface.to_string(), now, up));
+    up
+}
+
 async fn bind_socket<T: BindSocket>(socket: &T, indicator: &SocketAddr) -> io::Result<()> {
     match indicator.ip() {
         IpAddr::V4(v4) if v4.is_loopback() => {
@@ -187,65 +424,23 @@ async fn bind_socket<T: BindSocket>(socket: &T, indicator: &SocketAddr) -> io::R
         _ => {}
     }
     let mut last_err = None;
+    let mut down = Vec::new();
     for bind in option::OUTBOUND_BINDS.iter() {
         match bind {
             OutboundBind::Interface(iface) => {
-                #[cfg(target_os = "macos")]
-                unsafe {
-                    let ifa = CString::new(iface.as_bytes()).unwrap();
-                    let ifidx: libc::c_uint = libc::if_nametoindex(ifa.as_ptr());
-                    if ifidx == 0 {
-                        last_err = Some(io::Error::last_os_error());
-                        continue;
-                    }
-
-                    let ret = match indicator {
-                        SocketAddr::V4(..) => libc::setsockopt(
-                            socket.as_raw_fd(),
-                            libc::IPPROTO_IP,
-                            libc::IP_BOUND_IF,
-                            &ifidx as *const _ as *const libc::c_void,
-                            std::mem::size_of::<libc::c_uint>() as libc::socklen_t,
-                        ),
-                        SocketAddr::V6(..) => libc::setsockopt(
-                            socket.as_raw_fd(),
-                            libc::IPPROTO_IPV6,
-                            libc::IPV6_BOUND_IF,
-                            &ifidx as *const _ as *const libc::c_void,
-                            std::mem::size_of::<libc::c_uint>() as libc::socklen_t,
-                        ),
-                    };
-                    if ret == -1 {
-                        last_err = Some(io::Error::last_os_error());
-                        continue;
-                    }
-                    trace!("socket bind {}", iface);
-                    return Ok(());
+                if !outbound_interface_is_up(iface) {
+                    down.push(iface.as_str());
+                    continue;
                 }
-                #[cfg(target_os = "linux")]
-                unsafe {
-                    let ifa = CString::new(iface.as_bytes()).unwrap();
-                    let ret = libc::setsockopt(
-                        socket.as_raw_fd(),
-                        libc::SOL_SOCKET,
-                        libc::SO_BINDTODEVICE,
-                        ifa.as_ptr() as *const libc::c_void,
-                        ifa.as_bytes().len() as libc::socklen_t,
-                    );
-                    if ret == -1 {
-                        last_err = Some(io::Error::last_os_error());
-                        continue;
-                    }
-                    trace!("socket bind {}", iface);
-                    return Ok(());
+                if let Err(e) = bind_to_interface(socket, iface, indicator) {
+                    last_err = Some(e);
+                    continue;
                 }
-                #[cfg(not(any(target_os = "macos", target_os = "linux")))]
-                {
-                    return Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        "binding to interface is not supported on this platform",
-                    ));
+                if OUTBOUND_INTERFACE_DOWN.swap(false, Ordering::Relaxed) {
+                    info!("outbound interface {} is up", iface);
                 }
+                trace!("socket bind {}", iface);
+                return Ok(());
             }
             OutboundBind::Ip(addr) => {
                 if (addr.is_ipv4() && indicator.is_ipv4())
@@ -261,6 +456,22 @@ async fn bind_socket<T: BindSocket>(socket: &T, indicator: &SocketAddr) -> io::R
             }
         }
     }
+    if !down.is_empty() && last_err.is_none() {
+        let ifaces = down.join(", ");
+        if OUTBOUND_INTERFACE_FAIL_CLOSED.load(Ordering::Relaxed) {
+            return Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                format!("outbound interface {} is down or gone", ifaces),
+            ));
+        }
+        if !OUTBOUND_INTERFACE_DOWN.swap(true, Ordering::Relaxed) {
+            warn!(
+                "outbound interface {} is down or gone, connecting through the default route",
+                ifaces
+            );
+        }
+        return Ok(());
+    }
     Err(last_err.unwrap_or_else(|| {
         io::Error::new(
             io::ErrorKind::InvalidInput,
@@ -269,9 +480,179 @@ async fn bind_socket<T: BindSocket>(socket: &T, indicator: &SocketAddr) -> io::R
     }))
 }
 
+// Whether to bind direct connections to the client's source address.
+fn transparent_source(source: &SocketAddr) -> Option<SocketAddr> {
+    if cfg!(target_os = "linux")
+        && *option::OUTBOUND_TRANSPARENT
+        && !source.ip().is_unspecified()
+        && !source.ip().is_loopback()
+    {
+        Some(SocketAddr::new(source.ip(), 0))
+    } else {
+        None
+    }
+}
+
+// Binds the socket to a non-local address with IP_TRANSPARENT, so that
+// packets leave with the address of the original client.
+fn bind_transparent<T: BindSocket>(socket: &T, source: &SocketAddr) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    unsafe {
+        let enable: libc::c_int = 1;
+        let ret = match source {
+            SocketAddr::V4(..) => libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_IP,
+                libc::IP_TRANSPARENT,
+                &enable as *const _ as *const libc::c_void,
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            ),
+            SocketAddr::V6(..) => libc::setsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_IPV6,
+                libc::IPV6_TRANSPARENT,
+                &enable as *const _ as *const libc::c_void,
+                std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+            ),
+        };
+        if ret == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        socket.bind(source)?;
+        trace!("socket bind transparent {}", source);
+        Ok(())
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (socket, source);
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            "transparent binding is not supported on this platform",
+        ))
+    }
+}
+
+// Marks the packets of the socket with the DSCP value, in the upper 6 bits of
+// the ToS / Traffic Class byte.
+#[cfg(any(target_os = "macos", target_os = "linux"))]
+fn set_dscp<S: AsRawFd>(socket: &S, indicator: &SocketAddr, dscp: u8) -> io::Result<()> {
+    let tos: libc::c_int = (dscp as libc::c_int) << 2;
+    let set = |level, name| unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            level,
+            name,
+            &tos as *const _ as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    let ret = match indicator {
+        SocketAddr::V4(..) => set(libc::IPPROTO_IP, libc::IP_TOS),
+        SocketAddr::V6(..) => {
+            // Dual-stack sockets may also send to IPv4-mapped addresses, not
+            // all platforms allow it so it's best effort.
+            let _ = set(libc::IPPROTO_IP, libc::IP_TOS);
+            set(libc::IPPROTO_IPV6, libc::IPV6_TCLASS)
+        }
+    };
+    if ret == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    trace!("socket dscp {}", dscp);
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "macos", target_os = "linux")))]
+fn set_dscp<S>(_socket: &S, _indicator: &SocketAddr, _dscp: u8) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "dscp marking is not supported on this platform",
+    ))
+}
+
+/// How long a pooled connection is handed out to new sessions, if limited.
+pub fn pooled_conn_max_lifetime() -> Option<Duration> {
+    Some(*option::POOLED_CONN_MAX_LIFETIME)
+        .filter(|secs| *secs > 0)
+        .map(Duration::from_secs)
+}
+
+// The firewall mark of the outbound if set, otherwise the global one if any.
+fn outbound_fwmark(fwmark: Option<u32>) -> Option<u32> {
+    fwmark.or(option::OUTBOUND_FWMARK
+        .as_ref()
+        .ok()
+        .copied()
+        .filter(|m| *m != 0))
+}
+
+// Sets the firewall mark (SO_MARK) of the socket, for policy routing with
+// `ip rule add fwmark`, requires CAP_NET_ADMIN.
+#[cfg(target_os = "linux")]
+fn set_fwmark<S: AsRawFd>(socket: &S, fwmark: u32) -> io::Result<()> {
+    let ret = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_MARK,
+            &fwmark as *const _ as *const libc::c_void,
+            std::mem::size_of::<u32>() as libc::socklen_t,
+        )
+    };
+    if ret == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    trace!("socket fwmark {:#x}", fwmark);
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn set_fwmark<S>(_socket: &S, _fwmark: u32) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "fwmark is not supported on this platform",
+    ))
+}
+
+// New UDP socket bound to the client's source address, falls back to a
+// regular socket if transparent binding doesn't apply.
+pub async fn new_direct_udp_socket(
+    source: &SocketAddr,
+    fwmark: Option<u32>,
+) -> io::Result<UdpSocket> {
+    use socket2::{Domain, Socket, Type};
+    let bind_addr = match transparent_source(source) {
+        Some(a) => a,
+        None => return new_marked_udp_socket(source, fwmark).await,
+    };
+    let socket = match bind_addr {
+        SocketAddr::V4(..) => Socket::new(Domain::IPV4, Type::DGRAM, None)?,
+        SocketAddr::V6(..) => Socket::new(Domain::IPV6, Type::DGRAM, None)?,
+    };
+    socket.set_nonblocking(true)?;
+    bind_transparent(&socket, &bind_addr)?;
+    if let Some(fwmark) = outbound_fwmark(fwmark) {
+        set_fwmark(&socket, fwmark)?;
+    }
+    UdpSocket::from_std(socket.into())
+}
+
 // New UDP socket.
 pub async fn new_udp_socket(indicator: &SocketAddr) -> io::Result<UdpSocket> {
+    new_marked_udp_socket(indicator, None).await
+}
+
+// New UDP socket marked with the firewall mark if any, the broker sets the
+// mark of the sockets it supplies.
+pub async fn new_marked_udp_socket(
+    indicator: &SocketAddr,
+    fwmark: Option<u32>,
+) -> io::Result<UdpSocket> {
     use socket2::{Domain, Socket, Type};
+    #[cfg(any(target_os = "macos", target_os = "linux"))]
+    if broker::is_enabled() {
+        return broker::udp_socket(indicator, outbound_fwmark(fwmark)).await;
+    }
     let socket = if *option::ENABLE_IPV6 {
         // Dual-stack socket.
         // FIXME Windows IPV6_V6ONLY?
@@ -292,6 +673,10 @@ pub async fn new_udp_socket(indicator: &SocketAddr) -> io::Result<UdpSocket> {
         bind_socket(&socket, indicator).await?;
     }
 
+    if let Some(fwmark) = outbound_fwmark(fwmark) {
+        set_fwmark(&socket, fwmark)?;
+    }
+
     #[cfg(target_os = "android")]
     protect_socket(socket.as_raw_fd()).await?;
 
@@ -329,13 +714,46 @@ pub enum DialOrder {
 }
 
 // A single TCP dial.
-async fn tcp_dial_task(dial_addr: SocketAddr) -> io::Result<DialResult> {
+async fn tcp_dial_task(
+    dial_addr: SocketAddr,
+    source: Option<SocketAddr>,
+    dscp: Option<u8>,
+    fwmark: Option<u32>,
+) -> io::Result<DialResult> {
+    // The socket arrives connected, too late to mark it, the broker does.
+    #[cfg(any(target_os = "macos", target_os = "linux"))]
+    if broker::is_enabled() {
+        let stream = timeout(
+            Duration::from_secs(*option::OUTBOUND_DIAL_TIMEOUT),
+            broker::connect(dial_addr, outbound_fwmark(fwmark)),
+        )
+        .await??;
+        apply_socket_opts(&stream)?;
+        trace!("tcp {} connected through the socket broker", &dial_addr);
+        return Ok(DialResult {
+            stream: Box::new(stream),
+            addr: dial_addr,
+        });
+    }
+
     let socket = match dial_addr {
         SocketAddr::V4(..) => TcpSocket::new_v4()?,
         SocketAddr::V6(..) => TcpSocket::new_v6()?,
     };
 
-    bind_socket(&socket, &dial_addr).await?;
+    match source {
+        // The client address can only be kept within the same family.
+        Some(src) if src.is_ipv4() == dial_addr.is_ipv4() => bind_transparent(&socket, &src)?,
+        _ => bind_socket(&socket, &dial_addr).await?,
+    }
+
+    if let Some(dscp) = dscp {
+        set_dscp(&socket, &dial_addr, dscp)?;
+    }
+
+    if let Some(fwmark) = outbound_fwmark(fwmark) {
+        set_fwmark(&socket, fwmark)?;
+    }
 
     #[cfg(target_os = "android")]
     protect_socket(socket.as_raw_fd()).await?;
@@ -368,20 +786,54 @@ pub async fn connect_stream_outbound(
     dns_client: SyncDnsClient,
     handler: &AnyOutboundHandler,
 ) -> io::Result<Option<AnyStream>> {
-    match handler.stream()?.connect_addr() {
+    let dial = match handler.stream()?.connect_addr() {
         OutboundConnect::Proxy(Network::Tcp, addr, port) => {
-            Ok(Some(new_tcp_stream(dns_client, &addr, &port).await?))
+            dial_with_retries(handler, || {
+                dial_servers(&dns_client, handler, (addr.clone(), port), || {
+                    Ok(handler.stream()?.connect_addr())
+                })
+            })
+            .await?
         }
-        OutboundConnect::Direct => Ok(Some(
-            new_tcp_stream(
-                dns_client,
-                &sess.destination.host(),
-                &sess.destination.port(),
-            )
-            .await?,
-        )),
-        _ => Ok(None),
+        OutboundConnect::Direct => {
+            let host = sess.destination.host();
+            let port = sess.destination.port();
+            dial_with_retries(handler, || {
+                dial_tcp(
+                    dns_client.clone(),
+                    &host,
+                    &port,
+                    true,
+                    transparent_source(&sess.source),
+                    handler.dscp(),
+                    handler.fwmark(),
+                )
+            })
+            .await?
+        }
+        _ => return Ok(None),
+    };
+    if sess.trace {
+        info!(
+            "{} -> {} dialed {} for [{}]",
+            &sess.source,
+            &sess.destination,
+            &dial.addr,
+            handler.tag()
+        );
+    }
+    let mut stream = dial.stream;
+    if let Some(version) = handler.proxy_protocol() {
+        // The original destination if known, otherwise where the client
+        // connected to.
+        let dst = match &sess.destination {
+            SocksAddr::Ip(a) => *a,
+            SocksAddr::Domain(..) => sess.local_addr,
+        };
+        let header = crate::common::proxy_protocol::encode_header(version, &sess.source, &dst);
+        tokio::io::AsyncWriteExt::write_all(&mut stream, &header).await?;
     }
+    Ok(Some(stream))
 }
 
 pub async fn connect_datagram_outbound(
@@ -392,18 +844,30 @@ pub async fn connect_datagram_outbound(
     match handler.datagram()?.connect_addr() {
         OutboundConnect::Proxy(network, addr, port) => match network {
             Network::Udp => {
-                let socket = new_udp_socket(&sess.source).await?;
+                let socket = new_marked_udp_socket(&sess.source, handler.fwmark()).await?;
+                if let Some(dscp) = handler.dscp() {
+                    set_dscp(&socket, &socket.local_addr()?, dscp)?;
+                }
                 Ok(Some(OutboundTransport::Datagram(Box::new(
                     SimpleOutboundDatagram::new(socket, None, dns_client.clone()),
                 ))))
             }
             Network::Tcp => {
-                let stream = new_tcp_stream(dns_client.clone(), &addr, &port).await?;
+                let stream = dial_with_retries(handler, || {
+                    dial_servers(&dns_client, handler, (addr.clone(), port), || {
+                        Ok(handler.datagram()?.connect_addr())
+                    })
+                })
+                .await?
+                .stream;
                 Ok(Some(OutboundTransport::Stream(stream)))
             }
         },
         OutboundConnect::Direct => {
-            let socket = new_udp_socket(&sess.source).await?;
+            let socket = new_direct_udp_socket(&sess.source, handler.fwmark()).await?;
+            if let Some(dscp) = handler.dscp() {
+                set_dscp(&socket, &socket.local_addr()?, dscp)?;
+            }
             let dest = match &sess.destination {
                 SocksAddr::Domain(domain, port) => {
                     Some(SocksAddr::Domain(domain.to_owned(), port.to_owned()))
@@ -423,20 +887,201 @@ struct DialResult {
     addr: SocketAddr,
 }
 
+// Dials the server for the handler, each attempt bounded by its connect
+// timeout, until connected or out of retries.
+async fn dial_with_retries<F, Fut>(handler: &AnyOutboundHandler, dial: F) -> io::Result<DialResult>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = io::Result<DialResult>>,
+{
+    retry_dial(
+        handler.tag(),
+        handler.connect_timeout(),
+        handler.connect_retries(),
+        dial,
+    )
+    .await
+}
+
+// Dials for `what`, each attempt bounded by the connect timeout, until
+// connected or out of retries.
+async fn retry_dial<F, Fut>(
+    what: &str,
+    connect_timeout: Option<Duration>,
+    connect_retries: u32,
+    mut dial: F,
+) -> io::Result<DialResult>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = io::Result<DialResult>>,
+{
+    let mut retries = 0;
+    loop {
+        let res = match connect_timeout {
+            Some(t) => timeout(t, dial()).await.unwrap_or_else(|_| {
+                Err(io::Error::new(io::ErrorKind::TimedOut, "connect timed out"))
+            }),
+            None => dial().await,
+        };
+        match res {
+            Err(e) if retries < connect_retries => {
+                retries += 1;
+                debug!("connecting for [{}] failed, retry {}: {}", what, retries, e);
+            }
+            res => return res,
+        }
+    }
+}
+
+// Dials the server of the handler, starting with the given one. Handlers with
+// more than one server hand out the next one each time they're asked, those
+// are tried in turn until one is connected or they're all tried.
+async fn dial_servers<F>(
+    dns_client: &SyncDnsClient,
+    handler: &AnyOutboundHandler,
+    server: (String, u16),
+    mut connect_addr: F,
+) -> io::Result<DialResult>
+where
+    F: FnMut() -> io::Result<OutboundConnect>,
+{
+    let (mut address, mut port) = server;
+    let mut tried = Vec::new();
+    loop {
+        let err = match dial_tcp(
+            dns_client.clone(),
+            &address,
+            &port,
+            false,
+            None,
+            handler.dscp(),
+            handler.fwmark(),
+        )
+        .await
+        {
+            Ok(dial) => return Ok(dial),
+            Err(e) => e,
+        };
+        tried.push((address, port));
+        match connect_addr()? {
+            OutboundConnect::Proxy(Network::Tcp, a, p) if !tried.contains(&(a.clone(), p)) => {
+                debug!(
+                    "dialing {}:{} for [{}] failed, trying {}:{}: {}",
+                    &tried.last().unwrap().0,
+                    tried.last().unwrap().1,
+                    handler.tag(),
+                    &a,
+                    p,
+                    err
+                );
+                address = a;
+                port = p;
+            }
+            _ => return Err(err),
+        }
+    }
+}
+
+// Resolves the address to dial, retrying failed lookups after a growing
+// delay as they may fail transiently.
+async fn resolve_with_retries<T, F, Fut>(
+    address: &str,
+    retries: usize,
+    delay: Duration,
+    mut resolve: F,
+) -> io::Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = anyhow::Result<T>>,
+{
+    let mut attempts = 0;
+    loop {
+        match resolve().await {
+            Ok(v) => return Ok(v),
+            Err(e) if attempts < retries => {
+                attempts += 1;
+                debug!("resolving {} failed, retry {}: {}", address, attempts, e);
+                tokio::time::sleep(delay * attempts as u32).await;
+            }
+            Err(e) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!("resolve address failed: {}", e),
+                ))
+            }
+        }
+    }
+}
+
+// Dials a TCP stream to an already resolved address.
+pub async fn dial_tcp_stream(dial_addr: &SocketAddr) -> io::Result<AnyStream> {
+    tcp_dial_task(dial_addr.to_owned(), None, None, None)
+        .await
+        .map(|r| r.stream)
+}
+
 // Dials a TCP stream.
 pub async fn new_tcp_stream(
     dns_client: SyncDnsClient,
     address: &String,
     port: &u16,
 ) -> io::Result<AnyStream> {
-    let mut resolver = Resolver::new(dns_client.clone(), address, port)
-        .map_err(|e| {
-            io::Error::new(
-                io::ErrorKind::Other,
-                format!("resolve address failed: {}", e),
-            )
-        })
-        .await?;
+    dial_tcp(dns_client, address, port, false, None, None, None)
+        .await
+        .map(|r| r.stream)
+}
+
+// Dials a TCP stream, marked with the DSCP value and firewall mark if any.
+pub async fn new_marked_tcp_stream(
+    dns_client: SyncDnsClient,
+    address: &String,
+    port: &u16,
+    dscp: Option<u8>,
+    fwmark: Option<u32>,
+) -> io::Result<AnyStream> {
+    dial_tcp(dns_client, address, port, false, None, dscp, fwmark)
+        .await
+        .map(|r| r.stream)
+}
+
+// Dials a TCP stream like `new_marked_tcp_stream`, each attempt bounded by the
+// connect timeout, until connected or out of retries. For handlers dialing
+// their server on their own, outside `connect_stream_outbound`.
+pub async fn new_retried_tcp_stream(
+    dns_client: SyncDnsClient,
+    address: &String,
+    port: &u16,
+    dscp: Option<u8>,
+    fwmark: Option<u32>,
+    connect_timeout: Option<Duration>,
+    connect_retries: u32,
+) -> io::Result<AnyStream> {
+    retry_dial(address, connect_timeout, connect_retries, || {
+        dial_tcp(dns_client.clone(), address, port, false, None, dscp, fwmark)
+    })
+    .await
+    .map(|r| r.stream)
+}
+
+// Dials a TCP stream, bound to the source address and marked with the DSCP
+// value and firewall mark if any. The address is the destination of a direct
+// connection rather than a proxy server if `destination` is set.
+async fn dial_tcp(
+    dns_client: SyncDnsClient,
+    address: &String,
+    port: &u16,
+    destination: bool,
+    source: Option<SocketAddr>,
+    dscp: Option<u8>,
+    fwmark: Option<u32>,
+) -> io::Result<DialResult> {
+    let mut resolver = resolve_with_retries(
+        address,
+        *option::OUTBOUND_RESOLVE_RETRIES,
+        Duration::from_millis(*option::OUTBOUND_RESOLVE_RETRY_DELAY),
+        || Resolver::new(dns_client.clone(), address, port, destination),
+    )
+    .await?;
 
     let mut last_err = None;
 
@@ -452,7 +1097,7 @@ pub async fn new_tcp_stream(
                     break; // break and execute tasks if there're any
                 }
             };
-            let t = tcp_dial_task(dial_addr);
+            let t = tcp_dial_task(dial_addr, source, dscp, fwmark);
             tasks.push(Box::pin(t));
         }
         if !tasks.is_empty() {
@@ -463,7 +1108,7 @@ pub async fn new_tcp_stream(
                         .await
                         .optimize_cache(address.to_owned(), v.0.addr.ip())
                         .await;
-                    return Ok(v.0.stream);
+                    return Ok(v.0);
                 }
                 Err(e) => {
                     last_err = Some(io::Error::new(
@@ -517,6 +1162,32 @@ pub type AnyStream = Box<dyn ProxyStream>;
 pub trait OutboundHandler: Tag + Color + Sync + Send + Unpin {
     fn stream(&self) -> io::Result<&AnyOutboundStreamHandler>;
     fn datagram(&self) -> io::Result<&AnyOutboundDatagramHandler>;
+
+    /// The PROXY protocol header to send ahead of the payload, if any.
+    fn proxy_protocol(&self) -> Option<crate::common::proxy_protocol::Version> {
+        None
+    }
+
+    /// The DSCP value to mark the packets of outgoing connections with, if any.
+    fn dscp(&self) -> Option<u8> {
+        None
+    }
+
+    /// The firewall mark to set on the sockets of outgoing connections, if
+    /// any, overrides the global one.
+    fn fwmark(&self) -> Option<u32> {
+        None
+    }
+
+    /// How long connecting to the server may take in all, if limited.
+    fn connect_timeout(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Times to connect again after failing.
+    fn connect_retries(&self) -> u32 {
+        0
+    }
 }
 
 pub type AnyOutboundHandler = Arc<dyn OutboundHandler>;
@@ -539,6 +1210,10 @@ pub trait OutboundStreamHandler<S = AnyStream>: Send + Sync + Unpin {
     /// Handles a session with the given stream. On success, returns a
     /// stream wraps the incoming stream.
     async fn handle<'a>(&'a self, sess: &'a Session, stream: Option<S>) -> io::Result<S>;
+
+    /// Drops the connection kept for later sessions, if any, the next session
+    /// makes a new one.
+    async fn reset(&self) {}
 }
 
 type AnyOutboundStreamHandler = Box<dyn OutboundStreamHandler>;
@@ -711,3 +1386,157 @@ pub enum InboundTransport<S, D> {
 }
 
 pub type AnyInboundTransport = InboundTransport<AnyStream, AnyInboundDatagram>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(any(target_os = "macos", target_os = "linux"))]
+    #[test]
+    fn test_bind_reuse_port() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+            let listener = TcpListener::bind_now(&addr, "", true).unwrap();
+            let addr = listener.inner.local_addr().unwrap();
+            assert!(TcpListener::bind_now(&addr, "", true).is_ok());
+            assert!(TcpListener::bind_now(&addr, "", false).is_err());
+
+            let addr: SocketAddr = "127.0.0.1:0".parse().unwrap();
+            let socket = bind_udp_socket_now(&addr, "", true).unwrap();
+            let addr = socket.local_addr().unwrap();
+            assert!(bind_udp_socket_now(&addr, "", true).is_ok());
+            assert!(bind_udp_socket_now(&addr, "", false).is_err());
+        });
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_set_fwmark() {
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        match set_fwmark(&socket, 0x10) {
+            // Without CAP_NET_ADMIN.
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => return,
+            r => r.unwrap(),
+        }
+        let mut mark: u32 = 0;
+        let mut len = std::mem::size_of::<u32>() as libc::socklen_t;
+        let ret = unsafe {
+            libc::getsockopt(
+                socket.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_MARK,
+                &mut mark as *mut _ as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        assert_eq!(ret, 0);
+        assert_eq!(mark, 0x10);
+        assert_eq!(outbound_fwmark(Some(0x20)), Some(0x20));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_interface_is_up() {
+        assert!(interface_is_up("lo"));
+        assert!(!interface_is_up("ostrich-nonexistent"));
+
+        assert!(outbound_interface_is_up("lo"));
+        assert!(!outbound_interface_is_up("ostrich-nonexistent"));
+        let states = INTERFACE_STATES.lock().unwrap();
+        assert!(states
+            .iter()
+            .any(|(name, _, up)| name == "ostrich-nonexistent" && !up));
+    }
+
+    // Hands out the servers in turn, passes the dialed stream through.
+    struct Servers(Vec<u16>, std::sync::atomic::AtomicUsize);
+
+    #[async_trait]
+    impl OutboundStreamHandler for Servers {
+        fn connect_addr(&self) -> OutboundConnect {
+            let i = self.1.fetch_add(1, Ordering::Relaxed) % self.0.len();
+            OutboundConnect::Proxy(Network::Tcp, "127.0.0.1".to_string(), self.0[i])
+        }
+
+        async fn handle<'a>(
+            &'a self,
+            _sess: &'a Session,
+            stream: Option<AnyStream>,
+        ) -> io::Result<AnyStream> {
+            stream.ok_or_else(|| io::Error::new(io::ErrorKind::Other, "not dialed"))
+        }
+    }
+
+    #[test]
+    fn test_dial_servers() {
+        let dns_client = crate::app::dns_client::tests::dns_client();
+        let handler = |ports: Vec<u16>| {
+            outbound::HandlerBuilder::default()
+                .tag("test".to_string())
+                .stream_handler(Box::new(Servers(ports, Default::default())))
+                .build()
+        };
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let up = listener.local_addr().unwrap().port();
+            // Nothing listens on it once the listener is dropped.
+            let down = std::net::TcpListener::bind("127.0.0.1:0")
+                .unwrap()
+                .local_addr()
+                .unwrap()
+                .port();
+            let sess = Session::default();
+
+            // The server down is skipped for the next one.
+            let h = handler(vec![down, up]);
+            let stream = connect_stream_outbound(&sess, dns_client.clone(), &h).await;
+            assert!(stream.unwrap().is_some());
+
+            let h = handler(vec![down]);
+            assert!(connect_stream_outbound(&sess, dns_client.clone(), &h)
+                .await
+                .is_err());
+        });
+    }
+
+    #[test]
+    fn test_resolve_with_retries() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let delay = Duration::from_millis(1);
+            // Fails twice before it resolves.
+            let fails_twice = || {
+                let mut calls = 0;
+                move || {
+                    calls += 1;
+                    let n = calls;
+                    async move {
+                        if n < 3 {
+                            Err(anyhow::anyhow!("no network"))
+                        } else {
+                            Ok(n)
+                        }
+                    }
+                }
+            };
+            assert!(resolve_with_retries("example.com", 1, delay, fails_twice())
+                .await
+                .is_err());
+            let n = resolve_with_retries("example.com", 2, delay, fails_twice())
+                .await
+                .unwrap();
+            assert_eq!(n, 3);
+        });
+    }
+}
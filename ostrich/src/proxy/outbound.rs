@@ -1,27 +1,45 @@
 use std::io;
 use std::sync::Arc;
+use std::time::Duration;
 
 use super::*;
+use crate::common::proxy_protocol;
 
 /// An outbound handler groups a TCP outbound handler and a UDP outbound
 /// handler.
 pub struct Handler {
     tag: String,
     color: colored::Color,
+    proxy_protocol: Option<proxy_protocol::Version>,
+    dscp: Option<u8>,
+    fwmark: Option<u32>,
+    connect_timeout: Option<Duration>,
+    connect_retries: u32,
     stream_handler: Option<AnyOutboundStreamHandler>,
     datagram_handler: Option<AnyOutboundDatagramHandler>,
 }
 
 impl Handler {
+    #[allow(clippy::too_many_arguments)]
     pub(self) fn new(
         tag: String,
         color: colored::Color,
+        proxy_protocol: Option<proxy_protocol::Version>,
+        dscp: Option<u8>,
+        fwmark: Option<u32>,
+        connect_timeout: Option<Duration>,
+        connect_retries: u32,
         stream_handler: Option<AnyOutboundStreamHandler>,
         datagram_handler: Option<AnyOutboundDatagramHandler>,
     ) -> Arc<Self> {
         Arc::new(Handler {
             tag,
             color,
+            proxy_protocol,
+            dscp,
+            fwmark,
+            connect_timeout,
+            connect_retries,
             stream_handler,
             datagram_handler,
         })
@@ -40,6 +58,26 @@ impl OutboundHandler for Handler {
             .as_ref()
             .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "no udp handler"))
     }
+
+    fn proxy_protocol(&self) -> Option<proxy_protocol::Version> {
+        self.proxy_protocol
+    }
+
+    fn dscp(&self) -> Option<u8> {
+        self.dscp
+    }
+
+    fn fwmark(&self) -> Option<u32> {
+        self.fwmark
+    }
+
+    fn connect_timeout(&self) -> Option<Duration> {
+        self.connect_timeout
+    }
+
+    fn connect_retries(&self) -> u32 {
+        self.connect_retries
+    }
 }
 
 impl Tag for Handler {
@@ -57,6 +95,11 @@ impl Color for Handler {
 pub struct HandlerBuilder {
     tag: String,
     color: colored::Color,
+    proxy_protocol: Option<proxy_protocol::Version>,
+    dscp: Option<u8>,
+    fwmark: Option<u32>,
+    connect_timeout: Option<Duration>,
+    connect_retries: u32,
     stream_handler: Option<AnyOutboundStreamHandler>,
     datagram_handler: Option<AnyOutboundDatagramHandler>,
 }
@@ -66,6 +109,11 @@ impl HandlerBuilder {
         Self {
             tag: "".to_string(),
             color: colored::Color::Magenta,
+            proxy_protocol: None,
+            dscp: None,
+            fwmark: None,
+            connect_timeout: None,
+            connect_retries: 0,
             stream_handler: None,
             datagram_handler: None,
         }
@@ -81,6 +129,31 @@ impl HandlerBuilder {
         self
     }
 
+    pub fn proxy_protocol(mut self, v: Option<proxy_protocol::Version>) -> Self {
+        self.proxy_protocol = v;
+        self
+    }
+
+    pub fn dscp(mut self, v: Option<u8>) -> Self {
+        self.dscp = v;
+        self
+    }
+
+    pub fn fwmark(mut self, v: Option<u32>) -> Self {
+        self.fwmark = v;
+        self
+    }
+
+    pub fn connect_timeout(mut self, v: Option<Duration>) -> Self {
+        self.connect_timeout = v;
+        self
+    }
+
+    pub fn connect_retries(mut self, v: u32) -> Self {
+        self.connect_retries = v;
+        self
+    }
+
     pub fn stream_handler(mut self, v: AnyOutboundStreamHandler) -> Self {
         self.stream_handler.replace(v);
         self
@@ -95,6 +168,11 @@ impl HandlerBuilder {
         Handler::new(
             self.tag,
             self.color,
+            self.proxy_protocol,
+            self.dscp,
+            self.fwmark,
+            self.connect_timeout,
+            self.connect_retries,
             self.stream_handler,
             self.datagram_handler,
         )
@@ -0,0 +1,60 @@
+use anyhow::{anyhow, Result};
+use hkdf::Hkdf;
+use md5::{Digest, Md5};
+use sha1::Sha1;
+
+use crate::common::crypto::NonceSequence;
+
+/// Derives the master key from the password the way OpenSSL's
+/// `EVP_BytesToKey` does with MD5 and a single iteration.
+pub fn kdf(password: &str, key_len: usize) -> Vec<u8> {
+    let mut key = Vec::with_capacity(key_len + 16);
+    let mut prev: Vec<u8> = Vec::new();
+    while key.len() < key_len {
+        let mut hasher = Md5::new();
+        hasher.update(&prev);
+        hasher.update(password.as_bytes());
+        prev = hasher.finalize().to_vec();
+        key.extend_from_slice(&prev);
+    }
+    key.truncate(key_len);
+    key
+}
+
+/// Derives the key of a session from the master key and the salt it starts
+/// with.
+pub fn subkey(key: &[u8], salt: &[u8]) -> Result<Vec<u8>> {
+    let mut okm = vec![0u8; key.len()];
+    Hkdf::<Sha1>::new(Some(salt), key)
+        .expand(b"ss-subkey", &mut okm)
+        .map_err(|e| anyhow!("derive subkey failed: {}", e))?;
+    Ok(okm)
+}
+
+/// A little-endian counter starting from zero, one nonce per seal or open.
+pub struct ShadowsocksNonce(Option<Vec<u8>>, usize);
+
+impl ShadowsocksNonce {
+    pub fn new(size: usize) -> Self {
+        ShadowsocksNonce(None, size)
+    }
+}
+
+impl NonceSequence for ShadowsocksNonce {
+    fn advance(&mut self) -> Result<Vec<u8>> {
+        match self.0.as_mut() {
+            None => {
+                self.0 = Some(vec![0u8; self.1]);
+            }
+            Some(nonce) => {
+                for x in nonce.iter_mut() {
+                    *x = x.wrapping_add(1);
+                    if *x != 0 {
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(self.0.clone().unwrap())
+    }
+}
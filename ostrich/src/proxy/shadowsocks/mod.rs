@@ -0,0 +1,7 @@
+pub mod crypto;
+pub mod shadow;
+
+#[cfg(feature = "outbound-shadowsocks")]
+pub mod outbound;
+
+pub use shadow::{ShadowCipher, ShadowedStream};
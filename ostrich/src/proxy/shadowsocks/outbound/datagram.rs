@@ -0,0 +1,107 @@
+use std::convert::TryFrom;
+use std::io;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use bytes::BytesMut;
+
+use crate::{
+    proxy::{shadowsocks::ShadowCipher, *},
+    session::{Session, SocksAddr, SocksAddrWireType},
+};
+
+pub struct Handler {
+    pub address: String,
+    pub port: u16,
+    pub cipher: Arc<ShadowCipher>,
+}
+
+#[async_trait]
+impl OutboundDatagramHandler for Handler {
+    fn connect_addr(&self) -> OutboundConnect {
+        OutboundConnect::Proxy(Network::Udp, self.address.clone(), self.port)
+    }
+
+    fn transport_type(&self) -> DatagramTransportType {
+        DatagramTransportType::Unreliable
+    }
+
+    async fn handle<'a>(
+        &'a self,
+        _sess: &'a Session,
+        transport: Option<AnyOutboundTransport>,
+    ) -> io::Result<AnyOutboundDatagram> {
+        let datagram = match transport {
+            Some(OutboundTransport::Datagram(datagram)) => datagram,
+            _ => return Err(io::Error::new(io::ErrorKind::Other, "invalid input")),
+        };
+        let server = SocksAddr::try_from((&self.address, self.port))?;
+        Ok(Box::new(Datagram {
+            datagram,
+            server,
+            cipher: self.cipher.clone(),
+        }))
+    }
+}
+
+pub struct Datagram {
+    datagram: AnyOutboundDatagram,
+    server: SocksAddr,
+    cipher: Arc<ShadowCipher>,
+}
+
+impl OutboundDatagram for Datagram {
+    fn split(
+        self: Box<Self>,
+    ) -> (
+        Box<dyn OutboundDatagramRecvHalf>,
+        Box<dyn OutboundDatagramSendHalf>,
+    ) {
+        let (r, s) = self.datagram.split();
+        (
+            Box::new(DatagramRecvHalf(r, self.cipher.clone())),
+            Box::new(DatagramSendHalf(s, self.server, self.cipher)),
+        )
+    }
+}
+
+pub struct DatagramRecvHalf(Box<dyn OutboundDatagramRecvHalf>, Arc<ShadowCipher>);
+
+#[async_trait]
+impl OutboundDatagramRecvHalf for DatagramRecvHalf {
+    async fn recv_from(&mut self, buf: &mut [u8]) -> io::Result<(usize, SocksAddr)> {
+        let mut packet = vec![0u8; 64 * 1024];
+        let (n, _) = self.0.recv_from(&mut packet).await?;
+        let data = self.1.decrypt_packet(&packet[..n])?;
+        // The payload is preceded by where it comes from.
+        let addr = SocksAddr::try_from((&data[..], SocksAddrWireType::PortLast))?;
+        let payload = &data[addr.size()..];
+        if payload.len() > buf.len() {
+            return Err(io::Error::new(io::ErrorKind::Interrupted, "Small buffer"));
+        }
+        buf[..payload.len()].copy_from_slice(payload);
+        Ok((payload.len(), addr))
+    }
+}
+
+pub struct DatagramSendHalf(
+    Box<dyn OutboundDatagramSendHalf>,
+    SocksAddr,
+    Arc<ShadowCipher>,
+);
+
+#[async_trait]
+impl OutboundDatagramSendHalf for DatagramSendHalf {
+    async fn send_to(&mut self, buf: &[u8], target: &SocksAddr) -> io::Result<usize> {
+        let mut data = BytesMut::new();
+        target.write_buf(&mut data, SocksAddrWireType::PortLast);
+        data.extend_from_slice(buf);
+        let packet = self.2.encrypt_packet(&data)?;
+        self.0.send_to(&packet, &self.1).await?;
+        Ok(buf.len())
+    }
+
+    async fn close(&mut self) -> io::Result<()> {
+        self.0.close().await
+    }
+}
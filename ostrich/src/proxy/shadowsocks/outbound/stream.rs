@@ -0,0 +1,44 @@
+use std::io;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use bytes::BytesMut;
+use tokio::io::AsyncWriteExt;
+
+use crate::{
+    proxy::{
+        shadowsocks::{ShadowCipher, ShadowedStream},
+        *,
+    },
+    session::{Session, SocksAddrWireType},
+};
+
+pub struct Handler {
+    pub address: String,
+    pub port: u16,
+    pub cipher: Arc<ShadowCipher>,
+}
+
+#[async_trait]
+impl OutboundStreamHandler for Handler {
+    fn connect_addr(&self) -> OutboundConnect {
+        OutboundConnect::Proxy(Network::Tcp, self.address.clone(), self.port)
+    }
+
+    async fn handle<'a>(
+        &'a self,
+        sess: &'a Session,
+        stream: Option<AnyStream>,
+    ) -> io::Result<AnyStream> {
+        let stream = stream.ok_or_else(|| io::Error::new(io::ErrorKind::Other, "invalid input"))?;
+        let mut stream = ShadowedStream::new(stream, self.cipher.clone());
+        let mut buf = BytesMut::new();
+        sess.destination
+            .write_buf(&mut buf, SocksAddrWireType::PortLast);
+        // The server won't talk before it gets the destination, make sure
+        // it's sent in case the client waits for the server to speak first.
+        stream.write_all(&buf).await?;
+        stream.flush().await?;
+        Ok(Box::new(stream))
+    }
+}
@@ -0,0 +1,332 @@
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use bytes::{Buf, BufMut, BytesMut};
+use futures::ready;
+use rand::RngCore;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use crate::common::crypto::{
+    aead::{AeadCipher, AeadDecryptor, AeadEncryptor},
+    Cipher, Decryptor, Encryptor, SizedCipher,
+};
+
+use super::crypto::{kdf, subkey, ShadowsocksNonce};
+
+// Most bytes of a chunk, the upper 2 bits of the length are reserved.
+const MAX_PAYLOAD: usize = 0x3fff;
+
+fn crypto_err(e: anyhow::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+/// The cipher and the master key of an outbound, shared by its sessions.
+pub struct ShadowCipher {
+    cipher: AeadCipher,
+    key: Vec<u8>,
+}
+
+impl ShadowCipher {
+    pub fn new(method: &str, password: &str) -> anyhow::Result<Self> {
+        let cipher = AeadCipher::new(method)?;
+        let key = kdf(password, cipher.key_len());
+        Ok(ShadowCipher { cipher, key })
+    }
+
+    fn salt_len(&self) -> usize {
+        // The salt is as long as the key for all AEAD ciphers.
+        self.key.len()
+    }
+
+    fn encryptor(&self, salt: &[u8]) -> io::Result<AeadEncryptor<ShadowsocksNonce>> {
+        let key = subkey(&self.key, salt).map_err(crypto_err)?;
+        self.cipher
+            .encryptor(&key, ShadowsocksNonce::new(self.cipher.nonce_len()))
+            .map_err(crypto_err)
+    }
+
+    fn decryptor(&self, salt: &[u8]) -> io::Result<AeadDecryptor<ShadowsocksNonce>> {
+        let key = subkey(&self.key, salt).map_err(crypto_err)?;
+        self.cipher
+            .decryptor(&key, ShadowsocksNonce::new(self.cipher.nonce_len()))
+            .map_err(crypto_err)
+    }
+
+    fn new_salt(&self) -> Vec<u8> {
+        let mut salt = vec![0u8; self.salt_len()];
+        rand::thread_rng().fill_bytes(&mut salt);
+        salt
+    }
+
+    /// Seals a UDP packet: a random salt, then `data` sealed with the zero
+    /// nonce.
+    pub fn encrypt_packet(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let salt = self.new_salt();
+        let mut payload = data.to_vec();
+        self.encryptor(&salt)?
+            .encrypt(&mut payload)
+            .map_err(crypto_err)?;
+        let mut packet = salt;
+        packet.extend_from_slice(&payload);
+        Ok(packet)
+    }
+
+    /// Opens a UDP packet sealed by `encrypt_packet`.
+    pub fn decrypt_packet(&self, packet: &[u8]) -> io::Result<Vec<u8>> {
+        if packet.len() < self.salt_len() + self.cipher.tag_len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "packet too short",
+            ));
+        }
+        let (salt, payload) = packet.split_at(self.salt_len());
+        let mut payload = payload.to_vec();
+        self.decryptor(salt)?
+            .decrypt(&mut payload)
+            .map_err(crypto_err)?;
+        payload.truncate(payload.len() - self.cipher.tag_len());
+        Ok(payload)
+    }
+}
+
+/// Encrypts what's written to the inner stream and decrypts what's read from
+/// it, in the chunks of the Shadowsocks AEAD protocol. Each direction starts
+/// with its own salt.
+pub struct ShadowedStream<S> {
+    inner: S,
+    cipher: Arc<ShadowCipher>,
+    enc: Option<AeadEncryptor<ShadowsocksNonce>>,
+    dec: Option<AeadDecryptor<ShadowsocksNonce>>,
+    // Bytes read from the inner stream not decrypted yet.
+    rbuf: BytesMut,
+    // Length of the payload of the current chunk, once its length is opened.
+    payload_len: Option<usize>,
+    // Decrypted bytes not read yet.
+    decrypted: BytesMut,
+    // Encrypted chunks not written to the inner stream yet.
+    wbuf: BytesMut,
+}
+
+impl<S> ShadowedStream<S> {
+    pub fn new(inner: S, cipher: Arc<ShadowCipher>) -> Self {
+        Self {
+            inner,
+            cipher,
+            enc: None,
+            dec: None,
+            rbuf: BytesMut::new(),
+            payload_len: None,
+            decrypted: BytesMut::new(),
+            wbuf: BytesMut::new(),
+        }
+    }
+
+    /// Appends the chunk carrying `data` to the write buffer, preceded by
+    /// the salt if it's the first one.
+    fn encrypt_chunk(&mut self, data: &[u8]) -> io::Result<()> {
+        if self.enc.is_none() {
+            let salt = self.cipher.new_salt();
+            self.enc = Some(self.cipher.encryptor(&salt)?);
+            self.wbuf.put_slice(&salt);
+        }
+        let enc = self.enc.as_mut().unwrap();
+        let mut len = (data.len() as u16).to_be_bytes().to_vec();
+        enc.encrypt(&mut len).map_err(crypto_err)?;
+        let mut payload = data.to_vec();
+        enc.encrypt(&mut payload).map_err(crypto_err)?;
+        self.wbuf.put_slice(&len);
+        self.wbuf.put_slice(&payload);
+        Ok(())
+    }
+
+    /// Decrypts the chunk at the beginning of the read buffer and consumes
+    /// it, returns false if the chunk is not complete yet.
+    fn decrypt_chunk(&mut self) -> io::Result<bool> {
+        if self.dec.is_none() {
+            let salt_len = self.cipher.salt_len();
+            if self.rbuf.len() < salt_len {
+                return Ok(false);
+            }
+            let salt = self.rbuf.split_to(salt_len);
+            self.dec = Some(self.cipher.decryptor(&salt)?);
+        }
+        let tag_len = self.cipher.cipher.tag_len();
+        let dec = self.dec.as_mut().unwrap();
+        let len = match self.payload_len {
+            Some(len) => len,
+            None => {
+                if self.rbuf.len() < 2 + tag_len {
+                    return Ok(false);
+                }
+                let mut len = self.rbuf.split_to(2 + tag_len).to_vec();
+                dec.decrypt(&mut len).map_err(crypto_err)?;
+                let len = u16::from_be_bytes([len[0], len[1]]) as usize;
+                if len == 0 || len > MAX_PAYLOAD {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "invalid chunk length",
+                    ));
+                }
+                self.payload_len = Some(len);
+                len
+            }
+        };
+        if self.rbuf.len() < len + tag_len {
+            return Ok(false);
+        }
+        let mut payload = self.rbuf.split_to(len + tag_len).to_vec();
+        dec.decrypt(&mut payload).map_err(crypto_err)?;
+        self.decrypted.put_slice(&payload[..len]);
+        self.payload_len = None;
+        Ok(true)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> ShadowedStream<S> {
+    fn poll_write_pending(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+        while !self.wbuf.is_empty() {
+            let n = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.wbuf))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.wbuf.advance(n);
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for ShadowedStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut ReadBuf,
+    ) -> Poll<io::Result<()>> {
+        let me = self.get_mut();
+        loop {
+            if !me.decrypted.is_empty() {
+                let n = std::cmp::min(buf.remaining(), me.decrypted.len());
+                buf.put_slice(&me.decrypted.split_to(n));
+                return Poll::Ready(Ok(()));
+            }
+            if me.decrypt_chunk()? {
+                continue;
+            }
+            let mut tmp = [0u8; 4 * 1024];
+            let mut tmp_buf = ReadBuf::new(&mut tmp);
+            ready!(Pin::new(&mut me.inner).poll_read(cx, &mut tmp_buf))?;
+            if tmp_buf.filled().is_empty() {
+                if me.rbuf.is_empty() && me.payload_len.is_none() {
+                    return Poll::Ready(Ok(()));
+                }
+                return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+            }
+            me.rbuf.put_slice(tmp_buf.filled());
+        }
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for ShadowedStream<S> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let me = self.get_mut();
+        ready!(me.poll_write_pending(cx))?;
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        let n = std::cmp::min(buf.len(), MAX_PAYLOAD);
+        me.encrypt_chunk(&buf[..n])?;
+        // The chunk is accepted as a whole, what's left goes out on the next
+        // write or flush.
+        let _ = me.poll_write_pending(cx)?;
+        Poll::Ready(Ok(n))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let me = self.get_mut();
+        ready!(me.poll_write_pending(cx))?;
+        Pin::new(&mut me.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let me = self.get_mut();
+        ready!(me.poll_write_pending(cx))?;
+        Pin::new(&mut me.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    const METHODS: [&str; 3] = ["aes-128-gcm", "aes-256-gcm", "chacha20-ietf-poly1305"];
+
+    #[test]
+    fn test_stream_round_trip() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            for method in METHODS {
+                let cipher = Arc::new(ShadowCipher::new(method, "password").unwrap());
+                let (a, mut b) = tokio::io::duplex(256 * 1024);
+                let mut a = ShadowedStream::new(a, cipher.clone());
+
+                // Spans several chunks.
+                let data: Vec<u8> = (0..40000).map(|i| i as u8).collect();
+                a.write_all(&data).await.unwrap();
+                a.shutdown().await.unwrap();
+                let mut wire = Vec::new();
+                b.read_to_end(&mut wire).await.unwrap();
+                assert_ne!(&wire[cipher.salt_len()..][..data.len()], &data[..]);
+
+                let mut r = ShadowedStream::new(wire.as_slice(), cipher.clone());
+                let mut out = Vec::new();
+                r.read_to_end(&mut out).await.unwrap();
+                assert_eq!(out, data, "{}", method);
+
+                // A wrong password or a truncated chunk is an error.
+                let other = Arc::new(ShadowCipher::new(method, "wrong").unwrap());
+                let mut r = ShadowedStream::new(wire.as_slice(), other);
+                assert!(r.read_to_end(&mut Vec::new()).await.is_err());
+                let mut r = ShadowedStream::new(&wire[..wire.len() - 1], cipher);
+                assert!(r.read_to_end(&mut Vec::new()).await.is_err());
+            }
+        });
+    }
+
+    #[test]
+    fn test_packet_round_trip() {
+        for method in METHODS {
+            let cipher = ShadowCipher::new(method, "password").unwrap();
+            let packet = cipher.encrypt_packet(b"hello").unwrap();
+            assert_eq!(
+                packet.len(),
+                cipher.salt_len() + 5 + cipher.cipher.tag_len()
+            );
+            assert_eq!(
+                cipher.decrypt_packet(&packet).unwrap(),
+                b"hello",
+                "{}",
+                method
+            );
+            assert!(cipher.decrypt_packet(&packet[..packet.len() - 1]).is_err());
+        }
+        assert!(ShadowCipher::new("rc4-md5", "password").is_err());
+    }
+
+    #[test]
+    fn test_kdf() {
+        // MD5 of the password, then MD5 of that digest and the password.
+        let key: String = kdf("foobar", 32)
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        assert_eq!(
+            key,
+            "3858f62230ac3c915f300c664312c63f568378529614d22ddb49237d2f60bfdf"
+        );
+    }
+}
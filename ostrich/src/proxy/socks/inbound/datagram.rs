@@ -1,23 +1,39 @@
 use std::convert::TryFrom;
 use std::io;
 use std::net::SocketAddr;
+use std::sync::Arc;
 
 use anyhow::anyhow;
 use async_trait::async_trait;
 use bytes::{BufMut, BytesMut};
 
+use super::UdpAssociations;
 use crate::{
     proxy::*,
     session::{DatagramSource, SocksAddr, SocksAddrWireType},
 };
 
-pub struct Handler;
+#[derive(Default)]
+pub struct Handler {
+    // Datagrams are only accepted from the clients of these associations if
+    // the control connections require authentication.
+    associations: Option<Arc<UdpAssociations>>,
+}
+
+impl Handler {
+    pub fn new(associations: Option<Arc<UdpAssociations>>) -> Self {
+        Handler { associations }
+    }
+}
 
 #[async_trait]
 impl InboundDatagramHandler for Handler {
     async fn handle<'a>(&'a self, socket: AnyInboundDatagram) -> io::Result<AnyInboundTransport> {
         Ok(InboundTransport::Datagram(
-            Box::new(Datagram { socket }),
+            Box::new(Datagram {
+                socket,
+                associations: self.associations.clone(),
+            }),
             None,
         ))
     }
@@ -25,6 +41,7 @@ impl InboundDatagramHandler for Handler {
 
 pub struct Datagram {
     socket: Box<dyn InboundDatagram>,
+    associations: Option<Arc<UdpAssociations>>,
 }
 
 impl InboundDatagram for Datagram {
@@ -36,7 +53,7 @@ impl InboundDatagram for Datagram {
     ) {
         let (rh, sh) = self.socket.split();
         (
-            Box::new(DatagramRecvHalf(rh)),
+            Box::new(DatagramRecvHalf(rh, self.associations)),
             Box::new(DatagramSendHalf(sh)),
         )
     }
@@ -46,7 +63,10 @@ impl InboundDatagram for Datagram {
     }
 }
 
-pub struct DatagramRecvHalf(Box<dyn InboundDatagramRecvHalf>);
+pub struct DatagramRecvHalf(
+    Box<dyn InboundDatagramRecvHalf>,
+    Option<Arc<UdpAssociations>>,
+);
 
 #[async_trait]
 impl InboundDatagramRecvHalf for DatagramRecvHalf {
@@ -56,6 +76,14 @@ impl InboundDatagramRecvHalf for DatagramRecvHalf {
     ) -> ProxyResult<(usize, DatagramSource, SocksAddr)> {
         let mut recv_buf = vec![0u8; buf.len()];
         let (n, src_addr, _) = self.0.recv_from(&mut recv_buf).await?;
+        if let Some(associations) = &self.1 {
+            if !associations.contains(&src_addr.address.ip()) {
+                return Err(ProxyError::DatagramWarn(anyhow!(
+                    "Datagram from {} without an associated control connection",
+                    &src_addr.address
+                )));
+            }
+        }
         if n < 3 {
             return Err(ProxyError::DatagramWarn(anyhow!("Short message")));
         }
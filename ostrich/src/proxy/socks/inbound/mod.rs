@@ -1,5 +1,34 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+
 mod datagram;
 mod stream;
 
 pub use datagram::Handler as DatagramHandler;
 pub use stream::Handler as StreamHandler;
+
+/// Source IPs of clients holding a UDP ASSOCIATE on an authenticated control
+/// connection, the UDP relay only accepts datagrams from them.
+#[derive(Default)]
+pub struct UdpAssociations(Mutex<HashMap<IpAddr, usize>>);
+
+impl UdpAssociations {
+    fn add(&self, ip: IpAddr) {
+        *self.0.lock().unwrap().entry(ip).or_insert(0) += 1;
+    }
+
+    fn remove(&self, ip: IpAddr) {
+        let mut ips = self.0.lock().unwrap();
+        if let Some(n) = ips.get_mut(&ip) {
+            *n -= 1;
+            if *n == 0 {
+                ips.remove(&ip);
+            }
+        }
+    }
+
+    fn contains(&self, ip: &IpAddr) -> bool {
+        self.0.lock().unwrap().contains_key(ip)
+    }
+}
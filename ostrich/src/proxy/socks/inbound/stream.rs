@@ -1,16 +1,84 @@
 use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
 
 use async_trait::async_trait;
 use bytes::{BufMut, BytesMut};
 use log::*;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
+use super::UdpAssociations;
 use crate::{
     proxy::*,
     session::{Session, SocksAddr, SocksAddrWireType},
 };
 
-pub struct Handler;
+#[derive(Default)]
+pub struct Handler {
+    // Where UDP ASSOCIATE clients are told to send datagrams if the relay
+    // binds apart from the control port, an unspecified IP is replaced by
+    // the local address of the control connection.
+    udp_relay: Option<SocketAddr>,
+    // The username and password clients must log in with, no authentication
+    // is required if it's none.
+    credentials: Option<(String, String)>,
+    // Clients of authenticated UDP ASSOCIATE requests, shared with the
+    // datagram handler of the relay.
+    associations: Arc<UdpAssociations>,
+}
+
+impl Handler {
+    pub fn new(
+        udp_relay: Option<SocketAddr>,
+        credentials: Option<(String, String)>,
+        associations: Arc<UdpAssociations>,
+    ) -> Self {
+        Handler {
+            udp_relay,
+            credentials,
+            associations,
+        }
+    }
+}
+
+// Compares in time independent of where the inputs differ.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+// Reads the username/password request of RFC 1929 and replies whether it
+// matches the expected credentials.
+async fn authenticate(stream: &mut AnyStream, username: &str, password: &str) -> io::Result<()> {
+    let mut buf = [0u8; 255];
+    // ver, ulen
+    stream.read_exact(&mut buf[..2]).await?;
+    if buf[0] != 0x01 {
+        return Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("unknown socks5 authentication version {}", buf[0]),
+        ));
+    }
+    let ulen = buf[1] as usize;
+    stream.read_exact(&mut buf[..ulen]).await?;
+    let username_matched = constant_time_eq(&buf[..ulen], username.as_bytes());
+    // plen
+    stream.read_exact(&mut buf[..1]).await?;
+    let plen = buf[0] as usize;
+    stream.read_exact(&mut buf[..plen]).await?;
+    let password_matched = constant_time_eq(&buf[..plen], password.as_bytes());
+    if !username_matched || !password_matched {
+        stream.write_all(&[0x01, 0x01]).await?;
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "invalid socks5 username or password",
+        ));
+    }
+    stream.write_all(&[0x01, 0x00]).await?;
+    Ok(())
+}
 
 #[async_trait]
 impl InboundStreamHandler for Handler {
@@ -41,17 +109,14 @@ impl InboundStreamHandler for Handler {
         buf.resize(nmethods, 0);
         // methods
         stream.read_exact(&mut buf[..]).await?;
-        let mut method_accepted = false;
-        let mut method_idx: u8 = 0;
-        let supported_method: u8 = 0x0;
-        for (idx, method) in buf[..].iter().enumerate() {
-            if method == &supported_method {
-                method_accepted = true;
-                method_idx = idx as u8;
-                break;
-            }
-        }
-        if !method_accepted {
+        // username/password if credentials are configured, otherwise no
+        // authentication
+        let supported_method: u8 = if self.credentials.is_some() {
+            0x02
+        } else {
+            0x0
+        };
+        if !buf.contains(&supported_method) {
             stream.write_all(&[0x05, 0xff]).await?;
             return Err(io::Error::new(
                 io::ErrorKind::Other,
@@ -59,7 +124,12 @@ impl InboundStreamHandler for Handler {
             ));
         }
 
-        stream.write_all(&[0x05, method_idx]).await?;
+        stream.write_all(&[0x05, supported_method]).await?;
+
+        if let Some((username, password)) = &self.credentials {
+            authenticate(&mut stream, username, password).await?;
+            sess.user = Some(username.clone());
+        }
 
         // handle request
         buf.resize(3, 0);
@@ -109,9 +179,25 @@ impl InboundStreamHandler for Handler {
                 buf.put_u8(0x05); // version 5
                 buf.put_u8(0x0); // succeeded
                 buf.put_u8(0x0); // rsv
-                let relay_addr = SocksAddr::from(sess.local_addr);
+                let relay_addr = match self.udp_relay {
+                    Some(a) if a.ip().is_unspecified() => {
+                        SocketAddr::new(sess.local_addr.ip(), a.port())
+                    }
+                    Some(a) => a,
+                    None => sess.local_addr,
+                };
+                let relay_addr = SocksAddr::from(relay_addr);
                 relay_addr.write_buf(&mut buf, SocksAddrWireType::PortLast);
                 stream.write_all(&buf[..]).await?;
+                // The relay accepts datagrams from the client for as long as
+                // the control connection stays open.
+                let associations = if self.credentials.is_some() {
+                    let ip = sess.source.ip();
+                    self.associations.add(ip);
+                    Some((self.associations.clone(), ip))
+                } else {
+                    None
+                };
                 tokio::spawn(async move {
                     let mut buf = [0u8; 1];
                     // TODO explicitly drop resources allocated above before waiting?
@@ -123,6 +209,9 @@ impl InboundStreamHandler for Handler {
                         // perhaps explicitly notifies the NAT manager?
                         debug!("udp association end: {}", e);
                     }
+                    if let Some((associations, ip)) = associations {
+                        associations.remove(ip);
+                    }
                 });
                 Ok(InboundTransport::Empty)
             }
@@ -130,3 +219,122 @@ impl InboundStreamHandler for Handler {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Runs the handshake with what the client sends, returns the session of
+    // the stream and what the client gets back.
+    fn handshake(handler: &Handler, request: &[u8]) -> (io::Result<Session>, Vec<u8>) {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let (mut client, server) = tokio::io::duplex(1024);
+            client.write_all(request).await.unwrap();
+            // The server side of the stream is dropped so the reply ends.
+            let result = handler
+                .handle(Session::default(), Box::new(server))
+                .await
+                .map(|transport| match transport {
+                    InboundTransport::Stream(_, sess) => sess,
+                    _ => panic!("unexpected transport"),
+                });
+            let mut reply = Vec::new();
+            client.read_to_end(&mut reply).await.unwrap();
+            (result, reply)
+        })
+    }
+
+    // A CONNECT request to 1.1.1.1:80.
+    const CONNECT: &[u8] = &[0x05, 0x01, 0x00, 0x01, 1, 1, 1, 1, 0, 80];
+    // The CONNECT reply with an unspecified address.
+    const CONNECTED: &[u8] = &[0x05, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0];
+
+    fn login(username: &[u8], password: &[u8]) -> Vec<u8> {
+        let mut req = vec![0x05, 0x02, 0x00, 0x02, 0x01, username.len() as u8];
+        req.extend_from_slice(username);
+        req.push(password.len() as u8);
+        req.extend_from_slice(password);
+        req
+    }
+
+    #[test]
+    fn test_auth() {
+        let handler = Handler::new(
+            None,
+            Some(("user".to_string(), "pass".to_string())),
+            Arc::default(),
+        );
+
+        let mut req = login(b"user", b"pass");
+        req.extend_from_slice(CONNECT);
+        let (result, reply) = handshake(&handler, &req);
+        let sess = result.unwrap();
+        assert_eq!(sess.user.as_deref(), Some("user"));
+        assert_eq!(sess.destination.to_string(), "1.1.1.1:80");
+        assert_eq!(&reply[..4], &[0x05, 0x02, 0x01, 0x00]);
+        assert_eq!(&reply[4..], CONNECTED);
+
+        let mut req = login(b"user", b"wrong");
+        req.extend_from_slice(CONNECT);
+        let (result, reply) = handshake(&handler, &req);
+        assert!(result.is_err());
+        assert_eq!(reply, [0x05, 0x02, 0x01, 0x01]);
+
+        let mut req = vec![0x05, 0x01, 0x00];
+        req.extend_from_slice(CONNECT);
+        let (result, reply) = handshake(&handler, &req);
+        assert!(result.is_err());
+        assert_eq!(reply, [0x05, 0xff]);
+    }
+
+    #[test]
+    fn test_udp_associate_auth() {
+        let associations = Arc::new(UdpAssociations::default());
+        let handler = Handler::new(
+            None,
+            Some(("user".to_string(), "pass".to_string())),
+            associations.clone(),
+        );
+        let client_ip: std::net::IpAddr = "10.0.0.1".parse().unwrap();
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let (mut client, server) = tokio::io::duplex(1024);
+            let mut req = login(b"user", b"pass");
+            req.extend_from_slice(&[0x05, 0x03, 0x00, 0x01, 0, 0, 0, 0, 0, 0]);
+            client.write_all(&req).await.unwrap();
+            let mut sess = Session::default();
+            sess.source = SocketAddr::new(client_ip, 1234);
+            let transport = handler.handle(sess, Box::new(server)).await.unwrap();
+            assert!(matches!(transport, InboundTransport::Empty));
+            assert!(associations.contains(&client_ip));
+
+            // Closing the control connection ends the association.
+            drop(client);
+            for _ in 0..10 {
+                if !associations.contains(&client_ip) {
+                    break;
+                }
+                tokio::task::yield_now().await;
+            }
+            assert!(!associations.contains(&client_ip));
+        });
+    }
+
+    #[test]
+    fn test_no_auth() {
+        let handler = Handler::default();
+        let mut req = vec![0x05, 0x02, 0x02, 0x00];
+        req.extend_from_slice(CONNECT);
+        let (result, reply) = handshake(&handler, &req);
+        assert!(result.unwrap().user.is_none());
+        assert_eq!(&reply[..2], &[0x05, 0x00]);
+        assert_eq!(&reply[2..], CONNECTED);
+    }
+}
@@ -0,0 +1,3 @@
+pub mod stream;
+
+pub use stream::Handler as StreamHandler;
@@ -0,0 +1,395 @@
+use std::io;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use russh::client;
+use russh_keys::key;
+use tokio::sync::Mutex;
+use tokio::time::timeout;
+
+use crate::{app::SyncDnsClient, config::SshOutboundSettings, option, proxy::*, session::Session};
+
+fn ssh_err(e: russh::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e)
+}
+
+// Checks the host key of the server against the expected fingerprint, any
+// key is accepted if there's none.
+struct Client {
+    host_key: Option<String>,
+}
+
+#[async_trait]
+impl client::Handler for Client {
+    type Error = russh::Error;
+
+    async fn check_server_key(
+        &mut self,
+        server_public_key: &key::PublicKey,
+    ) -> std::result::Result<bool, Self::Error> {
+        match &self.host_key {
+            Some(expected) => {
+                let fingerprint = server_public_key.fingerprint();
+                if &fingerprint != expected {
+                    log::warn!("unexpected ssh host key SHA256:{}", fingerprint);
+                    return Ok(false);
+                }
+                Ok(true)
+            }
+            None => Ok(true),
+        }
+    }
+}
+
+// The connection shared by the sessions.
+type Pool = Mutex<Option<Arc<client::Handle<Client>>>>;
+
+// Stops handing out an expired connection, it closes when the sessions on it
+// are done.
+async fn retire(pool: Weak<Pool>, expired: Weak<client::Handle<Client>>) {
+    if let Some(pool) = pool.upgrade() {
+        let mut conn = pool.lock().await;
+        if matches!(conn.as_ref(), Some(c) if std::ptr::eq(Arc::as_ptr(c), expired.as_ptr())) {
+            conn.take();
+        }
+    }
+}
+
+/// Tunnels each session through a direct-tcpip channel, all the channels are
+/// multiplexed over a single SSH connection to the server.
+pub struct Handler {
+    address: String,
+    port: u16,
+    username: String,
+    password: Option<String>,
+    private_key: Option<Arc<key::KeyPair>>,
+    // The fingerprint without the `SHA256:` prefix.
+    host_key: Option<String>,
+    dns_client: SyncDnsClient,
+    dscp: Option<u8>,
+    fwmark: Option<u32>,
+    connect_timeout: Option<Duration>,
+    connect_retries: u32,
+    conn: Arc<Pool>,
+}
+
+impl Handler {
+    pub fn new(
+        settings: &SshOutboundSettings,
+        dns_client: SyncDnsClient,
+        dscp: Option<u8>,
+        fwmark: Option<u32>,
+        connect_timeout: Option<Duration>,
+        connect_retries: u32,
+    ) -> Result<Self> {
+        if settings.address.is_empty() || settings.port == 0 || settings.port > u16::MAX as u32 {
+            return Err(anyhow!("invalid server address"));
+        }
+        if settings.username.is_empty() {
+            return Err(anyhow!("missing username"));
+        }
+        if settings.password.is_empty() && settings.private_key.is_empty() {
+            return Err(anyhow!("missing password or private key"));
+        }
+        let private_key = if !settings.private_key.is_empty() {
+            let passphrase =
+                Some(settings.private_key_passphrase.as_str()).filter(|p| !p.is_empty());
+            let key = russh_keys::load_secret_key(&settings.private_key, passphrase)
+                .map_err(|e| anyhow!("load private key {} failed: {}", &settings.private_key, e))?;
+            Some(Arc::new(key))
+        } else {
+            None
+        };
+        let host_key = Some(settings.host_key.trim_start_matches("SHA256:"))
+            .filter(|k| !k.is_empty())
+            .map(str::to_string);
+        if host_key.is_none() {
+            if !settings.allow_insecure {
+                return Err(anyhow!("missing host key"));
+            }
+            log::warn!(
+                "any host key of ssh server {}:{} is accepted, connections can be intercepted",
+                &settings.address,
+                settings.port
+            );
+        }
+        Ok(Handler {
+            address: settings.address.clone(),
+            port: settings.port as u16,
+            username: settings.username.clone(),
+            password: Some(settings.password.clone()).filter(|p| !p.is_empty()),
+            private_key,
+            host_key,
+            dns_client,
+            dscp,
+            fwmark,
+            connect_timeout,
+            connect_retries,
+            conn: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    async fn authenticate(&self, handle: &mut client::Handle<Client>) -> io::Result<()> {
+        if let Some(key) = &self.private_key {
+            if handle
+                .authenticate_publickey(&self.username, key.clone())
+                .await
+                .map_err(ssh_err)?
+            {
+                return Ok(());
+            }
+        }
+        if let Some(password) = &self.password {
+            if handle
+                .authenticate_password(&self.username, password)
+                .await
+                .map_err(ssh_err)?
+            {
+                return Ok(());
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("ssh authentication as {} failed", &self.username),
+        ))
+    }
+
+    // Whether there's a connection to share which is not closed.
+    fn is_connected(conn: &Option<Arc<client::Handle<Client>>>) -> bool {
+        matches!(conn, Some(c) if !c.is_closed())
+    }
+
+    // Returns the current connection, otherwise connects and logs in a new
+    // one over the stream dialed to the server, or dials one if not given.
+    // The pool is not locked while connecting, so a stalled server doesn't
+    // hold up sessions once another connection is made.
+    async fn connection(
+        &self,
+        stream: Option<AnyStream>,
+    ) -> io::Result<Arc<client::Handle<Client>>> {
+        {
+            let conn = self.conn.lock().await;
+            if Self::is_connected(&conn) {
+                return Ok(conn.as_ref().unwrap().clone());
+            }
+        }
+        // Each dialing attempt is bounded by the connect timeout, the handshake
+        // by the dial timeout.
+        let stream = match stream {
+            Some(s) => s,
+            None => {
+                new_retried_tcp_stream(
+                    self.dns_client.clone(),
+                    &self.address,
+                    &self.port,
+                    self.dscp,
+                    self.fwmark,
+                    self.connect_timeout,
+                    self.connect_retries,
+                )
+                .await?
+            }
+        };
+        let connect = async {
+            let checker = Client {
+                host_key: self.host_key.clone(),
+            };
+            let mut handle =
+                client::connect_stream(Arc::new(client::Config::default()), stream, checker)
+                    .await
+                    .map_err(ssh_err)?;
+            self.authenticate(&mut handle).await?;
+            Ok::<_, io::Error>(handle)
+        };
+        let handle = timeout(Duration::from_secs(*option::OUTBOUND_DIAL_TIMEOUT), connect)
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "ssh handshake timed out"))??;
+        let handle = Arc::new(handle);
+        if let Some(lifetime) = pooled_conn_max_lifetime() {
+            let pool = Arc::downgrade(&self.conn);
+            let expired = Arc::downgrade(&handle);
+            let server = format!("{}:{}", &self.address, self.port);
+            tokio::spawn(async move {
+                tokio::time::sleep(lifetime).await;
+                log::debug!("ssh connection to {} expired", server);
+                retire(pool, expired).await;
+            });
+        }
+        // Takes the place of any connection made meanwhile, which closes once
+        // the sessions on it are done.
+        *self.conn.lock().await = Some(handle.clone());
+        Ok(handle)
+    }
+}
+
+#[async_trait]
+impl OutboundStreamHandler for Handler {
+    fn connect_addr(&self) -> OutboundConnect {
+        // Sessions share the connection, the server is only dialed if there's
+        // none to share.
+        match self.conn.try_lock() {
+            Ok(conn) if Self::is_connected(&conn) => OutboundConnect::Unknown,
+            _ => OutboundConnect::Proxy(Network::Tcp, self.address.clone(), self.port),
+        }
+    }
+
+    async fn handle<'a>(
+        &'a self,
+        sess: &'a Session,
+        stream: Option<AnyStream>,
+    ) -> io::Result<AnyStream> {
+        let channel = self
+            .connection(stream)
+            .await?
+            .channel_open_direct_tcpip(
+                sess.destination.host(),
+                sess.destination.port() as u32,
+                sess.source.ip().to_string(),
+                sess.source.port() as u32,
+            )
+            .await
+            .map_err(ssh_err)?;
+        Ok(Box::new(channel.into_stream()))
+    }
+
+    async fn reset(&self) {
+        self.conn.lock().await.take();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::dns_client::tests::dns_client;
+    use crate::session::SocksAddr;
+
+    #[test]
+    fn test_new() {
+        let mut settings = SshOutboundSettings::new();
+        settings.address = "ssh.example.com".to_string();
+        settings.port = 22;
+        settings.username = "user".to_string();
+        let dns_client = dns_client();
+
+        assert!(Handler::new(&settings, dns_client.clone(), None, None, None, 0).is_err());
+        settings.password = "pass".to_string();
+        // The host key is required unless explicitly not verified.
+        assert!(Handler::new(&settings, dns_client.clone(), None, None, None, 0).is_err());
+        settings.allow_insecure = true;
+        let h = Handler::new(&settings, dns_client.clone(), None, None, None, 0).unwrap();
+        assert!(h.host_key.is_none());
+        settings.host_key = "SHA256:nThbg6kXUpJWGl7E1IGOCspRomTxdCARLviKw6E5SY8".to_string();
+        let h = Handler::new(&settings, dns_client.clone(), None, None, None, 0).unwrap();
+        assert_eq!(h.password.as_deref(), Some("pass"));
+        assert!(h.private_key.is_none());
+        assert_eq!(
+            h.host_key.as_deref(),
+            Some("nThbg6kXUpJWGl7E1IGOCspRomTxdCARLviKw6E5SY8")
+        );
+
+        settings.private_key = "/nonexistent/id_ed25519".to_string();
+        assert!(Handler::new(&settings, dns_client, None, None, None, 0).is_err());
+    }
+
+    // Accepts any login and direct-tcpip channel, records where the channels
+    // go.
+    struct Server {
+        opened: Arc<std::sync::Mutex<Vec<(String, u32)>>>,
+        channels: Vec<russh::Channel<russh::server::Msg>>,
+    }
+
+    #[async_trait]
+    impl russh::server::Handler for Server {
+        type Error = russh::Error;
+
+        async fn auth_password(
+            &mut self,
+            _user: &str,
+            _password: &str,
+        ) -> std::result::Result<russh::server::Auth, Self::Error> {
+            Ok(russh::server::Auth::Accept)
+        }
+
+        async fn channel_open_direct_tcpip(
+            &mut self,
+            channel: russh::Channel<russh::server::Msg>,
+            host_to_connect: &str,
+            port_to_connect: u32,
+            _originator_address: &str,
+            _originator_port: u32,
+            _session: &mut russh::server::Session,
+        ) -> std::result::Result<bool, Self::Error> {
+            self.opened
+                .lock()
+                .unwrap()
+                .push((host_to_connect.to_string(), port_to_connect));
+            self.channels.push(channel);
+            Ok(true)
+        }
+    }
+
+    #[test]
+    fn test_handshake() {
+        let key = key::KeyPair::generate_ed25519().unwrap();
+        let fingerprint = key.clone_public_key().unwrap().fingerprint();
+        let config = Arc::new(russh::server::Config {
+            keys: vec![key],
+            ..Default::default()
+        });
+        let mut settings = SshOutboundSettings::new();
+        settings.address = "ssh.example.com".to_string();
+        settings.port = 22;
+        settings.username = "user".to_string();
+        settings.password = "pass".to_string();
+        let dns_client = dns_client();
+        let mut sess = Session::default();
+        sess.destination = SocksAddr::Domain("example.com".to_string(), 80);
+
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let wrong = "nThbg6kXUpJWGl7E1IGOCspRomTxdCARLviKw6E5SY8".to_string();
+            for (host_key, verified) in [(fingerprint, true), (wrong, false)] {
+                let (client, server) = tokio::io::duplex(64 * 1024);
+                let opened = Arc::new(std::sync::Mutex::new(Vec::new()));
+                let handler = Server {
+                    opened: opened.clone(),
+                    channels: Vec::new(),
+                };
+                let config = config.clone();
+                tokio::spawn(async move {
+                    if let Ok(session) = russh::server::run_stream(config, server, handler).await {
+                        let _ = session.await;
+                    }
+                });
+
+                settings.host_key = host_key;
+                let h = Handler::new(&settings, dns_client.clone(), None, None, None, 0).unwrap();
+                assert!(matches!(h.connect_addr(), OutboundConnect::Proxy(..)));
+                let res = h.handle(&sess, Some(Box::new(client))).await;
+                assert_eq!(res.is_ok(), verified);
+                if verified {
+                    assert_eq!(
+                        *opened.lock().unwrap(),
+                        vec![("example.com".to_string(), 80)]
+                    );
+                    // Later sessions share the connection.
+                    assert!(matches!(h.connect_addr(), OutboundConnect::Unknown));
+
+                    // Another connection has taken its place.
+                    retire(Arc::downgrade(&h.conn), Weak::new()).await;
+                    assert!(matches!(h.connect_addr(), OutboundConnect::Unknown));
+                    let handle = Arc::downgrade(h.conn.lock().await.as_ref().unwrap());
+                    retire(Arc::downgrade(&h.conn), handle).await;
+                    assert!(matches!(h.connect_addr(), OutboundConnect::Proxy(..)));
+                } else {
+                    assert!(opened.lock().unwrap().is_empty());
+                }
+            }
+        });
+    }
+}
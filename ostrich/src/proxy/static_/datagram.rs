@@ -0,0 +1,55 @@
+use std::io;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use crate::{app::SyncDnsClient, proxy::*, session::Session};
+
+use super::Selector;
+
+pub struct Handler {
+    pub selector: Arc<Selector>,
+    pub dns_client: SyncDnsClient,
+}
+
+#[async_trait]
+impl OutboundDatagramHandler for Handler {
+    fn connect_addr(&self) -> OutboundConnect {
+        OutboundConnect::Unknown
+    }
+
+    fn transport_type(&self) -> DatagramTransportType {
+        DatagramTransportType::Unknown
+    }
+
+    async fn handle<'a>(
+        &'a self,
+        sess: &'a Session,
+        _transport: Option<AnyOutboundTransport>,
+    ) -> io::Result<AnyOutboundDatagram> {
+        let mut last_err = None;
+        let actors = self.selector.select(sess);
+        let n_actors = actors.len();
+        for (i, a) in actors.into_iter().enumerate() {
+            let start = tokio::time::Instant::now();
+            let attempt = async {
+                let transport = connect_datagram_outbound(sess, self.dns_client.clone(), a).await?;
+                a.datagram()?.handle(sess, transport).await
+            };
+            // See Selector::attempt for the timeout on all but the last actor.
+            let res = self.selector.attempt(i + 1 == n_actors, attempt).await;
+            match res {
+                Ok(dgram) => {
+                    self.selector.report(sess, a, Some(start.elapsed()));
+                    return Ok(dgram);
+                }
+                Err(e) => {
+                    log::debug!("[{}] failed for {}: {}", a.tag(), &sess.destination, e);
+                    self.selector.report(sess, a, None);
+                    last_err = Some(e);
+                }
+            }
+        }
+        Err(last_err.unwrap_or_else(|| self.selector.no_actor_error()))
+    }
+}
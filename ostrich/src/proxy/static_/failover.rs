@@ -0,0 +1,69 @@
+use std::sync::{Arc, Mutex, Weak};
+use std::time::Duration;
+
+use futures::future::join_all;
+use log::*;
+
+use crate::{app::SyncDnsClient, proxy::*};
+
+use super::Selector;
+
+// Seconds to wait for the TCP handshake of a health check.
+const CHECK_TIMEOUT: u64 = 3;
+
+/// Whether each actor passed its most recent health check, in the configured
+/// order. All actors are assumed up until checked.
+pub type Health = Arc<Mutex<Vec<bool>>>;
+
+// Dials the server of the actor, marked as its own connections, actors
+// connecting directly have nothing to check and are always up.
+async fn check(dns_client: SyncDnsClient, actor: &AnyOutboundHandler) -> bool {
+    let (addr, port) = match actor.stream().map(|h| h.connect_addr()) {
+        Ok(OutboundConnect::Proxy(Network::Tcp, addr, port)) => (addr, port),
+        _ => return true,
+    };
+    let dial = new_marked_tcp_stream(dns_client, &addr, &port, actor.dscp(), actor.fwmark());
+    match tokio::time::timeout(Duration::from_secs(CHECK_TIMEOUT), dial).await {
+        Ok(Ok(_)) => true,
+        Ok(Err(e)) => {
+            debug!("health check of [{}] failed: {}", actor.tag(), e);
+            false
+        }
+        Err(_) => {
+            debug!("health check of [{}] timed out", actor.tag());
+            false
+        }
+    }
+}
+
+/// Checks the actors of the selector every interval, returns once the
+/// selector is dropped.
+pub async fn health_check_task(
+    selector: Weak<Selector>,
+    health: Health,
+    interval: Duration,
+    dns_client: SyncDnsClient,
+) {
+    loop {
+        let actors = match selector.upgrade() {
+            Some(s) => s.actors.clone(),
+            None => return,
+        };
+        let results = join_all(actors.iter().map(|a| check(dns_client.clone(), a))).await;
+        {
+            let mut health = health.lock().unwrap();
+            for (i, up) in results.into_iter().enumerate() {
+                if health[i] != up {
+                    info!(
+                        "[{}] is {}",
+                        actors[i].tag(),
+                        if up { "up" } else { "down" }
+                    );
+                }
+                health[i] = up;
+            }
+        }
+        drop(actors);
+        tokio::time::sleep(interval).await;
+    }
+}
@@ -0,0 +1,289 @@
+use std::future::Future;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use rand::{seq::SliceRandom, Rng};
+
+use crate::{config, proxy::*, session::Session};
+
+pub mod datagram;
+pub mod failover;
+pub mod smart;
+pub mod stream;
+
+pub use datagram::Handler as DatagramHandler;
+pub use stream::Handler as StreamHandler;
+
+enum Method {
+    Random,
+    // Picks an actor at random in proportion to its weight, the running sum
+    // of the weights in the actors' order.
+    Weighted(Vec<u64>),
+    RoundRobin(AtomicUsize),
+    // Prefers the actor with the lowest handshake latency per destination,
+    // falls back to the others in the order of their latencies.
+    Smart(Arc<smart::SmartStore>),
+    // Tries the actors in the configured order, each but the last one is
+    // given up after a timeout, e.g. a proxy with direct as the fallback.
+    Fallback(Duration),
+    // Uses the first actor in the configured order that passed its latest
+    // health check, checked every interval.
+    Failover(failover::Health, Duration),
+}
+
+// Default seconds to wait for an actor of the fallback method.
+const DEFAULT_FALLBACK_TIMEOUT: u64 = 3;
+// Default seconds between the health checks of the failover method.
+const DEFAULT_CHECK_INTERVAL: u64 = 10;
+
+// Returns the index of the first running sum above a random point below the
+// total, actors of weight 0 repeat the previous sum and are never picked.
+fn weighted_pick<R: Rng>(cumulative: &[u64], rng: &mut R) -> usize {
+    let point = rng.gen_range(0..*cumulative.last().unwrap());
+    cumulative.partition_point(|sum| *sum <= point)
+}
+
+/// Decides which actors of a static outbound to use for a session.
+pub struct Selector {
+    actors: Vec<AnyOutboundHandler>,
+    method: Method,
+}
+
+impl Selector {
+    pub fn new(
+        tag: &str,
+        actors: Vec<AnyOutboundHandler>,
+        settings: &config::StaticOutboundSettings,
+    ) -> Result<Self> {
+        let method = match settings.method.as_str() {
+            "" | "random" => Method::Random,
+            "rr" => Method::RoundRobin(AtomicUsize::new(0)),
+            "weighted" if settings.weights.is_empty() => Method::Random,
+            "weighted" => {
+                if settings.weights.len() != actors.len() {
+                    return Err(anyhow!(
+                        "{} weights for {} actors",
+                        settings.weights.len(),
+                        actors.len()
+                    ));
+                }
+                let cumulative: Vec<u64> = settings
+                    .weights
+                    .iter()
+                    .scan(0u64, |sum, w| {
+                        *sum += *w as u64;
+                        Some(*sum)
+                    })
+                    .collect();
+                if cumulative.last() == Some(&0) {
+                    return Err(anyhow!("all weights are 0"));
+                }
+                Method::Weighted(cumulative)
+            }
+            "smart" => {
+                let path = if !settings.smart_cache.is_empty() {
+                    Path::new(&settings.smart_cache).to_path_buf()
+                } else {
+                    Path::new(&*crate::option::ASSET_LOCATION).join(format!("{}.smart", tag))
+                };
+                Method::Smart(Arc::new(smart::SmartStore::load(path)))
+            }
+            "fallback" => {
+                let timeout = if settings.fallback_timeout > 0 {
+                    settings.fallback_timeout as u64
+                } else {
+                    DEFAULT_FALLBACK_TIMEOUT
+                };
+                Method::Fallback(Duration::from_secs(timeout))
+            }
+            "failover" => {
+                let interval = if settings.check_interval > 0 {
+                    settings.check_interval as u64
+                } else {
+                    DEFAULT_CHECK_INTERVAL
+                };
+                Method::Failover(
+                    Arc::new(Mutex::new(vec![true; actors.len()])),
+                    Duration::from_secs(interval),
+                )
+            }
+            m => return Err(anyhow!("unknown static method: {}", m)),
+        };
+        Ok(Selector { actors, method })
+    }
+
+    pub fn smart_store(&self) -> Option<Arc<smart::SmartStore>> {
+        match &self.method {
+            Method::Smart(store) => Some(store.clone()),
+            _ => None,
+        }
+    }
+
+    /// Returns the health of the actors and the interval to check them at if
+    /// the method is failover.
+    pub fn failover_health(&self) -> Option<(failover::Health, Duration)> {
+        match &self.method {
+            Method::Failover(health, interval) => Some((health.clone(), *interval)),
+            _ => None,
+        }
+    }
+
+    /// Returns an error for a session no actor was selected for.
+    pub fn no_actor_error(&self) -> io::Error {
+        match &self.method {
+            Method::Failover(..) => io::Error::new(
+                io::ErrorKind::ConnectionRefused,
+                "all actors failed their health checks",
+            ),
+            _ => io::Error::new(io::ErrorKind::Other, "no actor"),
+        }
+    }
+
+    /// Returns the actors to try for the session, in order.
+    pub fn select(&self, sess: &Session) -> Vec<&AnyOutboundHandler> {
+        match &self.method {
+            Method::Random => self
+                .actors
+                .choose(&mut rand::thread_rng())
+                .into_iter()
+                .collect(),
+            Method::Weighted(cumulative) => {
+                let idx = weighted_pick(cumulative, &mut rand::thread_rng());
+                vec![&self.actors[idx]]
+            }
+            Method::RoundRobin(next) => {
+                let idx = next.fetch_add(1, Ordering::Relaxed) % self.actors.len();
+                vec![&self.actors[idx]]
+            }
+            Method::Smart(store) => {
+                let tags: Vec<&str> = self.actors.iter().map(|a| a.tag().as_str()).collect();
+                store
+                    .order(&sess.destination.host(), &tags)
+                    .into_iter()
+                    .map(|i| &self.actors[i])
+                    .collect()
+            }
+            Method::Fallback(_) => self.actors.iter().collect(),
+            Method::Failover(health, _) => {
+                let health = health.lock().unwrap();
+                self.actors
+                    .iter()
+                    .zip(health.iter())
+                    .find(|(_, up)| **up)
+                    .map(|(a, _)| a)
+                    .into_iter()
+                    .collect()
+            }
+        }
+    }
+
+    /// Runs the attempt on an actor, giving up after the fallback timeout
+    /// unless it is the last actor to try.
+    pub async fn attempt<T, F>(&self, last: bool, attempt: F) -> io::Result<T>
+    where
+        F: Future<Output = io::Result<T>>,
+    {
+        match &self.method {
+            // The last actor is the final resort, no reason to give up on it early.
+            Method::Fallback(timeout) if !last => tokio::time::timeout(*timeout, attempt)
+                .await
+                .unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::TimedOut, "timed out"))),
+            _ => attempt.await,
+        }
+    }
+
+    /// Feeds back the handshake latency of an actor, `None` for a failure.
+    pub fn report(&self, sess: &Session, actor: &AnyOutboundHandler, rtt: Option<Duration>) {
+        if let Method::Smart(store) = &self.method {
+            store.update(&sess.destination.host(), actor.tag(), rtt);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_weighted_select() {
+        let actors = || -> Vec<AnyOutboundHandler> {
+            ["a", "b", "c", "d"]
+                .iter()
+                .map(|t| {
+                    outbound::HandlerBuilder::default()
+                        .tag(t.to_string())
+                        .build()
+                })
+                .collect()
+        };
+        let mut settings = config::StaticOutboundSettings::new();
+        settings.method = "weighted".to_string();
+        settings.weights = vec![5, 0, 3];
+        assert!(Selector::new("weighted", actors(), &settings).is_err());
+        settings.weights = vec![0; 4];
+        assert!(Selector::new("weighted", actors(), &settings).is_err());
+
+        settings.weights = vec![5, 0, 3, 2];
+        let selector = Selector::new("weighted", actors(), &settings).unwrap();
+        let sess = Session::default();
+        let mut counts = [0usize; 4];
+        let n = 10_000;
+        for _ in 0..n {
+            let selected = selector.select(&sess);
+            assert_eq!(selected.len(), 1);
+            let i = ["a", "b", "c", "d"]
+                .iter()
+                .position(|t| selected[0].tag() == t)
+                .unwrap();
+            counts[i] += 1;
+        }
+        assert_eq!(counts[1], 0);
+        for (i, w) in settings.weights.iter().enumerate() {
+            let expected = n as f64 * *w as f64 / 10.0;
+            assert!(
+                (counts[i] as f64 - expected).abs() < n as f64 * 0.03,
+                "actor {} picked {} times, expected {}",
+                i,
+                counts[i],
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn test_failover_select() {
+        let actors: Vec<AnyOutboundHandler> = ["a", "b", "c"]
+            .iter()
+            .map(|t| {
+                outbound::HandlerBuilder::default()
+                    .tag(t.to_string())
+                    .build()
+            })
+            .collect();
+        let mut settings = config::StaticOutboundSettings::new();
+        settings.method = "failover".to_string();
+        let selector = Selector::new("failover", actors, &settings).unwrap();
+        let (health, interval) = selector.failover_health().unwrap();
+        assert_eq!(interval, Duration::from_secs(DEFAULT_CHECK_INTERVAL));
+
+        let sess = Session::default();
+        let tags = |s: &Selector| -> Vec<String> {
+            s.select(&sess).iter().map(|a| a.tag().clone()).collect()
+        };
+        assert_eq!(tags(&selector), vec!["a"]);
+        health.lock().unwrap()[0] = false;
+        assert_eq!(tags(&selector), vec!["b"]);
+        health.lock().unwrap()[0] = true;
+        assert_eq!(tags(&selector), vec!["a"]);
+        *health.lock().unwrap() = vec![false; 3];
+        assert!(tags(&selector).is_empty());
+        assert_eq!(
+            selector.no_actor_error().kind(),
+            io::ErrorKind::ConnectionRefused
+        );
+    }
+}
@@ -0,0 +1,152 @@
+use std::fs;
+use std::io::{self, Write};
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use indexmap::IndexMap;
+use log::*;
+use lru::LruCache;
+
+// Weight of the latest sample in the moving average.
+const EWMA_ALPHA: f64 = 0.3;
+// Latency in milliseconds accounted for a failed attempt.
+const FAILURE_PENALTY: f64 = 10_000.0;
+// Maximum number of destinations to remember.
+const MAX_ENTRIES: usize = 4096;
+// Interval in seconds to persist the store.
+const FLUSH_INTERVAL: u64 = 60;
+
+/// Remembers the latency of each actor per destination, persisted to a file
+/// as lines of `destination<TAB>tag<TAB>latency`.
+pub struct SmartStore {
+    path: PathBuf,
+    entries: Mutex<LruCache<String, IndexMap<String, f64>>>,
+    dirty: AtomicBool,
+}
+
+impl SmartStore {
+    pub fn load(path: PathBuf) -> Self {
+        let mut entries = LruCache::new(NonZeroUsize::new(MAX_ENTRIES).unwrap());
+        match fs::read_to_string(&path) {
+            Ok(content) => {
+                for line in content.lines() {
+                    let parts: Vec<&str> = line.split('\t').collect();
+                    if parts.len() != 3 {
+                        continue;
+                    }
+                    if let Ok(rtt) = parts[2].parse::<f64>() {
+                        entries
+                            .get_or_insert_mut(parts[0].to_string(), IndexMap::new)
+                            .insert(parts[1].to_string(), rtt);
+                    }
+                }
+                debug!("loaded {} smart entries from {}", entries.len(), path.display());
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => (),
+            Err(e) => warn!("read smart cache {} failed: {}", path.display(), e),
+        }
+        SmartStore {
+            path,
+            entries: Mutex::new(entries),
+            dirty: AtomicBool::new(false),
+        }
+    }
+
+    /// Returns the indices of `tags` in the order to try for the destination,
+    /// actors never measured come first so that every actor gets a sample.
+    pub fn order(&self, dest: &str, tags: &[&str]) -> Vec<usize> {
+        let mut entries = self.entries.lock().unwrap();
+        let stats = entries.get(dest);
+        let mut order: Vec<(usize, Option<f64>)> = tags
+            .iter()
+            .enumerate()
+            .map(|(i, t)| (i, stats.and_then(|s| s.get(*t).copied())))
+            .collect();
+        // A stable sort keeps the configured order for unmeasured actors.
+        order.sort_by(|a, b| match (a.1, b.1) {
+            (None, None) => std::cmp::Ordering::Equal,
+            (None, Some(_)) => std::cmp::Ordering::Less,
+            (Some(_), None) => std::cmp::Ordering::Greater,
+            (Some(x), Some(y)) => x.total_cmp(&y),
+        });
+        order.into_iter().map(|(i, _)| i).collect()
+    }
+
+    pub fn update(&self, dest: &str, tag: &str, rtt: Option<Duration>) {
+        let sample = rtt.map_or(FAILURE_PENALTY, |d| d.as_millis() as f64);
+        let mut entries = self.entries.lock().unwrap();
+        let stats = entries.get_or_insert_mut(dest.to_string(), IndexMap::new);
+        let rtt = match stats.get(tag) {
+            Some(prev) => EWMA_ALPHA * sample + (1.0 - EWMA_ALPHA) * prev,
+            None => sample,
+        };
+        stats.insert(tag.to_string(), rtt);
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        let mut buf = Vec::new();
+        {
+            let entries = self.entries.lock().unwrap();
+            // Oldest first, so that the most recent ones stay on reloading.
+            for (dest, stats) in entries.iter().rev() {
+                for (tag, rtt) in stats.iter() {
+                    writeln!(buf, "{}\t{}\t{:.1}", dest, tag, rtt)?;
+                }
+            }
+        }
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, buf)?;
+        fs::rename(&tmp, &self.path)
+    }
+}
+
+impl Drop for SmartStore {
+    fn drop(&mut self) {
+        if let Err(e) = self.save() {
+            warn!("save smart cache {} failed: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Persists the store periodically.
+pub async fn flush_task(store: Arc<SmartStore>) {
+    loop {
+        tokio::time::sleep(Duration::from_secs(FLUSH_INTERVAL)).await;
+        if let Err(e) = store.save() {
+            warn!("save smart cache {} failed: {}", store.path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_smart_order() {
+        let path = std::env::temp_dir().join("ostrich_test_smart_order.smart");
+        let _ = fs::remove_file(&path);
+        let tags = ["a", "b", "c"];
+        {
+            let store = SmartStore::load(path.clone());
+            assert_eq!(store.order("example.com", &tags), vec![0, 1, 2]);
+            store.update("example.com", "a", Some(Duration::from_millis(300)));
+            store.update("example.com", "b", Some(Duration::from_millis(100)));
+            store.update("example.com", "c", None);
+            assert_eq!(store.order("example.com", &tags), vec![1, 0, 2]);
+            // Unmeasured destinations keep the configured order.
+            assert_eq!(store.order("example.org", &tags), vec![0, 1, 2]);
+        }
+        // Reloads what has been saved on drop.
+        let store = SmartStore::load(path.clone());
+        assert_eq!(store.order("example.com", &tags), vec![1, 0, 2]);
+        let _ = fs::remove_file(&path);
+    }
+}
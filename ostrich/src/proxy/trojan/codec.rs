@@ -0,0 +1,205 @@
+use std::convert::TryFrom;
+use std::io;
+
+use bytes::{BufMut, BytesMut};
+use sha2::{Digest, Sha224};
+
+use crate::session::{SocksAddr, SocksAddrWireType};
+
+/// Length of the hex encoded SHA-224 password key.
+pub const KEY_LEN: usize = 56;
+
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+
+/// Bytes required to tell the length of a request: key, CRLF, command,
+/// address type and the first address byte. It's shorter than any request.
+pub const PROBE_LEN: usize = KEY_LEN + 2 + 1 + 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Connect,
+    UdpAssociate,
+    // A connect with the payload compressed both ways, an extension only
+    // servers of this project understand, others reject the command.
+    ConnectCompressed,
+}
+
+impl Command {
+    fn to_u8(self) -> u8 {
+        match self {
+            Command::Connect => 0x01,
+            Command::UdpAssociate => 0x03,
+            Command::ConnectCompressed => 0x11,
+        }
+    }
+
+    fn from_u8(cmd: u8) -> io::Result<Self> {
+        match cmd {
+            0x01 => Ok(Command::Connect),
+            0x03 => Ok(Command::UdpAssociate),
+            0x11 => Ok(Command::ConnectCompressed),
+            _ => Err(invalid_data("invalid command")),
+        }
+    }
+}
+
+/// The request header sent by a trojan client after the TLS handshake.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Request {
+    pub key: Vec<u8>,
+    pub command: Command,
+    pub destination: SocksAddr,
+}
+
+fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn need_more() -> io::Error {
+    io::Error::new(io::ErrorKind::UnexpectedEof, "incomplete request")
+}
+
+/// Derives the key sent in requests from the password.
+pub fn password_key(password: &str) -> Vec<u8> {
+    hex::encode(Sha224::digest(password.as_bytes())).into_bytes()
+}
+
+pub fn encode_request(req: &Request, buf: &mut BytesMut) {
+    buf.put_slice(&req.key);
+    buf.put_slice(b"\r\n");
+    buf.put_u8(req.command.to_u8());
+    req.destination
+        .write_buf(buf, SocksAddrWireType::PortLast);
+    buf.put_slice(b"\r\n");
+}
+
+/// Returns the total length of the request starting at `buf`, `None` if
+/// there're not enough bytes to tell yet.
+pub fn request_len(buf: &[u8]) -> io::Result<Option<usize>> {
+    if buf.len() < PROBE_LEN {
+        return Ok(None);
+    }
+    let addr_len = match buf[KEY_LEN + 3] {
+        ATYP_IPV4 => 1 + 4 + 2,
+        ATYP_IPV6 => 1 + 16 + 2,
+        ATYP_DOMAIN => 1 + 1 + buf[KEY_LEN + 4] as usize + 2,
+        _ => return Err(invalid_data("invalid address type")),
+    };
+    Ok(Some(KEY_LEN + 2 + 1 + addr_len + 2))
+}
+
+/// Decodes a request from the beginning of `buf`, returns it along with the
+/// number of bytes consumed. An `UnexpectedEof` error means more bytes are
+/// needed.
+pub fn decode_request(buf: &[u8]) -> io::Result<(Request, usize)> {
+    let len = request_len(buf)?.ok_or_else(need_more)?;
+    if buf.len() < len {
+        return Err(need_more());
+    }
+    if &buf[KEY_LEN..KEY_LEN + 2] != b"\r\n" {
+        return Err(invalid_data("expected CRLF after key"));
+    }
+    let command = Command::from_u8(buf[KEY_LEN + 2])?;
+    let destination =
+        SocksAddr::try_from((&buf[KEY_LEN + 3..len - 2], SocksAddrWireType::PortLast))?;
+    if &buf[len - 2..len] != b"\r\n" {
+        return Err(invalid_data("expected CRLF after address"));
+    }
+    Ok((
+        Request {
+            key: buf[..KEY_LEN].to_vec(),
+            command,
+            destination,
+        },
+        len,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(req: &Request) -> Vec<u8> {
+        let mut buf = BytesMut::new();
+        encode_request(req, &mut buf);
+        buf.to_vec()
+    }
+
+    #[test]
+    fn test_request_roundtrip() {
+        let reqs = vec![
+            Request {
+                key: password_key("password"),
+                command: Command::Connect,
+                destination: SocksAddr::Ip("1.2.3.4:443".parse().unwrap()),
+            },
+            Request {
+                key: password_key("password"),
+                command: Command::UdpAssociate,
+                destination: SocksAddr::Ip("[::1]:53".parse().unwrap()),
+            },
+            Request {
+                key: password_key("password"),
+                command: Command::Connect,
+                destination: SocksAddr::Ip("[2606:4700::]:443".parse().unwrap()),
+            },
+            Request {
+                key: password_key("password"),
+                command: Command::Connect,
+                destination: SocksAddr::Domain("example.com".to_string(), 80),
+            },
+        ];
+        for req in reqs {
+            let mut buf = encode(&req);
+            let len = buf.len();
+            // Trailing payload is left untouched.
+            buf.extend_from_slice(b"payload");
+            assert_eq!(decode_request(&buf).unwrap(), (req, len));
+        }
+    }
+
+    #[test]
+    fn test_decode_malformed() {
+        let buf = encode(&Request {
+            key: password_key("password"),
+            command: Command::Connect,
+            destination: SocksAddr::Domain("example.com".to_string(), 80),
+        });
+        // Every truncation asks for more bytes.
+        for i in 0..buf.len() {
+            let err = decode_request(&buf[..i]).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        }
+
+        let mut bad = buf.clone();
+        bad[KEY_LEN] = b'x';
+        assert_eq!(
+            decode_request(&bad).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        let mut bad = buf.clone();
+        bad[KEY_LEN + 2] = 0x02;
+        assert_eq!(
+            decode_request(&bad).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        let mut bad = buf.clone();
+        bad[KEY_LEN + 3] = 0x09;
+        assert_eq!(
+            decode_request(&bad).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        let mut bad = buf.clone();
+        let n = bad.len();
+        bad[n - 1] = b'x';
+        assert_eq!(
+            decode_request(&bad).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+    }
+}
@@ -7,11 +7,11 @@ use async_trait::async_trait;
 use byteorder::{BigEndian, ByteOrder};
 use bytes::{BufMut, BytesMut};
 use futures::TryFutureExt;
-use sha2::{Digest, Sha224};
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 
 use crate::{
-    proxy::*,
+    common::compress::CompressStream,
+    proxy::{trojan::codec, *},
     session::{DatagramSource, Network, Session, SocksAddr, SocksAddrWireType},
 };
 
@@ -123,9 +123,7 @@ impl Handler {
     pub fn new(passwords: Vec<String>) -> Self {
         let mut keys = IndexMap::new();
         for pass in passwords {
-            let key = Sha224::digest(pass.as_bytes());
-            let key = hex::encode(&key[..]);
-            keys.insert(key.as_bytes().to_vec(), ());
+            keys.insert(codec::password_key(&pass), ());
         }
         Handler { keys }
     }
@@ -138,31 +136,27 @@ impl InboundStreamHandler for Handler {
         mut sess: Session,
         mut stream: AnyStream,
     ) -> std::io::Result<AnyInboundTransport> {
+        // Read just enough to tell the header length and then the rest of
+        // it, what follows the header is payload.
         let mut buf = BytesMut::new();
-        // read key
-        buf.resize(56, 0);
+        buf.resize(codec::PROBE_LEN, 0);
         stream.read_exact(&mut buf).await?;
-        if !self.keys.contains_key(&buf[..]) {
+        let len = codec::request_len(&buf)?
+            .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "invalid request"))?;
+        buf.resize(len, 0);
+        stream.read_exact(&mut buf[codec::PROBE_LEN..]).await?;
+        let (req, _) = codec::decode_request(&buf)?;
+        if !self.keys.contains_key(&req.key) {
             return Err(io::Error::new(io::ErrorKind::Other, "invalid key"));
         }
-        // read crlf
-        buf.resize(2, 0);
-        stream.read_exact(&mut buf).await?;
-        // read cmd
-        buf.resize(1, 0);
-        stream.read_exact(&mut buf).await?;
-        let cmd = buf[0];
-        // read addr
-        let dst_addr = SocksAddr::read_from(&mut stream, SocksAddrWireType::PortLast).await?;
-        sess.destination = dst_addr;
-        // read crlf
-        buf.resize(2, 0);
-        stream.read_exact(&mut buf).await?;
-        match cmd {
-            // tcp
-            0x01 => Ok(InboundTransport::Stream(stream, sess)),
-            // udp
-            0x03 => {
+        sess.destination = req.destination;
+        match req.command {
+            codec::Command::Connect => Ok(InboundTransport::Stream(stream, sess)),
+            codec::Command::ConnectCompressed => Ok(InboundTransport::Stream(
+                Box::new(CompressStream::new(stream)),
+                sess,
+            )),
+            codec::Command::UdpAssociate => {
                 sess.network = Network::Udp;
                 Ok(InboundTransport::Datagram(
                     Box::new(Datagram::new(
@@ -172,7 +166,6 @@ impl InboundStreamHandler for Handler {
                     Some(sess),
                 ))
             }
-            _ => Err(io::Error::new(io::ErrorKind::Other, "invalid command")),
         }
     }
 }
@@ -1,3 +1,5 @@
+pub mod codec;
+
 #[cfg(feature = "inbound-trojan")]
 pub mod inbound;
 #[cfg(feature = "outbound-trojan")]
@@ -2,7 +2,6 @@ use async_trait::async_trait;
 use byteorder::{BigEndian, ByteOrder};
 use bytes::{BufMut, BytesMut};
 use futures::future::TryFutureExt;
-use sha2::{Digest, Sha224};
 use std::cmp::min;
 use std::convert::TryFrom;
 use std::io;
@@ -11,9 +10,13 @@ use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf, Wr
 use crate::session::*;
 
 use crate::{
-    proxy::*,
+    app::SyncDnsClient,
+    common::tls::TlsConfig,
+    proxy::{trojan::codec, *},
     session::{Session, SocksAddr, SocksAddrWireType},
 };
+
+use super::Endpoints;
 fn tls_err<E>(_error: E) -> io::Error
 where
     E: Into<Box<dyn std::error::Error + Send + Sync>>,
@@ -23,25 +26,24 @@ where
         format!("tls error: {:?}", _error.into()),
     )
 }
-use {
-    std::sync::Arc,
-    tokio_rustls::{rustls::ClientConfig, TlsConnector},
-};
+use {std::sync::Arc, tokio_rustls::TlsConnector};
 
 pub struct Handler {
-    pub address: String,
-    pub port: u16,
+    pub endpoints: Arc<Endpoints>,
+    pub dns_client: SyncDnsClient,
+    pub dscp: Option<u8>,
+    pub fwmark: Option<u32>,
     pub password: String,
 
     pub server_name: String,
 
-    pub tls_config: Arc<ClientConfig>,
+    pub tls_config: Arc<TlsConfig>,
 }
 
 #[async_trait]
 impl OutboundDatagramHandler for Handler {
     fn connect_addr(&self) -> OutboundConnect {
-        OutboundConnect::Proxy(Network::Tcp, self.address.clone(), self.port)
+        self.endpoints.connect_addr()
     }
 
     fn transport_type(&self) -> DatagramTransportType {
@@ -53,10 +55,14 @@ impl OutboundDatagramHandler for Handler {
         sess: &'a Session,
         transport: Option<AnyOutboundTransport>,
     ) -> io::Result<AnyOutboundDatagram> {
-        let stream = if let Some(OutboundTransport::Stream(stream)) = transport {
-            stream
-        } else {
-            return Err(io::Error::new(io::ErrorKind::Other, "invalid input"));
+        let stream = match transport {
+            Some(OutboundTransport::Stream(stream)) => stream,
+            None => {
+                self.endpoints
+                    .dial(self.dns_client.clone(), self.dscp, self.fwmark)
+                    .await?
+            }
+            _ => return Err(io::Error::new(io::ErrorKind::Other, "invalid input")),
         };
 
         let name = if !&self.server_name.is_empty() {
@@ -64,7 +70,7 @@ impl OutboundDatagramHandler for Handler {
         } else {
             sess.destination.host()
         };
-        let config = TlsConnector::from(self.tls_config.clone());
+        let config = TlsConnector::from(self.tls_config.get());
         // let dnsname = DNSNameRef::try_from_ascii_str(&name).map_err(tls_err)?;
         let dnsname = tokio_rustls::rustls::ServerName::try_from(name.as_str()).map_err(|_| {
             io::Error::new(
@@ -75,14 +81,14 @@ impl OutboundDatagramHandler for Handler {
         let stream = config.connect(dnsname, stream).map_err(tls_err).await?;
 
         let mut buf = BytesMut::new();
-        let password = Sha224::digest(self.password.as_bytes());
-        let password = hex::encode(&password[..]);
-        buf.put_slice(password.as_bytes());
-        buf.put_slice(b"\r\n");
-        buf.put_u8(0x03); // udp
-        sess.destination
-            .write_buf(&mut buf, SocksAddrWireType::PortLast);
-        buf.put_slice(b"\r\n");
+        codec::encode_request(
+            &codec::Request {
+                key: codec::password_key(&self.password),
+                command: codec::Command::UdpAssociate,
+                destination: sess.destination.clone(),
+            },
+            &mut buf,
+        );
 
         let destination = match &sess.destination {
             SocksAddr::Domain(domain, port) => {
@@ -179,14 +185,16 @@ where
         data.put_slice(buf);
 
         // Writes the header along with the first payload.
-        if self.1.is_some() {
-            if let Some(mut head) = self.1.take() {
-                head.extend_from_slice(&data);
-                return self.0.write_all(&head).map_ok(|_| payload_size).await;
-            }
+        if let Some(mut head) = self.1.take() {
+            head.extend_from_slice(&data);
+            data = head;
         }
 
-        self.0.write_all(&data).map_ok(|_| payload_size).await
+        // Flushes every packet, the TLS stream would otherwise hold it back
+        // until the next write.
+        self.0.write_all(&data).await?;
+        self.0.flush().await?;
+        Ok(payload_size)
     }
 
     async fn close(&mut self) -> io::Result<()> {
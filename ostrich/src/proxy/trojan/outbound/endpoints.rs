@@ -0,0 +1,105 @@
+use std::io;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use anyhow::{anyhow, Result};
+
+use crate::{app::SyncDnsClient, proxy::*};
+
+/// The endpoints of a trojan server, shared by the TCP and UDP handlers.
+pub struct Endpoints {
+    servers: Vec<(String, u16)>,
+    next: AtomicUsize,
+}
+
+impl Endpoints {
+    /// Takes the address and port of the server, followed by more endpoints
+    /// in the form of `address:port`.
+    pub fn new(address: &str, port: u16, servers: &[String]) -> Result<Self> {
+        let mut endpoints = Vec::new();
+        if !address.is_empty() {
+            endpoints.push((address.to_string(), port));
+        }
+        for server in servers {
+            let (address, port) = server
+                .rsplit_once(':')
+                .and_then(|(a, p)| Some((a, p.parse::<u16>().ok()?)))
+                .ok_or_else(|| anyhow!("invalid server {}", server))?;
+            let address = address.trim_start_matches('[').trim_end_matches(']');
+            endpoints.push((address.to_string(), port));
+        }
+        if endpoints.is_empty() {
+            return Err(anyhow!("missing server address"));
+        }
+        Ok(Endpoints {
+            servers: endpoints,
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    /// The endpoint after the one handed out last time, the caller dials it
+    /// and asks for another one if that fails.
+    pub fn connect_addr(&self) -> OutboundConnect {
+        let n = self.servers.len();
+        let (address, port) = &self.servers[self.next.fetch_add(1, Ordering::Relaxed) % n];
+        OutboundConnect::Proxy(Network::Tcp, address.clone(), *port)
+    }
+
+    /// Dials the endpoints in turn, starting from the one after the endpoint
+    /// dialed last time, until one is connected.
+    pub async fn dial(
+        &self,
+        dns_client: SyncDnsClient,
+        dscp: Option<u8>,
+        fwmark: Option<u32>,
+    ) -> io::Result<AnyStream> {
+        let n = self.servers.len();
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let mut last_err = None;
+        for i in 0..n {
+            let (address, port) = &self.servers[(start + i) % n];
+            match new_marked_tcp_stream(dns_client.clone(), address, port, dscp, fwmark).await {
+                Ok(stream) => return Ok(stream),
+                Err(e) => {
+                    log::debug!("dial trojan server {}:{} failed: {}", address, port, e);
+                    last_err = Some(e);
+                }
+            }
+        }
+        Err(last_err.unwrap_or_else(|| io::Error::new(io::ErrorKind::Other, "no server")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_endpoints() {
+        let servers = vec!["1.2.3.5:8443".to_string(), "[::1]:443".to_string()];
+        let endpoints = Endpoints::new("1.2.3.4", 443, &servers).unwrap();
+        assert_eq!(
+            endpoints.servers,
+            vec![
+                ("1.2.3.4".to_string(), 443),
+                ("1.2.3.5".to_string(), 8443),
+                ("::1".to_string(), 443),
+            ]
+        );
+        // Handed out in turn.
+        for (address, port) in endpoints.servers.iter().chain(endpoints.servers.iter()) {
+            assert!(matches!(
+                endpoints.connect_addr(),
+                OutboundConnect::Proxy(Network::Tcp, a, p) if &a == address && p == *port
+            ));
+        }
+
+        let endpoints = Endpoints::new("", 0, &servers[..1]).unwrap();
+        assert!(matches!(
+            endpoints.connect_addr(),
+            OutboundConnect::Proxy(Network::Tcp, a, 8443) if a == "1.2.3.5"
+        ));
+
+        assert!(Endpoints::new("1.2.3.4", 443, &["1.2.3.5".to_string()]).is_err());
+        assert!(Endpoints::new("", 0, &[]).is_err());
+    }
+}
@@ -1,6 +1,7 @@
 pub mod datagram;
+pub mod endpoints;
 pub mod stream;
-pub mod tls;
 
 pub use datagram::Handler as DatagramHandler;
+pub use endpoints::Endpoints;
 pub use stream::Handler as StreamHandler;
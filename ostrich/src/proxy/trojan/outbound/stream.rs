@@ -1,19 +1,24 @@
 use async_trait::async_trait;
-use bytes::{BufMut, BytesMut};
-use sha2::{Digest, Sha224};
+use bytes::{Buf, BytesMut};
+use futures::{ready, Future};
 use std::convert::TryFrom;
 use std::io;
-use tokio::io::AsyncWriteExt;
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::time::{sleep, Sleep};
 
 use crate::{
-    proxy::*,
-    session::{Session, SocksAddrWireType},
+    app::SyncDnsClient,
+    common::{compress::CompressStream, tls::TlsConfig},
+    proxy::{trojan::codec, *},
+    session::Session,
 };
 
-use {
-    std::sync::Arc,
-    tokio_rustls::{rustls::ClientConfig, TlsConnector},
-};
+use super::Endpoints;
+
+use {std::sync::Arc, tokio_rustls::TlsConnector};
 fn tls_err<E>(_error: E) -> io::Error
 where
     E: Into<Box<dyn std::error::Error + Send + Sync>>,
@@ -24,18 +29,21 @@ where
     )
 }
 pub struct Handler {
-    pub address: String,
-    pub port: u16,
+    pub endpoints: Arc<Endpoints>,
+    pub dns_client: SyncDnsClient,
+    pub dscp: Option<u8>,
+    pub fwmark: Option<u32>,
     pub password: String,
 
     pub server_name: String,
-    pub tls_config: Arc<ClientConfig>,
+    pub tls_config: Arc<TlsConfig>,
+    pub compress: bool,
 }
 
 #[async_trait]
 impl OutboundStreamHandler for Handler {
     fn connect_addr(&self) -> OutboundConnect {
-        OutboundConnect::Proxy(Network::Tcp, self.address.clone(), self.port)
+        self.endpoints.connect_addr()
     }
 
     async fn handle<'a>(
@@ -43,7 +51,14 @@ impl OutboundStreamHandler for Handler {
         sess: &'a Session,
         stream: Option<AnyStream>,
     ) -> io::Result<AnyStream> {
-        let stream = stream.ok_or_else(|| io::Error::new(io::ErrorKind::Other, "invalid input"))?;
+        let stream = match stream {
+            Some(stream) => stream,
+            None => {
+                self.endpoints
+                    .dial(self.dns_client.clone(), self.dscp, self.fwmark)
+                    .await?
+            }
+        };
 
         let name = if !&self.server_name.is_empty() {
             self.server_name.clone()
@@ -51,7 +66,7 @@ impl OutboundStreamHandler for Handler {
             sess.destination.host()
         };
 
-        let config = TlsConnector::from(self.tls_config.clone());
+        let config = TlsConnector::from(self.tls_config.get());
         // let dnsname = DNSNameRef::try_from_ascii_str(&name).map_err(tls_err)?;
 
         let dnsname = tokio_rustls::rustls::ServerName::try_from(name.as_str()).map_err(|_| {
@@ -63,17 +78,270 @@ impl OutboundStreamHandler for Handler {
 
         let mut stream = config.connect(dnsname, stream).map_err(tls_err).await?;
 
+        let request = codec::Request {
+            key: codec::password_key(&self.password),
+            command: codec::Command::Connect,
+            destination: sess.destination.clone(),
+        };
+        if self.compress {
+            return Ok(Box::new(RequestStream::new(stream, request)));
+        }
         let mut buf = BytesMut::new();
-        let password = Sha224::digest(self.password.as_bytes());
-        let password = hex::encode(&password[..]);
-        buf.put_slice(password.as_bytes());
-        buf.put_slice(b"\r\n");
-        buf.put_u8(0x01); // tcp
-        sess.destination
-            .write_buf(&mut buf, SocksAddrWireType::PortLast);
-        buf.put_slice(b"\r\n");
-        // FIXME combine header and first payload
+        codec::encode_request(&request, &mut buf);
+        // The TLS stream may accept the header only partially or keep it
+        // buffered on slow links, make sure it's entirely sent before handing
+        // the stream over, the server won't talk before it gets the header.
         stream.write_all(&buf).await?;
+        stream.flush().await?;
         Ok(Box::new(stream))
     }
 }
+
+// The stream before and after switching to compression.
+enum Inner<S> {
+    Raw(S),
+    Compressed(CompressStream<S>),
+    Switching,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Inner<S> {
+    fn compress(&mut self) {
+        if let Inner::Raw(stream) = std::mem::replace(self, Inner::Switching) {
+            *self = Inner::Compressed(CompressStream::new(stream));
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for Inner<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut ReadBuf,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Inner::Raw(s) => Pin::new(s).poll_read(cx, buf),
+            Inner::Compressed(s) => Pin::new(s).poll_read(cx, buf),
+            Inner::Switching => unreachable!(),
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for Inner<S> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Inner::Raw(s) => Pin::new(s).poll_write(cx, buf),
+            Inner::Compressed(s) => Pin::new(s).poll_write(cx, buf),
+            Inner::Switching => unreachable!(),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Inner::Raw(s) => Pin::new(s).poll_flush(cx),
+            Inner::Compressed(s) => Pin::new(s).poll_flush(cx),
+            Inner::Switching => unreachable!(),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Inner::Raw(s) => Pin::new(s).poll_shutdown(cx),
+            Inner::Compressed(s) => Pin::new(s).poll_shutdown(cx),
+            Inner::Switching => unreachable!(),
+        }
+    }
+}
+
+// With compression enabled, holds the request back until the first payload
+// byte tells whether the payload is TLS, which is never compressed, see
+// `CompressStream`. When the client doesn't write first, e.g. the server
+// speaks first, the request goes out uncompressed after waiting as long as
+// sniffing would.
+struct RequestStream<S> {
+    inner: Inner<S>,
+    // The request not encoded yet.
+    request: Option<codec::Request>,
+    // Whether to switch to compression once the request is written, decided
+    // when encoding it.
+    compress: bool,
+    // The encoded request not written yet.
+    wbuf: BytesMut,
+    // Whether the request was sent by a read and must be flushed.
+    flush: bool,
+    delay: Option<Pin<Box<Sleep>>>,
+    // The read waiting for the first write.
+    read_waker: Option<Waker>,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> RequestStream<S> {
+    fn new(inner: S, request: codec::Request) -> Self {
+        Self {
+            inner: Inner::Raw(inner),
+            request: Some(request),
+            compress: true,
+            wbuf: BytesMut::new(),
+            flush: false,
+            delay: None,
+            read_waker: None,
+        }
+    }
+
+    // Encodes the request unless done already, compressed if `first`, the
+    // first payload byte, is known and doesn't start a TLS record, and writes
+    // it out.
+    fn poll_send_request(&mut self, cx: &mut Context, first: Option<u8>) -> Poll<io::Result<()>> {
+        if let Some(mut request) = self.request.take() {
+            self.compress = first.map_or(false, |b| b != 0x16);
+            if self.compress {
+                request.command = codec::Command::ConnectCompressed;
+            }
+            codec::encode_request(&request, &mut self.wbuf);
+            if let Some(waker) = self.read_waker.take() {
+                waker.wake();
+            }
+        }
+        while !self.wbuf.is_empty() {
+            let n = ready!(Pin::new(&mut self.inner).poll_write(cx, &self.wbuf))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.wbuf.advance(n);
+        }
+        if self.compress {
+            self.inner.compress();
+            self.compress = false;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for RequestStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+        buf: &mut ReadBuf,
+    ) -> Poll<io::Result<()>> {
+        let me = self.get_mut();
+        if me.request.is_some() {
+            let delay = me.delay.get_or_insert_with(|| {
+                Box::pin(sleep(Duration::from_millis(
+                    *crate::option::SNIFF_TIMEOUT_MS,
+                )))
+            });
+            if delay.as_mut().poll(cx).is_pending() {
+                me.read_waker = Some(cx.waker().clone());
+                return Poll::Pending;
+            }
+            // The server won't talk before it gets the request.
+            me.flush = true;
+        }
+        ready!(me.poll_send_request(cx, None))?;
+        if me.flush {
+            ready!(Pin::new(&mut me.inner).poll_flush(cx))?;
+            me.flush = false;
+        }
+        Pin::new(&mut me.inner).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for RequestStream<S> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context, buf: &[u8]) -> Poll<io::Result<usize>> {
+        let me = self.get_mut();
+        if buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+        ready!(me.poll_send_request(cx, Some(buf[0])))?;
+        Pin::new(&mut me.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let me = self.get_mut();
+        // Nothing's written yet.
+        if me.request.is_some() {
+            return Poll::Ready(Ok(()));
+        }
+        ready!(me.poll_send_request(cx, None))?;
+        Pin::new(&mut me.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let me = self.get_mut();
+        ready!(me.poll_send_request(cx, None))?;
+        Pin::new(&mut me.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use crate::session::SocksAddr;
+
+    fn request() -> codec::Request {
+        codec::Request {
+            key: codec::password_key("password"),
+            command: codec::Command::Connect,
+            destination: SocksAddr::Domain("example.com".to_string(), 443),
+        }
+    }
+
+    // Reads what the server got, returns the command and the payload.
+    async fn received(wire: &[u8]) -> (codec::Command, Vec<u8>) {
+        let (req, len) = codec::decode_request(wire).unwrap();
+        let mut payload = Vec::new();
+        if req.command == codec::Command::ConnectCompressed {
+            CompressStream::new(&wire[len..])
+                .read_to_end(&mut payload)
+                .await
+                .unwrap();
+        } else {
+            payload.extend_from_slice(&wire[len..]);
+        }
+        (req.command, payload)
+    }
+
+    #[test]
+    fn test_request_stream() {
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let client_hello = [0x16, 0x03, 0x01, 0x00, 0x05, 1, 2, 3, 4, 5];
+            let text = b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n";
+            for (payload, command) in [
+                (&client_hello[..], codec::Command::Connect),
+                (&text[..], codec::Command::ConnectCompressed),
+            ] {
+                let (a, mut b) = tokio::io::duplex(64 * 1024);
+                let mut a = RequestStream::new(a, request());
+                a.write_all(payload).await.unwrap();
+                a.shutdown().await.unwrap();
+                let mut wire = Vec::new();
+                b.read_to_end(&mut wire).await.unwrap();
+                assert_eq!(received(&wire).await, (command, payload.to_vec()));
+            }
+
+            // The server speaks first, the request goes out once the client
+            // has waited for a while.
+            let (a, mut b) = tokio::io::duplex(64 * 1024);
+            let mut a = RequestStream::new(a, request());
+            let server = async move {
+                let mut buf = vec![0u8; 1024];
+                let n = b.read(&mut buf).await.unwrap();
+                assert_eq!(
+                    received(&buf[..n]).await,
+                    (codec::Command::Connect, Vec::new())
+                );
+                b.write_all(b"220 ready\r\n").await.unwrap();
+            };
+            let client = async move {
+                let mut buf = vec![0u8; 1024];
+                let n = a.read(&mut buf).await.unwrap();
+                assert_eq!(&buf[..n], b"220 ready\r\n");
+            };
+            futures::future::join(server, client).await;
+        });
+    }
+}
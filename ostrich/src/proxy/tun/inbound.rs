@@ -1,4 +1,6 @@
-use std::net::SocketAddr;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
 
 use anyhow::{anyhow, Result};
@@ -6,12 +8,14 @@ use futures::{sink::SinkExt, stream::StreamExt};
 use log::*;
 use protobuf::Message;
 use tokio::sync::mpsc::channel as tokio_channel;
+use tokio::sync::mpsc::error::TrySendError;
 use tokio::sync::mpsc::{Receiver as TokioReceiver, Sender as TokioSender};
 use tun::{self, TunPacket};
 
 use crate::{
     app::dispatcher::Dispatcher,
     app::fake_dns::{FakeDns, FakeDnsMode},
+    app::inbound::manager::{TunDevice, TunTraffic},
     app::nat_manager::NatManager,
     app::nat_manager::UdpPacket,
     config::{Inbound, TunInboundSettings},
@@ -82,7 +86,8 @@ async fn handle_inbound_datagram(
             let src_addr = match pkt.src_addr {
                 SocksAddr::Ip(a) => a,
                 SocksAddr::Domain(domain, port) => {
-                    if let Some(ip) = fakedns_cloned.query_fake_ip(&domain).await {
+                    let ipv6 = pkt.dst_addr.must_ip().is_ipv6();
+                    if let Some(ip) = fakedns_cloned.query_fake_ip(&domain, ipv6).await {
                         SocketAddr::new(ip, port)
                     } else {
                         warn!(
@@ -99,13 +104,24 @@ async fn handle_inbound_datagram(
         }
     });
 
-    // Accept datagrams from netstack and send to NAT manager.
-    loop {
-        match lr.recv_from().await {
-            Err(e) => {
-                log::warn!("Failed to accept a datagram from netstack: {}", e);
-            }
-            Ok((data, src_addr, dst_addr)) => {
+    // Datagrams from netstack are handed to the workers by the hash of their
+    // source, so that the packets of a flow stay in order and its NAT session
+    // is set up by a single worker.
+    let n_workers = std::cmp::max(1, *option::TUN_WORKERS);
+    let mut workers = Vec::with_capacity(n_workers);
+    for _ in 0..n_workers {
+        let (w_tx, mut w_rx): (
+            TokioSender<(Vec<u8>, SocketAddr, SocketAddr)>,
+            TokioReceiver<(Vec<u8>, SocketAddr, SocketAddr)>,
+        ) = tokio_channel(*crate::option::NETSTACK_UDP_UPLINK_CHANNEL_SIZE);
+        workers.push(w_tx);
+        let ls = ls.clone();
+        let l_tx = l_tx.clone();
+        let inbound_tag = inbound_tag.clone();
+        let nat_manager = nat_manager.clone();
+        let fakedns = fakedns.clone();
+        tokio::spawn(async move {
+            while let Some((data, src_addr, dst_addr)) = w_rx.recv().await {
                 // Fake DNS logic.
                 if dst_addr.port() == 53 {
                     match fakedns.generate_fake_response(&data).await {
@@ -153,24 +169,89 @@ async fn handle_inbound_datagram(
                     .send(None, &dgram_src, &inbound_tag, &l_tx, pkt)
                     .await;
             }
+        });
+    }
+
+    // Accept datagrams from netstack and send to the workers.
+    loop {
+        match lr.recv_from().await {
+            Err(e) => {
+                log::warn!("Failed to accept a datagram from netstack: {}", e);
+            }
+            Ok((data, src_addr, dst_addr)) => {
+                let worker = &workers[worker_index(&src_addr, n_workers)];
+                if worker.send((data, src_addr, dst_addr)).await.is_err() {
+                    return;
+                }
+            }
         }
     }
 }
 
+// Picks the worker of the flow from the given source.
+fn worker_index(src_addr: &SocketAddr, n_workers: usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    src_addr.hash(&mut hasher);
+    (hasher.finish() % n_workers as u64) as usize
+}
+
+// Returns the source of the IP packet, the port is 0 if it's not TCP or UDP,
+// or a fragment, so that all the fragments go to the same worker.
+fn packet_source(pkt: &[u8]) -> Option<SocketAddr> {
+    let (ip, protocol, offset, fragment) = match pkt.first()? >> 4 {
+        4 if pkt.len() >= 20 => {
+            let ip: [u8; 4] = pkt[12..16].try_into().unwrap();
+            let fragment = pkt[6] & 0x3f != 0 || pkt[7] != 0;
+            (
+                IpAddr::from(ip),
+                pkt[9],
+                (pkt[0] & 0x0f) as usize * 4,
+                fragment,
+            )
+        }
+        6 if pkt.len() >= 40 => {
+            let ip: [u8; 16] = pkt[8..24].try_into().unwrap();
+            (IpAddr::from(ip), pkt[6], 40, false)
+        }
+        _ => return None,
+    };
+    let port = match protocol {
+        6 | 17 if !fragment && pkt.len() >= offset + 2 => {
+            u16::from_be_bytes([pkt[offset], pkt[offset + 1]])
+        }
+        _ => 0,
+    };
+    Some(SocketAddr::new(ip, port))
+}
+
 pub fn new(
     inbound: Inbound,
     dispatcher: Arc<Dispatcher>,
     nat_manager: Arc<NatManager>,
+    traffic: Arc<TunTraffic>,
 ) -> Result<Runner> {
     let settings = TunInboundSettings::parse_from_bytes(&inbound.settings)?;
+    let device = TunDevice::from_inbounds(std::slice::from_ref(&inbound), &[])?;
 
     let mut cfg = tun::Configuration::default();
     if settings.fd >= 0 {
         cfg.raw_fd(settings.fd);
+    } else if settings.existing {
+        if !cfg!(target_os = "linux") {
+            return Err(anyhow!(
+                "attaching to an existing tun device requires Linux"
+            ));
+        }
+        if settings.name.is_empty() {
+            return Err(anyhow!("missing the name of the existing tun device"));
+        }
+        // The device is set up by its owner, configuring it would require
+        // privileges we may not have.
+        cfg.name(&settings.name);
     } else if settings.auto {
-        cfg.name(&*option::DEFAULT_TUN_NAME)
-            .address(&*option::DEFAULT_TUN_IPV4_ADDR)
-            .destination(&*option::DEFAULT_TUN_IPV4_GW)
+        cfg.name(&device.name)
+            .address(&device.address)
+            .destination(&device.gateway)
             .mtu(1500);
 
         #[cfg(not(any(
@@ -180,7 +261,7 @@ pub fn new(
             target_arch = "mipsel64",
         )))]
         {
-            cfg.netmask(&*option::DEFAULT_TUN_IPV4_MASK);
+            cfg.netmask(&device.netmask);
         }
 
         cfg.up();
@@ -219,6 +300,19 @@ pub fn new(
 
     let tun = tun::create_as_async(&cfg).map_err(|e| anyhow!("create tun failed: {}", e))?;
 
+    // Gives the device a unique local IPv6 address, so that IPv6 destinations
+    // are proxied as well without a routable prefix, the auto mode does it
+    // along with the routes.
+    #[cfg(any(target_os = "macos", target_os = "linux"))]
+    if *option::ENABLE_IPV6 && !settings.auto && settings.fd < 0 && !settings.existing {
+        use tun::Device;
+        crate::sys::apply(crate::sys::Change::AddIpv6Address {
+            interface: tun.get_ref().name().to_string(),
+            addr: device.ipv6_address.parse()?,
+            prefixlen: device.ipv6_prefixlen,
+        })?;
+    }
+
     if settings.auto {
         assert!(settings.fd == -1, "tun-auto is not compatible with tun-fd");
     }
@@ -236,32 +330,77 @@ pub fn new(
             *crate::option::NETSTACK_OUTPUT_CHANNEL_SIZE,
             *crate::option::NETSTACK_UDP_UPLINK_CHANNEL_SIZE,
         );
-        let (mut stack_sink, mut stack_stream) = stack.split();
+        let (stack_sink, mut stack_stream) = stack.split();
 
         let mut futs: Vec<Runner> = Vec::new();
 
         // Reads packet from stack and sends to TUN.
+        let traffic_tx = traffic.clone();
         futs.push(Box::pin(async move {
             while let Some(pkt) = stack_stream.next().await {
                 if let Ok(pkt) = pkt {
+                    let n = pkt.len() as u64;
                     if let Err(e) = tun_sink.send(TunPacket::new(pkt)).await {
                         // TODO Return the error
                         log::error!("Sending packet to TUN failed: {}", e);
                         return;
                     }
+                    traffic_tx.record_tx(n);
                 }
             }
         }));
 
-        // Reads packet from TUN and sends to stack.
-        futs.push(Box::pin(async move {
-            while let Some(pkt) = tun_stream.next().await {
-                if let Ok(pkt) = pkt {
+        // A bounded queue between TUN and stack for each worker, the packets
+        // are handed to the workers by the hash of their source, so that the
+        // packets of a flow stay in order. When a queue is full, reading from
+        // TUN either pauses or the packets are dropped.
+        let n_workers = std::cmp::max(1, *option::TUN_WORKERS);
+        let stack_sink = Arc::new(tokio::sync::Mutex::new(stack_sink));
+        let mut queues = Vec::with_capacity(n_workers);
+        for _ in 0..n_workers {
+            let (queue_tx, mut queue_rx) =
+                tokio_channel::<TunPacket>(std::cmp::max(1, *option::TUN_QUEUE_SIZE));
+            queues.push(queue_tx);
+            let stack_sink = stack_sink.clone();
+            // Reads packet from the queue and sends to stack, the worker exits
+            // once reading from TUN stops.
+            tokio::spawn(async move {
+                while let Some(pkt) = queue_rx.recv().await {
+                    let mut stack_sink = stack_sink.lock().await;
                     if let Err(e) = stack_sink.send(pkt.into_bytes().into()).await {
                         log::error!("Sending packet to NetStack failed: {}", e);
                         return;
                     }
                 }
+            });
+        }
+
+        // Reads packet from TUN and sends to the queue of its worker.
+        futs.push(Box::pin(async move {
+            let mut dropped: u64 = 0;
+            while let Some(pkt) = tun_stream.next().await {
+                if let Ok(pkt) = pkt {
+                    traffic.record_rx(pkt.get_bytes().len() as u64);
+                    let queue_tx = &queues[packet_source(pkt.get_bytes())
+                        .map_or(0, |src| worker_index(&src, n_workers))];
+                    if !*option::TUN_QUEUE_DROP {
+                        if queue_tx.send(pkt).await.is_err() {
+                            return;
+                        }
+                        continue;
+                    }
+                    match queue_tx.try_send(pkt) {
+                        Ok(()) => (),
+                        Err(TrySendError::Full(_)) => {
+                            traffic.record_dropped();
+                            dropped += 1;
+                            if dropped % 1000 == 1 {
+                                log::warn!("TUN queue is full, {} packets dropped", dropped);
+                            }
+                        }
+                        Err(TrySendError::Closed(_)) => return,
+                    }
+                }
             }
         }));
 
@@ -291,3 +430,49 @@ pub fn new(
         futures::future::select_all(futs).await;
     }))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_worker_index() {
+        let src: SocketAddr = "10.0.0.1:1234".parse().unwrap();
+        assert_eq!(worker_index(&src, 1), 0);
+        assert_eq!(worker_index(&src, 4), worker_index(&src, 4));
+
+        // Flows are spread among all the workers.
+        let mut used = [false; 4];
+        for port in 1000..1100 {
+            let src = SocketAddr::new(src.ip(), port);
+            used[worker_index(&src, 4)] = true;
+        }
+        assert!(used.iter().all(|u| *u));
+    }
+
+    #[test]
+    fn test_packet_source() {
+        // IPv4 UDP from 10.0.0.1:1234.
+        let mut pkt = vec![0x45, 0, 0, 28, 0, 0, 0, 0, 64, 17, 0, 0];
+        pkt.extend_from_slice(&[10, 0, 0, 1, 10, 0, 0, 2]);
+        pkt.extend_from_slice(&[0x04, 0xd2, 0, 53, 0, 8, 0, 0]);
+        assert_eq!(packet_source(&pkt), Some("10.0.0.1:1234".parse().unwrap()));
+        // More fragments.
+        pkt[6] = 0x20;
+        assert_eq!(packet_source(&pkt), Some("10.0.0.1:0".parse().unwrap()));
+        // ICMP.
+        pkt[6] = 0;
+        pkt[9] = 1;
+        assert_eq!(packet_source(&pkt), Some("10.0.0.1:0".parse().unwrap()));
+
+        // IPv6 TCP from [fd00::1]:443.
+        let mut pkt = vec![0x60, 0, 0, 0, 0, 20, 6, 64];
+        pkt.extend_from_slice(&"fd00::1".parse::<std::net::Ipv6Addr>().unwrap().octets());
+        pkt.extend_from_slice(&"fd00::2".parse::<std::net::Ipv6Addr>().unwrap().octets());
+        pkt.extend_from_slice(&[0x01, 0xbb, 0, 80]);
+        assert_eq!(packet_source(&pkt), Some("[fd00::1]:443".parse().unwrap()));
+
+        assert_eq!(packet_source(&[]), None);
+        assert_eq!(packet_source(&[0x45, 0]), None);
+    }
+}
@@ -68,6 +68,28 @@ pub struct Session {
     /// Instructs a multiplexed transport should creates a new underlying
     /// connection for this session, and it will be used only once.
     pub new_conn_once: bool,
+    /// The server name sniffed from the TLS ClientHello.
+    pub sni: Option<String>,
+    /// The protocols offered in the ALPN extension of the TLS ClientHello.
+    pub alpn: Vec<String>,
+    /// Logs the session verbosely, set by the matched routing rule.
+    pub trace: bool,
+    /// The user the inbound authenticated the client as, if any.
+    pub user: Option<String>,
+}
+
+impl Session {
+    /// Identifies the client for accounting, the authenticated user if any,
+    /// otherwise the client IP.
+    pub fn client(&self) -> String {
+        match &self.user {
+            Some(user) => user.clone(),
+            None => self
+                .forwarded_source
+                .unwrap_or_else(|| self.source.ip())
+                .to_string(),
+        }
+    }
 }
 
 impl Clone for Session {
@@ -82,6 +104,10 @@ impl Clone for Session {
             stream_id: self.stream_id,
             forwarded_source: self.forwarded_source,
             new_conn_once: self.new_conn_once,
+            sni: self.sni.clone(),
+            alpn: self.alpn.clone(),
+            trace: self.trace,
+            user: self.user.clone(),
         }
     }
 }
@@ -98,6 +124,10 @@ impl Default for Session {
             stream_id: None,
             forwarded_source: None,
             new_conn_once: false,
+            sni: None,
+            alpn: Vec::new(),
+            trace: false,
+            user: None,
         }
     }
 }
@@ -123,7 +153,7 @@ pub enum SocksAddrWireType {
     PortLast,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Hash)]
 pub enum SocksAddr {
     Ip(SocketAddr),
     Domain(String, u16),
@@ -396,6 +426,14 @@ impl TryFrom<(String, u16)> for SocksAddr {
         if let Ok(ip) = addr.parse::<IpAddr>() {
             return Ok(Self::from((ip, port)));
         }
+        // IPv6 literals may come in brackets, as in URLs.
+        if let Some(ip) = addr
+            .strip_prefix('[')
+            .and_then(|a| a.strip_suffix(']'))
+            .and_then(|a| a.parse::<Ipv6Addr>().ok())
+        {
+            return Ok(Self::from((ip, port)));
+        }
         if addr.len() > 0xff {
             return Err(io::Error::new(io::ErrorKind::Other, "domain too long"));
         }
@@ -439,11 +477,11 @@ impl TryFrom<(&[u8], SocksAddrWireType)> for SocksAddr {
                     Ok(Self::Ip((ip, port).into()))
                 }
                 SocksAddrPortLastType::DOMAIN => {
-                    if buf.is_empty() {
+                    if buf.len() < 2 {
                         return Err(insuff_bytes());
                     }
                     let domain_len = buf[1] as usize;
-                    if buf.len() < 1 + domain_len + 2 {
+                    if buf.len() < 1 + 1 + domain_len + 2 {
                         return Err(insuff_bytes());
                     }
                     let domain =
@@ -504,3 +542,55 @@ impl TryFrom<(&[u8], SocksAddrWireType)> for SocksAddr {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+
+    use super::*;
+
+    #[test]
+    fn test_ipv6_roundtrip() {
+        let addr = SocksAddr::Ip("[2606:4700::]:443".parse().unwrap());
+        assert_eq!(addr.to_string(), "[2606:4700::]:443");
+        assert_eq!(addr.host(), "2606:4700::");
+        assert_eq!(SocksAddr::try_from(("[2606:4700::]", 443)).unwrap(), addr);
+        assert_eq!(SocksAddr::try_from(("2606:4700::", 443)).unwrap(), addr);
+        assert!(SocksAddr::try_from(("[example.com]", 443))
+            .unwrap()
+            .is_domain());
+
+        // ATYP 4 of a SOCKS5 request.
+        let mut buf = BytesMut::new();
+        addr.write_buf(&mut buf, SocksAddrWireType::PortLast);
+        let mut expected = vec![0x04, 0x26, 0x06, 0x47];
+        expected.extend_from_slice(&[0; 13]);
+        expected.extend_from_slice(&443u16.to_be_bytes());
+        assert_eq!(&buf[..], &expected[..]);
+        assert_eq!(buf.len(), addr.size());
+        assert_eq!(
+            SocksAddr::try_from((&buf[..], SocksAddrWireType::PortLast)).unwrap(),
+            addr
+        );
+
+        let mut buf = BytesMut::new();
+        addr.write_buf(&mut buf, SocksAddrWireType::PortFirst);
+        assert_eq!(
+            SocksAddr::try_from((&buf[..], SocksAddrWireType::PortFirst)).unwrap(),
+            addr
+        );
+
+        let rt = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        rt.block_on(async {
+            let mut r = &expected[..];
+            let read = SocksAddr::read_from(&mut r, SocksAddrWireType::PortLast)
+                .await
+                .unwrap();
+            assert_eq!(read, addr);
+            assert!(r.is_empty());
+        });
+    }
+}
@@ -1,6 +1,282 @@
+use std::fmt;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use parking_lot::Mutex;
+
+use super::app::inbound::manager::TunDevice;
 use super::common;
 use super::option;
 
+// Changes made to routes, rules, addresses and forwarding this session, in
+// the order they're made.
+static CHANGES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+fn record(change: String) {
+    log::debug!("{}", change);
+    CHANGES.lock().push(change);
+}
+
+/// Returns the system network changes made this session.
+pub fn changes() -> Vec<String> {
+    CHANGES.lock().clone()
+}
+
+// A change to the system network configuration, which is either made in
+// process or delegated to the route helper at `ROUTE_HELPER_PATH`, so that the
+// main process can run without the privileges to change routes.
+//
+// A request to the helper is a line of the command and its arguments:
+//
+//   add-tun <interface> <uid>
+//   delete-tun <interface>
+//   set-link-up <interface>
+//   add-ipv4-address <interface> <address> <gateway> <mask>
+//   add-ipv6-address <interface> <address> <prefixlen>
+//   add-default-route <gateway> <interface> primary|scoped
+//   delete-default-route ipv4|ipv6 [<interface>]
+//   delete-default-route-via <gateway>
+//   add-rule <address>
+//   delete-rule <address>
+//   set-forwarding ipv4|ipv6 on|off
+//   add-forward <interface>
+//   delete-forward <interface>
+//
+// The helper replies with a line of `ok` on success, or the reason of the
+// failure otherwise. The same line is what's logged and recorded in process.
+pub enum Change {
+    // The tun device owned by the user, so that tun2socks can attach to it
+    // without privileges.
+    #[cfg(target_os = "linux")]
+    AddTun {
+        interface: String,
+        user: u32,
+    },
+    #[cfg(target_os = "linux")]
+    DeleteTun(String),
+    SetLinkUp(String),
+    AddIpv4Address {
+        interface: String,
+        addr: Ipv4Addr,
+        gateway: Ipv4Addr,
+        mask: Ipv4Addr,
+    },
+    AddIpv6Address {
+        interface: String,
+        addr: Ipv6Addr,
+        prefixlen: i32,
+    },
+    AddDefaultIpv4Route {
+        gateway: Ipv4Addr,
+        interface: String,
+        primary: bool,
+    },
+    AddDefaultIpv6Route {
+        gateway: Ipv6Addr,
+        interface: String,
+        primary: bool,
+    },
+    DeleteDefaultIpv4Route(Option<String>),
+    DeleteDefaultIpv6Route(Option<String>),
+    DeleteDefaultIpv4RouteVia(Ipv4Addr),
+    DeleteDefaultIpv6RouteVia(Ipv6Addr),
+    #[cfg(target_os = "linux")]
+    AddIpv4Rule(Ipv4Addr),
+    #[cfg(target_os = "linux")]
+    AddIpv6Rule(Ipv6Addr),
+    #[cfg(target_os = "linux")]
+    DeleteIpv4Rule(Ipv4Addr),
+    #[cfg(target_os = "linux")]
+    DeleteIpv6Rule(Ipv6Addr),
+    SetIpv4Forwarding(bool),
+    SetIpv6Forwarding(bool),
+    #[cfg(target_os = "linux")]
+    AddIptablesForward(String),
+    #[cfg(target_os = "linux")]
+    DeleteIptablesForward(String),
+}
+
+impl Change {
+    fn execute(&self) -> Result<()> {
+        match self {
+            #[cfg(target_os = "linux")]
+            Change::AddTun { interface, user } => common::cmd::add_tun_device(interface, *user),
+            #[cfg(target_os = "linux")]
+            Change::DeleteTun(interface) => common::cmd::delete_tun_device(interface),
+            Change::SetLinkUp(interface) => common::cmd::set_link_up(interface),
+            Change::AddIpv4Address {
+                interface,
+                addr,
+                gateway,
+                mask,
+            } => common::cmd::add_interface_ipv4_address(interface, *addr, *gateway, *mask),
+            Change::AddIpv6Address {
+                interface,
+                addr,
+                prefixlen,
+            } => common::cmd::add_interface_ipv6_address(interface, *addr, *prefixlen),
+            Change::AddDefaultIpv4Route {
+                gateway,
+                interface,
+                primary,
+            } => common::cmd::add_default_ipv4_route(*gateway, interface.clone(), *primary),
+            Change::AddDefaultIpv6Route {
+                gateway,
+                interface,
+                primary,
+            } => common::cmd::add_default_ipv6_route(*gateway, interface.clone(), *primary),
+            Change::DeleteDefaultIpv4Route(scope) => {
+                common::cmd::delete_default_ipv4_route(scope.clone())
+            }
+            Change::DeleteDefaultIpv6Route(scope) => {
+                common::cmd::delete_default_ipv6_route(scope.clone())
+            }
+            Change::DeleteDefaultIpv4RouteVia(gateway) => {
+                common::cmd::delete_default_ipv4_route_via(*gateway)
+            }
+            Change::DeleteDefaultIpv6RouteVia(gateway) => {
+                common::cmd::delete_default_ipv6_route_via(*gateway)
+            }
+            #[cfg(target_os = "linux")]
+            Change::AddIpv4Rule(addr) => common::cmd::add_default_ipv4_rule(*addr),
+            #[cfg(target_os = "linux")]
+            Change::AddIpv6Rule(addr) => common::cmd::add_default_ipv6_rule(*addr),
+            #[cfg(target_os = "linux")]
+            Change::DeleteIpv4Rule(addr) => common::cmd::delete_default_ipv4_rule(*addr),
+            #[cfg(target_os = "linux")]
+            Change::DeleteIpv6Rule(addr) => common::cmd::delete_default_ipv6_rule(*addr),
+            Change::SetIpv4Forwarding(val) => common::cmd::set_ipv4_forwarding(*val),
+            Change::SetIpv6Forwarding(val) => common::cmd::set_ipv6_forwarding(*val),
+            #[cfg(target_os = "linux")]
+            Change::AddIptablesForward(interface) => common::cmd::add_iptable_forward(interface),
+            #[cfg(target_os = "linux")]
+            Change::DeleteIptablesForward(interface) => {
+                common::cmd::delete_iptable_forward(interface)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn scope(primary: bool) -> &'static str {
+            if primary {
+                "primary"
+            } else {
+                "scoped"
+            }
+        }
+        fn switch(val: bool) -> &'static str {
+            if val {
+                "on"
+            } else {
+                "off"
+            }
+        }
+        match self {
+            #[cfg(target_os = "linux")]
+            Change::AddTun { interface, user } => write!(f, "add-tun {} {}", interface, user),
+            #[cfg(target_os = "linux")]
+            Change::DeleteTun(interface) => write!(f, "delete-tun {}", interface),
+            Change::SetLinkUp(interface) => write!(f, "set-link-up {}", interface),
+            Change::AddIpv4Address {
+                interface,
+                addr,
+                gateway,
+                mask,
+            } => write!(
+                f,
+                "add-ipv4-address {} {} {} {}",
+                interface, addr, gateway, mask
+            ),
+            Change::AddIpv6Address {
+                interface,
+                addr,
+                prefixlen,
+            } => write!(f, "add-ipv6-address {} {} {}", interface, addr, prefixlen),
+            Change::AddDefaultIpv4Route {
+                gateway,
+                interface,
+                primary,
+            } => write!(
+                f,
+                "add-default-route {} {} {}",
+                gateway,
+                interface,
+                scope(*primary)
+            ),
+            Change::AddDefaultIpv6Route {
+                gateway,
+                interface,
+                primary,
+            } => write!(
+                f,
+                "add-default-route {} {} {}",
+                gateway,
+                interface,
+                scope(*primary)
+            ),
+            Change::DeleteDefaultIpv4Route(None) => write!(f, "delete-default-route ipv4"),
+            Change::DeleteDefaultIpv4Route(Some(interface)) => {
+                write!(f, "delete-default-route ipv4 {}", interface)
+            }
+            Change::DeleteDefaultIpv6Route(None) => write!(f, "delete-default-route ipv6"),
+            Change::DeleteDefaultIpv6Route(Some(interface)) => {
+                write!(f, "delete-default-route ipv6 {}", interface)
+            }
+            Change::DeleteDefaultIpv4RouteVia(gateway) => {
+                write!(f, "delete-default-route-via {}", gateway)
+            }
+            Change::DeleteDefaultIpv6RouteVia(gateway) => {
+                write!(f, "delete-default-route-via {}", gateway)
+            }
+            #[cfg(target_os = "linux")]
+            Change::AddIpv4Rule(addr) => write!(f, "add-rule {}", addr),
+            #[cfg(target_os = "linux")]
+            Change::AddIpv6Rule(addr) => write!(f, "add-rule {}", addr),
+            #[cfg(target_os = "linux")]
+            Change::DeleteIpv4Rule(addr) => write!(f, "delete-rule {}", addr),
+            #[cfg(target_os = "linux")]
+            Change::DeleteIpv6Rule(addr) => write!(f, "delete-rule {}", addr),
+            Change::SetIpv4Forwarding(val) => write!(f, "set-forwarding ipv4 {}", switch(*val)),
+            Change::SetIpv6Forwarding(val) => write!(f, "set-forwarding ipv6 {}", switch(*val)),
+            #[cfg(target_os = "linux")]
+            Change::AddIptablesForward(interface) => write!(f, "add-forward {}", interface),
+            #[cfg(target_os = "linux")]
+            Change::DeleteIptablesForward(interface) => write!(f, "delete-forward {}", interface),
+        }
+    }
+}
+
+fn request_helper(path: &str, request: &str) -> Result<()> {
+    let mut stream = UnixStream::connect(path)
+        .map_err(|e| anyhow!("connect route helper {} failed: {}", path, e))?;
+    stream.set_read_timeout(Some(Duration::from_secs(*option::ROUTE_HELPER_TIMEOUT)))?;
+    stream.write_all(format!("{}\n", request).as_bytes())?;
+    let mut reply = String::new();
+    BufReader::new(stream).read_line(&mut reply)?;
+    match reply.trim_end() {
+        "ok" => Ok(()),
+        "" => Err(anyhow!("route helper closed the connection")),
+        reason => Err(anyhow!("route helper: {}", reason)),
+    }
+}
+
+/// Makes the change, through the route helper if there's one.
+pub fn apply(change: Change) -> Result<()> {
+    let res = if option::ROUTE_HELPER_PATH.is_empty() {
+        change.execute()
+    } else {
+        request_helper(&option::ROUTE_HELPER_PATH, &change.to_string())
+    };
+    res.map_err(|e| anyhow!("{} failed: {}", change, e))?;
+    record(change.to_string());
+    Ok(())
+}
+
 pub struct NetInfo {
     pub default_ipv4_gateway: Option<String>,
     pub default_ipv6_gateway: Option<String>,
@@ -26,11 +302,11 @@ impl Default for NetInfo {
 }
 
 pub fn get_net_info() -> anyhow::Result<NetInfo> {
-    let iface = common::cmd::get_default_interface().unwrap();
+    let iface = common::cmd::get_default_interface()?;
 
-    let ipv4_gw = common::cmd::get_default_ipv4_gateway().unwrap();
+    let ipv4_gw = common::cmd::get_default_ipv4_gateway()?;
     let ipv6_gw = if *option::ENABLE_IPV6 {
-        Some(common::cmd::get_default_ipv6_gateway().unwrap())
+        common::cmd::get_default_ipv6_gateway().ok()
     } else {
         None
     };
@@ -62,9 +338,9 @@ pub fn get_net_info() -> anyhow::Result<NetInfo> {
     } else {
         None
     };
-    let ipv4_forwarding = common::cmd::get_ipv4_forwarding().unwrap();
+    let ipv4_forwarding = common::cmd::get_ipv4_forwarding()?;
     let ipv6_forwarding = if *option::ENABLE_IPV6 {
-        common::cmd::get_ipv6_forwarding().unwrap()
+        common::cmd::get_ipv6_forwarding()?
     } else {
         false
     };
@@ -80,7 +356,57 @@ pub fn get_net_info() -> anyhow::Result<NetInfo> {
     })
 }
 
-pub fn post_tun_creation_setup(net_info: &NetInfo) {
+/// Polls the system network until there's a default route through an
+/// interface with an IPv4 address, or the timeout elapses, in which case the
+/// last error is returned. Polls only once with a zero timeout.
+pub fn wait_for_net_info(timeout: std::time::Duration) -> anyhow::Result<NetInfo> {
+    let deadline = std::time::Instant::now() + timeout;
+    let mut waited = false;
+    loop {
+        let res = get_net_info().and_then(|info| {
+            if info.default_ipv4_address.is_none() {
+                return Err(anyhow::anyhow!(
+                    "no ipv4 address on {}",
+                    info.default_interface.as_deref().unwrap_or_default()
+                ));
+            }
+            Ok(info)
+        });
+        match res {
+            Ok(info) => {
+                if waited {
+                    log::info!("network is ready");
+                }
+                return Ok(info);
+            }
+            Err(e) if std::time::Instant::now() < deadline => {
+                if !waited {
+                    log::info!("waiting for the network: {}", e);
+                    waited = true;
+                }
+                std::thread::sleep(std::time::Duration::from_millis(500));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// Parses an address of the net info or the device, naming it in the error.
+fn parse_addr<T>(what: &str, s: &str) -> Result<T>
+where
+    T: std::str::FromStr,
+    T::Err: fmt::Display,
+{
+    s.parse()
+        .map_err(|e| anyhow!("invalid {} {}: {}", what, s, e))
+}
+
+// Routes the traffic into the device. The changes are made in order, each
+// paired with the change undoing it, so that the ones already made are undone
+// in reverse if a later one fails. The address of the device goes away with
+// the device and isn't undone.
+pub fn post_tun_creation_setup(net_info: &NetInfo, device: &TunDevice) -> Result<()> {
+    let mut steps: Vec<(Change, Option<Change>)> = Vec::new();
     #[allow(unused_variables)]
     if let NetInfo {
         default_ipv4_gateway: Some(ipv4_gw),
@@ -92,90 +418,158 @@ pub fn post_tun_creation_setup(net_info: &NetInfo) {
         default_interface: Some(iface),
     } = net_info
     {
-        use std::net::{Ipv4Addr, Ipv6Addr};
-        common::cmd::add_interface_ipv4_address(
-            &*option::DEFAULT_TUN_NAME,
-            (*option::DEFAULT_TUN_IPV4_ADDR)
-                .parse::<Ipv4Addr>()
-                .unwrap(),
-            (*option::DEFAULT_TUN_IPV4_GW).parse::<Ipv4Addr>().unwrap(),
-            (*option::DEFAULT_TUN_IPV4_MASK)
-                .parse::<Ipv4Addr>()
-                .unwrap(),
-        )
-        .unwrap();
-        common::cmd::delete_default_ipv4_route(None).unwrap();
-
-        common::cmd::add_default_ipv4_route(
-            option::DEFAULT_TUN_IPV4_GW.parse::<Ipv4Addr>().unwrap(),
-            iface.clone(),
-            true,
-        )
-        .unwrap();
-        common::cmd::add_default_ipv4_route(
-            ipv4_gw.parse::<Ipv4Addr>().unwrap(),
-            iface.clone(),
-            false,
-        )
-        .unwrap();
+        let ipv4_gw: Ipv4Addr = parse_addr("ipv4 gateway", ipv4_gw)?;
+        let tun_gw: Ipv4Addr = parse_addr("tun gateway", &device.gateway)?;
+
+        steps.push((
+            Change::AddIpv4Address {
+                interface: device.name.clone(),
+                addr: parse_addr("tun address", &device.address)?,
+                gateway: tun_gw,
+                mask: parse_addr("tun netmask", &device.netmask)?,
+            },
+            None,
+        ));
+        steps.push((
+            Change::DeleteDefaultIpv4Route(None),
+            Some(Change::AddDefaultIpv4Route {
+                gateway: ipv4_gw,
+                interface: iface.clone(),
+                primary: true,
+            }),
+        ));
+        steps.push((
+            Change::AddDefaultIpv4Route {
+                gateway: tun_gw,
+                interface: iface.clone(),
+                primary: true,
+            },
+            Some(Change::DeleteDefaultIpv4RouteVia(tun_gw)),
+        ));
+        steps.push((
+            Change::AddDefaultIpv4Route {
+                gateway: ipv4_gw,
+                interface: iface.clone(),
+                primary: false,
+            },
+            Some(Change::DeleteDefaultIpv4Route(Some(iface.clone()))),
+        ));
 
         #[cfg(target_os = "linux")]
         {
             if let Some(a) = ipv4_addr {
-                common::cmd::add_default_ipv4_rule(a.parse::<Ipv4Addr>().unwrap()).unwrap();
+                let a: Ipv4Addr = parse_addr("ipv4 address", a)?;
+                steps.push((Change::AddIpv4Rule(a), Some(Change::DeleteIpv4Rule(a))));
             }
         }
 
         if *option::GATEWAY_MODE && !ipv4_forwarding {
-            common::cmd::set_ipv4_forwarding(true).unwrap();
+            steps.push((
+                Change::SetIpv4Forwarding(true),
+                Some(Change::SetIpv4Forwarding(false)),
+            ));
         }
 
         if *option::ENABLE_IPV6 {
-            common::cmd::add_interface_ipv6_address(
-                &*option::DEFAULT_TUN_NAME,
-                option::DEFAULT_TUN_IPV6_ADDR.parse::<Ipv6Addr>().unwrap(),
-                *option::DEFAULT_TUN_IPV6_PREFIXLEN,
-            )
-            .unwrap();
+            let tun_gw: Ipv6Addr = parse_addr("tun ipv6 gateway", &device.ipv6_gateway)?;
+            let ipv6_gw: Option<Ipv6Addr> = ipv6_gw
+                .as_deref()
+                .map(|gw| parse_addr("ipv6 gateway", gw))
+                .transpose()?;
 
+            steps.push((
+                Change::AddIpv6Address {
+                    interface: device.name.clone(),
+                    addr: parse_addr("tun ipv6 address", &device.ipv6_address)?,
+                    prefixlen: device.ipv6_prefixlen,
+                },
+                None,
+            ));
+
+            // The IPv6 default route goes into the tunnel even if the host
+            // has no IPv6 gateway, so that IPv6 traffic is captured as well.
+            if let Some(ipv6_gw) = ipv6_gw {
+                steps.push((
+                    Change::DeleteDefaultIpv6Route(None),
+                    Some(Change::AddDefaultIpv6Route {
+                        gateway: ipv6_gw,
+                        interface: iface.clone(),
+                        primary: true,
+                    }),
+                ));
+            }
+            steps.push((
+                Change::AddDefaultIpv6Route {
+                    gateway: tun_gw,
+                    interface: device.name.clone(),
+                    primary: true,
+                },
+                Some(Change::DeleteDefaultIpv6RouteVia(tun_gw)),
+            ));
             if let Some(ipv6_gw) = ipv6_gw {
-                common::cmd::delete_default_ipv6_route(None).unwrap();
-                common::cmd::add_default_ipv6_route(
-                    option::DEFAULT_TUN_IPV6_GW.parse::<Ipv6Addr>().unwrap(),
-                    iface.clone(),
-                    true,
-                )
-                .unwrap();
-                common::cmd::add_default_ipv6_route(
-                    ipv6_gw.parse::<Ipv6Addr>().unwrap(),
-                    iface.clone(),
-                    false,
-                )
-                .unwrap();
+                steps.push((
+                    Change::AddDefaultIpv6Route {
+                        gateway: ipv6_gw,
+                        interface: iface.clone(),
+                        primary: false,
+                    },
+                    Some(Change::DeleteDefaultIpv6Route(Some(iface.clone()))),
+                ));
             }
 
             #[cfg(target_os = "linux")]
             {
                 if let Some(a) = ipv6_addr {
-                    common::cmd::add_default_ipv6_rule(a.parse::<Ipv6Addr>().unwrap()).unwrap();
+                    let a: Ipv6Addr = parse_addr("ipv6 address", a)?;
+                    steps.push((Change::AddIpv6Rule(a), Some(Change::DeleteIpv6Rule(a))));
                 }
             }
 
             if *option::GATEWAY_MODE && !ipv6_forwarding {
-                common::cmd::set_ipv6_forwarding(true).unwrap();
+                steps.push((
+                    Change::SetIpv6Forwarding(true),
+                    Some(Change::SetIpv6Forwarding(false)),
+                ));
             }
         }
 
         #[cfg(target_os = "linux")]
         {
             if *option::GATEWAY_MODE {
-                common::cmd::add_iptable_forward(&*option::DEFAULT_TUN_NAME).unwrap();
+                steps.push((
+                    Change::AddIptablesForward(device.name.clone()),
+                    Some(Change::DeleteIptablesForward(device.name.clone())),
+                ));
+            }
+        }
+    }
+    apply_all(steps, apply)
+}
+
+// Makes the changes in order, undoing the ones already made in reverse when
+// one fails. A failed undo is logged and doesn't stop the others.
+fn apply_all<F>(steps: Vec<(Change, Option<Change>)>, mut apply: F) -> Result<()>
+where
+    F: FnMut(Change) -> Result<()>,
+{
+    let mut undos = Vec::new();
+    for (change, undo) in steps {
+        if let Err(e) = apply(change) {
+            for undo in undos.into_iter().rev() {
+                let line = undo.to_string();
+                if let Err(e) = apply(undo) {
+                    log::warn!("undoing {} failed: {}", line, e);
+                }
             }
+            return Err(e);
         }
+        undos.extend(undo);
     }
+    Ok(())
 }
 
-pub fn post_tun_completion_setup(net_info: &NetInfo) {
+// Restores the routes of the system, stops at the first change that fails.
+pub fn post_tun_completion_setup(net_info: &NetInfo, device: &TunDevice) -> Result<()> {
     #[allow(unused_variables)]
     if let NetInfo {
         default_ipv4_gateway: Some(ipv4_gw),
@@ -187,57 +581,217 @@ pub fn post_tun_completion_setup(net_info: &NetInfo) {
         default_interface: Some(iface),
     } = &net_info
     {
-        use std::net::{Ipv4Addr, Ipv6Addr};
-        common::cmd::delete_default_ipv4_route(None).unwrap();
-        common::cmd::delete_default_ipv4_route(Some(iface.clone())).unwrap();
+        apply(Change::DeleteDefaultIpv4Route(None))?;
+        apply(Change::DeleteDefaultIpv4Route(Some(iface.clone())))?;
 
-        common::cmd::add_default_ipv4_route(
-            ipv4_gw.parse::<Ipv4Addr>().unwrap(),
-            iface.clone(),
-            true,
-        )
-        .unwrap();
+        apply(Change::AddDefaultIpv4Route {
+            gateway: parse_addr("ipv4 gateway", ipv4_gw)?,
+            interface: iface.clone(),
+            primary: true,
+        })?;
 
         #[cfg(target_os = "linux")]
         {
             if let Some(a) = ipv4_addr {
-                common::cmd::delete_default_ipv4_rule(a.parse::<Ipv4Addr>().unwrap()).unwrap();
+                apply(Change::DeleteIpv4Rule(parse_addr("ipv4 address", a)?))?;
             }
         }
 
         if *option::GATEWAY_MODE && !ipv4_forwarding {
-            common::cmd::set_ipv4_forwarding(false).unwrap();
+            apply(Change::SetIpv4Forwarding(false))?;
         }
 
         if *option::ENABLE_IPV6 {
+            apply(Change::DeleteDefaultIpv6Route(None))?;
             if let Some(ipv6_gw) = ipv6_gw {
-                common::cmd::delete_default_ipv6_route(None).unwrap();
-                common::cmd::delete_default_ipv6_route(Some(iface.clone())).unwrap();
-                common::cmd::add_default_ipv6_route(
-                    ipv6_gw.parse::<Ipv6Addr>().unwrap(),
-                    iface.clone(),
-                    true,
-                )
-                .unwrap();
+                apply(Change::DeleteDefaultIpv6Route(Some(iface.clone())))?;
+                apply(Change::AddDefaultIpv6Route {
+                    gateway: parse_addr("ipv6 gateway", ipv6_gw)?,
+                    interface: iface.clone(),
+                    primary: true,
+                })?;
             }
 
             #[cfg(target_os = "linux")]
             {
                 if let Some(a) = ipv6_addr {
-                    common::cmd::delete_default_ipv6_rule(a.parse::<Ipv6Addr>().unwrap()).unwrap();
+                    apply(Change::DeleteIpv6Rule(parse_addr("ipv6 address", a)?))?;
                 }
             }
 
             if *option::GATEWAY_MODE && !ipv6_forwarding {
-                common::cmd::set_ipv6_forwarding(false).unwrap();
+                apply(Change::SetIpv6Forwarding(false))?;
             }
         }
 
         #[cfg(target_os = "linux")]
         {
             if *option::GATEWAY_MODE {
-                common::cmd::delete_iptable_forward(&*option::DEFAULT_TUN_NAME).unwrap();
+                apply(Change::DeleteIptablesForward(device.name.clone()))?;
             }
         }
     }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixListener;
+
+    use super::*;
+
+    #[test]
+    fn test_command_lines() {
+        let cases = vec![
+            (Change::SetLinkUp("utun8".to_string()), "set-link-up utun8"),
+            (
+                Change::AddIpv4Address {
+                    interface: "utun8".to_string(),
+                    addr: "172.7.0.2".parse().unwrap(),
+                    gateway: "172.7.0.1".parse().unwrap(),
+                    mask: "255.255.255.0".parse().unwrap(),
+                },
+                "add-ipv4-address utun8 172.7.0.2 172.7.0.1 255.255.255.0",
+            ),
+            (
+                Change::AddIpv6Address {
+                    interface: "utun8".to_string(),
+                    addr: "fd00::2".parse().unwrap(),
+                    prefixlen: 64,
+                },
+                "add-ipv6-address utun8 fd00::2 64",
+            ),
+            (
+                Change::AddDefaultIpv4Route {
+                    gateway: "192.168.1.1".parse().unwrap(),
+                    interface: "eth0".to_string(),
+                    primary: false,
+                },
+                "add-default-route 192.168.1.1 eth0 scoped",
+            ),
+            (
+                Change::DeleteDefaultIpv6Route(None),
+                "delete-default-route ipv6",
+            ),
+            (
+                Change::DeleteDefaultIpv4RouteVia("172.7.0.1".parse().unwrap()),
+                "delete-default-route-via 172.7.0.1",
+            ),
+            (Change::SetIpv4Forwarding(true), "set-forwarding ipv4 on"),
+        ];
+        for (change, line) in cases {
+            assert_eq!(change.to_string(), line);
+        }
+        #[cfg(target_os = "linux")]
+        {
+            let change = Change::AddTun {
+                interface: "utun8".to_string(),
+                user: 1000,
+            };
+            assert_eq!(change.to_string(), "add-tun utun8 1000");
+            assert_eq!(
+                Change::AddIpv4Rule("192.168.1.2".parse().unwrap()).to_string(),
+                "add-rule 192.168.1.2"
+            );
+        }
+    }
+
+    #[test]
+    fn test_apply_all() {
+        let steps = || {
+            vec![
+                (Change::SetLinkUp("utun8".to_string()), None),
+                (
+                    Change::SetIpv4Forwarding(true),
+                    Some(Change::SetIpv4Forwarding(false)),
+                ),
+                (
+                    Change::DeleteDefaultIpv4Route(None),
+                    Some(Change::DeleteDefaultIpv4RouteVia(
+                        "192.168.1.1".parse().unwrap(),
+                    )),
+                ),
+                (
+                    Change::SetIpv6Forwarding(true),
+                    Some(Change::SetIpv6Forwarding(false)),
+                ),
+            ]
+        };
+        let mut applied = Vec::new();
+        assert!(apply_all(steps(), |change| {
+            applied.push(change.to_string());
+            Ok(())
+        })
+        .is_ok());
+        assert_eq!(applied.len(), 4);
+
+        // The changes already made are undone in reverse, the failed one and
+        // the rest aren't.
+        let mut applied = Vec::new();
+        let err = apply_all(steps(), |change| {
+            let line = change.to_string();
+            applied.push(line.clone());
+            if line == "set-forwarding ipv6 on" {
+                return Err(anyhow!("permission denied"));
+            }
+            Ok(())
+        })
+        .unwrap_err();
+        assert!(err.to_string().contains("permission denied"));
+        assert_eq!(
+            applied,
+            vec![
+                "set-link-up utun8",
+                "set-forwarding ipv4 on",
+                "delete-default-route ipv4",
+                "set-forwarding ipv6 on",
+                "delete-default-route-via 192.168.1.1",
+                "set-forwarding ipv4 off",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_addr() {
+        let addr: Ipv4Addr = parse_addr("ipv4 gateway", "192.168.1.1").unwrap();
+        assert_eq!(addr, Ipv4Addr::new(192, 168, 1, 1));
+        let err = parse_addr::<Ipv4Addr>("ipv4 gateway", "192.168.1").unwrap_err();
+        assert!(err.to_string().contains("invalid ipv4 gateway 192.168.1"));
+    }
+
+    #[test]
+    fn test_request_helper() {
+        let path =
+            std::env::temp_dir().join(format!("ostrich-route-helper-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        let helper = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for reply in ["ok", "no such device", ""] {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut request = String::new();
+                reader.read_line(&mut request).unwrap();
+                requests.push(request);
+                if !reply.is_empty() {
+                    writeln!(reader.get_mut(), "{}", reply).unwrap();
+                }
+            }
+            requests
+        });
+        let path_str = path.to_str().unwrap();
+        assert!(request_helper(path_str, "set-link-up utun8").is_ok());
+        let err = request_helper(path_str, "delete-tun utun8").unwrap_err();
+        assert!(err.to_string().contains("no such device"));
+        assert!(request_helper(path_str, "set-forwarding ipv4 on").is_err());
+        assert_eq!(
+            helper.join().unwrap(),
+            vec![
+                "set-link-up utun8\n",
+                "delete-tun utun8\n",
+                "set-forwarding ipv4 on\n"
+            ]
+        );
+        let _ = std::fs::remove_file(&path);
+    }
 }
@@ -9,7 +9,10 @@ use tokio::sync::RwLock;
 use tokio::time::timeout;
 
 use crate::{
-    app::{dns_client::DnsClient, outbound::manager::OutboundManager, SyncDnsClient},
+    app::{
+        dispatcher::Dispatcher, dns_client::DnsClient, nat_manager::NatManager,
+        outbound::manager::OutboundManager, router::Router, SyncDnsClient,
+    },
     config::Config,
     proxy::*,
     session::*,
@@ -21,13 +24,14 @@ fn get_start_options(
 ) -> crate::StartOptions {
     crate::StartOptions {
         config: crate::Config::File(config_path),
+        api_listen: None,
         #[cfg(target_os = "android")]
         socket_protect_path,
     }
 }
 
 pub fn run_with_options(
-    // rt_id: crate::RuntimeId,
+    rt_id: crate::RuntimeId,
     config_path: String,
     #[cfg(target_os = "android")] socket_protect_path: Option<String>,
     #[cfg(target_os = "windows")] wintun_path: String,
@@ -39,6 +43,7 @@ pub fn run_with_options(
         socket_protect_path,
     );
     crate::start(
+        rt_id,
         opts,
         #[cfg(target_os = "windows")]
         wintun_path,
@@ -69,26 +74,15 @@ async fn test_tcp_outbound(
     }
 }
 
-async fn test_udp_outbound(
-    dns_client: SyncDnsClient,
-    handler: AnyOutboundHandler,
-) -> Result<Duration> {
+// Builds an A query for the name, returns the message ID and the bytes.
+fn new_dns_query(name: &str) -> Result<(u16, Vec<u8>)> {
     use rand::{rngs::StdRng, Rng, SeedableRng};
     use trust_dns_proto::{
         op::{header::MessageType, op_code::OpCode, query::Query, Message},
         rr::{record_type::RecordType, Name},
     };
-    let addr = SocksAddr::Ip(SocketAddr::new(IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8)), 53));
-    let sess = Session {
-        destination: addr.clone(),
-        new_conn_once: true,
-        ..Default::default()
-    };
-    let start = tokio::time::Instant::now();
-    let dgram = crate::proxy::connect_datagram_outbound(&sess, dns_client, &handler).await?;
-    let dgram = handler.datagram()?.handle(&sess, dgram).await?;
     let mut msg = Message::new();
-    let name = Name::from_str("www.google.com.")?;
+    let name = Name::from_str(name)?;
     let query = Query::query(name, RecordType::A);
     msg.add_query(query);
     let mut rng = StdRng::from_entropy();
@@ -97,7 +91,23 @@ async fn test_udp_outbound(
     msg.set_op_code(OpCode::Query);
     msg.set_message_type(MessageType::Query);
     msg.set_recursion_desired(true);
-    let msg_buf = msg.to_vec()?;
+    Ok((id, msg.to_vec()?))
+}
+
+async fn test_udp_outbound(
+    dns_client: SyncDnsClient,
+    handler: AnyOutboundHandler,
+) -> Result<Duration> {
+    let addr = SocksAddr::Ip(SocketAddr::new(IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8)), 53));
+    let sess = Session {
+        destination: addr.clone(),
+        new_conn_once: true,
+        ..Default::default()
+    };
+    let start = tokio::time::Instant::now();
+    let dgram = crate::proxy::connect_datagram_outbound(&sess, dns_client, &handler).await?;
+    let dgram = handler.datagram()?.handle(&sess, dgram).await?;
+    let (_, msg_buf) = new_dns_query("www.google.com.")?;
     let (mut recv, mut send) = dgram.split();
     send.send_to(&msg_buf, &addr).await?;
     let mut buf = [0u8; 1500];
@@ -105,35 +115,428 @@ async fn test_udp_outbound(
     Ok(tokio::time::Instant::now().duration_since(start))
 }
 
+// Runs the TCP and UDP tests concurrently, each bounded by the timeout.
+async fn probe_outbound(
+    tag: &str,
+    dns_client: SyncDnsClient,
+    handler: AnyOutboundHandler,
+    to: Duration,
+) -> (
+    Result<Duration, crate::Error>,
+    Result<Duration, crate::Error>,
+) {
+    let (tcp_res, udp_res) = futures::future::join(
+        timeout(to, test_tcp_outbound(dns_client.clone(), handler.clone())),
+        timeout(to, test_udp_outbound(dns_client, handler)),
+    )
+    .await;
+    let unreachable = |reason| crate::Error::OutboundUnreachable {
+        tag: tag.to_string(),
+        reason,
+    };
+    let tcp_res = tcp_res
+        .map_err(anyhow::Error::from)
+        .and_then(|res| res)
+        .map_err(unreachable);
+    let udp_res = udp_res
+        .map_err(anyhow::Error::from)
+        .and_then(|res| res)
+        .map_err(unreachable);
+    (tcp_res, udp_res)
+}
+
+/// Tests the outbound over TCP and UDP, returns the time of the round-trips,
+/// or `Error::OutboundUnreachable` for those failed.
 pub async fn test_outbound(
     tag: &str,
     config: &Config,
     to: Option<Duration>,
-) -> Result<(Result<Duration>, Result<Duration>)> {
+) -> Result<
+    (
+        Result<Duration, crate::Error>,
+        Result<Duration, crate::Error>,
+    ),
+    crate::Error,
+> {
     let to = to.unwrap_or(Duration::from_secs(4));
     let dns_client = Arc::new(RwLock::new(DnsClient::new(&config.dns)?));
     let outbound_manager = OutboundManager::new(&config.outbounds, dns_client.clone())?;
     let handler = outbound_manager
         .get(tag)
         .ok_or_else(|| anyhow!("outbound {} not found", tag))?;
-    let (tcp_res, udp_res) = futures::future::join(
-        timeout(to, test_tcp_outbound(dns_client.clone(), handler.clone())),
-        timeout(to, test_udp_outbound(dns_client, handler)),
+    Ok(probe_outbound(tag, dns_client, handler, to).await)
+}
+
+/// Tests every outbound as `test_outbound` does, at most `concurrency` of
+/// them at a time, returns the results in the order of the config.
+pub async fn test_all_outbounds(
+    config: &Config,
+    to: Option<Duration>,
+    concurrency: usize,
+) -> Result<
+    Vec<(
+        String,
+        Result<Duration, crate::Error>,
+        Result<Duration, crate::Error>,
+    )>,
+    crate::Error,
+> {
+    let to = to.unwrap_or(Duration::from_secs(4));
+    let dns_client = Arc::new(RwLock::new(DnsClient::new(&config.dns)?));
+    let outbound_manager = OutboundManager::new(&config.outbounds, dns_client.clone())?;
+    let semaphore = tokio::sync::Semaphore::new(concurrency.max(1));
+    let tests = config.outbounds.iter().filter_map(|outbound| {
+        let handler = outbound_manager.get(&outbound.tag)?;
+        let dns_client = dns_client.clone();
+        let semaphore = &semaphore;
+        Some(async move {
+            // The semaphore is never closed.
+            let _permit = semaphore.acquire().await.unwrap();
+            let (tcp_res, udp_res) = probe_outbound(&outbound.tag, dns_client, handler, to).await;
+            (outbound.tag.clone(), tcp_res, udp_res)
+        })
+    });
+    Ok(futures::future::join_all(tests).await)
+}
+
+/// The result of a throughput test.
+#[derive(Debug)]
+pub struct Throughput {
+    pub bytes: u64,
+    pub elapsed: Duration,
+}
+
+impl Throughput {
+    pub fn mbps(&self) -> f64 {
+        self.bytes as f64 * 8.0 / 1_000_000.0 / self.elapsed.as_secs_f64().max(0.001)
+    }
+}
+
+async fn test_tcp_throughput(
+    dns_client: SyncDnsClient,
+    handler: AnyOutboundHandler,
+    max_bytes: u64,
+    deadline: tokio::time::Instant,
+) -> Result<Throughput> {
+    let host = &*crate::option::THROUGHPUT_TEST_HOST;
+    let sess = Session {
+        destination: SocksAddr::Domain(host.clone(), 80),
+        new_conn_once: true,
+        ..Default::default()
+    };
+    let connect = async {
+        let stream = crate::proxy::connect_stream_outbound(&sess, dns_client, &handler).await?;
+        let mut stream = handler.stream()?.handle(&sess, stream).await?;
+        let req = format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+            &*crate::option::THROUGHPUT_TEST_PATH,
+            host
+        );
+        stream.write_all(req.as_bytes()).await?;
+        // Reads until the end of the response header, what follows is body.
+        let mut head = Vec::new();
+        let body_start = loop {
+            if let Some(pos) = head.windows(4).position(|w| w == b"\r\n\r\n") {
+                break pos + 4;
+            }
+            if head.len() > 16 * 1024 || stream.read_buf(&mut head).await? == 0 {
+                return Err(anyhow!("invalid response header"));
+            }
+        };
+        let status_line = head.split(|b| *b == b'\r').next().unwrap_or_default();
+        if status_line.split(|b| *b == b' ').nth(1) != Some(&b"200"[..]) {
+            return Err(anyhow!(
+                "unexpected response {}",
+                String::from_utf8_lossy(status_line)
+            ));
+        }
+        Ok((stream, (head.len() - body_start) as u64))
+    };
+    let (mut stream, mut bytes) = tokio::time::timeout_at(deadline, connect)
+        .await
+        .map_err(|_| anyhow!("timed out before the download started"))??;
+    // Only the transfer is timed, not the connection setup.
+    let start = tokio::time::Instant::now();
+    let mut buf = vec![0u8; 64 * 1024];
+    while bytes < max_bytes {
+        match tokio::time::timeout_at(deadline, stream.read(&mut buf)).await {
+            Ok(Ok(0)) | Err(_) => break,
+            Ok(Ok(n)) => bytes += n as u64,
+            Ok(Err(e)) => return Err(e.into()),
+        }
+    }
+    if bytes == 0 {
+        return Err(anyhow!("no data received"));
+    }
+    Ok(Throughput {
+        bytes,
+        elapsed: start.elapsed(),
+    })
+}
+
+/// Downloads from the THROUGHPUT_TEST_HOST server through the outbound
+/// until THROUGHPUT_TEST_BYTES are received or THROUGHPUT_TEST_TIMEOUT has
+/// passed, returns the amount received and the time it took.
+pub async fn test_outbound_throughput(
+    tag: &str,
+    config: &Config,
+) -> Result<Throughput, crate::Error> {
+    let dns_client = Arc::new(RwLock::new(DnsClient::new(&config.dns)?));
+    let outbound_manager = OutboundManager::new(&config.outbounds, dns_client.clone())?;
+    let handler = outbound_manager
+        .get(tag)
+        .ok_or_else(|| anyhow!("outbound {} not found", tag))?;
+    let deadline =
+        tokio::time::Instant::now() + Duration::from_secs(*crate::option::THROUGHPUT_TEST_TIMEOUT);
+    test_tcp_throughput(
+        dns_client,
+        handler,
+        *crate::option::THROUGHPUT_TEST_BYTES,
+        deadline,
     )
-    .await;
-    let tcp_res = match tcp_res.map_err(|e| e.into()) {
-        Err(e) => Err(e),
-        Ok(res) => match res {
-            Err(e) => Err(e),
-            Ok(duration) => Ok(duration),
-        },
+    .await
+    .map_err(|reason| crate::Error::OutboundUnreachable {
+        tag: tag.to_string(),
+        reason,
+    })
+}
+
+// Talks to the SOCKS inbound at `addr` as a client, requests an HTTP server
+// through it and waits for the response.
+async fn test_socks_client(addr: SocketAddr) -> Result<()> {
+    use bytes::{BufMut, BytesMut};
+    let mut stream = tokio::net::TcpStream::connect(addr).await?;
+    // no authentication
+    stream.write_all(&[0x05, 0x01, 0x00]).await?;
+    let mut resp = [0u8; 2];
+    stream.read_exact(&mut resp).await?;
+    if resp != [0x05, 0x00] {
+        return Err(anyhow!("unexpected socks method reply {:?}", resp));
+    }
+    let mut req = BytesMut::new();
+    req.put_slice(&[0x05, 0x01, 0x00]);
+    SocksAddr::Domain("www.google.com".to_string(), 80)
+        .write_buf(&mut req, SocksAddrWireType::PortLast);
+    stream.write_all(&req).await?;
+    // The inbound always replies with an unspecified IPv4 address.
+    let mut resp = [0u8; 10];
+    stream.read_exact(&mut resp).await?;
+    if resp[1] != 0x00 {
+        return Err(anyhow!("socks request failed with {}", resp[1]));
+    }
+    stream.write_all(b"HEAD / HTTP/1.1\r\n\r\n").await?;
+    let mut buf = Vec::new();
+    if stream.read_buf(&mut buf).await? == 0 {
+        return Err(anyhow!("EOF"));
+    }
+    Ok(())
+}
+
+/// Runs the inbound with the outbounds and rules of the config, and makes
+/// an HTTP request through it, returns the time of the round-trip.
+pub async fn test_inbound(tag: &str, config: &Config, to: Option<Duration>) -> Result<Duration> {
+    let to = to.unwrap_or(Duration::from_secs(4));
+    let inbound = config
+        .inbounds
+        .iter()
+        .find(|i| i.tag == tag)
+        .ok_or_else(|| anyhow!("inbound {} not found", tag))?;
+    let handler: AnyInboundHandler = match inbound.protocol.as_str() {
+        #[cfg(feature = "inbound-socks")]
+        "socks" => Arc::new(crate::proxy::inbound::Handler::new(
+            tag.to_string(),
+            Some(Arc::new(
+                crate::proxy::socks::inbound::StreamHandler::default(),
+            )),
+            Some(Arc::new(
+                crate::proxy::socks::inbound::DatagramHandler::default(),
+            )),
+        )),
+        p => return Err(anyhow!("testing {} inbounds is not supported", p)),
+    };
+
+    let dns_client = Arc::new(RwLock::new(DnsClient::new(&config.dns)?));
+    let outbound_manager = Arc::new(RwLock::new(OutboundManager::new(
+        &config.outbounds,
+        dns_client.clone(),
+    )?));
+    let mut router_config = config.router.clone();
+    let router = Arc::new(RwLock::new(Router::new(
+        &mut router_config,
+        dns_client.clone(),
+    )?));
+    let dispatcher = Arc::new(Dispatcher::new(
+        outbound_manager,
+        router,
+        dns_client.clone(),
+        #[cfg(feature = "stat")]
+        Arc::new(RwLock::new(crate::app::stat_manager::StatManager::new())),
+    ));
+    dns_client
+        .write()
+        .await
+        .replace_dispatcher(Arc::downgrade(&dispatcher));
+    let nat_manager = Arc::new(NatManager::new(dispatcher.clone()));
+
+    // Listen on the address of the inbound, but on a free port so that it
+    // doesn't conflict with a running instance.
+    let listener =
+        tokio::net::TcpListener::bind(SocketAddr::new(inbound.address.parse()?, 0)).await?;
+    let listen_addr = listener.local_addr()?;
+    let server = async move {
+        let (stream, _) = listener.accept().await?;
+        crate::app::inbound::network_listener::handle_inbound_tcp_stream(
+            stream,
+            handler,
+            dispatcher,
+            nat_manager,
+            false,
+        )
+        .await?;
+        Ok::<(), anyhow::Error>(())
+    };
+    tokio::spawn(async move {
+        if let Err(e) = server.await {
+            log::debug!("test inbound server failed: {}", e);
+        }
+    });
+
+    let start = tokio::time::Instant::now();
+    timeout(to, test_socks_client(listen_addr)).await??;
+    Ok(tokio::time::Instant::now().duration_since(start))
+}
+
+/// Fetches the route changes made by the instance serving the API at the
+/// address.
+#[cfg(feature = "config-json")]
+pub fn show_routes(api_listen: &str) -> Result<Vec<String>> {
+    use std::io::{Read, Write};
+    // The first TCP one of the addresses.
+    let addr = api_listen
+        .split(',')
+        .find_map(|a| a.trim().parse::<SocketAddr>().ok())
+        .ok_or_else(|| anyhow!("no tcp api address in {}", api_listen))?;
+    let mut stream = std::net::TcpStream::connect_timeout(&addr, Duration::from_secs(4))?;
+    stream.set_read_timeout(Some(Duration::from_secs(4)))?;
+    stream.write_all(
+        format!(
+            "GET /routes HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n",
+            addr
+        )
+        .as_bytes(),
+    )?;
+    let mut resp = String::new();
+    stream.read_to_string(&mut resp)?;
+    let (head, body) = resp
+        .split_once("\r\n\r\n")
+        .ok_or_else(|| anyhow!("invalid api response"))?;
+    let status = head.lines().next().unwrap_or_default();
+    if status.split_whitespace().nth(1) != Some("200") {
+        return Err(anyhow!("api request failed: {}", status));
+    }
+    Ok(serde_json::from_str(body)?)
+}
+
+/// The result of a preflight check.
+pub struct DoctorCheck {
+    pub name: String,
+    pub result: Result<()>,
+}
+
+fn check_dns_server(server: &str) -> Result<()> {
+    use trust_dns_proto::op::Message;
+    let ip = server.parse::<IpAddr>()?;
+    let bind_addr: SocketAddr = match ip {
+        IpAddr::V4(..) => "0.0.0.0:0".parse().unwrap(),
+        IpAddr::V6(..) => "[::]:0".parse().unwrap(),
     };
-    let udp_res = match udp_res.map_err(|e| e.into()) {
-        Err(e) => Err(e),
-        Ok(res) => match res {
-            Err(e) => Err(e),
-            Ok(duration) => Ok(duration),
-        },
+    let socket = std::net::UdpSocket::bind(bind_addr)?;
+    socket.set_read_timeout(Some(Duration::from_secs(*crate::option::DNS_TIMEOUT)))?;
+    let (id, query) = new_dns_query("www.google.com.")?;
+    socket.send_to(&query, SocketAddr::new(ip, 53))?;
+    let mut buf = [0u8; 1500];
+    let (n, _) = socket.recv_from(&mut buf)?;
+    let resp = Message::from_vec(&buf[..n])?;
+    if resp.id() != id {
+        return Err(anyhow!("unexpected response"));
+    }
+    Ok(())
+}
+
+/// Checks everything known to block a successful start with the config:
+/// the config itself, the TUN prerequisites, the inbound ports and the
+/// reachability of the DNS servers.
+pub fn doctor(config_path: &str, tun2socks_path: Option<&str>) -> Vec<DoctorCheck> {
+    let mut checks = Vec::new();
+    let config = match crate::config::from_file(config_path) {
+        Ok(c) => {
+            checks.push(DoctorCheck {
+                name: format!("config {}", config_path),
+                result: Ok(()),
+            });
+            c
+        }
+        Err(e) => {
+            // Nothing else could be checked without a config.
+            checks.push(DoctorCheck {
+                name: format!("config {}", config_path),
+                result: Err(e),
+            });
+            return checks;
+        }
     };
-    Ok((tcp_res, udp_res))
+
+    if config.inbounds.iter().any(|i| i.protocol == "tun") {
+        if let Some(path) = tun2socks_path {
+            checks.push(DoctorCheck {
+                name: format!("tun2socks {}", path),
+                result: if std::path::Path::new(path).is_file() {
+                    Ok(())
+                } else {
+                    Err(anyhow!("not found"))
+                },
+            });
+        }
+        #[cfg(any(target_os = "macos", target_os = "linux"))]
+        checks.push(DoctorCheck {
+            name: "privileges for tun".to_string(),
+            result: if unsafe { libc::geteuid() } == 0 {
+                Ok(())
+            } else {
+                Err(anyhow!("root is required"))
+            },
+        });
+    }
+
+    for inbound in config.inbounds.iter() {
+        if inbound.port == 0 {
+            continue;
+        }
+        checks.push(DoctorCheck {
+            name: format!(
+                "port {}:{} of inbound {}",
+                &inbound.address, inbound.port, &inbound.tag
+            ),
+            result: inbound
+                .address
+                .parse::<IpAddr>()
+                .map_err(|e| anyhow!("invalid address: {}", e))
+                .and_then(|ip| {
+                    std::net::TcpListener::bind(SocketAddr::new(ip, inbound.port as u16))
+                        .map(|_| ())
+                        .map_err(|e| e.into())
+                }),
+        });
+    }
+
+    if let Some(dns) = config.dns.as_ref() {
+        for server in dns.servers.iter() {
+            checks.push(DoctorCheck {
+                name: format!("dns server {}", server),
+                result: check_dns_server(server),
+            });
+        }
+    }
+
+    checks
 }
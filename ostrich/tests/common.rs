@@ -60,9 +60,9 @@ pub fn run_ostrich_instances(
         let config = ostrich::config::json::from_string(&config).unwrap();
         let opts = ostrich::StartOptions {
             config: ostrich::Config::Internal(config),
-            #[cfg(feature = "auto-reload")]
-            auto_reload: false,
-            runtime_opt: ostrich::RuntimeOption::SingleThread,
+            api_listen: None,
+            #[cfg(target_os = "android")]
+            socket_protect_path: None,
         };
         rt.spawn_blocking(move || {
             ostrich::start(rt_id, opts).unwrap();